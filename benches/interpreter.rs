@@ -0,0 +1,262 @@
+//! Benchmarks of the interpreter, the storage types and instruction decoding.
+//!
+//! Run them with `cargo bench --bench interpreter`, optionally followed by `-- NAME` to only run the benchmarks
+//! whose names match `NAME`. [Criterion](https://docs.rs/criterion) reports the time of one iteration together
+//! with the number of instructions executed per second, and the change since the last run, so that the dispatch
+//! and the storage can be compared before and after a change.
+
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+use num_traits::FromPrimitive;
+use std::hint::black_box;
+use vcpu::*;
+
+/// Size of the RAM of all programs, which keep their data at its start.
+const RAM_SIZE: u32 = 1 << 16;
+
+/// Measures `run`, which returns the number of instructions it executed, so that the throughput is reported in
+/// instructions per second.
+fn bench<F: FnMut() -> u64>(group: &mut BenchmarkGroup<WallTime>, name: &str, mut run: F) {
+    group.throughput(Throughput::Elements(run()));
+    group.bench_function(name, |b| b.iter(&mut run));
+}
+
+/// Runs `program` until it stops and returns the number of executed instructions, including the last one.
+fn run(program: &[u8], storage: &mut dyn StorageMut) -> u64 {
+    let mut processor = Processor::default();
+    let mut executed = 1;
+    while processor.tick(program, storage).is_none() {
+        executed += 1;
+    }
+    assert_eq!(processor.state(), Some(ExitCode::Halted));
+    executed
+}
+
+/// Loads `value` into `register` with `LHI` and `SLO`.
+fn load_word(register: RegisterId, value: u32) -> [Word; 2] {
+    [
+        make_i_instruction(
+            Opcode::LHI,
+            register,
+            RegisterId::ZERO,
+            (value >> 16) as i16,
+        ),
+        make_i_instruction(Opcode::SLO, register, RegisterId::ZERO, value as i16),
+    ]
+}
+
+/// A loop of arithmetic and logic instructions which never accesses the memory.
+fn alu_loop(iterations: u32) -> Vec<u8> {
+    let mut program = load_word(RegisterId::T0, iterations).to_vec();
+    program.extend_from_slice(&[
+        instr_i!(LI, T1, ZERO, 1),
+        // loop:
+        instr_alu!(ADD, T2, T2, T1),
+        instr_alu!(XOR, T3, T3, T2),
+        instr_i!(SLLI, T4, T3, 3),
+        instr_alu!(SUB, T2, T4, T0),
+        instr_alu!(MUL, T5, T2, T1),
+        instr_i!(ANDI, T3, T5, 0x7FFF),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, -28),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+    instructions_from_words(&program)
+}
+
+/// A loop which reads, adds and writes every word of the first `words` words of the memory.
+fn memory_loop(words: u32) -> Vec<u8> {
+    let mut program = load_word(RegisterId::T0, words).to_vec();
+    program.extend_from_slice(&[
+        instr_i!(LI, T1, ZERO, 0),
+        // loop:
+        instr_i!(LW, T2, T1, 0),
+        instr_alu!(ADD, T3, T3, T2),
+        instr_i!(SW, T3, T1, 0),
+        instr_i!(LB, T4, T1, 1),
+        instr_i!(SB, T4, T1, 2),
+        instr_i!(ADDI, T1, T1, 4),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, -28),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+    instructions_from_words(&program)
+}
+
+/// A loop which adds the first `words` words of the memory to the words which follow them, one word at a time.
+fn scalar_add_loop(words: u32) -> Vec<u8> {
+    let mut program = load_word(RegisterId::T0, words).to_vec();
+    program.extend_from_slice(&load_word(RegisterId::T1, words * WORD_BYTES));
+    program.extend_from_slice(&[
+        instr_i!(LI, T2, ZERO, 0),
+        // loop:
+        instr_i!(LW, T3, T2, 0),
+        instr_alu!(ADD, T4, T2, T1),
+        instr_i!(LW, T5, T4, 0),
+        instr_alu!(ADD, T5, T5, T3),
+        instr_i!(SW, T5, T4, 0),
+        instr_i!(ADDI, T2, T2, 4),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, -28),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+    instructions_from_words(&program)
+}
+
+/// The same additions as [`scalar_add_loop`], but with vector instructions.
+fn vector_add_loop(words: u32) -> Vec<u8> {
+    let t = |id: RegisterId| enum_to_u32(id);
+    let mut program = load_word(RegisterId::T0, words / VECTOR_LANES as u32).to_vec();
+    program.extend_from_slice(&load_word(RegisterId::T1, words * WORD_BYTES));
+    program.extend_from_slice(&[
+        instr_i!(LI, T2, ZERO, 0),
+        // loop:
+        make_vec_instruction(VecFunct::VLD, 0, t(RegisterId::T2), 0),
+        instr_alu!(ADD, T4, T2, T1),
+        make_vec_instruction(VecFunct::VLD, 1, t(RegisterId::T4), 0),
+        make_vec_instruction(VecFunct::VADD, 1, 1, 0),
+        make_vec_instruction(VecFunct::VST, 1, t(RegisterId::T4), 0),
+        instr_i!(ADDI, T2, T2, VECTOR_BYTES as i16),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, -28),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+    instructions_from_words(&program)
+}
+
+/// Generates a program resembling Dhrystone, which runs `iterations` times through a mix of procedure calls,
+/// record accesses, byte string copies, multiplications, divisions and comparisons.
+///
+/// The records and strings are stored at the start of the memory, which must be at least 256 bytes large.
+fn dhrystone_like(iterations: u32) -> Vec<u8> {
+    let mut program = load_word(RegisterId::S0, iterations).to_vec();
+    let main = vec![
+        // main loop:
+        // record at 0x00: four words, the second of which counts the iterations
+        instr_i!(LI, A0, ZERO, 0x00),
+        instr_j!(JL, 13 * 4),
+        // copy 16 bytes from 0x40 to 0x80
+        instr_i!(LI, A0, ZERO, 0x40),
+        instr_i!(LI, A1, ZERO, 0x80),
+        instr_i!(LI, A2, ZERO, 16),
+        instr_j!(JL, 17 * 4),
+        // integer arithmetic with values from the record
+        instr_i!(LW, T0, ZERO, 4),
+        instr_i!(MULI, T1, T0, 13),
+        instr_i!(DIVI, T2, T1, 7),
+        instr_alu!(SLT, T3, T2, T1),
+        instr_alu!(ADD, S1, S1, T3),
+        instr_i!(SUBI, S0, S0, 1),
+        instr_i!(BNZ, ZERO, S0, -12 * 4),
+        instr_i!(HALT, ZERO, ZERO, 0),
+        // proc_record(A0 = record): updates the record and compares two of its fields
+        instr_i!(LW, T0, A0, 4),
+        instr_i!(ADDI, T0, T0, 1),
+        instr_i!(SW, T0, A0, 4),
+        instr_i!(LW, T1, A0, 8),
+        instr_alu!(SLTU, T2, T1, T0),
+        instr_i!(SW, T2, A0, 12),
+        instr_i!(SW, T0, A0, 8),
+        instr_i!(JR, ZERO, RA, 0),
+        // copy_bytes(A0 = source, A1 = destination, A2 = count)
+        instr_i!(LB, T0, A0, 0),
+        instr_i!(SB, T0, A1, 0),
+        instr_i!(ADDI, A0, A0, 1),
+        instr_i!(ADDI, A1, A1, 1),
+        instr_i!(SUBI, A2, A2, 1),
+        instr_i!(BNZ, ZERO, A2, -5 * 4),
+        instr_i!(JR, ZERO, RA, 0),
+    ];
+    program.extend(main);
+    instructions_from_words(&program)
+}
+
+/// The fields of an instruction, extracted the same way as the interpreter does before executing it.
+fn decode(word: Word) -> (Option<Opcode>, u32, u32, u32, i16, u32) {
+    let opcode = Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET);
+    let rd = (word & RD_MASK) >> RD_OFFSET;
+    let rs1 = (word & RS1_MASK) >> RS1_OFFSET;
+    let rs2 = (word & RS2_MASK) >> RS2_OFFSET;
+    let immediate = ((word & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as i16;
+    let mut address = (word & ADDRESS_MASK) >> ADDRESS_OFFSET;
+    if (address & ADDRESS_SIGN_MASK) != 0 {
+        address |= ADDRESS_EXTENSION;
+    }
+    (opcode, rd, rs1, rs2, immediate, address)
+}
+
+fn composite_memory() -> CompositeMemory {
+    let mut memory = CompositeMemory::new();
+    memory
+        .mount(0, "ram", vec![0u8; RAM_SIZE as usize])
+        .unwrap();
+    // a second fragment, like a device, so that every access has to find the right fragment
+    memory.mount(0xFFFF_0000, "device", vec![0u8; 4]).unwrap();
+    memory
+}
+
+fn io_memory() -> IOMemory<impl IOHandler> {
+    IOMemory::new(
+        RAM_SIZE,
+        DelegateIOHandler::new(
+            |_, address, _| address != 0xFFFF,
+            |memory, address, _| {
+                black_box(memory[address as usize]);
+            },
+        ),
+    )
+}
+
+fn interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+
+    let program = alu_loop(100_000);
+    bench(&mut group, "alu_loop", || {
+        run(&program, &mut Vec::<u8>::new())
+    });
+
+    let words = RAM_SIZE / WORD_BYTES;
+    let program = memory_loop(words);
+    let mut memory = vec![0u8; RAM_SIZE as usize];
+    bench(&mut group, "memory_loop/vec", || run(&program, &mut memory));
+    let mut memory = composite_memory();
+    bench(&mut group, "memory_loop/composite", || {
+        run(&program, &mut memory)
+    });
+    let mut memory = io_memory();
+    bench(&mut group, "memory_loop/io", || run(&program, &mut memory));
+
+    // the same work with scalar and vector instructions, whose loop runs a quarter as often
+    let words = RAM_SIZE / WORD_BYTES / 2;
+    let mut memory = vec![0u8; RAM_SIZE as usize];
+    let program = scalar_add_loop(words);
+    bench(&mut group, "add_loop/scalar", || run(&program, &mut memory));
+    let program = vector_add_loop(words);
+    bench(&mut group, "add_loop/vector", || run(&program, &mut memory));
+
+    let program = dhrystone_like(10_000);
+    let mut memory = vec![0u8; RAM_SIZE as usize];
+    bench(&mut group, "dhrystone_like/vec", || {
+        run(&program, &mut memory)
+    });
+    let mut memory = composite_memory();
+    bench(&mut group, "dhrystone_like/composite", || {
+        run(&program, &mut memory)
+    });
+
+    // every opcode with many different registers and immediates
+    let words: Vec<Word> = (0..1 << 16)
+        .map(|i: u32| i.wrapping_mul(0x9E37_79B9))
+        .collect();
+    bench(&mut group, "decode", || {
+        for word in words.iter() {
+            black_box(decode(black_box(*word)));
+        }
+        words.len() as u64
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
@@ -1,2 +1 @@
-cargo install cbindgen
-cbindgen --config vcpu-interop/cbindgen.toml vcpu-interop -o target/include/vcpu.h
+VCPU_UPDATE_HEADER=1 cargo build -p vcpu-interop
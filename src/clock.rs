@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// A clock of the host, which timeouts are measured with.
+///
+/// [`Processor.run_with_clock`] reads it for its [`RunOptions.timeout`], and the runner of `vcpu-run` reads
+/// it for its timeouts and sleeps with it to pace programs. Targets without a clock of the operating system,
+/// like `wasm32-unknown-unknown`, implement it with a clock of their host instead of using the
+/// [`SystemClock`](struct.SystemClock.html).
+///
+/// [`Processor.run_with_clock`]: ./struct.Processor.html#method.run_with_clock
+/// [`RunOptions.timeout`]: ./struct.RunOptions.html#structfield.timeout
+pub trait ClockSource {
+    /// Time since an arbitrary origin, which never goes backwards.
+    fn now(&self) -> Duration;
+
+    /// Waits for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The monotonic clock of the operating system.
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Immediate, Word};
+use super::{Immediate, RegisterId, Word};
 use std::mem;
 
 pub const BYTE_BYTES: u32 = mem::size_of::<u8>() as u32;
@@ -16,12 +16,14 @@ pub const IMMEDIATE_WIDTH: u32 = IMMEDIATE_BYTES * BYTE_WIDTH;
 pub const OPCODE_WIDTH: u32 = 6;
 pub const REG_ID_WIDTH: u32 = 5;
 pub const FUNCT_WIDTH: u32 = 6;
+pub const SHAMT_WIDTH: u32 = 5;
 pub const ADDRESS_WIDTH: u32 = 26;
 
 pub const OPCODE_OFFSET: u32 = 26;
 pub const RD_OFFSET: u32 = 21;
 pub const RS1_OFFSET: u32 = 16;
 pub const RS2_OFFSET: u32 = 11;
+pub const SHAMT_OFFSET: u32 = 6;
 pub const FUNCT_OFFSET: u32 = 0;
 pub const IMMEDIATE_OFFSET: u32 = 0;
 pub const ADDRESS_OFFSET: u32 = 0;
@@ -30,6 +32,7 @@ pub const OPCODE_MASK: u32 = 0b1111_1100_0000_0000_0000_0000_0000_0000;
 pub const RD_MASK: u32 = 0b0000_0011_1110_0000_0000_0000_0000_0000;
 pub const RS1_MASK: u32 = 0b0000_0000_0001_1111_0000_0000_0000_0000;
 pub const RS2_MASK: u32 = 0b0000_0000_0000_0000_1111_1000_0000_0000;
+pub const SHAMT_MASK: u32 = 0b0000_0000_0000_0000_0000_0111_1100_0000;
 pub const FUNCT_MASK: u32 = 0b0000_0000_0000_0000_0000_0000_0011_1111;
 pub const IMMEDIATE_MASK: u32 = 0b0000_0000_0000_0000_1111_1111_1111_1111;
 pub const ADDRESS_MASK: u32 = 0b0000_0011_1111_1111_1111_1111_1111_1111;
@@ -37,10 +40,48 @@ pub const ADDRESS_SIGN_MASK: u32 = 0b0000_0010_0000_0000_0000_0000_0000_0000;
 pub const ADDRESS_EXTENSION: u32 = 0b1111_1100_0000_0000_0000_0000_0000_0000;
 
 pub const REGISTER_COUNT: usize = 32;
+pub const VECTOR_REGISTER_COUNT: usize = 8;
+pub const VECTOR_LANES: usize = 4;
+pub const VECTOR_BYTES: u32 = VECTOR_LANES as u32 * WORD_BYTES;
 
 pub const LOW_BITS_MASK: u32 = 0x0000_FFFF;
 pub const HIGH_BITS_MASK: u32 = 0xFFFF_0000;
 
+// Calling convention, which the `PUSH`, `POP`, `CALL` and `RET` mnemonics of the assembler, its standard library
+// and the runners follow. Routines are called with `JL`, which stores the return address, take their arguments
+// in the argument registers and return values in the return value registers. Temporary registers may be
+// modified by any call, while the saved registers, the stack pointer and the frame pointer must be restored
+// before returning.
+
+/// Points at the last word pushed onto the stack, which grows downwards. Runners start programs with the stack
+/// pointer at the end of the RAM.
+pub const STACK_POINTER: RegisterId = RegisterId::SP;
+pub const FRAME_POINTER: RegisterId = RegisterId::FP;
+pub const RETURN_ADDRESS: RegisterId = RegisterId::RA;
+pub const ARGUMENT_REGISTERS: [RegisterId; 5] = [
+    RegisterId::A0,
+    RegisterId::A1,
+    RegisterId::A2,
+    RegisterId::A3,
+    RegisterId::A4,
+];
+pub const RETURN_VALUE_REGISTERS: [RegisterId; 2] = [RegisterId::V0, RegisterId::V1];
+/// Registers a routine must restore before it returns, apart from the stack and frame pointer.
+pub const SAVED_REGISTERS: [RegisterId; 10] = [
+    RegisterId::S0,
+    RegisterId::S1,
+    RegisterId::S2,
+    RegisterId::S3,
+    RegisterId::S4,
+    RegisterId::S5,
+    RegisterId::S6,
+    RegisterId::S7,
+    RegisterId::S8,
+    RegisterId::S9,
+];
+/// Alignment of the stack pointer in bytes.
+pub const STACK_ALIGNMENT: u32 = WORD_BYTES;
+
 #[cfg(test)]
 mod test {
     use super::*;
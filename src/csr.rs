@@ -0,0 +1,246 @@
+use crate::{enum_to_u32, ExitCode, InstructionGroup, IsaProfile};
+
+/// Cycles since the processor was reset, low 32 bits (read-only).
+pub const CSR_CYCLE: u16 = 0x000;
+/// Cycles since the processor was reset, high 32 bits (read-only).
+pub const CSR_CYCLEH: u16 = 0x001;
+/// Instructions since the processor was reset, low 32 bits.
+pub const CSR_INSTRET: u16 = 0x002;
+/// Instructions since the processor was reset, high 32 bits.
+pub const CSR_INSTRETH: u16 = 0x003;
+/// Number of the [`ExitCode`](enum.ExitCode.html) of the last error (read-only).
+pub const CSR_CAUSE: u16 = 0x010;
+/// Address of the instruction which caused the last error (read-only).
+pub const CSR_EPC: u16 = 0x011;
+/// Interrupt lines which are enabled, one bit each.
+pub const CSR_IE: u16 = 0x018;
+/// Interrupt lines which are pending, one bit each. Devices raise them, handlers clear them.
+pub const CSR_IP: u16 = 0x019;
+/// Address of the interrupt handler.
+pub const CSR_IVEC: u16 = 0x01A;
+/// Address of the instruction which `IRET` returns to.
+pub const CSR_IEPC: u16 = 0x01B;
+/// [`ISTATUS_HANDLING`](constant.ISTATUS_HANDLING.html) and
+/// [`ISTATUS_BANKED`](constant.ISTATUS_BANKED.html) (read-only).
+pub const CSR_ISTATUS: u16 = 0x01C;
+/// [`MACHINE_ID`](constant.MACHINE_ID.html) (read-only).
+pub const CSR_MACHINE_ID: u16 = 0x020;
+/// The instruction groups the processor implements, one bit per
+/// [`InstructionGroup`](enum.InstructionGroup.html) in the order of `InstructionGroup::ALL` (read-only).
+pub const CSR_ISA: u16 = 0x021;
+
+/// Value of [`CSR_MACHINE_ID`](constant.CSR_MACHINE_ID.html), "VCPU" in ASCII.
+pub const MACHINE_ID: u32 = 0x5643_5055;
+
+/// Bit of [`CSR_ISTATUS`](constant.CSR_ISTATUS.html) which is set while an interrupt is handled.
+pub const ISTATUS_HANDLING: u32 = 1;
+/// Bit of [`CSR_ISTATUS`](constant.CSR_ISTATUS.html) which is set if the shadow bank was swapped in when the
+/// handler was entered.
+pub const ISTATUS_BANKED: u32 = 2;
+
+/// Number of interrupt lines, one per bit of [`CSR_IE`](constant.CSR_IE.html) and
+/// [`CSR_IP`](constant.CSR_IP.html).
+pub const INTERRUPT_LINES: u32 = 32;
+
+/// The control and status registers of a processor, which programs access with `CSRR`, `CSRW`, `CSRS` and
+/// `CSRC`.
+///
+/// | Number  | Name         | Access     | Description                                            |
+/// |---------|--------------|------------|--------------------------------------------------------|
+/// | `0x000` | `CYCLE`      | read-only  | Cycles since the reset, low 32 bits                    |
+/// | `0x001` | `CYCLEH`     | read-only  | Cycles since the reset, high 32 bits                   |
+/// | `0x002` | `INSTRET`    | read-write | Instructions since the reset, low 32 bits              |
+/// | `0x003` | `INSTRETH`   | read-write | Instructions since the reset, high 32 bits             |
+/// | `0x010` | `CAUSE`      | read-only  | Exit code of the last error                            |
+/// | `0x011` | `EPC`        | read-only  | Address of the instruction which caused the last error |
+/// | `0x018` | `IE`         | read-write | Bits of the interrupt lines which are enabled          |
+/// | `0x019` | `IP`         | read-write | Bits of the interrupt lines which are pending          |
+/// | `0x01A` | `IVEC`       | read-write | Address of the interrupt handler                       |
+/// | `0x01B` | `IEPC`       | read-write | Address which `IRET` returns to                        |
+/// | `0x01C` | `ISTATUS`    | read-only  | Whether an interrupt is handled, and with which bank   |
+/// | `0x020` | `MACHINE_ID` | read-only  | `0x56435055` ("VCPU")                                  |
+/// | `0x021` | `ISA`        | read-only  | Bits of the instruction groups which are implemented   |
+///
+/// Every instruction takes one cycle, including one which stops the processor. Both counters include the
+/// instruction which accesses them, and only differ once the program writes `INSTRET`. `CAUSE` and `EPC` are
+/// only of use to hosts which let the program continue after an error, e.g. after handling it.
+///
+/// Before an instruction, if a line is both pending and enabled and no interrupt is being handled, the processor
+/// enters the handler: `IEPC` is set to the address of the instruction, and the program continues at `IVEC`. If
+/// the processor implements [`InstructionGroup::Shadow`](enum.InstructionGroup.html#variant.Shadow), the shadow
+/// bank is swapped in as well, so the handler can use every register without saving it. Handlers are not
+/// interrupted themselves. They clear the lines they handled in `IP` and return with `IRET`, which swaps the
+/// banks back and continues at `IEPC`.
+///
+/// Accessing an unknown register or writing a read-only one stops the processor with
+/// [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode). `CSRS` and `CSRC` with `ZERO` as
+/// the mask do not write, so they read read-only registers as well.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControlRegisters {
+    cycles: u64,
+    instret: u64,
+    cause: u32,
+    epc: u32,
+    ie: u32,
+    ip: u32,
+    ivec: u32,
+    iepc: u32,
+    istatus: u32,
+}
+
+impl ControlRegisters {
+    pub fn new() -> ControlRegisters {
+        Default::default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    pub fn cause(&self) -> u32 {
+        self.cause
+    }
+
+    pub fn epc(&self) -> u32 {
+        self.epc
+    }
+
+    pub fn ie(&self) -> u32 {
+        self.ie
+    }
+
+    pub fn ip(&self) -> u32 {
+        self.ip
+    }
+
+    pub fn ivec(&self) -> u32 {
+        self.ivec
+    }
+
+    pub fn iepc(&self) -> u32 {
+        self.iepc
+    }
+
+    pub fn istatus(&self) -> u32 {
+        self.istatus
+    }
+
+    /// Sets the cycle counter, e.g. when a saved processor is restored. Unlike [`write`](#method.write), the
+    /// setters also change registers which are read-only to the program.
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    pub fn set_instret(&mut self, instret: u64) {
+        self.instret = instret;
+    }
+
+    pub fn set_cause(&mut self, cause: u32) {
+        self.cause = cause;
+    }
+
+    pub fn set_epc(&mut self, epc: u32) {
+        self.epc = epc;
+    }
+
+    pub fn set_ie(&mut self, ie: u32) {
+        self.ie = ie;
+    }
+
+    pub fn set_ip(&mut self, ip: u32) {
+        self.ip = ip;
+    }
+
+    pub fn set_ivec(&mut self, ivec: u32) {
+        self.ivec = ivec;
+    }
+
+    pub fn set_iepc(&mut self, iepc: u32) {
+        self.iepc = iepc;
+    }
+
+    pub fn set_istatus(&mut self, istatus: u32) {
+        self.istatus = istatus & (ISTATUS_HANDLING | ISTATUS_BANKED);
+    }
+
+    /// Returns the value of register `number` for a processor with `profile`, or `None` if it does not exist.
+    pub fn read(&self, number: u16, profile: &IsaProfile) -> Option<u32> {
+        Some(match number {
+            CSR_CYCLE => self.cycles as u32,
+            CSR_CYCLEH => (self.cycles >> 32) as u32,
+            CSR_INSTRET => self.instret as u32,
+            CSR_INSTRETH => (self.instret >> 32) as u32,
+            CSR_CAUSE => self.cause,
+            CSR_EPC => self.epc,
+            CSR_IE => self.ie,
+            CSR_IP => self.ip,
+            CSR_IVEC => self.ivec,
+            CSR_IEPC => self.iepc,
+            CSR_ISTATUS => self.istatus,
+            CSR_MACHINE_ID => MACHINE_ID,
+            CSR_ISA => InstructionGroup::ALL
+                .iter()
+                .enumerate()
+                .filter(|(_, group)| profile.enables(**group))
+                .fold(0, |bits, (i, _)| bits | 1 << i),
+            _ => return None,
+        })
+    }
+
+    /// Writes register `number` as the program would.
+    ///
+    /// # Errors
+    /// Returns an error if the register does not exist or is read-only.
+    pub fn write(&mut self, number: u16, value: u32) -> Result<(), String> {
+        match number {
+            CSR_INSTRET => self.instret = (self.instret & !0xFFFF_FFFF) | u64::from(value),
+            CSR_INSTRETH => self.instret = (self.instret & 0xFFFF_FFFF) | u64::from(value) << 32,
+            CSR_IE => self.ie = value,
+            CSR_IP => self.ip = value,
+            CSR_IVEC => self.ivec = value,
+            CSR_IEPC => self.iepc = value,
+            CSR_CYCLE | CSR_CYCLEH | CSR_CAUSE | CSR_EPC | CSR_ISTATUS | CSR_MACHINE_ID
+            | CSR_ISA => return Err(format!("Control register 0x{:03X} is read-only", number)),
+            _ => return Err(format!("Control register 0x{:03X} does not exist", number)),
+        }
+        Ok(())
+    }
+
+    /// Counts an instruction before it is executed.
+    pub(crate) fn count(&mut self) {
+        self.cycles += 1;
+        self.instret += 1;
+    }
+
+    /// Enters the handler if an enabled line is pending and no interrupt is handled, and returns its address.
+    /// `banked` tells whether the processor swaps in the shadow bank.
+    pub(crate) fn enter_interrupt(&mut self, pc: u32, banked: bool) -> Option<u32> {
+        if self.istatus & ISTATUS_HANDLING != 0 || self.ie & self.ip == 0 {
+            return None;
+        }
+        self.iepc = pc;
+        self.istatus = ISTATUS_HANDLING | if banked { ISTATUS_BANKED } else { 0 };
+        Some(self.ivec)
+    }
+
+    /// Leaves the handler, and returns the address to return to and whether the banks have to be swapped back.
+    /// Returns `None` if no interrupt is handled.
+    pub(crate) fn leave_interrupt(&mut self) -> Option<(u32, bool)> {
+        if self.istatus & ISTATUS_HANDLING == 0 {
+            return None;
+        }
+        let banked = self.istatus & ISTATUS_BANKED != 0;
+        self.istatus = 0;
+        Some((self.iepc, banked))
+    }
+
+    /// Records the error which the instruction at `pc` stopped the processor with.
+    pub(crate) fn trap(&mut self, exit_code: ExitCode, pc: u32) {
+        self.cause = enum_to_u32(exit_code);
+        self.epc = pc;
+    }
+}
@@ -10,9 +10,14 @@ use util_derive::{EnumFromStr, InteropGetName};
 ///
 /// | Format   | Bits 31-26 | Bits 25-21 | Bits 20-16 | Bits 15-11 | Bits 10-6 | Bits 5-0  |
 /// |----------|------------|------------|------------|------------|-----------|-----------|
-/// | R-Format | opcode     | Rd         | Rs1        | Rs2        | unused    | funct     |
+/// | R-Format | opcode     | Rd         | Rs1        | Rs2        | shamt     | funct     |
 /// | I-Format | opcode     | Rd         | Rs1        | immediate  | immediate | immediate |
 /// | J-Format | opcode     | address    | address    | address    | address   | address   |
+///
+/// `shamt` is only used by the funnel shifts ([`AluFunct::FSL`](enum.AluFunct.html#variant.FSL) and
+/// [`AluFunct::FSR`](enum.AluFunct.html#variant.FSR)) and, as the index of a byte or half, by
+/// [`AluFunct::EXTB`](enum.AluFunct.html#variant.EXTB) to [`AluFunct::INSH`](enum.AluFunct.html#variant.INSH).
+/// It is zero otherwise.
 #[derive(
     Clone, Copy, PartialEq, Eq, Debug, ToPrimitive, FromPrimitive, InteropGetName, EnumFromStr,
 )]
@@ -238,6 +243,95 @@ pub enum Opcode {
     /// Format: `I`.
     /// Performs floating point operation specified by `funct` (see [`FlopFunct`](enum.FlopFunct.html)).
     FLOP,
+    /// Read control and status register.
+    ///
+    /// Format: `I`.
+    /// Sets `Rd` to the control and status register `immediate` (see
+    /// [`ControlRegisters`](struct.ControlRegisters.html)).
+    CSRR,
+    /// Write control and status register.
+    ///
+    /// Format: `I`.
+    /// Sets `Rd` to the control and status register `immediate`, then sets the register to `Rs1`.
+    CSRW,
+    /// Set bits of control and status register.
+    ///
+    /// Format: `I`.
+    /// Sets `Rd` to the control and status register `immediate`, then sets the bits of `Rs1` in the register.
+    /// With `Rs1` = `ZERO` the register is only read.
+    CSRS,
+    /// Clear bits of control and status register.
+    ///
+    /// Format: `I`.
+    /// Sets `Rd` to the control and status register `immediate`, then clears the bits of `Rs1` in the
+    /// register. With `Rs1` = `ZERO` the register is only read.
+    CSRC,
+    /// Vector operation.
+    ///
+    /// Format: `R`.
+    /// Performs vector operation specified by `funct` (see [`VecFunct`](enum.VecFunct.html)).
+    VEC,
+    /// Branch if equal.
+    ///
+    /// Format: `I`.
+    /// If `Rd == Rs1`, adds `immediate` to program counter.
+    BEQ,
+    /// Branch if not equal.
+    ///
+    /// Format: `I`.
+    /// If `Rd != Rs1`, adds `immediate` to program counter.
+    BNE,
+    /// Branch if less than.
+    ///
+    /// Format: `I`.
+    /// If `Rd < Rs1`, adds `immediate` to program counter.
+    BLT,
+    /// Branch if greater or equal.
+    ///
+    /// Format: `I`.
+    /// If `Rd >= Rs1`, adds `immediate` to program counter.
+    BGE,
+    /// Branch if less than unsigned.
+    ///
+    /// Format: `I`.
+    /// If `Rd < Rs1`, adds `immediate` to program counter (using unsigned arithmetic).
+    BLTU,
+    /// Branch if greater or equal unsigned.
+    ///
+    /// Format: `I`.
+    /// If `Rd >= Rs1`, adds `immediate` to program counter (using unsigned arithmetic).
+    BGEU,
+    /// Register bank operation.
+    ///
+    /// Format: `R`.
+    /// Swaps the register banks or moves a value between them as specified by `funct` (see
+    /// [`BankFunct`](enum.BankFunct.html)).
+    BANK,
+    /// Return from interrupt.
+    ///
+    /// Format: `I`.
+    /// Sets program counter to the control and status register `IEPC`, and swaps the register banks back if the
+    /// handler was entered with the shadow bank (see [`ControlRegisters`](struct.ControlRegisters.html)).
+    /// Stops with `InvalidOpcode` if no interrupt is handled.
+    IRET,
+}
+
+impl Opcode {
+    /// Whether the opcode is a conditional branch, which adds its immediate to the program counter if its
+    /// condition holds.
+    pub fn is_branch(self) -> bool {
+        matches!(
+            self,
+            Opcode::BEZ
+                | Opcode::BNZ
+                | Opcode::BEQ
+                | Opcode::BNE
+                | Opcode::BLT
+                | Opcode::BGE
+                | Opcode::BLTU
+                | Opcode::BGEU
+        )
+    }
 }
 
 /// List of functions used by the [`Opcode::ALU`](enum.Opcode.html#variant.ALU) instruction.
@@ -325,6 +419,63 @@ pub enum AluFunct {
     ///
     /// Sets `Rd` to `1` if `Rs1 >= Rs1` and to `0` otherwise (using unsigned arithmetic).
     SGEU,
+    /// Add with overflow check.
+    ///
+    /// Sets `Rd` to `Rs1 + Rs2`, or stops the processor with
+    /// [`ExitCode::Overflow`](enum.ExitCode.html#variant.Overflow) if the sum does not fit into a signed word.
+    ADDO,
+    /// Subtract with overflow check.
+    ///
+    /// Sets `Rd` to `Rs1 - Rs2`, or stops the processor with
+    /// [`ExitCode::Overflow`](enum.ExitCode.html#variant.Overflow) if the difference does not fit into a signed
+    /// word.
+    SUBO,
+    /// Rotate left.
+    ///
+    /// Sets `Rd` to `Rs1` rotated left by `Rs2` bits (modulo 32).
+    ROL,
+    /// Rotate right.
+    ///
+    /// Sets `Rd` to `Rs1` rotated right by `Rs2` bits (modulo 32).
+    ROR,
+    /// Funnel shift left.
+    ///
+    /// Shifts the pair `Rs1:Rs2`, with `Rs1` as the high word, left by `shamt` bits and sets `Rd` to the high
+    /// word of the result, i.e. the high bits of `Rs2` are shifted into `Rs1`.
+    FSL,
+    /// Funnel shift right.
+    ///
+    /// Shifts the pair `Rs1:Rs2`, with `Rs1` as the high word, right by `shamt` bits and sets `Rd` to the low
+    /// word of the result, i.e. the low bits of `Rs1` are shifted into `Rs2`.
+    FSR,
+    /// Byte swap.
+    ///
+    /// Sets `Rd` to `Rs1` with its bytes in reverse order, i.e. converts it between little and big endian.
+    BSWAP,
+    /// Extract byte.
+    ///
+    /// Sets `Rd` to byte `shamt` of `Rs1` (zero-extended), where byte 0 is the least significant one.
+    /// Stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode) if
+    /// `shamt` is greater than 3.
+    EXTB,
+    /// Extract half.
+    ///
+    /// Sets `Rd` to half `shamt` of `Rs1` (zero-extended), where half 0 is the least significant one.
+    /// Stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode) if
+    /// `shamt` is greater than 1.
+    EXTH,
+    /// Insert byte.
+    ///
+    /// Sets `Rd` to `Rs1` with byte `shamt` replaced by the least significant byte of `Rs2`.
+    /// Stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode) if
+    /// `shamt` is greater than 3.
+    INSB,
+    /// Insert half.
+    ///
+    /// Sets `Rd` to `Rs1` with half `shamt` replaced by the least significant half of `Rs2`.
+    /// Stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode) if
+    /// `shamt` is greater than 1.
+    INSH,
 }
 
 // TODO: add more float operations
@@ -352,6 +503,72 @@ pub enum FlopFunct {
     FDIV,
 }
 
+/// List of functions used by the [`Opcode::VEC`](enum.Opcode.html#variant.VEC) instruction.
+///
+/// Vector registers (see [`VectorRegisterId`](enum.VectorRegisterId.html)) are called `Qd`, `Qs1` and `Qs2`,
+/// and are given in the fields of `Rd`, `Rs1` and `Rs2`. All operations work on each of the
+/// [`VECTOR_LANES`](constant.VECTOR_LANES.html) 32 bit lanes separately.
+#[derive(
+    Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, Debug, InteropGetName, EnumFromStr,
+)]
+pub enum VecFunct {
+    /// Vector add.
+    ///
+    /// Sets `Qd` to `Qs1 + Qs2`.
+    VADD,
+    /// Vector subtract.
+    ///
+    /// Sets `Qd` to `Qs1 - Qs2`.
+    VSUB,
+    /// Vector multiply.
+    ///
+    /// Sets `Qd` to the low 32 bits of `Qs1 * Qs2`.
+    VMUL,
+    /// Vector splat.
+    ///
+    /// Sets every lane of `Qd` to `Rs1`.
+    VSPLAT,
+    /// Vector insert.
+    ///
+    /// Sets lane `Rs2` of `Qd` to `Rs1`, where the field of `Rs2` holds the number of the lane.
+    VINS,
+    /// Vector extract.
+    ///
+    /// Sets `Rd` to lane `Rs2` of `Qs1`, where the field of `Rs2` holds the number of the lane.
+    VEXT,
+    /// Vector load.
+    ///
+    /// Sets `Qd` to the words loaded from memory at address `Rs1`, the first one in lane 0.
+    VLD,
+    /// Vector store.
+    ///
+    /// Writes the lanes of `Qd` to memory at address `Rs1`, lane 0 first.
+    VST,
+}
+
+/// List of functions used by the [`Opcode::BANK`](enum.Opcode.html#variant.BANK) instruction, which work on
+/// the two banks of the [`RegisterFile`](struct.RegisterFile.html). Simple handlers can swap in the shadow
+/// bank instead of saving the registers they use to the stack, and swap back when they are done.
+///
+/// `ZERO` is zero in both banks, writing it has no effect.
+#[derive(
+    Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, Debug, InteropGetName, EnumFromStr,
+)]
+pub enum BankFunct {
+    /// Swap banks.
+    ///
+    /// Makes the shadow bank the active one and the active bank the shadow one.
+    SWAPB,
+    /// Read shadow register.
+    ///
+    /// Sets `Rd` to `Rs1` of the shadow bank.
+    RDSH,
+    /// Write shadow register.
+    ///
+    /// Sets `Rd` of the shadow bank to `Rs1`.
+    WRSH,
+}
+
 /// List of available registers.
 #[derive(
     Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, Debug, InteropGetName, EnumFromStr,
@@ -423,6 +640,21 @@ pub enum RegisterId {
     RA,
 }
 
+/// List of available vector registers, each of which holds [`VECTOR_LANES`](constant.VECTOR_LANES.html) words.
+#[derive(
+    Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, Debug, InteropGetName, EnumFromStr,
+)]
+pub enum VectorRegisterId {
+    Q0,
+    Q1,
+    Q2,
+    Q3,
+    Q4,
+    Q5,
+    Q6,
+    Q7,
+}
+
 #[inline]
 pub fn enum_to_u32<T: ToPrimitive + Copy>(val: T) -> u32 {
     val.to_u32().unwrap()
@@ -441,7 +673,10 @@ macro_rules! impl_enum_display {
 impl_enum_display!(Opcode);
 impl_enum_display!(AluFunct);
 impl_enum_display!(FlopFunct);
+impl_enum_display!(VecFunct);
+impl_enum_display!(BankFunct);
 impl_enum_display!(RegisterId);
+impl_enum_display!(VectorRegisterId);
 
 #[inline]
 pub fn register_index(id: RegisterId) -> usize {
@@ -501,6 +736,59 @@ macro_rules! instr_alu {
     };
 }
 
+/// Constructs a funnel shift ([`AluFunct::FSL`](enum.AluFunct.html#variant.FSL) or
+/// [`AluFunct::FSR`](enum.AluFunct.html#variant.FSR)) by `shamt` bits.
+pub fn make_funnel_instruction(
+    funct: AluFunct,
+    rd: RegisterId,
+    rs1: RegisterId,
+    rs2: RegisterId,
+    shamt: u32,
+) -> Word {
+    make_alu_instruction(funct, rd, rs1, rs2)
+        | ((shamt << constants::SHAMT_OFFSET) & constants::SHAMT_MASK)
+}
+
+/// Constructs a funnel shift instruction.
+#[macro_export]
+macro_rules! instr_funnel {
+    ($funct:ident, $rd:ident, $rs1:ident, $rs2:ident, $shamt:expr) => {
+        make_funnel_instruction(
+            AluFunct::$funct,
+            RegisterId::$rd,
+            RegisterId::$rs1,
+            RegisterId::$rs2,
+            $shamt,
+        )
+    };
+}
+
+/// Constructs an instruction which extracts or inserts byte or half `index`
+/// ([`AluFunct::EXTB`](enum.AluFunct.html#variant.EXTB) to [`AluFunct::INSH`](enum.AluFunct.html#variant.INSH)).
+pub fn make_index_instruction(
+    funct: AluFunct,
+    rd: RegisterId,
+    rs1: RegisterId,
+    rs2: RegisterId,
+    index: u32,
+) -> Word {
+    make_funnel_instruction(funct, rd, rs1, rs2, index)
+}
+
+/// Constructs a byte or half extract or insert instruction.
+#[macro_export]
+macro_rules! instr_index {
+    ($funct:ident, $rd:ident, $rs1:ident, $rs2:ident, $index:expr) => {
+        make_index_instruction(
+            AluFunct::$funct,
+            RegisterId::$rd,
+            RegisterId::$rs1,
+            RegisterId::$rs2,
+            $index,
+        )
+    };
+}
+
 /// Constructs a FLOP instruction.
 pub fn make_flop_instruction(
     funct: FlopFunct,
@@ -524,6 +812,32 @@ macro_rules! instr_flop {
     };
 }
 
+/// Constructs a VEC instruction. Depending on `funct`, the fields hold the numbers of vector registers, of
+/// registers or of a lane (see [`VecFunct`](enum.VecFunct.html)).
+pub fn make_vec_instruction(funct: VecFunct, rd: u32, rs1: u32, rs2: u32) -> Word {
+    ((enum_to_u32(Opcode::VEC) << constants::OPCODE_OFFSET) & constants::OPCODE_MASK)
+        | ((rd << constants::RD_OFFSET) & constants::RD_MASK)
+        | ((rs1 << constants::RS1_OFFSET) & constants::RS1_MASK)
+        | ((rs2 << constants::RS2_OFFSET) & constants::RS2_MASK)
+        | ((enum_to_u32(funct) << constants::FUNCT_OFFSET) & constants::FUNCT_MASK)
+}
+
+/// Constructs a BANK instruction.
+pub fn make_bank_instruction(funct: BankFunct, rd: RegisterId, rs1: RegisterId) -> Word {
+    make_r_instruction(Opcode::BANK, rd, rs1, RegisterId::ZERO, enum_to_u32(funct))
+}
+
+/// Constructs a BANK instruction.
+#[macro_export]
+macro_rules! instr_bank {
+    ($funct:ident) => {
+        make_bank_instruction(BankFunct::$funct, RegisterId::ZERO, RegisterId::ZERO)
+    };
+    ($funct:ident, $rd:ident, $rs1:ident) => {
+        make_bank_instruction(BankFunct::$funct, RegisterId::$rd, RegisterId::$rs1)
+    };
+}
+
 /// Constructs an I-format instruction.
 #[inline]
 pub fn make_i_instruction(
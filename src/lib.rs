@@ -1,7 +1,10 @@
+mod clock;
 mod constants;
+mod csr;
 mod instructions;
 mod memory;
 mod processor;
+mod profile;
 mod register;
 mod storage;
 
@@ -11,10 +14,13 @@ pub type Address = i32;
 
 pub type Endian = util::Endian;
 
+pub use crate::clock::*;
 pub use crate::constants::*;
+pub use crate::csr::*;
 pub use crate::instructions::*;
 pub use crate::memory::*;
 pub use crate::processor::*;
+pub use crate::profile::*;
 pub use crate::register::*;
 pub use crate::storage::*;
 
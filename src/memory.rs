@@ -1,5 +1,7 @@
 mod composite;
 mod io;
+mod program;
 
 pub use composite::*;
 pub use io::*;
+pub use program::*;
@@ -115,6 +115,11 @@ impl CompositeMemory {
         let index = self.find_mount_index(address, upper_bound)?;
 
         self.fragments.insert(index, (address, Box::new(fragment)));
+        for i in self.registry.values_mut() {
+            if *i >= index {
+                *i += 1;
+            }
+        }
         self.registry.insert(key.to_string(), index);
 
         Ok(())
@@ -137,9 +142,35 @@ impl CompositeMemory {
     /// assert!(memory.unmount("f0").is_none());
     /// ```
     pub fn unmount(&mut self, key: &str) -> Option<Box<dyn StorageMut>> {
-        self.registry
-            .remove(key)
-            .map(|i| self.fragments.remove(i).1)
+        let index = self.registry.remove(key)?;
+        for i in self.registry.values_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        Some(self.fragments.remove(index).1)
+    }
+
+    /// Returns the key, address and length of every mounted fragment, ordered by address.
+    ///
+    /// # Examples
+    /// ```
+    /// use vcpu::CompositeMemory;
+    ///
+    /// let mut memory = CompositeMemory::new();
+    /// memory.mount(16, "f1", [0u8; 4]).unwrap();
+    /// memory.mount(0, "f0", [0u8; 16]).unwrap();
+    /// assert_eq!(memory.fragments(), vec![("f0", 0, 16), ("f1", 16, 4)]);
+    /// ```
+    pub fn fragments(&self) -> Vec<(&str, u32, u32)> {
+        let mut keys = vec![""; self.fragments.len()];
+        for (key, index) in self.registry.iter() {
+            keys[*index] = key;
+        }
+        keys.into_iter()
+            .zip(self.fragments.iter())
+            .map(|(key, (address, fragment))| (key, *address, fragment.length()))
+            .collect()
     }
 
     fn find_mount_index(&self, address: u32, upper_bound: u32) -> Result<usize, MountError> {
@@ -223,6 +254,17 @@ impl StorageMut for CompositeMemory {
     }
 }
 
+#[test]
+fn unmount_after_mounting_below() {
+    let mut memory = CompositeMemory::new();
+    assert_eq!(memory.mount(32, "high", [2u8; 4]), Ok(()));
+    assert_eq!(memory.mount(0, "low", [1u8; 4]), Ok(()));
+    assert_eq!(memory.unmount("high").map(|f| f.length()), Some(4));
+    assert_eq!(memory.read_byte(0), Ok(1));
+    assert_eq!(memory.read_byte(32), Err(()));
+    assert_eq!(memory.fragments(), vec![("low", 0, 4)]);
+}
+
 #[test]
 fn find_mount_index() {
     let mut memory = CompositeMemory::new();
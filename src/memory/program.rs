@@ -0,0 +1,114 @@
+use std::convert::TryFrom;
+
+/// Error type for [`ProgramMemory.load`] and [`ProgramMemory.patch`].
+///
+/// [`ProgramMemory.load`]: ./struct.ProgramMemory.html#method.load
+/// [`ProgramMemory.patch`]: ./struct.ProgramMemory.html#method.patch
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ProgramError {
+    /// The instructions were marked read-only.
+    ReadOnly,
+
+    /// The range does not lie within the instructions, or would exceed the address space.
+    OutOfRange,
+}
+
+/// Owns the instructions a [`Processor`] executes and allows changing them while a program runs, e.g. for
+/// loaders of dynamic libraries or for debuggers which patch code.
+///
+/// Every change increments the [`generation`], so that tools which cache decoded instructions know when to
+/// invalidate them. Marking the instructions read-only makes all changes fail.
+///
+/// # Examples
+/// ```
+/// use vcpu::{ProgramError, ProgramMemory};
+///
+/// let mut program = ProgramMemory::new(vec![0u8; 8]);
+/// assert_eq!(program.patch(4, &[1, 2, 3, 4]), Ok(()));
+/// assert_eq!(program.load(8, &[5, 6, 7, 8]), Ok(()));
+/// assert_eq!(program.as_ref(), &[0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8][..]);
+/// assert_eq!(program.generation(), 2);
+///
+/// program.set_read_only(true);
+/// assert_eq!(program.patch(0, &[1]), Err(ProgramError::ReadOnly));
+/// ```
+/// [`Processor`]: ../struct.Processor.html
+/// [`generation`]: #method.generation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramMemory {
+    bytes: Vec<u8>,
+    read_only: bool,
+    generation: u64,
+}
+
+impl ProgramMemory {
+    pub fn new(bytes: Vec<u8>) -> ProgramMemory {
+        ProgramMemory {
+            bytes,
+            read_only: false,
+            generation: 0,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Number of changes since the instructions were created.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Stores `bytes` at `address`, and extends the instructions with zeros if they end before.
+    pub fn load(&mut self, address: u32, bytes: &[u8]) -> Result<(), ProgramError> {
+        let end = self.check(address, bytes)?;
+        if end > self.bytes.len() {
+            self.bytes.resize(end, 0);
+        }
+        self.store(address, bytes);
+        Ok(())
+    }
+
+    /// Replaces the bytes at `address`, which must lie within the instructions.
+    pub fn patch(&mut self, address: u32, bytes: &[u8]) -> Result<(), ProgramError> {
+        if self.check(address, bytes)? > self.bytes.len() {
+            return Err(ProgramError::OutOfRange);
+        }
+        self.store(address, bytes);
+        Ok(())
+    }
+
+    /// Returns the end of the range `bytes` would occupy at `address`.
+    fn check(&self, address: u32, bytes: &[u8]) -> Result<usize, ProgramError> {
+        if self.read_only {
+            return Err(ProgramError::ReadOnly);
+        }
+        u32::try_from(bytes.len())
+            .ok()
+            .and_then(|length| address.checked_add(length))
+            .map(|end| end as usize)
+            .ok_or(ProgramError::OutOfRange)
+    }
+
+    fn store(&mut self, address: u32, bytes: &[u8]) {
+        let start = address as usize;
+        self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+        self.generation += 1;
+    }
+}
+
+impl AsRef<[u8]> for ProgramMemory {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<Vec<u8>> for ProgramMemory {
+    fn from(bytes: Vec<u8>) -> ProgramMemory {
+        ProgramMemory::new(bytes)
+    }
+}
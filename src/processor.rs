@@ -1,13 +1,19 @@
 mod logic;
 
 use crate::StorageMut;
-use crate::{constants, register_index, Address, Endian, Immediate, Register, RegisterId, Word};
+use crate::{
+    constants, enum_to_u32, register_index, Address, ClockSource, ControlRegisters, Endian,
+    Immediate, InstructionGroup, IsaProfile, Register, RegisterFile, RegisterId, SystemClock,
+    Vector, VectorRegisterId, Word, INTERRUPT_LINES,
+};
 use logic::TickResult;
 use util::InteropGetName;
 use util_derive::InteropGetName;
 
 use byteorder::ByteOrder;
 use num_derive::{FromPrimitive, ToPrimitive};
+use std::fmt;
+use std::time::Duration;
 
 pub const fn jmp_addr_i16(offset: i16) -> Immediate {
     offset * (constants::WORD_BYTES as i16)
@@ -48,12 +54,198 @@ pub enum ExitCode {
     InvalidOpcode,
     /// Program counter is out of instruction memory range.
     BadProgramCounter,
+    /// Execution was stopped by the host before the program stopped by itself.
+    Terminated,
+    /// The result of a checked arithmetic instruction (`ADDO` or `SUBO`) overflowed.
+    Overflow,
+}
+
+impl ExitCode {
+    /// Whether the program stopped because of an error, i.e. not by halting or by the host.
+    pub fn is_error(self) -> bool {
+        !matches!(self, ExitCode::Halted | ExitCode::Terminated)
+    }
+
+    /// Converts errors into `Err`, e.g. to propagate them with `?`.
+    pub fn into_result(self) -> Result<ExitCode, ExitCode> {
+        if self.is_error() {
+            Err(self)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExitCode::Halted => "halted",
+            ExitCode::DivisionByZero => "division by zero",
+            ExitCode::BadMemoryAccess => "bad memory access",
+            ExitCode::BadAlignment => "jump to an unaligned address",
+            ExitCode::BadJump => "jump out of the instruction memory",
+            ExitCode::InvalidOpcode => "invalid opcode",
+            ExitCode::BadProgramCounter => "program counter out of the instruction memory",
+            ExitCode::Terminated => "terminated",
+            ExitCode::Overflow => "integer overflow",
+        })
+    }
+}
+
+impl std::error::Error for ExitCode {}
+
+/// Details of the instruction which stopped the processor with an error.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Fault {
+    /// The instruction word, unless the program counter was outside of the instruction memory.
+    pub instruction: Option<Word>,
+    /// The memory address of a failed load or store, or the target of a failed jump.
+    pub address: Option<u32>,
+}
+
+/// The outcome of [`Processor.run_result`].
+///
+/// [`Processor.run_result`]: ./struct.Processor.html#method.run_result
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct RunResult {
+    pub exit_code: ExitCode,
+    /// Address of the instruction which stopped the processor.
+    pub program_counter: u32,
+    /// Number of instructions executed by the run, including the one which stopped it.
+    pub executed: u64,
+    /// Set if the exit code is an error.
+    pub fault: Option<Fault>,
+}
+
+impl RunResult {
+    /// Converts runs which stopped because of an error into `Err`.
+    pub fn into_result(self) -> Result<RunResult, RunResult> {
+        if self.exit_code.is_error() {
+            Err(self)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl fmt::Display for RunResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at 0x{:08X}", self.exit_code, self.program_counter)?;
+        if let Some(fault) = self.fault {
+            if let Some(instruction) = fault.instruction {
+                write!(f, " (instruction 0x{:08X})", instruction)?;
+            }
+            if let Some(address) = fault.address {
+                write!(f, ", address 0x{:08X}", address)?;
+            }
+        }
+        write!(f, " after {} instructions", self.executed)
+    }
+}
+
+/// Limits for [`Processor.run_with`], all of which are unlimited by default.
+///
+/// [`Processor.run_with`]: ./struct.Processor.html#method.run_with
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    /// Maximum number of instructions the run executes.
+    pub max_instructions: Option<u64>,
+    /// Maximum value of the [`cycles`](struct.Processor.html#method.cycles) of the processor, which also
+    /// counts the instructions of earlier runs.
+    pub max_cycles: Option<u64>,
+    /// Maximum time the run takes, as measured by the [`ClockSource`](trait.ClockSource.html) of the run. It
+    /// is only checked every [`TIMEOUT_INTERVAL`] instructions.
+    ///
+    /// [`TIMEOUT_INTERVAL`]: ./constant.TIMEOUT_INTERVAL.html
+    pub timeout: Option<Duration>,
+}
+
+/// Number of instructions between two checks of a [`RunOptions.timeout`](struct.RunOptions.html).
+pub const TIMEOUT_INTERVAL: u64 = 1024;
+
+/// The limit of [`RunOptions`](struct.RunOptions.html) which stopped a run.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Budget {
+    Instructions,
+    Cycles,
+    Timeout,
+}
+
+/// A run which reached a limit before the processor stopped. The processor can continue where it left off.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BudgetExceeded {
+    pub budget: Budget,
+    /// Address of the next instruction.
+    pub program_counter: u32,
+    /// Number of instructions executed by the run.
+    pub executed: u64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let budget = match self.budget {
+            Budget::Instructions => "instruction limit",
+            Budget::Cycles => "cycle limit",
+            Budget::Timeout => "timeout",
+        };
+        write!(
+            f,
+            "{} reached at 0x{:08X} after {} instructions",
+            budget, self.program_counter, self.executed
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Where the instructions are fetched from.
+#[derive(Clone, Copy)]
+enum Fetch<'a> {
+    /// A separate instruction memory, at whose end the program counter wraps around to 0.
+    Program(&'a [u8]),
+    /// The storage which loads and stores access.
+    Storage,
+}
+
+impl Fetch<'_> {
+    fn instruction(self, storage: &dyn StorageMut, address: u32) -> Option<Word> {
+        match self {
+            Fetch::Program(instructions) => {
+                let start = address as usize;
+                instructions
+                    .get(start..start.saturating_add(constants::WORD_BYTES as usize))
+                    .map(Endian::read_u32)
+            }
+            Fetch::Storage => storage.read_word(address).ok(),
+        }
+    }
+
+    /// Address of the instruction after the one at `pc`.
+    fn next(self, pc: u32) -> u32 {
+        match self {
+            Fetch::Program(instructions) => get_next_pc(pc, instructions.len() as u32),
+            Fetch::Storage => pc.wrapping_add(constants::WORD_BYTES),
+        }
+    }
+
+    /// Whether the program can jump to `address`.
+    fn contains(self, storage: &dyn StorageMut, address: u32) -> bool {
+        match self {
+            Fetch::Program(instructions) => (address as usize) < instructions.len(),
+            Fetch::Storage => storage.check_range(address, constants::WORD_BYTES),
+        }
+    }
 }
 
 pub struct Processor {
-    registers: [Register; constants::REGISTER_COUNT],
+    registers: RegisterFile,
+    vectors: [Vector; constants::VECTOR_REGISTER_COUNT],
     program_counter: u32,
+    reset_vector: u32,
     state: Option<ExitCode>,
+    fault: Option<Fault>,
+    csrs: ControlRegisters,
+    profile: IsaProfile,
 }
 
 impl Processor {
@@ -61,109 +253,403 @@ impl Processor {
         Default::default()
     }
 
+    /// Creates a processor which only implements the instruction groups enabled by `profile`.
+    pub fn with_profile(profile: IsaProfile) -> Processor {
+        Processor {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    /// The registers of the active bank.
     pub fn registers(&self) -> &[Register; constants::REGISTER_COUNT] {
-        &self.registers
+        self.registers.active()
     }
 
     pub fn registers_mut(&mut self) -> &mut [Register; constants::REGISTER_COUNT] {
-        &mut self.registers
+        self.registers.active_mut()
     }
 
     pub fn register(&self, id: RegisterId) -> &Register {
-        &self.registers[register_index(id)]
+        &self.registers.active()[register_index(id)]
     }
 
     pub fn register_mut(&mut self, id: RegisterId) -> &mut Register {
-        &mut self.registers[register_index(id)]
+        &mut self.registers.active_mut()[register_index(id)]
+    }
+
+    /// Both banks of registers, see [`BankFunct`](enum.BankFunct.html).
+    pub fn register_file(&self) -> &RegisterFile {
+        &self.registers
+    }
+
+    pub fn register_file_mut(&mut self) -> &mut RegisterFile {
+        &mut self.registers
+    }
+
+    pub fn vectors(&self) -> &[Vector; constants::VECTOR_REGISTER_COUNT] {
+        &self.vectors
+    }
+
+    pub fn vectors_mut(&mut self) -> &mut [Vector; constants::VECTOR_REGISTER_COUNT] {
+        &mut self.vectors
+    }
+
+    pub fn vector(&self, id: VectorRegisterId) -> &Vector {
+        &self.vectors[enum_to_u32(id) as usize]
+    }
+
+    pub fn vector_mut(&mut self, id: VectorRegisterId) -> &mut Vector {
+        &mut self.vectors[enum_to_u32(id) as usize]
     }
 
     pub fn program_counter(&self) -> u32 {
         self.program_counter
     }
 
+    /// Sets the address of the next instruction to execute, e.g. the entry point of an executable.
+    pub fn set_program_counter(&mut self, program_counter: u32) {
+        self.program_counter = program_counter;
+    }
+
+    /// Address at which the processor starts after a [`reset`](#method.reset), which is 0 unless it was changed.
+    pub fn reset_vector(&self) -> u32 {
+        self.reset_vector
+    }
+
+    /// Sets where the processor starts after a [`reset`](#method.reset), e.g. at a boot ROM. It takes effect with
+    /// the next reset.
+    pub fn set_reset_vector(&mut self, reset_vector: u32) {
+        self.reset_vector = reset_vector;
+    }
+
     pub fn state(&self) -> Option<ExitCode> {
         self.state
     }
 
+    /// Sets the state, e.g. when a saved processor is restored. With `None` the processor runs again. The
+    /// [`fault`](#method.fault) is cleared.
+    pub fn set_state(&mut self, state: Option<ExitCode>) {
+        self.state = state;
+        self.fault = None;
+    }
+
+    /// Number of instructions the processor executed since it was created or [`reset`](#method.reset), including
+    /// those which stopped it. Every instruction takes one cycle.
+    pub fn cycles(&self) -> u64 {
+        self.csrs.cycles()
+    }
+
+    pub fn control_registers(&self) -> &ControlRegisters {
+        &self.csrs
+    }
+
+    pub fn control_registers_mut(&mut self) -> &mut ControlRegisters {
+        &mut self.csrs
+    }
+
+    /// Makes interrupt `line` pending, e.g. when a device has data. The handler is entered before the next
+    /// instruction if the line is enabled, see [`ControlRegisters`](struct.ControlRegisters.html).
+    ///
+    /// # Panics
+    /// Panics if `line` is not below [`INTERRUPT_LINES`](constant.INTERRUPT_LINES.html).
+    pub fn raise_interrupt(&mut self, line: u32) {
+        assert!(
+            line < INTERRUPT_LINES,
+            "There is no interrupt line {}",
+            line
+        );
+        let ip = self.csrs.ip() | 1 << line;
+        self.csrs.set_ip(ip);
+    }
+
+    /// Makes interrupt `line` no longer pending, e.g. when a device was read before the handler was entered.
+    ///
+    /// # Panics
+    /// Panics if `line` is not below [`INTERRUPT_LINES`](constant.INTERRUPT_LINES.html).
+    pub fn lower_interrupt(&mut self, line: u32) {
+        assert!(
+            line < INTERRUPT_LINES,
+            "There is no interrupt line {}",
+            line
+        );
+        let ip = self.csrs.ip() & !(1 << line);
+        self.csrs.set_ip(ip);
+    }
+
+    /// Returns the control and status register `number`, or `None` if it does not exist.
+    pub fn csr(&self, number: u16) -> Option<u32> {
+        self.csrs.read(number, &self.profile)
+    }
+
+    /// Details of the error the processor stopped with, if it was caused by an instruction.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    pub fn profile(&self) -> &IsaProfile {
+        &self.profile
+    }
+
+    /// Changes the instruction groups which are implemented, which takes effect with the next instruction.
+    pub fn set_profile(&mut self, profile: IsaProfile) {
+        self.profile = profile;
+    }
+
     pub fn is_stopped(&self) -> bool {
         self.state.is_some()
     }
 
-    pub fn tick(&mut self, instructions: &[u8], storage: &mut dyn StorageMut) -> Option<ExitCode> {
+    /// Executes the instruction at the program counter, unless the processor has stopped already. The
+    /// instructions are usually a [`ProgramMemory`](struct.ProgramMemory.html), or any bytes.
+    pub fn tick<P: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        instructions: &P,
+        storage: &mut dyn StorageMut,
+    ) -> Option<ExitCode> {
+        self.execute(Fetch::Program(instructions.as_ref()), storage)
+    }
+
+    /// Like [`tick`](#method.tick), but fetches the instruction from `storage` like a load, e.g. from a boot ROM
+    /// or from a program which was copied into the RAM. The program counter does not wrap around, and jumps to
+    /// addresses which cannot be read stop the processor with [`ExitCode::BadJump`](enum.ExitCode.html).
+    pub fn tick_from_storage(&mut self, storage: &mut dyn StorageMut) -> Option<ExitCode> {
+        self.execute(Fetch::Storage, storage)
+    }
+
+    fn execute(&mut self, fetch: Fetch, storage: &mut dyn StorageMut) -> Option<ExitCode> {
         if !self.is_stopped() {
-            self.state = self.get_new_state(instructions, storage);
+            self.take_interrupt();
+            self.csrs.count();
+            self.state = self.get_new_state(fetch, storage);
+            if let Some(exit_code) = self.state.filter(|exit_code| exit_code.is_error()) {
+                self.csrs.trap(exit_code, self.program_counter);
+                let fault = self.fault.get_or_insert(Fault {
+                    instruction: None,
+                    address: None,
+                });
+                fault.instruction = fetch.instruction(storage, self.program_counter);
+            }
         }
 
         self.state
     }
 
+    /// Clears the registers, the vector registers, the state and the control and status registers, and continues
+    /// at the [`reset_vector`](#method.reset_vector). The profile stays the same.
     pub fn reset(&mut self) {
-        self.registers = [Default::default(); constants::REGISTER_COUNT];
-        self.program_counter = 0u32;
+        self.registers = RegisterFile::new();
+        self.vectors = Default::default();
+        self.program_counter = self.reset_vector;
         self.state = None;
+        self.fault = None;
+        self.csrs = ControlRegisters::new();
     }
 
-    fn get_new_state(
-        &mut self,
-        instructions: &[u8],
-        storage: &mut dyn StorageMut,
-    ) -> Option<ExitCode> {
-        let instr_len = instructions.len() as u32;
-        if self.program_counter + constants::WORD_BYTES > instr_len {
-            Some(ExitCode::BadProgramCounter)
-        } else {
-            let pc = self.program_counter as usize;
+    /// Enters the interrupt handler if an enabled interrupt is pending, which the next instruction would do
+    /// otherwise, and returns whether it did. The shadow bank is swapped in if the processor implements it.
+    ///
+    /// Runners which look at the instruction at the program counter before they execute it call this first, so
+    /// that they see the first instruction of the handler instead of the interrupted one.
+    pub fn take_interrupt(&mut self) -> bool {
+        if self.is_stopped() {
+            return false;
+        }
+        let banked = self.profile.enables(InstructionGroup::Shadow);
+        match self.csrs.enter_interrupt(self.program_counter, banked) {
+            Some(handler) => {
+                if banked {
+                    self.registers.swap();
+                }
+                self.program_counter = handler;
+                true
+            }
+            None => false,
+        }
+    }
 
-            let instruction =
-                Endian::read_u32(&instructions[pc..(pc + constants::WORD_BYTES as usize)]);
+    fn get_new_state(&mut self, fetch: Fetch, storage: &mut dyn StorageMut) -> Option<ExitCode> {
+        let instruction = match fetch.instruction(storage, self.program_counter) {
+            Some(instruction) => instruction,
+            None => return Some(ExitCode::BadProgramCounter),
+        };
+        if !self.profile.allows(instruction) {
+            return Some(ExitCode::InvalidOpcode);
+        }
 
-            let tick_result = logic::tick(
-                &mut self.registers,
-                storage,
-                instruction,
-                self.program_counter,
-            );
+        let tick_result = logic::tick(
+            &mut self.registers,
+            &mut self.vectors,
+            &mut self.csrs,
+            &self.profile,
+            storage,
+            instruction,
+            self.program_counter,
+        );
 
-            match tick_result {
-                TickResult::Next => {
-                    self.program_counter = get_next_pc(self.program_counter, instr_len);
-                    None
-                }
-                TickResult::Jump(new_pc, link) => {
-                    if (new_pc % (constants::WORD_BYTES as u32)) != 0 {
-                        Some(ExitCode::BadAlignment)
-                    } else if new_pc >= instr_len {
-                        Some(ExitCode::BadJump)
-                    } else {
-                        let old_pc = self.program_counter;
-                        if link {
-                            self.register_mut(RegisterId::RA)
-                                .set_u(get_next_pc(old_pc, instr_len));
-                        }
-                        self.program_counter = new_pc;
-                        None
+        match tick_result {
+            TickResult::Next => {
+                self.program_counter = fetch.next(self.program_counter);
+                None
+            }
+            TickResult::Jump(new_pc, link) => {
+                if (new_pc % (constants::WORD_BYTES as u32)) != 0 {
+                    self.fault_at(new_pc);
+                    Some(ExitCode::BadAlignment)
+                } else if !fetch.contains(storage, new_pc) {
+                    self.fault_at(new_pc);
+                    Some(ExitCode::BadJump)
+                } else {
+                    let old_pc = self.program_counter;
+                    if link {
+                        self.register_mut(RegisterId::RA).set_u(fetch.next(old_pc));
                     }
+                    self.program_counter = new_pc;
+                    None
                 }
-                TickResult::Stop(exit_code) => Some(exit_code),
+            }
+            TickResult::Stop(exit_code) => Some(exit_code),
+            TickResult::BadAccess(address) => {
+                self.fault_at(address);
+                Some(ExitCode::BadMemoryAccess)
             }
         }
     }
 
-    pub fn run(&mut self, instructions: &[u8], storage: &mut dyn StorageMut) -> ExitCode {
+    /// Records the address of a fault, to which `tick` adds the instruction.
+    fn fault_at(&mut self, address: u32) {
+        self.fault = Some(Fault {
+            instruction: None,
+            address: Some(address),
+        });
+    }
+
+    pub fn run<P: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        instructions: &P,
+        storage: &mut dyn StorageMut,
+    ) -> ExitCode {
         loop {
             if let Some(exit_code) = self.tick(instructions, storage) {
                 return exit_code;
             }
         }
     }
+
+    /// Like [`run`](#method.run), but also returns where the processor stopped and why.
+    ///
+    /// # Examples
+    /// ```
+    /// use vcpu::*;
+    ///
+    /// let load = instr_i!(LW, T0, ZERO, 16);
+    /// let instructions = instructions_from_words(&[load]);
+    /// let mut processor = Processor::new();
+    /// let result = processor.run_result(&instructions, &mut [0u8; 8]);
+    /// assert_eq!(result.exit_code, ExitCode::BadMemoryAccess);
+    /// assert_eq!(
+    ///     result.fault,
+    ///     Some(Fault {
+    ///         instruction: Some(load),
+    ///         address: Some(16)
+    ///     })
+    /// );
+    /// assert!(result.into_result().is_err());
+    /// ```
+    pub fn run_result<P: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        instructions: &P,
+        storage: &mut dyn StorageMut,
+    ) -> RunResult {
+        match self.run_with(instructions, storage, &RunOptions::default()) {
+            Ok(result) => result,
+            Err(_) => unreachable!("a run without limits exceeded its budget"),
+        }
+    }
+
+    /// Like [`run_result`](#method.run_result), but stops once a limit of `options` is reached, e.g. so that
+    /// programs which never halt cannot block the host.
+    ///
+    /// # Examples
+    /// ```
+    /// use vcpu::*;
+    ///
+    /// // loops forever
+    /// let instructions = instructions_from_words(&[instr_j!(JMP, 0)]);
+    /// let mut processor = Processor::new();
+    /// let options = RunOptions {
+    ///     max_instructions: Some(100),
+    ///     ..RunOptions::default()
+    /// };
+    /// let exceeded = processor
+    ///     .run_with(&instructions, &mut [0u8; 0], &options)
+    ///     .unwrap_err();
+    /// assert_eq!(exceeded.budget, Budget::Instructions);
+    /// assert_eq!(exceeded.executed, 100);
+    /// ```
+    pub fn run_with<P: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        instructions: &P,
+        storage: &mut dyn StorageMut,
+        options: &RunOptions,
+    ) -> Result<RunResult, BudgetExceeded> {
+        self.run_with_clock(instructions, storage, options, &SystemClock::new())
+    }
+
+    /// Like [`run_with`](#method.run_with), but measures the timeout with `clock` instead of the
+    /// [`SystemClock`](struct.SystemClock.html).
+    pub fn run_with_clock<P: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        instructions: &P,
+        storage: &mut dyn StorageMut,
+        options: &RunOptions,
+        clock: &dyn ClockSource,
+    ) -> Result<RunResult, BudgetExceeded> {
+        let deadline = options.timeout.map(|timeout| clock.now() + timeout);
+        let mut executed = 0;
+        let exceeded = |budget, processor: &Processor, executed| BudgetExceeded {
+            budget,
+            program_counter: processor.program_counter,
+            executed,
+        };
+        let exit_code = loop {
+            if let Some(exit_code) = self.state {
+                break exit_code;
+            }
+            if options.max_instructions.is_some_and(|max| executed >= max) {
+                return Err(exceeded(Budget::Instructions, self, executed));
+            }
+            if options.max_cycles.is_some_and(|max| self.cycles() >= max) {
+                return Err(exceeded(Budget::Cycles, self, executed));
+            }
+            if let Some(deadline) = deadline {
+                if executed.is_multiple_of(TIMEOUT_INTERVAL) && clock.now() >= deadline {
+                    return Err(exceeded(Budget::Timeout, self, executed));
+                }
+            }
+            self.tick(instructions, storage);
+            executed += 1;
+        };
+        Ok(RunResult {
+            exit_code,
+            program_counter: self.program_counter,
+            executed,
+            fault: self.fault,
+        })
+    }
 }
 
 impl Default for Processor {
     fn default() -> Processor {
         Processor {
-            registers: [Default::default(); constants::REGISTER_COUNT],
+            registers: RegisterFile::new(),
+            vectors: Default::default(),
             program_counter: 0u32,
+            reset_vector: 0u32,
             state: None,
+            fault: None,
+            csrs: ControlRegisters::new(),
+            profile: IsaProfile::full(),
         }
     }
 }
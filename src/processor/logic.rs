@@ -2,14 +2,16 @@ use num::FromPrimitive;
 use std::num::Wrapping;
 
 use crate::{
-    constants, register_index, AluFunct, ExitCode, FlopFunct, Opcode, Register, RegisterId,
-    StorageMut, Word,
+    constants, register_index, AluFunct, BankFunct, ControlRegisters, ExitCode, FlopFunct,
+    IsaProfile, Opcode, Register, RegisterFile, RegisterId, StorageMut, VecFunct, Vector, Word,
 };
 
 pub enum TickResult {
     Next,
     Jump(u32, bool),
     Stop(ExitCode),
+    /// A load or store at the address failed.
+    BadAccess(u32),
 }
 
 fn write_i(registers: &mut [Register], id: usize, value: Wrapping<i32>) {
@@ -78,12 +80,126 @@ fn load(
         .is_ok()
 }
 
+/// Sets every lane of `vectors[id]` to `f` of the lanes of `a` and `b`.
+fn lanes(vectors: &mut [Vector], id: usize, a: Vector, b: Vector, f: fn(u32, u32) -> u32) {
+    for (lane, (a, b)) in vectors[id].iter_mut().zip(a.iter().zip(b.iter())) {
+        *lane = f(*a, *b);
+    }
+}
+
+fn vector_operation(
+    registers: &mut [Register],
+    vectors: &mut [Vector],
+    storage: &mut dyn StorageMut,
+    instruction: Word,
+    rdid: usize,
+    rs1id: usize,
+    rs2id: usize,
+) -> TickResult {
+    let funct_value = (instruction & constants::FUNCT_MASK) >> constants::FUNCT_OFFSET;
+    let funct = match VecFunct::from_u32(funct_value) {
+        Some(funct) => funct,
+        None => return TickResult::Stop(ExitCode::InvalidOpcode),
+    };
+    let vector_count = vectors.len();
+    let is_vector = |id: usize| id < vector_count;
+    let is_lane = |id: usize| id < constants::VECTOR_LANES;
+    let rs1u = registers[rs1id].u();
+
+    match funct {
+        VecFunct::VADD | VecFunct::VSUB | VecFunct::VMUL => {
+            if !(is_vector(rdid) && is_vector(rs1id) && is_vector(rs2id)) {
+                return TickResult::Stop(ExitCode::InvalidOpcode);
+            }
+            let (a, b) = (vectors[rs1id], vectors[rs2id]);
+            match funct {
+                VecFunct::VADD => lanes(vectors, rdid, a, b, u32::wrapping_add),
+                VecFunct::VSUB => lanes(vectors, rdid, a, b, u32::wrapping_sub),
+                _ => lanes(vectors, rdid, a, b, u32::wrapping_mul),
+            }
+        }
+
+        VecFunct::VSPLAT => {
+            if !is_vector(rdid) {
+                return TickResult::Stop(ExitCode::InvalidOpcode);
+            }
+            vectors[rdid] = [rs1u; constants::VECTOR_LANES];
+        }
+
+        VecFunct::VINS => {
+            if !(is_vector(rdid) && is_lane(rs2id)) {
+                return TickResult::Stop(ExitCode::InvalidOpcode);
+            }
+            vectors[rdid][rs2id] = rs1u;
+        }
+
+        VecFunct::VEXT => {
+            if !(is_vector(rs1id) && is_lane(rs2id)) {
+                return TickResult::Stop(ExitCode::InvalidOpcode);
+            }
+            write_u(registers, rdid, Wrapping(vectors[rs1id][rs2id]));
+        }
+
+        VecFunct::VLD | VecFunct::VST => {
+            if !is_vector(rdid) {
+                return TickResult::Stop(ExitCode::InvalidOpcode);
+            }
+            // check the whole range first, so that a failed store does not write some of the lanes
+            if rs1u > u32::MAX - (constants::VECTOR_BYTES - 1)
+                || !storage.check_range(rs1u, constants::VECTOR_BYTES)
+            {
+                return TickResult::BadAccess(rs1u);
+            }
+            for (lane, value) in vectors[rdid].iter_mut().enumerate() {
+                let address = rs1u + lane as u32 * constants::WORD_BYTES;
+                let result = if funct == VecFunct::VLD {
+                    storage
+                        .read(address, constants::WORD_BYTES)
+                        .map(|loaded| *value = loaded)
+                } else {
+                    storage.write_word(address, *value)
+                };
+                if result.is_err() {
+                    return TickResult::BadAccess(address);
+                }
+            }
+        }
+    }
+
+    TickResult::Next
+}
+
+fn bank_operation(
+    register_file: &mut RegisterFile,
+    instruction: Word,
+    rdid: usize,
+    rs1id: usize,
+) -> TickResult {
+    let funct_value = (instruction & constants::FUNCT_MASK) >> constants::FUNCT_OFFSET;
+    match BankFunct::from_u32(funct_value) {
+        Some(BankFunct::SWAPB) => register_file.swap(),
+        Some(BankFunct::RDSH) => {
+            let value = register_file.shadow()[rs1id].u();
+            write_u(register_file.active_mut(), rdid, Wrapping(value));
+        }
+        Some(BankFunct::WRSH) => {
+            let value = register_file.active()[rs1id].u();
+            write_u(register_file.shadow_mut(), rdid, Wrapping(value));
+        }
+        None => return TickResult::Stop(ExitCode::InvalidOpcode),
+    }
+    TickResult::Next
+}
+
 fn jump(new_addr: Wrapping<u32>, link: bool) -> TickResult {
     TickResult::Jump(new_addr.0, link)
 }
 
 pub fn tick(
-    registers: &mut [Register],
+    register_file: &mut RegisterFile,
+    vectors: &mut [Vector],
+    csrs: &mut ControlRegisters,
+    profile: &IsaProfile,
     storage: &mut dyn StorageMut,
     instruction: Word,
     program_counter: u32,
@@ -98,6 +214,8 @@ pub fn tick(
         let rs1id = ((instruction & constants::RS1_MASK) >> constants::RS1_OFFSET) as usize;
         let rs2id = ((instruction & constants::RS2_MASK) >> constants::RS2_OFFSET) as usize;
 
+        let registers = register_file.active_mut();
+
         let rd = &registers[rdid];
         let rs1 = &registers[rs1id];
         let rs2 = &registers[rs2id];
@@ -115,6 +233,7 @@ pub fn tick(
         let imm_u = Wrapping(imm_u16 as u32);
         let imm_i = Wrapping(imm_i16 as i32);
         let imm_u_ex = Wrapping(imm_i.0 as u32);
+        let data_address = rs1u + imm_u_ex;
 
         let mut address = (instruction & constants::ADDRESS_MASK) >> constants::ADDRESS_OFFSET;
 
@@ -214,6 +333,59 @@ pub fn tick(
                         AluFunct::SGEU => {
                             set_if(registers, rdid, rs1u >= rs2u);
                         }
+
+                        AluFunct::ADDO => match rs1i.0.checked_add(rs2i.0) {
+                            Some(sum) => write_i(registers, rdid, Wrapping(sum)),
+                            None => return TickResult::Stop(ExitCode::Overflow),
+                        },
+
+                        AluFunct::SUBO => match rs1i.0.checked_sub(rs2i.0) {
+                            Some(difference) => write_i(registers, rdid, Wrapping(difference)),
+                            None => return TickResult::Stop(ExitCode::Overflow),
+                        },
+
+                        AluFunct::ROL => {
+                            write_u(registers, rdid, Wrapping(rs1u.0.rotate_left(rs2u.0)));
+                        }
+
+                        AluFunct::ROR => {
+                            write_u(registers, rdid, Wrapping(rs1u.0.rotate_right(rs2u.0)));
+                        }
+
+                        AluFunct::FSL | AluFunct::FSR => {
+                            let shamt =
+                                (instruction & constants::SHAMT_MASK) >> constants::SHAMT_OFFSET;
+                            let pair = u64::from(rs1u.0) << 32 | u64::from(rs2u.0);
+                            let word = if funct == AluFunct::FSL {
+                                (pair << shamt >> 32) as u32
+                            } else {
+                                (pair >> shamt) as u32
+                            };
+                            write_u(registers, rdid, Wrapping(word));
+                        }
+
+                        AluFunct::BSWAP => {
+                            write_u(registers, rdid, Wrapping(rs1u.0.swap_bytes()));
+                        }
+
+                        AluFunct::EXTB | AluFunct::EXTH | AluFunct::INSB | AluFunct::INSH => {
+                            let index =
+                                (instruction & constants::SHAMT_MASK) >> constants::SHAMT_OFFSET;
+                            let width = match funct {
+                                AluFunct::EXTB | AluFunct::INSB => constants::BYTE_WIDTH,
+                                _ => constants::HALF_WIDTH,
+                            };
+                            if index >= constants::WORD_WIDTH / width {
+                                return TickResult::Stop(ExitCode::InvalidOpcode);
+                            }
+                            let shift = index * width;
+                            let mask = (u32::MAX >> (constants::WORD_WIDTH - width)) << shift;
+                            let word = match funct {
+                                AluFunct::EXTB | AluFunct::EXTH => (rs1u.0 & mask) >> shift,
+                                _ => (rs1u.0 & !mask) | ((rs2u.0 << shift) & mask),
+                            };
+                            write_u(registers, rdid, Wrapping(word));
+                        }
                     }
                 } else {
                     return TickResult::Stop(ExitCode::InvalidOpcode);
@@ -255,10 +427,10 @@ pub fn tick(
                     registers,
                     storage,
                     rdid,
-                    rs1u + imm_u_ex,
+                    data_address,
                     constants::BYTE_BYTES,
                 ) {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
@@ -267,10 +439,10 @@ pub fn tick(
                     registers,
                     storage,
                     rdid,
-                    rs1u + imm_u_ex,
+                    data_address,
                     constants::HALF_BYTES,
                 ) {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
@@ -279,37 +451,28 @@ pub fn tick(
                     registers,
                     storage,
                     rdid,
-                    rs1u + imm_u_ex,
+                    data_address,
                     constants::WORD_BYTES,
                 ) {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
             Opcode::SB => {
-                if storage
-                    .write_byte((rs1u + imm_u_ex).0, rd.u() as u8)
-                    .is_err()
-                {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                if storage.write_byte(data_address.0, rd.u() as u8).is_err() {
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
             Opcode::SH => {
-                if storage
-                    .write_half((rs1u + imm_u_ex).0, rd.u() as u16)
-                    .is_err()
-                {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                if storage.write_half(data_address.0, rd.u() as u16).is_err() {
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
             Opcode::SW => {
-                if storage
-                    .write_word((rs1u + imm_u_ex).0, rd.u() as u32)
-                    .is_err()
-                {
-                    return TickResult::Stop(ExitCode::BadMemoryAccess);
+                if storage.write_word(data_address.0, rd.u()).is_err() {
+                    return TickResult::BadAccess(data_address.0);
                 }
             }
 
@@ -411,6 +574,21 @@ pub fn tick(
                 }
             }
 
+            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BGE | Opcode::BLTU | Opcode::BGEU => {
+                let (rdi, rdu) = (Wrapping(rd.i()), Wrapping(rd.u()));
+                let taken = match op_code {
+                    Opcode::BEQ => rdi == rs1i,
+                    Opcode::BNE => rdi != rs1i,
+                    Opcode::BLT => rdi < rs1i,
+                    Opcode::BGE => rdi >= rs1i,
+                    Opcode::BLTU => rdu < rs1u,
+                    _ => rdu >= rs1u,
+                };
+                if taken {
+                    return jump(program_counter + imm_u_ex, false);
+                }
+            }
+
             Opcode::JMP => {
                 return jump(program_counter + address, false);
             }
@@ -463,6 +641,53 @@ pub fn tick(
                     return TickResult::Stop(ExitCode::InvalidOpcode);
                 }
             }
+
+            Opcode::CSRR | Opcode::CSRW | Opcode::CSRS | Opcode::CSRC => {
+                let old = match csrs.read(imm_u16, profile) {
+                    Some(value) => value,
+                    None => return TickResult::Stop(ExitCode::InvalidOpcode),
+                };
+                // setting or clearing the bits of ZERO only reads, even a read-only register
+                let new = match op_code {
+                    Opcode::CSRW => Some(rs1u.0),
+                    Opcode::CSRS | Opcode::CSRC if rs1id == 0 => None,
+                    Opcode::CSRS => Some(old | rs1u.0),
+                    Opcode::CSRC => Some(old & !rs1u.0),
+                    _ => None,
+                };
+                if let Some(new) = new {
+                    if csrs.write(imm_u16, new).is_err() {
+                        return TickResult::Stop(ExitCode::InvalidOpcode);
+                    }
+                }
+                write_u(registers, rdid, Wrapping(old));
+            }
+
+            Opcode::VEC => {
+                return vector_operation(
+                    registers,
+                    vectors,
+                    storage,
+                    instruction,
+                    rdid,
+                    rs1id,
+                    rs2id,
+                );
+            }
+
+            Opcode::BANK => {
+                return bank_operation(register_file, instruction, rdid, rs1id);
+            }
+
+            Opcode::IRET => match csrs.leave_interrupt() {
+                Some((return_address, banked)) => {
+                    if banked {
+                        register_file.swap();
+                    }
+                    return TickResult::Jump(return_address, false);
+                }
+                None => return TickResult::Stop(ExitCode::InvalidOpcode),
+            },
         }
     } else {
         return TickResult::Stop(ExitCode::InvalidOpcode);
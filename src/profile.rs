@@ -0,0 +1,497 @@
+use crate::{constants, AluFunct, Opcode, RegisterId, VecFunct, Word};
+use num::FromPrimitive;
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// A group of instructions which a [`IsaProfile`](struct.IsaProfile.html) can leave out, e.g. to model a
+/// smaller core. All other instructions are always available.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InstructionGroup {
+    /// `MUL` and `DIV` functions of `ALU`, `MULI` and `DIVI`.
+    MulDiv,
+    /// Loads and stores of bytes and halves: `LB`, `LH`, `SB` and `SH`, and the `BSWAP`, `EXTB`, `EXTH`,
+    /// `INSB` and `INSH` functions of `ALU`.
+    SubWord,
+    /// `ITOF`, `FTOI` and `FLOP`.
+    Float,
+    /// `SLL`, `SRL`, `SRA`, `ROL`, `ROR`, `FSL` and `FSR` functions of `ALU`, `SLLI`, `SRLI` and `SRAI`.
+    Shift,
+    /// `VEC` and the vector registers.
+    Vector,
+    /// `BANK` and the shadow register bank, which interrupt handlers run on.
+    Shadow,
+}
+
+impl InstructionGroup {
+    pub const ALL: [InstructionGroup; 6] = [
+        InstructionGroup::MulDiv,
+        InstructionGroup::SubWord,
+        InstructionGroup::Float,
+        InstructionGroup::Shift,
+        InstructionGroup::Vector,
+        InstructionGroup::Shadow,
+    ];
+
+    /// Returns the group of an instruction, or `None` if it is always available or not recognized.
+    pub fn of(instruction: Word) -> Option<InstructionGroup> {
+        let op_code = (instruction & constants::OPCODE_MASK) >> constants::OPCODE_OFFSET;
+        match Opcode::from_u32(op_code)? {
+            Opcode::MULI | Opcode::DIVI => Some(InstructionGroup::MulDiv),
+            Opcode::LB | Opcode::LH | Opcode::SB | Opcode::SH => Some(InstructionGroup::SubWord),
+            Opcode::ITOF | Opcode::FTOI | Opcode::FLOP => Some(InstructionGroup::Float),
+            Opcode::SLLI | Opcode::SRLI | Opcode::SRAI => Some(InstructionGroup::Shift),
+            Opcode::VEC => Some(InstructionGroup::Vector),
+            Opcode::BANK => Some(InstructionGroup::Shadow),
+            Opcode::ALU => {
+                let funct = (instruction & constants::FUNCT_MASK) >> constants::FUNCT_OFFSET;
+                match AluFunct::from_u32(funct)? {
+                    AluFunct::MUL | AluFunct::DIV => Some(InstructionGroup::MulDiv),
+                    AluFunct::SLL
+                    | AluFunct::SRL
+                    | AluFunct::SRA
+                    | AluFunct::ROL
+                    | AluFunct::ROR
+                    | AluFunct::FSL
+                    | AluFunct::FSR => Some(InstructionGroup::Shift),
+                    AluFunct::BSWAP
+                    | AluFunct::EXTB
+                    | AluFunct::EXTH
+                    | AluFunct::INSB
+                    | AluFunct::INSH => Some(InstructionGroup::SubWord),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InstructionGroup::MulDiv => "muldiv",
+            InstructionGroup::SubWord => "subword",
+            InstructionGroup::Float => "float",
+            InstructionGroup::Shift => "shift",
+            InstructionGroup::Vector => "vector",
+            InstructionGroup::Shadow => "shadow",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl std::fmt::Display for InstructionGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for InstructionGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InstructionGroup::ALL
+            .iter()
+            .copied()
+            .find(|group| group.name() == s)
+            .ok_or_else(|| format!("Unknown instruction group \"{}\"", s))
+    }
+}
+
+const STANDARD_REGISTERS: [(RegisterId, Cow<str>); constants::REGISTER_COUNT] = [
+    (RegisterId::ZERO, Cow::Borrowed("ZERO")),
+    (RegisterId::V0, Cow::Borrowed("V0")),
+    (RegisterId::V1, Cow::Borrowed("V1")),
+    (RegisterId::A0, Cow::Borrowed("A0")),
+    (RegisterId::A1, Cow::Borrowed("A1")),
+    (RegisterId::A2, Cow::Borrowed("A2")),
+    (RegisterId::A3, Cow::Borrowed("A3")),
+    (RegisterId::A4, Cow::Borrowed("A4")),
+    (RegisterId::T0, Cow::Borrowed("T0")),
+    (RegisterId::T1, Cow::Borrowed("T1")),
+    (RegisterId::T2, Cow::Borrowed("T2")),
+    (RegisterId::T3, Cow::Borrowed("T3")),
+    (RegisterId::T4, Cow::Borrowed("T4")),
+    (RegisterId::T5, Cow::Borrowed("T5")),
+    (RegisterId::T6, Cow::Borrowed("T6")),
+    (RegisterId::T7, Cow::Borrowed("T7")),
+    (RegisterId::T8, Cow::Borrowed("T8")),
+    (RegisterId::T9, Cow::Borrowed("T9")),
+    (RegisterId::S0, Cow::Borrowed("S0")),
+    (RegisterId::S1, Cow::Borrowed("S1")),
+    (RegisterId::S2, Cow::Borrowed("S2")),
+    (RegisterId::S3, Cow::Borrowed("S3")),
+    (RegisterId::S4, Cow::Borrowed("S4")),
+    (RegisterId::S5, Cow::Borrowed("S5")),
+    (RegisterId::S6, Cow::Borrowed("S6")),
+    (RegisterId::S7, Cow::Borrowed("S7")),
+    (RegisterId::S8, Cow::Borrowed("S8")),
+    (RegisterId::S9, Cow::Borrowed("S9")),
+    (RegisterId::SP, Cow::Borrowed("SP")),
+    (RegisterId::FP, Cow::Borrowed("FP")),
+    (RegisterId::RM, Cow::Borrowed("RM")),
+    (RegisterId::RA, Cow::Borrowed("RA")),
+];
+
+const SMALL_REGISTERS: [(RegisterId, Cow<str>); 16] = [
+    (RegisterId::ZERO, Cow::Borrowed("ZERO")),
+    (RegisterId::V0, Cow::Borrowed("V0")),
+    (RegisterId::A0, Cow::Borrowed("A0")),
+    (RegisterId::A1, Cow::Borrowed("A1")),
+    (RegisterId::A2, Cow::Borrowed("A2")),
+    (RegisterId::T0, Cow::Borrowed("T0")),
+    (RegisterId::T1, Cow::Borrowed("T1")),
+    (RegisterId::T2, Cow::Borrowed("T2")),
+    (RegisterId::T3, Cow::Borrowed("T3")),
+    (RegisterId::S0, Cow::Borrowed("S0")),
+    (RegisterId::S1, Cow::Borrowed("S1")),
+    (RegisterId::S2, Cow::Borrowed("S2")),
+    (RegisterId::SP, Cow::Borrowed("SP")),
+    (RegisterId::FP, Cow::Borrowed("FP")),
+    (RegisterId::RM, Cow::Borrowed("RM")),
+    (RegisterId::RA, Cow::Borrowed("RA")),
+];
+
+/// The registers a [`IsaProfile`](struct.IsaProfile.html) implements, and the names which the assembler and
+/// the disassembler use for them.
+///
+/// Registers keep their numbers in every table, so that a smaller table only leaves registers out and its
+/// programs run unchanged with all registers. Executing an instruction which names a register that is not in
+/// the table stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode).
+/// Registers which are not in the table stay zero. The processor writes `RM` and `RA` itself (`DIV`, `JL` and
+/// `JLR`), and the runners set up `SP`, so tables should keep those.
+///
+/// Besides the predefined tables, a table can be built at runtime, either from its entries or from a
+/// description like `ZERO/V0/A0/ACC=T0/SP/RA`, see [`from_str`](#method.from_str).
+///
+/// # Examples
+/// ```
+/// use vcpu::{RegisterId, RegisterTable};
+///
+/// let small = RegisterTable::SMALL;
+/// assert_eq!(small.count(), 16);
+/// assert_eq!(small.find("sp"), Some(RegisterId::SP));
+/// assert!(!small.contains(RegisterId::T4));
+///
+/// let renamed = RegisterTable::new(vec![(RegisterId::ZERO, "X0"), (RegisterId::V0, "X1")]).unwrap();
+/// assert_eq!(renamed.name(RegisterId::V0), Some("X1"));
+///
+/// let described: RegisterTable = "ZERO/X1=V0/sp".parse().unwrap();
+/// assert_eq!(described.name(RegisterId::V0), Some("X1"));
+/// assert_eq!(described.to_string(), "ZERO/X1=V0/SP");
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RegisterTable {
+    entries: Cow<'static, [(RegisterId, Cow<'static, str>)]>,
+    implemented: u32,
+}
+
+impl RegisterTable {
+    /// All 32 registers, named after [`RegisterId`](enum.RegisterId.html).
+    pub const STANDARD: RegisterTable = RegisterTable::from_static(&STANDARD_REGISTERS);
+
+    /// 16 registers, e.g. for teaching: `ZERO`, `V0`, `A0`-`A2`, `T0`-`T3`, `S0`-`S2`, `SP`, `FP`, `RM` and
+    /// `RA`.
+    pub const SMALL: RegisterTable = RegisterTable::from_static(&SMALL_REGISTERS);
+
+    /// Creates a table of the registers in `entries`, each with its name.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not contain `ZERO`, which unused fields of instructions refer to, if
+    /// a register or a name appears twice (names are compared ignoring case), or if a name is not an
+    /// identifier.
+    pub fn new<N: Into<Cow<'static, str>>>(
+        entries: impl IntoIterator<Item = (RegisterId, N)>,
+    ) -> Result<RegisterTable, String> {
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(id, name)| (id, name.into()))
+            .collect();
+        let implemented = entries
+            .iter()
+            .fold(0, |bits, (id, _)| bits | 1 << *id as u32);
+        let table = RegisterTable {
+            entries: Cow::Owned(entries),
+            implemented,
+        };
+        if !table.contains(RegisterId::ZERO) {
+            return Err("The register table does not contain ZERO".to_owned());
+        }
+        for (i, (id, name)) in table.entries.iter().enumerate() {
+            let mut chars = name.chars();
+            if !chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("Invalid register name \"{}\"", name));
+            }
+            if table.entries[..i]
+                .iter()
+                .any(|(other, other_name)| other == id || other_name.eq_ignore_ascii_case(name))
+            {
+                return Err(format!(
+                    "The register table contains {} or {:?} more than once",
+                    name, id
+                ));
+            }
+        }
+        Ok(table)
+    }
+
+    const fn from_static(entries: &'static [(RegisterId, Cow<'static, str>)]) -> RegisterTable {
+        let mut implemented = 0;
+        let mut i = 0;
+        while i < entries.len() {
+            implemented |= 1 << entries[i].0 as u32;
+            i += 1;
+        }
+        RegisterTable {
+            entries: Cow::Borrowed(entries),
+            implemented,
+        }
+    }
+
+    /// Number of registers in the table.
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The registers and their names, in the order they were given in.
+    pub fn entries(&self) -> &[(RegisterId, Cow<'static, str>)] {
+        &self.entries
+    }
+
+    pub fn contains(&self, id: RegisterId) -> bool {
+        self.implements(id as u32)
+    }
+
+    /// Whether the register with `number` is in the table.
+    pub fn implements(&self, number: u32) -> bool {
+        number < constants::REGISTER_COUNT as u32 && self.implemented & 1 << number != 0
+    }
+
+    /// Returns the name of register `id`, or `None` if it is not in the table.
+    pub fn name(&self, id: RegisterId) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(other, _)| *other == id)
+            .map(|(_, name)| name.as_ref())
+    }
+
+    /// Returns the register called `name`, ignoring case, or `None` if there is none.
+    pub fn find(&self, name: &str) -> Option<RegisterId> {
+        self.entries
+            .iter()
+            .find(|(_, other)| other.eq_ignore_ascii_case(name))
+            .map(|(id, _)| *id)
+    }
+
+    /// Returns whether all registers which `instruction` names are in the table. Fields which do not hold
+    /// registers, e.g. the immediate value of an I-format instruction, are not checked.
+    pub fn covers(&self, instruction: Word) -> bool {
+        if self.implemented == u32::MAX {
+            return true;
+        }
+        let field = |mask: u32, offset: u32| (instruction & mask) >> offset;
+        let rd = field(constants::RD_MASK, constants::RD_OFFSET);
+        let rs1 = field(constants::RS1_MASK, constants::RS1_OFFSET);
+        let rs2 = field(constants::RS2_MASK, constants::RS2_OFFSET);
+        let funct = field(constants::FUNCT_MASK, constants::FUNCT_OFFSET);
+        let opcode = Opcode::from_u32(field(constants::OPCODE_MASK, constants::OPCODE_OFFSET));
+        let (fields, count) = match opcode {
+            None | Some(Opcode::JMP) | Some(Opcode::JL) => ([0; 3], 0),
+            Some(Opcode::ALU) | Some(Opcode::FLOP) => ([rd, rs1, rs2], 3),
+            Some(Opcode::VEC) => match VecFunct::from_u32(funct) {
+                Some(VecFunct::VSPLAT)
+                | Some(VecFunct::VINS)
+                | Some(VecFunct::VLD)
+                | Some(VecFunct::VST) => ([rs1, 0, 0], 1),
+                Some(VecFunct::VEXT) => ([rd, 0, 0], 1),
+                _ => ([0; 3], 0),
+            },
+            Some(_) => ([rd, rs1, 0], 2),
+        };
+        fields[..count]
+            .iter()
+            .all(|number| self.implements(*number))
+    }
+}
+
+impl Default for RegisterTable {
+    fn default() -> RegisterTable {
+        RegisterTable::STANDARD
+    }
+}
+
+/// Formats the table as its description, e.g. `ZERO/V0/ACC=T0`, see [`from_str`](#method.from_str).
+impl std::fmt::Display for RegisterTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (id, name)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            if RegisterTable::STANDARD.name(*id) == Some(name.as_ref()) {
+                write!(f, "{}", name)?;
+            } else {
+                write!(f, "{}={}", name, id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a description of a table, the entries separated by `/`. An entry is either the name of a register,
+/// e.g. `SP`, or a new name and the register it stands for, e.g. `ACC=T0` or `ACC=8`. Names of registers are
+/// those of [`RegisterTable::STANDARD`](#associatedconstant.STANDARD) and are not case-sensitive.
+impl FromStr for RegisterTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let register = |name: &str| {
+            RegisterTable::STANDARD
+                .find(name)
+                .or_else(|| name.parse().ok().and_then(RegisterId::from_u32))
+                .ok_or_else(|| format!("Unknown register \"{}\"", name))
+        };
+        let entries = s
+            .split('/')
+            .map(str::trim)
+            .map(|entry| match entry.split_once('=') {
+                Some((name, id)) => Ok((register(id.trim())?, name.trim().to_owned())),
+                None => {
+                    let id = register(entry)?;
+                    Ok((id, id.to_string()))
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        RegisterTable::new(entries)
+    }
+}
+
+/// The instruction groups and the registers a processor implements. Executing an instruction of a disabled
+/// group stops the processor with [`ExitCode::InvalidOpcode`](enum.ExitCode.html#variant.InvalidOpcode), as
+/// if the instruction did not exist, and so does one which names a register that is not in the
+/// [`RegisterTable`](struct.RegisterTable.html).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IsaProfile {
+    disabled: u8,
+    registers: RegisterTable,
+}
+
+impl IsaProfile {
+    /// The whole instruction set with all registers.
+    pub const fn full() -> IsaProfile {
+        IsaProfile {
+            disabled: 0,
+            registers: RegisterTable::STANDARD,
+        }
+    }
+
+    /// Only the instructions which are not in any group.
+    pub fn base() -> IsaProfile {
+        InstructionGroup::ALL
+            .iter()
+            .fold(IsaProfile::full(), |profile, group| profile.without(*group))
+    }
+
+    pub fn with(self, group: InstructionGroup) -> IsaProfile {
+        IsaProfile {
+            disabled: self.disabled & !group.bit(),
+            ..self
+        }
+    }
+
+    pub fn without(self, group: InstructionGroup) -> IsaProfile {
+        IsaProfile {
+            disabled: self.disabled | group.bit(),
+            ..self
+        }
+    }
+
+    pub fn with_registers(self, registers: RegisterTable) -> IsaProfile {
+        IsaProfile { registers, ..self }
+    }
+
+    pub fn registers(&self) -> &RegisterTable {
+        &self.registers
+    }
+
+    pub fn enables(&self, group: InstructionGroup) -> bool {
+        self.disabled & group.bit() == 0
+    }
+
+    /// Returns whether an instruction may be executed. Instructions which are not recognized are allowed, so
+    /// that they fail the same way with every profile.
+    pub fn allows(&self, instruction: Word) -> bool {
+        let enabled = match InstructionGroup::of(instruction) {
+            Some(group) => self.enables(group),
+            None => true,
+        };
+        enabled && self.registers.covers(instruction)
+    }
+}
+
+impl Default for IsaProfile {
+    fn default() -> IsaProfile {
+        IsaProfile::full()
+    }
+}
+
+/// Formats the profile as the groups which are disabled, e.g. `-muldiv,-float`, followed by the registers
+/// unless all are implemented, e.g. `-float,regs16` or `regs:ZERO/V0/SP/RA`, or `full`.
+impl std::fmt::Display for IsaProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut items: Vec<String> = InstructionGroup::ALL
+            .iter()
+            .filter(|group| !self.enables(**group))
+            .map(|group| format!("-{}", group))
+            .collect();
+        if self.registers == RegisterTable::SMALL {
+            items.push("regs16".to_owned());
+        } else if self.registers != RegisterTable::STANDARD {
+            items.push(format!("regs:{}", self.registers));
+        }
+        if items.is_empty() {
+            f.write_str("full")
+        } else {
+            f.write_str(&items.join(","))
+        }
+    }
+}
+
+/// Parses a comma separated list, which may start with `full` or `base` and continues with groups to enable
+/// (`+muldiv`) or disable (`-float`), e.g. `base,+shift`. Without `base` the list starts from the full
+/// instruction set. `regs16` selects [`RegisterTable::SMALL`](struct.RegisterTable.html#associatedconstant.SMALL)
+/// and `regs32` all registers, e.g. `base,regs16`, and `regs:` followed by the description of a
+/// [`RegisterTable`](struct.RegisterTable.html#method.from_str) any other table, e.g. `regs:ZERO/V0/ACC=T0/SP/RA`.
+impl FromStr for IsaProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = IsaProfile::full();
+        for (i, item) in s.split(',').map(str::trim).enumerate() {
+            profile = match item {
+                "full" if i == 0 => IsaProfile::full(),
+                "base" if i == 0 => IsaProfile::base(),
+                "regs16" => profile.with_registers(RegisterTable::SMALL),
+                "regs32" => profile.with_registers(RegisterTable::STANDARD),
+                _ if item.starts_with("regs:") => {
+                    profile.with_registers(item["regs:".len()..].parse()?)
+                }
+                _ => match (item.strip_prefix('+'), item.strip_prefix('-')) {
+                    (Some(group), _) => profile.with(group.parse()?),
+                    (_, Some(group)) => profile.without(group.parse()?),
+                    _ => {
+                        return Err(format!(
+                            "Invalid instruction set \"{}\", expected e.g. base,+muldiv or -float",
+                            s
+                        ))
+                    }
+                },
+            };
+        }
+        Ok(profile)
+    }
+}
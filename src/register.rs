@@ -1,3 +1,8 @@
+use crate::constants::{REGISTER_COUNT, VECTOR_LANES};
+
+/// The lanes of a vector register, see [`VecFunct`](enum.VecFunct.html).
+pub type Vector = [u32; VECTOR_LANES];
+
 #[derive(Clone, Copy)]
 pub union Register {
     i: i32,
@@ -70,3 +75,47 @@ impl std::fmt::Debug for Register {
             .finish()
     }
 }
+
+/// The registers of a processor, in two banks of [`REGISTER_COUNT`](constant.REGISTER_COUNT.html) registers.
+/// Instructions use the active bank, the other one is the shadow bank, see
+/// [`BankFunct`](enum.BankFunct.html). Bank 0 is active after a reset, and entering an interrupt handler swaps
+/// the banks until the handler returns, see [`ControlRegisters`](struct.ControlRegisters.html).
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct RegisterFile {
+    banks: [[Register; REGISTER_COUNT]; 2],
+    active: usize,
+}
+
+impl RegisterFile {
+    pub fn new() -> RegisterFile {
+        Default::default()
+    }
+
+    /// The registers of the active bank.
+    pub fn active(&self) -> &[Register; REGISTER_COUNT] {
+        &self.banks[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut [Register; REGISTER_COUNT] {
+        &mut self.banks[self.active]
+    }
+
+    /// The registers of the shadow bank.
+    pub fn shadow(&self) -> &[Register; REGISTER_COUNT] {
+        &self.banks[1 - self.active]
+    }
+
+    pub fn shadow_mut(&mut self) -> &mut [Register; REGISTER_COUNT] {
+        &mut self.banks[1 - self.active]
+    }
+
+    /// The number of the active bank, 0 or 1.
+    pub fn active_bank(&self) -> usize {
+        self.active
+    }
+
+    /// Makes the shadow bank the active one.
+    pub fn swap(&mut self) {
+        self.active = 1 - self.active;
+    }
+}
@@ -1,4 +1,6 @@
 use super::*;
+use std::cell::Cell;
+use std::time::Duration;
 
 macro_rules! instr {
     (a $opcode:ident $rd:ident $rs1:ident $rs2:ident) => {
@@ -150,4 +152,367 @@ fn truncation_in_store_instruction() {
     assert_eq!(0xFF, storage[0]);
 }
 
+#[test]
+fn start_at_program_counter() {
+    let instructions = instructions_from_words(&instructions![
+        (i LI T0 ZERO 1),
+        (i LI T1 ZERO 2),
+        (i HALT ZERO ZERO 0)
+    ]);
+
+    let mut processor = Processor::default();
+    processor.set_program_counter(constants::WORD_BYTES);
+    let mut memory = empty_storage!();
+    assert_eq!(processor.run(&instructions, &mut memory), ExitCode::Halted);
+
+    assert_eq!(0, processor.register(RegisterId::T0).i());
+    assert_eq!(2, processor.register(RegisterId::T1).i());
+}
+
+#[test]
+fn program_counter_at_end_of_address_space() {
+    let instructions = instructions_from_words(&instructions![(i HALT ZERO ZERO 0)]);
+
+    let mut processor = Processor::default();
+    processor.set_program_counter(u32::MAX - 1);
+    let mut memory = empty_storage!();
+    let result = processor.run_result(&instructions, &mut memory);
+    assert_eq!(result.exit_code, ExitCode::BadProgramCounter);
+    assert_eq!(result.program_counter, u32::MAX - 1);
+}
+
+#[test]
+fn reset_vector() {
+    let mut processor = Processor::default();
+    assert_eq!(processor.reset_vector(), 0);
+    processor.set_reset_vector(0x100);
+    assert_eq!(processor.program_counter(), 0);
+
+    processor.register_mut(RegisterId::T0).set_i(1);
+    processor.reset();
+    assert_eq!(processor.program_counter(), 0x100);
+    assert_eq!(processor.register(RegisterId::T0).i(), 0);
+    assert_eq!(processor.reset_vector(), 0x100);
+}
+
+#[test]
+fn fetch_from_storage() {
+    let program = instructions![
+        (i LI T0 ZERO 5),
+        (j JL 8),
+        (i HALT ZERO ZERO 0),
+        (i ADDI T0 T0 1),
+        (i JR ZERO RA 0)
+    ];
+    let mut memory = vec![0u8; 64];
+    memory[16..36].copy_from_slice(&instructions_from_words(&program));
+
+    let mut processor = Processor::default();
+    processor.set_reset_vector(16);
+    processor.reset();
+    while processor.tick_from_storage(&mut memory).is_none() {}
+    assert_eq!(processor.state(), Some(ExitCode::Halted));
+    assert_eq!(processor.register(RegisterId::T0).i(), 6);
+    assert_eq!(processor.register(RegisterId::RA).u(), 24);
+    assert_eq!(processor.program_counter(), 24);
+
+    // the program counter does not wrap around at the end of the storage
+    let mut processor = Processor::default();
+    processor.set_program_counter(56);
+    memory[56..64].copy_from_slice(&instructions_from_words(&instructions![
+        (i LI T0 ZERO 1),
+        (i LI T1 ZERO 2)
+    ]));
+    assert_eq!(processor.tick_from_storage(&mut memory), None);
+    assert_eq!(processor.program_counter(), 60);
+    assert_eq!(processor.tick_from_storage(&mut memory), None);
+    assert_eq!(
+        processor.tick_from_storage(&mut memory),
+        Some(ExitCode::BadProgramCounter)
+    );
+    assert_eq!(processor.program_counter(), 64);
+
+    let jump = instructions_from_words(&instructions![(j JMP 64)]);
+    memory[..4].copy_from_slice(&jump);
+    let mut processor = Processor::default();
+    let result = processor.run_result(&memory.clone(), &mut memory);
+    assert_eq!(result.exit_code, ExitCode::BadJump);
+    processor.reset();
+    assert_eq!(
+        processor.tick_from_storage(&mut memory),
+        Some(ExitCode::BadJump)
+    );
+    assert_eq!(
+        processor.fault(),
+        Some(Fault {
+            instruction: Some(instr_j!(JMP, 64)),
+            address: Some(64),
+        })
+    );
+}
+
+#[test]
+fn run_result() {
+    let instructions = instructions_from_words(&instructions![
+        (i LI T0 ZERO 6),
+        (i SW T0 T0 -2),
+        (i SW T0 T0 4),
+        (i HALT ZERO ZERO 0)
+    ]);
+
+    let mut processor = Processor::default();
+    let mut memory = [0u8; 8];
+    let result = processor.run_result(&instructions, &mut memory);
+    assert_eq!(
+        result,
+        RunResult {
+            exit_code: ExitCode::BadMemoryAccess,
+            program_counter: 2 * constants::WORD_BYTES,
+            executed: 3,
+            fault: Some(Fault {
+                instruction: Some(instr_i!(SW, T0, T0, 4)),
+                address: Some(10),
+            }),
+        }
+    );
+    assert_eq!(
+        result.to_string(),
+        "bad memory access at 0x00000008 (instruction 0x39080004), address 0x0000000A after 3 instructions"
+    );
+    // a stopped processor executes nothing
+    assert_eq!(processor.run_result(&instructions, &mut memory).executed, 0);
+
+    processor.reset();
+    assert_eq!(processor.fault(), None);
+    processor.set_program_counter(3 * constants::WORD_BYTES);
+    let result = processor.run_result(&instructions, &mut memory);
+    assert_eq!(result.fault, None);
+    assert_eq!(result.into_result(), Ok(result));
+
+    let jump = instructions_from_words(&instructions![(j JMP 6)]);
+    processor.reset();
+    let result = processor.run_result(&jump, &mut memory);
+    assert_eq!(result.exit_code, ExitCode::BadAlignment);
+    assert_eq!(result.fault.unwrap().address, Some(6));
+    assert_eq!(ExitCode::BadJump.into_result(), Err(ExitCode::BadJump));
+    assert!(!ExitCode::Terminated.is_error());
+}
+
+struct HourlyClock(Cell<Duration>);
+
+impl ClockSource for HourlyClock {
+    fn now(&self) -> Duration {
+        let now = self.0.get() + Duration::from_secs(3600);
+        self.0.set(now);
+        now
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+#[test]
+fn run_options() {
+    let instructions = instructions_from_words(&instructions![
+        (i ADDI T0 T0 1),
+        (i SLTI T1 T0 10),
+        (i BNZ ZERO T1 jmp_addr_i16(-2)),
+        (i HALT ZERO ZERO 0)
+    ]);
+
+    let mut processor = Processor::default();
+    let mut memory = empty_storage!();
+    let options = RunOptions {
+        max_instructions: Some(4),
+        ..RunOptions::default()
+    };
+    assert_eq!(
+        processor.run_with(&instructions, &mut memory, &options),
+        Err(BudgetExceeded {
+            budget: Budget::Instructions,
+            program_counter: constants::WORD_BYTES,
+            executed: 4,
+        })
+    );
+    assert_eq!(processor.cycles(), 4);
+    assert!(!processor.is_stopped());
+
+    // the cycles count the instructions of the earlier run as well
+    let options = RunOptions {
+        max_cycles: Some(10),
+        ..RunOptions::default()
+    };
+    let exceeded = processor
+        .run_with(&instructions, &mut memory, &options)
+        .unwrap_err();
+    assert_eq!(exceeded.budget, Budget::Cycles);
+    assert_eq!(exceeded.executed, 6);
+
+    let options = RunOptions {
+        timeout: Some(Duration::from_secs(0)),
+        ..RunOptions::default()
+    };
+    let exceeded = processor
+        .run_with(&instructions, &mut memory, &options)
+        .unwrap_err();
+    assert_eq!(exceeded.budget, Budget::Timeout);
+    assert_eq!(exceeded.executed, 0);
+
+    // an hour passes every time the clock is read
+    let clock = HourlyClock(Cell::new(Duration::from_secs(0)));
+    let options = RunOptions {
+        timeout: Some(Duration::from_secs(3600)),
+        ..RunOptions::default()
+    };
+    let exceeded = processor
+        .run_with_clock(&instructions, &mut memory, &options, &clock)
+        .unwrap_err();
+    assert_eq!(exceeded.budget, Budget::Timeout);
+    assert_eq!(clock.0.get(), Duration::from_secs(2 * 3600));
+
+    let result = processor
+        .run_with(&instructions, &mut memory, &RunOptions::default())
+        .unwrap();
+    assert_eq!(result.exit_code, ExitCode::Halted);
+    assert_eq!(processor.cycles(), 10 * 3 + 1);
+    assert_eq!(processor.register(RegisterId::T0).i(), 10);
+
+    processor.reset();
+    assert_eq!(processor.cycles(), 0);
+}
+
+#[test]
+fn isa_profile() {
+    let instructions = instructions_from_words(&instructions![
+        (i LI T0 ZERO 6),
+        (i LI T1 ZERO 7),
+        (a MUL T2 T0 T1),
+        (i HALT ZERO ZERO 0)
+    ]);
+
+    let profile: IsaProfile = "-muldiv,-float".parse().unwrap();
+    let mut processor = Processor::with_profile(profile.clone());
+    let mut memory = empty_storage!();
+    assert_eq!(
+        processor.run(&instructions, &mut memory),
+        ExitCode::InvalidOpcode
+    );
+    assert_eq!(processor.program_counter(), 2 * constants::WORD_BYTES);
+    assert_eq!(processor.register(RegisterId::T2).i(), 0);
+
+    processor.reset();
+    assert_eq!(processor.profile(), &profile);
+    processor.set_profile(profile.clone().with(InstructionGroup::MulDiv));
+    assert_eq!(processor.run(&instructions, &mut memory), ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::T2).i(), 42);
+
+    assert!(IsaProfile::full().allows(instr_i!(LB, T0, T1, 0)));
+    assert!(!IsaProfile::base().allows(instr_i!(LB, T0, T1, 0)));
+    assert!(!IsaProfile::base().allows(instr_alu!(SRA, T0, T1, T2)));
+    assert!(!IsaProfile::base().allows(instr_index!(EXTB, T0, T1, ZERO, 0)));
+    assert!(IsaProfile::base().allows(instr_alu!(ADD, T0, T1, T2)));
+    assert!(IsaProfile::base().allows(0xFFFF_FFFF));
+    assert_eq!(
+        "base,+shift".parse(),
+        Ok(IsaProfile::base().with(InstructionGroup::Shift))
+    );
+    assert_eq!(profile.to_string(), "-muldiv,-float");
+    assert_eq!(IsaProfile::full().to_string(), "full");
+    assert!("-simd".parse::<IsaProfile>().is_err());
+    assert!(!"-vector"
+        .parse::<IsaProfile>()
+        .unwrap()
+        .enables(InstructionGroup::Vector));
+    assert!("muldiv".parse::<IsaProfile>().is_err());
+    assert!("-float,base".parse::<IsaProfile>().is_err());
+}
+
+#[test]
+fn register_table() {
+    let instructions = instructions_from_words(&instructions![
+        (i LI T0 ZERO 6),
+        (i LI T4 ZERO 7),
+        (i HALT ZERO ZERO 0)
+    ]);
+
+    let profile: IsaProfile = "regs16".parse().unwrap();
+    assert_eq!(profile.registers(), &RegisterTable::SMALL);
+    assert_eq!(profile.to_string(), "regs16");
+    assert_eq!(
+        "-float,regs16,regs32".parse(),
+        Ok(IsaProfile::full().without(InstructionGroup::Float))
+    );
+
+    let mut processor = Processor::with_profile(profile);
+    let mut memory = empty_storage!();
+    assert_eq!(
+        processor.run(&instructions, &mut memory),
+        ExitCode::InvalidOpcode
+    );
+    assert_eq!(processor.program_counter(), constants::WORD_BYTES);
+    assert_eq!(processor.register(RegisterId::T0).i(), 6);
+
+    let small = RegisterTable::SMALL;
+    assert!(small.covers(instr_alu!(ADD, T0, T1, SP)));
+    assert!(!small.covers(instr_alu!(ADD, T0, T1, T4)));
+    assert!(!small.covers(instr_i!(ADDI, S5, T1, 0)));
+    // the immediate overlaps the field of rs2, but does not name a register
+    assert!(small.covers(instr_i!(ADDI, T0, T1, -1)));
+    assert!(small.covers(instr_j!(JMP, -4)));
+    let vins = |rs1| make_vec_instruction(VecFunct::VINS, 0, register_index(rs1) as u32, 3);
+    assert!(small.covers(vins(RegisterId::T0)));
+    assert!(!small.covers(vins(RegisterId::T4)));
+    assert!(RegisterTable::STANDARD.covers(instr_alu!(ADD, T9, S9, RM)));
+
+    assert_eq!(
+        RegisterTable::STANDARD.entries().len(),
+        constants::REGISTER_COUNT
+    );
+    for (i, (id, name)) in RegisterTable::STANDARD.entries().iter().enumerate() {
+        assert_eq!(*id as usize, i);
+        assert_eq!(id.to_string(), *name);
+    }
+    assert!(RegisterTable::new([(RegisterId::V0, "V0")]).is_err());
+    assert!(RegisterTable::new([(RegisterId::ZERO, "ZERO"), (RegisterId::V0, "zero")]).is_err());
+    assert!(RegisterTable::new([(RegisterId::ZERO, "ZERO"), (RegisterId::ZERO, "R0")]).is_err());
+    assert!(RegisterTable::new([(RegisterId::ZERO, "0")]).is_err());
+}
+
+#[test]
+fn register_table_description() {
+    let built = RegisterTable::new(vec![
+        (RegisterId::ZERO, "ZERO".to_owned()),
+        (RegisterId::T0, "ACC".to_owned()),
+        (RegisterId::SP, "SP".to_owned()),
+    ])
+    .unwrap();
+    let described: RegisterTable = "zero/ACC=t0/SP".parse().unwrap();
+    assert_eq!(described, built);
+    assert_eq!(described.to_string(), "ZERO/ACC=T0/SP");
+    assert_eq!("ZERO/ACC=8/SP".parse(), Ok(built));
+    assert_eq!(
+        "ZERO/V0".parse::<RegisterTable>().unwrap().to_string(),
+        "ZERO/V0"
+    );
+    assert!("ZERO/ACC=T10".parse::<RegisterTable>().is_err());
+    assert!("ZERO/ACC=32".parse::<RegisterTable>().is_err());
+    assert!("V0/SP".parse::<RegisterTable>().is_err());
+    assert!("ZERO/1X=V0".parse::<RegisterTable>().is_err());
+
+    let profile: IsaProfile = "-float,regs:ZERO/ACC=T0/SP/RA".parse().unwrap();
+    assert_eq!(profile.registers().find("acc"), Some(RegisterId::T0));
+    assert!(!profile.registers().contains(RegisterId::V0));
+    assert_eq!(profile.to_string(), "-float,regs:ZERO/ACC=T0/SP/RA");
+    assert_eq!(profile.to_string().parse(), Ok(profile));
+    assert_eq!(
+        "regs:ZERO/V0/A0/A1/A2/T0/T1/T2/T3/S0/S1/S2/SP/FP/RM/RA"
+            .parse::<IsaProfile>()
+            .unwrap()
+            .to_string(),
+        "regs16"
+    );
+    assert!("regs:".parse::<IsaProfile>().is_err());
+}
+
 mod instructions;
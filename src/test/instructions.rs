@@ -184,20 +184,40 @@ fn zero_register_read_only() {
 
 mod add;
 mod addi;
+mod addo;
 mod and;
 mod andi;
+mod bank;
+mod beq;
 mod bez;
+mod bge;
+mod bgeu;
+mod blt;
+mod bltu;
+mod bne;
 mod bnz;
+mod bswap;
 mod copy;
+mod csrc;
+mod csrr;
+mod csrs;
+mod csrw;
 mod div;
 mod divi;
+mod extb;
+mod exth;
 mod fadd;
 mod fdiv;
 mod flip;
 mod fmul;
+mod fsl;
+mod fsr;
 mod fsub;
 mod ftoi;
+mod insb;
+mod insh;
 mod invalid;
+mod iret;
 mod itof;
 mod jl;
 mod jlr;
@@ -212,6 +232,8 @@ mod mul;
 mod muli;
 mod or;
 mod ori;
+mod rol;
+mod ror;
 mod sb;
 mod seq;
 mod seqi;
@@ -243,6 +265,8 @@ mod srl;
 mod srli;
 mod sub;
 mod subi;
+mod subo;
 mod sw;
+mod vec;
 mod xor;
 mod xori;
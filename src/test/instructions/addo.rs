@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn positive() {
+    instruction_runs! {
+        instr_alu!(ADDO, T0, T1, T2),
+        [T1 = 5678, T2 = 1234] => [T0 = 6912]
+    };
+}
+
+#[test]
+fn negative() {
+    instruction_runs! {
+        instr_alu!(ADDO, T0, T1, T2),
+        [T1 = -5678, T2 = 1234] => [T0 = -4444]
+    };
+}
+
+#[test]
+fn unsigned_wrap_around() {
+    instruction_runs! {
+        instr_alu!(ADDO, T0, T1, T2),
+        [T1 = 0xFFFF_FFFFu32, T2 = 1234] => [T0 = 1233]
+    };
+}
+
+#[test]
+fn overflow() {
+    instruction_exits! {
+        instr_alu!(ADDO, T0, T1, T2),
+        [T0 = 7, T1 = 0x7FFF_FFFF, T2 = 1] => [],
+        Overflow
+    };
+}
+
+#[test]
+fn negative_overflow() {
+    instruction_exits! {
+        instr_alu!(ADDO, T0, T1, T2),
+        [T1 = i32::MIN, T2 = -1] => [],
+        Overflow
+    };
+}
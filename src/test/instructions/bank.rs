@@ -0,0 +1,106 @@
+use super::*;
+
+/// Runs `words` on a processor prepared by `setup` and returns it.
+fn run(words: &[Word], setup: impl FnOnce(&mut Processor)) -> Processor {
+    let instructions = super::instructions_from_words(words);
+    let mut processor = Processor::default();
+    setup(&mut processor);
+    processor.run(&instructions, &mut empty_storage!());
+    processor
+}
+
+fn shadow(processor: &Processor, id: RegisterId) -> u32 {
+    processor.register_file().shadow()[register_index(id)].u()
+}
+
+#[test]
+fn swaps_banks() {
+    let processor = run(
+        &[
+            instr_bank!(SWAPB),
+            instr_i!(LI, T0, ZERO, 7),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ],
+        |processor| processor.register_mut(RegisterId::T0).set_u(5),
+    );
+    assert_eq!(processor.state(), Some(ExitCode::Halted));
+    assert_eq!(processor.register_file().active_bank(), 1);
+    assert_eq!(processor.register(RegisterId::T0).u(), 7);
+    assert_eq!(shadow(&processor, RegisterId::T0), 5);
+
+    let processor = run(
+        &[
+            instr_bank!(SWAPB),
+            instr_bank!(SWAPB),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ],
+        |processor| processor.register_mut(RegisterId::T0).set_u(5),
+    );
+    assert_eq!(processor.register_file().active_bank(), 0);
+    assert_eq!(processor.register(RegisterId::T0).u(), 5);
+}
+
+#[test]
+fn reads_shadow() {
+    let processor = run(
+        &[instr_bank!(RDSH, T0, T1), instr_i!(HALT, ZERO, ZERO, 0)],
+        |processor| {
+            processor.register_file_mut().shadow_mut()[register_index(RegisterId::T1)].set_u(9);
+            processor.register_mut(RegisterId::T1).set_u(3);
+        },
+    );
+    assert_eq!(processor.register(RegisterId::T0).u(), 9);
+    assert_eq!(processor.register(RegisterId::T1).u(), 3);
+}
+
+#[test]
+fn writes_shadow() {
+    let processor = run(
+        &[instr_bank!(WRSH, T0, T1), instr_i!(HALT, ZERO, ZERO, 0)],
+        |processor| processor.register_mut(RegisterId::T1).set_u(9),
+    );
+    assert_eq!(shadow(&processor, RegisterId::T0), 9);
+    assert_eq!(processor.register(RegisterId::T0).u(), 0);
+}
+
+#[test]
+fn zero_stays_zero() {
+    let processor = run(
+        &[
+            instr_bank!(WRSH, ZERO, T1),
+            instr_bank!(SWAPB),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ],
+        |processor| processor.register_mut(RegisterId::T1).set_u(9),
+    );
+    assert_eq!(processor.register(RegisterId::ZERO).u(), 0);
+}
+
+#[test]
+fn invalid() {
+    instruction_exits! {
+        make_r_instruction(Opcode::BANK, RegisterId::T0, RegisterId::T1, RegisterId::ZERO, 3),
+        [] => [],
+        InvalidOpcode
+    };
+
+    let instructions = super::instructions_from_words(&[instr_bank!(SWAPB)]);
+    let mut processor =
+        Processor::with_profile(IsaProfile::full().without(InstructionGroup::Shadow));
+    assert_eq!(
+        processor.run(&instructions, &mut empty_storage!()),
+        ExitCode::InvalidOpcode
+    );
+    assert_eq!(processor.register_file().active_bank(), 0);
+}
+
+#[test]
+fn reset() {
+    let mut processor = run(
+        &[instr_bank!(SWAPB), instr_i!(HALT, ZERO, ZERO, 0)],
+        |processor| processor.register_mut(RegisterId::T0).set_u(5),
+    );
+    processor.reset();
+    assert_eq!(processor.register_file().active_bank(), 0);
+    assert_eq!(shadow(&processor, RegisterId::T0), 0);
+}
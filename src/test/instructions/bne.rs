@@ -0,0 +1,55 @@
+use super::*;
+
+#[test]
+fn not_equal() {
+    instructions_execute! {
+        [
+            nop!(),
+            instr_i!(BNE, T0, T1, 12),
+            nop!(),
+            nop!(),
+            nop!(),
+        ],
+        [T0 = 5, T1 = 6] => [],
+        empty_storage!() => empty_storage!(),
+        2,
+        None,
+        16
+    }
+}
+
+#[test]
+fn equal() {
+    instructions_execute! {
+        [
+            nop!(),
+            instr_i!(BNE, T0, T1, 12),
+            nop!(),
+            nop!(),
+            nop!(),
+        ],
+        [T0 = -5, T1 = -5] => [],
+        empty_storage!() => empty_storage!(),
+        2,
+        None,
+        8
+    }
+}
+
+#[test]
+fn branch_negative() {
+    instructions_execute! {
+        [
+            nop!(),
+            nop!(),
+            nop!(),
+            instr_i!(BNE, T0, T1, -8),
+            nop!(),
+        ],
+        [T0 = 5, T1 = 6] => [],
+        empty_storage!() => empty_storage!(),
+        4,
+        None,
+        4
+    }
+}
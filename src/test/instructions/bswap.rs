@@ -0,0 +1,17 @@
+use super::*;
+
+#[test]
+fn success() {
+    instruction_runs! {
+        instr_alu!(BSWAP, T0, T1, ZERO),
+        [T1 = 0x1234_5678u32] => [T0 = 0x7856_3412u32]
+    };
+}
+
+#[test]
+fn same_register() {
+    instruction_runs! {
+        instr_alu!(BSWAP, T0, T0, ZERO),
+        [T0 = 0xDEAD_BEEFu32] => [T0 = 0xEFBE_ADDEu32]
+    };
+}
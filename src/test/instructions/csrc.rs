@@ -0,0 +1,30 @@
+use super::*;
+
+#[test]
+fn clears_bits() {
+    instructions_execute! {
+        [instr_i!(CSRC, T0, T1, CSR_INSTRET as i16), instr_i!(CSRR, T2, ZERO, CSR_INSTRET as i16)],
+        [T1 = 1] => [T0 = 1, T2 = 1],
+        empty_storage!() => empty_storage!(),
+        2,
+        None,
+        0
+    };
+}
+
+#[test]
+fn read_only_register() {
+    instruction_exits! {
+        instr_i!(CSRC, T0, T1, CSR_ISA as i16),
+        [T1 = 1] => [],
+        InvalidOpcode
+    };
+}
+
+#[test]
+fn zero_mask_only_reads() {
+    instruction_runs! {
+        instr_i!(CSRC, T0, ZERO, CSR_CYCLE as i16),
+        [] => [T0 = 1]
+    };
+}
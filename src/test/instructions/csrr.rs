@@ -0,0 +1,38 @@
+use super::*;
+
+#[test]
+fn machine_id() {
+    instruction_runs! {
+        instr_i!(CSRR, T0, ZERO, CSR_MACHINE_ID as i16),
+        [] => [T0 = MACHINE_ID]
+    };
+}
+
+#[test]
+fn isa() {
+    instruction_runs! {
+        instr_i!(CSRR, T0, ZERO, CSR_ISA as i16),
+        [] => [T0 = 0b11_1111u32]
+    };
+}
+
+#[test]
+fn counts_itself() {
+    instructions_execute! {
+        [nop!(), nop!(), instr_i!(CSRR, T0, ZERO, CSR_CYCLE as i16), instr_i!(CSRR, T1, ZERO, CSR_CYCLEH as i16)],
+        [T1 = 5] => [T0 = 3, T1 = 0],
+        empty_storage!() => empty_storage!(),
+        4,
+        None,
+        0
+    };
+}
+
+#[test]
+fn unknown_register() {
+    instruction_exits! {
+        instr_i!(CSRR, T0, ZERO, 0x7FF),
+        [] => [],
+        InvalidOpcode
+    };
+}
@@ -0,0 +1,55 @@
+use super::*;
+
+#[test]
+fn returns_old_value() {
+    instructions_execute! {
+        [instr_i!(CSRW, T0, T1, CSR_INSTRET as i16), instr_i!(CSRR, T2, ZERO, CSR_INSTRET as i16)],
+        [T0 = 7, T1 = 100] => [T0 = 1, T2 = 101],
+        empty_storage!() => empty_storage!(),
+        2,
+        None,
+        0
+    };
+}
+
+#[test]
+fn high_bits() {
+    instructions_execute! {
+        [instr_i!(CSRW, ZERO, T1, CSR_INSTRETH as i16), instr_i!(CSRR, T2, ZERO, CSR_INSTRETH as i16)],
+        [T1 = 3] => [T2 = 3],
+        empty_storage!() => empty_storage!(),
+        2,
+        None,
+        0
+    };
+}
+
+#[test]
+fn interrupt_enable() {
+    instructions_execute! {
+        [instr_i!(CSRW, ZERO, T1, CSR_IE as i16), instr_i!(CSRR, T2, ZERO, CSR_IE as i16), instr_i!(CSRR, T3, ZERO, CSR_IP as i16)],
+        [T1 = 5, T3 = 1] => [T2 = 5, T3 = 0],
+        empty_storage!() => empty_storage!(),
+        3,
+        None,
+        0
+    };
+}
+
+#[test]
+fn read_only_register() {
+    instruction_exits! {
+        instr_i!(CSRW, T0, T1, CSR_CYCLE as i16),
+        [T1 = 100] => [],
+        InvalidOpcode
+    };
+}
+
+#[test]
+fn interrupt_status_is_read_only() {
+    instruction_exits! {
+        instr_i!(CSRW, T0, T1, CSR_ISTATUS as i16),
+        [T1 = 1] => [],
+        InvalidOpcode
+    };
+}
@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn lowest_byte() {
+    instruction_runs! {
+        instr_index!(EXTB, T0, T1, ZERO, 0),
+        [T0 = 7, T1 = 0x1234_5678u32] => [T0 = 0x78]
+    };
+}
+
+#[test]
+fn highest_byte() {
+    instruction_runs! {
+        instr_index!(EXTB, T0, T1, ZERO, 3),
+        [T1 = 0xF234_5678u32] => [T0 = 0xF2]
+    };
+}
+
+#[test]
+fn bad_index() {
+    instruction_exits! {
+        instr_index!(EXTB, T0, T1, ZERO, 4),
+        [T1 = 0x1234_5678u32] => [],
+        InvalidOpcode
+    };
+}
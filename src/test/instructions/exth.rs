@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn lower_half() {
+    instruction_runs! {
+        instr_index!(EXTH, T0, T1, ZERO, 0),
+        [T0 = 7, T1 = 0x1234_F678u32] => [T0 = 0xF678]
+    };
+}
+
+#[test]
+fn upper_half() {
+    instruction_runs! {
+        instr_index!(EXTH, T0, T1, ZERO, 1),
+        [T1 = 0xF234_5678u32] => [T0 = 0xF234]
+    };
+}
+
+#[test]
+fn bad_index() {
+    instruction_exits! {
+        instr_index!(EXTH, T0, T1, ZERO, 2),
+        [T1 = 0x1234_5678u32] => [],
+        InvalidOpcode
+    };
+}
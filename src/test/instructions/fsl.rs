@@ -0,0 +1,33 @@
+use super::*;
+
+#[test]
+fn shifts_in_low_word() {
+    instruction_runs! {
+        instr_funnel!(FSL, T0, T1, T2, 8),
+        [T1 = 0x1234_5678u32, T2 = 0x9ABC_DEF0u32] => [T0 = 0x3456_789Au32]
+    };
+}
+
+#[test]
+fn zero_amount() {
+    instruction_runs! {
+        instr_funnel!(FSL, T0, T1, T2, 0),
+        [T1 = 0x1234_5678u32, T2 = 0x9ABC_DEF0u32] => [T0 = 0x1234_5678u32]
+    };
+}
+
+#[test]
+fn maximum_amount() {
+    instruction_runs! {
+        instr_funnel!(FSL, T0, T1, T2, 31),
+        [T1 = 1, T2 = 0x8000_0000u32] => [T0 = 0xC000_0000u32]
+    };
+}
+
+#[test]
+fn rotate() {
+    instruction_runs! {
+        instr_funnel!(FSL, T0, T1, T1, 4),
+        [T1 = 0x1234_5678u32] => [T0 = 0x2345_6781u32]
+    };
+}
@@ -0,0 +1,33 @@
+use super::*;
+
+#[test]
+fn shifts_in_high_word() {
+    instruction_runs! {
+        instr_funnel!(FSR, T0, T1, T2, 8),
+        [T1 = 0x1234_5678u32, T2 = 0x9ABC_DEF0u32] => [T0 = 0x789A_BCDEu32]
+    };
+}
+
+#[test]
+fn zero_amount() {
+    instruction_runs! {
+        instr_funnel!(FSR, T0, T1, T2, 0),
+        [T1 = 0x1234_5678u32, T2 = 0x9ABC_DEF0u32] => [T0 = 0x9ABC_DEF0u32]
+    };
+}
+
+#[test]
+fn maximum_amount() {
+    instruction_runs! {
+        instr_funnel!(FSR, T0, T1, T2, 31),
+        [T1 = 3, T2 = 0x8000_0000u32] => [T0 = 7]
+    };
+}
+
+#[test]
+fn rotate() {
+    instruction_runs! {
+        instr_funnel!(FSR, T0, T1, T1, 4),
+        [T1 = 0x1234_5678u32] => [T0 = 0x8123_4567u32]
+    };
+}
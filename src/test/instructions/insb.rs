@@ -0,0 +1,34 @@
+use super::*;
+
+#[test]
+fn lowest_byte() {
+    instruction_runs! {
+        instr_index!(INSB, T0, T1, T2, 0),
+        [T1 = 0x1234_5678u32, T2 = 0xABCDu32] => [T0 = 0x1234_56CDu32]
+    };
+}
+
+#[test]
+fn highest_byte() {
+    instruction_runs! {
+        instr_index!(INSB, T0, T1, T2, 3),
+        [T1 = 0x1234_5678u32, T2 = 0xABCDu32] => [T0 = 0xCD34_5678u32]
+    };
+}
+
+#[test]
+fn in_place() {
+    instruction_runs! {
+        instr_index!(INSB, T0, T0, T1, 1),
+        [T0 = 0x1234_5678u32, T1 = 0xFFu32] => [T0 = 0x1234_FF78u32]
+    };
+}
+
+#[test]
+fn bad_index() {
+    instruction_exits! {
+        instr_index!(INSB, T0, T1, T2, 4),
+        [T1 = 0x1234_5678u32] => [],
+        InvalidOpcode
+    };
+}
@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn lower_half() {
+    instruction_runs! {
+        instr_index!(INSH, T0, T1, T2, 0),
+        [T1 = 0x1234_5678u32, T2 = 0x89AB_CDEFu32] => [T0 = 0x1234_CDEFu32]
+    };
+}
+
+#[test]
+fn upper_half() {
+    instruction_runs! {
+        instr_index!(INSH, T0, T1, T2, 1),
+        [T1 = 0x1234_5678u32, T2 = 0x89AB_CDEFu32] => [T0 = 0xCDEF_5678u32]
+    };
+}
+
+#[test]
+fn bad_index() {
+    instruction_exits! {
+        instr_index!(INSH, T0, T1, T2, 2),
+        [T1 = 0x1234_5678u32] => [],
+        InvalidOpcode
+    };
+}
@@ -0,0 +1,96 @@
+use super::*;
+
+/// A program at 0 with an interrupt handler at 8, which sets `T0` of the bank it runs on to 40.
+fn program() -> [Word; 6] {
+    [
+        instr_i!(ADDI, T0, T0, 1),
+        instr_i!(HALT, ZERO, ZERO, 0),
+        instr_i!(LI, T1, ZERO, 1),
+        instr_i!(CSRC, ZERO, T1, CSR_IP as i16),
+        instr_i!(LI, T0, ZERO, 40),
+        instr_i!(IRET, ZERO, ZERO, 0),
+    ]
+}
+
+fn run(mut processor: Processor, ie: u32) -> Processor {
+    let instructions = super::instructions_from_words(&program());
+    processor.register_mut(RegisterId::T0).set_u(5);
+    processor.control_registers_mut().set_ivec(8);
+    processor.control_registers_mut().set_ie(ie);
+    processor.raise_interrupt(0);
+    assert_eq!(
+        processor.run(&instructions, &mut empty_storage!()),
+        ExitCode::Halted
+    );
+    processor
+}
+
+#[test]
+fn swaps_in_shadow_bank() {
+    let processor = run(Processor::default(), 1);
+    assert_eq!(processor.register(RegisterId::T0).u(), 6);
+    assert_eq!(processor.register(RegisterId::T1).u(), 0);
+    assert_eq!(processor.register_file().active_bank(), 0);
+    assert_eq!(
+        processor.register_file().shadow()[register_index(RegisterId::T0)].u(),
+        40
+    );
+    assert_eq!(processor.csr(CSR_IP), Some(0));
+    assert_eq!(processor.csr(CSR_IEPC), Some(0));
+    assert_eq!(processor.csr(CSR_ISTATUS), Some(0));
+    assert_eq!(processor.cycles(), 6);
+}
+
+#[test]
+fn without_shadow_bank() {
+    let profile = IsaProfile::full().without(InstructionGroup::Shadow);
+    let processor = run(Processor::with_profile(profile), 1);
+    assert_eq!(processor.register(RegisterId::T0).u(), 41);
+    assert_eq!(processor.register(RegisterId::T1).u(), 1);
+}
+
+#[test]
+fn disabled_line() {
+    let processor = run(Processor::default(), 2);
+    assert_eq!(processor.register(RegisterId::T0).u(), 6);
+    assert_eq!(processor.csr(CSR_IP), Some(1));
+    assert_eq!(processor.cycles(), 2);
+}
+
+#[test]
+fn status_while_handling() {
+    let instructions = super::instructions_from_words(&[
+        instr_i!(HALT, ZERO, ZERO, 0),
+        instr_i!(CSRR, T0, ZERO, CSR_ISTATUS as i16),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+    let mut processor = Processor::default();
+    processor.control_registers_mut().set_ivec(4);
+    processor.control_registers_mut().set_ie(1 << 31);
+    processor.raise_interrupt(31);
+    processor.run(&instructions, &mut empty_storage!());
+    assert_eq!(
+        processor.register(RegisterId::T0).u(),
+        ISTATUS_HANDLING | ISTATUS_BANKED
+    );
+    // the line is still pending, but the handler is not entered again
+    assert_eq!(processor.program_counter(), 8);
+
+    processor.lower_interrupt(31);
+    assert_eq!(processor.csr(CSR_IP), Some(0));
+}
+
+#[test]
+fn not_handling() {
+    instruction_exits! {
+        instr_i!(IRET, ZERO, ZERO, 0),
+        [] => [],
+        InvalidOpcode
+    };
+}
+
+#[test]
+#[should_panic]
+fn invalid_line() {
+    Processor::default().raise_interrupt(INTERRUPT_LINES);
+}
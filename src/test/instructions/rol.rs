@@ -0,0 +1,35 @@
+use super::*;
+
+#[test]
+fn positive_amount() {
+    instruction_runs! {
+        instr_alu!(ROL, T0, T1, T2),
+        [
+            T1 = 0b0101_0011_0010_0011_1111_0100_0110_1011_u32,
+            T2 = 13
+        ] => [
+            T0 = 0b0111_1110_1000_1101_0110_1010_0110_0100_u32
+        ]
+    };
+}
+
+#[test]
+fn negative_amount() {
+    instruction_runs! {
+        instr_alu!(ROL, T0, T1, T2),
+        [
+            T1 = 0b0101_0011_0010_0011_1111_0100_0110_1011_u32,
+            T2 = -6
+        ] => [
+            T0 = 0b1010_1101_0100_1100_1000_1111_1101_0001_u32
+        ]
+    };
+}
+
+#[test]
+fn full_rotation() {
+    instruction_runs! {
+        instr_alu!(ROL, T0, T1, T2),
+        [T1 = 0x1234_5678u32, T2 = 32] => [T0 = 0x1234_5678u32]
+    };
+}
@@ -0,0 +1,35 @@
+use super::*;
+
+#[test]
+fn positive() {
+    instruction_runs! {
+        instr_alu!(SUBO, T0, T1, T2),
+        [T1 = 5678, T2 = 1234] => [T0 = 4444]
+    };
+}
+
+#[test]
+fn negative() {
+    instruction_runs! {
+        instr_alu!(SUBO, T0, T1, T2),
+        [T1 = 1234, T2 = 5678] => [T0 = -4444]
+    };
+}
+
+#[test]
+fn overflow() {
+    instruction_exits! {
+        instr_alu!(SUBO, T0, T1, T2),
+        [T0 = 7, T1 = i32::MIN, T2 = 1] => [],
+        Overflow
+    };
+}
+
+#[test]
+fn negative_overflow() {
+    instruction_exits! {
+        instr_alu!(SUBO, T0, T1, T2),
+        [T1 = 0, T2 = i32::MIN] => [],
+        Overflow
+    };
+}
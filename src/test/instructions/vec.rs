@@ -0,0 +1,246 @@
+use super::*;
+
+/// Executes `instruction` on a processor prepared by `setup` and returns it.
+fn execute(
+    instruction: Word,
+    storage: &mut dyn StorageMut,
+    setup: impl FnOnce(&mut Processor),
+) -> Processor {
+    let instructions = super::instructions_from_words(&[instruction, nop!()]);
+    let mut processor = Processor::default();
+    setup(&mut processor);
+    processor.tick(&instructions, storage);
+    processor
+}
+
+#[test]
+fn arithmetic() {
+    let add = make_vec_instruction(VecFunct::VADD, 0, 1, 2);
+    let sub = make_vec_instruction(VecFunct::VSUB, 0, 1, 2);
+    let mul = make_vec_instruction(VecFunct::VMUL, 0, 1, 2);
+    let setup = |processor: &mut Processor| {
+        *processor.vector_mut(VectorRegisterId::Q1) = [1, 2, 3, 0xFFFF_FFFF];
+        *processor.vector_mut(VectorRegisterId::Q2) = [10, 20, 30, 2];
+    };
+
+    let processor = execute(add, &mut empty_storage!(), setup);
+    assert_eq!(processor.state(), None);
+    assert_eq!(processor.program_counter(), 4);
+    assert_eq!(processor.vector(VectorRegisterId::Q0), &[11, 22, 33, 1]);
+    let processor = execute(sub, &mut empty_storage!(), setup);
+    assert_eq!(
+        processor.vector(VectorRegisterId::Q0),
+        &[-9i32 as u32, -18i32 as u32, -27i32 as u32, 0xFFFF_FFFD]
+    );
+    let processor = execute(mul, &mut empty_storage!(), setup);
+    assert_eq!(
+        processor.vector(VectorRegisterId::Q0),
+        &[10, 40, 90, 0xFFFF_FFFE]
+    );
+    assert_eq!(
+        processor.vector(VectorRegisterId::Q1),
+        &[1, 2, 3, 0xFFFF_FFFF]
+    );
+}
+
+#[test]
+fn lanes() {
+    let splat = make_vec_instruction(
+        VecFunct::VSPLAT,
+        3,
+        register_index(RegisterId::T0) as u32,
+        0,
+    );
+    let processor = execute(splat, &mut empty_storage!(), |processor| {
+        processor.register_mut(RegisterId::T0).set_u(7);
+    });
+    assert_eq!(processor.vector(VectorRegisterId::Q3), &[7; VECTOR_LANES]);
+
+    let insert = make_vec_instruction(VecFunct::VINS, 3, register_index(RegisterId::T0) as u32, 2);
+    let processor = execute(insert, &mut empty_storage!(), |processor| {
+        processor.register_mut(RegisterId::T0).set_u(7);
+        *processor.vector_mut(VectorRegisterId::Q3) = [1, 2, 3, 4];
+    });
+    assert_eq!(processor.vector(VectorRegisterId::Q3), &[1, 2, 7, 4]);
+
+    let extract = make_vec_instruction(VecFunct::VEXT, register_index(RegisterId::T0) as u32, 3, 1);
+    let processor = execute(extract, &mut empty_storage!(), |processor| {
+        *processor.vector_mut(VectorRegisterId::Q3) = [1, 2, 3, 4];
+    });
+    assert_eq!(processor.register(RegisterId::T0).u(), 2);
+
+    // the zero register stays zero
+    let extract = make_vec_instruction(VecFunct::VEXT, 0, 3, 1);
+    let processor = execute(extract, &mut empty_storage!(), |processor| {
+        *processor.vector_mut(VectorRegisterId::Q3) = [1, 2, 3, 4];
+    });
+    assert_eq!(processor.register(RegisterId::ZERO).u(), 0);
+}
+
+#[test]
+fn load_store() {
+    let t0 = register_index(RegisterId::T0) as u32;
+    let mut memory = vec![0u8; 20];
+    memory[4..20].copy_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0x78, 0x56, 0x34, 0x12]);
+    let load = make_vec_instruction(VecFunct::VLD, 5, t0, 0);
+    let processor = execute(load, &mut memory, |processor| {
+        processor.register_mut(RegisterId::T0).set_u(4);
+    });
+    assert_eq!(
+        processor.vector(VectorRegisterId::Q5),
+        &[1, 2, 3, 0x1234_5678]
+    );
+
+    let mut memory = vec![0u8; 17];
+    let store = make_vec_instruction(VecFunct::VST, 5, t0, 0);
+    let processor = execute(store, &mut memory, |processor| {
+        processor.register_mut(RegisterId::T0).set_u(1);
+        *processor.vector_mut(VectorRegisterId::Q5) = [1, 2, 3, 0x1234_5678];
+    });
+    assert_eq!(processor.state(), None);
+    assert_eq!(
+        &memory[1..],
+        &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0x78, 0x56, 0x34, 0x12]
+    );
+}
+
+#[test]
+fn bad_access() {
+    let t0 = register_index(RegisterId::T0) as u32;
+    // the last lane does not fit, so nothing is stored
+    let mut memory = vec![0u8; 16];
+    let store = make_vec_instruction(VecFunct::VST, 5, t0, 0);
+    let processor = execute(store, &mut memory, |processor| {
+        processor.register_mut(RegisterId::T0).set_u(4);
+        *processor.vector_mut(VectorRegisterId::Q5) = [1, 2, 3, 4];
+    });
+    assert_eq!(processor.state(), Some(ExitCode::BadMemoryAccess));
+    assert_eq!(processor.fault().unwrap().address, Some(4));
+    assert_eq!(memory, vec![0u8; 16]);
+
+    let load = make_vec_instruction(VecFunct::VLD, 5, t0, 0);
+    let processor = execute(load, &mut memory, |processor| {
+        processor.register_mut(RegisterId::T0).set_u(0xFFFF_FFFC);
+    });
+    assert_eq!(processor.state(), Some(ExitCode::BadMemoryAccess));
+}
+
+/// The last 16 bytes of the address space.
+struct TopOfMemory([u8; 16]);
+
+impl TopOfMemory {
+    const BASE: u32 = 0xFFFF_FFF0;
+}
+
+impl Storage for TopOfMemory {
+    fn length(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        address >= Self::BASE && self.0.check_range(address - Self::BASE, length)
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        if address < Self::BASE {
+            return Err(());
+        }
+        self.0.read(address - Self::BASE, size)
+    }
+}
+
+impl StorageMut for TopOfMemory {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        if address < Self::BASE {
+            return Err(());
+        }
+        self.0.write(address - Self::BASE, size, value)
+    }
+}
+
+#[test]
+fn end_of_address_space() {
+    let t0 = register_index(RegisterId::T0) as u32;
+    // the last slot ends exactly at the end of the address space
+    let mut memory = TopOfMemory([0u8; 16]);
+    let store = make_vec_instruction(VecFunct::VST, 5, t0, 0);
+    let processor = execute(store, &mut memory, |processor| {
+        processor
+            .register_mut(RegisterId::T0)
+            .set_u(TopOfMemory::BASE);
+        *processor.vector_mut(VectorRegisterId::Q5) = [1, 2, 3, 4];
+    });
+    assert_eq!(processor.state(), None);
+    assert_eq!(memory.0, [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+
+    let load = make_vec_instruction(VecFunct::VLD, 6, t0, 0);
+    let processor = execute(load, &mut memory, |processor| {
+        processor
+            .register_mut(RegisterId::T0)
+            .set_u(TopOfMemory::BASE);
+    });
+    assert_eq!(processor.state(), None);
+    assert_eq!(processor.vector(VectorRegisterId::Q6), &[1, 2, 3, 4]);
+
+    // one byte further would wrap around
+    let processor = execute(load, &mut memory, |processor| {
+        processor
+            .register_mut(RegisterId::T0)
+            .set_u(TopOfMemory::BASE + 1);
+    });
+    assert_eq!(processor.state(), Some(ExitCode::BadMemoryAccess));
+}
+
+#[test]
+fn invalid() {
+    let t0 = register_index(RegisterId::T0) as u32;
+    for instruction in [
+        // there are only 8 vector registers and 4 lanes
+        make_vec_instruction(VecFunct::VADD, 8, 1, 2),
+        make_vec_instruction(VecFunct::VSUB, 0, 1, 31),
+        make_vec_instruction(VecFunct::VINS, 0, t0, 4),
+        make_vec_instruction(VecFunct::VEXT, t0, 0, 4),
+        make_vec_instruction(VecFunct::VLD, 8, t0, 0),
+        make_r_instruction(
+            Opcode::VEC,
+            RegisterId::ZERO,
+            RegisterId::ZERO,
+            RegisterId::ZERO,
+            63,
+        ),
+    ]
+    .iter()
+    {
+        let processor = execute(*instruction, &mut vec![0u8; 16], |_| {});
+        assert_eq!(processor.state(), Some(ExitCode::InvalidOpcode));
+    }
+
+    let disabled = execute(
+        make_vec_instruction(VecFunct::VADD, 0, 1, 2),
+        &mut empty_storage!(),
+        |processor| {
+            processor.set_profile(IsaProfile::full().without(InstructionGroup::Vector));
+        },
+    );
+    assert_eq!(disabled.state(), Some(ExitCode::InvalidOpcode));
+}
+
+#[test]
+fn reset() {
+    let mut processor = execute(
+        make_vec_instruction(
+            VecFunct::VSPLAT,
+            0,
+            register_index(RegisterId::T0) as u32,
+            0,
+        ),
+        &mut empty_storage!(),
+        |processor| processor.register_mut(RegisterId::T0).set_u(1),
+    );
+    assert_eq!(processor.vectors()[0], [1; VECTOR_LANES]);
+    processor.reset();
+    assert_eq!(
+        processor.vectors(),
+        &[[0; VECTOR_LANES]; VECTOR_REGISTER_COUNT]
+    );
+}
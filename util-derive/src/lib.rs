@@ -26,6 +26,8 @@ pub fn interop_get_name_derive(input: TokenStream) -> TokenStream {
     let name = &syn_item.ident;
     let variants = get_enum_variants(&syn_item, true);
 
+    // match instead of indexing, since the discriminants need not start at zero
+    let variant_idents = variants.iter().map(|v| &v.ident);
     let variant_names = variants.iter().map(|v| {
         let mut name = v.ident.to_string().into_bytes();
         name.push(0);
@@ -35,8 +37,9 @@ pub fn interop_get_name_derive(input: TokenStream) -> TokenStream {
     let expanded = quote! {
         impl InteropGetName for #name {
             fn interop_name(&self) -> &'static [u8] {
-                const NAMES: &[&[u8]] = &[#(#variant_names),*];
-                &NAMES[*self as usize]
+                match self {
+                    #(#name::#variant_idents => #variant_names),*
+                }
             }
         }
     };
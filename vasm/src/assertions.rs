@@ -0,0 +1,152 @@
+use crate::expressions::*;
+use crate::labels::*;
+use crate::*;
+use matches::debug_assert_matches;
+use pest::iterators::Pair;
+use vcpu::WORD_BYTES;
+
+/// An `.assert` directive, which is checked once the addresses of all labels are known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion<'i> {
+    /// Section the assertion appears in. Local and numeric labels are resolved in this section.
+    pub section: Section,
+    /// Index of the source file the assertion appears in.
+    pub file: u32,
+    pub condition: Expression<'i>,
+    pub message: String,
+    pub span: Span<'i>,
+}
+
+/// Returns the contents of a `string` pair with all escape sequences replaced.
+pub fn process_string(pair: Pair<Rule>) -> Result<String> {
+    debug_assert_matches!(pair.as_rule(), Rule::string);
+    let inner = pair.into_inner().next().unwrap();
+
+    let mut result = String::new();
+    let mut chars = inner.as_str().chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        result.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            _ => {
+                return Err(new_parser_error(
+                    inner.as_span(),
+                    "Unknown escape sequence".to_owned(),
+                ))
+            }
+        });
+    }
+    Ok(result)
+}
+
+pub fn process_assertion<'i>(
+    pair: Pair<'i, Rule>,
+    section: Section,
+    file: u32,
+    scope: &LabelScope<'i>,
+    constants: &Constants<'i>,
+) -> Result<Assertion<'i>> {
+    debug_assert_matches!(pair.as_rule(), Rule::assert);
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+    let condition =
+        process_expression(pairs.next().unwrap(), scope)?.substitute(&mut |label| match label {
+            LabelRef::Global(span) => constants.get(span.as_str()).cloned(),
+            LabelRef::Scoped(..) => None,
+        });
+
+    Ok(Assertion {
+        section,
+        file,
+        condition,
+        message: process_string(pairs.next().unwrap())?,
+        span,
+    })
+}
+
+impl<'i> Assertion<'i> {
+    /// Evaluates the condition and fails with the message of the assertion if it is zero.
+    pub fn check<F>(&self, resolve: &mut F) -> Result<()>
+    where
+        F: FnMut(&LabelRef<'i>) -> Result<i64>,
+    {
+        if self.condition.evaluate(resolve)? == 0 {
+            Err(new_parser_error(
+                self.span,
+                format!("Assertion failed: {}", self.message),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks all `assertions` against the final label addresses.
+///
+/// Scoped labels are looked up in the section of the assertion. Global labels are looked up in the
+/// section of the assertion first and in the other section if there is no such label.
+/// Data labels evaluate to their address in main memory, instruction labels to their address in instruction memory.
+pub fn check_assertions<'i>(
+    assertions: &[Assertion<'i>],
+    data_labels: &LabelMap,
+    data_offset: u32,
+    instr_labels: &LabelMap,
+    paths: &[Option<&str>],
+    linter: &mut Linter,
+) -> Result<()> {
+    for assertion in assertions {
+        let mut resolve = |label: &LabelRef<'i>| {
+            let mut sections = vec![assertion.section];
+            if let LabelRef::Global(_) = label {
+                sections.push(match assertion.section {
+                    Section::Data => Section::Instructions,
+                    Section::Instructions => Section::Data,
+                });
+            }
+            for section in sections {
+                let address = match section {
+                    Section::Data => data_labels
+                        .get(label.key())
+                        .map(|offset| data_offset.wrapping_add(*offset)),
+                    Section::Instructions => instr_labels
+                        .get(label.key())
+                        .map(|index| index * WORD_BYTES),
+                };
+                if let Some(address) = address {
+                    linter.use_label(section, label.key());
+                    return Ok(Into::<i64>::into(address));
+                }
+            }
+            Err(new_parser_error(
+                label.span(),
+                "Label was not found".to_owned(),
+            ))
+        };
+        assertion
+            .check(&mut resolve)
+            .map_err(|err| with_path(err, paths[assertion.file as usize]))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::parse_rule;
+    use crate::Rule;
+
+    #[test]
+    fn process_string() {
+        let pair = parse_rule(Rule::string, r#""a \"table\"\n\\""#).unwrap();
+        assert_eq!(super::process_string(pair).unwrap(), "a \"table\"\n\\");
+
+        let pair = parse_rule(Rule::string, r#""\q""#).unwrap();
+        assert!(super::process_string(pair).is_err());
+    }
+}
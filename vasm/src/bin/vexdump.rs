@@ -0,0 +1,215 @@
+//! Prints the contents of a vexfile (or an ELF file converted from one) in human readable form.
+
+#[macro_use]
+extern crate clap;
+
+use byteorder::ByteOrder;
+use clap::{App, Arg};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use util::Endian;
+use vcpu::{RegisterTable, WORD_BYTES};
+use vex::debug::{DebugInfo, SymbolKind};
+use vex::Executable;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+
+fn main() {
+    let matches = App::new("vexdump")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Prints the header, sections, symbols and disassembly of a vexfile.")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the vexfile or ELF file to print")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("debug_info")
+                .short("g")
+                .long("debug_info")
+                .takes_value(true)
+                .value_name("DEBUG_INFO")
+                .help("Reads symbols from this file instead of the debug information embedded in the input"),
+        )
+        .arg(
+            Arg::with_name("isa")
+                .long("isa")
+                .takes_value(true)
+                .value_name("PROFILE")
+                .validator(|value| value.parse::<vcpu::IsaProfile>().map(|_| ()))
+                .help("Disassembles with the registers of this instruction set profile, e.g. regs16"),
+        )
+        .get_matches();
+
+    let input = matches.value_of("INPUT").unwrap();
+    let bytes = std::fs::read(input).unwrap_or_else(|err| {
+        eprintln!("Reading input file \"{}\" failed: {}", input, err);
+        std::process::exit(1);
+    });
+    let (format, executable) = if bytes.starts_with(ELF_MAGIC) {
+        ("ELF".to_owned(), vex::elf::read(&mut &bytes[..]))
+    } else {
+        let version = bytes.get(4..8).map_or(0, Endian::read_u32);
+        (
+            format!("vex version {}", version),
+            Executable::from_bytes(&bytes),
+        )
+    };
+    let executable = executable.unwrap_or_else(|err| {
+        eprintln!("Reading input file \"{}\" failed: {}", input, err);
+        std::process::exit(1);
+    });
+
+    let debug_info = match matches.value_of("debug_info") {
+        Some(path) => Some(vex::debug::read_file(path).unwrap_or_else(|err| {
+            eprintln!("Reading debug information \"{}\" failed: {}", path, err);
+            std::process::exit(1);
+        })),
+        None => executable.debug_info().cloned(),
+    };
+    let registers = matches
+        .value_of("isa")
+        .map_or(RegisterTable::STANDARD, |value| {
+            value
+                .parse::<vcpu::IsaProfile>()
+                .unwrap()
+                .registers()
+                .clone()
+        });
+
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if let Err(err) = dump(
+        &mut writer,
+        &format,
+        &executable,
+        debug_info.as_ref(),
+        &registers,
+    )
+    .and_then(|_| writer.flush())
+    {
+        eprintln!("Writing output failed: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn dump<W: Write>(
+    writer: &mut W,
+    format: &str,
+    executable: &Executable,
+    debug_info: Option<&DebugInfo>,
+    registers: &RegisterTable,
+) -> std::io::Result<()> {
+    let mut labels: HashMap<u32, &str> = HashMap::new();
+    for symbol in debug_info.iter().flat_map(|info| info.symbols.iter()) {
+        if symbol.kind == SymbolKind::Instruction {
+            labels.entry(symbol.address).or_insert(&symbol.name);
+        }
+    }
+    let label = |address: u32| match labels.get(&address) {
+        Some(name) => format!(" <{}>", name),
+        None => String::new(),
+    };
+
+    writeln!(writer, "Header:")?;
+    writeln!(writer, "  Format:       {}", format)?;
+    writeln!(
+        writer,
+        "  Compressed:   {}",
+        if executable.is_compressed() {
+            "yes"
+        } else {
+            "no"
+        }
+    )?;
+    writeln!(
+        writer,
+        "  Entry point:  0x{:08X}{}",
+        executable.entry_point(),
+        label(executable.entry_point())
+    )?;
+    writeln!(writer, "  Memory size:  0x{:08X}", executable.memory_size())?;
+    writeln!(
+        writer,
+        "  Instructions: {} bytes",
+        executable.instructions().len()
+    )?;
+
+    if !executable.metadata().is_empty() {
+        writeln!(writer)?;
+        writeln!(writer, "Metadata:")?;
+        for (key, value) in executable.metadata() {
+            writeln!(writer, "  {} = {}", key, value)?;
+        }
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "Sections:")?;
+    writeln!(writer, "  Address   Size      Flags")?;
+    for section in executable.sections() {
+        let flags = section.flags();
+        let mut names = Vec::new();
+        if flags.read_only {
+            names.push("read-only");
+        }
+        if flags.zero_fill {
+            names.push("zero-fill");
+        }
+        writeln!(
+            writer,
+            "  {:08X}  {:08X}  {}",
+            section.address(),
+            section.size(),
+            names.join(", ")
+        )?;
+    }
+
+    if let Some(debug_info) = debug_info {
+        writeln!(writer)?;
+        writeln!(writer, "Symbols:")?;
+        let mut symbols: Vec<_> = debug_info.symbols.iter().collect();
+        symbols.sort_by_key(|symbol| (symbol.kind == SymbolKind::Data, symbol.address));
+        for symbol in symbols {
+            let kind = match symbol.kind {
+                SymbolKind::Instruction => 'I',
+                SymbolKind::Data => 'D',
+            };
+            writeln!(
+                writer,
+                "  {:08X}  {}  {}",
+                symbol.address, kind, symbol.name
+            )?;
+        }
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "Disassembly:")?;
+    for (i, chunk) in executable
+        .instructions()
+        .chunks_exact(WORD_BYTES as usize)
+        .enumerate()
+    {
+        let address = i as u32 * WORD_BYTES;
+        let word = Endian::read_u32(chunk);
+        if let Some(name) = labels.get(&address) {
+            writeln!(writer, "{}:", name)?;
+        }
+        let text = vasm::disassemble_with(word, registers).unwrap_or_else(|| "???".to_owned());
+        match vasm::jump_target(word, address) {
+            Some(target) => writeln!(
+                writer,
+                "  {:08X}  {:08X}  {:<24}# {:08X}{}",
+                address,
+                word,
+                text,
+                target,
+                label(target)
+            )?,
+            None => writeln!(writer, "  {:08X}  {:08X}  {}", address, word, text)?,
+        }
+    }
+    Ok(())
+}
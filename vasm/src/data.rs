@@ -1,13 +1,16 @@
+use crate::assertions::{process_assertion, Assertion};
+use crate::expressions::{define_constant, Constants};
 use crate::int_util::*;
 use crate::labels::*;
+use crate::source_map::LineCounter;
 use crate::*;
 use byteorder::ByteOrder;
 use matches::debug_assert_matches;
 use num::{Num, ToPrimitive};
 use pest::iterators::Pair;
-use std::collections::HashMap;
 use std::num::ParseIntError;
 use util::Endian;
+use vcpu::{HALF_BYTES, WORD_BYTES};
 
 fn process_int_list<T>(pair: Pair<Rule>, data: &mut Vec<u8>) -> Result<()>
 where
@@ -68,23 +71,107 @@ fn process_data_element(pair: Pair<Rule>, data: &mut Vec<u8>) -> Result<()> {
     }
 }
 
-pub fn process_data(pair: Pair<Rule>) -> Result<(Vec<u8>, LabelMap)> {
+fn check_alignment(pair: &Pair<Rule>, address: u32, linter: &mut Linter) -> Result<()> {
+    let inner = pair.clone().into_inner().next().unwrap();
+    let alignment = match inner.as_rule() {
+        Rule::data_short => HALF_BYTES,
+        Rule::data_word => WORD_BYTES,
+        _ => return Ok(()),
+    };
+    if !address.is_multiple_of(alignment) {
+        let message = format!(
+            "Data element at address 0x{:08X} is not aligned to {} bytes",
+            address, alignment
+        );
+        linter.report(WarningKind::MisalignedData, inner.as_span(), &message)?;
+    }
+    Ok(())
+}
+
+/// The combined data sections of all source files.
+#[derive(Default)]
+pub struct DataOutput<'i> {
+    pub data: Vec<u8>,
+    pub labels: LabelMap,
+    pub data_map: DataMap,
+    pub assertions: Vec<Assertion<'i>>,
+    /// Constants defined with `.equ`, which stay visible in the instruction sections.
+    pub constants: Constants<'i>,
+}
+
+/// Appends the data section of source file number `file` to `output`.
+pub fn process_data<'i>(
+    pair: Pair<'i, Rule>,
+    file: u32,
+    output: &mut DataOutput<'i>,
+    data_offset: u32,
+    linter: &mut Linter<'i, '_>,
+) -> Result<()> {
     debug_assert_matches!(pair.as_rule(), Rule::data);
 
-    let mut data = Vec::new();
-    let mut labels = HashMap::new();
+    let DataOutput {
+        data,
+        labels,
+        data_map,
+        assertions,
+        constants,
+    } = output;
+    let mut scope = LabelScope::for_file(file);
+    let mut line_counter = LineCounter::new(pair.as_span());
 
     for labeled_data_element in pair.into_inner() {
+        match labeled_data_element.as_rule() {
+            Rule::assert => {
+                assertions.push(process_assertion(
+                    labeled_data_element,
+                    Section::Data,
+                    file,
+                    &scope,
+                    constants,
+                )?);
+                continue;
+            }
+            Rule::equ => {
+                define_constant(
+                    labeled_data_element,
+                    &scope,
+                    constants,
+                    labels,
+                    data_offset,
+                    linter,
+                )?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let (start_line, line_count) = line_counter.lines(labeled_data_element.as_span());
+        let offset = data.len() as u32;
+
         process_labeled_element(
             labeled_data_element,
-            &mut labels,
+            labels,
+            &mut scope,
             Rule::data_element,
             data.len() as u32,
-            |p| process_data_element(p, &mut data),
+            |p, _| {
+                check_alignment(&p, data_offset.wrapping_add(offset), linter)?;
+                process_data_element(p, data)
+            },
         )?;
+
+        data_map.push(DataMapItem {
+            offset,
+            length: data.len() as u32 - offset,
+            file,
+            start_line,
+            line_count,
+        });
     }
 
-    Ok((data, labels))
+    linter.define_labels(Section::Data, scope.into_definitions());
+
+    Ok(())
 }
 
 #[cfg(test)]
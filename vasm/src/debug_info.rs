@@ -0,0 +1,82 @@
+use crate::{Section, SourceMap, SymbolTable};
+use vcpu::WORD_BYTES;
+use vex::debug::{DebugInfo, DebugSymbol, LineEntry, SymbolKind};
+
+/// Converts the source map and symbol table into debug information, using `source_names` as
+/// the names of the source files, in the order they were assembled in.
+pub fn build_debug_info(
+    source_map: &SourceMap,
+    symbols: &SymbolTable,
+    source_names: &[&str],
+) -> DebugInfo {
+    let lines = source_map
+        .iter()
+        .enumerate()
+        .map(|(i, item)| LineEntry {
+            address: i as u32 * WORD_BYTES,
+            file: item.file,
+            line: item.start_line,
+        })
+        .collect();
+
+    let symbols = symbols
+        .iter()
+        .map(|symbol| DebugSymbol {
+            name: symbol.name.clone(),
+            kind: match symbol.section {
+                Section::Data => SymbolKind::Data,
+                Section::Instructions => SymbolKind::Instruction,
+            },
+            address: symbol.address,
+        })
+        .collect();
+
+    DebugInfo {
+        files: source_names.iter().map(|name| (*name).to_owned()).collect(),
+        lines,
+        symbols,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble_sources, Options, Source};
+    use vex::debug::{LineEntry, SymbolKind};
+
+    #[test]
+    fn debug_info() {
+        let input = ".data
+.instructions
+main: NOP
+      HALT";
+
+        let sources = [Source {
+            name: "main.vasm",
+            text: input,
+        }];
+        let debug_info = assemble_sources(&sources, &Options::default())
+            .unwrap()
+            .debug_info;
+
+        assert_eq!(debug_info.files, vec!["main.vasm".to_owned()]);
+        assert_eq!(
+            debug_info.lines,
+            vec![
+                LineEntry {
+                    address: 0,
+                    file: 0,
+                    line: 3,
+                },
+                LineEntry {
+                    address: 4,
+                    file: 0,
+                    line: 4,
+                },
+            ]
+        );
+
+        let main = debug_info.symbol("main").unwrap();
+        assert_eq!(main.kind, SymbolKind::Instruction);
+        assert_eq!(main.address, 0);
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, Write};
+
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '#' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes a Makefile style dependency file, which states that all `targets` depend on all `dependencies`.
+///
+/// Duplicate dependencies are only listed once, in the order they first appear. Every dependency also gets
+/// an empty rule of its own, so `make` does not fail if a dependency is deleted. The format is understood by
+/// ninja's `depfile` as well.
+pub fn write_depfile<W: Write>(
+    writer: &mut W,
+    targets: &[&str],
+    dependencies: &[&str],
+) -> io::Result<()> {
+    let mut unique: Vec<&str> = Vec::new();
+    for dependency in dependencies {
+        if !unique.contains(dependency) {
+            unique.push(dependency);
+        }
+    }
+
+    let targets: Vec<String> = targets.iter().map(|target| escape(target)).collect();
+    write!(writer, "{}:", targets.join(" "))?;
+    for dependency in unique.iter() {
+        write!(writer, " \\\n  {}", escape(dependency))?;
+    }
+    writeln!(writer)?;
+
+    for dependency in unique.iter() {
+        writeln!(writer, "\n{}:", escape(dependency))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn depfile() {
+        let mut output = Vec::new();
+        super::write_depfile(
+            &mut output,
+            &["out/main.hex", "out/main.data.hex"],
+            &["main.vasm", "lib/my file.vasm", "main.vasm", "$x.vasm"],
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "out/main.hex out/main.data.hex: \\
+  main.vasm \\
+  lib/my\\ file.vasm \\
+  $$x.vasm
+
+main.vasm:
+
+lib/my\\ file.vasm:
+
+$$x.vasm:
+"
+        );
+    }
+}
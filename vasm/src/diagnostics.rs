@@ -0,0 +1,151 @@
+use crate::*;
+use pest::error::LineColLocation;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for DiagnosticSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            DiagnosticSeverity::Error => "error",
+            DiagnosticSeverity::Warning => "warning",
+        })
+    }
+}
+
+/// A line and column in the source, both starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// An error or warning in a form which is independent of pest, e.g. for showing it in an editor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    /// The kind of warning, `None` for errors. Warnings which were turned into errors keep their kind.
+    pub kind: Option<WarningKind>,
+    pub message: String,
+    /// Name of the source file, if the source was assembled with a name.
+    pub path: Option<String>,
+    pub start: Position,
+    /// End of the affected part of the source (exclusive). Equal to `start` if the error refers to a single position.
+    pub end: Position,
+}
+
+impl Diagnostic {
+    fn new(severity: DiagnosticSeverity, error: &Error) -> Self {
+        let position = |(line, column)| Position { line, column };
+        let (start, end) = match error.line_col {
+            LineColLocation::Pos(pos) => (position(pos), position(pos)),
+            LineColLocation::Span(start, end) => (position(start), position(end)),
+        };
+
+        // warnings and denied warnings end with the flag which controls them, e.g. " [-Wtruncation]"
+        let message = error.variant.message();
+        let (kind, message) = WarningKind::ALL
+            .iter()
+            .find_map(|kind| {
+                let stripped = message.strip_suffix(&format!(" [-W{}]", kind))?;
+                Some((Some(*kind), stripped.to_owned()))
+            })
+            .unwrap_or_else(|| (None, message.clone().into_owned()));
+
+        Diagnostic {
+            severity,
+            kind,
+            message,
+            path: error.path().map(str::to_owned),
+            start,
+            end,
+        }
+    }
+
+    pub fn from_error(error: &Error) -> Self {
+        Diagnostic::new(DiagnosticSeverity::Error, error)
+    }
+
+    pub fn from_warning(warning: &Warning) -> Self {
+        Diagnostic::new(DiagnosticSeverity::Warning, &warning.error)
+    }
+}
+
+impl Assembly {
+    /// Returns the warnings of this assembly as diagnostics.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.warnings.iter().map(Diagnostic::from_warning).collect()
+    }
+}
+
+/// Formats the diagnostic on a single line, as `path:line:column: severity: message`.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path)?;
+        }
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.start.line, self.start.column, self.severity, self.message
+        )?;
+        if let Some(kind) = self.kind {
+            write!(f, " [-W{}]", kind)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diagnostics() {
+        let input = ".data
+.instructions
+unused: SLLI $T0, $T0, 32
+        HALT";
+
+        let assembly = assemble(input, &Options::default()).unwrap();
+        let diagnostics = assembly.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0],
+            Diagnostic {
+                severity: DiagnosticSeverity::Warning,
+                kind: Some(WarningKind::Truncation),
+                message: "Shift amount 32 is truncated to 0".to_owned(),
+                path: None,
+                start: Position {
+                    line: 3,
+                    column: 24
+                },
+                end: Position {
+                    line: 3,
+                    column: 26
+                },
+            }
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "3:1: warning: Label \"unused\" is never used [-Wunused-label]"
+        );
+
+        let mut options = Options::default();
+        options.warnings.apply_flag("error=unused-label").unwrap();
+        let diagnostics = assemble(input, &options).unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, DiagnosticSeverity::Warning);
+        assert_eq!(diagnostics[1].severity, DiagnosticSeverity::Error);
+        assert_eq!(diagnostics[1].kind, Some(WarningKind::UnusedLabel));
+
+        let diagnostics = assemble(".data\n.instructions\nHALT $T0", &options).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].start, Position { line: 3, column: 6 });
+    }
+}
@@ -0,0 +1,246 @@
+use num::FromPrimitive;
+use vcpu::*;
+
+fn register(word: Word, mask: u32, offset: u32, registers: &RegisterTable) -> String {
+    // register fields are exactly wide enough for all registers, so every value is valid. Only the fields which
+    // the instruction does not use can name registers that are not in the table, and those are not printed.
+    let id = RegisterId::from_u32((word & mask) >> offset).unwrap();
+    format!("${}", registers.name(id).unwrap_or_default())
+}
+
+fn immediate(word: Word) -> Immediate {
+    ((word & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as Immediate
+}
+
+fn address(word: Word) -> Address {
+    let mut address = (word & ADDRESS_MASK) >> ADDRESS_OFFSET;
+    if address & ADDRESS_SIGN_MASK != 0 {
+        address |= ADDRESS_EXTENSION;
+    }
+    address as Address
+}
+
+fn opcode(word: Word) -> Option<Opcode> {
+    Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET)
+}
+
+/// Returns the VASM source of the instruction `word`.
+///
+/// Assembling the returned line produces `word` again, except for bits which the instruction does not use.
+/// Branches and jumps are written with their offset in bytes, relative to their own address.
+/// Returns `None` if `word` has no mnemonic, e.g. because its opcode or function is unknown.
+pub fn disassemble(word: Word) -> Option<String> {
+    disassemble_with(word, &RegisterTable::STANDARD)
+}
+
+/// Returns the VASM source of the instruction `word` like [`disassemble`](fn.disassemble.html), with the
+/// register names of `registers`. Returns `None` if `word` names a register which is not in the table, since
+/// a processor with the table would not execute it.
+pub fn disassemble_with(word: Word, registers: &RegisterTable) -> Option<String> {
+    if !registers.covers(word) {
+        return None;
+    }
+    let rd = register(word, RD_MASK, RD_OFFSET, registers);
+    let rs1 = register(word, RS1_MASK, RS1_OFFSET, registers);
+    let rs2 = register(word, RS2_MASK, RS2_OFFSET, registers);
+    let funct = (word & FUNCT_MASK) >> FUNCT_OFFSET;
+    let immediate = immediate(word);
+
+    let opcode = opcode(word)?;
+    Some(match opcode {
+        Opcode::NOP | Opcode::HALT | Opcode::IRET => opcode.to_string(),
+        Opcode::CALL => return None,
+        Opcode::ALU => match AluFunct::from_u32(funct)? {
+            funct @ (AluFunct::FSL | AluFunct::FSR) => {
+                let shamt = (word & SHAMT_MASK) >> SHAMT_OFFSET;
+                format!("{} {}, {}, {}, {}", funct, rd, rs1, rs2, shamt)
+            }
+            AluFunct::BSWAP => format!("{} {}, {}", AluFunct::BSWAP, rd, rs1),
+            funct @ (AluFunct::EXTB | AluFunct::EXTH) => {
+                let index = (word & SHAMT_MASK) >> SHAMT_OFFSET;
+                format!("{} {}, {}, {}", funct, rd, rs1, index)
+            }
+            funct @ (AluFunct::INSB | AluFunct::INSH) => {
+                let index = (word & SHAMT_MASK) >> SHAMT_OFFSET;
+                format!("{} {}, {}, {}, {}", funct, rd, rs1, rs2, index)
+            }
+            funct => format!("{} {}, {}, {}", funct, rd, rs1, rs2),
+        },
+        Opcode::FLOP => format!("{} {}, {}, {}", FlopFunct::from_u32(funct)?, rd, rs1, rs2),
+        Opcode::COPY | Opcode::FLIP | Opcode::ITOF | Opcode::FTOI => {
+            format!("{} {}, {}", opcode, rd, rs1)
+        }
+        Opcode::LI | Opcode::LHI => format!("{} {}, {}", opcode, rd, immediate),
+        Opcode::SLO | Opcode::SHI => format!("{} {}, {}", opcode, rd, immediate as u16),
+        Opcode::LB | Opcode::LH | Opcode::LW | Opcode::SB | Opcode::SH | Opcode::SW => {
+            format!("{} {}, {}({})", opcode, rd, immediate, rs1)
+        }
+        Opcode::SLTUI | Opcode::SGTUI | Opcode::SLEUI | Opcode::SGEUI => {
+            format!("{} {}, {}, {}", opcode, rd, rs1, immediate as u16)
+        }
+        Opcode::BEZ | Opcode::BNZ => format!("{} {}, {}", opcode, rs1, immediate),
+        Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BGE | Opcode::BLTU | Opcode::BGEU => {
+            format!("{} {}, {}, {}", opcode, rd, rs1, immediate)
+        }
+        Opcode::JMP | Opcode::JL => format!("{} {}", opcode, address(word)),
+        Opcode::JR | Opcode::JLR => format!("{} {}", opcode, rs1),
+        Opcode::CSRR => format!("{} {}, {}", opcode, rd, immediate as u16),
+        Opcode::CSRW | Opcode::CSRS | Opcode::CSRC => {
+            format!("{} {}, {}, {}", opcode, rd, rs1, immediate as u16)
+        }
+        Opcode::VEC => {
+            let vector = |mask, offset| {
+                VectorRegisterId::from_u32((word & mask) >> offset).map(|id| format!("${}", id))
+            };
+            let lane = (word & RS2_MASK) >> RS2_OFFSET;
+            match VecFunct::from_u32(funct)? {
+                funct @ (VecFunct::VADD | VecFunct::VSUB | VecFunct::VMUL) => format!(
+                    "{} {}, {}, {}",
+                    funct,
+                    vector(RD_MASK, RD_OFFSET)?,
+                    vector(RS1_MASK, RS1_OFFSET)?,
+                    vector(RS2_MASK, RS2_OFFSET)?
+                ),
+                VecFunct::VSPLAT => format!("VSPLAT {}, {}", vector(RD_MASK, RD_OFFSET)?, rs1),
+                VecFunct::VINS => {
+                    format!("VINS {}, {}, {}", vector(RD_MASK, RD_OFFSET)?, rs1, lane)
+                }
+                VecFunct::VEXT => {
+                    format!("VEXT {}, {}, {}", rd, vector(RS1_MASK, RS1_OFFSET)?, lane)
+                }
+                funct @ (VecFunct::VLD | VecFunct::VST) => {
+                    format!("{} {}, ({})", funct, vector(RD_MASK, RD_OFFSET)?, rs1)
+                }
+            }
+        }
+        Opcode::BANK => match BankFunct::from_u32(funct)? {
+            BankFunct::SWAPB => "SWAPB".to_owned(),
+            funct => format!("{} {}, {}", funct, rd, rs1),
+        },
+        _ => format!("{} {}, {}, {}", opcode, rd, rs1, immediate),
+    })
+}
+
+/// Returns the address which the branch or jump `word` at `address` continues at, if it is one.
+///
+/// Jumps to registers are not included, since their target is only known at runtime.
+pub fn jump_target(word: Word, address: u32) -> Option<u32> {
+    let offset = match opcode(word)? {
+        opcode if opcode.is_branch() => Into::<i32>::into(immediate(word)),
+        Opcode::JMP | Opcode::JL => self::address(word),
+        _ => return None,
+    };
+    Some(address.wrapping_add(offset as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use byteorder::ByteOrder;
+
+    #[test]
+    fn reassemble() {
+        let lines = [
+            "NOP",
+            "HALT",
+            "ADD $T0, $T1, $T2",
+            "SGEU $V0, $ZERO, $RA",
+            "ADDO $T0, $T1, $T2",
+            "SUBO $S0, $A0, $ZERO",
+            "ROR $T0, $T1, $T2",
+            "FSL $T0, $T1, $T2, 7",
+            "FSR $A0, $A1, $A0, 31",
+            "BSWAP $T0, $T1",
+            "EXTB $T0, $T1, 3",
+            "EXTH $V0, $A0, 1",
+            "INSB $T0, $T0, $T1, 2",
+            "INSH $T0, $T1, $T2, 0",
+            "FDIV $S0, $S1, $S2",
+            "COPY $A0, $SP",
+            "FTOI $T0, $T0",
+            "LI $T0, -5",
+            "LHI $T1, 4660",
+            "SLO $T1, 65535",
+            "LW $T0, -4($SP)",
+            "SB $A1, 0($T3)",
+            "ADDI $SP, $SP, -8",
+            "SRAI $T0, $T1, 3",
+            "SLTUI $T0, $T1, 40000",
+            "BNZ $T0, -8",
+            "BLTU $T0, $T1, 12",
+            "BNE $A0, $ZERO, -4",
+            "JMP 16",
+            "JL -4",
+            "JLR $T5",
+            "CSRR $T0, 2",
+            "CSRC $V0, $T1, 33",
+            "VMUL $Q0, $Q7, $Q3",
+            "VSPLAT $Q1, $T0",
+            "VINS $Q2, $A0, 3",
+            "VEXT $V0, $Q2, 1",
+            "VLD $Q0, ($SP)",
+            "VST $Q6, ($T9)",
+            "SWAPB",
+            "RDSH $T0, $SP",
+            "WRSH $A0, $V0",
+            "IRET",
+        ];
+        for line in lines.iter() {
+            let assembly = assemble_program(&format!(".data\n.instructions\n{}", line), 0)
+                .unwrap_or_else(|e| panic!("{}: {}", line, e));
+            let word = util::Endian::read_u32(assembly.executable.instructions());
+            assert_eq!(disassemble(word).as_deref(), Some(*line));
+        }
+
+        assert_eq!(
+            disassemble(enum_to_u32(Opcode::CALL) << OPCODE_OFFSET),
+            None
+        );
+        assert_eq!(disassemble(0xFFFF_FFFF), None);
+    }
+
+    #[test]
+    fn register_tables() {
+        let numbered = RegisterTable::new(vec![
+            (RegisterId::ZERO, "X0".to_owned()),
+            (RegisterId::T0, "X1".to_owned()),
+            (RegisterId::SP, "X2".to_owned()),
+        ])
+        .unwrap();
+        for (registers, line) in [
+            (RegisterTable::SMALL, "ADD $T0, $T3, $SP"),
+            (RegisterTable::SMALL, "BEZ $RA, 4"),
+            (numbered.clone(), "LW $X1, -4($X2)"),
+            (numbered.clone(), "VSPLAT $Q0, $X1"),
+            (numbered.clone(), "VINS $Q0, $X1, 3"),
+        ] {
+            let options = Options {
+                registers: registers.clone(),
+                ..Options::default()
+            };
+            let input = format!(".data\n.instructions\n{}", line);
+            let assembly = assemble_with_options(&input, &options)
+                .unwrap_or_else(|e| panic!("{}: {}", line, e));
+            let word = util::Endian::read_u32(assembly.executable.instructions());
+            assert_eq!(disassemble_with(word, &registers).as_deref(), Some(line));
+        }
+
+        assert_eq!(
+            disassemble_with(instr_alu!(ADD, T0, T4, T1), &RegisterTable::SMALL),
+            None
+        );
+        assert_eq!(
+            disassemble_with(instr_i!(LI, T0, ZERO, -1), &numbered).as_deref(),
+            Some("LI $X1, -1")
+        );
+    }
+
+    #[test]
+    fn jump_targets() {
+        assert_eq!(jump_target(instr_j!(JMP, -8), 12), Some(4));
+        assert_eq!(jump_target(instr_i!(BEZ, ZERO, T0, 8), 4), Some(12));
+        assert_eq!(jump_target(instr_i!(JR, ZERO, RA, 0), 4), None);
+        assert_eq!(jump_target(nop!(), 4), None);
+    }
+}
@@ -0,0 +1,286 @@
+//! Expressions which are evaluated by tools instead of the assembler, e.g. addresses and breakpoint conditions
+//! in the debugger or filters of traces.
+//!
+//! They have the same syntax and operators as the expressions of the assembler, and may also contain registers
+//! (`$SP`) and the word in memory at an address (`[$SP - 4]`). Unlike in the assembler, values are 32-bit words:
+//! arithmetic wraps around and comparisons are signed. A name which the context does not know as a symbol is a
+//! register without `$`, so `[sp-4]` works as well.
+
+use crate::expressions::{binary_op, pratt_parser, BinaryOp, UnaryOp};
+use crate::int_util::process_uint;
+use crate::parser::{Rule, VASMParser};
+use pest::iterators::Pair;
+use pest::Parser;
+use std::str::FromStr;
+use vcpu::RegisterId;
+
+/// Provides the values of symbols, registers and memory to [`ToolExpression::evaluate`](struct.ToolExpression.html#method.evaluate).
+pub trait EvalContext {
+    /// Returns the value of a symbol, or `None` if it is not known.
+    fn symbol(&self, name: &str) -> Option<u32>;
+
+    fn register(&self, _register: RegisterId) -> Result<u32, String> {
+        Err("Registers are not available here".to_owned())
+    }
+
+    /// Returns the word at `address`, or an error if it cannot be read.
+    fn memory(&self, _address: u32) -> Result<u32, String> {
+        Err("Memory is not available here".to_owned())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Int(u32),
+    Symbol(String),
+    Register(RegisterId),
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// A parsed expression, which can be evaluated many times, e.g. whenever a breakpoint is reached.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolExpression {
+    root: Node,
+}
+
+fn process_node(pair: Pair<Rule>) -> Result<Node, String> {
+    pratt_parser()
+        .map_primary(|primary| match primary.as_rule() {
+            Rule::uint => process_uint::<u32>(primary)
+                .map(Node::Int)
+                .map_err(|err| err.variant.message().into_owned()),
+            Rule::expression => process_node(primary),
+            Rule::register_reference => {
+                let name = primary.into_inner().next().unwrap().as_str();
+                Ok(Node::Register(name.to_uppercase().parse().unwrap()))
+            }
+            Rule::memory_reference => Ok(Node::Memory(Box::new(process_node(
+                primary.into_inner().next().unwrap(),
+            )?))),
+            _ => Ok(Node::Symbol(primary.as_str().to_owned())),
+        })
+        .map_prefix(|op, operand| {
+            let op = match op.as_rule() {
+                Rule::op_neg => UnaryOp::Neg,
+                Rule::op_bit_not => UnaryOp::BitNot,
+                Rule::op_not => UnaryOp::Not,
+                _ => unreachable!(),
+            };
+            Ok(Node::Unary(op, Box::new(operand?)))
+        })
+        .map_infix(|lhs, op, rhs| {
+            Ok(Node::Binary(
+                binary_op(op.as_rule()),
+                Box::new(lhs?),
+                Box::new(rhs?),
+            ))
+        })
+        .parse(pair.into_inner())
+}
+
+impl FromStr for ToolExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs = VASMParser::parse(Rule::tool_expression, s).map_err(|err| {
+            let column = match err.line_col {
+                pest::error::LineColLocation::Pos((_, column))
+                | pest::error::LineColLocation::Span((_, column), _) => column,
+            };
+            format!(
+                "Invalid expression \"{}\" at column {}: {}",
+                s.trim(),
+                column,
+                err.variant.message()
+            )
+        })?;
+        let expression = pairs.next().unwrap().into_inner().next().unwrap();
+        Ok(ToolExpression {
+            root: process_node(expression)?,
+        })
+    }
+}
+
+impl Node {
+    fn evaluate(&self, context: &dyn EvalContext) -> Result<u32, String> {
+        Ok(match self {
+            Node::Int(value) => *value,
+            Node::Symbol(name) => match context.symbol(name) {
+                Some(value) => value,
+                None => match name.to_uppercase().parse::<RegisterId>() {
+                    Ok(register) => context.register(register)?,
+                    Err(_) => return Err(format!("Unknown symbol {}", name)),
+                },
+            },
+            Node::Register(register) => context.register(*register)?,
+            Node::Memory(address) => context.memory(address.evaluate(context)?)?,
+            Node::Unary(op, operand) => {
+                let value = operand.evaluate(context)?;
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                    UnaryOp::Not => (value == 0).into(),
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let a = lhs.evaluate(context)?;
+                let b = rhs.evaluate(context)?;
+                let (signed_a, signed_b) = (a as i32, b as i32);
+                match op {
+                    BinaryOp::Or => (a != 0 || b != 0).into(),
+                    BinaryOp::And => (a != 0 && b != 0).into(),
+                    BinaryOp::Eq => (a == b).into(),
+                    BinaryOp::Ne => (a != b).into(),
+                    BinaryOp::Lt => (signed_a < signed_b).into(),
+                    BinaryOp::Le => (signed_a <= signed_b).into(),
+                    BinaryOp::Gt => (signed_a > signed_b).into(),
+                    BinaryOp::Ge => (signed_a >= signed_b).into(),
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b),
+                    BinaryOp::Shr => a.wrapping_shr(b),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err("Division by zero".to_owned())
+                    }
+                    BinaryOp::Div => signed_a.wrapping_div(signed_b) as u32,
+                    BinaryOp::Rem => signed_a.wrapping_rem(signed_b) as u32,
+                }
+            }
+        })
+    }
+
+    fn for_each_symbol<F: FnMut(&str)>(&self, f: &mut F) {
+        match self {
+            Node::Symbol(name) => f(name),
+            Node::Memory(operand) | Node::Unary(_, operand) => operand.for_each_symbol(f),
+            Node::Binary(_, lhs, rhs) => {
+                lhs.for_each_symbol(f);
+                rhs.for_each_symbol(f);
+            }
+            Node::Int(_) | Node::Register(_) => {}
+        }
+    }
+}
+
+impl ToolExpression {
+    /// Computes the value of the expression. Comparisons and logical operators evaluate to 1 if they are true
+    /// and 0 otherwise.
+    pub fn evaluate(&self, context: &dyn EvalContext) -> Result<u32, String> {
+        self.root.evaluate(context)
+    }
+
+    /// Calls `f` with the name of every symbol, including register names without `$`.
+    pub fn for_each_symbol<F: FnMut(&str)>(&self, mut f: F) {
+        self.root.for_each_symbol(&mut f)
+    }
+
+    /// Returns an error for the first symbol which is neither known to `context` nor a register name, so that
+    /// mistakes are found before the expression is evaluated.
+    pub fn check_symbols(&self, context: &dyn EvalContext) -> Result<(), String> {
+        let mut unknown = None;
+        self.for_each_symbol(|name| {
+            let known =
+                context.symbol(name).is_some() || name.to_uppercase().parse::<RegisterId>().is_ok();
+            if !known && unknown.is_none() {
+                unknown = Some(name.to_owned());
+            }
+        });
+        match unknown {
+            Some(name) => Err(format!("Unknown symbol {}", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses and evaluates an expression.
+pub fn evaluate_expression(expression: &str, context: &dyn EvalContext) -> Result<u32, String> {
+    expression.parse::<ToolExpression>()?.evaluate(context)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn evaluate(expression: &str, context: &dyn EvalContext) -> Result<u32, String> {
+        evaluate_expression(expression, context)
+    }
+
+    struct Registers;
+
+    impl EvalContext for Registers {
+        fn symbol(&self, name: &str) -> Option<u32> {
+            match name {
+                "main" => Some(0x40),
+                "sp" => Some(1),
+                _ => None,
+            }
+        }
+
+        fn register(&self, register: RegisterId) -> Result<u32, String> {
+            Ok(register as u32 * 0x100)
+        }
+
+        fn memory(&self, address: u32) -> Result<u32, String> {
+            if address < 0x10000 {
+                Ok(address / 4)
+            } else {
+                Err(format!("Address 0x{:08X} cannot be read", address))
+            }
+        }
+    }
+
+    struct Symbols;
+
+    impl EvalContext for Symbols {
+        fn symbol(&self, name: &str) -> Option<u32> {
+            Some(name.len() as u32)
+        }
+    }
+
+    #[test]
+    fn values() {
+        let sp = RegisterId::SP as u32 * 0x100;
+        assert_eq!(evaluate("main+0x10", &Registers), Ok(0x50));
+        assert_eq!(evaluate(" $SP - 4 ", &Registers), Ok(sp - 4));
+        assert_eq!(evaluate("$sp", &Registers), Ok(sp));
+        assert_eq!(
+            evaluate("t0", &Registers),
+            Ok(RegisterId::T0 as u32 * 0x100)
+        );
+        // symbols take precedence over register names without $
+        assert_eq!(evaluate("sp", &Registers), Ok(1));
+        assert_eq!(evaluate("[$SP-4] == ($SP-4)/4", &Registers), Ok(1));
+        assert_eq!(evaluate("[[main]] + 1", &Registers), Ok(5));
+        assert_eq!(evaluate("-1 < 0 && 0xFFFFFFFF == -1", &Registers), Ok(1));
+        assert_eq!(evaluate("-7 / 2", &Registers), Ok(-3i32 as u32));
+        assert_eq!(evaluate("1 << 31 >> 31", &Registers), Ok(1));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(evaluate("main +", &Registers).is_err());
+        assert!(evaluate("[main", &Registers).is_err());
+        assert!(evaluate("$XY", &Registers).is_err());
+        assert!(evaluate("nowhere", &Registers).is_err());
+        assert!(evaluate("[0x10000]", &Registers).is_err());
+        assert!(evaluate("1 % 0", &Registers).is_err());
+        assert_eq!(evaluate("abc * 2", &Symbols), Ok(6));
+        assert!(evaluate("$T0", &Symbols).is_err());
+        assert!(evaluate("[abc]", &Symbols).is_err());
+
+        let expression: ToolExpression = "main + nowhere + [t0]".parse().unwrap();
+        assert_eq!(
+            expression.check_symbols(&Registers),
+            Err("Unknown symbol nowhere".to_owned())
+        );
+        let mut symbols = Vec::new();
+        expression.for_each_symbol(|name| symbols.push(name.to_owned()));
+        assert_eq!(symbols, ["main", "nowhere", "t0"]);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::int_util::process_uint;
+use crate::labels::*;
+use crate::*;
+use matches::debug_assert_matches;
+use pest::iterators::Pair;
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    BitNot,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// An integer expression, which may refer to the addresses of labels.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression<'i> {
+    Int(i64),
+    Label(LabelRef<'i>),
+    /// A label whose address is already known.
+    Address(Section, i64),
+    Unary(UnaryOp, Box<Expression<'i>>),
+    Binary(BinaryOp, Box<Expression<'i>>, Box<Expression<'i>>, Span<'i>),
+}
+
+pub(crate) fn pratt_parser() -> PrattParser<Rule> {
+    // operator precedence follows Rust, from lowest to highest
+    PrattParser::new()
+        .op(Op::infix(Rule::op_or, Assoc::Left))
+        .op(Op::infix(Rule::op_and, Assoc::Left))
+        .op(Op::infix(Rule::op_eq, Assoc::Left)
+            | Op::infix(Rule::op_ne, Assoc::Left)
+            | Op::infix(Rule::op_lt, Assoc::Left)
+            | Op::infix(Rule::op_le, Assoc::Left)
+            | Op::infix(Rule::op_gt, Assoc::Left)
+            | Op::infix(Rule::op_ge, Assoc::Left))
+        .op(Op::infix(Rule::op_bit_or, Assoc::Left))
+        .op(Op::infix(Rule::op_bit_xor, Assoc::Left))
+        .op(Op::infix(Rule::op_bit_and, Assoc::Left))
+        .op(Op::infix(Rule::op_shl, Assoc::Left) | Op::infix(Rule::op_shr, Assoc::Left))
+        .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_sub, Assoc::Left))
+        .op(Op::infix(Rule::op_mul, Assoc::Left)
+            | Op::infix(Rule::op_div, Assoc::Left)
+            | Op::infix(Rule::op_rem, Assoc::Left))
+        .op(Op::prefix(Rule::op_neg) | Op::prefix(Rule::op_bit_not) | Op::prefix(Rule::op_not))
+}
+
+pub(crate) fn binary_op(rule: Rule) -> BinaryOp {
+    match rule {
+        Rule::op_or => BinaryOp::Or,
+        Rule::op_and => BinaryOp::And,
+        Rule::op_eq => BinaryOp::Eq,
+        Rule::op_ne => BinaryOp::Ne,
+        Rule::op_lt => BinaryOp::Lt,
+        Rule::op_le => BinaryOp::Le,
+        Rule::op_gt => BinaryOp::Gt,
+        Rule::op_ge => BinaryOp::Ge,
+        Rule::op_bit_or => BinaryOp::BitOr,
+        Rule::op_bit_xor => BinaryOp::BitXor,
+        Rule::op_bit_and => BinaryOp::BitAnd,
+        Rule::op_shl => BinaryOp::Shl,
+        Rule::op_shr => BinaryOp::Shr,
+        Rule::op_add => BinaryOp::Add,
+        Rule::op_sub => BinaryOp::Sub,
+        Rule::op_mul => BinaryOp::Mul,
+        Rule::op_div => BinaryOp::Div,
+        Rule::op_rem => BinaryOp::Rem,
+        _ => unreachable!(),
+    }
+}
+
+/// Parses an `expression` pair. Labels are qualified with `scope`, so they can be resolved later.
+pub fn process_expression<'i>(
+    pair: Pair<'i, Rule>,
+    scope: &LabelScope<'i>,
+) -> Result<Expression<'i>> {
+    debug_assert_matches!(pair.as_rule(), Rule::expression);
+
+    pratt_parser()
+        .map_primary(|primary| match primary.as_rule() {
+            Rule::uint => Ok(Expression::Int(process_uint::<u32>(primary)?.into())),
+            Rule::expression => process_expression(primary, scope),
+            Rule::register_reference => Err(new_parser_error(
+                primary.as_span(),
+                "Registers cannot be used in assembler expressions".to_owned(),
+            )),
+            Rule::memory_reference => Err(new_parser_error(
+                primary.as_span(),
+                "Memory cannot be read in assembler expressions".to_owned(),
+            )),
+            Rule::qualified_reference => Err(new_parser_error(
+                primary.as_span(),
+                "Local labels can only be referenced in their own scope".to_owned(),
+            )),
+            _ => Ok(Expression::Label(scope.reference(primary)?)),
+        })
+        .map_prefix(|op, operand| {
+            let op = match op.as_rule() {
+                Rule::op_neg => UnaryOp::Neg,
+                Rule::op_bit_not => UnaryOp::BitNot,
+                Rule::op_not => UnaryOp::Not,
+                _ => unreachable!(),
+            };
+            Ok(Expression::Unary(op, Box::new(operand?)))
+        })
+        .map_infix(|lhs, op, rhs| {
+            Ok(Expression::Binary(
+                binary_op(op.as_rule()),
+                Box::new(lhs?),
+                Box::new(rhs?),
+                op.as_span(),
+            ))
+        })
+        .parse(pair.into_inner())
+}
+
+impl<'i> Expression<'i> {
+    /// Computes the value of the expression with 64-bit wrapping arithmetic.
+    /// Comparisons and logical operators evaluate to 1 if they are true and 0 otherwise.
+    ///
+    /// `resolve` returns the address of a label, or an error if it is not defined.
+    pub fn evaluate<F>(&self, resolve: &mut F) -> Result<i64>
+    where
+        F: FnMut(&LabelRef<'i>) -> Result<i64>,
+    {
+        Ok(match self {
+            Expression::Int(value) | Expression::Address(_, value) => *value,
+            Expression::Label(label) => resolve(label)?,
+            Expression::Unary(op, operand) => {
+                let value = operand.evaluate(resolve)?;
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                    UnaryOp::Not => (value == 0).into(),
+                }
+            }
+            Expression::Binary(op, lhs, rhs, span) => {
+                let a = lhs.evaluate(resolve)?;
+                let b = rhs.evaluate(resolve)?;
+                match op {
+                    BinaryOp::Or => (a != 0 || b != 0).into(),
+                    BinaryOp::And => (a != 0 && b != 0).into(),
+                    BinaryOp::Eq => (a == b).into(),
+                    BinaryOp::Ne => (a != b).into(),
+                    BinaryOp::Lt => (a < b).into(),
+                    BinaryOp::Le => (a <= b).into(),
+                    BinaryOp::Gt => (a > b).into(),
+                    BinaryOp::Ge => (a >= b).into(),
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err(new_parser_error(*span, "Division by zero".to_owned()));
+                    }
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Rem => a.wrapping_rem(b),
+                }
+            }
+        })
+    }
+}
+
+impl<'i> Expression<'i> {
+    /// Replaces every label for which `replace` returns an expression.
+    pub fn substitute<F>(self, replace: &mut F) -> Expression<'i>
+    where
+        F: FnMut(&LabelRef<'i>) -> Option<Expression<'i>>,
+    {
+        match self {
+            Expression::Label(label) => replace(&label).unwrap_or(Expression::Label(label)),
+            Expression::Unary(op, operand) => {
+                Expression::Unary(op, Box::new(operand.substitute(replace)))
+            }
+            Expression::Binary(op, lhs, rhs, span) => Expression::Binary(
+                op,
+                Box::new(lhs.substitute(replace)),
+                Box::new(rhs.substitute(replace)),
+                span,
+            ),
+            expression => expression,
+        }
+    }
+
+    /// Calls `f` with every label which is not resolved yet.
+    pub fn for_each_label<F: FnMut(&LabelRef<'i>)>(&self, f: &mut F) {
+        match self {
+            Expression::Label(label) => f(label),
+            Expression::Unary(_, operand) => operand.for_each_label(f),
+            Expression::Binary(_, lhs, rhs, _) => {
+                lhs.for_each_label(f);
+                rhs.for_each_label(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// Determines which section the value of the expression is an address in, `None` for plain numbers.
+    /// Labels which are not resolved yet are instruction labels.
+    ///
+    /// Addresses can only be offset by a number, or subtracted from an address in the same section,
+    /// which results in a number. Every other use of an address is an error, reported at `span` for
+    /// prefix operators.
+    pub fn relocation(&self, span: Span<'i>) -> Result<Option<Section>> {
+        Ok(match self {
+            Expression::Int(_) => None,
+            Expression::Label(_) => Some(Section::Instructions),
+            Expression::Address(section, _) => Some(*section),
+            Expression::Unary(_, operand) => match operand.relocation(span)? {
+                None => None,
+                Some(_) => {
+                    return Err(new_parser_error(
+                        span,
+                        "Operator cannot be applied to an address".to_owned(),
+                    ))
+                }
+            },
+            Expression::Binary(op, lhs, rhs, op_span) => {
+                let error = |message: &str| Err(new_parser_error(*op_span, message.to_owned()));
+                match (op, lhs.relocation(span)?, rhs.relocation(span)?) {
+                    (_, None, None) => None,
+                    (BinaryOp::Add, Some(section), None) | (BinaryOp::Add, None, Some(section)) => {
+                        Some(section)
+                    }
+                    (BinaryOp::Sub, Some(section), None) => Some(section),
+                    (BinaryOp::Add, Some(_), Some(_)) => return error("Cannot add two addresses"),
+                    (BinaryOp::Sub, None, Some(_)) => {
+                        return error("Cannot subtract an address from a number")
+                    }
+                    (_, Some(a), Some(b)) if a != b => {
+                        return error("Expression mixes data and instruction addresses")
+                    }
+                    (BinaryOp::Sub, Some(_), Some(_))
+                    | (BinaryOp::Eq, Some(_), Some(_))
+                    | (BinaryOp::Ne, Some(_), Some(_))
+                    | (BinaryOp::Lt, Some(_), Some(_))
+                    | (BinaryOp::Le, Some(_), Some(_))
+                    | (BinaryOp::Gt, Some(_), Some(_))
+                    | (BinaryOp::Ge, Some(_), Some(_)) => None,
+                    _ => return error("Operator cannot be applied to an address"),
+                }
+            }
+        })
+    }
+}
+
+/// Values of all constants defined with `.equ`.
+pub type Constants<'i> = HashMap<String, Expression<'i>>;
+
+/// The symbols which are known while a section is processed.
+pub struct KnownSymbols<'a, 'i> {
+    pub constants: &'a Constants<'i>,
+    /// Data labels defined so far. All of them are known once the instruction sections are processed.
+    pub data_labels: &'a LabelMap,
+    pub data_offset: u32,
+}
+
+impl<'a, 'i> KnownSymbols<'a, 'i> {
+    /// Parses an `expression` pair and replaces all constants and known data labels with their values.
+    /// The remaining labels are instruction labels, whose addresses are only known after layout.
+    ///
+    /// Fails if the expression combines addresses in a way that has no meaning, see
+    /// [`Expression::relocation`](enum.Expression.html#method.relocation).
+    pub fn fold(
+        &self,
+        pair: Pair<'i, Rule>,
+        scope: &LabelScope<'i>,
+        linter: &mut Linter,
+    ) -> Result<Expression<'i>> {
+        let span = pair.as_span();
+        let expression = process_expression(pair, scope)?.substitute(&mut |label| {
+            if let Some(constant) = self.constant(label) {
+                return Some(constant.clone());
+            }
+            let address = self.data_labels.get(label.key())?;
+            linter.use_label(Section::Data, label.key());
+            Some(Expression::Address(
+                Section::Data,
+                self.data_offset.wrapping_add(*address).into(),
+            ))
+        });
+        expression.relocation(span)?;
+        expression
+            .for_each_label(&mut |label| linter.use_label(Section::Instructions, label.key()));
+        Ok(expression)
+    }
+
+    fn constant(&self, label: &LabelRef<'i>) -> Option<&'a Expression<'i>> {
+        match label {
+            LabelRef::Global(span) => self.constants.get(span.as_str()),
+            LabelRef::Scoped(..) => None,
+        }
+    }
+}
+
+/// Processes an `equ` pair and adds the constant to `constants`.
+pub fn define_constant<'i>(
+    pair: Pair<'i, Rule>,
+    scope: &LabelScope<'i>,
+    constants: &mut Constants<'i>,
+    data_labels: &LabelMap,
+    data_offset: u32,
+    linter: &mut Linter,
+) -> Result<()> {
+    debug_assert_matches!(pair.as_rule(), Rule::equ);
+    let mut pairs = pair.into_inner();
+    let name = pairs.next().unwrap();
+    if constants.contains_key(name.as_str()) {
+        return Err(new_parser_error(
+            name.as_span(),
+            format!("Constant \"{}\" is already defined", name.as_str()),
+        ));
+    }
+
+    let symbols = KnownSymbols {
+        constants,
+        data_labels,
+        data_offset,
+    };
+    let value = symbols.fold(pairs.next().unwrap(), scope, linter)?;
+    constants.insert(name.as_str().to_owned(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::parse_rule;
+
+    fn evaluate(input: &str) -> Result<i64> {
+        let scope = LabelScope::default();
+        let expression = process_expression(parse_rule(Rule::expression, input)?, &scope)?;
+        expression.evaluate(&mut |label| Ok(if label.key() == "table" { 64 } else { 4 }))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(evaluate("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(evaluate("-table + 0x10 << 1").unwrap(), -96);
+        assert_eq!(evaluate("1 << 4 == 16 && !0").unwrap(), 1);
+        assert_eq!(evaluate("table & (table - 1) == 0").unwrap(), 1);
+        assert_eq!(evaluate("~0 ^ 5 | 2").unwrap(), -6 | 2);
+        assert_eq!(evaluate("7 % 4 >= 3 || end < 2").unwrap(), 1);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(evaluate("1 / (end - 4)").is_err());
+        assert!(evaluate("1 % 0").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::assertions::{check_assertions, process_assertion};
+use crate::expressions::{Constants, Expression, KnownSymbols};
+use crate::instructions::{finalize_instruction, process_instruction};
+use crate::labels::*;
+use crate::registers::RegisterAliases;
+use crate::*;
+use std::collections::HashMap;
+use vcpu::{Word, WORD_BYTES};
+
+/// The result of feeding a single line to an [`Assembler`](struct.Assembler.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssembledLine {
+    /// Address of the first instruction of the line in instruction memory.
+    pub address: u32,
+    /// The encoded instructions, empty if the line contains no instruction.
+    pub words: Vec<Word>,
+    pub warnings: Vec<Warning>,
+}
+
+/// Assembles instructions one line at a time, e.g. for an interactive monitor.
+///
+/// Each line may contain a single (labeled) instruction, an `.alias`, an `.equ` or an `.assert`. Labels, aliases
+/// and constants stay defined for all following lines, so every line is assembled in the context of all lines before it.
+/// Since every line is encoded immediately, labels must be defined before they are used,
+/// and branches are not relaxed.
+pub struct Assembler {
+    data_offset: u32,
+    data_labels: LabelMap,
+    labels: LabelMap,
+    aliases: RegisterAliases,
+    /// Constants are evaluated when they are defined, since the lines they come from are not kept.
+    constants: Constants<'static>,
+    warnings: WarningOptions,
+    global: String,
+    numeric: HashMap<u32, u32>,
+    index: u32,
+}
+
+impl Assembler {
+    /// Creates an assembler without any labels, which starts at address 0.
+    pub fn new(data_offset: u32) -> Self {
+        Assembler {
+            data_offset,
+            data_labels: LabelMap::new(),
+            labels: LabelMap::new(),
+            aliases: RegisterAliases::default(),
+            constants: Constants::new(),
+            warnings: WarningOptions::default(),
+            global: String::new(),
+            numeric: HashMap::new(),
+            index: 0,
+        }
+    }
+
+    /// Creates an assembler which knows all symbols of `assembly` and continues after its last instruction.
+    pub fn from_assembly(assembly: &Assembly) -> Self {
+        let executable = &assembly.executable;
+        let mut assembler = Assembler::new(executable.data_offset());
+        for symbol in assembly.symbols.iter() {
+            assembler.define_symbol(symbol);
+        }
+        assembler.index = executable.instructions().len() as u32 / WORD_BYTES;
+        assembler
+    }
+
+    /// Makes a label from an existing program known to the assembler.
+    pub fn define_symbol(&mut self, symbol: &Symbol) {
+        match symbol.section {
+            Section::Data => self.data_labels.insert(
+                symbol.name.clone(),
+                symbol.address.wrapping_sub(self.data_offset),
+            ),
+            Section::Instructions => self
+                .labels
+                .insert(symbol.name.clone(), symbol.address / WORD_BYTES),
+        };
+    }
+
+    /// All labels known to the assembler, including the ones defined by fed lines.
+    pub fn symbols(&self) -> SymbolTable {
+        symbols::build_symbol_table(&self.data_labels, self.data_offset, &self.labels)
+    }
+
+    /// Address in instruction memory where the next instruction will be placed.
+    pub fn address(&self) -> u32 {
+        self.index * WORD_BYTES
+    }
+
+    /// Sets the address of the next instruction, which is rounded down to a multiple of the word size.
+    pub fn set_address(&mut self, address: u32) {
+        self.index = address / WORD_BYTES;
+    }
+
+    pub fn set_warning_options(&mut self, warnings: WarningOptions) {
+        self.warnings = warnings;
+    }
+
+    /// Sets the registers which the following lines may use, see
+    /// [`Options::registers`](struct.Options.html#structfield.registers).
+    pub fn set_registers(&mut self, registers: RegisterTable) {
+        self.aliases.set_registers(registers);
+    }
+
+    /// Assembles a single line. If the line contains an error, the state of the assembler is not changed.
+    pub fn feed_line(&mut self, line: &str) -> Result<AssembledLine> {
+        let address = self.address();
+        let pair = VASMParser::parse(Rule::line, line)?.next().unwrap();
+        let element = match pair.into_inner().find(|p| p.as_rule() != Rule::EOI) {
+            Some(element) => element,
+            None => {
+                return Ok(AssembledLine {
+                    address,
+                    words: Vec::new(),
+                    warnings: Vec::new(),
+                })
+            }
+        };
+
+        let mut linter = Linter::new(&self.warnings);
+        let global = self.global.clone();
+        let mut scope = LabelScope::resume(0, &global, self.numeric.clone());
+        let mut labels = self.labels.clone();
+        let mut instructions = Vec::new();
+        let symbols = KnownSymbols {
+            constants: &self.constants,
+            data_labels: &self.data_labels,
+            data_offset: self.data_offset,
+        };
+
+        match element.as_rule() {
+            Rule::alias => self.aliases.define(element)?,
+            Rule::entry => {
+                return Err(new_parser_error(
+                    element.as_span(),
+                    "Entry point cannot be changed while assembling single lines".to_owned(),
+                ))
+            }
+            Rule::equ => {
+                let mut pairs = element.into_inner();
+                let name = pairs.next().unwrap();
+                if self.constants.contains_key(name.as_str()) {
+                    return Err(new_parser_error(
+                        name.as_span(),
+                        format!("Constant \"{}\" is already defined", name.as_str()),
+                    ));
+                }
+                let value_pair = pairs.next().unwrap();
+                let span = value_pair.as_span();
+                let value = symbols.fold(value_pair, &scope, &mut linter)?;
+                let relocation = value.relocation(span)?;
+                let value = value.evaluate(&mut |label| {
+                    let index = labels.get(label.key()).ok_or_else(|| {
+                        new_parser_error(label.span(), "Label not found".to_owned())
+                    })?;
+                    Ok(Into::<i64>::into(*index) * Into::<i64>::into(WORD_BYTES))
+                })?;
+                let value = match relocation {
+                    Some(section) => Expression::Address(section, value),
+                    None => Expression::Int(value),
+                };
+                self.constants.insert(name.as_str().to_owned(), value);
+            }
+            Rule::assert => {
+                let assertion =
+                    process_assertion(element, Section::Instructions, 0, &scope, &self.constants)?;
+                check_assertions(
+                    &[assertion],
+                    &self.data_labels,
+                    self.data_offset,
+                    &labels,
+                    &[None],
+                    &mut linter,
+                )?;
+            }
+            _ => {
+                let aliases = &self.aliases;
+                process_labeled_element(
+                    element,
+                    &mut labels,
+                    &mut scope,
+                    Rule::instruction,
+                    self.index,
+                    |p, scope| {
+                        process_instruction(
+                            p,
+                            &mut instructions,
+                            scope,
+                            aliases,
+                            &symbols,
+                            &mut linter,
+                        )
+                        .map(|_| ())
+                    },
+                )?;
+            }
+        }
+
+        let words = instructions
+            .iter()
+            .enumerate()
+            .map(|(i, instr)| finalize_instruction(&labels, instr, self.index + i as u32))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (global, numeric) = scope.into_state();
+        self.global = global;
+        self.numeric = numeric;
+        self.labels = labels;
+        self.index += words.len() as u32;
+
+        Ok(AssembledLine {
+            address,
+            words,
+            warnings: linter.into_warnings(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use vcpu::*;
+
+    #[test]
+    fn feed_lines() {
+        let assembly = assemble_program(
+            ".data
+value: .word 5
+.instructions
+main: HALT",
+            0x100,
+        )
+        .unwrap();
+        let mut assembler = Assembler::from_assembly(&assembly);
+
+        let line = assembler.feed_line("loop: LDA $T0, value").unwrap();
+        assert_eq!(line.address, 4);
+        assert_eq!(line.words.len(), 2);
+
+        let line = assembler.feed_line("  .alias ptr, T0 # comment").unwrap();
+        assert!(line.words.is_empty());
+
+        assert_eq!(
+            assembler.feed_line("BNZ ptr, loop").unwrap().words,
+            vec![instr_i!(BNZ, ZERO, T0, -8)]
+        );
+        assert_eq!(
+            assembler.feed_line("JMP main").unwrap().words,
+            vec![instr_j!(JMP, -16)]
+        );
+        assert_eq!(assembler.address(), 20);
+        assert!(assembler.feed_line(".assert loop == 4, \"x\"").is_ok());
+
+        // errors leave the state untouched
+        assert!(assembler.feed_line("again: JMP later").is_err());
+        assert!(assembler.feed_line("again: NOP").is_ok());
+        assert_eq!(assembler.address(), 24);
+        assert!(assembler
+            .symbols()
+            .iter()
+            .any(|symbol| symbol.name == "again" && symbol.address == 20));
+    }
+}
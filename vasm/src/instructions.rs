@@ -1,10 +1,13 @@
+use crate::assertions::{process_assertion, Assertion};
+use crate::expressions::*;
 use crate::int_util::*;
 use crate::labels::*;
+use crate::registers::RegisterAliases;
+use crate::source_map::LineCounter;
 use crate::*;
 use byteorder::ByteOrder;
 use matches::debug_assert_matches;
 use num::*;
-use std::collections::HashMap;
 use std::num::ParseIntError;
 use std::str::FromStr;
 use util::ParseEnumError;
@@ -15,15 +18,17 @@ type InstrVec<'i> = Vec<ParsedInstruction<'i>>;
 #[derive(Debug, PartialEq)]
 pub enum JumpTarget<'i, T: Num + Copy> {
     Address(T),
-    Label(Span<'i>),
+    Label(LabelRef<'i>),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ParsedInstruction<'i> {
     Complete(Word),
 
+    /// A conditional branch, which compares `rd` with `rs1` unless it is `BEZ` or `BNZ`.
     Branch {
         opcode: Opcode,
+        rd: RegisterId,
         rs1: RegisterId,
         target: JumpTarget<'i, Immediate>,
     },
@@ -33,13 +38,101 @@ pub enum ParsedInstruction<'i> {
         target: JumpTarget<'i, Address>,
     },
 
-    LoadInstructionAddress {
-        label: Span<'i>,
-        rd: RegisterId,
-        upper: bool,
+    /// An instruction whose immediate depends on instruction addresses, which are only known after layout.
+    Deferred {
+        /// The instruction with an immediate of zero.
+        word: Word,
+        value: Expression<'i>,
+        part: ImmediatePart,
+        span: Span<'i>,
     },
 }
 
+/// Which part of a value is encoded as the immediate of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImmediatePart {
+    /// The whole value, which must fit into 16 bits.
+    Full,
+    /// The low 16 bits of a 32-bit value.
+    Low,
+    /// The high 16 bits of a 32-bit value.
+    High,
+}
+
+/// Sets the immediate of `word` to the given part of `value`.
+fn encode_immediate(word: Word, value: i64, part: ImmediatePart, span: Span) -> Result<Word> {
+    let bits = match part {
+        ImmediatePart::Full => 16,
+        ImmediatePart::Low | ImmediatePart::High => 32,
+    };
+    // numbers are accepted both as signed and as unsigned values
+    if value < -(1 << (bits - 1)) || value >= 1 << bits {
+        return Err(new_parser_error(
+            span,
+            format!("Value {} does not fit into {} bits", value, bits),
+        ));
+    }
+    let immediate = match part {
+        ImmediatePart::Full | ImmediatePart::Low => value as u16,
+        ImmediatePart::High => (value >> 16) as u16,
+    };
+    Ok(word | (Into::<u32>::into(immediate) << IMMEDIATE_OFFSET))
+}
+
+/// The value of an `immediate` pair, unless it depends on instruction addresses.
+enum ImmediateValue<'i> {
+    Known(i64),
+    Deferred(Expression<'i>),
+}
+
+fn process_immediate<'i, T>(
+    pair: Pair<'i, Rule>,
+    scope: &LabelScope<'i>,
+    symbols: &KnownSymbols<'_, 'i>,
+    linter: &mut Linter,
+) -> Result<ImmediateValue<'i>>
+where
+    T: GetUnsigned + Num<FromStrRadixErr = ParseIntError> + NumCastTrunc + Into<i64>,
+    <T as GetUnsigned>::Unsigned: Num<FromStrRadixErr = ParseIntError> + ToPrimitiveTrunc,
+{
+    debug_assert_matches!(pair.as_rule(), Rule::immediate);
+    let inner = pair.into_inner().next().unwrap();
+    if inner.as_rule() == Rule::int {
+        return Ok(ImmediateValue::Known(process_int::<T>(inner)?.into()));
+    }
+
+    let value = symbols.fold(inner, scope, linter)?;
+    let mut resolved = true;
+    value.for_each_label(&mut |_| resolved = false);
+    Ok(if resolved {
+        ImmediateValue::Known(value.evaluate(&mut |_| unreachable!())?)
+    } else {
+        ImmediateValue::Deferred(value)
+    })
+}
+
+/// Pushes `word` with the given part of `value` as its immediate.
+fn push_immediate<'i>(
+    instr: &mut InstrVec<'i>,
+    word: Word,
+    value: &ImmediateValue<'i>,
+    part: ImmediatePart,
+    span: Span<'i>,
+) -> Result<()> {
+    instr.push(match value {
+        ImmediateValue::Known(value) => {
+            ParsedInstruction::Complete(encode_immediate(word, *value, part, span)?)
+        }
+        ImmediateValue::Deferred(value) => ParsedInstruction::Deferred {
+            word,
+            value: value.clone(),
+            part,
+            span,
+        },
+    });
+    Ok(())
+}
+
 fn process_enum_inner<'i, T: FromStr<Err = ParseEnumError>>(pair: &Pair<'i, Rule>) -> Result<T> {
     pair.as_str()
         .to_uppercase()
@@ -47,11 +140,46 @@ fn process_enum_inner<'i, T: FromStr<Err = ParseEnumError>>(pair: &Pair<'i, Rule
         .map_err(|err| new_parser_error(pair.as_span(), format!("{}", err)))
 }
 
-fn process_enum<T: FromStr<Err = ParseEnumError>>(pair: Pair<Rule>) -> Result<T> {
-    process_enum_inner(&pair.into_inner().next().unwrap())
+/// Returns the number of the vector register given as a `vector_register` pair.
+fn process_vector_register(pair: Pair<Rule>) -> Result<u32> {
+    let id: VectorRegisterId = process_enum_inner(&pair.into_inner().next().unwrap())?;
+    Ok(enum_to_u32(id))
+}
+
+fn process_lane(pair: Pair<Rule>) -> Result<u32> {
+    let span = pair.as_span();
+    let lane = process_uint::<u32>(pair)?;
+    if lane as usize >= VECTOR_LANES {
+        return Err(new_parser_error(
+            span,
+            format!("Vector registers only have lanes 0 to {}", VECTOR_LANES - 1),
+        ));
+    }
+    Ok(lane)
+}
+
+/// Parses the index of a byte (`EXTB`, `INSB`) or half (`EXTH`, `INSH`) within a word.
+fn process_index(funct: AluFunct, pair: Pair<Rule>) -> Result<u32> {
+    let span = pair.as_span();
+    let index = process_uint::<u32>(pair)?;
+    let (count, unit) = match funct {
+        AluFunct::EXTB | AluFunct::INSB => (WORD_BYTES / BYTE_BYTES, "bytes"),
+        _ => (WORD_BYTES / HALF_BYTES, "halves"),
+    };
+    if index >= count {
+        return Err(new_parser_error(
+            span,
+            format!("Words only have {} 0 to {}", unit, count - 1),
+        ));
+    }
+    Ok(index)
 }
 
-fn process_jump_target<T>(pair: Pair<Rule>) -> Result<JumpTarget<T>>
+fn process_jump_target<'i, T>(
+    pair: Pair<'i, Rule>,
+    scope: &LabelScope<'i>,
+    linter: &mut Linter,
+) -> Result<JumpTarget<'i, T>>
 where
     T: GetUnsigned + Num<FromStrRadixErr = ParseIntError> + NumCastTrunc + Copy,
     <T as GetUnsigned>::Unsigned: Num<FromStrRadixErr = ParseIntError> + ToPrimitiveTrunc,
@@ -60,17 +188,22 @@ where
     let rule = inner.as_rule();
     let target = match rule {
         Rule::int => JumpTarget::Address(process_int(inner)?),
-        Rule::identifier => JumpTarget::Label(inner.as_span()),
-        _ => unreachable!(),
+        _ => {
+            let label = scope.reference(inner)?;
+            linter.use_label(Section::Instructions, label.key());
+            JumpTarget::Label(label)
+        }
     };
     Ok(target)
 }
 
-fn process_instruction<'i>(
+pub fn process_instruction<'i>(
     pair: Pair<'i, Rule>,
     instr: &mut InstrVec<'i>,
-    data_labels: &LabelMap<'i>,
-    data_offset: u32,
+    scope: &LabelScope<'i>,
+    aliases: &RegisterAliases,
+    symbols: &KnownSymbols<'_, 'i>,
+    linter: &mut Linter,
 ) -> Result<usize> {
     let span = pair.as_span();
     let inner = pair.into_inner().next().unwrap();
@@ -82,35 +215,105 @@ fn process_instruction<'i>(
     match rule {
         Rule::instruction_alu => {
             let alu_funct = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
-            let rs2 = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let rs2 = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_alu_instruction(
                 alu_funct, rd, rs1, rs2,
             )));
         }
         Rule::instruction_flop => {
             let flop_funct = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
-            let rs2 = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let rs2 = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_flop_instruction(
                 flop_funct, rd, rs1, rs2,
             )));
         }
+        Rule::instruction_funnel => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let rs2 = aliases.resolve(pairs.next().unwrap())?;
+            let shamt_pair = pairs.next().unwrap();
+            let shamt_span = shamt_pair.as_span();
+            let shamt = process_uint::<u32>(shamt_pair)?;
+            if shamt >= WORD_WIDTH {
+                let message = format!(
+                    "Shift amount {} is truncated to {}",
+                    shamt,
+                    shamt % WORD_WIDTH
+                );
+                linter.report(WarningKind::Truncation, shamt_span, &message)?;
+            }
+            instr.push(ParsedInstruction::Complete(make_funnel_instruction(
+                funct, rd, rs1, rs2, shamt,
+            )));
+        }
+        Rule::instruction_bswap => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_alu_instruction(
+                funct,
+                rd,
+                rs1,
+                RegisterId::ZERO,
+            )));
+        }
+        Rule::instruction_ext => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let index = process_index(funct, pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_index_instruction(
+                funct,
+                rd,
+                rs1,
+                RegisterId::ZERO,
+                index,
+            )));
+        }
+        Rule::instruction_ins => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let rs2 = aliases.resolve(pairs.next().unwrap())?;
+            let index = process_index(funct, pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_index_instruction(
+                funct, rd, rs1, rs2, index,
+            )));
+        }
         Rule::instruction_i => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
-            let immediate = process_int(pairs.next().unwrap())?;
-            instr.push(ParsedInstruction::Complete(make_i_instruction(
-                opcode, rd, rs1, immediate,
-            )));
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let immediate_pair = pairs.next().unwrap();
+            let immediate_span = immediate_pair.as_span();
+            let immediate = process_immediate::<Immediate>(immediate_pair, scope, symbols, linter)?;
+            if let ImmediateValue::Known(value) = immediate {
+                if is_shift(opcode) && !(0..WORD_WIDTH.into()).contains(&value) {
+                    let message = format!(
+                        "Shift amount {} is truncated to {}",
+                        value,
+                        value as u16 as u32 % WORD_WIDTH
+                    );
+                    linter.report(WarningKind::Truncation, immediate_span, &message)?;
+                }
+            }
+            push_immediate(
+                instr,
+                make_i_instruction(opcode, rd, rs1, 0),
+                &immediate,
+                ImmediatePart::Full,
+                immediate_span,
+            )?;
         }
         Rule::instruction_iu => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
             let immediate = process_uint::<u16>(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 opcode,
@@ -121,26 +324,29 @@ fn process_instruction<'i>(
         }
         Rule::instruction_ds => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 opcode, rd, rs1, 0i16,
             )));
         }
         Rule::instruction_li => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let immediate = process_int(pairs.next().unwrap())?;
-            instr.push(ParsedInstruction::Complete(make_i_instruction(
-                opcode,
-                rd,
-                RegisterId::ZERO,
-                immediate,
-            )));
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let immediate_pair = pairs.next().unwrap();
+            let immediate_span = immediate_pair.as_span();
+            let immediate = process_immediate::<Immediate>(immediate_pair, scope, symbols, linter)?;
+            push_immediate(
+                instr,
+                make_i_instruction(opcode, rd, RegisterId::ZERO, 0),
+                &immediate,
+                ImmediatePart::Full,
+                immediate_span,
+            )?;
         }
         Rule::instruction_si => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
             let immediate = process_uint::<u16>(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 opcode,
@@ -160,17 +366,30 @@ fn process_instruction<'i>(
         }
         Rule::instruction_br => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
-            let target = process_jump_target(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let target = process_jump_target(pairs.next().unwrap(), scope, linter)?;
+            instr.push(ParsedInstruction::Branch {
+                opcode,
+                rd: RegisterId::ZERO,
+                rs1,
+                target,
+            });
+        }
+        Rule::instruction_bc => {
+            let opcode = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let target = process_jump_target(pairs.next().unwrap(), scope, linter)?;
             instr.push(ParsedInstruction::Branch {
                 opcode,
+                rd,
                 rs1,
                 target,
             });
         }
         Rule::instruction_jr => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 opcode,
                 RegisterId::ZERO,
@@ -178,103 +397,203 @@ fn process_instruction<'i>(
                 0,
             )));
         }
+        Rule::instruction_csrr | Rule::instruction_csr => {
+            let opcode = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = if rule == Rule::instruction_csr {
+                aliases.resolve(pairs.next().unwrap())?
+            } else {
+                RegisterId::ZERO
+            };
+            let number_pair = pairs.next().unwrap();
+            let number_span = number_pair.as_span();
+            let number = process_immediate::<Immediate>(number_pair, scope, symbols, linter)?;
+            push_immediate(
+                instr,
+                make_i_instruction(opcode, rd, rs1, 0),
+                &number,
+                ImmediatePart::Full,
+                number_span,
+            )?;
+        }
+        Rule::instruction_vec => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let qd = process_vector_register(pairs.next().unwrap())?;
+            let qs1 = process_vector_register(pairs.next().unwrap())?;
+            let qs2 = process_vector_register(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_vec_instruction(
+                funct, qd, qs1, qs2,
+            )));
+        }
+        Rule::instruction_vsplat | Rule::instruction_vls => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let qd = process_vector_register(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_vec_instruction(
+                funct,
+                qd,
+                enum_to_u32(rs1),
+                0,
+            )));
+        }
+        Rule::instruction_vins => {
+            let qd = process_vector_register(pairs.nth(1).unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            let lane = process_lane(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_vec_instruction(
+                VecFunct::VINS,
+                qd,
+                enum_to_u32(rs1),
+                lane,
+            )));
+        }
+        Rule::instruction_vext => {
+            let rd = aliases.resolve(pairs.nth(1).unwrap())?;
+            let qs1 = process_vector_register(pairs.next().unwrap())?;
+            let lane = process_lane(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_vec_instruction(
+                VecFunct::VEXT,
+                enum_to_u32(rd),
+                qs1,
+                lane,
+            )));
+        }
+        Rule::instruction_swapb => {
+            instr.push(ParsedInstruction::Complete(make_bank_instruction(
+                BankFunct::SWAPB,
+                RegisterId::ZERO,
+                RegisterId::ZERO,
+            )));
+        }
+        Rule::instruction_shadow => {
+            let funct = process_enum_inner(&pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let rs1 = aliases.resolve(pairs.next().unwrap())?;
+            instr.push(ParsedInstruction::Complete(make_bank_instruction(
+                funct, rd, rs1,
+            )));
+        }
         Rule::instruction_ls => {
             let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let rd = process_enum(pairs.next().unwrap())?;
-            let immediate = process_int(pairs.next().unwrap())?;
-            let rs1 = process_enum(pairs.next().unwrap())?;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let mut next = pairs.next().unwrap();
+            let immediate = if next.as_rule() == Rule::int {
+                let immediate = process_int(next)?;
+                next = pairs.next().unwrap();
+                immediate
+            } else {
+                0
+            };
+            let rs1 = aliases.resolve(next)?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 opcode, rd, rs1, immediate,
             )));
         }
-        Rule::instruction_j => {
-            let opcode = process_enum_inner(&pairs.next().unwrap())?;
-            let target = process_jump_target(pairs.next().unwrap())?;
+        Rule::instruction_j | Rule::instruction_call => {
+            let opcode = if rule == Rule::instruction_call {
+                Opcode::JL
+            } else {
+                process_enum_inner(&pairs.next().unwrap())?
+            };
+            let target_pair = pairs.next().unwrap();
+            let target_span = target_pair.as_span();
+            let target = process_jump_target(target_pair, scope, linter)?;
+            if let JumpTarget::Address(address) = target {
+                if !fits_address(address) {
+                    let message = format!(
+                        "Jump offset {} is truncated to {} bits",
+                        address, ADDRESS_WIDTH
+                    );
+                    linter.report(WarningKind::Truncation, target_span, &message)?;
+                }
+            }
             instr.push(ParsedInstruction::Jump { opcode, target });
         }
         Rule::instruction_push => {
-            let register = process_enum(pairs.next().unwrap())?;
+            let register = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 Opcode::SW,
                 register,
-                RegisterId::SP,
+                STACK_POINTER,
                 -4i16,
             )));
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 Opcode::SUBI,
-                RegisterId::SP,
-                RegisterId::SP,
+                STACK_POINTER,
+                STACK_POINTER,
                 4i16,
             )));
         }
         Rule::instruction_pop => {
-            let register = process_enum(pairs.next().unwrap())?;
+            let register = aliases.resolve(pairs.next().unwrap())?;
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 Opcode::LW,
                 register,
-                RegisterId::SP,
+                STACK_POINTER,
                 0i16,
             )));
             instr.push(ParsedInstruction::Complete(make_i_instruction(
                 Opcode::ADDI,
-                RegisterId::SP,
-                RegisterId::SP,
+                STACK_POINTER,
+                STACK_POINTER,
                 4i16,
             )));
         }
         Rule::instruction_lwi => {
-            let register = process_enum(pairs.next().unwrap())?;
-            let value: i32 = process_int(pairs.next().unwrap())?;
-            instr.push(ParsedInstruction::Complete(make_i_instruction(
-                Opcode::SLO,
-                register,
-                RegisterId::ZERO,
-                value as i16,
-            )));
-            instr.push(ParsedInstruction::Complete(make_i_instruction(
-                Opcode::SHI,
-                register,
-                RegisterId::ZERO,
-                (value >> 16) as i16,
-            )));
+            let register = aliases.resolve(pairs.next().unwrap())?;
+            let value_pair = pairs.next().unwrap();
+            let value_span = value_pair.as_span();
+            let value = process_immediate::<i32>(value_pair, scope, symbols, linter)?;
+            push_load_word(instr, register, &value, value_span)?;
         }
         Rule::instruction_lda => {
-            let rd = process_enum(pairs.next().unwrap())?;
-            let label_span = pairs.next().unwrap().as_span();
-            let label = label_span.as_str();
-            let address = data_labels.get(label).ok_or_else(|| {
-                new_parser_error(label_span, "Data label was not found".to_owned())
-            })?;
-            let offset_address = *address + data_offset;
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let address_pair = pairs.next().unwrap();
+            let address_span = address_pair.as_span();
+            let address = symbols.fold(address_pair, scope, linter)?;
 
+            // all data labels are known, so any label left is not a data label
+            let mut unknown = None;
+            address.for_each_label(&mut |label| {
+                unknown.get_or_insert(label.span());
+            });
+            if let Some(span) = unknown {
+                return Err(new_parser_error(
+                    span,
+                    "Data label was not found".to_owned(),
+                ));
+            }
+            if address.relocation(address_span)? != Some(Section::Data) {
+                return Err(new_parser_error(
+                    address_span,
+                    "LDA expects a data address".to_owned(),
+                ));
+            }
+
+            let value = address.evaluate(&mut |_| unreachable!())?;
+            push_load_word(instr, rd, &ImmediateValue::Known(value), address_span)?;
+        }
+        Rule::instruction_lia => {
+            let rd = aliases.resolve(pairs.next().unwrap())?;
+            let address_pair = pairs.next().unwrap();
+            let address_span = address_pair.as_span();
+            let address = symbols.fold(address_pair, scope, linter)?;
+            if address.relocation(address_span)? != Some(Section::Instructions) {
+                return Err(new_parser_error(
+                    address_span,
+                    "LIA expects an instruction address".to_owned(),
+                ));
+            }
+            push_load_word(instr, rd, &ImmediateValue::Deferred(address), address_span)?;
+        }
+        Rule::instruction_ret => {
             instr.push(ParsedInstruction::Complete(make_i_instruction(
-                Opcode::SLO,
-                rd,
-                RegisterId::ZERO,
-                offset_address as i16,
-            )));
-            instr.push(ParsedInstruction::Complete(make_i_instruction(
-                Opcode::SHI,
-                rd,
+                Opcode::JR,
                 RegisterId::ZERO,
-                (offset_address >> 16) as i16,
+                RETURN_ADDRESS,
+                0,
             )));
         }
-        Rule::instruction_lia => {
-            let rd = process_enum(pairs.next().unwrap())?;
-            let label = pairs.next().unwrap();
-
-            instr.push(ParsedInstruction::LoadInstructionAddress {
-                label: label.as_span(),
-                rd,
-                upper: false,
-            });
-            instr.push(ParsedInstruction::LoadInstructionAddress {
-                label: label.as_span(),
-                rd,
-                upper: true,
-            });
-        }
         _ => unreachable!(),
     }
 
@@ -291,34 +610,159 @@ fn process_instruction<'i>(
     }
 }
 
+/// Pushes the `SLO` + `SHI` pair which loads the 32-bit `value` into `rd`.
+fn push_load_word<'i>(
+    instr: &mut InstrVec<'i>,
+    rd: RegisterId,
+    value: &ImmediateValue<'i>,
+    span: Span<'i>,
+) -> Result<()> {
+    let low = make_i_instruction(Opcode::SLO, rd, RegisterId::ZERO, 0);
+    let high = make_i_instruction(Opcode::SHI, rd, RegisterId::ZERO, 0);
+    push_immediate(instr, low, value, ImmediatePart::Low, span)?;
+    push_immediate(instr, high, value, ImmediatePart::High, span)
+}
+
+fn is_shift(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::SLLI | Opcode::SRLI | Opcode::SRAI)
+}
+
+fn fits_address(address: Address) -> bool {
+    let limit = 1 << (ADDRESS_WIDTH - 1);
+    -limit <= address && address < limit
+}
+
+/// Returns whether execution never continues with the instruction after `instr`.
+fn ends_control_flow(instr: &ParsedInstruction) -> bool {
+    match instr {
+        ParsedInstruction::Jump { opcode, .. } => *opcode == Opcode::JMP,
+        ParsedInstruction::Complete(word) => {
+            let opcode = (word & OPCODE_MASK) >> OPCODE_OFFSET;
+            opcode == enum_to_u32(Opcode::HALT)
+                || opcode == enum_to_u32(Opcode::JR)
+                || opcode == enum_to_u32(Opcode::IRET)
+        }
+        _ => false,
+    }
+}
+
+/// The combined instruction sections of all source files.
+#[derive(Default)]
+pub struct InstructionOutput<'i> {
+    pub instructions: InstrVec<'i>,
+    pub labels: LabelMap,
+    pub source_map: SourceMap,
+    pub assertions: Vec<Assertion<'i>>,
+    /// The label given by `.entry` and the number of the file it was defined in.
+    pub entry: Option<(u32, LabelRef<'i>)>,
+}
+
+/// Appends the instruction section of source file number `file` to `output`.
 pub fn process_instructions<'i>(
     pair: Pair<'i, Rule>,
-    data_labels: &LabelMap<'i>,
-    data_offset: u32,
-) -> Result<(InstrVec<'i>, LabelMap<'i>, SourceMap)> {
+    file: u32,
+    output: &mut InstructionOutput<'i>,
+    constants: &mut Constants<'i>,
+    data_labels: &LabelMap,
+    options: &Options,
+    linter: &mut Linter<'i, '_>,
+) -> Result<()> {
     debug_assert_matches!(pair.as_rule(), Rule::instructions);
 
-    let mut instructions = Vec::new();
-    let mut labels = HashMap::new();
-    let mut source_map = Vec::new();
+    let InstructionOutput {
+        instructions,
+        labels,
+        source_map,
+        assertions,
+        entry,
+    } = output;
+    let mut scope = LabelScope::for_file(file);
+    let mut reachable = true;
+    let mut line_counter = LineCounter::new(pair.as_span());
+    let data_offset = options.data_offset;
+    let mut aliases = RegisterAliases::new(options.registers.clone());
 
     for labeled_instruction in pair.into_inner() {
+        match labeled_instruction.as_rule() {
+            Rule::alias => {
+                aliases.define(labeled_instruction)?;
+                continue;
+            }
+            Rule::assert => {
+                assertions.push(process_assertion(
+                    labeled_instruction,
+                    Section::Instructions,
+                    file,
+                    &scope,
+                    constants,
+                )?);
+                continue;
+            }
+            Rule::equ => {
+                define_constant(
+                    labeled_instruction,
+                    &scope,
+                    constants,
+                    data_labels,
+                    data_offset,
+                    linter,
+                )?;
+                continue;
+            }
+            Rule::entry => {
+                let span = labeled_instruction.as_span();
+                if entry.is_some() {
+                    return Err(new_parser_error(
+                        span,
+                        "Entry point is already defined".to_owned(),
+                    ));
+                }
+                let label = scope.reference(labeled_instruction.into_inner().next().unwrap())?;
+                linter.use_label(Section::Instructions, label.key());
+                *entry = Some((file, label));
+                continue;
+            }
+            _ => {}
+        }
+
         let span = labeled_instruction.as_span();
-        let start_line = span.start_pos().line_col().0 as u32;
-        let end_line = span.end_pos().line_col().0 as u32;
-        let line_count = end_line - start_line + 1;
+
+        let labeled = labeled_instruction
+            .clone()
+            .into_inner()
+            .next()
+            .unwrap()
+            .as_rule()
+            == Rule::label;
+        if !reachable && !labeled {
+            linter.report(
+                WarningKind::UnreachableCode,
+                span,
+                "Instruction is unreachable",
+            )?;
+        }
+
+        let (start_line, line_count) = line_counter.lines(span);
         let source_map_item = SourceMapItem {
+            file,
             start_line,
             line_count,
         };
 
         process_labeled_element(
             labeled_instruction,
-            &mut labels,
+            labels,
+            &mut scope,
             Rule::instruction,
             instructions.len() as u32,
-            |p| {
-                let count = process_instruction(p, &mut instructions, &data_labels, data_offset)?;
+            |p, scope| {
+                let symbols = KnownSymbols {
+                    constants,
+                    data_labels,
+                    data_offset,
+                };
+                let count =
+                    process_instruction(p, instructions, scope, &aliases, &symbols, linter)?;
                 for _ in 0..count {
                     source_map.push(source_map_item);
                 }
@@ -326,9 +770,14 @@ pub fn process_instructions<'i>(
                 Ok(())
             },
         )?;
+
+        // only the first instruction of an unreachable block is reported
+        reachable = !instructions.last().is_some_and(ends_control_flow);
     }
 
-    Ok((instructions, labels, source_map))
+    linter.define_labels(Section::Instructions, scope.into_definitions());
+
+    Ok(())
 }
 
 fn resolve_jump_target<T: NumCast + Num + Copy>(
@@ -339,20 +788,139 @@ fn resolve_jump_target<T: NumCast + Num + Copy>(
     match target {
         JumpTarget::Address(address) => Ok(*address),
         JumpTarget::Label(label) => {
-            let absolute =
-                Into::<i64>::into(*labels.get(label.as_str()).ok_or_else(|| {
-                    new_parser_error(label.clone(), "Label not found".to_owned())
-                })?);
+            let absolute = Into::<i64>::into(
+                *labels
+                    .get(label.key())
+                    .ok_or_else(|| new_parser_error(label.span(), "Label not found".to_owned()))?,
+            );
 
             let relative = absolute - Into::<i64>::into(current_instr);
             let byte_dist = relative * Into::<i64>::into(WORD_BYTES);
-            num::NumCast::from(byte_dist)
-                .ok_or_else(|| new_parser_error(label.clone(), "Jump distance too far".to_owned()))
+            num::NumCast::from(byte_dist).ok_or_else(|| {
+                new_parser_error(
+                    label.span(),
+                    format!("Jump distance of {} bytes is out of range", byte_dist),
+                )
+            })
+        }
+    }
+}
+
+/// Position of each instruction after all instructions flagged in `relaxed` have been expanded.
+/// Contains one additional element for the position after the last instruction.
+fn relaxed_layout(relaxed: &[bool]) -> Vec<u32> {
+    let mut positions = Vec::with_capacity(relaxed.len() + 1);
+    let mut position = 0;
+    for r in relaxed.iter() {
+        positions.push(position);
+        position += if *r { 2 } else { 1 };
+    }
+    positions.push(position);
+    positions
+}
+
+fn invert_branch(opcode: Opcode) -> Opcode {
+    match opcode {
+        Opcode::BEZ => Opcode::BNZ,
+        Opcode::BNZ => Opcode::BEZ,
+        Opcode::BEQ => Opcode::BNE,
+        Opcode::BNE => Opcode::BEQ,
+        Opcode::BLT => Opcode::BGE,
+        Opcode::BGE => Opcode::BLT,
+        Opcode::BLTU => Opcode::BGEU,
+        Opcode::BGEU => Opcode::BLTU,
+        _ => unreachable!(),
+    }
+}
+
+/// Rewrites every branch whose target label is out of range of the immediate value into an inverted
+/// branch which skips over a `JMP` to the original target.
+///
+/// Since expanding a branch moves all following instructions, this is repeated until no more
+/// branches need to be rewritten. `labels` and `source_map` are updated to the new layout.
+pub fn relax_branches<'i>(
+    instructions: InstrVec<'i>,
+    labels: &mut LabelMap,
+    source_map: &mut SourceMap,
+) -> InstrVec<'i> {
+    let mut relaxed = vec![false; instructions.len()];
+
+    loop {
+        let positions = relaxed_layout(&relaxed);
+        let mut changed = false;
+
+        for (i, instr) in instructions.iter().enumerate() {
+            if let ParsedInstruction::Branch {
+                target: JumpTarget::Label(label),
+                ..
+            } = instr
+            {
+                // unknown labels are reported when the instructions are assembled
+                if let Some(target) = labels.get(label.key()) {
+                    let relative = Into::<i64>::into(positions[*target as usize])
+                        - Into::<i64>::into(positions[i]);
+                    let byte_dist = relative * Into::<i64>::into(WORD_BYTES);
+                    let in_range = Into::<i64>::into(Immediate::MIN) <= byte_dist
+                        && byte_dist <= Into::<i64>::into(Immediate::MAX);
+                    if !relaxed[i] && !in_range {
+                        relaxed[i] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    if !relaxed.contains(&true) {
+        return instructions;
+    }
+
+    let positions = relaxed_layout(&relaxed);
+    for position in labels.values_mut() {
+        *position = positions[*position as usize];
+    }
+
+    let mut result = Vec::with_capacity(positions[instructions.len()] as usize);
+    let mut new_source_map = Vec::with_capacity(result.capacity());
+
+    for ((instr, item), r) in instructions.into_iter().zip(source_map.iter()).zip(relaxed) {
+        new_source_map.push(*item);
+        match instr {
+            ParsedInstruction::Branch {
+                opcode,
+                rd,
+                rs1,
+                target,
+            } if r => {
+                let target = match target {
+                    JumpTarget::Label(label) => JumpTarget::Label(label),
+                    JumpTarget::Address(_) => unreachable!(),
+                };
+                result.push(ParsedInstruction::Branch {
+                    opcode: invert_branch(opcode),
+                    rd,
+                    rs1,
+                    target: JumpTarget::Address(2 * WORD_BYTES as Immediate),
+                });
+                result.push(ParsedInstruction::Jump {
+                    opcode: Opcode::JMP,
+                    target,
+                });
+                new_source_map.push(*item);
+            }
+            instr => result.push(instr),
         }
     }
+
+    *source_map = new_source_map;
+    result
 }
 
-fn finalize_instruction(
+pub fn finalize_instruction(
     labels: &LabelMap,
     instr: &ParsedInstruction,
     current_instr: u32,
@@ -361,11 +929,12 @@ fn finalize_instruction(
         ParsedInstruction::Complete(word) => word,
         ParsedInstruction::Branch {
             ref opcode,
+            ref rd,
             ref rs1,
             ref target,
         } => make_i_instruction(
             *opcode,
-            RegisterId::ZERO,
+            *rd,
             *rs1,
             resolve_jump_target(labels, &target, current_instr)?,
         ),
@@ -376,31 +945,37 @@ fn finalize_instruction(
             *opcode,
             resolve_jump_target(labels, &target, current_instr)?,
         ),
-        ParsedInstruction::LoadInstructionAddress {
-            ref label,
-            ref rd,
-            ref upper,
+        ParsedInstruction::Deferred {
+            word,
+            ref value,
+            part,
+            span,
         } => {
-            let address = *labels
-                .get(label.as_str())
-                .ok_or_else(|| new_parser_error(label.clone(), "Label not found".to_owned()))?
-                as u32
-                * WORD_BYTES;
-            if *upper {
-                make_i_instruction(Opcode::SHI, *rd, RegisterId::ZERO, (address >> 16) as i16)
-            } else {
-                make_i_instruction(Opcode::SLO, *rd, RegisterId::ZERO, address as i16)
-            }
+            let value = value.evaluate(&mut |label| {
+                let index = labels
+                    .get(label.key())
+                    .ok_or_else(|| new_parser_error(label.span(), "Label not found".to_owned()))?;
+                Ok(Into::<i64>::into(*index) * Into::<i64>::into(WORD_BYTES))
+            })?;
+            encode_immediate(word, value, part, span)?
         }
     })
 }
 
-pub fn assemble_instructions(instr: &[ParsedInstruction], labels: &LabelMap) -> Result<Vec<u8>> {
+/// Encodes all instructions. Errors are attributed to the path of the file the instruction comes from,
+/// as given by `source_map` and `paths`.
+pub fn assemble_instructions(
+    instr: &[ParsedInstruction],
+    labels: &LabelMap,
+    source_map: &SourceMap,
+    paths: &[Option<&str>],
+) -> Result<Vec<u8>> {
     let result_size = instr.len() * WORD_BYTES as usize;
     let mut result = vec![0; result_size];
 
     for (i, pi) in instr.iter().enumerate() {
-        let instr = finalize_instruction(labels, pi, i as u32)?;
+        let instr = finalize_instruction(labels, pi, i as u32)
+            .map_err(|err| with_path(err, paths[source_map[i].file as usize]))?;
         let start = i * WORD_BYTES as usize;
         let end = start + WORD_BYTES as usize;
         Endian::write_u32(&mut result[start..end], instr);
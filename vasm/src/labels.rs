@@ -2,27 +2,167 @@ use crate::*;
 use pest::iterators::Pair;
 use std::collections::HashMap;
 
-pub type LabelMap<'i> = HashMap<&'i str, u32>;
+pub type LabelMap = HashMap<String, u32>;
+
+/// Reference to a label, as it appears in the source.
+///
+/// Global labels are looked up by their name, while local and numeric labels are qualified
+/// with the scope they were referenced from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum LabelRef<'i> {
+    Global(Span<'i>),
+    Scoped(String, Span<'i>),
+}
+
+impl<'i> LabelRef<'i> {
+    pub fn key(&self) -> &str {
+        match self {
+            LabelRef::Global(span) => span.as_str(),
+            LabelRef::Scoped(key, _) => key,
+        }
+    }
+
+    pub fn span(&self) -> Span<'i> {
+        match self {
+            LabelRef::Global(span) => *span,
+            LabelRef::Scoped(_, span) => *span,
+        }
+    }
+}
+
+/// Keeps track of the current label scope within a section.
+///
+/// Local labels (`.name`) are scoped to the most recently defined global label.
+/// Numeric labels (`1:`) may be defined multiple times and are referenced as the
+/// nearest definition before (`1b`) or after (`1f`) the reference.
+#[derive(Default)]
+pub struct LabelScope<'i> {
+    file: u32,
+    global: &'i str,
+    numeric: HashMap<u32, u32>,
+    definitions: Vec<(String, Span<'i>)>,
+}
+
+impl<'i> LabelScope<'i> {
+    /// Creates the scope for a section of source file number `file`.
+    /// Numeric labels are only visible within the file they are defined in.
+    pub fn for_file(file: u32) -> Self {
+        LabelScope {
+            file,
+            ..LabelScope::default()
+        }
+    }
+
+    /// Creates a scope which continues after the state returned by [`into_state`](#method.into_state).
+    pub fn resume(file: u32, global: &'i str, numeric: HashMap<u32, u32>) -> Self {
+        LabelScope {
+            file,
+            global,
+            numeric,
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Returns the current global label and the number of definitions of each numeric label,
+    /// which is everything needed to continue the scope later.
+    pub fn into_state(self) -> (String, HashMap<u32, u32>) {
+        (self.global.to_owned(), self.numeric)
+    }
+
+    fn local_key(&self, name: &str) -> String {
+        format!("{}{}", self.global, name)
+    }
+
+    fn numeric_key(&self, number: u32, instance: u32) -> String {
+        format!("{}:{}:{}", self.file, number, instance)
+    }
+
+    fn parse_number(pair: &Pair<'i, Rule>, digits: &str) -> Result<u32> {
+        digits
+            .parse()
+            .map_err(|_| new_parser_error(pair.as_span(), "Numeric label is too big".to_owned()))
+    }
+
+    fn define(&mut self, pair: Pair<'i, Rule>) -> Result<String> {
+        let name = pair.as_str();
+        let key = match pair.as_rule() {
+            Rule::identifier => {
+                self.global = name;
+                name.to_owned()
+            }
+            Rule::local_identifier => self.local_key(name),
+            Rule::numeric_identifier => {
+                let number = Self::parse_number(&pair, name)?;
+                let instance = self.numeric.get(&number).cloned().unwrap_or(0);
+                self.numeric.insert(number, instance + 1);
+                self.numeric_key(number, instance)
+            }
+            _ => unreachable!(),
+        };
+        self.definitions.push((key.clone(), pair.as_span()));
+        Ok(key)
+    }
+
+    /// Returns the keys of all labels defined in this scope, together with the span of their definition.
+    pub fn into_definitions(self) -> Vec<(String, Span<'i>)> {
+        self.definitions
+    }
+
+    /// Qualifies a label reference given as `identifier`, `local_identifier` or `numeric_reference`.
+    pub fn reference(&self, pair: Pair<'i, Rule>) -> Result<LabelRef<'i>> {
+        let span = pair.as_span();
+        match pair.as_rule() {
+            Rule::identifier => Ok(LabelRef::Global(span)),
+            Rule::local_identifier => Ok(LabelRef::Scoped(self.local_key(span.as_str()), span)),
+            Rule::numeric_reference => {
+                let text = span.as_str();
+                let (digits, direction) = text.split_at(text.len() - 1);
+                let number = Self::parse_number(&pair, digits)?;
+                let defined = self.numeric.get(&number).cloned().unwrap_or(0);
+                let instance = if direction == "f" {
+                    defined
+                } else if defined > 0 {
+                    defined - 1
+                } else {
+                    return Err(new_parser_error(
+                        span,
+                        "Numeric label has no preceding definition".to_owned(),
+                    ));
+                };
+                Ok(LabelRef::Scoped(self.numeric_key(number, instance), span))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
 
 pub fn process_labeled_element<'i, F>(
     pair: Pair<'i, Rule>,
-    labels: &mut LabelMap<'i>,
+    labels: &mut LabelMap,
+    scope: &mut LabelScope<'i>,
     rule: Rule,
     len: u32,
     op: F,
 ) -> Result<()>
 where
-    F: FnOnce(Pair<'i, Rule>) -> Result<()>,
+    F: FnOnce(Pair<'i, Rule>, &LabelScope<'i>) -> Result<()>,
 {
     let mut pairs = pair.into_inner();
     let first = pairs.next().unwrap();
     let r = first.as_rule();
     if r == Rule::label {
-        let label_str = first.into_inner().next().unwrap().as_span().as_str();
-        labels.insert(label_str, len);
-        op(pairs.next().unwrap())?;
+        let label = first.into_inner().next().unwrap();
+        let span = label.as_span();
+        let key = scope.define(label)?;
+        if labels.insert(key, len).is_some() {
+            return Err(new_parser_error(
+                span,
+                "Label is already defined".to_owned(),
+            ));
+        }
+        op(pairs.next().unwrap(), scope)?;
     } else if r == rule {
-        op(first)?;
+        op(first, scope)?;
     } else {
         unreachable!();
     }
@@ -1,10 +1,36 @@
 //! Assembler for the [vcpu](../vcpu/index.html) virtual processor.
 //!
-//! The main functions provided by this crate are [`assemble`](fn.assemble.html) and [`assemble_addressed`](fn.assemble_addressed.html),
-//! which accept some string input containing a program written in a custom assembly language, and output the assembled executable.
-//! The executable is represented by the [`Executable`](../vex/struct.Executable.html) type from the [vex](../vex/index.html) crate.
-//! Additionally, a [`SourceMap`](type.SourceMap.html) is returned, which associates each assembled instruction in the
-//! executable with the corresponding line(s) in the source.
+//! The main function provided by this crate is [`assemble`](fn.assemble.html), which accepts some string input containing
+//! a program written in a custom assembly language and [`Options`](struct.Options.html) that control how it is assembled.
+//! It returns an [`Assembly`](struct.Assembly.html), which contains the assembled executable, represented by the
+//! [`Executable`](../vex/struct.Executable.html) type from the [vex](../vex/index.html) crate, together with all information
+//! tools need to work with the program:
+//!
+//! * a [`SourceMap`](type.SourceMap.html), which associates each assembled instruction in the executable with the
+//!   corresponding line(s) in the source, and a [`DataMap`](type.DataMap.html) for the data section.
+//!   This is what [`write_listing`](fn.write_listing.html) uses to print a listing of the source alongside the encoded
+//!   instructions and data.
+//! * the [`SymbolTable`](type.SymbolTable.html), which contains the final address of each label
+//!   and can be written as a map file using [`write_map`](fn.write_map.html).
+//! * the [`DebugInfo`](../vex/debug/struct.DebugInfo.html) used by debuggers.
+//! * all warnings, which are also available as [`Diagnostic`](struct.Diagnostic.html)s.
+//!
+//! If assembling fails, `assemble` returns diagnostics for the error and all warnings found before it.
+//! Diagnostics carry plain line and column information, so editors and other tools don't need to depend on [pest].
+//!
+//! The [`Severity`](enum.Severity.html) of each [`WarningKind`](enum.WarningKind.html) is configured in the options.
+//! Warnings are collected in the assembly, unless they are configured to be errors.
+//! [`assemble_sources`](fn.assemble_sources.html) assembles several named [`Source`](struct.Source.html) files
+//! as a single program, with errors and warnings pointing to the file they occurred in.
+//! [`write_depfile`](fn.write_depfile.html) lists these files in a dependency file for build systems like make or ninja.
+//!
+//! An [`Assembler`](struct.Assembler.html) encodes programs one line at a time against the symbols of an existing
+//! assembly, which is what interactive monitors need.
+//! [`disassemble`](fn.disassemble.html) turns encoded instructions back into source, which the `vexdump` tool uses
+//! to print the contents of vexfiles.
+//!
+//! [`assemble_with_options`](fn.assemble_with_options.html), [`assemble_program`](fn.assemble_program.html) and
+//! [`assemble_addressed`](fn.assemble_addressed.html) are shorthands which report errors as [pest] errors.
 //!
 //! Parsing the assembly language is implemented using [pest]. In fact, the main [`Error`](type.Error.html) type used by this
 //! crate is just a type alias of `pest::error::Error`. This means that all functionality provided by [pest]
@@ -45,7 +71,8 @@
 //! ### Register Identifiers
 //!
 //! Many mnemonics require registers to be specified so their id can be encoded in the resulting instruction(s).
-//! To specify a register, prefix the name of the register with `$`. The following registers are available:
+//! To specify a register, write its name, optionally prefixed with `$` (`$T0` and `T0` are equivalent).
+//! Register names are case-insensitive. The following registers are available:
 //!
 //! Register    | Purpose
 //! ------------|---------
@@ -54,11 +81,35 @@
 //! `$A0`-`$A4` | "Argument". General purpose, but used for passing arguments to functions.
 //! `$T0`-`$T9` | "Temporary". General purpose, but used for holding temporary values (must be saved by caller).
 //! `$S0`-`$S9` | "Saved". General purpose, but callees should save the contents to the stack.
-//! `$SP`       | Stack pointer. Automatically updated by `PUSH` and `POP` mnemonics.
+//! `$SP`       | Stack pointer. Points at the last pushed word and is automatically updated by `PUSH` and `POP` mnemonics.
 //! `$FP`       | Frame pointer. Contains address of current function's stack frame (must be maintained manually).
 //! `$RM`       | Remainder. Contains the high bits of multiplication product, or the remainder of division.
 //! `$RA`       | Return address. Contains address of the instruction to "return" to after jump and link instruction.
 //!
+//! Registers can also be referred to by their number, from `R0` (`ZERO`) to `R31` (`RA`).
+//!
+//! Programs for a processor with fewer registers are assembled with the
+//! [`RegisterTable`](../vcpu/struct.RegisterTable.html) of its profile in
+//! [`Options::registers`](struct.Options.html#structfield.registers) (`--isa regs16` on the command line), e.g.
+//! [`RegisterTable::SMALL`](../vcpu/struct.RegisterTable.html#associatedconstant.SMALL) with 16 registers, or a
+//! table built at runtime (`--isa regs:ZERO/V0/ACC=T0/SP/RA`).
+//! Registers which are not in the table are an error, and tables with other names replace the names above.
+//! [`disassemble_with`](fn.disassemble_with.html) takes the same table, so the disassembly uses the same names.
+//!
+//! Additional names can be defined in the `.instructions` section with `.alias name, register`.
+//! An alias applies to all following instructions and can be redefined at any point:
+//!
+//! ```text
+//! .alias ptr, T3
+//! .alias count, T4
+//!        LW count, 0(ptr)
+//! ```
+//!
+//! Aliases are written without `$`, which is reserved for the register names listed above.
+//!
+//! Execution starts at the first instruction, unless another one is chosen with `.entry label`.
+//! Only one `.entry` may appear in the whole program. The entry point is stored in the executable.
+//!
 //! ### Single Instruction Mnemonics
 //!
 //! Mnemonics that produce a single instruction correspond directly to one of the [`Opcode`](../vcpu/enum.Opcode.html)s
@@ -77,6 +128,8 @@
 //! `COPY`   | Copy register                                | `COPY rd, rs`
 //! `ADD`    | Integer addition                             | `ADD rd, rs1, rs2`
 //! `SUB`    | Integer subtraction                          | `SUB rd, rs1, rs2`
+//! `ADDO`   | Integer addition, stops on overflow          | `ADDO rd, rs1, rs2`
+//! `SUBO`   | Integer subtraction, stops on overflow       | `SUBO rd, rs1, rs2`
 //! `MUL`    | Integer multiplication                       | `MUL rd, rs1, rs2`
 //! `DIV`    | Integer division                             | `DIV rd, rs1, rs2`
 //! `AND`    | Bitwise And                                  | `AND rd, rs1, rs2`
@@ -85,6 +138,15 @@
 //! `SLL`    | Shift left logical                           | `SLL rd, rs1, rs2`
 //! `SRL`    | Shift right logical                          | `SRL rd, rs1, rs2`
 //! `SRA`    | Shift right arithmetic                       | `SRA rd, rs1, rs2`
+//! `ROL`    | Rotate left                                  | `ROL rd, rs1, rs2`
+//! `ROR`    | Rotate right                                 | `ROR rd, rs1, rs2`
+//! `FSL`    | Funnel shift left                            | `FSL rd, rs1, rs2, amount`
+//! `FSR`    | Funnel shift right                           | `FSR rd, rs1, rs2, amount`
+//! `BSWAP`  | Reverse order of bytes                       | `BSWAP rd, rs`
+//! `EXTB`   | Extract byte                                 | `EXTB rd, rs, index`
+//! `EXTH`   | Extract half                                 | `EXTH rd, rs, index`
+//! `INSB`   | Insert byte                                  | `INSB rd, rs1, rs2, index`
+//! `INSH`   | Insert half                                  | `INSH rd, rs1, rs2, index`
 //! `SEQ`    | Set if equal                                 | `SEQ rd, rs1, rs2`
 //! `SNE`    | Set if not equal                             | `SNE rd, rs1, rs2`
 //! `SLT`    | Set if less than                             | `SLT rd, rs1, rs2`
@@ -128,6 +190,12 @@
 //! `SGEUI`  | Set if greater or equal unsigned immediate   | `SGEUI, rd, rs, value`
 //! `BEZ`    | Branch if zero                               | `BEZ rs, target`
 //! `BNZ`    | Branch if not zero                           | `BNZ rs, target`
+//! `BEQ`    | Branch if equal                              | `BEQ rd, rs, target`
+//! `BNE`    | Branch if not equal                          | `BNE rd, rs, target`
+//! `BLT`    | Branch if less than                          | `BLT rd, rs, target`
+//! `BGE`    | Branch if greater or equal                   | `BGE rd, rs, target`
+//! `BLTU`   | Branch if less than unsigned                 | `BLTU rd, rs, target`
+//! `BGEU`   | Branch if greater or equal unsigned          | `BGEU rd, rs, target`
 //! `JMP`    | Jump                                         | `JMP target`
 //! `JL`     | Jump and link                                | `JL target`
 //! `JR`     | Jump to register value                       | `JR rs`
@@ -138,6 +206,47 @@
 //! `FSUB`   | Float subtraction                            | `FSUB rd, rs1, rs2`
 //! `FMUL`   | Float multiplication                         | `FMUL rd, rs1, rs2`
 //! `FDIV`   | Float division                               | `FDIV rd, rs1, rs2`
+//! `CSRR`   | Read control and status register             | `CSRR rd, csr`
+//! `CSRW`   | Write control and status register            | `CSRW rd, rs, csr`
+//! `CSRS`   | Set bits of control and status register      | `CSRS rd, rs, csr`
+//! `CSRC`   | Clear bits of control and status register    | `CSRC rd, rs, csr`
+//! `VADD`   | Vector addition                              | `VADD qd, qs1, qs2`
+//! `VSUB`   | Vector subtraction                           | `VSUB qd, qs1, qs2`
+//! `VMUL`   | Vector multiplication                        | `VMUL qd, qs1, qs2`
+//! `VSPLAT` | Set all lanes of vector to register          | `VSPLAT qd, rs`
+//! `VINS`   | Insert register into lane of vector          | `VINS qd, rs, lane`
+//! `VEXT`   | Extract lane of vector into register         | `VEXT rd, qs, lane`
+//! `VLD`    | Load vector                                  | `VLD qd, (rs)`
+//! `VST`    | Store vector                                 | `VST qd, (rs)`
+//! `SWAPB`  | Swap register banks                          | `SWAPB`
+//! `RDSH`   | Read register of shadow bank                 | `RDSH rd, rs`
+//! `WRSH`   | Write register of shadow bank                | `WRSH rd, rs`
+//! `IRET`   | Return from interrupt                        | `IRET`
+//!
+//! Loads and stores access memory at the address `rs + offset`, written as `offset(rs)` like in most other
+//! assemblers, e.g. `LW T0, 8(T1)`. The offset can be omitted if it is zero: `SW T0, (SP)`.
+//!
+//! The funnel shifts shift the 64 bit pair of `rs1` (the high word) and `rs2` by a constant amount from 0 to
+//! 31, and keep the high word (`FSL`) or the low word (`FSR`), e.g. to shift multi-word integers. With the same
+//! register as `rs1` and `rs2` they rotate by a constant amount.
+//!
+//! `EXTB` and `EXTH` set `rd` to byte or half `index` of `rs`, zero-extended, and `INSB` and `INSH` set `rd`
+//! to `rs1` with byte or half `index` replaced by the low byte or half of `rs2`. Byte 0 is the least
+//! significant one, so `EXTB T0, T1, 3` takes the most significant byte. Together with `BSWAP`, which converts
+//! a word between little and big endian, they read and write fields of binary data without shifting and
+//! masking.
+//!
+//! The control and status register instructions write the old value of the register to `rd`, and take
+//! the number of the register, which `std/csr.vasm` defines constants for, e.g. `CSRR T0, CSR_CYCLE`.
+//!
+//! The vector instructions work on the vector registers `$Q0`-`$Q7`, each of which holds four words that
+//! are added, subtracted and multiplied lane by lane. Lanes are numbered from 0 to 3, and vectors are
+//! loaded and stored as four consecutive words, lane 0 first, at the address in `rs`.
+//!
+//! `SWAPB` exchanges all registers with the shadow bank, e.g. so that a routine can use them without saving
+//! the ones of its caller. `RDSH` sets `rd` to register `rs` of the shadow bank, and `WRSH` sets register `rd`
+//! of the shadow bank to `rs`, to pass values between the banks. Interrupt handlers start with the shadow bank
+//! already swapped in, and `IRET` swaps back when it returns to the interrupted instruction.
 //!
 //! ### Shorthand Mnemonics
 //!
@@ -152,8 +261,156 @@
 //! `PUSH`   | Push register value onto stack               | `PUSH rs`
 //! `POP`    | Pop register value from stack                | `POP rd`
 //! `LWI`    | Load word immediate                          | `LWI rd, value`
-//! `LDA`    | Load data address                            | `LDA rd, label`
-//! `LIA`    | Load instruction address                     | `LIA rd, label`
+//! `LDA`    | Load data address                            | `LDA rd, address`
+//! `LIA`    | Load instruction address                     | `LIA rd, address`
+//! `CALL`   | Call a routine, storing the return address   | `CALL label`
+//! `RET`    | Return from a routine                        | `RET`
+//!
+//! `CALL` and `RET` are `JL label` and `JR $RA`. Together with `PUSH` and `POP` they follow the calling
+//! convention of the processor (see [`STACK_POINTER`](../vcpu/constant.STACK_POINTER.html)): arguments are
+//! passed in `$A0`-`$A4`, values returned in `$V0`-`$V1`, and routines which call others push `$RA` first:
+//!
+//! ```text
+//! twice:  PUSH $RA
+//!         CALL double
+//!         CALL double
+//!         POP $RA
+//!         RET
+//! double: ADD $A0, $A0, $A0
+//!         RET
+//! ```
+//!
+//! ## Labels
+//!
+//! Data elements and instructions can be prefixed with a label (`name:`), which can then be used
+//! as a jump target or be loaded with `LDA`/`LIA`. Label names must be unique within their section.
+//!
+//! Besides these global labels, two kinds of labels exist for short-lived jump targets inside routines:
+//!
+//! Kind    | Definition | Reference      | Description
+//! --------|------------|----------------|------------
+//! Local   | `.name:`   | `.name`        | Scoped to the preceding global label, so the same name can be reused in every routine.
+//! Numeric | `1:`       | `1b` or `1f`   | Can be defined any number of times. `b` refers to the nearest definition before, `f` to the nearest one after the reference.
+//!
+//! ```text
+//! count_down: LI $T0, 8
+//! .loop:      SUBI $T0, $T0, 1
+//!             BNZ $T0, .loop
+//! 1:          BEZ $T1, 1f
+//!             JMP 1b
+//! 1:          JR $RA
+//! ```
+//!
+//! ## Warnings
+//!
+//! Some constructs are valid, but most likely not what was intended. The assembler reports them as warnings:
+//!
+//! Name               | Reported for
+//! -------------------|-------------
+//! `truncation`       | shift amounts and jump offsets which do not fit into their instruction field
+//! `unused-label`     | labels which are never referenced
+//! `unreachable-code` | unlabeled instructions directly following `JMP`, `JR` or `HALT`
+//! `misaligned-data`  | `.short` and `.word` elements which don't start at a multiple of their size
+//!
+//! The command line tool accepts `-W<name>`, `-Wno-<name>`, `-Werror` and `-Werror=<name>` to configure them.
+//!
+//! ## Branch Relaxation
+//!
+//! Conditional branches can only reach targets within ±32 KiB. If a branch to a label is further away, it is
+//! automatically rewritten into the inverted branch, which skips over a `JMP` to the label:
+//!
+//! ```text
+//! BEZ $T0, far      =>     BNZ $T0, 8
+//!                          JMP far
+//! BLT $T0, $T1, far =>     BGE $T0, $T1, 8
+//!                          JMP far
+//! ```
+//!
+//! This can be disabled with [`Options::relax_branches`](struct.Options.html#structfield.relax_branches)
+//! or the `--no-relax` command line flag, in which case out of range branches are an error.
+//!
+//! ## Optimization
+//!
+//! If [`Options::optimize`](struct.Options.html#structfield.optimize) or the `-O` command line flag is set,
+//! the assembler cleans up the instructions before branches are relaxed:
+//!
+//! * moves which leave all registers unchanged, like `COPY $T0, $T0` or `ADDI $T0, $T0, 0`, are removed.
+//! * `LI rd, a` followed by `ADDI rd, rd, b` becomes `LI rd, a + b`, unless a label refers to the `ADDI`
+//!   or the sum does not fit into the immediate.
+//! * `JMP` and conditional branches to the next instruction are removed.
+//!
+//! Labels and relative jump offsets are adjusted to the new layout, addresses computed at runtime are not.
+//! Every change is recorded in [`Assembly::optimizations`](struct.Assembly.html#structfield.optimizations)
+//! and can be printed with [`write_optimizations`](fn.write_optimizations.html) (`--dump-optimizations`).
+//!
+//! ## Assertions
+//!
+//! `.assert <expression>, "<message>"` can appear in both sections, between data elements or instructions.
+//! The expression is evaluated once the final address of every label is known, and assembling fails with
+//! the message if it evaluates to zero:
+//!
+//! ```text
+//! .assert table_end - table == 64, "table must have 16 entries"
+//! .assert (handler_end - handler) & ~0xFF == 0, "handler does not fit into 256 bytes"
+//! ```
+//!
+//! Expressions consist of unsigned integer literals, labels, constants and parentheses, combined with the operators
+//! of Rust (`-`, `~` and `!` as prefix operators, `* / % + - << >> & ^ | == != < <= > >= && ||` with Rust's precedence).
+//! Arithmetic uses 64-bit signed integers, comparisons evaluate to 1 or 0. Data labels evaluate to their address
+//! in main memory, instruction labels to their address in instruction memory.
+//!
+//! ## Constants and Expressions
+//!
+//! `.equ <name>, <expression>` defines a constant in either section. Constants can be used wherever an expression
+//! is allowed after their definition, including in other constants. Since all data sections are processed before
+//! the instruction sections, constants defined in a `.data` section are visible in every `.instructions` section.
+//!
+//! Besides assertions, expressions are allowed as the immediate of `LI`, `LHI`, `LWI` and the I-format
+//! instructions like `ADDI`, and as the address of `LDA` and `LIA`:
+//!
+//! ```text
+//! .equ ENTRY_SIZE, 8
+//! .equ HANDLER_SIZE, handler_end - handler
+//!         LDA $T0, table + 3 * ENTRY_SIZE
+//!         LI $T1, HANDLER_SIZE / 4
+//!         LIA $T2, handler + 4
+//! ```
+//!
+//! Names are looked up as constants first, then as data labels and finally as instruction labels.
+//! Constants and data labels are folded right away. Instruction addresses are only known after branch relaxation
+//! and optimization, so terms containing instruction labels are kept until then.
+//!
+//! Every expression keeps track of whether its value is a number, a data address or an instruction address.
+//! Numbers can be added to or subtracted from addresses, and two addresses in the same section can be
+//! subtracted or compared, both resulting in numbers. All other operations on addresses are errors, as is
+//! mixing data and instruction addresses. `LDA` requires a data address, `LIA` an instruction address.
+//! Values which don't fit into the immediate are errors instead of being truncated.
+//!
+//! Tools like the debugger evaluate expressions with the same syntax using [`ToolExpression`](struct.ToolExpression.html),
+//! where they may also contain registers (`$SP`) and words in memory (`[$SP - 4]`). The assembler reports those
+//! as errors.
+//!
+//! ## Standard Library
+//!
+//! The assembler ships with a library of routines, which a program can include before its `.data` section:
+//!
+//! ```text
+//! .include <std/string.vasm>
+//! ```
+//!
+//! File              | Routines
+//! ------------------|---------
+//! `std/string.vasm` | `memcpy`, `memset`, `strlen`
+//! `std/fmt.vasm`    | `itoa`
+//! `std/uart.vasm`   | `uart_putc`, `uart_puts`, `uart_puti`, printing over a memory mapped UART
+//! `std/heap.vasm`   | `heap_init`, `malloc`, a simple heap that never frees memory
+//! `std/args.vasm`   | `args_init`, `args_get`, `getenv`, the command line arguments and environment passed by a runner
+//! `std/semihosting.vasm` | `sh_open`, `sh_read`, `sh_write`, `sh_malloc`, `sh_free` and more, files, streams and a heap of the host
+//!
+//! The routines are called with `JL`, take their arguments in `$A0`-`$A4`, return values in `$V0` and only
+//! modify temporary registers unless documented otherwise in their source, which is available from
+//! [`library_source`](fn.library_source.html).
+//! Included files are appended after all other files, each one only once, and are never warned about.
 //!
 //! [pest]: https://docs.rs/pest/
 
@@ -161,55 +418,307 @@
 // TODO: describe data labels and instruction labels
 // TODO: provide detailed documentation for each mnemonic (separate pages?)
 
+mod assertions;
 mod data;
+mod debug_info;
+mod depfile;
+mod diagnostics;
+mod disassembler;
+mod evaluator;
+mod expressions;
+mod incremental;
 mod instructions;
 mod int_util;
 mod labels;
+mod library;
+mod listing;
+mod optimizer;
 mod parser;
+mod registers;
 mod source_map;
+mod symbols;
+mod warnings;
 
 #[cfg(test)]
 mod test;
 
+pub use depfile::write_depfile;
+pub use diagnostics::{Diagnostic, DiagnosticSeverity, Position};
+pub use disassembler::{disassemble, disassemble_with, jump_target};
+pub use evaluator::{evaluate_expression, EvalContext, ToolExpression};
+pub use incremental::{AssembledLine, Assembler};
+use library::ParsedFile;
+pub use library::{library_names, library_source};
+pub use listing::{write_listing, write_optimizations};
+pub use optimizer::{Optimization, OptimizationKind};
 use parser::{Rule, VASMParser};
 use pest::iterators::Pair;
 use pest::{Parser, Span};
-pub use source_map::{SourceMap, SourceMapItem};
+pub use source_map::{DataMap, DataMapItem, SourceMap, SourceMapItem};
+pub use symbols::{write_map, Section, Symbol, SymbolTable};
+use vcpu::RegisterTable;
+use vex::debug::DebugInfo;
 use vex::Executable;
+use warnings::Linter;
+pub use warnings::{Severity, Warning, WarningKind, WarningOptions};
 
 pub type Error = pest::error::Error<Rule>;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub fn assemble_addressed(input: &str, data_offset: u32) -> Result<(Executable, SourceMap)> {
-    assemble_parsed(parse(input)?, data_offset)
+/// The complete output of assembling a program.
+#[derive(Debug, PartialEq)]
+pub struct Assembly {
+    /// The assembled executable.
+    pub executable: Executable,
+    /// Associates each instruction with the line(s) it was assembled from.
+    pub source_map: SourceMap,
+    /// Associates each data element with its location in the data and the line(s) it was assembled from.
+    pub data_map: DataMap,
+    /// All labels of the program with their final addresses.
+    pub symbols: SymbolTable,
+    /// The source map and symbols in the format used by debuggers. Sources without a name have an empty file name.
+    pub debug_info: DebugInfo,
+    /// Warnings which were emitted while assembling, in the order they were found.
+    pub warnings: Vec<Warning>,
+    /// Changes made by the optimizer, empty unless [`Options::optimize`](struct.Options.html#structfield.optimize) is set.
+    pub optimizations: Vec<Optimization>,
+}
+
+/// Settings which control how a program is assembled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// Address in main memory where the data section will be loaded.
+    pub data_offset: u32,
+    /// Severity of each kind of warning.
+    pub warnings: WarningOptions,
+    /// Whether branches to labels which are out of range are rewritten into an inverted branch and a `JMP`.
+    /// If disabled, such branches are an error. Enabled by default.
+    pub relax_branches: bool,
+    /// Whether peephole optimizations are applied to the instructions. Disabled by default.
+    pub optimize: bool,
+    /// The registers which instructions may use and their names, usually those of the
+    /// [`IsaProfile`](../vcpu/struct.IsaProfile.html) the program is written for. All registers by default.
+    pub registers: RegisterTable,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            data_offset: 0,
+            warnings: WarningOptions::default(),
+            relax_branches: true,
+            optimize: false,
+            registers: RegisterTable::STANDARD,
+        }
+    }
 }
 
-pub fn assemble(input: &str) -> Result<(Executable, SourceMap)> {
-    assemble_addressed(input, 0u32)
+/// A named source file, used when assembling multiple files as one program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Source<'a> {
+    /// Name of the source file, which is used as path in errors and warnings.
+    pub name: &'a str,
+    /// Contents of the source file.
+    pub text: &'a str,
+}
+
+pub fn assemble_with_options(input: &str, options: &Options) -> Result<Assembly> {
+    assemble_parsed(parse_files(&[(None, input)])?, options)
+}
+
+/// Assembles `source` and returns either the assembly or the diagnostics which made it fail.
+///
+/// On failure, the error is the last diagnostic and is preceded by all warnings which were found before it.
+/// On success, the warnings are available from [`Assembly::diagnostics`](struct.Assembly.html#method.diagnostics).
+pub fn assemble(source: &str, options: &Options) -> std::result::Result<Assembly, Vec<Diagnostic>> {
+    let files = parse_files(&[(None, source)]).map_err(|err| vec![Diagnostic::from_error(&err)])?;
+    let (result, warnings) = assemble_parsed_with_warnings(files, options);
+    match result {
+        Ok(assembly) => Ok(Assembly {
+            warnings,
+            ..assembly
+        }),
+        Err(err) => {
+            let mut diagnostics: Vec<Diagnostic> =
+                warnings.iter().map(Diagnostic::from_warning).collect();
+            diagnostics.push(Diagnostic::from_error(&err));
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Assembles several source files as a single program.
+///
+/// The data sections of all files are concatenated in the given order, and so are the instruction sections.
+/// Global labels are shared between all files, while numeric labels are only visible within their file.
+/// All errors and warnings carry the name of the file they occurred in as their path.
+pub fn assemble_sources(sources: &[Source], options: &Options) -> Result<Assembly> {
+    let sources: Vec<_> = sources
+        .iter()
+        .map(|source| (Some(source.name), source.text))
+        .collect();
+    assemble_parsed(parse_files(&sources)?, options)
+}
+
+pub fn assemble_program(input: &str, data_offset: u32) -> Result<Assembly> {
+    assemble_with_options(
+        input,
+        &Options {
+            data_offset,
+            ..Options::default()
+        },
+    )
+}
+
+pub fn assemble_addressed(input: &str, data_offset: u32) -> Result<(Executable, SourceMap)> {
+    let assembly = assemble_program(input, data_offset)?;
+    Ok((assembly.executable, assembly.source_map))
 }
 
 fn new_parser_error(span: Span, message: String) -> Error {
     Error::new_from_span(pest::error::ErrorVariant::CustomError { message }, span)
 }
 
+fn with_path(err: Error, path: Option<&str>) -> Error {
+    match path {
+        Some(path) => err.with_path(path),
+        None => err,
+    }
+}
+
 fn parse(input: &str) -> Result<Pair<Rule>> {
     Ok(VASMParser::parse(Rule::program, input)?.next().unwrap())
 }
 
-fn assemble_parsed(pair: Pair<Rule>, data_offset: u32) -> Result<(Executable, SourceMap)> {
-    let mut pairs = pair.into_inner();
+/// Parses each `(path, text)` source, followed by all library files they include.
+fn parse_files<'i>(sources: &[(Option<&'i str>, &'i str)]) -> Result<Vec<ParsedFile<'i>>> {
+    let mut files = Vec::new();
+    for (path, text) in sources {
+        files.push(ParsedFile {
+            path: *path,
+            program: parse(text).map_err(|err| with_path(err, *path))?,
+            library: false,
+        });
+    }
+    library::resolve_includes(&mut files)?;
+    Ok(files)
+}
+
+/// Assembles the parsed `program` of each file. The path of each file is attached to its errors, if there is one.
+///
+/// All warnings are returned separately, so that the ones which were reported before an error are not lost.
+fn assemble_parsed_with_warnings<'i: 'o, 'o>(
+    files: Vec<ParsedFile<'i>>,
+    options: &'o Options,
+) -> (Result<Assembly>, Vec<Warning>) {
+    let mut linter = Linter::new(&options.warnings);
+    let result = assemble_files(files, options, &mut linter)
+        .and_then(|assembly| linter.report_unused_labels().map(|_| assembly));
+    (result, linter.into_warnings())
+}
+
+fn assemble_parsed(files: Vec<ParsedFile>, options: &Options) -> Result<Assembly> {
+    let (result, warnings) = assemble_parsed_with_warnings(files, options);
+    result.map(|assembly| Assembly {
+        warnings,
+        ..assembly
+    })
+}
+
+fn assemble_files<'i: 'o, 'o>(
+    files: Vec<ParsedFile<'i>>,
+    options: &'o Options,
+    linter: &mut Linter<'i, 'o>,
+) -> Result<Assembly> {
+    let data_offset = options.data_offset;
+    let paths: Vec<_> = files.iter().map(|file| file.path).collect();
 
-    let (data, data_labels) = data::process_data(pairs.next().unwrap())?;
-    let (instr, instr_labels, source_map) =
-        instructions::process_instructions(pairs.next().unwrap(), &data_labels, data_offset)?;
+    // all data sections are processed first, so that instructions can refer to data labels from any file
+    let mut data = data::DataOutput::default();
+    let mut instruction_pairs = Vec::new();
+    for (index, file) in files.into_iter().enumerate() {
+        let path = file.path;
+        let mut pairs = file
+            .program
+            .into_inner()
+            .filter(|pair| pair.as_rule() != Rule::include);
+        linter.set_path(path);
+        linter.set_quiet(file.library);
+        data::process_data(
+            pairs.next().unwrap(),
+            index as u32,
+            &mut data,
+            data_offset,
+            linter,
+        )
+        .map_err(|err| with_path(err, path))?;
+        instruction_pairs.push((path, file.library, pairs.next().unwrap()));
+    }
+
+    let mut instr = instructions::InstructionOutput::default();
+    for (file, (path, library, pair)) in instruction_pairs.into_iter().enumerate() {
+        linter.set_path(path);
+        linter.set_quiet(library);
+        instructions::process_instructions(
+            pair,
+            file as u32,
+            &mut instr,
+            &mut data.constants,
+            &data.labels,
+            options,
+            linter,
+        )
+        .map_err(|err| with_path(err, path))?;
+    }
+    linter.set_quiet(false);
+    let instructions::InstructionOutput {
+        instructions: mut instr,
+        labels: mut instr_labels,
+        mut source_map,
+        assertions,
+        entry,
+    } = instr;
+    let optimizations = if options.optimize {
+        optimizer::optimize(&mut instr, &mut instr_labels, &mut source_map)
+    } else {
+        Vec::new()
+    };
+    if options.relax_branches {
+        instr = instructions::relax_branches(instr, &mut instr_labels, &mut source_map);
+    }
 
-    Ok((
-        Executable::from(
+    for assertions in [&data.assertions, &assertions] {
+        assertions::check_assertions(
+            assertions,
+            &data.labels,
             data_offset,
-            instructions::assemble_instructions(&instr, &instr_labels)?,
-            data,
-        ),
+            &instr_labels,
+            &paths,
+            linter,
+        )?;
+    }
+
+    let encoded = instructions::assemble_instructions(&instr, &instr_labels, &source_map, &paths)?;
+    let symbols = symbols::build_symbol_table(&data.labels, data_offset, &instr_labels);
+    let source_names: Vec<&str> = paths.iter().map(|path| path.unwrap_or("")).collect();
+
+    let mut executable = Executable::from(data_offset, encoded, data.data);
+    if let Some((file, label)) = entry {
+        let index = instr_labels.get(label.key()).ok_or_else(|| {
+            let err = new_parser_error(label.span(), "Label not found".to_owned());
+            with_path(err, paths[file as usize])
+        })?;
+        executable.set_entry_point(index * vcpu::WORD_BYTES);
+    }
+
+    Ok(Assembly {
+        executable,
+        debug_info: debug_info::build_debug_info(&source_map, &symbols, &source_names),
         source_map,
-    ))
+        data_map: data.data_map,
+        symbols,
+        warnings: Vec::new(),
+        optimizations,
+    })
 }
@@ -0,0 +1,106 @@
+use crate::*;
+
+struct LibraryFile {
+    name: &'static str,
+    path: &'static str,
+    text: &'static str,
+}
+
+macro_rules! library_file {
+    ($name:literal) => {
+        LibraryFile {
+            name: $name,
+            path: concat!("<", $name, ">"),
+            text: include_str!(concat!("../", $name)),
+        }
+    };
+}
+
+const LIBRARY: &[LibraryFile] = &[
+    library_file!("std/args.vasm"),
+    library_file!("std/csr.vasm"),
+    library_file!("std/fmt.vasm"),
+    library_file!("std/heap.vasm"),
+    library_file!("std/semihosting.vasm"),
+    library_file!("std/string.vasm"),
+    library_file!("std/uart.vasm"),
+];
+
+/// Returns the source of the library file `name`, e.g. `std/string.vasm`.
+pub fn library_source(name: &str) -> Option<&'static str> {
+    find(name).map(|file| file.text)
+}
+
+/// Names of all files in the standard library.
+pub fn library_names() -> impl Iterator<Item = &'static str> {
+    LIBRARY.iter().map(|file| file.name)
+}
+
+fn find(name: &str) -> Option<&'static LibraryFile> {
+    LIBRARY.iter().find(|file| file.name == name)
+}
+
+/// A parsed source file.
+pub(crate) struct ParsedFile<'i> {
+    pub path: Option<&'i str>,
+    pub program: Pair<'i, Rule>,
+    /// Whether the file is part of the library, which is never warned about.
+    pub library: bool,
+}
+
+/// Appends the library files which are included by `files`, directly or by other library files.
+///
+/// Every library file is only appended once, no matter how often it is included.
+/// They come after all other files, so that the program still starts with the first file.
+pub(crate) fn resolve_includes(files: &mut Vec<ParsedFile<'_>>) -> Result<()> {
+    let mut included = Vec::new();
+    let mut index = 0;
+    while index < files.len() {
+        let path = files[index].path;
+        let includes: Vec<_> = files[index]
+            .program
+            .clone()
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::include)
+            .collect();
+
+        for include in includes {
+            let name = include
+                .into_inner()
+                .next()
+                .unwrap()
+                .into_inner()
+                .next()
+                .unwrap();
+            let file = find(name.as_str()).ok_or_else(|| {
+                let message = format!("Library file \"{}\" does not exist", name.as_str());
+                with_path(new_parser_error(name.as_span(), message), path)
+            })?;
+
+            if !included.contains(&file.name) {
+                included.push(file.name);
+                files.push(ParsedFile {
+                    path: Some(file.path),
+                    program: parse(file.text).map_err(|err| err.with_path(file.path))?,
+                    library: true,
+                });
+            }
+        }
+        index += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn library_files_assemble() {
+        for name in library_names() {
+            let input = format!(".include <{}>\n.data\n.instructions\nHALT", name);
+            let assembly = assemble_with_options(&input, &Options::default()).unwrap();
+            assert!(assembly.warnings.is_empty(), "{}", name);
+        }
+    }
+}
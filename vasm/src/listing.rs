@@ -0,0 +1,248 @@
+use crate::{library_source, Assembly, Source, SourceMapItem};
+use byteorder::ByteOrder;
+use std::io::{self, Write};
+use util::Endian;
+use vcpu::WORD_BYTES;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+const CODE_COLUMN_WIDTH: usize = 40;
+
+enum ListingEntry<'a> {
+    Instruction(u32, u32),
+    Data(u32, &'a [u8]),
+}
+
+impl<'a> ListingEntry<'a> {
+    fn format(&self) -> String {
+        match self {
+            ListingEntry::Instruction(address, word) => format!("I {:08X}  {:08X}", address, word),
+            ListingEntry::Data(address, bytes) => {
+                let mut shown = bytes
+                    .iter()
+                    .take(DATA_BYTES_PER_LINE)
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if bytes.len() > DATA_BYTES_PER_LINE {
+                    shown.push_str(" ...");
+                }
+                format!("D {:08X}  {}", address, shown)
+            }
+        }
+    }
+}
+
+/// Writes a listing of the assembled program to `writer`.
+///
+/// Every line of each source is printed alongside the addresses and encoded contents of all data elements
+/// and instructions which were assembled from it. Instructions are listed with their address in instruction memory,
+/// data elements with their address in main memory. Mnemonics which produce multiple instructions are listed
+/// with one row per produced instruction. If there is more than one source, each one is preceded by its name.
+///
+/// `assembly` must be the result of assembling `sources`, in the same order.
+pub fn write_listing<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+    assembly: &Assembly,
+) -> io::Result<()> {
+    for (file, source) in sources.iter().enumerate() {
+        if sources.len() > 1 {
+            if file > 0 {
+                writeln!(writer)?;
+            }
+            writeln!(writer, "{}:", source.name)?;
+        }
+        write_file_listing(writer, file as u32, source.text, assembly)?;
+    }
+    Ok(())
+}
+
+fn write_file_listing<W: Write>(
+    writer: &mut W,
+    file: u32,
+    source: &str,
+    assembly: &Assembly,
+) -> io::Result<()> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut entries: Vec<Vec<ListingEntry>> = lines.iter().map(|_| Vec::new()).collect();
+
+    let executable = &assembly.executable;
+    let data = executable.data();
+
+    for item in assembly.data_map.iter().filter(|item| item.file == file) {
+        let start = item.offset as usize;
+        let end = start + item.length as usize;
+        if let Some(line_entries) = entries.get_mut(item.start_line as usize - 1) {
+            line_entries.push(ListingEntry::Data(
+                executable.data_offset() + item.offset,
+                &data[start..end],
+            ));
+        }
+    }
+
+    let words = executable.instructions().chunks(WORD_BYTES as usize);
+    for (i, (item, word)) in assembly.source_map.iter().zip(words).enumerate() {
+        if item.file != file {
+            continue;
+        }
+        if let Some(line_entries) = entries.get_mut(item.start_line as usize - 1) {
+            line_entries.push(ListingEntry::Instruction(
+                i as u32 * WORD_BYTES,
+                Endian::read_u32(word),
+            ));
+        }
+    }
+
+    for (i, (line, line_entries)) in lines.iter().zip(entries.iter()).enumerate() {
+        let mut iter = line_entries.iter();
+        let first = iter.next().map(ListingEntry::format).unwrap_or_default();
+        writeln!(
+            writer,
+            "{:>5}  {:<width$}  {}",
+            i + 1,
+            first,
+            line,
+            width = CODE_COLUMN_WIDTH
+        )?;
+        for entry in iter {
+            writeln!(writer, "{:>5}  {}", "", entry.format())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the (first) line of `sources` which `item` was assembled from, without surrounding whitespace.
+/// Files which are not part of `sources` are looked up in the library.
+fn source_line<'a>(sources: &[Source<'a>], assembly: &Assembly, item: &SourceMapItem) -> &'a str {
+    let text = match sources.get(item.file as usize) {
+        Some(source) => Some(source.text),
+        None => assembly
+            .debug_info
+            .files
+            .get(item.file as usize)
+            .and_then(|name| name.strip_prefix('<')?.strip_suffix('>'))
+            .and_then(library_source),
+    };
+    text.and_then(|text| text.lines().nth(item.start_line as usize - 1))
+        .unwrap_or_default()
+        .trim()
+}
+
+/// Writes every change the optimizer made to `writer`.
+///
+/// Each change starts with the location and kind of the change, followed by the replaced instructions
+/// (`-`) alongside the source line they were assembled from, and the instructions which replaced them (`+`).
+///
+/// `assembly` must be the result of assembling `sources`, in the same order.
+pub fn write_optimizations<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+    assembly: &Assembly,
+) -> io::Result<()> {
+    for optimization in assembly.optimizations.iter() {
+        let item = &optimization.before[0].0;
+        match assembly.debug_info.files.get(item.file as usize) {
+            Some(name) if !name.is_empty() => write!(writer, "{}:", name)?,
+            _ => {}
+        }
+        writeln!(writer, "{}: {}", item.start_line, optimization.kind)?;
+        for (item, word) in optimization.before.iter() {
+            writeln!(
+                writer,
+                "  - {:08X}  {}",
+                word,
+                source_line(sources, assembly, item)
+            )?;
+        }
+        for word in optimization.after.iter() {
+            writeln!(writer, "  + {:08X}", word)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble_program, assemble_sources, Options, Source};
+
+    #[test]
+    fn listing() {
+        let input = ".data
+value: .word 0x12345678
+.instructions
+    PUSH $T0
+    HALT";
+
+        let assembly = assemble_program(input, 16).unwrap();
+        let mut output = Vec::new();
+        let sources = [Source {
+            name: "main.vasm",
+            text: input,
+        }];
+        super::write_listing(&mut output, &sources, &assembly).unwrap();
+
+        let expected = "    1                                            .data
+    2  D 00000010  78 56 34 12                   value: .word 0x12345678
+    3                                            .instructions
+    4  I 00000000  391CFFFC                          PUSH $T0
+       I 00000004  439C0004
+    5  I 00000008  08000000                          HALT
+";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn listing_multiple_sources() {
+        let sources = [
+            Source {
+                name: "a.vasm",
+                text: ".data\n.instructions\nmain: JMP end",
+            },
+            Source {
+                name: "b.vasm",
+                text: ".data\n.instructions\nend: HALT",
+            },
+        ];
+
+        let assembly = assemble_sources(&sources, &Options::default()).unwrap();
+        let mut output = Vec::new();
+        super::write_listing(&mut output, &sources, &assembly).unwrap();
+
+        let expected = "a.vasm:
+    1                                            .data
+    2                                            .instructions
+    3  I 00000000  98000004                      main: JMP end
+
+b.vasm:
+    1                                            .data
+    2                                            .instructions
+    3  I 00000004  08000000                      end: HALT
+";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn optimizations() {
+        let sources = [Source {
+            name: "main.vasm",
+            text: ".data\n.instructions\n  COPY $T0, $T0\n  LI $T1, 5\n  ADDI $T1, $T1, 1\n  HALT",
+        }];
+        let options = Options {
+            optimize: true,
+            ..Options::default()
+        };
+        let assembly = assemble_sources(&sources, &options).unwrap();
+        let mut output = Vec::new();
+        super::write_optimizations(&mut output, &sources, &assembly).unwrap();
+
+        let expected = "main.vasm:3: removed redundant move
+  - 11080000  COPY $T0, $T0
+main.vasm:4: folded LI and ADDI
+  - 15200005  LI $T1, 5
+  - 3D290001  ADDI $T1, $T1, 1
+  + 15200006
+";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
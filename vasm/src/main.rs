@@ -7,8 +7,9 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use util::Endian;
-use vasm::SourceMapItem;
+use vasm::{Assembly, Source, SourceMapItem};
 
 #[derive(Debug)]
 enum IOErrorContext {
@@ -48,8 +49,12 @@ fn main() {
     let matches = app_from_crate!()
         .arg(
             Arg::with_name("INPUT")
-                .help("Sets the input file to use")
+                .help(
+                    "Sets the input files to use, or - to read from stdin. \
+                     Multiple files are assembled as a single program, in the given order",
+                )
                 .required(true)
+                .multiple(true)
                 .index(1),
         )
         .arg(
@@ -58,7 +63,20 @@ fn main() {
                 .long("output")
                 .takes_value(true)
                 .value_name("OUTPUT")
-                .help("Sets the output file to write to"),
+                .help(
+                    "Sets the output file to write to, or - to write to stdout \
+                     (default when reading from stdin)",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&Format::NAMES)
+                .default_value("vexfile")
+                .help("Sets the format of the output file"),
         )
         .arg(
             Arg::with_name("source_map")
@@ -68,55 +86,522 @@ fn main() {
                 .value_name("SOURCE_MAP")
                 .help("Sets the file to write the source map to"),
         )
+        .arg(
+            Arg::with_name("listing")
+                .short("l")
+                .long("listing")
+                .takes_value(true)
+                .value_name("LISTING")
+                .help("Sets the file to write the listing to"),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .takes_value(true)
+                .value_name("MAP")
+                .help("Sets the file to write the symbol map to"),
+        )
+        .arg(
+            Arg::with_name("debug_info")
+                .short("g")
+                .long("debug_info")
+                .takes_value(true)
+                .value_name("DEBUG_INFO")
+                .help("Sets the file to write the debug information to"),
+        )
+        .arg(
+            Arg::with_name("embed_debug_info")
+                .long("embed-debug-info")
+                .help("Stores the debug information in the vexfile, so that debuggers need no separate file"),
+        )
+        .arg(
+            Arg::with_name("metadata")
+                .long("metadata")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KEY=VALUE")
+                .validator(|value| {
+                    if value.contains('=') {
+                        Ok(())
+                    } else {
+                        Err("expected KEY=VALUE".to_owned())
+                    }
+                })
+                .help(
+                    "Stores a metadata entry in the vexfile. By default, the name of the first input file, \
+                     the toolchain and the build time (or SOURCE_DATE_EPOCH) are stored",
+                ),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .help("Compresses the vexfile, which is decompressed transparently when it is loaded"),
+        )
+        .arg(
+            Arg::with_name("deps")
+                .short("M")
+                .long("deps")
+                .takes_value(true)
+                .value_name("DEPS")
+                .help(
+                    "Sets the file to write a Makefile style list of all files read by the assembler to, \
+                     for use with make or ninja",
+                ),
+        )
+        .arg(
+            Arg::with_name("warning")
+                .short("W")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("WARNING")
+                .help("Configures a warning: NAME, no-NAME, error, error=NAME or no-error=NAME"),
+        )
+        .arg(
+            Arg::with_name("no_relax")
+                .long("no-relax")
+                .help("Reports out of range branches as errors instead of rewriting them"),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .short("O")
+                .long("optimize")
+                .help("Removes redundant moves and jumps and folds LI + ADDI pairs"),
+        )
+        .arg(
+            Arg::with_name("dump_optimizations")
+                .long("dump-optimizations")
+                .requires("optimize")
+                .help("Prints every instruction changed by the optimizer before and after the change"),
+        )
+        .arg(
+            Arg::with_name("isa")
+                .long("isa")
+                .takes_value(true)
+                .value_name("PROFILE")
+                .validator(|value| value.parse::<vcpu::IsaProfile>().map(|_| ()))
+                .help("Only accepts the registers of this instruction set profile, e.g. regs16"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Assembles again whenever one of the input files changes, until interrupted"),
+        )
         .get_matches();
 
-    let input = matches.value_of("INPUT").unwrap();
-    let output = matches.value_of("OUTPUT");
-    let map = matches.value_of("SOURCE_MAP");
+    let inputs: Vec<&str> = matches.values_of("INPUT").unwrap().collect();
+    if inputs.iter().filter(|input| **input == STDIO_PATH).count() > 1 {
+        eprintln!("stdin can only be used as input once");
+        std::process::exit(1);
+    }
+    let outputs = Outputs {
+        output: matches.value_of("output"),
+        format: Format::from_name(matches.value_of("format").unwrap()),
+        source_map: matches.value_of("source_map"),
+        listing: matches.value_of("listing"),
+        symbol_map: matches.value_of("map"),
+        debug_info: matches.value_of("debug_info"),
+        embed_debug_info: matches.is_present("embed_debug_info"),
+        compress: matches.is_present("compress"),
+        metadata: matches
+            .values_of("metadata")
+            .into_iter()
+            .flatten()
+            .map(|entry| {
+                let (key, value) = entry.split_once('=').unwrap();
+                (key, value)
+            })
+            .collect(),
+        deps: matches.value_of("deps"),
+        dump_optimizations: matches.is_present("dump_optimizations"),
+    };
 
-    if let Err(err) = vasm(input, output, map) {
+    let mut options = vasm::Options {
+        relax_branches: !matches.is_present("no_relax"),
+        optimize: matches.is_present("optimize"),
+        ..vasm::Options::default()
+    };
+    if let Some(value) = matches.value_of("isa") {
+        options.registers = value
+            .parse::<vcpu::IsaProfile>()
+            .unwrap()
+            .registers()
+            .clone();
+    }
+    for flag in matches.values_of("warning").into_iter().flatten() {
+        if let Err(err) = options.warnings.apply_flag(flag) {
+            eprintln!("Invalid warning option \"-W{}\": {}", flag, err);
+            std::process::exit(1);
+        }
+    }
+
+    if matches.is_present("watch") {
+        if inputs.contains(&STDIO_PATH) {
+            eprintln!("stdin cannot be used as input in watch mode");
+            std::process::exit(1);
+        }
+        watch(&inputs, &outputs, &options);
+    }
+
+    if let Err(err) = vasm(&inputs, &outputs, &options) {
         eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// How often the input files are checked for changes in watch mode.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Returns the modification time of each input file, `None` for files which cannot be accessed.
+fn modification_times(input_path_strs: &[&str]) -> Vec<Option<SystemTime>> {
+    input_path_strs
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Assembles the input files, then polls them and assembles them again every time one of them changes.
+/// Errors are reported, but do not stop watching.
+fn watch(input_path_strs: &[&str], outputs: &Outputs, options: &vasm::Options) -> ! {
+    let mut last_times = None;
+    loop {
+        let times = modification_times(input_path_strs);
+        if last_times.as_ref() != Some(&times) {
+            last_times = Some(times);
+            match vasm(input_path_strs, outputs, options) {
+                Ok(()) => eprintln!("Assembled successfully, watching for changes..."),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    eprintln!("Watching for changes...");
+                }
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
     }
 }
 
-fn vasm(input: &str, output: Option<&str>, map: Option<&str>) -> Result<(), Error> {
-    let input_path = Path::new(input);
+/// Paths of all files to write, `None` if the file should not be written.
+struct Outputs<'a> {
+    output: Option<&'a str>,
+    format: Format,
+    source_map: Option<&'a str>,
+    listing: Option<&'a str>,
+    symbol_map: Option<&'a str>,
+    debug_info: Option<&'a str>,
+    /// Whether the debug information is stored in the vexfile.
+    embed_debug_info: bool,
+    /// Whether the vexfile is compressed.
+    compress: bool,
+    /// Metadata entries given on the command line, which replace the default ones.
+    metadata: Vec<(&'a str, &'a str)>,
+    deps: Option<&'a str>,
+    /// Whether the changes made by the optimizer are printed to stderr.
+    dump_optimizations: bool,
+}
+
+/// Path which stands for stdin when used as input and for stdout when used as output.
+const STDIO_PATH: &str = "-";
 
-    // Read input file
-    let input_file = File::open(input_path)
-        .map_err(|err| Error::Io(err, IOErrorContext::ReadInput, input_path.to_owned()))?;
-    let mut buf_reader = BufReader::new(input_file);
+fn read_input(path: &Path) -> std::io::Result<String> {
     let mut input = String::new();
+    if path == Path::new(STDIO_PATH) {
+        std::io::stdin().lock().read_to_string(&mut input)?;
+    } else {
+        BufReader::new(File::open(path)?).read_to_string(&mut input)?;
+    }
+    Ok(input)
+}
 
-    buf_reader
-        .read_to_string(&mut input)
-        .map_err(|err| Error::Io(err, IOErrorContext::ReadInput, input_path.to_owned()))?;
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Vexfile,
+    Elf,
+    Bin,
+    Hex,
+    Srec,
+}
 
-    // Perform parse
-    let (executable, source_map) = vasm::assemble(&input).map_err(|err| {
-        Error::Vasm(match input_path.to_str() {
-            Some(path_str) => err.with_path(path_str),
-            None => err,
+impl Format {
+    const NAMES: [&'static str; 5] = ["vexfile", "elf", "bin", "hex", "srec"];
+
+    fn from_name(name: &str) -> Format {
+        match name {
+            "elf" => Format::Elf,
+            "bin" => Format::Bin,
+            "hex" => Format::Hex,
+            "srec" => Format::Srec,
+            _ => Format::Vexfile,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Vexfile => "vex",
+            Format::Elf => "elf",
+            Format::Bin => "bin",
+            Format::Hex => "hex",
+            Format::Srec => "srec",
+        }
+    }
+}
+
+fn write_image<W: Write>(
+    writer: &mut W,
+    format: Format,
+    bytes: &[u8],
+    base_address: u32,
+) -> std::io::Result<()> {
+    match format {
+        Format::Vexfile | Format::Elf => unreachable!(),
+        Format::Bin => vex::image::write_binary(writer, bytes)?,
+        Format::Hex => vex::image::write_intel_hex(writer, bytes, base_address)?,
+        Format::Srec => vex::image::write_srec(writer, bytes, base_address, "vasm")?,
+    }
+    writer.flush()
+}
+
+/// Writes the executable to `path` in the given format.
+///
+/// Except for vexfiles and ELF files, the formats can only hold a single address space. The instruction memory image
+/// is therefore written to `path`, and the data memory image (if there is any data) next to it,
+/// with `.data` inserted before the extension.
+///
+/// Returns the paths of all files which were written.
+fn write_output(
+    path: &Path,
+    format: Format,
+    executable: &vex::Executable,
+) -> Result<Vec<PathBuf>, Error> {
+    let to_stdout = path == Path::new(STDIO_PATH);
+    let output_error =
+        |err, path: &Path| Error::Io(err, IOErrorContext::WriteOutput, path.to_owned());
+
+    if let Format::Vexfile | Format::Elf = format {
+        let write = |mut writer: &mut dyn Write| match format {
+            Format::Elf => vex::elf::write(&mut writer, executable),
+            _ => vex::write(&mut writer, executable),
+        };
+        return if to_stdout {
+            let stdout = std::io::stdout();
+            let mut writer = stdout.lock();
+            write(&mut writer)
+                .and_then(|_| writer.flush())
+                .map(|_| Vec::new())
+        } else {
+            File::create(path)
+                .and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    write(&mut writer)?;
+                    writer.flush()
+                })
+                .map(|_| vec![path.to_owned()])
+        }
+        .map_err(|err| output_error(err, path));
+    }
+
+    if to_stdout {
+        if !executable.data().is_empty() {
+            let err =
+                std::io::Error::other("data section cannot be written to stdout in this format");
+            return Err(output_error(err, path));
+        }
+        let stdout = std::io::stdout();
+        return write_image(&mut stdout.lock(), format, executable.instructions(), 0)
+            .map(|_| Vec::new())
+            .map_err(|err| output_error(err, path));
+    }
+
+    File::create(path)
+        .and_then(|file| {
+            write_image(
+                &mut BufWriter::new(file),
+                format,
+                executable.instructions(),
+                0,
+            )
+        })
+        .map_err(|err| output_error(err, path))?;
+    let mut written = vec![path.to_owned()];
+
+    if !executable.data().is_empty() {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_else(|| format.extension());
+        let data_path = path.with_extension(format!("data.{}", extension));
+        File::create(&data_path)
+            .and_then(|file| {
+                write_image(
+                    &mut BufWriter::new(file),
+                    format,
+                    executable.data(),
+                    executable.data_offset(),
+                )
+            })
+            .map_err(|err| output_error(err, &data_path))?;
+        written.push(data_path);
+    }
+
+    Ok(written)
+}
+
+/// Stores the default metadata entries, followed by the ones given on the command line.
+fn set_metadata(executable: &mut vex::Executable, first_input: &str, entries: &[(&str, &str)]) {
+    if first_input != STDIO_PATH {
+        if let Some(name) = Path::new(first_input).file_stem() {
+            executable.set_metadata(vex::METADATA_NAME, name.to_string_lossy());
+        }
+    }
+    executable.set_metadata(
+        vex::METADATA_TOOLCHAIN,
+        concat!(crate_name!(), " ", crate_version!()),
+    );
+
+    // SOURCE_DATE_EPOCH makes builds reproducible, see https://reproducible-builds.org/specs/source-date-epoch/
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").ok().or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|time| time.as_secs().to_string())
+    });
+    if let Some(timestamp) = timestamp {
+        executable.set_metadata(vex::METADATA_BUILD_TIMESTAMP, timestamp);
+    }
+
+    for (key, value) in entries {
+        executable.set_metadata(*key, *value);
+    }
+}
+
+fn source_name(input_path_str: &str) -> &str {
+    if input_path_str == STDIO_PATH {
+        "<stdin>"
+    } else {
+        input_path_str
+    }
+}
+
+fn vasm(input_path_strs: &[&str], outputs: &Outputs, options: &vasm::Options) -> Result<(), Error> {
+    // Read input files
+    let mut inputs = Vec::new();
+    for input_path_str in input_path_strs {
+        let input_path = Path::new(input_path_str);
+        let input = read_input(input_path)
+            .map_err(|err| Error::Io(err, IOErrorContext::ReadInput, input_path.to_owned()))?;
+        inputs.push(input);
+    }
+    let sources: Vec<Source> = input_path_strs
+        .iter()
+        .zip(inputs.iter())
+        .map(|(path, input)| Source {
+            name: source_name(path),
+            text: input,
         })
-    })?;
+        .collect();
 
-    let output_path: PathBuf = output
-        .map(PathBuf::from)
-        .unwrap_or_else(|| input_path.with_extension("vex"));
+    // Perform parse
+    let mut assembly = vasm::assemble_sources(&sources, options).map_err(Error::Vasm)?;
+    if outputs.embed_debug_info {
+        let debug_info = assembly.debug_info.clone();
+        assembly.executable.set_debug_info(Some(debug_info));
+    }
+    assembly.executable.set_compressed(outputs.compress);
+    set_metadata(
+        &mut assembly.executable,
+        input_path_strs[0],
+        &outputs.metadata,
+    );
+
+    for warning in assembly.warnings.iter() {
+        eprintln!("Warning:\n{}", warning);
+    }
+
+    if outputs.dump_optimizations {
+        let stderr = std::io::stderr();
+        vasm::write_optimizations(&mut stderr.lock(), &sources, &assembly).map_err(|err| {
+            Error::Io(err, IOErrorContext::WriteOutput, PathBuf::from("<stderr>"))
+        })?;
+    }
+
+    // The default output path is derived from the first input file
+    let format = outputs.format;
+    let output_path: PathBuf = match outputs.output {
+        Some(path) => PathBuf::from(path),
+        None if input_path_strs[0] == STDIO_PATH => PathBuf::from(STDIO_PATH),
+        None => Path::new(input_path_strs[0]).with_extension(format.extension()),
+    };
 
     // Write output file
-    vex::write_file(&output_path, &executable)
-        .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, output_path))?;
+    let mut written = write_output(&output_path, format, &assembly.executable)?;
 
     // Write source map file (if path is set)
-    if let Some(map_path_str) = map {
+    if let Some(map_path_str) = outputs.source_map {
         let map_path = PathBuf::from(map_path_str);
-        write_source_map(&source_map[..], &map_path)
-            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, map_path))?;
+        write_source_map(&assembly.source_map[..], &map_path)
+            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, map_path.clone()))?;
+        written.push(map_path);
+    }
+
+    // Write listing file (if path is set)
+    if let Some(listing_path_str) = outputs.listing {
+        let listing_path = PathBuf::from(listing_path_str);
+        write_listing_file(&sources, &assembly, &listing_path)
+            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, listing_path.clone()))?;
+        written.push(listing_path);
+    }
+
+    // Write symbol map file (if path is set)
+    if let Some(symbol_map_path_str) = outputs.symbol_map {
+        let symbol_map_path = PathBuf::from(symbol_map_path_str);
+        write_symbol_map_file(&assembly, &symbol_map_path)
+            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, symbol_map_path.clone()))?;
+        written.push(symbol_map_path);
+    }
+
+    // Write debug information file (if path is set)
+    if let Some(debug_info_path_str) = outputs.debug_info {
+        let debug_info_path = PathBuf::from(debug_info_path_str);
+        vex::debug::write_file(&debug_info_path, &assembly.debug_info)
+            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, debug_info_path.clone()))?;
+        written.push(debug_info_path);
+    }
+
+    // Write dependency file (if path is set)
+    if let Some(deps_path_str) = outputs.deps {
+        let deps_path = PathBuf::from(deps_path_str);
+        let dependencies: Vec<&str> = input_path_strs
+            .iter()
+            .cloned()
+            .filter(|path| *path != STDIO_PATH)
+            .collect();
+        write_deps_file(&written, &dependencies, &deps_path)
+            .map_err(|err| Error::Io(err, IOErrorContext::WriteOutput, deps_path))?;
     }
     Ok(())
 }
 
+fn write_deps_file(
+    targets: &[PathBuf],
+    dependencies: &[&str],
+    path: &PathBuf,
+) -> std::io::Result<()> {
+    if targets.is_empty() {
+        return Err(std::io::Error::other(
+            "dependency file needs at least one output file",
+        ));
+    }
+    let targets: Vec<String> = targets
+        .iter()
+        .map(|target| target.display().to_string())
+        .collect();
+    let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    vasm::write_depfile(&mut writer, &targets, dependencies)?;
+    writer.flush()
+}
+
 fn write_source_map(source_map: &[SourceMapItem], path: &PathBuf) -> std::io::Result<()> {
     let mut writer = BufWriter::new(File::create(path)?);
     for item in source_map.iter() {
@@ -125,3 +610,19 @@ fn write_source_map(source_map: &[SourceMapItem], path: &PathBuf) -> std::io::Re
     }
     Ok(())
 }
+
+fn write_listing_file(
+    sources: &[Source],
+    assembly: &Assembly,
+    path: &PathBuf,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    vasm::write_listing(&mut writer, sources, assembly)?;
+    writer.flush()
+}
+
+fn write_symbol_map_file(assembly: &Assembly, path: &PathBuf) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    vasm::write_map(&mut writer, &assembly.symbols)?;
+    writer.flush()
+}
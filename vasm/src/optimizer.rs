@@ -0,0 +1,350 @@
+use crate::instructions::{finalize_instruction, JumpTarget, ParsedInstruction};
+use crate::labels::LabelMap;
+use crate::{SourceMap, SourceMapItem};
+use num::FromPrimitive;
+use std::collections::HashSet;
+use std::fmt;
+use vcpu::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptimizationKind {
+    /// An instruction which leaves all registers unchanged, e.g. `COPY $T0, $T0` or `ADDI $T0, $T0, 0`.
+    RedundantMove,
+    /// `LI rd, a` followed by `ADDI rd, rd, b`, which was replaced by `LI rd, a + b`.
+    FoldedImmediate,
+    /// A `JMP` or a conditional branch to the instruction directly after it.
+    JumpToNext,
+}
+
+impl fmt::Display for OptimizationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            OptimizationKind::RedundantMove => "removed redundant move",
+            OptimizationKind::FoldedImmediate => "folded LI and ADDI",
+            OptimizationKind::JumpToNext => "removed jump to the next instruction",
+        })
+    }
+}
+
+/// A change made by the optimizer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Optimization {
+    pub kind: OptimizationKind,
+    /// The replaced instructions, each with the line(s) it was assembled from.
+    pub before: Vec<(SourceMapItem, Word)>,
+    /// The instructions which replaced them, empty if they were removed.
+    pub after: Vec<Word>,
+}
+
+struct Fields {
+    opcode: Option<Opcode>,
+    rd: u32,
+    rs1: u32,
+    rs2: u32,
+    funct: Option<AluFunct>,
+    immediate: Immediate,
+}
+
+fn decode(word: Word) -> Fields {
+    Fields {
+        opcode: Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET),
+        rd: (word & RD_MASK) >> RD_OFFSET,
+        rs1: (word & RS1_MASK) >> RS1_OFFSET,
+        rs2: (word & RS2_MASK) >> RS2_OFFSET,
+        funct: AluFunct::from_u32((word & FUNCT_MASK) >> FUNCT_OFFSET),
+        immediate: ((word & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as Immediate,
+    }
+}
+
+fn is_redundant_move(word: Word) -> bool {
+    let zero = register_index(RegisterId::ZERO) as u32;
+    let f = decode(word);
+    match f.opcode {
+        Some(Opcode::COPY) => f.rd == f.rs1 || f.rd == zero,
+        Some(Opcode::ADDI) | Some(Opcode::SUBI) | Some(Opcode::ORI) | Some(Opcode::XORI)
+        | Some(Opcode::SLLI) | Some(Opcode::SRLI) | Some(Opcode::SRAI) => {
+            f.rd == f.rs1 && f.immediate == 0
+        }
+        Some(Opcode::ALU) => match f.funct {
+            Some(AluFunct::ADD) | Some(AluFunct::OR) | Some(AluFunct::XOR) => {
+                (f.rd == f.rs1 && f.rs2 == zero) || (f.rd == f.rs2 && f.rs1 == zero)
+            }
+            Some(AluFunct::SUB) | Some(AluFunct::SLL) | Some(AluFunct::SRL)
+            | Some(AluFunct::SRA) => f.rd == f.rs1 && f.rs2 == zero,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns `LI rd, a + b` if `first` is `LI rd, a`, `second` is `ADDI rd, rd, b` and the sum fits into the immediate.
+fn fold_immediate(first: Word, second: Word) -> Option<Word> {
+    let (li, addi) = (decode(first), decode(second));
+    if li.opcode != Some(Opcode::LI) || addi.opcode != Some(Opcode::ADDI) {
+        return None;
+    }
+    if addi.rd != li.rd || addi.rs1 != li.rd {
+        return None;
+    }
+    let sum = li.immediate.checked_add(addi.immediate)?;
+    Some((first & !IMMEDIATE_MASK) | ((sum as u16 as Word) << IMMEDIATE_OFFSET))
+}
+
+/// Index of the instruction which a jump target refers to, if it is known.
+fn target_index<T: Into<i64> + num::Num + Copy>(
+    labels: &LabelMap,
+    target: &JumpTarget<T>,
+    current: usize,
+) -> Option<i64> {
+    match target {
+        JumpTarget::Label(label) => labels
+            .get(label.key())
+            .map(|index| Into::<i64>::into(*index)),
+        JumpTarget::Address(offset) => {
+            Some(current as i64 + Into::<i64>::into(*offset) / Into::<i64>::into(WORD_BYTES))
+        }
+    }
+}
+
+fn is_jump_to_next(labels: &LabelMap, instr: &ParsedInstruction, current: usize) -> bool {
+    let next = current as i64 + 1;
+    match instr {
+        ParsedInstruction::Jump {
+            opcode: Opcode::JMP,
+            target,
+        } => target_index(labels, target, current) == Some(next),
+        ParsedInstruction::Branch { target, .. } => {
+            target_index(labels, target, current) == Some(next)
+        }
+        _ => false,
+    }
+}
+
+/// Moves a relative address target so that it refers to the same instruction in the new layout.
+/// Targets outside of the program are kept as they are.
+fn relocate<T: Into<i64> + num::Num + Copy + std::convert::TryFrom<i64>>(
+    target: &mut JumpTarget<T>,
+    current: usize,
+    positions: &[u32],
+) {
+    if let JumpTarget::Address(offset) = target {
+        let bytes = Into::<i64>::into(WORD_BYTES);
+        let index = current as i64 + Into::<i64>::into(*offset) / bytes;
+        if 0 <= index && (index as usize) < positions.len() {
+            let distance = Into::<i64>::into(positions[index as usize])
+                - Into::<i64>::into(positions[current]);
+            if let Ok(new_offset) = T::try_from(distance * bytes) {
+                *offset = new_offset;
+            }
+        }
+    }
+}
+
+/// Applies peephole optimizations to `instructions` until none of them applies anymore.
+///
+/// Instructions which are known to have no effect are removed, and pairs of instructions are merged
+/// if no label refers to the second one. `labels` and `source_map` are updated to the new layout.
+/// Returns all changes which were made, in the order they were made.
+pub fn optimize(
+    instructions: &mut Vec<ParsedInstruction<'_>>,
+    labels: &mut LabelMap,
+    source_map: &mut SourceMap,
+) -> Vec<Optimization> {
+    let mut optimizations = Vec::new();
+
+    loop {
+        let mut targets: HashSet<i64> = labels
+            .values()
+            .map(|index| Into::<i64>::into(*index))
+            .collect();
+        for (i, instr) in instructions.iter().enumerate() {
+            match instr {
+                ParsedInstruction::Branch { target, .. } => {
+                    targets.extend(target_index(labels, target, i))
+                }
+                ParsedInstruction::Jump { target, .. } => {
+                    targets.extend(target_index(labels, target, i))
+                }
+                _ => {}
+            }
+        }
+
+        let mut removed = vec![false; instructions.len()];
+        let mut replaced = Vec::new();
+        let mut i = 0;
+        while i < instructions.len() {
+            let word = match finalize_instruction(labels, &instructions[i], i as u32) {
+                Ok(word) => word,
+                Err(_) => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            if let ParsedInstruction::Complete(first) = instructions[i] {
+                if let Some(ParsedInstruction::Complete(second)) = instructions.get(i + 1) {
+                    if !targets.contains(&(i as i64 + 1)) {
+                        if let Some(folded) = fold_immediate(first, *second) {
+                            optimizations.push(Optimization {
+                                kind: OptimizationKind::FoldedImmediate,
+                                before: vec![(source_map[i], first), (source_map[i + 1], *second)],
+                                after: vec![folded],
+                            });
+                            replaced.push((i, folded));
+                            removed[i + 1] = true;
+                            i += 2;
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            let kind = match instructions[i] {
+                ParsedInstruction::Complete(word) if is_redundant_move(word) => {
+                    Some(OptimizationKind::RedundantMove)
+                }
+                ref instr if is_jump_to_next(labels, instr, i) => {
+                    Some(OptimizationKind::JumpToNext)
+                }
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                optimizations.push(Optimization {
+                    kind,
+                    before: vec![(source_map[i], word)],
+                    after: Vec::new(),
+                });
+                removed[i] = true;
+            }
+            i += 1;
+        }
+
+        if !removed.contains(&true) {
+            return optimizations;
+        }
+
+        for (i, word) in replaced {
+            instructions[i] = ParsedInstruction::Complete(word);
+        }
+
+        let mut positions = Vec::with_capacity(instructions.len() + 1);
+        let mut position = 0;
+        for r in removed.iter() {
+            positions.push(position);
+            if !r {
+                position += 1;
+            }
+        }
+        positions.push(position);
+
+        for index in labels.values_mut() {
+            *index = positions[*index as usize];
+        }
+        for (i, instr) in instructions.iter_mut().enumerate() {
+            match instr {
+                ParsedInstruction::Branch { target, .. } => relocate(target, i, &positions),
+                ParsedInstruction::Jump { target, .. } => relocate(target, i, &positions),
+                _ => {}
+            }
+        }
+
+        let mut r = removed.iter();
+        instructions.retain(|_| !r.next().unwrap());
+        let mut r = removed.iter();
+        source_map.retain(|_| !r.next().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use vcpu::*;
+
+    fn optimized(input: &str) -> Assembly {
+        let options = Options {
+            optimize: true,
+            ..Options::default()
+        };
+        assemble_with_options(input, &options).unwrap()
+    }
+
+    #[test]
+    fn optimize() {
+        let assembly = optimized(
+            ".data
+.instructions
+start:  COPY $T0, $T0
+        LI $T1, 5
+        ADDI $T1, $T1, -2
+        JMP next
+next:   ADD $T2, $T2, $ZERO
+        BNZ $T1, 4
+        LI $T3, 1
+        ADDI $T3, $T3, 1
+        BEZ $T1, start
+        HALT",
+        );
+
+        let expected = transmute_vec(vec![
+            instr_i!(LI, T1, ZERO, 3),
+            instr_i!(LI, T3, ZERO, 2),
+            instr_i!(BEZ, ZERO, T1, -8),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ]);
+        assert_eq!(assembly.executable.instructions(), &expected[..]);
+
+        let kinds: Vec<_> = assembly.optimizations.iter().map(|o| o.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                OptimizationKind::RedundantMove,
+                OptimizationKind::FoldedImmediate,
+                OptimizationKind::JumpToNext,
+                OptimizationKind::RedundantMove,
+                OptimizationKind::JumpToNext,
+                OptimizationKind::FoldedImmediate,
+            ]
+        );
+        assert_eq!(assembly.optimizations[1].before[1].0.start_line, 5);
+        assert_eq!(
+            assembly
+                .source_map
+                .iter()
+                .map(|item| item.start_line)
+                .collect::<Vec<_>>(),
+            vec![4, 9, 11, 12]
+        );
+        assert!(assembly
+            .symbols
+            .iter()
+            .any(|symbol| symbol.name == "next" && symbol.address == 4));
+    }
+
+    #[test]
+    fn keep_jump_targets() {
+        // the ADDI is a jump target, so the pair cannot be folded
+        let assembly = optimized(
+            ".data
+.instructions
+        LI $T0, 1
+loop:   ADDI $T0, $T0, 1
+        JL loop
+        JMP 4
+        HALT",
+        );
+        assert!(assembly
+            .optimizations
+            .iter()
+            .all(|o| o.kind == OptimizationKind::JumpToNext));
+        assert_eq!(
+            assembly.executable.instructions().len(),
+            4 * WORD_BYTES as usize
+        );
+    }
+
+    fn transmute_vec(words: Vec<Word>) -> Vec<u8> {
+        let mut bytes = vec![0; words.len() * WORD_BYTES as usize];
+        <util::Endian as byteorder::ByteOrder>::write_u32_into(&words, &mut bytes);
+        bytes
+    }
+}
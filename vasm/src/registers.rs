@@ -0,0 +1,106 @@
+use crate::*;
+use num::FromPrimitive;
+use pest::iterators::Pair;
+use std::collections::HashMap;
+use vcpu::{RegisterId, RegisterTable, REGISTER_COUNT};
+
+/// Register aliases defined with `.alias`, in addition to the built-in numeric names `R0` to `R31`, and the
+/// [`RegisterTable`](../vcpu/struct.RegisterTable.html) which says which registers exist and what they are
+/// called.
+#[derive(Default)]
+pub struct RegisterAliases {
+    aliases: HashMap<String, RegisterId>,
+    registers: RegisterTable,
+}
+
+fn builtin_alias(name: &str) -> Option<RegisterId> {
+    let upper = name.to_uppercase();
+    let number: usize = upper.strip_prefix('R')?.parse().ok()?;
+    // reject leading zeroes and signs, so that every register has exactly one numeric name
+    if number < REGISTER_COUNT && number.to_string() == upper[1..] {
+        RegisterId::from_usize(number)
+    } else {
+        None
+    }
+}
+
+impl RegisterAliases {
+    pub fn new(registers: RegisterTable) -> RegisterAliases {
+        RegisterAliases {
+            aliases: HashMap::new(),
+            registers,
+        }
+    }
+
+    /// Replaces the register table. Aliases of registers which are not in the new table can no longer be used.
+    pub fn set_registers(&mut self, registers: RegisterTable) {
+        self.registers = registers;
+    }
+
+    fn lookup(&self, name: &str) -> Option<RegisterId> {
+        self.aliases
+            .get(name)
+            .cloned()
+            .or_else(|| builtin_alias(name))
+            .or_else(|| self.registers.find(name))
+    }
+
+    /// Processes an `alias` directive. Aliases may be redefined, the new definition applies
+    /// to all following instructions.
+    pub fn define(&mut self, pair: Pair<Rule>) -> Result<()> {
+        let mut pairs = pair.into_inner();
+        let name = pairs.next().unwrap();
+        let register = self.resolve(pairs.next().unwrap())?;
+
+        if name.as_str().to_uppercase().parse::<RegisterId>().is_ok()
+            || self.registers.find(name.as_str()).is_some()
+        {
+            return Err(new_parser_error(
+                name.as_span(),
+                "Register names cannot be used as alias".to_owned(),
+            ));
+        }
+
+        self.aliases.insert(name.as_str().to_owned(), register);
+        Ok(())
+    }
+
+    /// Returns the id of the register given as a `register` pair, which is either a register name or an alias.
+    pub fn resolve(&self, pair: Pair<Rule>) -> Result<RegisterId> {
+        let inner = pair.into_inner().next().unwrap();
+        let span = inner.as_span();
+        let id = match inner.as_rule() {
+            Rule::register_id | Rule::register_name => {
+                self.registers.find(span.as_str()).ok_or_else(|| {
+                    new_parser_error(span, format!("Register {} was not found", span.as_str()))
+                })?
+            }
+            Rule::identifier => self.lookup(span.as_str()).ok_or_else(|| {
+                new_parser_error(span, "Register or alias was not found".to_owned())
+            })?,
+            _ => unreachable!(),
+        };
+        if !self.registers.contains(id) {
+            return Err(new_parser_error(
+                span,
+                format!("Register {} is not in the register table", id),
+            ));
+        }
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::builtin_alias;
+    use vcpu::RegisterId;
+
+    #[test]
+    fn builtin_aliases() {
+        assert_eq!(Some(RegisterId::ZERO), builtin_alias("r0"));
+        assert_eq!(Some(RegisterId::RA), builtin_alias("R31"));
+        assert_eq!(None, builtin_alias("R32"));
+        assert_eq!(None, builtin_alias("R01"));
+        assert_eq!(None, builtin_alias("Rx"));
+    }
+}
@@ -1,7 +1,59 @@
+use pest::Span;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SourceMapItem {
+    /// Index of the source file the instruction was assembled from.
+    pub file: u32,
     pub start_line: u32,
     pub line_count: u32,
 }
 
 pub type SourceMap = Vec<SourceMapItem>;
+
+/// Associates a data element with its location within the data and the corresponding line(s) in the source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataMapItem {
+    pub offset: u32,
+    pub length: u32,
+    /// Index of the source file the data element was assembled from.
+    pub file: u32,
+    pub start_line: u32,
+    pub line_count: u32,
+}
+
+pub type DataMap = Vec<DataMapItem>;
+
+/// Determines the lines of consecutive elements within a section.
+///
+/// `Position::line_col` scans the whole input up to the position every time, which makes
+/// large programs very slow to assemble. This instead only counts the lines between elements.
+pub struct LineCounter<'i> {
+    text: &'i str,
+    start: usize,
+    position: usize,
+    line: u32,
+}
+
+impl<'i> LineCounter<'i> {
+    pub fn new(section: Span<'i>) -> Self {
+        LineCounter {
+            text: section.as_str(),
+            start: section.start(),
+            position: 0,
+            line: section.start_pos().line_col().0 as u32,
+        }
+    }
+
+    fn count_lines(text: &str) -> u32 {
+        text.bytes().filter(|b| *b == b'\n').count() as u32
+    }
+
+    /// Returns the start line and line count of `span`.
+    /// Must be called with spans in the order they appear in the section.
+    pub fn lines(&mut self, span: Span) -> (u32, u32) {
+        let start = span.start() - self.start;
+        self.line += Self::count_lines(&self.text[self.position..start]);
+        self.position = start;
+        (self.line, Self::count_lines(span.as_str()) + 1)
+    }
+}
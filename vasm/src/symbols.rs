@@ -0,0 +1,128 @@
+use crate::labels::LabelMap;
+use std::fmt;
+use std::io::{self, Write};
+use vcpu::WORD_BYTES;
+
+/// Section of a program which a [`Symbol`](struct.Symbol.html) belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Section {
+    /// Addresses refer to main memory.
+    Data,
+    /// Addresses refer to instruction memory.
+    Instructions,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Section::Data => "data",
+            Section::Instructions => "instructions",
+        })
+    }
+}
+
+/// A label together with its final address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub address: u32,
+}
+
+/// List of all symbols of a program, sorted by section and address.
+pub type SymbolTable = Vec<Symbol>;
+
+pub fn build_symbol_table(
+    data_labels: &LabelMap,
+    data_offset: u32,
+    instr_labels: &LabelMap,
+) -> SymbolTable {
+    // numeric labels are anonymous and therefore not part of the symbol table
+    let named = |labels: &LabelMap| {
+        labels
+            .iter()
+            .filter(|(name, _)| !name.contains(':'))
+            .map(|(name, value)| (name.clone(), *value))
+            .collect::<Vec<_>>()
+    };
+
+    let data_symbols = named(data_labels).into_iter().map(|(name, offset)| Symbol {
+        name,
+        section: Section::Data,
+        address: data_offset + offset,
+    });
+
+    let instr_symbols = named(instr_labels).into_iter().map(|(name, index)| Symbol {
+        name,
+        section: Section::Instructions,
+        address: index * WORD_BYTES,
+    });
+
+    let mut symbols: SymbolTable = data_symbols.chain(instr_symbols).collect();
+    symbols.sort_by(|a, b| (a.section, a.address, &a.name).cmp(&(b.section, b.address, &b.name)));
+    symbols
+}
+
+/// Writes `symbols` as a map file to `writer`, one symbol per line.
+///
+/// Each line consists of the address in hexadecimal notation, the section and the name of the symbol.
+pub fn write_map<W: Write>(writer: &mut W, symbols: &[Symbol]) -> io::Result<()> {
+    for symbol in symbols {
+        writeln!(
+            writer,
+            "{:08X}  {:<12}  {}",
+            symbol.address, symbol.section, symbol.name
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble_program;
+
+    #[test]
+    fn symbol_table() {
+        let input = ".data
+.block 8
+table: .word 1, 2
+.instructions
+main: NOP
+.loop: JMP .loop
+1: HALT";
+
+        let assembly = assemble_program(input, 256).unwrap();
+
+        assert_eq!(
+            assembly.symbols,
+            vec![
+                Symbol {
+                    name: "table".to_owned(),
+                    section: Section::Data,
+                    address: 264,
+                },
+                Symbol {
+                    name: "main".to_owned(),
+                    section: Section::Instructions,
+                    address: 0,
+                },
+                Symbol {
+                    name: "main.loop".to_owned(),
+                    section: Section::Instructions,
+                    address: 4,
+                },
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_map(&mut output, &assembly.symbols).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "00000108  data          table
+00000000  instructions  main
+00000004  instructions  main.loop
+"
+        );
+    }
+}
@@ -1,4 +1,6 @@
 use crate::instructions::*;
+use crate::labels::{LabelMap, LabelRef};
+use crate::warnings::Linter;
 use crate::*;
 use ::pest::{iterators::Pair, Parser, Span};
 use byteorder::ByteOrder;
@@ -17,10 +19,29 @@ macro_rules! hashmap {
     }}
 }
 
+fn assemble(input: &str) -> Result<(Executable, SourceMap)> {
+    assemble_addressed(input, 0)
+}
+
 pub fn parse_rule(rule: Rule, input: &str) -> Result<Pair<Rule>> {
     Ok(VASMParser::parse(rule, input)?.next().unwrap())
 }
 
+fn process_section(pair: Pair<Rule>) -> (Vec<ParsedInstruction>, LabelMap, SourceMap) {
+    let mut output = InstructionOutput::default();
+    process_instructions(
+        pair,
+        0,
+        &mut output,
+        &mut HashMap::new(),
+        &HashMap::new(),
+        &Options::default(),
+        &mut Linter::new(&WarningOptions::default()),
+    )
+    .unwrap();
+    (output.instructions, output.labels, output.source_map)
+}
+
 #[test]
 fn large_unsigned_literal() {
     let input = ".instructions 
@@ -33,7 +54,7 @@ fn large_unsigned_literal() {
     ];
 
     let pair = parse_rule(Rule::instructions, input).unwrap();
-    let (instr, _, _) = process_instructions(pair, &HashMap::new(), 0).unwrap();
+    let (instr, _, _) = process_section(pair);
 
     assert_eq!(instr, expected_instr);
 }
@@ -56,7 +77,7 @@ HALT";
     let expected_labels = HashMap::new();
 
     let pair = parse_rule(Rule::instructions, input).unwrap();
-    let (instr, labels, _) = process_instructions(pair, &HashMap::new(), 0).unwrap();
+    let (instr, labels, _) = process_section(pair);
 
     assert_eq!(instr, expected_instr);
     assert_eq!(labels, expected_labels);
@@ -77,26 +98,27 @@ end:  HALT";
         ParsedInstruction::Complete(instr_i!(SLTI, T2, T0, 32)),
         ParsedInstruction::Branch {
             opcode: Opcode::BEZ,
+            rd: RegisterId::ZERO,
             rs1: RegisterId::T2,
-            target: JumpTarget::Label(Span::new(input, 54, 57).unwrap()),
+            target: JumpTarget::Label(LabelRef::Global(Span::new(input, 54, 57).unwrap())),
         },
         ParsedInstruction::Complete(instr_i!(SLLI, T1, T0, 2)),
         ParsedInstruction::Complete(instr_i!(SW, T0, T1, 0)),
         ParsedInstruction::Complete(instr_i!(ADDI, T0, T0, 1)),
         ParsedInstruction::Jump {
             opcode: Opcode::JMP,
-            target: JumpTarget::Label(Span::new(input, 137, 141).unwrap()),
+            target: JumpTarget::Label(LabelRef::Global(Span::new(input, 137, 141).unwrap())),
         },
         ParsedInstruction::Complete(instr_i!(HALT, ZERO, ZERO, 0)),
     ];
 
     let expected_labels = hashmap![
-        "loop" => 0,
-        "end" => 6
+        "loop".to_owned() => 0,
+        "end".to_owned() => 6
     ];
 
     let pair = parse_rule(Rule::instructions, input).unwrap();
-    let (instr, labels, _) = process_instructions(pair, &HashMap::new(), 0).unwrap();
+    let (instr, labels, _) = process_section(pair);
 
     assert_eq!(instr, expected_instr);
     assert_eq!(labels, expected_labels);
@@ -143,30 +165,37 @@ end:  HALT";
         source_map,
         vec![
             SourceMapItem {
+                file: 0,
                 start_line: 4,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 5,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 6,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 7,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 8,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 9,
                 line_count: 1
             },
             SourceMapItem {
+                file: 0,
                 start_line: 10,
                 line_count: 1
             },
@@ -188,14 +217,17 @@ HALT
         source_map,
         vec![
             SourceMapItem {
+                file: 0,
                 start_line: 4,
                 line_count: 1,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 4,
                 line_count: 1,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 5,
                 line_count: 1,
             },
@@ -231,26 +263,32 @@ NOP
         source_map,
         vec![
             SourceMapItem {
+                file: 0,
                 start_line: 4,
                 line_count: 1,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 5,
                 line_count: 2,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 8,
                 line_count: 1,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 8,
                 line_count: 1,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 10,
                 line_count: 6,
             },
             SourceMapItem {
+                file: 0,
                 start_line: 17,
                 line_count: 1,
             },
@@ -274,6 +312,26 @@ HALT";
     assert_eq!(executable.instructions(), &expected_instr[..]);
 }
 
+#[test]
+fn unsigned_comparison() {
+    let input = ".data
+.instructions
+SLTU $T0, $T1, $T2
+SGTU $T0, $T1, $T2
+SLEU $T0, $T1, $T2
+SGEU $T0, $T1, $T2";
+
+    let expected_instr = transmute_vec(vec![
+        instr_alu!(SLTU, T0, T1, T2),
+        instr_alu!(SGTU, T0, T1, T2),
+        instr_alu!(SLEU, T0, T1, T2),
+        instr_alu!(SGEU, T0, T1, T2),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
 #[test]
 fn unsigned_set_immediate() {
     let input = ".data
@@ -326,6 +384,26 @@ HALT";
     assert_eq!(executable.instructions(), &expected_instr[..]);
 }
 
+#[test]
+fn macro_call_and_ret() {
+    let input = ".data
+.instructions
+CALL double
+HALT
+double: ADD $A0, $A0, $A0
+ret";
+
+    let expected_instr = transmute_vec(vec![
+        instr_j!(JL, jmp_addr_i32(2)),
+        instr_i!(HALT, ZERO, ZERO, 0),
+        instr_alu!(ADD, A0, A0, A0),
+        instr_i!(JR, ZERO, RA, 0),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
 // TODO: add loading macro optimizations (only use one instruction when possible)
 
 #[test]
@@ -402,3 +480,753 @@ HALT";
     let (executable, _) = assemble(input).unwrap();
     assert_eq!(executable.instructions(), &expected_instr[..]);
 }
+
+#[test]
+fn local_labels() {
+    let input = ".data
+.instructions
+first: LI $t0, 4
+.loop: SUBI $t0, $t0, 1
+       BNZ $t0, .loop
+second: LI $t0, 4
+.loop:  SUBI $t0, $t0, 1
+        BNZ $t0, .loop
+        LIA $t1, .loop
+HALT";
+
+    let expected_instr = transmute_vec(vec![
+        instr_i!(LI, T0, ZERO, 4),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, jmp_addr_i16(-1)),
+        instr_i!(LI, T0, ZERO, 4),
+        instr_i!(SUBI, T0, T0, 1),
+        instr_i!(BNZ, ZERO, T0, jmp_addr_i16(-1)),
+        instr_i!(SLO, T1, ZERO, 16),
+        instr_i!(SHI, T1, ZERO, 0),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
+#[test]
+fn numeric_labels() {
+    let input = ".data
+.instructions
+1:  BEZ $t0, 1f
+    JMP 1b
+1:  JMP 1b
+    HALT";
+
+    let expected_instr = transmute_vec(vec![
+        instr_i!(BEZ, ZERO, T0, jmp_addr_i16(2)),
+        instr_j!(JMP, jmp_addr_i32(-1)),
+        instr_j!(JMP, jmp_addr_i32(0)),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
+#[test]
+fn numeric_label_without_definition() {
+    let input = ".data
+.instructions
+    JMP 1b
+1:  HALT";
+
+    assert!(assemble(input).is_err());
+}
+
+#[test]
+fn duplicate_label() {
+    let input = ".data
+.instructions
+label: NOP
+label: HALT";
+
+    assert!(assemble(input).is_err());
+}
+
+fn warning_kinds(assembly: &Assembly) -> Vec<WarningKind> {
+    assembly.warnings.iter().map(|w| w.kind).collect()
+}
+
+#[test]
+fn warnings() {
+    let input = ".data
+padding: .block 1
+value:   .word 0
+.instructions
+start: LDA $T0, value
+       SLLI $T1, $T1, 33
+       JMP 0x2000000
+       NOP
+       HALT";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    assert_eq!(
+        warning_kinds(&assembly),
+        vec![
+            WarningKind::MisalignedData,
+            WarningKind::Truncation,
+            WarningKind::Truncation,
+            WarningKind::UnreachableCode,
+            WarningKind::UnusedLabel,
+            WarningKind::UnusedLabel,
+        ]
+    );
+}
+
+#[test]
+fn labeled_instruction_after_jump_is_reachable() {
+    let input = ".data
+.instructions
+       JMP skip
+       NOP
+skip:  JR $RA
+.loop: JMP .loop";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    assert_eq!(warning_kinds(&assembly), vec![WarningKind::UnreachableCode]);
+}
+
+#[test]
+fn warning_severity() {
+    let input = ".data
+.instructions
+unused: HALT";
+
+    let mut options = Options::default();
+    options
+        .warnings
+        .set_severity(WarningKind::UnusedLabel, Severity::Allow);
+    assert!(assemble_with_options(input, &options)
+        .unwrap()
+        .warnings
+        .is_empty());
+
+    options.warnings.apply_flag("error=unused-label").unwrap();
+    assert!(assemble_with_options(input, &options).is_err());
+}
+
+fn far_branch_program(distance: usize) -> String {
+    format!(
+        ".data
+.instructions
+start: BEZ $T0, far
+{}far:   JMP start",
+        "       NOP\n".repeat(distance)
+    )
+}
+
+#[test]
+fn branch_relaxation() {
+    let input = far_branch_program(9000);
+    let assembly = assemble_program(&input, 0).unwrap();
+    let instructions = assembly.executable.instructions();
+
+    let word = |i: usize| Endian::read_u32(&instructions[i * 4..(i + 1) * 4]);
+    assert_eq!(instructions.len(), 9003 * 4);
+    assert_eq!(word(0), instr_i!(BNZ, ZERO, T0, 8));
+    assert_eq!(word(1), instr_j!(JMP, 9001 * 4));
+    assert_eq!(word(9002), instr_j!(JMP, -9002 * 4));
+    assert_eq!(assembly.source_map.len(), 9003);
+    assert_eq!(assembly.source_map[1].start_line, 3);
+    assert_eq!(assembly.source_map[2].start_line, 4);
+}
+
+#[test]
+fn compare_and_branch() {
+    let input = ".data
+.instructions
+loop:   ADDI $T0, $T0, 1
+        BLT $T0, $T1, loop
+        BGEU $T0, $T1, done
+        NOP
+done:   HALT";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    let instructions = assembly.executable.instructions();
+    let word = |i: usize| Endian::read_u32(&instructions[i * 4..(i + 1) * 4]);
+    assert_eq!(word(1), instr_i!(BLT, T0, T1, -4));
+    assert_eq!(word(2), instr_i!(BGEU, T0, T1, 8));
+
+    let far = format!(
+        ".data\n.instructions\nstart: BNE $T0, $T1, far\n{}far:   JMP start",
+        "       NOP\n".repeat(9000)
+    );
+    let assembly = assemble_program(&far, 0).unwrap();
+    let instructions = assembly.executable.instructions();
+    let word = |i: usize| Endian::read_u32(&instructions[i * 4..(i + 1) * 4]);
+    assert_eq!(word(0), instr_i!(BEQ, T0, T1, 8));
+    assert_eq!(word(1), instr_j!(JMP, 9001 * 4));
+}
+
+#[test]
+fn funnel_shifts() {
+    let input = ".data
+.instructions
+        ROL $T0, $T1, $T2
+        FSL $T0, $T1, $T2, 7
+        FSR $T0, $T1, $T2, 33
+        HALT";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    let instructions = assembly.executable.instructions();
+    let word = |i: usize| Endian::read_u32(&instructions[i * 4..(i + 1) * 4]);
+    assert_eq!(word(0), instr_alu!(ROL, T0, T1, T2));
+    assert_eq!(word(1), instr_funnel!(FSL, T0, T1, T2, 7));
+    assert_eq!(word(2), instr_funnel!(FSR, T0, T1, T2, 1));
+    assert_eq!(warning_kinds(&assembly), vec![WarningKind::Truncation]);
+}
+
+#[test]
+fn byte_instructions() {
+    let input = ".data
+.instructions
+        BSWAP $T0, $T1
+        EXTB $T0, $T1, 3
+        INSH $T0, $T0, $T1, 1
+        HALT";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    let instructions = assembly.executable.instructions();
+    let word = |i: usize| Endian::read_u32(&instructions[i * 4..(i + 1) * 4]);
+    assert_eq!(word(0), instr_alu!(BSWAP, T0, T1, ZERO));
+    assert_eq!(word(1), instr_index!(EXTB, T0, T1, ZERO, 3));
+    assert_eq!(word(2), instr_index!(INSH, T0, T0, T1, 1));
+
+    assert!(assemble(".data\n.instructions\nEXTB $T0, $T1, 4").is_err());
+    assert!(assemble(".data\n.instructions\nINSH $T0, $T1, $T2, 2").is_err());
+}
+
+#[test]
+fn branch_relaxation_disabled() {
+    let options = Options {
+        relax_branches: false,
+        ..Options::default()
+    };
+    assert!(assemble_with_options(&far_branch_program(9000), &options).is_err());
+    assert!(assemble_with_options(&far_branch_program(100), &options).is_ok());
+}
+
+#[test]
+fn base_offset_addressing() {
+    let input = ".data
+.instructions
+LW T0, 8(T1)
+sw $t0, -4(sp)
+LB A0, (A1)
+HALT";
+
+    let expected_instr = transmute_vec(vec![
+        instr_i!(LW, T0, T1, 8),
+        instr_i!(SW, T0, SP, -4),
+        instr_i!(LB, A0, A1, 0),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
+#[test]
+fn register_aliases() {
+    let input = ".data
+.instructions
+.alias ptr, T3
+.alias count, ptr
+       LW count, 4(ptr)
+.alias ptr, R4
+       ADD ptr, r0, R31
+       HALT";
+
+    let expected_instr = transmute_vec(vec![
+        instr_i!(LW, T3, T3, 4),
+        instr_alu!(ADD, A1, ZERO, RA),
+        instr_i!(HALT, ZERO, ZERO, 0),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
+#[test]
+fn invalid_register_aliases() {
+    assert!(assemble(".data\n.instructions\n.alias sp, T0\nHALT").is_err());
+    assert!(assemble(".data\n.instructions\nJR ptr").is_err());
+}
+
+#[test]
+fn small_register_table() {
+    let options = Options {
+        registers: RegisterTable::SMALL,
+        ..Options::default()
+    };
+    let assemble_small =
+        |line: &str| assemble_with_options(&format!(".data\n.instructions\n{}", line), &options);
+
+    let assembly = assemble_small(".alias ptr, R28\nLW $T3, 4(ptr)\nADD S2, A2, RA").unwrap();
+    let expected_instr = transmute_vec(vec![instr_i!(LW, T3, SP, 4), instr_alu!(ADD, S2, A2, RA)]);
+    assert_eq!(assembly.executable.instructions(), &expected_instr[..]);
+
+    assert!(assemble_small("ADD $T0, $T4, $T1").is_err());
+    assert!(assemble_small("LW T0, (S5)").is_err());
+    assert!(assemble_small("JR R12").is_err());
+    assert!(assemble_small(".alias ptr, V1").is_err());
+    assert!(assemble_small("$T0x").is_err());
+}
+
+fn two_sources<'a>(first: &'a str, second: &'a str) -> [Source<'a>; 2] {
+    [
+        Source {
+            name: "first.vasm",
+            text: first,
+        },
+        Source {
+            name: "second.vasm",
+            text: second,
+        },
+    ]
+}
+
+#[test]
+fn multiple_sources() {
+    let sources = two_sources(
+        ".data
+first: .word 1
+.instructions
+main: LDA $T0, second
+1:    JMP helper
+      JMP 1b",
+        ".data
+second: .word 2
+.instructions
+helper: JMP 1f
+1:      HALT",
+    );
+
+    let assembly = assemble_sources(&sources, &Options::default()).unwrap();
+
+    assert_eq!(assembly.executable.data(), &[1, 0, 0, 0, 2, 0, 0, 0]);
+    let address = |name: &str| {
+        assembly
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.address)
+    };
+    assert_eq!(address("second"), Some(4));
+    assert_eq!(address("helper"), Some(16));
+
+    let files: Vec<u32> = assembly.source_map.iter().map(|item| item.file).collect();
+    assert_eq!(files, vec![0, 0, 0, 0, 1, 1]);
+    assert_eq!(assembly.data_map[1].file, 1);
+}
+
+#[test]
+fn multiple_sources_errors() {
+    let sources = two_sources(
+        ".data\n.instructions\nmain: HALT",
+        ".data\n.instructions\nmain: HALT",
+    );
+    let err = assemble_sources(&sources, &Options::default()).unwrap_err();
+    assert_eq!(err.path(), Some("second.vasm"));
+
+    let sources = two_sources(
+        ".data\n.instructions\nJMP 1f",
+        ".data\n.instructions\n1: HALT",
+    );
+    let err = assemble_sources(&sources, &Options::default()).unwrap_err();
+    assert_eq!(err.path(), Some("first.vasm"));
+}
+
+#[test]
+fn assertions() {
+    let input = ".data
+table:     .word 1, 2, 3, 4
+table_end: .block 0
+.assert (table_end - table) & (table_end - table - 1) == 0, \"table size must be a power of two\"
+.instructions
+routine: NOP
+.loop:   BNZ $T0, .loop
+.assert .loop == 4, \"local labels are resolved in the current scope\"
+end:     HALT
+.assert end - routine <= 8, \"routine is too long\"
+.assert table % 0x100 == 0, \"table is not page aligned\"";
+
+    let assembly = assemble_program(input, 0x100).unwrap();
+    assert!(warning_kinds(&assembly).is_empty());
+
+    let err = assemble_program(input, 0x104).unwrap_err();
+    assert!(format!("{}", err).contains("Assertion failed: table is not page aligned"));
+
+    let input = input.replace("<= 8", "< 8");
+    let err = assemble_program(&input, 0x100).unwrap_err();
+    assert!(format!("{}", err).contains("Assertion failed: routine is too long"));
+
+    assert!(assemble(".data\n.instructions\n.assert missing, \"x\"").is_err());
+    let err = assemble(".data\n.instructions\n.assert $SP == 0, \"x\"").unwrap_err();
+    assert!(format!("{}", err).contains("Registers cannot be used in assembler expressions"));
+    let err = assemble(".data\n.instructions\n.assert [0] == 0, \"x\"").unwrap_err();
+    assert!(format!("{}", err).contains("Memory cannot be read in assembler expressions"));
+}
+
+#[test]
+fn standard_library() {
+    let input = ".include <std/string.vasm>
+.include <std/fmt.vasm>
+.include <std/string.vasm>
+.data
+source: .byte 1, 2, 3, 4, 5
+target: .block 5
+number: .block 12
+.instructions
+        LDA $A0, target
+        LDA $A1, source
+        LI $A2, 5
+        JL memcpy
+        LWI $A0, -2147483648
+        LDA $A1, number
+        JL itoa
+        COPY $S0, $V0
+        LDA $A0, number
+        JL strlen
+        HALT";
+
+    let assembly = assemble_program(input, 16).unwrap();
+    assert!(assembly.warnings.is_empty());
+
+    // library files come last, but must not hide warnings about the program
+    let unused = assemble_program(
+        ".include <std/fmt.vasm>\n.data\n.instructions\nunused: HALT",
+        0,
+    )
+    .unwrap();
+    assert_eq!(unused.warnings.len(), 1);
+    assert!(assembly
+        .symbols
+        .iter()
+        .any(|symbol| symbol.name == "itoa" && symbol.section == Section::Instructions));
+
+    let data = assembly.executable.data();
+    let mut memory = vec![0u8; 16 + data.len()];
+    memory[16..].copy_from_slice(data);
+    let mut processor = Processor::new();
+    let exit_code = processor.run(assembly.executable.instructions(), &mut memory);
+
+    assert_eq!(exit_code, ExitCode::Halted);
+    assert_eq!(&memory[21..26], &[1, 2, 3, 4, 5]);
+    assert_eq!(&memory[26..38], b"-2147483648\0");
+    assert_eq!(processor.register(RegisterId::S0).i(), 11);
+    assert_eq!(processor.register(RegisterId::V0).i(), 11);
+
+    let err = assemble(".include <std/missing.vasm>\n.data\n.instructions\nHALT").unwrap_err();
+    assert_eq!(
+        err.variant.message(),
+        "Library file \"std/missing.vasm\" does not exist"
+    );
+}
+
+#[test]
+fn heap() {
+    let input = ".include <std/heap.vasm>
+.data
+.instructions
+        LI $A0, 0x100
+        LI $A1, 0x40
+        JL heap_init
+        LI $A0, 5
+        JL malloc
+        COPY $S0, $V0
+        LI $A0, -8
+        JL malloc
+        COPY $S1, $V0
+        LI $A0, -1
+        JL malloc
+        COPY $S2, $V0
+        LI $A0, 0x38
+        JL malloc
+        COPY $S3, $V0
+        HALT";
+
+    let assembly = assemble_program(input, 0).unwrap();
+    let mut memory = vec![0u8; 0x200];
+    memory[..assembly.executable.data().len()].copy_from_slice(assembly.executable.data());
+    let mut processor = Processor::new();
+    let exit_code = processor.run(assembly.executable.instructions(), &mut memory);
+
+    assert_eq!(exit_code, ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::S0).u(), 0x100);
+    // sizes which wrap around the address space must not return a block
+    assert_eq!(processor.register(RegisterId::S1).u(), 0);
+    assert_eq!(processor.register(RegisterId::S2).u(), 0);
+    // and must leave the heap as it was
+    assert_eq!(processor.register(RegisterId::S3).u(), 0x108);
+}
+
+#[test]
+fn control_registers() {
+    let input = ".include <std/csr.vasm>
+.data
+.instructions
+        CSRW $ZERO, $ZERO, CSR_INSTRET
+        CSRR $T0, CSR_INSTRET
+        CSRR $T1, CSR_MACHINE_ID
+        LI $T2, 1
+        CSRS $T3, $T2, CSR_INSTRET
+        CSRC $T4, $T2, CSR_INSTRET
+        HALT";
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(
+        &executable.instructions()[..8],
+        &transmute_vec(vec![
+            instr_i!(CSRW, ZERO, ZERO, CSR_INSTRET as i16),
+            instr_i!(CSRR, T0, ZERO, CSR_INSTRET as i16),
+        ])[..]
+    );
+
+    let mut processor = Processor::new();
+    let exit_code = processor.run(executable.instructions(), &mut []);
+    assert_eq!(exit_code, ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::T0).u(), 1);
+    assert_eq!(processor.register(RegisterId::T1).u(), MACHINE_ID);
+    assert_eq!(processor.register(RegisterId::T3).u(), 4);
+    // CSRS set bit 0 of 4, so the counter continued at 6
+    assert_eq!(processor.register(RegisterId::T4).u(), 6);
+
+    let err = assemble(".data\n.instructions\nCSRR $T0, CSR_CYCLE").unwrap_err();
+    assert!(format!("{}", err).contains("CSR_CYCLE"));
+}
+
+#[test]
+fn vector_instructions() {
+    // adds two arrays of 8 words, 4 at a time, and sums the lanes of the result
+    let input = ".data
+a:      .word 1, 2, 3, 4, 5, 6, 7, 8
+b:      .word 10, 20, 30, 40, 50, 60, 70, 80
+.instructions
+        LDA $T0, a
+        LDA $T1, b
+        LI $T2, 2
+loop:   VLD $Q0, ($T0)
+        vld q1, (T1)
+        VADD $Q0, $Q0, $Q1
+        VST $Q0, ($T0)
+        ADDI $T0, $T0, 16
+        ADDI $T1, $T1, 16
+        SUBI $T2, $T2, 1
+        BNZ $T2, loop
+        VSPLAT $Q2, $ZERO
+        VINS $Q2, $T2, 3
+        VEXT $V0, $Q0, 3
+        HALT";
+
+    let (executable, _) = assemble(input).unwrap();
+    let words: Vec<Word> = executable
+        .instructions()
+        .chunks(4)
+        .map(util::Endian::read_u32)
+        .collect();
+    assert_eq!(
+        &words[5..8],
+        &[
+            make_vec_instruction(VecFunct::VLD, 0, enum_to_u32(RegisterId::T0), 0),
+            make_vec_instruction(VecFunct::VLD, 1, enum_to_u32(RegisterId::T1), 0),
+            make_vec_instruction(VecFunct::VADD, 0, 0, 1),
+        ]
+    );
+
+    let mut memory = executable.data().to_vec();
+    let mut processor = Processor::new();
+    let exit_code = processor.run(executable.instructions(), &mut memory);
+    assert_eq!(exit_code, ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::V0).u(), 88);
+    assert_eq!(memory[0], 11);
+    assert_eq!(util::Endian::read_u32(&memory[28..32]), 88);
+
+    let err = assemble(".data\n.instructions\nVINS $Q0, $T0, 4").unwrap_err();
+    assert!(format!("{}", err).contains("Vector registers only have lanes 0 to 3"));
+    assert!(assemble(".data\n.instructions\nVADD $Q0, $Q1, $T0").is_err());
+    assert!(assemble(".data\n.instructions\nVADD $Q8, $Q1, $Q2").is_err());
+}
+
+#[test]
+fn register_banks() {
+    // a routine which only uses the shadow bank, and passes its result back in V0
+    let input = ".data
+.instructions
+        LI $T0, 5
+        JL handler
+        HALT
+handler: SWAPB
+        LI $T0, 40
+        RDSH $T1, $T0
+        ADD $T0, $T0, $T1
+        WRSH $V0, $T0
+        SWAPB
+        JR $RA";
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(
+        &executable.instructions()[12..16],
+        &transmute_vec(vec![instr_bank!(SWAPB)])[..]
+    );
+
+    let mut processor = Processor::new();
+    let exit_code = processor.run(executable.instructions(), &mut []);
+    assert_eq!(exit_code, ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::V0).u(), 45);
+    assert_eq!(processor.register(RegisterId::T0).u(), 5);
+    assert_eq!(processor.register(RegisterId::T1).u(), 0);
+
+    assert!(assemble(".data\n.instructions\nSWAPB $T0").is_err());
+    assert!(assemble(".data\n.instructions\nRDSH $Q0, $T0").is_err());
+}
+
+#[test]
+fn interrupt_handler() {
+    // waits until the handler, which runs on the shadow bank, sets T1 of the interrupted program
+    let input = ".include <std/csr.vasm>
+.data
+.instructions
+        LIA $T0, handler
+        CSRW $ZERO, $T0, CSR_IVEC
+        LI $T0, 1
+        CSRS $ZERO, $T0, CSR_IE
+wait:   BEZ $T1, wait
+        HALT
+handler: LI $T0, 1
+        CSRC $ZERO, $T0, CSR_IP
+        LI $T0, 7
+        WRSH $T1, $T0
+        IRET";
+
+    let (executable, _) = assemble(input).unwrap();
+    let instructions = executable.instructions();
+    let mut processor = Processor::new();
+    for _ in 0..20 {
+        processor.tick(instructions, &mut []);
+    }
+    assert!(!processor.is_stopped());
+    processor.raise_interrupt(0);
+    assert_eq!(processor.run(instructions, &mut []), ExitCode::Halted);
+    assert_eq!(processor.register(RegisterId::T0).u(), 1);
+    assert_eq!(processor.register(RegisterId::T1).u(), 7);
+    assert_eq!(processor.csr(CSR_IP), Some(0));
+
+    assert!(assemble(".data\n.instructions\nIRET $T0").is_err());
+}
+
+#[test]
+fn constants() {
+    let input = ".data
+.equ COUNT, 3
+table:  .word 1, 2, 3
+end:    .word 0
+.equ TABLE_SIZE, end - table
+.instructions
+.equ LOOP_SIZE, done - loop
+        LDA $T0, table + 4 * (COUNT - 1)
+        LI $T1, TABLE_SIZE / 4
+        ADDI $T1, $T1, COUNT
+loop:   LIA $T2, done + 4
+        LI $T3, LOOP_SIZE
+done:   LWI $T4, -COUNT
+.assert TABLE_SIZE == COUNT * 4, \"table size\"";
+
+    let expected_instr = transmute_vec(vec![
+        instr_i!(SLO, T0, ZERO, 8),
+        instr_i!(SHI, T0, ZERO, 0),
+        instr_i!(LI, T1, ZERO, 3),
+        instr_i!(ADDI, T1, T1, 3),
+        instr_i!(SLO, T2, ZERO, 32),
+        instr_i!(SHI, T2, ZERO, 0),
+        instr_i!(LI, T3, ZERO, 12),
+        instr_i!(SLO, T4, ZERO, -3),
+        instr_i!(SHI, T4, ZERO, -1),
+    ]);
+
+    let (executable, _) = assemble(input).unwrap();
+    assert_eq!(executable.instructions(), &expected_instr[..]);
+}
+
+#[test]
+fn constant_errors() {
+    let error = |instructions: &str| {
+        let input = format!(
+            ".data
+table: .word 0
+.equ COUNT, 2
+.instructions
+loop: {}
+      JMP loop",
+            instructions
+        );
+        assemble(&input).unwrap_err().variant.message().into_owned()
+    };
+
+    assert_eq!(
+        error("LDA $T0, table * 2"),
+        "Operator cannot be applied to an address"
+    );
+    assert_eq!(
+        error("LDA $T0, -table"),
+        "Operator cannot be applied to an address"
+    );
+    assert_eq!(error("LDA $T0, COUNT"), "LDA expects a data address");
+    assert_eq!(error("LDA $T0, loop"), "Data label was not found");
+    assert_eq!(
+        error("LIA $T0, table"),
+        "LIA expects an instruction address"
+    );
+    assert_eq!(
+        error("LI $T0, table - loop"),
+        "Expression mixes data and instruction addresses"
+    );
+    assert_eq!(error("LI $T0, table + table"), "Cannot add two addresses");
+    assert_eq!(
+        error("LI $T0, COUNT - loop"),
+        "Cannot subtract an address from a number"
+    );
+    assert_eq!(
+        error("LI $T0, 70000 + COUNT"),
+        "Value 70002 does not fit into 16 bits"
+    );
+    assert_eq!(
+        error("NOP\n.equ COUNT, 3"),
+        "Constant \"COUNT\" is already defined"
+    );
+}
+
+#[test]
+fn entry_point() {
+    let input = ".data
+       .word 1
+.instructions
+       .entry main
+helper: JR $RA
+main:  JL helper
+       HALT";
+    let assembly = assemble_program(input, 0x100).unwrap();
+    assert_eq!(assembly.executable.entry_point(), 4);
+    assert_eq!(assembly.executable.memory_size(), 0x104);
+    assert!(assembly.warnings.is_empty());
+
+    let (executable, _) = assemble(".data\n.instructions\nHALT").unwrap();
+    assert_eq!(executable.entry_point(), 0);
+
+    let error = |input: &str| assemble(input).unwrap_err().variant.message().into_owned();
+    assert_eq!(
+        error(".data\n.instructions\n.entry main\nHALT"),
+        "Label not found"
+    );
+    assert_eq!(
+        error(".data\n.instructions\n.entry a\n.entry a\na: HALT"),
+        "Entry point is already defined"
+    );
+}
@@ -213,20 +213,42 @@ fn register() {
         tokens: [ register(0, 3, [register_id(1, 3)]) ]
     };
 
-    fails_with! {
+    // names of custom register tables are only checked when the register is resolved
+    parses_to! {
         parser: VASMParser,
         input: "$bla",
         rule: Rule::register,
-        positives: vec![Rule::register_id],
+        tokens: [ register(0, 4, [register_name(1, 4)]) ]
+    };
+
+    fails_with! {
+        parser: VASMParser,
+        input: "$1bla",
+        rule: Rule::register,
+        positives: vec![Rule::register_name, Rule::register_id],
         negatives: vec![],
         pos: 1
     };
 
+    parses_to! {
+        parser: VASMParser,
+        input: "RA",
+        rule: Rule::register,
+        tokens: [ register(0, 2, [register_id(0, 2)]) ]
+    };
+
+    parses_to! {
+        parser: VASMParser,
+        input: "T0x",
+        rule: Rule::register,
+        tokens: [ register(0, 3, [identifier(0, 3)]) ]
+    };
+
     fails_with! {
         parser: VASMParser,
         input: "$ t0",
         rule: Rule::register,
-        positives: vec![Rule::register_id],
+        positives: vec![Rule::register_name, Rule::register_id],
         negatives: vec![],
         pos: 1
     };
@@ -272,7 +294,7 @@ fn instruction_i() {
             mnemonic_i(0, 4),
             register(6, 11, [ register_id(7, 11) ]),
             register(12, 15, [ register_id(13, 15) ]),
-            int(17, 21, [ dec_int(17, 21) ])
+            immediate(17, 21, [ int(17, 21, [ dec_int(17, 21) ]) ])
         ]) ]
     };
 }
@@ -315,7 +337,7 @@ fn instruction_li() {
         tokens: [ instruction_li(0, 14, [
             mnemonic_li(0, 3),
             register(4, 7, [ register_id(5, 7) ]),
-            int(9, 14, [ oct_uint(9, 14, [ oct_lit(11, 14) ]) ])
+            immediate(9, 14, [ int(9, 14, [ oct_uint(9, 14, [ oct_lit(11, 14) ]) ]) ])
         ]) ]
     };
 }
@@ -368,6 +390,21 @@ fn instruction_br() {
     };
 }
 
+#[test]
+fn instruction_bc() {
+    parses_to! {
+        parser: VASMParser,
+        input: "BLTU $t6, A0, -8",
+        rule: Rule::instruction_bc,
+        tokens: [ instruction_bc(0, 16, [
+            mnemonic_bc(0, 4),
+            register(5, 8, [ register_id(6, 8) ]),
+            register(10, 12, [ register_id(10, 12) ]),
+            jump_target(14, 16, [ int(14, 16, [ dec_int(14, 16) ]) ])
+        ]) ]
+    };
+}
+
 #[test]
 fn instruction_jr() {
     parses_to! {
@@ -394,6 +431,16 @@ fn instruction_ls() {
             register(13, 16, [ register_id(14, 16) ])
         ]) ]
     };
+    parses_to! {
+        parser: VASMParser,
+        input: "LW T0, (sp)",
+        rule: Rule::instruction_ls,
+        tokens: [ instruction_ls(0, 11, [
+            mnemonic_ls(0, 2),
+            register(3, 5, [ register_id(3, 5) ]),
+            register(8, 10, [ register_id(8, 10) ])
+        ]) ]
+    };
 }
 
 #[test]
@@ -450,7 +497,7 @@ fn instruction_lwi() {
         rule: Rule::instruction_lwi,
         tokens: [ instruction_lwi(0, 20, [
             register(4, 7, [ register_id(5, 7) ]),
-            int(10, 20, [ hex_uint(10, 20, [ hex_lit(12, 20) ]) ])
+            immediate(10, 20, [ int(10, 20, [ hex_uint(10, 20, [ hex_lit(12, 20) ]) ]) ])
         ]) ]
     };
 }
@@ -463,7 +510,7 @@ fn instruction_lda() {
         rule: Rule::instruction_lda,
         tokens: [ instruction_lda(0, 19, [
             register(4, 7, [ register_id(5, 7) ]),
-            identifier(9, 19)
+            expression(9, 19, [ identifier(9, 19) ])
         ]) ]
     };
 }
@@ -476,11 +523,122 @@ fn instruction_lia() {
         rule: Rule::instruction_lia,
         tokens: [ instruction_lia(0, 19, [
             register(4, 7, [ register_id(5, 7) ]),
-            identifier(9, 19)
+            expression(9, 19, [ identifier(9, 19) ])
         ]) ]
     };
 }
 
+#[test]
+fn immediate_expression() {
+    parses_to! {
+        parser: VASMParser,
+        input: "addi $t0, $t0, SIZE * -4",
+        rule: Rule::instruction_i,
+        tokens: [ instruction_i(0, 24, [
+            mnemonic_i(0, 4),
+            register(5, 8, [ register_id(6, 8) ]),
+            register(10, 13, [ register_id(11, 13) ]),
+            immediate(15, 24, [ expression(15, 24, [
+                identifier(15, 19),
+                op_mul(20, 21),
+                op_neg(22, 23),
+                uint(23, 24, [ dec_uint(23, 24) ])
+            ]) ])
+        ]) ]
+    };
+}
+
+#[test]
+fn equ() {
+    parses_to! {
+        parser: VASMParser,
+        input: ".equ SIZE, (1 << 4)",
+        rule: Rule::equ,
+        tokens: [ equ(0, 19, [
+            identifier(5, 9),
+            expression(11, 19, [ expression(12, 18, [
+                uint(12, 13, [ dec_uint(12, 13) ]),
+                op_shl(14, 16),
+                uint(17, 18, [ dec_uint(17, 18) ])
+            ]) ])
+        ]) ]
+    };
+}
+
+#[test]
+fn entry() {
+    parses_to! {
+        parser: VASMParser,
+        input: ".entry main",
+        rule: Rule::entry,
+        tokens: [ entry(0, 11, [ identifier(7, 11) ]) ]
+    };
+}
+
+#[test]
+fn local_identifier() {
+    parses_to! {
+        parser: VASMParser,
+        input: ".Lloop_1",
+        rule: Rule::local_identifier,
+        tokens: [ local_identifier(0, 8) ]
+    };
+    fails_with! {
+        parser: VASMParser,
+        input: ".1loop",
+        rule: Rule::local_identifier,
+        positives: vec![Rule::local_identifier],
+        negatives: vec![],
+        pos: 0
+    };
+}
+
+#[test]
+fn numeric_reference() {
+    parses_to! {
+        parser: VASMParser,
+        input: "12f",
+        rule: Rule::numeric_reference,
+        tokens: [ numeric_reference(0, 3) ]
+    };
+    parses_to! {
+        parser: VASMParser,
+        input: "3b",
+        rule: Rule::numeric_reference,
+        tokens: [ numeric_reference(0, 2) ]
+    };
+    fails_with! {
+        parser: VASMParser,
+        input: "0b1",
+        rule: Rule::numeric_reference,
+        positives: vec![Rule::numeric_reference],
+        negatives: vec![],
+        pos: 0
+    };
+}
+
+#[test]
+fn jump_target() {
+    parses_to! {
+        parser: VASMParser,
+        input: "0b11",
+        rule: Rule::jump_target,
+        tokens: [ jump_target(0, 4, [ int(0, 4, [ bin_uint(0, 4, [ bin_lit(2, 4) ]) ]) ]) ]
+    };
+    parses_to! {
+        parser: VASMParser,
+        input: "1b",
+        rule: Rule::jump_target,
+        tokens: [ jump_target(0, 2, [ numeric_reference(0, 2) ]) ]
+    };
+    parses_to! {
+        parser: VASMParser,
+        input: ".end",
+        rule: Rule::jump_target,
+        tokens: [ jump_target(0, 4, [ local_identifier(0, 4) ]) ]
+    };
+}
+
 #[test]
 fn labeled_instruction() {
     parses_to! {
@@ -551,3 +709,34 @@ ADD $t0, $t1, $t2";
         ]) ]
     };
 }
+
+#[test]
+fn alias() {
+    parses_to! {
+        parser: VASMParser,
+        input: ".alias ptr, $T3",
+        rule: Rule::alias,
+        tokens: [ alias(0, 15, [
+            identifier(7, 10),
+            register(12, 15, [ register_id(13, 15) ])
+        ]) ]
+    };
+}
+
+#[test]
+fn assert() {
+    parses_to! {
+        parser: VASMParser,
+        input: ".assert -1f < e, \"x\"",
+        rule: Rule::assert,
+        tokens: [ assert(0, 20, [
+            expression(8, 15, [
+                op_neg(8, 9),
+                numeric_reference(9, 11),
+                op_lt(12, 13),
+                identifier(14, 15)
+            ]),
+            string(17, 20, [ string_inner(18, 19) ])
+        ]) ]
+    };
+}
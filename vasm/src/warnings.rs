@@ -0,0 +1,232 @@
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// All kinds of warnings the assembler can emit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// A value does not fit into its instruction field and is cut off.
+    Truncation,
+    /// A label is defined, but never referenced.
+    UnusedLabel,
+    /// An instruction directly follows an unconditional jump or `HALT` and has no label.
+    UnreachableCode,
+    /// A `.short` or `.word` element does not start at an address which is a multiple of its size.
+    MisalignedData,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 4] = [
+        WarningKind::Truncation,
+        WarningKind::UnusedLabel,
+        WarningKind::UnreachableCode,
+        WarningKind::MisalignedData,
+    ];
+
+    /// The name used to refer to this kind of warning on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::Truncation => "truncation",
+            WarningKind::UnusedLabel => "unused-label",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::MisalignedData => "misaligned-data",
+        }
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for WarningKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        WarningKind::ALL
+            .iter()
+            .find(|kind| kind.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown warning \"{}\"", s))
+    }
+}
+
+/// How the assembler reacts to a kind of warning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The warning is ignored.
+    Allow,
+    /// The warning is reported in [`Assembly::warnings`](struct.Assembly.html#structfield.warnings).
+    Warn,
+    /// The warning is turned into an error and assembling fails.
+    Deny,
+}
+
+/// Severity of each kind of warning. By default, all warnings are enabled and none of them are errors.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WarningOptions {
+    severities: HashMap<WarningKind, Severity>,
+}
+
+impl WarningOptions {
+    pub fn severity(&self, kind: WarningKind) -> Severity {
+        self.severities
+            .get(&kind)
+            .cloned()
+            .unwrap_or(Severity::Warn)
+    }
+
+    pub fn set_severity(&mut self, kind: WarningKind, severity: Severity) {
+        self.severities.insert(kind, severity);
+    }
+
+    /// Applies a command line style warning flag (the part after `-W`):
+    ///
+    /// Flag           | Effect
+    /// ---------------|-------
+    /// `name`         | Enables the warning `name`
+    /// `no-name`      | Disables the warning `name`
+    /// `error`        | Turns all enabled warnings into errors
+    /// `error=name`   | Turns the warning `name` into an error
+    /// `no-error=name`| Turns the warning `name` back into a regular warning
+    pub fn apply_flag(&mut self, flag: &str) -> std::result::Result<(), String> {
+        if flag == "error" {
+            for kind in WarningKind::ALL.iter() {
+                if self.severity(*kind) == Severity::Warn {
+                    self.set_severity(*kind, Severity::Deny);
+                }
+            }
+        } else if let Some(name) = flag.strip_prefix("error=") {
+            self.set_severity(name.parse()?, Severity::Deny);
+        } else if let Some(name) = flag.strip_prefix("no-error=") {
+            self.set_severity(name.parse()?, Severity::Warn);
+        } else if let Some(name) = flag.strip_prefix("no-") {
+            self.set_severity(name.parse()?, Severity::Allow);
+        } else {
+            self.set_severity(flag.parse()?, Severity::Warn);
+        }
+        Ok(())
+    }
+}
+
+/// A warning emitted while assembling a program.
+///
+/// The location and message are stored as an [`Error`](type.Error.html), so warnings can be
+/// formatted the same way as errors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub error: Error,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+/// Collects warnings according to the configured severities.
+pub struct Linter<'i, 'o> {
+    options: &'o WarningOptions,
+    warnings: Vec<Warning>,
+    path: Option<&'o str>,
+    quiet: bool,
+    labels: Vec<(Section, String, Span<'i>, Option<&'o str>)>,
+    used_labels: HashSet<(Section, String)>,
+}
+
+impl<'i, 'o> Linter<'i, 'o> {
+    pub fn new(options: &'o WarningOptions) -> Self {
+        Linter {
+            options,
+            warnings: Vec::new(),
+            path: None,
+            quiet: false,
+            labels: Vec::new(),
+            used_labels: HashSet::new(),
+        }
+    }
+
+    /// Sets the path of the source file which all following warnings are reported in.
+    pub fn set_path(&mut self, path: Option<&'o str>) {
+        self.path = path;
+    }
+
+    /// Ignores all following warnings and label definitions while `quiet` is set, e.g. for library files.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn report(&mut self, kind: WarningKind, span: Span<'_>, message: &str) -> Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        let message = format!("{} [-W{}]", message, kind);
+        let error = with_path(new_parser_error(span, message), self.path);
+        match self.options.severity(kind) {
+            Severity::Allow => Ok(()),
+            Severity::Warn => {
+                self.warnings.push(Warning { kind, error });
+                Ok(())
+            }
+            Severity::Deny => Err(error),
+        }
+    }
+
+    pub fn define_labels(&mut self, section: Section, definitions: Vec<(String, Span<'i>)>) {
+        if self.quiet {
+            return;
+        }
+        let path = self.path;
+        self.labels.extend(
+            definitions
+                .into_iter()
+                .map(|(key, span)| (section, key, span, path)),
+        );
+    }
+
+    pub fn use_label(&mut self, section: Section, key: &str) {
+        self.used_labels.insert((section, key.to_owned()));
+    }
+
+    /// Reports all defined labels which were never used.
+    pub fn report_unused_labels(&mut self) -> Result<()> {
+        let labels = std::mem::take(&mut self.labels);
+        for (section, key, span, path) in labels {
+            if !self.used_labels.contains(&(section, key)) {
+                let message = format!("Label \"{}\" is never used", span.as_str());
+                self.set_path(path);
+                self.report(WarningKind::UnusedLabel, span, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all collected warnings, in the order they were reported.
+    pub fn into_warnings(self) -> Vec<Warning> {
+        self.warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply_flags() {
+        let mut options = WarningOptions::default();
+        options.apply_flag("no-unused-label").unwrap();
+        options.apply_flag("error").unwrap();
+        options.apply_flag("no-error=truncation").unwrap();
+
+        assert_eq!(Severity::Allow, options.severity(WarningKind::UnusedLabel));
+        assert_eq!(Severity::Warn, options.severity(WarningKind::Truncation));
+        assert_eq!(
+            Severity::Deny,
+            options.severity(WarningKind::MisalignedData)
+        );
+        assert!(options.apply_flag("error=unknown").is_err());
+    }
+}
@@ -0,0 +1,434 @@
+//! Generates the C header `vcpu.h` from the sources of this crate, so that it always matches the library.
+//!
+//! Every `#[no_mangle]` function becomes a declaration, `#[repr(C)]` enums and structs become typedefs with
+//! the prefix `Vcpu`, other types used behind pointers become opaque structs and `pub const` items become
+//! macros with the prefix `VCPU_`. Doc comments are kept. The sources are read line by line, so items have
+//! to be formatted by rustfmt.
+//!
+//! The header is written to `OUT_DIR`. With `VCPU_UPDATE_HEADER=1` it also replaces `include/vcpu.h`, which a
+//! test keeps in sync with the generated one.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const PREFIX: &str = "Vcpu";
+const MACRO_PREFIX: &str = "VCPU_";
+/// Lines of declarations which are longer are split into one parameter per line.
+const MAX_LINE: usize = 100;
+
+struct Const {
+    docs: Vec<String>,
+    name: String,
+    ty: String,
+    value: u64,
+}
+
+struct Enum {
+    docs: Vec<String>,
+    name: String,
+    variants: Vec<(Vec<String>, String, i64)>,
+}
+
+struct Struct {
+    docs: Vec<String>,
+    name: String,
+    fields: Vec<(Vec<String>, String, String)>,
+}
+
+/// A function or function pointer type, with a list of parameter names and types.
+struct Function {
+    docs: Vec<String>,
+    name: String,
+    params: Vec<(String, String)>,
+    ret: Option<String>,
+}
+
+#[derive(Default)]
+struct Items {
+    consts: Vec<Const>,
+    enums: Vec<Enum>,
+    structs: Vec<Struct>,
+    callbacks: Vec<Function>,
+    functions: Vec<Function>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=VCPU_UPDATE_HEADER");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let mut paths: Vec<_> = fs::read_dir(Path::new(&manifest_dir).join("src"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+        .filter(|path| path.file_stem().is_some_and(|stem| stem != "test"))
+        .collect();
+    paths.sort();
+
+    let mut items = Items::default();
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        parse(&source, &mut items).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    }
+    let header = generate(&items);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("vcpu.h"), &header).unwrap();
+    if env::var_os("VCPU_UPDATE_HEADER").is_some() {
+        let include = Path::new(&manifest_dir).join("include");
+        fs::create_dir_all(&include).unwrap();
+        fs::write(include.join("vcpu.h"), &header).unwrap();
+    }
+}
+
+fn parse(source: &str, items: &mut Items) -> Result<(), String> {
+    let mut lines = source.lines().map(str::trim);
+    let mut docs = Vec::new();
+    let mut repr_c = false;
+    let mut no_mangle = false;
+    while let Some(line) = lines.next() {
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(doc.strip_prefix(' ').unwrap_or(doc).to_owned());
+            continue;
+        }
+        if line.starts_with("#[") {
+            repr_c |= line == "#[repr(C)]";
+            no_mangle |= line == "#[no_mangle]";
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("pub const ") {
+            let (name, rest) = rest.split_once(':').ok_or("expected the type of a const")?;
+            let (ty, value) = rest
+                .split_once('=')
+                .ok_or("expected the value of a const")?;
+            items.consts.push(Const {
+                docs: std::mem::take(&mut docs),
+                name: name.trim().to_owned(),
+                ty: ty.trim().to_owned(),
+                value: eval(value.trim().trim_end_matches(';'))?,
+            });
+        } else if let (true, Some(rest)) = (repr_c, line.strip_prefix("pub enum ")) {
+            let name = rest.trim_end_matches('{').trim().to_owned();
+            let mut variants = Vec::new();
+            let mut variant_docs = Vec::new();
+            let mut next = 0;
+            for line in lines.by_ref().take_while(|line| *line != "}") {
+                if let Some(doc) = line.strip_prefix("///") {
+                    variant_docs.push(doc.trim().to_owned());
+                    continue;
+                }
+                let variant = line.trim_end_matches(',');
+                let (variant, value) = match variant.split_once('=') {
+                    Some((variant, value)) => (
+                        variant.trim(),
+                        value.trim().parse().map_err(|_| "invalid discriminant")?,
+                    ),
+                    None => (variant, next),
+                };
+                variants.push((std::mem::take(&mut variant_docs), variant.to_owned(), value));
+                next = value + 1;
+            }
+            items.enums.push(Enum {
+                docs: std::mem::take(&mut docs),
+                name,
+                variants,
+            });
+        } else if let (true, Some(rest)) = (repr_c, line.strip_prefix("pub struct ")) {
+            let name = rest.trim_end_matches('{').trim().to_owned();
+            let mut fields = Vec::new();
+            let mut field_docs = Vec::new();
+            for line in lines.by_ref().take_while(|line| *line != "}") {
+                if let Some(doc) = line.strip_prefix("///") {
+                    field_docs.push(doc.trim().to_owned());
+                    continue;
+                }
+                let field = line
+                    .strip_prefix("pub ")
+                    .ok_or("fields of C structs must be public")?;
+                let (field, ty) = field
+                    .split_once(':')
+                    .ok_or("expected the type of a field")?;
+                fields.push((
+                    std::mem::take(&mut field_docs),
+                    field.trim().to_owned(),
+                    ty.trim().trim_end_matches(',').to_owned(),
+                ));
+            }
+            items.structs.push(Struct {
+                docs: std::mem::take(&mut docs),
+                name,
+                fields,
+            });
+        } else if line.starts_with("pub type ") && line.contains("extern \"C\" fn") {
+            let mut declaration = line.to_owned();
+            while !declaration.ends_with(';') {
+                declaration.push_str(lines.next().ok_or("unterminated type")?);
+            }
+            let (name, signature) = declaration["pub type ".len()..]
+                .split_once('=')
+                .ok_or("expected = in a type alias")?;
+            let mut callback = parse_signature(signature.trim().trim_end_matches(';'))?;
+            callback.name = name.trim().to_owned();
+            callback.docs = std::mem::take(&mut docs);
+            items.callbacks.push(callback);
+        } else if no_mangle && line.starts_with("pub ") && line.contains("extern \"C\" fn ") {
+            let mut declaration = line.to_owned();
+            while !declaration.contains('{') {
+                declaration.push_str(lines.next().ok_or("unterminated function")?);
+            }
+            let signature = &declaration[declaration.find("fn ").unwrap()..];
+            let mut function = parse_signature(signature.split('{').next().unwrap().trim())?;
+            function.docs = std::mem::take(&mut docs);
+            items.functions.push(function);
+        }
+        docs.clear();
+        repr_c = false;
+        no_mangle = false;
+    }
+    Ok(())
+}
+
+/// Parses `fn name(a: A, b: B) -> R`, where the name is optional.
+fn parse_signature(signature: &str) -> Result<Function, String> {
+    let signature = signature.trim_start_matches("extern \"C\" ").trim();
+    let signature = signature.strip_prefix("fn").ok_or("expected fn")?.trim();
+    let open = signature.find('(').ok_or("expected (")?;
+    let close = signature.rfind(')').ok_or("expected )")?;
+    let params = signature[open + 1..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, ty) = param
+                .split_once(':')
+                .ok_or("expected the type of a parameter")?;
+            Ok((name.trim().to_owned(), ty.trim().to_owned()))
+        })
+        .collect::<Result<_, String>>()?;
+    let ret = signature[close + 1..]
+        .trim()
+        .strip_prefix("->")
+        .map(|ret| ret.trim().to_owned());
+    Ok(Function {
+        docs: Vec::new(),
+        name: signature[..open].trim().to_owned(),
+        params,
+        ret,
+    })
+}
+
+/// Evaluates constants like `1 << 8`.
+fn eval(expression: &str) -> Result<u64, String> {
+    let number = |text: &str| {
+        let text = text.trim().replace('_', "");
+        match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => text.parse(),
+        }
+        .map_err(|_| format!("unsupported constant {}", expression))
+    };
+    match expression.split_once("<<") {
+        Some((value, shift)) => Ok(number(value)? << number(shift)?),
+        None => number(expression),
+    }
+}
+
+fn prefixed(name: &str) -> String {
+    if name.starts_with(PREFIX) {
+        name.to_owned()
+    } else {
+        format!("{}{}", PREFIX, name)
+    }
+}
+
+fn c_type(ty: &str) -> String {
+    let ty = ty.trim();
+    if let Some(inner) = ty.strip_prefix("*const ") {
+        let inner = c_type(inner);
+        return if inner.ends_with('*') {
+            format!("{} const*", inner)
+        } else {
+            format!("const {}*", inner)
+        };
+    }
+    if let Some(inner) = ty.strip_prefix("*mut ") {
+        return format!("{}*", c_type(inner));
+    }
+    // function pointers are nullable in C anyway
+    if let Some(inner) = ty.strip_prefix("Option<") {
+        return c_type(inner.trim_end_matches('>'));
+    }
+    match ty {
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        "usize" => "size_t",
+        "isize" => "ptrdiff_t",
+        "f32" => "float",
+        "f64" => "double",
+        "bool" => "bool",
+        "c_char" => "char",
+        "c_void" => "void",
+        name => return prefixed(name),
+    }
+    .to_owned()
+}
+
+/// Declares `name` with the type `ty`, e.g. `const char *name` or `const char *const *names`.
+fn declare(ty: &str, name: &str) -> String {
+    let ty = c_type(ty);
+    let base = ty.trim_end_matches('*');
+    format!(
+        "{} {}{}",
+        base.replace("* const", " *const"),
+        &ty[base.len()..],
+        name
+    )
+}
+
+/// Names of the types which are only used behind pointers, without their prefix.
+fn opaque_types(items: &Items) -> BTreeSet<String> {
+    let known: BTreeSet<&str> = items
+        .enums
+        .iter()
+        .map(|e| e.name.as_str())
+        .chain(items.structs.iter().map(|s| s.name.as_str()))
+        .chain(items.callbacks.iter().map(|c| c.name.as_str()))
+        .collect();
+    items
+        .functions
+        .iter()
+        .flat_map(|f| f.params.iter().map(|(_, ty)| ty).chain(f.ret.iter()))
+        .map(|ty| ty.rsplit(' ').next().unwrap().to_owned())
+        .filter(|name| {
+            c_type(name) == prefixed(name)
+                && !known.contains(name.as_str())
+                && !name.starts_with("Option<")
+        })
+        .collect()
+}
+
+/// Replaces rustdoc links like ``[`name`](fn.name.html)`` with their text.
+fn strip_links(line: &str) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("](") {
+        let end = match rest[start..].find(')') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let open = rest[..start].rfind('[').unwrap_or(start);
+        result.push_str(&rest[..open]);
+        result.push_str(&rest[open + 1..start]);
+        rest = &rest[end + 1..];
+    }
+    result + rest
+}
+
+fn write_docs(header: &mut String, docs: &[String], indent: &str) {
+    let docs: Vec<String> = docs.iter().map(|line| strip_links(line)).collect();
+    match docs.as_slice() {
+        [] => {}
+        [line] => header.push_str(&format!("{}/** {} */\n", indent, line)),
+        lines => {
+            header.push_str(&format!("{}/**\n", indent));
+            for line in lines {
+                header.push_str(&format!("{} * {}\n", indent, line).replace(" \n", "\n"));
+            }
+            header.push_str(&format!("{} */\n", indent));
+        }
+    }
+}
+
+/// Formats the parameter list of a function, split into lines if the declaration gets too long.
+fn parameters(prefix: &str, params: &[(String, String)], suffix: &str) -> String {
+    if params.is_empty() {
+        return format!("{}(void){}\n", prefix, suffix);
+    }
+    let params: Vec<String> = params.iter().map(|(name, ty)| declare(ty, name)).collect();
+    let line = format!("{}({}){}", prefix, params.join(", "), suffix);
+    if line.len() <= MAX_LINE {
+        return line + "\n";
+    }
+    format!("{}(\n    {}\n){}\n", prefix, params.join(",\n    "), suffix)
+}
+
+fn generate(items: &Items) -> String {
+    let mut header = String::from(
+        "/* Generated from the sources of vcpu-interop by its build script, do not edit. */\n\n\
+         #ifndef VCPU_H\n\
+         #define VCPU_H\n\n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\n",
+    );
+
+    for constant in items.consts.iter() {
+        write_docs(&mut header, &constant.docs, "");
+        let value = match constant.ty.as_str() {
+            "u64" => format!("UINT64_C(0x{:X})", constant.value),
+            _ => constant.value.to_string(),
+        };
+        header.push_str(&format!(
+            "#define {}{} {}\n",
+            MACRO_PREFIX, constant.name, value
+        ));
+    }
+
+    for item in items.enums.iter() {
+        header.push('\n');
+        write_docs(&mut header, &item.docs, "");
+        let name = prefixed(&item.name);
+        header.push_str(&format!("typedef enum {} {{\n", name));
+        for (docs, variant, value) in item.variants.iter() {
+            write_docs(&mut header, docs, "    ");
+            header.push_str(&format!("    {}_{} = {},\n", name, variant, value));
+        }
+        header.push_str(&format!("}} {};\n", name));
+    }
+
+    header.push('\n');
+    for name in opaque_types(items) {
+        let name = prefixed(&name);
+        header.push_str(&format!("typedef struct {} {};\n", name, name));
+    }
+
+    for callback in items.callbacks.iter() {
+        header.push('\n');
+        write_docs(&mut header, &callback.docs, "");
+        let ret = callback.ret.as_deref().map_or("void".to_owned(), c_type);
+        let prefix = format!("typedef {} (*{})", ret, prefixed(&callback.name));
+        header.push_str(&parameters(&prefix, &callback.params, ";"));
+    }
+
+    for item in items.structs.iter() {
+        header.push('\n');
+        write_docs(&mut header, &item.docs, "");
+        let name = prefixed(&item.name);
+        header.push_str(&format!("typedef struct {} {{\n", name));
+        for (docs, field, ty) in item.fields.iter() {
+            write_docs(&mut header, docs, "    ");
+            header.push_str(&format!("    {};\n", declare(ty, field)));
+        }
+        header.push_str(&format!("}} {};\n", name));
+    }
+
+    header.push_str("\n#ifdef __cplusplus\nextern \"C\" {\n#endif\n");
+    for function in items.functions.iter() {
+        header.push('\n');
+        write_docs(&mut header, &function.docs, "");
+        let prefix = declare(function.ret.as_deref().unwrap_or("c_void"), &function.name);
+        header.push_str(&parameters(&prefix, &function.params, ";"));
+    }
+    header.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif /* VCPU_H */\n");
+    header
+}
@@ -0,0 +1,637 @@
+/* Generated from the sources of vcpu-interop by its build script, do not edit. */
+
+#ifndef VCPU_H
+#define VCPU_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Version of the binary interface, i.e. the signatures of the functions and the layout of the `#[repr(C)]`
+ * enums and structs in `vcpu.h`. Within one ABI version, functions are only added and existing signatures and
+ * layouts never change. Hosts allocate the structs themselves, so adding a field to one is an ABI change as well.
+ */
+#define VCPU_ABI_VERSION 1
+/** The instructions of the floating point unit (`FLOP`). */
+#define VCPU_CAPABILITY_FLOATING_POINT UINT64_C(0x1)
+/** The control and status register instructions (`CSRR`, `CSRW`, `CSRS` and `CSRC`). */
+#define VCPU_CAPABILITY_CONTROL_REGISTERS UINT64_C(0x2)
+/** The vector instructions (`VEC`) and vector registers. */
+#define VCPU_CAPABILITY_VECTOR UINT64_C(0x4)
+/** Interrupts, `IRET` and `vcpu_processor_raise_interrupt`, with the shadow register bank swapped in on entry. */
+#define VCPU_CAPABILITY_INTERRUPTS UINT64_C(0x8)
+/** Memory created with `vcpu_memory_create_plain`. */
+#define VCPU_CAPABILITY_PLAIN_MEMORY UINT64_C(0x100)
+/** Memory created with `vcpu_memory_create_io`, whose writes call back into the host. */
+#define VCPU_CAPABILITY_IO_MEMORY UINT64_C(0x200)
+/** Memory created with `vcpu_memory_create_comp`, which consists of fragments of other memory. */
+#define VCPU_CAPABILITY_COMPOSITE_MEMORY UINT64_C(0x400)
+/** Assembling source, including structured diagnostics. */
+#define VCPU_CAPABILITY_ASSEMBLER UINT64_C(0x10000)
+/** Loading and saving executables in the vex format. */
+#define VCPU_CAPABILITY_VEX UINT64_C(0x20000)
+/** Breakpoints and `vcpu_debugger_run_until_event`. */
+#define VCPU_CAPABILITY_DEBUGGER UINT64_C(0x40000)
+/** Saving and loading the state of a processor and its memory. */
+#define VCPU_CAPABILITY_SAVE_STATE UINT64_C(0x80000)
+/** Handles which can be used from multiple threads and `vcpu_processor_request_stop`. */
+#define VCPU_CAPABILITY_THREADS UINT64_C(0x100000)
+/** Disassembling instructions. */
+#define VCPU_CAPABILITY_DISASSEMBLER UINT64_C(0x200000)
+/** Messages of failed calls with `vcpu_get_last_error_message`, including panics. */
+#define VCPU_CAPABILITY_LAST_ERROR UINT64_C(0x400000)
+/** Running whole programs with the `vcpu_machine_*` functions. */
+#define VCPU_CAPABILITY_MACHINE UINT64_C(0x800000)
+
+typedef enum VcpuDebugEventKind {
+    /** The program counter reached a breakpoint, whose instruction was not executed yet. */
+    VcpuDebugEventKind_Breakpoint = 0,
+    /** The program executed `HALT`. */
+    VcpuDebugEventKind_Halted = 1,
+    /** The processor stopped with an exit code other than `Halted`. */
+    VcpuDebugEventKind_Fault = 2,
+    /** The maximum number of instructions was executed without another event. */
+    VcpuDebugEventKind_Paused = 3,
+    /** The run was stopped with `vcpu_processor_request_stop`. */
+    VcpuDebugEventKind_Terminated = 4,
+} VcpuDebugEventKind;
+
+typedef enum VcpuDiagnosticSeverity {
+    VcpuDiagnosticSeverity_Error = 0,
+    VcpuDiagnosticSeverity_Warning = 1,
+} VcpuDiagnosticSeverity;
+
+typedef enum VcpuResult {
+    VcpuResult_UnknownError = -1,
+    VcpuResult_Ok = 0,
+    VcpuResult_InvalidType = 1,
+    VcpuResult_UTF8Error = 2,
+    VcpuResult_AssemblerError = 3,
+    VcpuResult_MemoryInUse = 4,
+    VcpuResult_FragmentIntersection = 5,
+    VcpuResult_KeyAlreadyExists = 6,
+    VcpuResult_OutOfRange = 7,
+    VcpuResult_ExecutableLoadFailed = 8,
+    VcpuResult_ExecutableSaveFailed = 9,
+    VcpuResult_UnknownName = 10,
+    VcpuResult_StateLoadFailed = 11,
+    VcpuResult_StateSaveFailed = 12,
+    VcpuResult_InvalidInstruction = 13,
+} VcpuResult;
+
+typedef struct VcpuDebugger VcpuDebugger;
+typedef struct VcpuDiagnostics VcpuDiagnostics;
+typedef struct VcpuDisassembly VcpuDisassembly;
+typedef struct VcpuExecutable VcpuExecutable;
+typedef struct VcpuMachine VcpuMachine;
+typedef struct VcpuMemory VcpuMemory;
+typedef struct VcpuProcessor VcpuProcessor;
+typedef struct VcpuSourceMap VcpuSourceMap;
+
+typedef bool (*VcpuCanWriteCallback)(
+    const uint8_t *data,
+    size_t data_len,
+    uint32_t address,
+    uint32_t size,
+    void *user_data
+);
+
+typedef void (*VcpuOnWriteCallback)(
+    const uint8_t *data,
+    size_t data_len,
+    uint32_t address,
+    uint32_t size,
+    void *user_data
+);
+
+/** What made `vcpu_debugger_run_until_event` return. */
+typedef struct VcpuDebugEvent {
+    VcpuDebugEventKind kind;
+    /** Program counter after the event, i.e. the address of the breakpoint or of the next instruction. */
+    uint32_t address;
+    /** Exit code of the processor, -1 if it is still running. */
+    int32_t exit_code;
+    /** Number of instructions executed by this call. */
+    uint64_t executed;
+} VcpuDebugEvent;
+
+/**
+ * An error or warning of the assembler. Lines and columns start at 1, the end is exclusive.
+ * `message` stays valid until the diagnostics are destroyed.
+ */
+typedef struct VcpuDiagnostic {
+    VcpuDiagnosticSeverity severity;
+    uint32_t line;
+    uint32_t column;
+    uint32_t end_line;
+    uint32_t end_column;
+    const char *message;
+} VcpuDiagnostic;
+
+/** A disassembled instruction. `text` stays valid until the disassembly is destroyed. */
+typedef struct VcpuDisassemblyLine {
+    uint32_t address;
+    uint32_t word;
+    /** Whether the instruction has a mnemonic, otherwise `text` is `???`. */
+    bool valid;
+    /** Whether the instruction is a branch or jump to `target`. */
+    bool has_target;
+    uint32_t target;
+    const char *text;
+} VcpuDisassemblyLine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+VcpuDebugger *vcpu_debugger_create(void);
+
+void vcpu_debugger_destroy(VcpuDebugger *debugger);
+
+/** Returns whether the breakpoint was added, i.e. there was none at `address` yet. */
+bool vcpu_debugger_add_breakpoint(VcpuDebugger *debugger, uint32_t address);
+
+/** Returns whether there was a breakpoint at `address`. */
+bool vcpu_debugger_remove_breakpoint(VcpuDebugger *debugger, uint32_t address);
+
+bool vcpu_debugger_has_breakpoint(const VcpuDebugger *debugger, uint32_t address);
+
+void vcpu_debugger_clear_breakpoints(VcpuDebugger *debugger);
+
+size_t vcpu_debugger_get_breakpoint_count(const VcpuDebugger *debugger);
+
+/**
+ * Runs the processor until it stops, reaches a breakpoint or executed `max_instructions` instructions.
+ *
+ * With `max_instructions` 0 the call blocks until one of the first two happens, otherwise it can be called
+ * repeatedly to poll, e.g. from the event loop of a user interface. The instruction at the program counter is
+ * always executed, even if it has a breakpoint, so that the program can be continued after a breakpoint.
+ * If the processor already stopped, nothing is executed and the event reports its exit code.
+ */
+VcpuResult vcpu_debugger_run_until_event(
+    const VcpuDebugger *debugger,
+    VcpuProcessor *processor,
+    const uint8_t *instr,
+    size_t instr_len,
+    VcpuMemory *memory,
+    uint64_t max_instructions,
+    VcpuDebugEvent *event
+);
+
+size_t vcpu_diagnostics_get_count(const VcpuDiagnostics *diagnostics);
+
+VcpuResult vcpu_diagnostics_get(
+    const VcpuDiagnostics *diagnostics,
+    size_t index,
+    VcpuDiagnostic *diagnostic
+);
+
+/**
+ * Returns all diagnostics as one array of `data_len` elements, which stays valid until the diagnostics are
+ * destroyed.
+ */
+void vcpu_diagnostics_get_data(
+    const VcpuDiagnostics *diagnostics,
+    const VcpuDiagnostic **data,
+    size_t *data_len
+);
+
+void vcpu_diagnostics_destroy(VcpuDiagnostics *diagnostics);
+
+/**
+ * Writes the VASM source of the instruction `word` including a terminating null byte into `out_buf`.
+ * Fails with `InvalidInstruction` if the word has no mnemonic, or with `OutOfRange` if `len` is too small.
+ */
+VcpuResult vcpu_disassemble(uint32_t word, char *out_buf, size_t len);
+
+/**
+ * Disassembles `count` instructions of the instruction memory `instr`, starting at `address`. Instructions
+ * which cannot be disassembled are included as `???`, but the range must be within the instruction memory.
+ * The disassembly must be destroyed with `vcpu_disassembly_destroy`.
+ */
+VcpuResult vcpu_disassemble_range(
+    const uint8_t *instr,
+    size_t instr_len,
+    uint32_t address,
+    uint32_t count,
+    VcpuDisassembly **disassembly
+);
+
+size_t vcpu_disassembly_get_count(const VcpuDisassembly *disassembly);
+
+/** Returns all lines as one array of `data_len` elements, which stays valid until the disassembly is destroyed. */
+void vcpu_disassembly_get_data(
+    const VcpuDisassembly *disassembly,
+    const VcpuDisassemblyLine **data,
+    size_t *data_len
+);
+
+void vcpu_disassembly_destroy(VcpuDisassembly *disassembly);
+
+VcpuResult vcpu_executable_assemble(
+    const char *source,
+    uint32_t data_offset,
+    VcpuExecutable **executable,
+    VcpuSourceMap **source_map,
+    const char **error
+);
+
+/**
+ * Assembles `source` like `vcpu_executable_assemble`, but reports all
+ * errors and warnings as structured `diagnostics`, which must be destroyed with `vcpu_diagnostics_destroy`.
+ * They are created whether assembling succeeds or not, unless the source is not valid UTF-8.
+ */
+VcpuResult vcpu_executable_assemble_with_diagnostics(
+    const char *source,
+    uint32_t data_offset,
+    VcpuExecutable **executable,
+    VcpuSourceMap **source_map,
+    VcpuDiagnostics **diagnostics
+);
+
+VcpuResult vcpu_executable_load_vex(
+    const uint8_t *vex_data,
+    size_t vex_data_len,
+    VcpuExecutable **executable
+);
+
+uint32_t vcpu_executable_get_data_offset(const VcpuExecutable *executable);
+
+uint32_t vcpu_executable_get_entry_point(const VcpuExecutable *executable);
+
+uint32_t vcpu_executable_get_memory_size(const VcpuExecutable *executable);
+
+void vcpu_executable_get_instructions(
+    const VcpuExecutable *executable,
+    const uint8_t **instr,
+    size_t *instr_len
+);
+
+void vcpu_executable_get_data(
+    const VcpuExecutable *executable,
+    const uint8_t **data,
+    size_t *data_len
+);
+
+size_t vcpu_executable_get_section_count(const VcpuExecutable *executable);
+
+/**
+ * Returns the section with the given `index`. `flags` receives bit 0 for read-only and bit 1 for zero-filled
+ * sections, whose `data_len` is 0.
+ */
+VcpuResult vcpu_executable_get_section(
+    const VcpuExecutable *executable,
+    size_t index,
+    uint32_t *address,
+    uint32_t *size,
+    uint32_t *flags,
+    const uint8_t **data,
+    size_t *data_len
+);
+
+void vcpu_executable_destroy(VcpuExecutable *executable);
+
+size_t vcpu_executable_get_vex_size(const VcpuExecutable *executable);
+
+VcpuResult vcpu_executable_save_vex(
+    const VcpuExecutable *executable,
+    uint8_t *vex_data,
+    size_t vex_data_len
+);
+
+VcpuResult vcpu_exit_code_get_description(int32_t code, const char **desc);
+
+/**
+ * Creates a machine which is about to execute the first instruction of a copy of `executable`.
+ *
+ * With `ram_size` 0 the RAM has 1 MiB, or more if the program needs it. `devices` is a comma separated list
+ * like `uart@0xFFFF0000`, which is also the default if it is null or empty. Fails with `UnknownName` if a
+ * device is invalid, or with `OutOfRange` if the program does not fit into the RAM or devices overlap.
+ * The machine must be destroyed with `vcpu_machine_destroy`.
+ */
+VcpuResult vcpu_machine_create(
+    const VcpuExecutable *executable,
+    uint32_t ram_size,
+    const char *devices,
+    VcpuMachine **machine
+);
+
+/**
+ * Creates a machine with the default RAM and devices for the program at `path`, which is a vexfile, an ELF
+ * file or assembly source. The path is passed to the program as its first argument.
+ */
+VcpuResult vcpu_machine_create_from_file(const char *path, VcpuMachine **machine);
+
+void vcpu_machine_destroy(VcpuMachine *machine);
+
+/**
+ * Restarts the program with the given command line arguments and environment strings (`NAME=VALUE`), which
+ * are passed like `vcpu-run` does. Fails with `OutOfRange` if they do not fit into the RAM, in which case the
+ * machine is unchanged.
+ */
+VcpuResult vcpu_machine_set_arguments(
+    VcpuMachine *machine,
+    const char *const *args,
+    size_t arg_count,
+    const char *const *env,
+    size_t env_count
+);
+
+/** Restarts the program with fresh memory and clears the console. */
+VcpuResult vcpu_machine_reset(VcpuMachine *machine);
+
+/**
+ * Executes a single instruction and returns the state of the processor afterwards, like
+ * `vcpu_processor_get_state`.
+ */
+int32_t vcpu_machine_step(VcpuMachine *machine);
+
+/**
+ * Executes at most `max_instructions` instructions, or runs until the processor stops if it is 0, and returns
+ * the state of the processor afterwards, which is -1 if the program is still running.
+ */
+int32_t vcpu_machine_run(VcpuMachine *machine, uint64_t max_instructions);
+
+int32_t vcpu_machine_get_state(const VcpuMachine *machine);
+
+/** Number of instructions executed since the program was started. */
+uint64_t vcpu_machine_get_executed(const VcpuMachine *machine);
+
+uint32_t vcpu_machine_get_program_counter(const VcpuMachine *machine);
+
+VcpuResult vcpu_machine_get_register(const VcpuMachine *machine, uint32_t index, int32_t *value);
+
+VcpuResult vcpu_machine_set_register(VcpuMachine *machine, uint32_t index, int32_t value);
+
+/** Copies `length` bytes of the memory at `address`, including devices, to `dest`. */
+VcpuResult vcpu_machine_read_memory(
+    const VcpuMachine *machine,
+    uint32_t address,
+    uint8_t *dest,
+    uint32_t length
+);
+
+/**
+ * Copies `length` bytes from `src` into the memory at `address`. Writes to devices have the same effect as if
+ * the program made them.
+ */
+VcpuResult vcpu_machine_write_memory(
+    VcpuMachine *machine,
+    uint32_t address,
+    const uint8_t *src,
+    uint32_t length
+);
+
+/** Number of bytes the program wrote to the console which were not read yet. */
+size_t vcpu_machine_get_console_len(VcpuMachine *machine);
+
+/** Moves at most `len` bytes of the console output into `buf` and returns how many were moved. */
+size_t vcpu_machine_read_console(VcpuMachine *machine, uint8_t *buf, size_t len);
+
+VcpuMemory *vcpu_memory_create_plain(uint32_t size);
+
+VcpuMemory *vcpu_memory_create_io(
+    uint32_t size,
+    VcpuCanWriteCallback can_write,
+    VcpuOnWriteCallback on_write,
+    void *user_data
+);
+
+/** Replaces the callbacks and the user data of an IO memory, keeping its contents. */
+VcpuResult vcpu_memory_io_set_handler(
+    VcpuMemory *memory,
+    VcpuCanWriteCallback can_write,
+    VcpuOnWriteCallback on_write,
+    void *user_data
+);
+
+VcpuResult vcpu_memory_get_ptr(VcpuMemory *memory, uint8_t **ptr, uint32_t *size);
+
+VcpuResult vcpu_memory_read(
+    const VcpuMemory *memory,
+    uint8_t *dest,
+    uint32_t offset,
+    uint32_t length
+);
+
+VcpuResult vcpu_memory_write(
+    VcpuMemory *memory,
+    const uint8_t *src,
+    uint32_t offset,
+    uint32_t length
+);
+
+VcpuResult vcpu_memory_get_length(const VcpuMemory *memory, uint32_t *length);
+
+VcpuResult vcpu_memory_get_word(const VcpuMemory *memory, uint32_t address, uint32_t *value);
+
+VcpuResult vcpu_memory_get_half(const VcpuMemory *memory, uint32_t address, uint16_t *value);
+
+VcpuResult vcpu_memory_get_byte(const VcpuMemory *memory, uint32_t address, uint8_t *value);
+
+VcpuResult vcpu_memory_set_word(VcpuMemory *memory, uint32_t address, uint32_t value);
+
+VcpuResult vcpu_memory_set_half(VcpuMemory *memory, uint32_t address, uint16_t value);
+
+VcpuResult vcpu_memory_set_byte(VcpuMemory *memory, uint32_t address, uint8_t value);
+
+VcpuResult vcpu_memory_resize(VcpuMemory *memory, uint32_t size);
+
+VcpuMemory *vcpu_memory_create_comp(void);
+
+VcpuResult vcpu_memory_comp_mount(
+    VcpuMemory *memory,
+    uint32_t address,
+    const char *key,
+    VcpuMemory *fragment
+);
+
+VcpuResult vcpu_memory_comp_unmount(VcpuMemory *memory, const char *key);
+
+VcpuResult vcpu_memory_comp_get_fragment_count(const VcpuMemory *memory, size_t *count);
+
+/**
+ * Returns the fragment with the given `index`, in the order of their addresses. The `key` is not null terminated
+ * and stays valid until the fragment is unmounted.
+ */
+VcpuResult vcpu_memory_comp_get_fragment(
+    const VcpuMemory *memory,
+    size_t index,
+    uint32_t *address,
+    uint32_t *length,
+    const uint8_t **key,
+    size_t *key_len
+);
+
+void vcpu_memory_destroy(VcpuMemory *memory);
+
+VcpuProcessor *vcpu_processor_create(void);
+
+VcpuResult vcpu_processor_get_register(
+    const VcpuProcessor *processor,
+    uint32_t index,
+    int32_t *value
+);
+
+VcpuResult vcpu_processor_set_register(VcpuProcessor *processor, uint32_t index, int32_t value);
+
+VcpuResult vcpu_processor_get_register_by_name(
+    const VcpuProcessor *processor,
+    const char *name,
+    int32_t *value
+);
+
+VcpuResult vcpu_processor_set_register_by_name(
+    VcpuProcessor *processor,
+    const char *name,
+    int32_t value
+);
+
+uint32_t vcpu_processor_get_program_counter(const VcpuProcessor *processor);
+
+void vcpu_processor_set_program_counter(VcpuProcessor *processor, uint32_t value);
+
+int32_t vcpu_processor_get_state(const VcpuProcessor *processor);
+
+void vcpu_processor_destroy(VcpuProcessor *processor);
+
+VcpuResult vcpu_processor_tick(
+    VcpuProcessor *processor,
+    const uint8_t *instr,
+    size_t instr_len,
+    VcpuMemory *memory
+);
+
+/** Runs until the processor stops, or another thread requests it to stop with `vcpu_processor_request_stop`. */
+VcpuResult vcpu_processor_run(
+    VcpuProcessor *processor,
+    const uint8_t *instr,
+    size_t instr_len,
+    VcpuMemory *memory
+);
+
+/**
+ * Executes at most `count` instructions and stops early if the processor stops or a stop is requested.
+ * The number of executed instructions, including the one which stopped the processor,
+ * is written to `executed` unless it is null.
+ */
+VcpuResult vcpu_processor_run_for(
+    VcpuProcessor *processor,
+    const uint8_t *instr,
+    size_t instr_len,
+    VcpuMemory *memory,
+    uint64_t count,
+    uint64_t *executed
+);
+
+/**
+ * Requests a run of the processor on another thread to stop, which then stops with `ExitCode::Terminated`
+ * after the current batch of instructions. If the processor is not running, its next run stops immediately.
+ */
+void vcpu_processor_request_stop(const VcpuProcessor *processor);
+
+/**
+ * Makes interrupt `line` (0 to 31) pending, e.g. from an IO callback of a device or from another thread. The
+ * program enters its handler before the next instruction if it enabled the line. This does not lock the
+ * processor, the line is applied to `IP` by the next call which does.
+ */
+VcpuResult vcpu_processor_raise_interrupt(VcpuProcessor *processor, uint32_t line);
+
+/**
+ * Makes interrupt `line` (0 to 31) no longer pending. Like `vcpu_processor_raise_interrupt`, this can be
+ * called from IO callbacks.
+ */
+VcpuResult vcpu_processor_lower_interrupt(VcpuProcessor *processor, uint32_t line);
+
+bool vcpu_processor_is_stopped(const VcpuProcessor *processor);
+
+void vcpu_processor_reset(VcpuProcessor *processor);
+
+uint32_t vcpu_register_get_count(void);
+
+VcpuResult vcpu_register_get_name(uint32_t index, const char **name);
+
+VcpuResult vcpu_register_get_index(const char *name, uint32_t *index);
+
+VcpuResult vcpu_result_get_description(int32_t result, const char **desc);
+
+/**
+ * Returns a description of the last error of the calling thread, e.g. the diagnostics of the assembler or the
+ * address which could not be accessed. Functions only set the message if they fail, so it is only meaningful
+ * right after a call which did not return `Ok`. The string stays valid until the next error on the same thread.
+ */
+const char *vcpu_get_last_error_message(void);
+
+void vcpu_source_map_get_data(
+    const VcpuSourceMap *source_map,
+    const uint32_t **data,
+    size_t *data_len
+);
+
+void vcpu_source_map_destroy(VcpuSourceMap *source_map);
+
+/** Writes the size of the state of `processor` and `memory` to `size`. */
+VcpuResult vcpu_system_get_state_size(
+    const VcpuProcessor *processor,
+    const VcpuMemory *memory,
+    size_t *size
+);
+
+/**
+ * Saves the state of `processor` and `memory` into `buffer`, which must hold at least the size returned by
+ * `vcpu_system_get_state_size`.
+ */
+VcpuResult vcpu_system_save_state(
+    const VcpuProcessor *processor,
+    const VcpuMemory *memory,
+    uint8_t *buffer,
+    size_t buffer_len
+);
+
+/** Restores the state of `processor` and `memory` from `buffer`. Nothing is changed if loading fails. */
+VcpuResult vcpu_system_load_state(
+    VcpuProcessor *processor,
+    VcpuMemory *memory,
+    const uint8_t *buffer,
+    size_t buffer_len
+);
+
+VcpuResult vcpu_system_save_state_file(
+    const VcpuProcessor *processor,
+    const VcpuMemory *memory,
+    const char *path
+);
+
+VcpuResult vcpu_system_load_state_file(
+    VcpuProcessor *processor,
+    VcpuMemory *memory,
+    const char *path
+);
+
+void *vcpu_memcpy(void *dst, const void *src, size_t length);
+
+/**
+ * Writes the version of this library. Versions with the same major version are compatible, as long as the
+ * minor version is at least the one the host was built against.
+ */
+void vcpu_get_version(uint32_t *major, uint32_t *minor, uint32_t *patch);
+
+/**
+ * Returns the `ABI_VERSION` of this library, which has to be equal to the one
+ * of the header the host was built with.
+ */
+uint32_t vcpu_get_abi_version(void);
+
+/**
+ * Returns the `CAPABILITY_*` bits of everything this library supports, so that hosts can check for features
+ * of newer versions at runtime. Bits 0 to 7 are instruction set extensions, bits 8 to 15 kinds of memory and
+ * bits 16 and above groups of functions.
+ */
+uint64_t vcpu_get_capabilities(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* VCPU_H */
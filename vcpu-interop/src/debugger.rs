@@ -0,0 +1,137 @@
+use crate::memory::Memory;
+use crate::processor::Processor;
+use crate::result::{contain, VcpuResult};
+use crate::util::{destroy, into_ptr};
+use num_traits::ToPrimitive;
+use std::collections::BTreeSet;
+use std::slice;
+use vcpu::ExitCode;
+
+/// The breakpoints of a processor, which is run with `vcpu_debugger_run_until_event`.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u32>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DebugEventKind {
+    /// The program counter reached a breakpoint, whose instruction was not executed yet.
+    Breakpoint = 0,
+    /// The program executed `HALT`.
+    Halted = 1,
+    /// The processor stopped with an exit code other than `Halted`.
+    Fault = 2,
+    /// The maximum number of instructions was executed without another event.
+    Paused = 3,
+    /// The run was stopped with `vcpu_processor_request_stop`.
+    Terminated = 4,
+}
+
+/// What made `vcpu_debugger_run_until_event` return.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DebugEvent {
+    pub kind: DebugEventKind,
+    /// Program counter after the event, i.e. the address of the breakpoint or of the next instruction.
+    pub address: u32,
+    /// Exit code of the processor, -1 if it is still running.
+    pub exit_code: i32,
+    /// Number of instructions executed by this call.
+    pub executed: u64,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_create() -> *mut Debugger {
+    contain(|| into_ptr(Debugger::default()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_destroy(debugger: *mut Debugger) {
+    contain(|| destroy(debugger))
+}
+
+/// Returns whether the breakpoint was added, i.e. there was none at `address` yet.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_add_breakpoint(
+    debugger: *mut Debugger,
+    address: u32,
+) -> bool {
+    contain(|| (*debugger).breakpoints.insert(address))
+}
+
+/// Returns whether there was a breakpoint at `address`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_remove_breakpoint(
+    debugger: *mut Debugger,
+    address: u32,
+) -> bool {
+    contain(|| (*debugger).breakpoints.remove(&address))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_has_breakpoint(
+    debugger: *const Debugger,
+    address: u32,
+) -> bool {
+    contain(|| (*debugger).breakpoints.contains(&address))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_clear_breakpoints(debugger: *mut Debugger) {
+    contain(|| (*debugger).breakpoints.clear())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_get_breakpoint_count(debugger: *const Debugger) -> usize {
+    contain(|| (*debugger).breakpoints.len())
+}
+
+/// Runs the processor until it stops, reaches a breakpoint or executed `max_instructions` instructions.
+///
+/// With `max_instructions` 0 the call blocks until one of the first two happens, otherwise it can be called
+/// repeatedly to poll, e.g. from the event loop of a user interface. The instruction at the program counter is
+/// always executed, even if it has a breakpoint, so that the program can be continued after a breakpoint.
+/// If the processor already stopped, nothing is executed and the event reports its exit code.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_debugger_run_until_event(
+    debugger: *const Debugger,
+    processor: *mut Processor,
+    instr: *const u8,
+    instr_len: usize,
+    memory: *mut Memory,
+    max_instructions: u64,
+    event: *mut DebugEvent,
+) -> VcpuResult {
+    contain(|| {
+        let breakpoints = &(*debugger).breakpoints;
+        let at_breakpoint =
+            |processor: &vcpu::Processor| breakpoints.contains(&processor.program_counter());
+        let instructions = slice::from_raw_parts(instr, instr_len);
+        let result =
+            (*processor).run_batched(instructions, &mut *memory, None, |processor, executed| {
+                (executed > 0 && at_breakpoint(processor))
+                    || (max_instructions != 0 && executed == max_instructions)
+            });
+        let executed = match result {
+            Ok(executed) => executed,
+            Err(result) => return result,
+        };
+
+        let processor = (*processor).lock();
+        let kind = match processor.state() {
+            Some(ExitCode::Halted) => DebugEventKind::Halted,
+            Some(ExitCode::Terminated) => DebugEventKind::Terminated,
+            Some(_) => DebugEventKind::Fault,
+            None if executed > 0 && at_breakpoint(&processor) => DebugEventKind::Breakpoint,
+            None => DebugEventKind::Paused,
+        };
+        *event = DebugEvent {
+            kind,
+            address: processor.program_counter(),
+            exit_code: processor.state().map_or(-1, |code| code.to_i32().unwrap()),
+            executed,
+        };
+        VcpuResult::Ok
+    })
+}
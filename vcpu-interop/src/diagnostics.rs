@@ -0,0 +1,113 @@
+use crate::result::{contain, fail, VcpuResult};
+use crate::util::destroy;
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiagnosticSeverity {
+    Error = 0,
+    Warning = 1,
+}
+
+/// An error or warning of the assembler. Lines and columns start at 1, the end is exclusive.
+/// `message` stays valid until the diagnostics are destroyed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    pub message: *const c_char,
+}
+
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+    /// Owns the messages the items point to.
+    _messages: Vec<CString>,
+}
+
+impl Diagnostics {
+    pub fn new(diagnostics: &[vasm::Diagnostic]) -> Self {
+        let messages: Vec<CString> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                CString::new(diagnostic.message.replace('\0', " ")).unwrap_or_default()
+            })
+            .collect();
+        // the pointers stay valid when the vector is moved, since they point into the heap
+        let items = diagnostics
+            .iter()
+            .zip(messages.iter())
+            .map(|(diagnostic, message)| Diagnostic {
+                severity: match diagnostic.severity {
+                    vasm::DiagnosticSeverity::Error => DiagnosticSeverity::Error,
+                    vasm::DiagnosticSeverity::Warning => DiagnosticSeverity::Warning,
+                },
+                line: diagnostic.start.line as u32,
+                column: diagnostic.start.column as u32,
+                end_line: diagnostic.end.line as u32,
+                end_column: diagnostic.end.column as u32,
+                message: message.as_ptr(),
+            })
+            .collect();
+        Diagnostics {
+            items,
+            _messages: messages,
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_diagnostics_get_count(diagnostics: *const Diagnostics) -> usize {
+    contain(|| (*diagnostics).items.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_diagnostics_get(
+    diagnostics: *const Diagnostics,
+    index: usize,
+    diagnostic: *mut Diagnostic,
+) -> VcpuResult {
+    contain(|| {
+        let items = &(*diagnostics).items;
+        match items.get(index) {
+            Some(item) => {
+                *diagnostic = *item;
+                VcpuResult::Ok
+            }
+            None => fail(
+                VcpuResult::OutOfRange,
+                format!(
+                    "Diagnostic {} does not exist, there are {} diagnostics",
+                    index,
+                    items.len()
+                ),
+            ),
+        }
+    })
+}
+
+/// Returns all diagnostics as one array of `data_len` elements, which stays valid until the diagnostics are
+/// destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_diagnostics_get_data(
+    diagnostics: *const Diagnostics,
+    data: *mut *const Diagnostic,
+    data_len: *mut usize,
+) {
+    contain(|| {
+        let items = &(*diagnostics).items;
+        *data = items.as_ptr();
+        *data_len = items.len();
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_diagnostics_destroy(diagnostics: *mut Diagnostics) {
+    contain(|| {
+        destroy(diagnostics);
+    })
+}
@@ -0,0 +1,155 @@
+use crate::result::{contain, fail, VcpuResult};
+use crate::util::{destroy, into_ptr};
+use byteorder::ByteOrder;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::slice;
+use util::Endian;
+use vcpu::{Word, WORD_BYTES};
+
+/// Text of instructions which cannot be disassembled.
+const UNKNOWN: &str = "???";
+
+/// A disassembled instruction. `text` stays valid until the disassembly is destroyed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DisassemblyLine {
+    pub address: u32,
+    pub word: u32,
+    /// Whether the instruction has a mnemonic, otherwise `text` is `???`.
+    pub valid: bool,
+    /// Whether the instruction is a branch or jump to `target`.
+    pub has_target: bool,
+    pub target: u32,
+    pub text: *const c_char,
+}
+
+pub struct Disassembly {
+    lines: Vec<DisassemblyLine>,
+    /// Owns the texts the lines point to.
+    _texts: Vec<CString>,
+}
+
+/// Writes the VASM source of the instruction `word` including a terminating null byte into `out_buf`.
+/// Fails with `InvalidInstruction` if the word has no mnemonic, or with `OutOfRange` if `len` is too small.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_disassemble(
+    word: u32,
+    out_buf: *mut c_char,
+    len: usize,
+) -> VcpuResult {
+    contain(|| {
+        let text = match vasm::disassemble(word) {
+            Some(text) => text,
+            None => {
+                return fail(
+                    VcpuResult::InvalidInstruction,
+                    format!("0x{:08X} is not an instruction", word),
+                )
+            }
+        };
+        if text.len() >= len {
+            return fail(
+                VcpuResult::OutOfRange,
+                format!(
+                    "The disassembly needs {} bytes, but the buffer only has {}",
+                    text.len() + 1,
+                    len
+                ),
+            );
+        }
+        let buffer = slice::from_raw_parts_mut(out_buf as *mut u8, text.len() + 1);
+        buffer[..text.len()].copy_from_slice(text.as_bytes());
+        buffer[text.len()] = 0;
+        VcpuResult::Ok
+    })
+}
+
+/// Disassembles `count` instructions of the instruction memory `instr`, starting at `address`. Instructions
+/// which cannot be disassembled are included as `???`, but the range must be within the instruction memory.
+/// The disassembly must be destroyed with `vcpu_disassembly_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_disassemble_range(
+    instr: *const u8,
+    instr_len: usize,
+    address: u32,
+    count: u32,
+    disassembly: *mut *mut Disassembly,
+) -> VcpuResult {
+    contain(|| {
+        let instructions = slice::from_raw_parts(instr, instr_len);
+        let start = address as usize;
+        let end = start.saturating_add(count as usize * WORD_BYTES as usize);
+        if !address.is_multiple_of(WORD_BYTES) || end > instructions.len() {
+            return fail(
+                VcpuResult::OutOfRange,
+                format!(
+                    "{} instructions at 0x{:08X} are outside of the {} bytes of instructions",
+                    count,
+                    address,
+                    instructions.len()
+                ),
+            );
+        }
+
+        let words: Vec<(u32, Word)> = instructions[start..end]
+            .chunks_exact(WORD_BYTES as usize)
+            .enumerate()
+            .map(|(i, bytes)| (address + i as u32 * WORD_BYTES, Endian::read_u32(bytes)))
+            .collect();
+        let texts: Vec<CString> = words
+            .iter()
+            .map(|(_, word)| {
+                let text = vasm::disassemble(*word).unwrap_or_else(|| UNKNOWN.to_owned());
+                CString::new(text).unwrap_or_default()
+            })
+            .collect();
+        // the pointers stay valid when the vector is moved, since they point into the heap
+        let lines = words
+            .iter()
+            .zip(texts.iter())
+            .map(|((address, word), text)| {
+                let target = vasm::jump_target(*word, *address);
+                DisassemblyLine {
+                    address: *address,
+                    word: *word,
+                    valid: vasm::disassemble(*word).is_some(),
+                    has_target: target.is_some(),
+                    target: target.unwrap_or(0),
+                    text: text.as_ptr(),
+                }
+            })
+            .collect();
+        *disassembly = into_ptr(Disassembly {
+            lines,
+            _texts: texts,
+        });
+        VcpuResult::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_disassembly_get_count(disassembly: *const Disassembly) -> usize {
+    contain(|| (*disassembly).lines.len())
+}
+
+/// Returns all lines as one array of `data_len` elements, which stays valid until the disassembly is destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_disassembly_get_data(
+    disassembly: *const Disassembly,
+    data: *mut *const DisassemblyLine,
+    data_len: *mut usize,
+) {
+    contain(|| {
+        let lines = &(*disassembly).lines;
+        *data = lines.as_ptr();
+        *data_len = lines.len();
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_disassembly_destroy(disassembly: *mut Disassembly) {
+    contain(|| {
+        destroy(disassembly);
+    })
+}
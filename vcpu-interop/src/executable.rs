@@ -1,12 +1,12 @@
-use crate::result::VcpuResult;
+use crate::diagnostics::Diagnostics;
+use crate::result::{contain, fail, last_error, VcpuResult};
 use crate::source_map::SourceMap;
 use crate::util::{destroy, into_ptr};
 use std::os::raw::c_char;
-use vasm::assemble_addressed;
+use vasm::{assemble, assemble_addressed, Options};
 use vex::{Executable, ReadVexExt, WriteVexExt};
 
-use std::cell::Cell;
-use std::ffi::{CStr, CString};
+use std::ffi::CStr;
 use std::slice;
 
 #[no_mangle]
@@ -17,38 +17,76 @@ pub unsafe extern "C" fn vcpu_executable_assemble(
     source_map: *mut *mut SourceMap,
     error: *mut *const c_char,
 ) -> VcpuResult {
-    match CStr::from_ptr(source).to_str() {
+    contain(|| match CStr::from_ptr(source).to_str() {
         Ok(src) => match assemble_addressed(src, data_offset) {
             Ok((result, result_map)) => {
                 *executable = into_ptr(result);
                 if !source_map.is_null() {
-                    let map_data = result_map
-                        .into_iter()
-                        .flat_map(|item| vec![item.start_line, item.line_count])
-                        .collect();
-                    let map_copy = SourceMap { data: map_data };
-                    *source_map = into_ptr(map_copy);
+                    *source_map = into_ptr(SourceMap::new(result_map));
                 }
                 VcpuResult::Ok
             }
             Err(err) => {
+                let result = fail(VcpuResult::AssemblerError, err.to_string());
                 if !error.is_null() {
-                    LAST_ERROR.with(|f| {
-                        let err_str = CString::new(format!("{}", err)).unwrap_or_default();
-                        *error = err_str.as_ptr();
-                        f.set(err_str);
-                    });
+                    *error = last_error();
                 }
-                VcpuResult::AssemblerError
+                result
             }
         },
 
-        Err(_) => VcpuResult::UTF8Error,
-    }
+        Err(err) => fail(
+            VcpuResult::UTF8Error,
+            format!("Source is not valid UTF-8: {}", err),
+        ),
+    })
 }
 
-thread_local! {
-    static LAST_ERROR: Cell<CString> = Cell::new(Default::default());
+/// Assembles `source` like [`vcpu_executable_assemble`](fn.vcpu_executable_assemble.html), but reports all
+/// errors and warnings as structured `diagnostics`, which must be destroyed with `vcpu_diagnostics_destroy`.
+/// They are created whether assembling succeeds or not, unless the source is not valid UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_executable_assemble_with_diagnostics(
+    source: *const c_char,
+    data_offset: u32,
+    executable: *mut *mut Executable,
+    source_map: *mut *mut SourceMap,
+    diagnostics: *mut *mut Diagnostics,
+) -> VcpuResult {
+    contain(|| {
+        let src = match CStr::from_ptr(source).to_str() {
+            Ok(src) => src,
+            Err(err) => {
+                return fail(
+                    VcpuResult::UTF8Error,
+                    format!("Source is not valid UTF-8: {}", err),
+                )
+            }
+        };
+        let options = Options {
+            data_offset,
+            ..Options::default()
+        };
+        match assemble(src, &options) {
+            Ok(assembly) => {
+                *diagnostics = into_ptr(Diagnostics::new(&assembly.diagnostics()));
+                *executable = into_ptr(assembly.executable);
+                if !source_map.is_null() {
+                    *source_map = into_ptr(SourceMap::new(assembly.source_map));
+                }
+                VcpuResult::Ok
+            }
+            Err(errors) => {
+                *diagnostics = into_ptr(Diagnostics::new(&errors));
+                let message = errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                fail(VcpuResult::AssemblerError, message)
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -57,18 +95,33 @@ pub unsafe extern "C" fn vcpu_executable_load_vex(
     vex_data_len: usize,
     executable: *mut *mut Executable,
 ) -> VcpuResult {
-    match slice::from_raw_parts(vex_data, vex_data_len).read_vex() {
-        Ok(result) => {
-            *executable = into_ptr(result);
-            VcpuResult::Ok
-        }
-        Err(_) => VcpuResult::ExecutableLoadFailed,
-    }
+    contain(
+        || match slice::from_raw_parts(vex_data, vex_data_len).read_vex() {
+            Ok(result) => {
+                *executable = into_ptr(result);
+                VcpuResult::Ok
+            }
+            Err(err) => fail(
+                VcpuResult::ExecutableLoadFailed,
+                format!("Loading the vexfile failed: {}", err),
+            ),
+        },
+    )
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_executable_get_data_offset(executable: *const Executable) -> u32 {
-    (*executable).data_offset()
+    contain(|| (*executable).data_offset())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_executable_get_entry_point(executable: *const Executable) -> u32 {
+    contain(|| (*executable).entry_point())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_executable_get_memory_size(executable: *const Executable) -> u32 {
+    contain(|| (*executable).memory_size())
 }
 
 #[no_mangle]
@@ -77,9 +130,11 @@ pub unsafe extern "C" fn vcpu_executable_get_instructions(
     instr: *mut *const u8,
     instr_len: *mut usize,
 ) {
-    let prog_instr = (*executable).instructions();
-    *instr = prog_instr.as_ptr();
-    *instr_len = prog_instr.len();
+    contain(|| {
+        let prog_instr = (*executable).instructions();
+        *instr = prog_instr.as_ptr();
+        *instr_len = prog_instr.len();
+    })
 }
 
 #[no_mangle]
@@ -88,19 +143,60 @@ pub unsafe extern "C" fn vcpu_executable_get_data(
     data: *mut *const u8,
     data_len: *mut usize,
 ) {
-    let prog_data = (*executable).data();
-    *data = prog_data.as_ptr();
-    *data_len = prog_data.len();
+    contain(|| {
+        let prog_data = (*executable).data();
+        *data = prog_data.as_ptr();
+        *data_len = prog_data.len();
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_executable_get_section_count(executable: *const Executable) -> usize {
+    contain(|| (*executable).sections().len())
+}
+
+/// Returns the section with the given `index`. `flags` receives bit 0 for read-only and bit 1 for zero-filled
+/// sections, whose `data_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_executable_get_section(
+    executable: *const Executable,
+    index: usize,
+    address: *mut u32,
+    size: *mut u32,
+    flags: *mut u32,
+    data: *mut *const u8,
+    data_len: *mut usize,
+) -> VcpuResult {
+    contain(|| match (*executable).sections().get(index) {
+        Some(section) => {
+            *address = section.address();
+            *size = section.size();
+            *flags = section.flags().read_only as u32 | (section.flags().zero_fill as u32) << 1;
+            *data = section.bytes().as_ptr();
+            *data_len = section.bytes().len();
+            VcpuResult::Ok
+        }
+        None => fail(
+            VcpuResult::OutOfRange,
+            format!(
+                "Section {} does not exist, the executable has {} sections",
+                index,
+                (*executable).sections().len()
+            ),
+        ),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_executable_destroy(executable: *mut Executable) {
-    destroy(executable);
+    contain(|| {
+        destroy(executable);
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_executable_get_vex_size(executable: *const Executable) -> usize {
-    (&*executable).required_size()
+    contain(|| (&*executable).required_size())
 }
 
 #[no_mangle]
@@ -109,9 +205,14 @@ pub unsafe extern "C" fn vcpu_executable_save_vex(
     vex_data: *mut u8,
     vex_data_len: usize,
 ) -> VcpuResult {
-    let mut output = slice::from_raw_parts_mut(vex_data, vex_data_len);
-    match output.write_vex(&*executable) {
-        Ok(_) => VcpuResult::Ok,
-        Err(_) => VcpuResult::ExecutableSaveFailed,
-    }
+    contain(|| {
+        let mut output = slice::from_raw_parts_mut(vex_data, vex_data_len);
+        match output.write_vex(&*executable) {
+            Ok(_) => VcpuResult::Ok,
+            Err(err) => fail(
+                VcpuResult::ExecutableSaveFailed,
+                format!("Saving the vexfile failed: {}", err),
+            ),
+        }
+    })
 }
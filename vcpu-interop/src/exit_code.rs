@@ -1,4 +1,4 @@
-use crate::result::VcpuResult;
+use crate::result::{contain, fail, VcpuResult};
 use num_traits::FromPrimitive;
 use std::os::raw::c_char;
 use util::InteropGetName;
@@ -8,10 +8,15 @@ pub unsafe extern "C" fn vcpu_exit_code_get_description(
     code: i32,
     desc: *mut *const c_char,
 ) -> VcpuResult {
-    if let Some(code) = vcpu::ExitCode::from_i32(code) {
-        *desc = code.interop_name().as_ptr() as *const c_char;
-        VcpuResult::Ok
-    } else {
-        VcpuResult::OutOfRange
-    }
+    contain(|| {
+        if let Some(code) = vcpu::ExitCode::from_i32(code) {
+            *desc = code.interop_name().as_ptr() as *const c_char;
+            VcpuResult::Ok
+        } else {
+            fail(
+                VcpuResult::OutOfRange,
+                format!("{} is not an exit code", code),
+            )
+        }
+    })
 }
@@ -4,14 +4,20 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 #![allow(clippy::missing_safety_doc)]
 
+mod debugger;
+mod diagnostics;
+mod disassembly;
 mod executable;
 mod exit_code;
+mod machine;
 mod memory;
 mod processor;
 mod register;
 mod result;
 mod source_map;
+mod system;
 mod util;
+mod version;
 
 // TODO: unit tests for all functions
 
@@ -0,0 +1,364 @@
+use crate::result::{contain, fail, VcpuResult};
+use crate::util::{destroy, into_ptr};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::slice;
+use vcpu::{Storage, StorageMut};
+use vcpu_run::config::MachineConfig;
+use vcpu_run::debugger::Session;
+use vcpu_run::monitor::ConsoleBuffer;
+use vcpu_run::Limits;
+use vex::Executable;
+
+/// A program together with the processor, RAM and devices it runs on, like the machine of `vcpu-run`.
+///
+/// The RAM starts at address 0, the stack pointer at its end and the UARTs write to a console buffer, which
+/// is read with `vcpu_machine_read_console`. Unlike processors and memory, a machine must only be used from
+/// the thread which created it.
+pub struct Machine {
+    session: Session,
+    machine: vcpu_run::Machine,
+    console: ConsoleBuffer,
+    /// Console output which was moved out of the buffer, but not read by the host yet.
+    pending: Vec<u8>,
+}
+
+impl Machine {
+    fn new(session: Session) -> Result<Machine, String> {
+        let console = ConsoleBuffer::default();
+        let machine = session.start(Box::new(console.clone()))?;
+        Ok(Machine {
+            session,
+            machine,
+            console,
+            pending: Vec::new(),
+        })
+    }
+
+    fn restart(&mut self) -> Result<(), String> {
+        self.machine = self.session.start(Box::new(self.console.clone()))?;
+        self.console.clear();
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn state(&self) -> i32 {
+        self.machine
+            .processor()
+            .state()
+            .map_or(-1, |code| code.to_i32().unwrap())
+    }
+
+    fn console_output(&mut self) -> &mut Vec<u8> {
+        self.pending.extend(self.console.take());
+        &mut self.pending
+    }
+}
+
+unsafe fn string_arg<'a>(value: *const c_char, what: &str) -> Result<&'a str, VcpuResult> {
+    CStr::from_ptr(value).to_str().map_err(|err| {
+        fail(
+            VcpuResult::UTF8Error,
+            format!("{} is not valid UTF-8: {}", what, err),
+        )
+    })
+}
+
+unsafe fn string_args(
+    values: *const *const c_char,
+    count: usize,
+) -> Result<Vec<String>, VcpuResult> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    slice::from_raw_parts(values, count)
+        .iter()
+        .map(|value| string_arg(*value, "Argument").map(str::to_owned))
+        .collect()
+}
+
+unsafe fn create(
+    executable: Executable,
+    ram_size: u32,
+    devices: *const c_char,
+    args: Vec<String>,
+    machine: *mut *mut Machine,
+) -> VcpuResult {
+    let mut config = MachineConfig::default();
+    if ram_size != 0 {
+        config.ram_size = Some(ram_size);
+    }
+    if !devices.is_null() {
+        let devices = match string_arg(devices, "Devices") {
+            Ok(devices) => devices,
+            Err(result) => return result,
+        };
+        for device in devices.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match device.parse() {
+                Ok(device) => config.devices.push(device),
+                Err(err) => return fail(VcpuResult::UnknownName, err),
+            }
+        }
+    }
+    match Machine::new(Session::new(executable, config, args, Vec::new())) {
+        Ok(created) => {
+            *machine = into_ptr(created);
+            VcpuResult::Ok
+        }
+        Err(err) => fail(VcpuResult::OutOfRange, err),
+    }
+}
+
+/// Creates a machine which is about to execute the first instruction of a copy of `executable`.
+///
+/// With `ram_size` 0 the RAM has 1 MiB, or more if the program needs it. `devices` is a comma separated list
+/// like `uart@0xFFFF0000`, which is also the default if it is null or empty. Fails with `UnknownName` if a
+/// device is invalid, or with `OutOfRange` if the program does not fit into the RAM or devices overlap.
+/// The machine must be destroyed with `vcpu_machine_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_create(
+    executable: *const Executable,
+    ram_size: u32,
+    devices: *const c_char,
+    machine: *mut *mut Machine,
+) -> VcpuResult {
+    contain(|| {
+        create(
+            (*executable).clone(),
+            ram_size,
+            devices,
+            Vec::new(),
+            machine,
+        )
+    })
+}
+
+/// Creates a machine with the default RAM and devices for the program at `path`, which is a vexfile, an ELF
+/// file or assembly source. The path is passed to the program as its first argument.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_create_from_file(
+    path: *const c_char,
+    machine: *mut *mut Machine,
+) -> VcpuResult {
+    contain(|| {
+        let path = match string_arg(path, "Path") {
+            Ok(path) => path,
+            Err(result) => return result,
+        };
+        match vcpu_run::load(path) {
+            Ok(loaded) => create(
+                loaded.executable,
+                0,
+                std::ptr::null(),
+                vec![path.to_owned()],
+                machine,
+            ),
+            Err(err) => fail(VcpuResult::ExecutableLoadFailed, err),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_destroy(machine: *mut Machine) {
+    contain(|| destroy(machine))
+}
+
+/// Restarts the program with the given command line arguments and environment strings (`NAME=VALUE`), which
+/// are passed like `vcpu-run` does. Fails with `OutOfRange` if they do not fit into the RAM, in which case the
+/// machine is unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_set_arguments(
+    machine: *mut Machine,
+    args: *const *const c_char,
+    arg_count: usize,
+    env: *const *const c_char,
+    env_count: usize,
+) -> VcpuResult {
+    contain(|| {
+        let (args, env) = match (string_args(args, arg_count), string_args(env, env_count)) {
+            (Ok(args), Ok(env)) => (args, env),
+            (Err(result), _) | (_, Err(result)) => return result,
+        };
+        let machine = &mut *machine;
+        let old_args = std::mem::replace(&mut machine.session.args, args);
+        let old_env = std::mem::replace(&mut machine.session.env, env);
+        match machine.restart() {
+            Ok(()) => VcpuResult::Ok,
+            Err(err) => {
+                machine.session.args = old_args;
+                machine.session.env = old_env;
+                fail(VcpuResult::OutOfRange, err)
+            }
+        }
+    })
+}
+
+/// Restarts the program with fresh memory and clears the console.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_reset(machine: *mut Machine) -> VcpuResult {
+    contain(|| match (*machine).restart() {
+        Ok(()) => VcpuResult::Ok,
+        Err(err) => fail(VcpuResult::OutOfRange, err),
+    })
+}
+
+/// Executes a single instruction and returns the state of the processor afterwards, like
+/// `vcpu_processor_get_state`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_step(machine: *mut Machine) -> i32 {
+    contain(|| {
+        (*machine).machine.step();
+        (*machine).state()
+    })
+}
+
+/// Executes at most `max_instructions` instructions, or runs until the processor stops if it is 0, and returns
+/// the state of the processor afterwards, which is -1 if the program is still running.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_run(machine: *mut Machine, max_instructions: u64) -> i32 {
+    contain(|| {
+        let machine = &mut *machine;
+        let limits = Limits {
+            max_instructions: Some(max_instructions)
+                .filter(|max| *max != 0)
+                .map(|max| machine.machine.executed().saturating_add(max)),
+            ..Limits::default()
+        };
+        machine.machine.run(&limits);
+        machine.state()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_get_state(machine: *const Machine) -> i32 {
+    contain(|| (*machine).state())
+}
+
+/// Number of instructions executed since the program was started.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_get_executed(machine: *const Machine) -> u64 {
+    contain(|| (*machine).machine.executed())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_get_program_counter(machine: *const Machine) -> u32 {
+    contain(|| (*machine).machine.processor().program_counter())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_get_register(
+    machine: *const Machine,
+    index: u32,
+    value: *mut i32,
+) -> VcpuResult {
+    contain(|| match FromPrimitive::from_u32(index) {
+        Some(rid) => {
+            *value = (*machine).machine.processor().register(rid).i();
+            VcpuResult::Ok
+        }
+        None => fail(
+            VcpuResult::OutOfRange,
+            format!("Register index {} is out of range", index),
+        ),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_set_register(
+    machine: *mut Machine,
+    index: u32,
+    value: i32,
+) -> VcpuResult {
+    contain(|| match FromPrimitive::from_u32(index) {
+        Some(rid) => {
+            (*machine)
+                .machine
+                .processor_mut()
+                .register_mut(rid)
+                .set_i(value);
+            VcpuResult::Ok
+        }
+        None => fail(
+            VcpuResult::OutOfRange,
+            format!("Register index {} is out of range", index),
+        ),
+    })
+}
+
+/// Copies `length` bytes of the memory at `address`, including devices, to `dest`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_read_memory(
+    machine: *const Machine,
+    address: u32,
+    dest: *mut u8,
+    length: u32,
+) -> VcpuResult {
+    contain(|| {
+        let memory = (*machine).machine.memory();
+        for i in 0..length {
+            let byte_address = address.wrapping_add(i);
+            match memory.read_byte(byte_address) {
+                Ok(byte) => *dest.add(i as usize) = byte,
+                Err(_) => {
+                    return fail(
+                        VcpuResult::OutOfRange,
+                        format!("Address 0x{:08X} cannot be read", byte_address),
+                    )
+                }
+            }
+        }
+        VcpuResult::Ok
+    })
+}
+
+/// Copies `length` bytes from `src` into the memory at `address`. Writes to devices have the same effect as if
+/// the program made them.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_write_memory(
+    machine: *mut Machine,
+    address: u32,
+    src: *const u8,
+    length: u32,
+) -> VcpuResult {
+    contain(|| {
+        let memory = (*machine).machine.memory_mut();
+        for i in 0..length {
+            let byte_address = address.wrapping_add(i);
+            if memory
+                .write_byte(byte_address, *src.add(i as usize))
+                .is_err()
+            {
+                return fail(
+                    VcpuResult::OutOfRange,
+                    format!("Address 0x{:08X} cannot be written", byte_address),
+                );
+            }
+        }
+        VcpuResult::Ok
+    })
+}
+
+/// Number of bytes the program wrote to the console which were not read yet.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_get_console_len(machine: *mut Machine) -> usize {
+    contain(|| (*machine).console_output().len())
+}
+
+/// Moves at most `len` bytes of the console output into `buf` and returns how many were moved.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_machine_read_console(
+    machine: *mut Machine,
+    buf: *mut u8,
+    len: usize,
+) -> usize {
+    contain(|| {
+        let output = (*machine).console_output();
+        let count = len.min(output.len());
+        if count > 0 {
+            slice::from_raw_parts_mut(buf, count).copy_from_slice(&output[..count]);
+            output.drain(..count);
+        }
+        count
+    })
+}
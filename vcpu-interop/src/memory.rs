@@ -1,10 +1,9 @@
-use crate::result::VcpuResult;
+use crate::result::{contain, fail, VcpuResult};
 use crate::util::{destroy, into_ptr};
-use std::cell::RefCell;
 use std::ffi::{c_void, CStr};
-use std::ops::{Deref, DerefMut};
 use std::os::raw::c_char;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, ThreadId};
 use vcpu::{CompositeMemory, IOHandler, IOMemory, MountError, Storage, StorageMut};
 
 pub type CanWriteCallback = extern "C" fn(
@@ -23,19 +22,27 @@ pub type OnWriteCallback = extern "C" fn(
     user_data: *mut c_void,
 );
 
+/// Calls the callbacks of the host, which may be null. Without `can_write_fn` every write is allowed.
 pub struct FunPtrIOHandler {
-    can_write_fn: CanWriteCallback,
-    on_write_fn: OnWriteCallback,
+    can_write_fn: Option<CanWriteCallback>,
+    on_write_fn: Option<OnWriteCallback>,
     user_data: *mut c_void,
 }
 
 impl IOHandler for FunPtrIOHandler {
     fn can_write(&self, memory: &[u8], address: u32, size: u32) -> bool {
-        (self.can_write_fn)(memory.as_ptr(), memory.len(), address, size, self.user_data)
+        match self.can_write_fn {
+            Some(can_write) => {
+                can_write(memory.as_ptr(), memory.len(), address, size, self.user_data)
+            }
+            None => true,
+        }
     }
 
     fn on_write(&self, memory: &[u8], address: u32, size: u32) {
-        (self.on_write_fn)(memory.as_ptr(), memory.len(), address, size, self.user_data)
+        if let Some(on_write) = self.on_write_fn {
+            on_write(memory.as_ptr(), memory.len(), address, size, self.user_data)
+        }
     }
 }
 
@@ -45,25 +52,98 @@ pub enum MemoryVariant {
     Composite(CompositeMemory),
 }
 
-pub struct Memory(Rc<RefCell<MemoryVariant>>);
+impl MemoryVariant {
+    fn kind(&self) -> &'static str {
+        match self {
+            MemoryVariant::Plain(_) => "plain",
+            MemoryVariant::IO(_) => "IO",
+            MemoryVariant::Composite(_) => "composite",
+        }
+    }
+
+    pub fn storage_mut(&mut self) -> &mut dyn StorageMut {
+        match self {
+            MemoryVariant::Plain(inner) => inner,
+            MemoryVariant::IO(inner) => inner,
+            MemoryVariant::Composite(inner) => inner,
+        }
+    }
+}
+
+fn invalid_type(variant: &MemoryVariant, expected: &str) -> VcpuResult {
+    fail(
+        VcpuResult::InvalidType,
+        format!(
+            "Expected {} memory instead of {} memory",
+            expected,
+            variant.kind()
+        ),
+    )
+}
+
+fn out_of_range(address: u32, length: u32) -> VcpuResult {
+    fail(
+        VcpuResult::OutOfRange,
+        format!("Cannot access {} bytes at 0x{:08X}", length, address),
+    )
+}
+
+fn in_use() -> VcpuResult {
+    fail(VcpuResult::MemoryInUse, "The memory is already in use")
+}
+
+/// Locks a memory for the current thread, so that a nested use by the same thread, e.g. from an IO callback
+/// while a processor runs on the memory, fails instead of deadlocking.
+struct Lock {
+    variant: Mutex<MemoryVariant>,
+    owner: Mutex<Option<ThreadId>>,
+}
+
+/// Releases the ownership of a lock, even if the function using the memory panics.
+struct OwnerGuard<'a>(&'a Mutex<Option<ThreadId>>);
+
+impl Drop for OwnerGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+impl Lock {
+    fn with<R, F: FnOnce(&mut MemoryVariant) -> R>(&self, f: F) -> Option<R> {
+        let current = thread::current().id();
+        if *self.owner.lock().unwrap_or_else(PoisonError::into_inner) == Some(current) {
+            return None;
+        }
+        let mut variant = self.variant.lock().unwrap_or_else(PoisonError::into_inner);
+        *self.owner.lock().unwrap_or_else(PoisonError::into_inner) = Some(current);
+        let _guard = OwnerGuard(&self.owner);
+        Some(f(&mut variant))
+    }
+}
+
+// The callbacks and user data of IO memory belong to the host, which is responsible for making them usable
+// from the threads it uses the memory on. Composite memory only contains other handles.
+unsafe impl Send for MemoryVariant {}
+
+/// A shared handle to a memory, which can be used from multiple threads. Every call locks the memory, so calls
+/// from other threads wait until it is unlocked, while a nested call from the same thread fails with
+/// `MemoryInUse`.
+pub struct Memory(Arc<Lock>);
 
 impl Memory {
     pub fn new(variant: MemoryVariant) -> Memory {
-        Memory(Rc::new(RefCell::new(variant)))
+        Memory(Arc::new(Lock {
+            variant: Mutex::new(variant),
+            owner: Mutex::new(None),
+        }))
     }
 
     pub fn try_use<F: FnOnce(&MemoryVariant) -> VcpuResult>(&self, f: F) -> VcpuResult {
-        match &self.0.try_borrow() {
-            Ok(reference) => f(reference.deref()),
-            Err(_) => VcpuResult::MemoryInUse,
-        }
+        self.0.with(|variant| f(variant)).unwrap_or_else(in_use)
     }
 
     pub fn try_use_mut<F: FnOnce(&mut MemoryVariant) -> VcpuResult>(&mut self, f: F) -> VcpuResult {
-        match &mut self.0.try_borrow_mut() {
-            Ok(reference) => f(reference.deref_mut()),
-            Err(_) => VcpuResult::MemoryInUse,
-        }
+        self.0.with(f).unwrap_or_else(in_use)
     }
 }
 
@@ -75,66 +155,98 @@ impl Clone for Memory {
 
 impl Storage for Memory {
     fn length(&self) -> u32 {
-        match self.0.try_borrow() {
-            Ok(reference) => match reference.deref() {
+        self.0
+            .with(|variant| match variant {
                 MemoryVariant::Plain(inner) => inner.length(),
                 MemoryVariant::IO(inner) => inner.length(),
                 MemoryVariant::Composite(inner) => inner.length(),
-            },
-            Err(_) => 0,
-        }
+            })
+            .unwrap_or(0)
     }
 
     fn check_range(&self, address: u32, length: u32) -> bool {
-        match self.0.try_borrow() {
-            Ok(reference) => match reference.deref() {
+        self.0
+            .with(|variant| match variant {
                 MemoryVariant::Plain(inner) => inner.check_range(address, length),
                 MemoryVariant::IO(inner) => inner.check_range(address, length),
                 MemoryVariant::Composite(inner) => inner.check_range(address, length),
-            },
-            Err(_) => false,
-        }
+            })
+            .unwrap_or(false)
     }
 
     fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
-        match self.0.try_borrow().map_err(|_| ())?.deref() {
-            MemoryVariant::Plain(inner) => inner.read(address, size),
-            MemoryVariant::IO(inner) => inner.read(address, size),
-            MemoryVariant::Composite(inner) => inner.read(address, size),
-        }
+        self.0
+            .with(|variant| match variant {
+                MemoryVariant::Plain(inner) => inner.read(address, size),
+                MemoryVariant::IO(inner) => inner.read(address, size),
+                MemoryVariant::Composite(inner) => inner.read(address, size),
+            })
+            .unwrap_or(Err(()))
     }
 }
 
 impl StorageMut for Memory {
     fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
-        match self.0.try_borrow_mut().map_err(|_| ())?.deref_mut() {
-            MemoryVariant::Plain(inner) => inner.write(address, size, value),
-            MemoryVariant::IO(inner) => inner.write(address, size, value),
-            MemoryVariant::Composite(inner) => inner.write(address, size, value),
-        }
+        self.0
+            .with(|variant| match variant {
+                MemoryVariant::Plain(inner) => inner.write(address, size, value),
+                MemoryVariant::IO(inner) => inner.write(address, size, value),
+                MemoryVariant::Composite(inner) => inner.write(address, size, value),
+            })
+            .unwrap_or(Err(()))
     }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_memory_create_plain(size: u32) -> *mut Memory {
-    into_ptr(Memory::new(MemoryVariant::Plain(vec![0; size as usize])))
+    contain(|| into_ptr(Memory::new(MemoryVariant::Plain(vec![0; size as usize]))))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_memory_create_io(
     size: u32,
-    can_write: CanWriteCallback,
-    on_write: OnWriteCallback,
+    can_write: Option<CanWriteCallback>,
+    on_write: Option<OnWriteCallback>,
     user_data: *mut c_void,
 ) -> *mut Memory {
-    into_ptr(Memory::new(MemoryVariant::IO(IOMemory::new(
-        size,
-        FunPtrIOHandler {
-            can_write_fn: can_write,
-            on_write_fn: on_write,
-            user_data,
-        },
-    ))))
+    contain(|| {
+        into_ptr(Memory::new(MemoryVariant::IO(IOMemory::new(
+            size,
+            FunPtrIOHandler {
+                can_write_fn: can_write,
+                on_write_fn: on_write,
+                user_data,
+            },
+        ))))
+    })
+}
+
+/// Replaces the callbacks and the user data of an IO memory, keeping its contents.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_memory_io_set_handler(
+    memory: *mut Memory,
+    can_write: Option<CanWriteCallback>,
+    on_write: Option<OnWriteCallback>,
+    user_data: *mut c_void,
+) -> VcpuResult {
+    contain(|| {
+        (*memory).try_use_mut(|variant| match variant {
+            MemoryVariant::IO(inner) => {
+                let mut replaced = IOMemory::new(
+                    inner.data().len() as u32,
+                    FunPtrIOHandler {
+                        can_write_fn: can_write,
+                        on_write_fn: on_write,
+                        user_data,
+                    },
+                );
+                replaced.data_mut().copy_from_slice(inner.data());
+                *inner = replaced;
+                VcpuResult::Ok
+            }
+            other => invalid_type(other, "IO"),
+        })
+    })
 }
 
 #[no_mangle]
@@ -143,19 +255,21 @@ pub unsafe extern "C" fn vcpu_memory_get_ptr(
     ptr: *mut *mut u8,
     size: *mut u32,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| {
-        let slice = match variant {
-            MemoryVariant::Plain(inner) => inner,
-            MemoryVariant::IO(inner) => inner.data_mut(),
-            _ => {
-                return VcpuResult::InvalidType;
-            }
-        };
+    contain(|| {
+        (*memory).try_use_mut(|variant| {
+            let slice = match variant {
+                MemoryVariant::Plain(inner) => inner,
+                MemoryVariant::IO(inner) => inner.data_mut(),
+                other => {
+                    return invalid_type(other, "plain or IO");
+                }
+            };
 
-        *ptr = slice.as_mut_ptr();
-        *size = slice.len() as u32;
+            *ptr = slice.as_mut_ptr();
+            *size = slice.len() as u32;
 
-        VcpuResult::Ok
+            VcpuResult::Ok
+        })
     })
 }
 
@@ -166,22 +280,35 @@ pub unsafe extern "C" fn vcpu_memory_read(
     offset: u32,
     length: u32,
 ) -> VcpuResult {
-    (*memory).try_use(|variant| {
-        let slice = match variant {
-            MemoryVariant::Plain(inner) => inner,
-            MemoryVariant::IO(inner) => inner.data(),
-            _ => {
-                return VcpuResult::InvalidType;
-            }
-        };
+    contain(|| {
+        (*memory).try_use(|variant| {
+            let slice = match variant {
+                MemoryVariant::Plain(inner) => inner,
+                MemoryVariant::IO(inner) => inner.data(),
+                MemoryVariant::Composite(inner) => {
+                    // the range has to lie within a single fragment, which is read byte by byte
+                    if !inner.check_range(offset, length) {
+                        return out_of_range(offset, length);
+                    }
+                    let dest = std::slice::from_raw_parts_mut(dest, length as usize);
+                    for (address, byte) in (offset..).zip(dest.iter_mut()) {
+                        match inner.read_byte(address) {
+                            Ok(value) => *byte = value,
+                            Err(_) => return in_use(),
+                        }
+                    }
+                    return VcpuResult::Ok;
+                }
+            };
 
-        if slice.check_range(offset, length) {
-            std::slice::from_raw_parts_mut(dest, length as usize)
-                .copy_from_slice(&slice[offset as usize..(offset + length) as usize]);
-            VcpuResult::Ok
-        } else {
-            VcpuResult::OutOfRange
-        }
+            if slice.check_range(offset, length) {
+                std::slice::from_raw_parts_mut(dest, length as usize)
+                    .copy_from_slice(&slice[offset as usize..(offset + length) as usize]);
+                VcpuResult::Ok
+            } else {
+                out_of_range(offset, length)
+            }
+        })
     })
 }
 
@@ -192,22 +319,51 @@ pub unsafe extern "C" fn vcpu_memory_write(
     offset: u32,
     length: u32,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| {
-        let slice = match variant {
-            MemoryVariant::Plain(inner) => inner,
-            MemoryVariant::IO(inner) => inner.data_mut(),
-            _ => {
-                return VcpuResult::InvalidType;
+    contain(|| {
+        (*memory).try_use_mut(|variant| {
+            let slice = match variant {
+                MemoryVariant::Plain(inner) => inner,
+                MemoryVariant::IO(inner) => inner.data_mut(),
+                MemoryVariant::Composite(inner) => {
+                    // the range has to lie within a single fragment, which is written byte by byte
+                    if !inner.check_range(offset, length) {
+                        return out_of_range(offset, length);
+                    }
+                    let src = std::slice::from_raw_parts(src, length as usize);
+                    for (address, byte) in (offset..).zip(src.iter()) {
+                        if inner.write_byte(address, *byte).is_err() {
+                            return in_use();
+                        }
+                    }
+                    return VcpuResult::Ok;
+                }
+            };
+
+            if slice.check_range(offset, length) {
+                slice[offset as usize..(offset + length) as usize]
+                    .copy_from_slice(std::slice::from_raw_parts(src, length as usize));
+                VcpuResult::Ok
+            } else {
+                out_of_range(offset, length)
             }
-        };
+        })
+    })
+}
 
-        if slice.check_range(offset, length) {
-            slice[offset as usize..(offset + length) as usize]
-                .copy_from_slice(std::slice::from_raw_parts(src, length as usize));
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_memory_get_length(
+    memory: *const Memory,
+    length: *mut u32,
+) -> VcpuResult {
+    contain(|| {
+        (*memory).try_use(|variant| {
+            *length = match variant {
+                MemoryVariant::Plain(inner) => inner.length(),
+                MemoryVariant::IO(inner) => inner.length(),
+                MemoryVariant::Composite(inner) => inner.length(),
+            };
             VcpuResult::Ok
-        } else {
-            VcpuResult::OutOfRange
-        }
+        })
     })
 }
 
@@ -229,7 +385,7 @@ unsafe fn memory_get(
                 *value = v;
                 VcpuResult::Ok
             }
-            Err(_) => VcpuResult::OutOfRange,
+            Err(_) => out_of_range(address, size),
         }
     })
 }
@@ -240,7 +396,7 @@ pub unsafe extern "C" fn vcpu_memory_get_word(
     address: u32,
     value: *mut u32,
 ) -> VcpuResult {
-    memory_get(memory, address, vcpu::WORD_BYTES, value)
+    contain(|| memory_get(memory, address, vcpu::WORD_BYTES, value))
 }
 
 #[no_mangle]
@@ -249,10 +405,12 @@ pub unsafe extern "C" fn vcpu_memory_get_half(
     address: u32,
     value: *mut u16,
 ) -> VcpuResult {
-    let mut v = 0u32;
-    let result = memory_get(memory, address, vcpu::HALF_BYTES, &mut v);
-    *value = v as u16;
-    result
+    contain(|| {
+        let mut v = 0u32;
+        let result = memory_get(memory, address, vcpu::HALF_BYTES, &mut v);
+        *value = v as u16;
+        result
+    })
 }
 
 #[no_mangle]
@@ -261,10 +419,12 @@ pub unsafe extern "C" fn vcpu_memory_get_byte(
     address: u32,
     value: *mut u8,
 ) -> VcpuResult {
-    let mut v = 0u32;
-    let result = memory_get(memory, address, vcpu::BYTE_BYTES, &mut v);
-    *value = v as u8;
-    result
+    contain(|| {
+        let mut v = 0u32;
+        let result = memory_get(memory, address, vcpu::BYTE_BYTES, &mut v);
+        *value = v as u8;
+        result
+    })
 }
 
 unsafe fn memory_set(memory: *mut Memory, address: u32, size: u32, value: u32) -> VcpuResult {
@@ -277,7 +437,7 @@ unsafe fn memory_set(memory: *mut Memory, address: u32, size: u32, value: u32) -
 
         match result {
             Ok(_) => VcpuResult::Ok,
-            Err(_) => VcpuResult::OutOfRange,
+            Err(_) => out_of_range(address, size),
         }
     })
 }
@@ -288,7 +448,7 @@ pub unsafe extern "C" fn vcpu_memory_set_word(
     address: u32,
     value: u32,
 ) -> VcpuResult {
-    memory_set(memory, address, vcpu::WORD_BYTES, value)
+    contain(|| memory_set(memory, address, vcpu::WORD_BYTES, value))
 }
 
 #[no_mangle]
@@ -297,7 +457,7 @@ pub unsafe extern "C" fn vcpu_memory_set_half(
     address: u32,
     value: u16,
 ) -> VcpuResult {
-    memory_set(memory, address, vcpu::HALF_BYTES, value.into())
+    contain(|| memory_set(memory, address, vcpu::HALF_BYTES, value.into()))
 }
 
 #[no_mangle]
@@ -306,29 +466,33 @@ pub unsafe extern "C" fn vcpu_memory_set_byte(
     address: u32,
     value: u8,
 ) -> VcpuResult {
-    memory_set(memory, address, vcpu::BYTE_BYTES, value.into())
+    contain(|| memory_set(memory, address, vcpu::BYTE_BYTES, value.into()))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_memory_resize(memory: *mut Memory, size: u32) -> VcpuResult {
-    (*memory).try_use_mut(|variant| match variant {
-        MemoryVariant::Plain(inner) => {
-            inner.resize(size as usize, u8::default());
-            VcpuResult::Ok
-        }
-        MemoryVariant::IO(inner) => {
-            inner.resize(size);
-            VcpuResult::Ok
-        }
-        _ => VcpuResult::InvalidType,
+    contain(|| {
+        (*memory).try_use_mut(|variant| match variant {
+            MemoryVariant::Plain(inner) => {
+                inner.resize(size as usize, u8::default());
+                VcpuResult::Ok
+            }
+            MemoryVariant::IO(inner) => {
+                inner.resize(size);
+                VcpuResult::Ok
+            }
+            other => invalid_type(other, "plain or IO"),
+        })
     })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_memory_create_comp() -> *mut Memory {
-    into_ptr(Memory::new(
-        MemoryVariant::Composite(CompositeMemory::new()),
-    ))
+    contain(|| {
+        into_ptr(Memory::new(
+            MemoryVariant::Composite(CompositeMemory::new()),
+        ))
+    })
 }
 
 #[no_mangle]
@@ -338,20 +502,34 @@ pub unsafe extern "C" fn vcpu_memory_comp_mount(
     key: *const c_char,
     fragment: *mut Memory,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| match variant {
-        MemoryVariant::Composite(inner) => match CStr::from_ptr(key).to_str() {
-            Ok(key_str) => {
-                let result = inner.mount(address, key_str, (*fragment).clone());
-
-                match result {
-                    Ok(_) => VcpuResult::Ok,
-                    Err(MountError::FragmentIntersection) => VcpuResult::FragmentIntersection,
-                    Err(MountError::KeyAlreadyExists) => VcpuResult::KeyAlreadyExists,
+    contain(|| {
+        (*memory).try_use_mut(|variant| match variant {
+            MemoryVariant::Composite(inner) => match CStr::from_ptr(key).to_str() {
+                Ok(key_str) => {
+                    let result = inner.mount(address, key_str, (*fragment).clone());
+
+                    match result {
+                        Ok(_) => VcpuResult::Ok,
+                        Err(MountError::FragmentIntersection) => fail(
+                            VcpuResult::FragmentIntersection,
+                            format!(
+                                "Fragment \"{}\" at 0x{:08X} intersects another fragment",
+                                key_str, address
+                            ),
+                        ),
+                        Err(MountError::KeyAlreadyExists) => fail(
+                            VcpuResult::KeyAlreadyExists,
+                            format!("A fragment is already mounted as \"{}\"", key_str),
+                        ),
+                    }
                 }
-            }
-            Err(_) => VcpuResult::UTF8Error,
-        },
-        _ => VcpuResult::InvalidType,
+                Err(err) => fail(
+                    VcpuResult::UTF8Error,
+                    format!("Key is not valid UTF-8: {}", err),
+                ),
+            },
+            other => invalid_type(other, "composite"),
+        })
     })
 }
 
@@ -360,19 +538,75 @@ pub unsafe extern "C" fn vcpu_memory_comp_unmount(
     memory: *mut Memory,
     key: *const c_char,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| match variant {
-        MemoryVariant::Composite(inner) => match CStr::from_ptr(key).to_str() {
-            Ok(key_str) => {
-                inner.unmount(key_str);
+    contain(|| {
+        (*memory).try_use_mut(|variant| match variant {
+            MemoryVariant::Composite(inner) => match CStr::from_ptr(key).to_str() {
+                Ok(key_str) => {
+                    inner.unmount(key_str);
+                    VcpuResult::Ok
+                }
+                Err(err) => fail(
+                    VcpuResult::UTF8Error,
+                    format!("Key is not valid UTF-8: {}", err),
+                ),
+            },
+            other => invalid_type(other, "composite"),
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_memory_comp_get_fragment_count(
+    memory: *const Memory,
+    count: *mut usize,
+) -> VcpuResult {
+    contain(|| {
+        (*memory).try_use(|variant| match variant {
+            MemoryVariant::Composite(inner) => {
+                *count = inner.fragments().len();
                 VcpuResult::Ok
             }
-            Err(_) => VcpuResult::UTF8Error,
-        },
-        _ => VcpuResult::InvalidType,
+            other => invalid_type(other, "composite"),
+        })
+    })
+}
+
+/// Returns the fragment with the given `index`, in the order of their addresses. The `key` is not null terminated
+/// and stays valid until the fragment is unmounted.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_memory_comp_get_fragment(
+    memory: *const Memory,
+    index: usize,
+    address: *mut u32,
+    length: *mut u32,
+    key: *mut *const u8,
+    key_len: *mut usize,
+) -> VcpuResult {
+    contain(|| {
+        (*memory).try_use(|variant| match variant {
+            MemoryVariant::Composite(inner) => match inner.fragments().get(index) {
+                Some((fragment_key, fragment_address, fragment_length)) => {
+                    *address = *fragment_address;
+                    *length = *fragment_length;
+                    *key = fragment_key.as_ptr();
+                    *key_len = fragment_key.len();
+                    VcpuResult::Ok
+                }
+                None => fail(
+                    VcpuResult::OutOfRange,
+                    format!(
+                        "Fragment {} does not exist, the memory has {} fragments",
+                        index,
+                        inner.fragments().len()
+                    ),
+                ),
+            },
+            other => invalid_type(other, "composite"),
+        })
     })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_memory_destroy(memory: *mut Memory) {
-    destroy(memory)
+    contain(|| destroy(memory))
 }
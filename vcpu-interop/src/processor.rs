@@ -1,13 +1,116 @@
-use crate::memory::{Memory, MemoryVariant};
-use crate::result::VcpuResult;
+use crate::memory::Memory;
+use crate::register::register_from_name;
+use crate::result::{contain, fail, VcpuResult};
 use crate::util::{destroy, into_ptr};
 use num_traits::{FromPrimitive, ToPrimitive};
+use std::os::raw::c_char;
 use std::slice;
-use vcpu::Processor;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use vcpu::ExitCode;
+
+/// Number of instructions a run executes before it unlocks the processor and the memory, so that other threads
+/// can access them, and checks whether a stop was requested.
+const RUN_BATCH: u64 = 4096;
+
+/// A processor which can be used from multiple threads.
+///
+/// Every call locks the processor, while runs only lock it for a batch of instructions at a time, so other
+/// threads can inspect a running processor and request it to stop. Functions of a processor must not be called
+/// from the IO callbacks of a run of the same processor, except for raising and lowering interrupts, which only
+/// take effect before the next instruction. Calls which use a processor together with a memory always lock the
+/// processor first, so that they cannot deadlock each other.
+pub struct Processor {
+    inner: Mutex<vcpu::Processor>,
+    stop_requested: AtomicBool,
+    /// Interrupt lines which were raised or lowered without the lock, and are not applied to `IP` yet.
+    raised: AtomicU32,
+    lowered: AtomicU32,
+}
+
+impl Processor {
+    /// Locks the processor and applies the interrupt lines which were raised or lowered in the meantime.
+    pub fn lock(&self) -> MutexGuard<'_, vcpu::Processor> {
+        let mut processor = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        self.apply_interrupts(&mut processor);
+        processor
+    }
+
+    fn apply_interrupts(&self, processor: &mut vcpu::Processor) {
+        if self.raised.load(Ordering::Relaxed) | self.lowered.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let raised = self.raised.swap(0, Ordering::SeqCst);
+        let lowered = self.lowered.swap(0, Ordering::SeqCst);
+        let csrs = processor.control_registers_mut();
+        let ip = (csrs.ip() | raised) & !lowered;
+        csrs.set_ip(ip);
+    }
+
+    /// Returns whether a stop was requested and clears the request. If so, the processor is terminated.
+    fn take_stop_request(&self, processor: &mut vcpu::Processor) -> bool {
+        let requested = self.stop_requested.swap(false, Ordering::SeqCst);
+        if requested && !processor.is_stopped() {
+            processor.set_state(Some(ExitCode::Terminated));
+        }
+        requested
+    }
+
+    /// Executes at most `count` instructions, a batch at a time, until the processor stops, a stop is requested
+    /// or `stop` returns true before an instruction. Returns the number of executed instructions.
+    pub fn run_batched<F: FnMut(&vcpu::Processor, u64) -> bool>(
+        &self,
+        instructions: &[u8],
+        memory: &mut Memory,
+        count: Option<u64>,
+        mut stop: F,
+    ) -> Result<u64, VcpuResult> {
+        let mut executed = 0;
+        loop {
+            let mut processor = self.lock();
+            if self.take_stop_request(&mut processor) || processor.is_stopped() {
+                return Ok(executed);
+            }
+            let mut finished = false;
+            let result = memory.try_use_mut(|variant| {
+                let storage = variant.storage_mut();
+                for _ in 0..RUN_BATCH {
+                    // IO callbacks of the previous instruction may have raised interrupts, whose handler is
+                    // entered before `stop` looks at the program counter
+                    self.apply_interrupts(&mut processor);
+                    processor.take_interrupt();
+                    if processor.is_stopped()
+                        || count == Some(executed)
+                        || stop(&processor, executed)
+                    {
+                        finished = true;
+                        break;
+                    }
+                    processor.tick(instructions, storage);
+                    executed += 1;
+                }
+                VcpuResult::Ok
+            });
+            if result != VcpuResult::Ok {
+                return Err(result);
+            }
+            if finished {
+                return Ok(executed);
+            }
+        }
+    }
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_create() -> *mut Processor {
-    into_ptr(Processor::new())
+    contain(|| {
+        into_ptr(Processor {
+            inner: Mutex::new(vcpu::Processor::new()),
+            stop_requested: AtomicBool::new(false),
+            raised: AtomicU32::new(0),
+            lowered: AtomicU32::new(0),
+        })
+    })
 }
 
 #[no_mangle]
@@ -16,13 +119,16 @@ pub unsafe extern "C" fn vcpu_processor_get_register(
     index: u32,
     value: *mut i32,
 ) -> VcpuResult {
-    match FromPrimitive::from_u32(index) {
+    contain(|| match FromPrimitive::from_u32(index) {
         Some(rid) => {
-            *value = (*processor).register(rid).i();
+            *value = (*processor).lock().register(rid).i();
             VcpuResult::Ok
         }
-        None => VcpuResult::OutOfRange,
-    }
+        None => fail(
+            VcpuResult::OutOfRange,
+            format!("Register index {} is out of range", index),
+        ),
+    })
 }
 
 #[no_mangle]
@@ -31,31 +137,69 @@ pub unsafe extern "C" fn vcpu_processor_set_register(
     index: u32,
     value: i32,
 ) -> VcpuResult {
-    match FromPrimitive::from_u32(index) {
+    contain(|| match FromPrimitive::from_u32(index) {
         Some(rid) => {
-            (*processor).register_mut(rid).set_i(value);
+            (*processor).lock().register_mut(rid).set_i(value);
             VcpuResult::Ok
         }
-        None => VcpuResult::OutOfRange,
-    }
+        None => fail(
+            VcpuResult::OutOfRange,
+            format!("Register index {} is out of range", index),
+        ),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_get_register_by_name(
+    processor: *const Processor,
+    name: *const c_char,
+    value: *mut i32,
+) -> VcpuResult {
+    contain(|| match register_from_name(name) {
+        Ok(rid) => {
+            *value = (*processor).lock().register(rid).i();
+            VcpuResult::Ok
+        }
+        Err(result) => result,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_set_register_by_name(
+    processor: *mut Processor,
+    name: *const c_char,
+    value: i32,
+) -> VcpuResult {
+    contain(|| match register_from_name(name) {
+        Ok(rid) => {
+            (*processor).lock().register_mut(rid).set_i(value);
+            VcpuResult::Ok
+        }
+        Err(result) => result,
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_get_program_counter(processor: *const Processor) -> u32 {
-    (*processor).program_counter()
+    contain(|| (*processor).lock().program_counter())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_set_program_counter(processor: *mut Processor, value: u32) {
+    contain(|| (*processor).lock().set_program_counter(value))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_get_state(processor: *const Processor) -> i32 {
-    match (*processor).state() {
+    contain(|| match (*processor).lock().state() {
         Some(code) => code.to_i32().unwrap(),
         None => -1,
-    }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_destroy(processor: *mut Processor) {
-    destroy(processor)
+    contain(|| destroy(processor))
 }
 
 #[no_mangle]
@@ -65,19 +209,19 @@ pub unsafe extern "C" fn vcpu_processor_tick(
     instr_len: usize,
     memory: *mut Memory,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| {
-        (*processor).tick(
-            slice::from_raw_parts(instr, instr_len),
-            match variant {
-                MemoryVariant::Plain(inner) => inner,
-                MemoryVariant::IO(inner) => inner,
-                MemoryVariant::Composite(inner) => inner,
-            },
-        );
-        VcpuResult::Ok
+    contain(|| {
+        let mut processor = (*processor).lock();
+        (*memory).try_use_mut(|variant| {
+            processor.tick(
+                slice::from_raw_parts(instr, instr_len),
+                variant.storage_mut(),
+            );
+            VcpuResult::Ok
+        })
     })
 }
 
+/// Runs until the processor stops, or another thread requests it to stop with `vcpu_processor_request_stop`.
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_run(
     processor: *mut Processor,
@@ -85,20 +229,97 @@ pub unsafe extern "C" fn vcpu_processor_run(
     instr_len: usize,
     memory: *mut Memory,
 ) -> VcpuResult {
-    (*memory).try_use_mut(|variant| {
-        (*processor).run(
-            slice::from_raw_parts(instr, instr_len),
-            match variant {
-                MemoryVariant::Plain(inner) => inner,
-                MemoryVariant::IO(inner) => inner,
-                MemoryVariant::Composite(inner) => inner,
-            },
-        );
+    contain(|| {
+        let instructions = slice::from_raw_parts(instr, instr_len);
+        match (*processor).run_batched(instructions, &mut *memory, None, |_, _| false) {
+            Ok(_) => VcpuResult::Ok,
+            Err(result) => result,
+        }
+    })
+}
+
+/// Executes at most `count` instructions and stops early if the processor stops or a stop is requested.
+/// The number of executed instructions, including the one which stopped the processor,
+/// is written to `executed` unless it is null.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_run_for(
+    processor: *mut Processor,
+    instr: *const u8,
+    instr_len: usize,
+    memory: *mut Memory,
+    count: u64,
+    executed: *mut u64,
+) -> VcpuResult {
+    contain(|| {
+        let instructions = slice::from_raw_parts(instr, instr_len);
+        match (*processor).run_batched(instructions, &mut *memory, Some(count), |_, _| false) {
+            Ok(ticks) => {
+                if !executed.is_null() {
+                    *executed = ticks;
+                }
+                VcpuResult::Ok
+            }
+            Err(result) => result,
+        }
+    })
+}
+
+/// Requests a run of the processor on another thread to stop, which then stops with `ExitCode::Terminated`
+/// after the current batch of instructions. If the processor is not running, its next run stops immediately.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_request_stop(processor: *const Processor) {
+    contain(|| (*processor).stop_requested.store(true, Ordering::SeqCst))
+}
+
+/// Makes interrupt `line` (0 to 31) pending, e.g. from an IO callback of a device or from another thread. The
+/// program enters its handler before the next instruction if it enabled the line. This does not lock the
+/// processor, the line is applied to `IP` by the next call which does.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_raise_interrupt(
+    processor: *mut Processor,
+    line: u32,
+) -> VcpuResult {
+    contain(|| {
+        if line >= vcpu::INTERRUPT_LINES {
+            return fail(
+                VcpuResult::OutOfRange,
+                format!("Interrupt line {} is out of range", line),
+            );
+        }
+        let bit = 1 << line;
+        (*processor).lowered.fetch_and(!bit, Ordering::SeqCst);
+        (*processor).raised.fetch_or(bit, Ordering::SeqCst);
         VcpuResult::Ok
     })
 }
 
+/// Makes interrupt `line` (0 to 31) no longer pending. Like `vcpu_processor_raise_interrupt`, this can be
+/// called from IO callbacks.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_lower_interrupt(
+    processor: *mut Processor,
+    line: u32,
+) -> VcpuResult {
+    contain(|| {
+        if line >= vcpu::INTERRUPT_LINES {
+            return fail(
+                VcpuResult::OutOfRange,
+                format!("Interrupt line {} is out of range", line),
+            );
+        }
+        let bit = 1 << line;
+        (*processor).raised.fetch_and(!bit, Ordering::SeqCst);
+        (*processor).lowered.fetch_or(bit, Ordering::SeqCst);
+        VcpuResult::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_processor_is_stopped(processor: *const Processor) -> bool {
+    contain(|| (*processor).lock().is_stopped())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_processor_reset(processor: *mut Processor) {
-    (*processor).reset()
+    contain(|| (*processor).lock().reset())
 }
@@ -1,11 +1,30 @@
-use crate::result::VcpuResult;
+use crate::result::{contain, fail, VcpuResult};
 use num_traits::FromPrimitive;
+use std::ffi::CStr;
 use std::os::raw::c_char;
 use util::InteropGetName;
+use vcpu::RegisterId;
+
+/// Looks up a register by its name, which is case insensitive and may start with `$`.
+pub unsafe fn register_from_name(name: *const c_char) -> Result<RegisterId, VcpuResult> {
+    let name = CStr::from_ptr(name).to_str().map_err(|err| {
+        fail(
+            VcpuResult::UTF8Error,
+            format!("Register name is not valid UTF-8: {}", err),
+        )
+    })?;
+    let register = name.strip_prefix('$').unwrap_or(name);
+    register.to_uppercase().parse().map_err(|_| {
+        fail(
+            VcpuResult::UnknownName,
+            format!("Unknown register {}", name),
+        )
+    })
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_register_get_count() -> u32 {
-    vcpu::REGISTER_COUNT as u32
+    contain(|| vcpu::REGISTER_COUNT as u32)
 }
 
 #[no_mangle]
@@ -13,10 +32,29 @@ pub unsafe extern "C" fn vcpu_register_get_name(
     index: u32,
     name: *mut *const c_char,
 ) -> VcpuResult {
-    if let Some(id) = vcpu::RegisterId::from_u32(index) {
-        *name = id.interop_name().as_ptr() as *const c_char;
-        VcpuResult::Ok
-    } else {
-        VcpuResult::OutOfRange
-    }
+    contain(|| {
+        if let Some(id) = vcpu::RegisterId::from_u32(index) {
+            *name = id.interop_name().as_ptr() as *const c_char;
+            VcpuResult::Ok
+        } else {
+            fail(
+                VcpuResult::OutOfRange,
+                format!("Register index {} is out of range", index),
+            )
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_register_get_index(
+    name: *const c_char,
+    index: *mut u32,
+) -> VcpuResult {
+    contain(|| match register_from_name(name) {
+        Ok(id) => {
+            *index = vcpu::register_index(id) as u32;
+            VcpuResult::Ok
+        }
+        Err(result) => result,
+    })
 }
@@ -1,6 +1,9 @@
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use std::cell::RefCell;
+use std::ffi::CString;
 use std::os::raw::c_char;
+use std::panic::AssertUnwindSafe;
 use util::InteropGetName;
 use util_derive::InteropGetName;
 
@@ -18,6 +21,10 @@ pub enum VcpuResult {
     OutOfRange = 7,
     ExecutableLoadFailed = 8,
     ExecutableSaveFailed = 9,
+    UnknownName = 10,
+    StateLoadFailed = 11,
+    StateSaveFailed = 12,
+    InvalidInstruction = 13,
 }
 
 #[no_mangle]
@@ -25,10 +32,99 @@ pub unsafe extern "C" fn vcpu_result_get_description(
     result: i32,
     desc: *mut *const c_char,
 ) -> VcpuResult {
-    if let Some(result) = VcpuResult::from_i32(result) {
-        *desc = result.interop_name().as_ptr() as *const c_char;
-        VcpuResult::OutOfRange
-    } else {
-        VcpuResult::OutOfRange
+    contain(|| {
+        if let Some(result) = VcpuResult::from_i32(result) {
+            *desc = result.interop_name().as_ptr() as *const c_char;
+            VcpuResult::Ok
+        } else {
+            fail(
+                VcpuResult::OutOfRange,
+                format!("{} is not a result", result),
+            )
+        }
+    })
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(Default::default());
+}
+
+/// Records `message` as the last error of the current thread and returns `result`.
+pub fn fail<S: Into<String>>(result: VcpuResult, message: S) -> VcpuResult {
+    let message = message.into().replace('\0', " ");
+    LAST_ERROR.with(|error| *error.borrow_mut() = CString::new(message).unwrap_or_default());
+    result
+}
+
+/// Returns the last error of the current thread, which stays valid until the next error.
+pub fn last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ptr())
+}
+
+/// Returns a description of the last error of the calling thread, e.g. the diagnostics of the assembler or the
+/// address which could not be accessed. Functions only set the message if they fail, so it is only meaningful
+/// right after a call which did not return `Ok`. The string stays valid until the next error on the same thread.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_get_last_error_message() -> *const c_char {
+    contain(last_error)
+}
+
+/// The value an entry point returns if it panicked.
+pub trait OnPanic {
+    fn on_panic() -> Self;
+}
+
+impl OnPanic for VcpuResult {
+    fn on_panic() -> Self {
+        VcpuResult::UnknownError
     }
 }
+
+impl OnPanic for () {
+    fn on_panic() -> Self {}
+}
+
+impl OnPanic for bool {
+    fn on_panic() -> Self {
+        false
+    }
+}
+
+macro_rules! on_panic_zero {
+    ($($t:ty),*) => {
+        $(impl OnPanic for $t {
+            fn on_panic() -> Self {
+                0
+            }
+        })*
+    };
+}
+
+on_panic_zero!(i32, u32, u64, usize);
+
+impl<T> OnPanic for *const T {
+    fn on_panic() -> Self {
+        std::ptr::null()
+    }
+}
+
+impl<T> OnPanic for *mut T {
+    fn on_panic() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+/// Runs the body of an entry point and catches panics, since unwinding into the host is undefined behavior.
+/// After a panic, the last error describes it and the entry point returns `UnknownError`, a null pointer, zero
+/// or false.
+pub fn contain<R: OnPanic, F: FnOnce() -> R>(f: F) -> R {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_owned());
+        fail(VcpuResult::UnknownError, format!("Panicked: {}", message));
+        R::on_panic()
+    })
+}
@@ -1,21 +1,37 @@
+use crate::result::contain;
 use crate::util::destroy;
 
 pub struct SourceMap {
     pub data: Vec<u32>,
 }
 
+impl SourceMap {
+    /// Stores the start line and line count of every instruction.
+    pub fn new(source_map: vasm::SourceMap) -> Self {
+        let data = source_map
+            .into_iter()
+            .flat_map(|item| vec![item.start_line, item.line_count])
+            .collect();
+        SourceMap { data }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_source_map_get_data(
     source_map: *const SourceMap,
     data: *mut *const u32,
     data_len: *mut usize,
 ) {
-    let sm_data = &(*source_map).data;
-    *data = sm_data.as_ptr();
-    *data_len = sm_data.len();
+    contain(|| {
+        let sm_data = &(*source_map).data;
+        *data = sm_data.as_ptr();
+        *data_len = sm_data.len();
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_source_map_destroy(source_map: *mut SourceMap) {
-    destroy(source_map);
+    contain(|| {
+        destroy(source_map);
+    })
 }
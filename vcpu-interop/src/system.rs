@@ -0,0 +1,347 @@
+//! Save states of a processor together with its memory.
+//!
+//! A state starts with the magic bytes `VSTA` and a version, followed by the program counter, the exit code
+//! (-1 while running), every register of the active bank, every lane of the vector registers, the control and
+//! status registers (`CYCLE` and `INSTRET` as 64 bits, then `CAUSE`, `EPC`, `IE`, `IP`, `IVEC`, `IEPC` and
+//! `ISTATUS`), the number of the active bank, every register of the shadow bank and the contents of the memory,
+//! all little endian. Plain and IO memory is stored as its length and its bytes, composite memory as the address,
+//! length and bytes of every fragment.
+//! A state can only be loaded into a memory of the same kind and layout, since the memory is created by the host.
+
+use crate::memory::{Memory, MemoryVariant};
+use crate::processor::Processor;
+use crate::result::{contain, fail, VcpuResult};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::ffi::CStr;
+use std::io::{Cursor, Read};
+use std::os::raw::c_char;
+use std::slice;
+use util::Endian;
+use vcpu::{
+    ControlRegisters, ExitCode, RegisterFile, Storage, StorageMut, Vector, REGISTER_COUNT,
+    VECTOR_REGISTER_COUNT,
+};
+
+const MAGIC: &[u8; 4] = b"VSTA";
+/// Version 1 did not contain the vector and control registers and the shadow bank, which are zero after loading
+/// it.
+const VERSION: u32 = 2;
+
+const CONTIGUOUS: u32 = 0;
+const COMPOSITE: u32 = 1;
+
+/// The address and bytes of every part of a memory.
+fn memory_parts(variant: &MemoryVariant) -> Vec<(u32, Vec<u8>)> {
+    match variant {
+        MemoryVariant::Plain(inner) => vec![(0, inner.clone())],
+        MemoryVariant::IO(inner) => vec![(0, inner.data().to_vec())],
+        MemoryVariant::Composite(inner) => inner
+            .fragments()
+            .into_iter()
+            .map(|(_, address, length)| {
+                let bytes = (0..length)
+                    .map(|i| inner.read_byte(address + i).unwrap_or(0))
+                    .collect();
+                (address, bytes)
+            })
+            .collect(),
+    }
+}
+
+fn save(processor: &vcpu::Processor, variant: &MemoryVariant) -> Vec<u8> {
+    let mut state = MAGIC.to_vec();
+    state.write_u32::<Endian>(VERSION).unwrap();
+    state
+        .write_u32::<Endian>(processor.program_counter())
+        .unwrap();
+    let exit_code = processor.state().map_or(-1, |code| code.to_i32().unwrap());
+    state.write_i32::<Endian>(exit_code).unwrap();
+    for register in processor.registers().iter() {
+        state.write_u32::<Endian>(register.u()).unwrap();
+    }
+    for lane in processor.vectors().iter().flatten() {
+        state.write_u32::<Endian>(*lane).unwrap();
+    }
+    let csrs = processor.control_registers();
+    state.write_u64::<Endian>(csrs.cycles()).unwrap();
+    state.write_u64::<Endian>(csrs.instret()).unwrap();
+    for value in &[
+        csrs.cause(),
+        csrs.epc(),
+        csrs.ie(),
+        csrs.ip(),
+        csrs.ivec(),
+        csrs.iepc(),
+        csrs.istatus(),
+    ] {
+        state.write_u32::<Endian>(*value).unwrap();
+    }
+    let register_file = processor.register_file();
+    state
+        .write_u32::<Endian>(register_file.active_bank() as u32)
+        .unwrap();
+    for register in register_file.shadow().iter() {
+        state.write_u32::<Endian>(register.u()).unwrap();
+    }
+
+    let parts = memory_parts(variant);
+    if let MemoryVariant::Composite(_) = variant {
+        state.write_u32::<Endian>(COMPOSITE).unwrap();
+        state.write_u32::<Endian>(parts.len() as u32).unwrap();
+    } else {
+        state.write_u32::<Endian>(CONTIGUOUS).unwrap();
+    }
+    for (address, bytes) in parts {
+        if let MemoryVariant::Composite(_) = variant {
+            state.write_u32::<Endian>(address).unwrap();
+        }
+        state.write_u32::<Endian>(bytes.len() as u32).unwrap();
+        state.extend_from_slice(&bytes);
+    }
+    state
+}
+
+fn load(
+    processor: &mut vcpu::Processor,
+    variant: &mut MemoryVariant,
+    state: &[u8],
+) -> Result<(), String> {
+    let truncated = |_| "The state is truncated".to_owned();
+    let mut reader = Cursor::new(state);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).map_err(truncated)?;
+    if &magic != MAGIC {
+        return Err("The data is not a save state".to_owned());
+    }
+    let version = reader.read_u32::<Endian>().map_err(truncated)?;
+    if version == 0 || version > VERSION {
+        return Err(format!("Unsupported save state version {}", version));
+    }
+    let program_counter = reader.read_u32::<Endian>().map_err(truncated)?;
+    let exit_code = match reader.read_i32::<Endian>().map_err(truncated)? {
+        -1 => None,
+        code => {
+            Some(ExitCode::from_i32(code).ok_or_else(|| format!("{} is not an exit code", code))?)
+        }
+    };
+    let mut register_file = RegisterFile::new();
+    let mut registers = [0u32; REGISTER_COUNT];
+    for register in registers.iter_mut() {
+        *register = reader.read_u32::<Endian>().map_err(truncated)?;
+    }
+    let mut vectors = [Vector::default(); VECTOR_REGISTER_COUNT];
+    let mut csrs = ControlRegisters::new();
+    if version >= 2 {
+        for lane in vectors.iter_mut().flatten() {
+            *lane = reader.read_u32::<Endian>().map_err(truncated)?;
+        }
+        csrs.set_cycles(reader.read_u64::<Endian>().map_err(truncated)?);
+        csrs.set_instret(reader.read_u64::<Endian>().map_err(truncated)?);
+        csrs.set_cause(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_epc(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_ie(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_ip(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_ivec(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_iepc(reader.read_u32::<Endian>().map_err(truncated)?);
+        csrs.set_istatus(reader.read_u32::<Endian>().map_err(truncated)?);
+        match reader.read_u32::<Endian>().map_err(truncated)? {
+            0 => {}
+            1 => register_file.swap(),
+            bank => return Err(format!("{} is not a register bank", bank)),
+        }
+        for register in register_file.shadow_mut().iter_mut() {
+            register.set_u(reader.read_u32::<Endian>().map_err(truncated)?);
+        }
+    }
+    for (register, value) in register_file.active_mut().iter_mut().zip(registers.iter()) {
+        register.set_u(*value);
+    }
+
+    let kind = reader.read_u32::<Endian>().map_err(truncated)?;
+    let expected = memory_parts(variant);
+    let composite = matches!(variant, MemoryVariant::Composite(_));
+    if composite != (kind == COMPOSITE) {
+        return Err(format!(
+            "The state contains {} memory",
+            if kind == COMPOSITE {
+                "composite"
+            } else {
+                "plain or IO"
+            }
+        ));
+    }
+    let count = if composite {
+        reader.read_u32::<Endian>().map_err(truncated)? as usize
+    } else {
+        1
+    };
+    if count != expected.len() {
+        return Err(format!(
+            "The state contains {} fragments instead of {}",
+            count,
+            expected.len()
+        ));
+    }
+    let mut parts = Vec::with_capacity(count);
+    for (address, bytes) in expected {
+        let saved_address = if composite {
+            reader.read_u32::<Endian>().map_err(truncated)?
+        } else {
+            0
+        };
+        let length = reader.read_u32::<Endian>().map_err(truncated)?;
+        if saved_address != address || length as usize != bytes.len() {
+            return Err(format!(
+                "The state contains {} bytes at 0x{:08X} instead of {} bytes at 0x{:08X}",
+                length,
+                saved_address,
+                bytes.len(),
+                address
+            ));
+        }
+        let start = reader.position() as usize;
+        let data = state
+            .get(start..start + length as usize)
+            .ok_or_else(|| "The state is truncated".to_owned())?;
+        reader.set_position((start + data.len()) as u64);
+        parts.push((address, data));
+    }
+
+    match variant {
+        MemoryVariant::Plain(inner) => inner.copy_from_slice(parts[0].1),
+        MemoryVariant::IO(inner) => inner.data_mut().copy_from_slice(parts[0].1),
+        MemoryVariant::Composite(inner) => {
+            for (address, data) in parts {
+                for (i, byte) in data.iter().enumerate() {
+                    // fragments which are not writable keep their contents
+                    let _ = inner.write_byte(address + i as u32, *byte);
+                }
+            }
+        }
+    }
+    processor.set_program_counter(program_counter);
+    processor.set_state(exit_code);
+    *processor.register_file_mut() = register_file;
+    *processor.vectors_mut() = vectors;
+    *processor.control_registers_mut() = csrs;
+    Ok(())
+}
+
+/// Writes the size of the state of `processor` and `memory` to `size`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_system_get_state_size(
+    processor: *const Processor,
+    memory: *const Memory,
+    size: *mut usize,
+) -> VcpuResult {
+    contain(|| {
+        let processor = (*processor).lock();
+        (*memory).try_use(|variant| {
+            *size = save(&processor, variant).len();
+            VcpuResult::Ok
+        })
+    })
+}
+
+/// Saves the state of `processor` and `memory` into `buffer`, which must hold at least the size returned by
+/// `vcpu_system_get_state_size`.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_system_save_state(
+    processor: *const Processor,
+    memory: *const Memory,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> VcpuResult {
+    contain(|| {
+        let processor = (*processor).lock();
+        (*memory).try_use(|variant| {
+            let state = save(&processor, variant);
+            if state.len() > buffer_len {
+                return fail(
+                    VcpuResult::StateSaveFailed,
+                    format!(
+                        "The state needs {} bytes, but the buffer only has {}",
+                        state.len(),
+                        buffer_len
+                    ),
+                );
+            }
+            slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+            VcpuResult::Ok
+        })
+    })
+}
+
+/// Restores the state of `processor` and `memory` from `buffer`. Nothing is changed if loading fails.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_system_load_state(
+    processor: *mut Processor,
+    memory: *mut Memory,
+    buffer: *const u8,
+    buffer_len: usize,
+) -> VcpuResult {
+    contain(|| {
+        let state = slice::from_raw_parts(buffer, buffer_len);
+        let mut processor = (*processor).lock();
+        (*memory).try_use_mut(|variant| match load(&mut processor, variant, state) {
+            Ok(()) => VcpuResult::Ok,
+            Err(err) => fail(VcpuResult::StateLoadFailed, err),
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_system_save_state_file(
+    processor: *const Processor,
+    memory: *const Memory,
+    path: *const c_char,
+) -> VcpuResult {
+    contain(|| {
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(err) => {
+                return fail(
+                    VcpuResult::UTF8Error,
+                    format!("Path is not valid UTF-8: {}", err),
+                )
+            }
+        };
+        let processor = (*processor).lock();
+        (*memory).try_use(
+            |variant| match std::fs::write(path, save(&processor, variant)) {
+                Ok(()) => VcpuResult::Ok,
+                Err(err) => fail(
+                    VcpuResult::StateSaveFailed,
+                    format!("Writing state file \"{}\" failed: {}", path, err),
+                ),
+            },
+        )
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_system_load_state_file(
+    processor: *mut Processor,
+    memory: *mut Memory,
+    path: *const c_char,
+) -> VcpuResult {
+    contain(|| {
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(err) => {
+                return fail(
+                    VcpuResult::UTF8Error,
+                    format!("Path is not valid UTF-8: {}", err),
+                )
+            }
+        };
+        match std::fs::read(path) {
+            Ok(state) => vcpu_system_load_state(processor, memory, state.as_ptr(), state.len()),
+            Err(err) => fail(
+                VcpuResult::StateLoadFailed,
+                format!("Reading state file \"{}\" failed: {}", path, err),
+            ),
+        }
+    })
+}
@@ -1,9 +1,16 @@
+use crate::debugger::*;
+use crate::diagnostics::*;
+use crate::disassembly::*;
 use crate::executable::*;
 use crate::exit_code::*;
+use crate::machine::*;
 use crate::memory::*;
 use crate::processor::*;
 use crate::register::*;
 use crate::result::*;
+use crate::source_map::*;
+use crate::system::*;
+use crate::version::*;
 use std::ffi::{c_void, CStr, CString};
 use std::os::raw::c_char;
 use std::ptr::{null, null_mut};
@@ -19,12 +26,77 @@ fn composite_memory() {
     unsafe {
         let composite = vcpu_memory_create_comp();
         let plain = vcpu_memory_create_plain(16);
+        let device = vcpu_memory_create_plain(4);
 
         let key = get_c_str("f");
+        let device_key = get_c_str("device");
+
+        assert_eq!(
+            vcpu_memory_comp_mount(composite, 0x100, device_key.as_ptr(), device),
+            VcpuResult::Ok
+        );
+        assert_eq!(
+            vcpu_memory_comp_mount(composite, 0, key.as_ptr(), plain),
+            VcpuResult::Ok
+        );
+        assert_eq!(
+            vcpu_memory_comp_mount(composite, 0x80, key.as_ptr(), device),
+            VcpuResult::KeyAlreadyExists
+        );
+        assert_eq!(
+            vcpu_memory_comp_mount(composite, 8, device_key.as_ptr(), plain),
+            VcpuResult::KeyAlreadyExists
+        );
+        let other_key = get_c_str("other");
+        assert_eq!(
+            vcpu_memory_comp_mount(composite, 8, other_key.as_ptr(), plain),
+            VcpuResult::FragmentIntersection
+        );
+
+        let mut count = 0;
+        assert_eq!(
+            vcpu_memory_comp_get_fragment_count(composite, &mut count),
+            VcpuResult::Ok
+        );
+        assert_eq!(count, 2);
+        let (mut address, mut length, mut name, mut name_len) = (0, 0, null(), 0);
+        assert_eq!(
+            vcpu_memory_comp_get_fragment(
+                composite,
+                1,
+                &mut address,
+                &mut length,
+                &mut name,
+                &mut name_len
+            ),
+            VcpuResult::Ok
+        );
+        assert_eq!((address, length), (0x100, 4));
+        assert_eq!(std::slice::from_raw_parts(name, name_len), b"device");
+        assert_eq!(
+            vcpu_memory_comp_get_fragment(
+                composite,
+                2,
+                &mut address,
+                &mut length,
+                &mut name,
+                &mut name_len
+            ),
+            VcpuResult::OutOfRange
+        );
+        assert_eq!(
+            vcpu_memory_comp_get_fragment_count(plain, &mut count),
+            VcpuResult::InvalidType
+        );
 
-        vcpu_memory_comp_mount(composite, 0, key.as_ptr(), plain);
         vcpu_memory_comp_unmount(composite, key.as_ptr());
+        assert_eq!(
+            vcpu_memory_comp_get_fragment_count(composite, &mut count),
+            VcpuResult::Ok
+        );
+        assert_eq!(count, 1);
 
+        vcpu_memory_destroy(device);
         vcpu_memory_destroy(plain);
         vcpu_memory_destroy(composite);
     }
@@ -72,6 +144,494 @@ fn run_simple() {
     }
 }
 
+#[test]
+fn run_for_and_tick() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ]);
+        let (instr, instr_len) = (instructions.as_ptr(), instructions.len());
+
+        assert_eq!(
+            vcpu_processor_tick(processor, instr, instr_len, memory),
+            VcpuResult::Ok
+        );
+        assert_eq!(vcpu_processor_get_program_counter(processor), 4);
+
+        let mut executed = 0u64;
+        assert_eq!(
+            vcpu_processor_run_for(processor, instr, instr_len, memory, 1, &mut executed),
+            VcpuResult::Ok
+        );
+        assert_eq!(executed, 1);
+        assert!(!vcpu_processor_is_stopped(processor));
+        assert_eq!(vcpu_processor_get_state(processor), -1);
+
+        assert_eq!(
+            vcpu_processor_run_for(processor, instr, instr_len, memory, 10, &mut executed),
+            VcpuResult::Ok
+        );
+        assert_eq!(executed, 2);
+        assert!(vcpu_processor_is_stopped(processor));
+        assert_eq!(vcpu_processor_get_state(processor), ExitCode::Halted as i32);
+
+        let mut value = 0;
+        vcpu_processor_get_register(processor, RegisterId::T0 as u32, &mut value);
+        assert_eq!(value, 3);
+
+        assert_eq!(
+            vcpu_processor_run_for(processor, instr, instr_len, memory, 10, null_mut()),
+            VcpuResult::Ok
+        );
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn breakpoints_and_events() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        let debugger = vcpu_debugger_create();
+
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(DIVI, T0, T0, 0),
+        ]);
+        let (instr, instr_len) = (instructions.as_ptr(), instructions.len());
+
+        assert!(vcpu_debugger_add_breakpoint(debugger, 8));
+        assert!(!vcpu_debugger_add_breakpoint(debugger, 8));
+        assert!(vcpu_debugger_add_breakpoint(debugger, 4));
+        assert!(vcpu_debugger_remove_breakpoint(debugger, 4));
+        assert!(!vcpu_debugger_remove_breakpoint(debugger, 4));
+        assert!(vcpu_debugger_has_breakpoint(debugger, 8));
+        assert_eq!(vcpu_debugger_get_breakpoint_count(debugger), 1);
+
+        let mut event = std::mem::zeroed::<DebugEvent>();
+        let mut run = |max_instructions| {
+            assert_eq!(
+                vcpu_debugger_run_until_event(
+                    debugger,
+                    processor,
+                    instr,
+                    instr_len,
+                    memory,
+                    max_instructions,
+                    &mut event
+                ),
+                VcpuResult::Ok
+            );
+            event
+        };
+
+        let event = run(0);
+        assert_eq!(event.kind, DebugEventKind::Breakpoint);
+        assert_eq!((event.address, event.exit_code, event.executed), (8, -1, 2));
+
+        let event = run(1);
+        assert_eq!(event.kind, DebugEventKind::Paused);
+        assert_eq!((event.address, event.executed), (12, 1));
+
+        let event = run(0);
+        assert_eq!(event.kind, DebugEventKind::Fault);
+        assert_eq!(event.exit_code, ExitCode::DivisionByZero as i32);
+        assert_eq!(event.executed, 1);
+
+        vcpu_processor_reset(processor);
+        vcpu_debugger_clear_breakpoints(debugger);
+        let event = run(0);
+        assert_eq!(event.kind, DebugEventKind::Fault);
+        assert_eq!(event.executed, 4);
+
+        vcpu_debugger_destroy(debugger);
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn save_and_load_state() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(SW, T0, ZERO, 4),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ]);
+        let (instr, instr_len) = (instructions.as_ptr(), instructions.len());
+        vcpu_processor_run_for(processor, instr, instr_len, memory, 2, null_mut());
+
+        let mut size = 0;
+        assert_eq!(
+            vcpu_system_get_state_size(processor, memory, &mut size),
+            VcpuResult::Ok
+        );
+        let mut state = vec![0u8; size];
+        assert_eq!(
+            vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size - 1),
+            VcpuResult::StateSaveFailed
+        );
+        assert_eq!(
+            vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size),
+            VcpuResult::Ok
+        );
+
+        vcpu_processor_run(processor, instr, instr_len, memory);
+        vcpu_processor_reset(processor);
+        let zeros = [0u8; 16];
+        vcpu_memory_write(memory, zeros.as_ptr(), 0, 16);
+
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, state.as_ptr(), state.len()),
+            VcpuResult::Ok
+        );
+        assert_eq!(vcpu_processor_get_program_counter(processor), 8);
+        assert_eq!(vcpu_processor_get_state(processor), -1);
+        let mut value = 0;
+        vcpu_processor_get_register(processor, RegisterId::T0 as u32, &mut value);
+        assert_eq!(value, 1);
+        let mut bytes = [0u8; 4];
+        vcpu_memory_read(memory, bytes.as_mut_ptr(), 4, 4);
+        assert_eq!(bytes, [1, 0, 0, 0]);
+
+        // a state only fits a memory with the same layout, and a failed load changes nothing
+        let other = vcpu_memory_create_plain(32);
+        assert_eq!(
+            vcpu_system_load_state(processor, other, state.as_ptr(), state.len()),
+            VcpuResult::StateLoadFailed
+        );
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, state.as_ptr(), 20),
+            VcpuResult::StateLoadFailed
+        );
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert_eq!(message, "The state is truncated");
+        assert_eq!(vcpu_processor_get_program_counter(processor), 8);
+
+        let composite = vcpu_memory_create_comp();
+        let key = get_c_str("ram");
+        vcpu_memory_comp_mount(composite, 0x100, key.as_ptr(), other);
+        vcpu_memory_write(composite, [7u8; 4].as_ptr(), 0x104, 4);
+        vcpu_system_get_state_size(processor, composite, &mut size);
+        let mut state = vec![0u8; size];
+        vcpu_system_save_state(processor, composite, state.as_mut_ptr(), size);
+        vcpu_memory_write(composite, [0u8; 4].as_ptr(), 0x104, 4);
+        assert_eq!(
+            vcpu_system_load_state(processor, composite, state.as_ptr(), state.len()),
+            VcpuResult::Ok
+        );
+        vcpu_memory_read(composite, bytes.as_mut_ptr(), 0x104, 4);
+        assert_eq!(bytes, [7; 4]);
+
+        vcpu_memory_destroy(composite);
+        vcpu_memory_destroy(other);
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn request_stop_from_another_thread() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        // an endless loop which counts its iterations in memory
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(SW, T0, ZERO, 0),
+            instr_j!(JMP, -8),
+        ]);
+
+        // raw pointers cannot be sent, but the handles can be used from any thread
+        let (processor_address, memory_address) = (processor as usize, memory as usize);
+        let runner = std::thread::spawn(move || {
+            vcpu_processor_run(
+                processor_address as *mut _,
+                instructions.as_ptr(),
+                instructions.len(),
+                memory_address as *mut _,
+            )
+        });
+
+        // wait until the program is running, while the memory can still be read in between
+        let mut bytes = [0u8; 4];
+        while bytes == [0; 4] {
+            assert_eq!(
+                vcpu_memory_read(memory, bytes.as_mut_ptr(), 0, 4),
+                VcpuResult::Ok
+            );
+        }
+        vcpu_processor_request_stop(processor);
+        assert_eq!(runner.join().unwrap(), VcpuResult::Ok);
+        assert_eq!(
+            vcpu_processor_get_state(processor),
+            ExitCode::Terminated as i32
+        );
+
+        let mut value = 0;
+        vcpu_processor_get_register(processor, RegisterId::T0 as u32, &mut value);
+        vcpu_memory_read(memory, bytes.as_mut_ptr(), 0, 4);
+        assert!(value > 0 && value - u32::from_le_bytes(bytes) as i32 <= 1);
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn save_and_load_vector_registers() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        *(*processor).lock().vector_mut(VectorRegisterId::Q0) = [1, 2, 3, 4];
+        *(*processor).lock().vector_mut(VectorRegisterId::Q7) = [u32::MAX, 0, 7, 8];
+
+        let mut size = 0;
+        vcpu_system_get_state_size(processor, memory, &mut size);
+        let mut state = vec![0u8; size];
+        vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size);
+
+        vcpu_processor_reset(processor);
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, state.as_ptr(), state.len()),
+            VcpuResult::Ok
+        );
+        assert_eq!(
+            *(*processor).lock().vector(VectorRegisterId::Q0),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            *(*processor).lock().vector(VectorRegisterId::Q7),
+            [u32::MAX, 0, 7, 8]
+        );
+
+        // version 1 has no vector and control registers and no shadow bank, which are cleared when it is loaded
+        let header = 4 + 4 + 4 + 4 + 4 * REGISTER_COUNT;
+        let added =
+            4 * VECTOR_REGISTER_COUNT * VECTOR_LANES + 8 + 8 + 4 * 7 + 4 + 4 * REGISTER_COUNT;
+        let mut old = state[..header].to_vec();
+        old[4..8].copy_from_slice(&1u32.to_le_bytes());
+        old.extend_from_slice(&state[header + added..]);
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, old.as_ptr(), old.len()),
+            VcpuResult::Ok
+        );
+        assert_eq!(*(*processor).lock().vector(VectorRegisterId::Q0), [0; 4]);
+
+        old[4..8].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, old.as_ptr(), old.len()),
+            VcpuResult::StateLoadFailed
+        );
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn save_and_load_control_registers() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, ZERO, 5),
+            instr_i!(CSRW, ZERO, T0, CSR_IE as i16),
+            instr_i!(DIVI, T0, T0, 0),
+        ]);
+        vcpu_processor_run(processor, instructions.as_ptr(), instructions.len(), memory);
+        let saved = *(*processor).lock().control_registers();
+        assert_eq!(saved.cycles(), 3);
+        assert_eq!(saved.ie(), 5);
+        assert_eq!(saved.epc(), 8);
+
+        let mut size = 0;
+        vcpu_system_get_state_size(processor, memory, &mut size);
+        let mut state = vec![0u8; size];
+        vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size);
+
+        vcpu_processor_reset(processor);
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, state.as_ptr(), state.len()),
+            VcpuResult::Ok
+        );
+        let processor_ref = (*processor).lock();
+        assert_eq!(*processor_ref.control_registers(), saved);
+        assert_eq!(
+            processor_ref.csr(CSR_CAUSE),
+            Some(ExitCode::DivisionByZero as u32)
+        );
+        drop(processor_ref);
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn save_and_load_register_banks() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        // the handler at 8 is entered with the shadow bank, and the state is saved before it returns
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(HALT, ZERO, ZERO, 0),
+            instr_i!(LI, T0, ZERO, 40),
+            instr_i!(IRET, ZERO, ZERO, 0),
+        ]);
+        let (instr, instr_len) = (instructions.as_ptr(), instructions.len());
+        {
+            let mut processor = (*processor).lock();
+            processor.register_mut(RegisterId::T0).set_u(5);
+            processor.control_registers_mut().set_ivec(8);
+            processor.control_registers_mut().set_ie(1);
+        }
+        assert_eq!(
+            vcpu_processor_raise_interrupt(processor, 32),
+            VcpuResult::OutOfRange
+        );
+        assert_eq!(vcpu_processor_raise_interrupt(processor, 0), VcpuResult::Ok);
+        vcpu_processor_tick(processor, instr, instr_len, memory);
+
+        let mut size = 0;
+        vcpu_system_get_state_size(processor, memory, &mut size);
+        let mut state = vec![0u8; size];
+        vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size);
+
+        vcpu_processor_reset(processor);
+        assert_eq!(
+            vcpu_system_load_state(processor, memory, state.as_ptr(), state.len()),
+            VcpuResult::Ok
+        );
+        {
+            let processor = (*processor).lock();
+            assert_eq!(processor.register_file().active_bank(), 1);
+            assert_eq!(processor.register(RegisterId::T0).u(), 40);
+            assert_eq!(
+                processor.csr(CSR_ISTATUS),
+                Some(ISTATUS_HANDLING | ISTATUS_BANKED)
+            );
+            assert_eq!(processor.csr(CSR_IP), Some(1));
+        }
+
+        // the program resumes on its own bank after the handler returns
+        assert_eq!(vcpu_processor_lower_interrupt(processor, 0), VcpuResult::Ok);
+        vcpu_processor_run(processor, instr, instr_len, memory);
+        let mut value = 0;
+        vcpu_processor_get_register(processor, RegisterId::T0 as u32, &mut value);
+        assert_eq!(value, 6);
+        assert_eq!((*processor).lock().register_file().active_bank(), 0);
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+/// Raises interrupt line 0 of the processor pointed to by `user_data`.
+extern "C" fn on_write_raise(
+    _data: *const u8,
+    _data_len: usize,
+    _address: u32,
+    _size: u32,
+    user_data: *mut c_void,
+) {
+    unsafe {
+        vcpu_processor_raise_interrupt(user_data as *mut crate::processor::Processor, 0);
+    }
+}
+
+#[test]
+fn raise_interrupt_from_io_callback() {
+    unsafe {
+        let processor = vcpu_processor_create();
+        let memory = vcpu_memory_create_io(4, None, Some(on_write_raise), processor as *mut c_void);
+        // the write raises the interrupt, whose handler at 8 is entered instead of the HALT at 4
+        let instructions = instructions_from_words(&[
+            instr_i!(SW, T1, ZERO, 0),
+            instr_i!(HALT, ZERO, ZERO, 0),
+            instr_i!(LI, T0, ZERO, 40),
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ]);
+        {
+            let mut processor = (*processor).lock();
+            processor.control_registers_mut().set_ivec(8);
+            processor.control_registers_mut().set_ie(1);
+        }
+        assert_eq!(
+            vcpu_processor_run(processor, instructions.as_ptr(), instructions.len(), memory),
+            VcpuResult::Ok
+        );
+        let mut value = 0;
+        vcpu_processor_get_register(processor, RegisterId::T0 as u32, &mut value);
+        assert_eq!(value, 40);
+        assert_eq!(vcpu_processor_get_program_counter(processor), 12);
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
+#[test]
+fn save_state_while_running() {
+    unsafe {
+        let memory = vcpu_memory_create_plain(16);
+        let processor = vcpu_processor_create();
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(SW, T0, ZERO, 0),
+            instr_j!(JMP, -8),
+        ]);
+
+        let (processor_address, memory_address) = (processor as usize, memory as usize);
+        let runner = std::thread::spawn(move || {
+            vcpu_processor_run(
+                processor_address as *mut _,
+                instructions.as_ptr(),
+                instructions.len(),
+                memory_address as *mut _,
+            )
+        });
+
+        let mut bytes = [0u8; 4];
+        while bytes == [0; 4] {
+            vcpu_memory_read(memory, bytes.as_mut_ptr(), 0, 4);
+        }
+        // saving locks the processor and the memory in the same order as the run, so both make progress
+        let mut size = 0;
+        for _ in 0..1000 {
+            assert_eq!(
+                vcpu_system_get_state_size(processor, memory, &mut size),
+                VcpuResult::Ok
+            );
+            let mut state = vec![0u8; size];
+            assert_eq!(
+                vcpu_system_save_state(processor, memory, state.as_mut_ptr(), size),
+                VcpuResult::Ok
+            );
+        }
+        vcpu_processor_request_stop(processor);
+        assert_eq!(runner.join().unwrap(), VcpuResult::Ok);
+
+        vcpu_processor_destroy(processor);
+        vcpu_memory_destroy(memory);
+    }
+}
+
 #[test]
 fn run_assembled() {
     unsafe {
@@ -151,6 +711,261 @@ HALT";
     }
 }
 
+#[test]
+fn assemble_with_diagnostics() {
+    unsafe {
+        let source = get_c_str(".data\n.instructions\nSTUFF\nHALT");
+        let mut executable: *mut Executable = null_mut();
+        let mut diagnostics: *mut Diagnostics = null_mut();
+        assert_eq!(
+            vcpu_executable_assemble_with_diagnostics(
+                source.as_ptr(),
+                0,
+                &mut executable,
+                null_mut(),
+                &mut diagnostics
+            ),
+            VcpuResult::AssemblerError
+        );
+        assert_eq!(executable, null_mut());
+        assert_eq!(vcpu_diagnostics_get_count(diagnostics), 1);
+
+        let mut diagnostic = std::mem::zeroed::<Diagnostic>();
+        assert_eq!(
+            vcpu_diagnostics_get(diagnostics, 0, &mut diagnostic),
+            VcpuResult::Ok
+        );
+        assert_eq!(diagnostic.severity, DiagnosticSeverity::Error);
+        assert_eq!((diagnostic.line, diagnostic.column), (3, 1));
+        assert!(!CStr::from_ptr(diagnostic.message).to_bytes().is_empty());
+        assert_eq!(
+            vcpu_diagnostics_get(diagnostics, 1, &mut diagnostic),
+            VcpuResult::OutOfRange
+        );
+        vcpu_diagnostics_destroy(diagnostics);
+
+        let source = get_c_str(".data\n.instructions\nunused: SLLI $T0, $T0, 32\nHALT");
+        let mut source_map: *mut SourceMap = null_mut();
+        assert_eq!(
+            vcpu_executable_assemble_with_diagnostics(
+                source.as_ptr(),
+                0,
+                &mut executable,
+                &mut source_map,
+                &mut diagnostics
+            ),
+            VcpuResult::Ok
+        );
+        assert_ne!(executable, null_mut());
+        assert_ne!(source_map, null_mut());
+
+        let mut data: *const Diagnostic = null();
+        let mut data_len = 0;
+        vcpu_diagnostics_get_data(diagnostics, &mut data, &mut data_len);
+        assert_eq!(data_len, 2);
+        let warnings = std::slice::from_raw_parts(data, data_len);
+        assert!(warnings
+            .iter()
+            .all(|warning| warning.severity == DiagnosticSeverity::Warning && warning.line == 3));
+
+        vcpu_diagnostics_destroy(diagnostics);
+        vcpu_source_map_destroy(source_map);
+        vcpu_executable_destroy(executable);
+    }
+}
+
+#[test]
+fn contain_panics() {
+    unsafe {
+        let result: VcpuResult = contain(|| panic!("Invalid handle {}", 42));
+        assert_eq!(result, VcpuResult::UnknownError);
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert_eq!(message, "Panicked: Invalid handle 42");
+
+        let pointer: *mut Memory = contain(|| panic!("Out of handles"));
+        assert_eq!(pointer, null_mut());
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert_eq!(message, "Panicked: Out of handles");
+
+        assert_eq!(contain(|| VcpuResult::Ok), VcpuResult::Ok);
+    }
+}
+
+#[test]
+fn disassemble() {
+    unsafe {
+        let mut buffer = [0 as c_char; 32];
+        let word = instr_i!(ADDI, T0, T1, 5);
+        assert_eq!(
+            vcpu_disassemble(word, buffer.as_mut_ptr(), buffer.len()),
+            VcpuResult::Ok
+        );
+        assert_eq!(
+            CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(),
+            "ADDI $T0, $T1, 5"
+        );
+        assert_eq!(
+            vcpu_disassemble(word, buffer.as_mut_ptr(), 16),
+            VcpuResult::OutOfRange
+        );
+        assert_eq!(
+            vcpu_disassemble(0xFFFF_FFFF, buffer.as_mut_ptr(), buffer.len()),
+            VcpuResult::InvalidInstruction
+        );
+
+        let instructions = instructions_from_words(&[
+            instr_i!(ADDI, T0, T0, 1),
+            instr_i!(BNZ, ZERO, T0, -4),
+            0xFFFF_FFFF,
+            instr_i!(HALT, ZERO, ZERO, 0),
+        ]);
+        let mut disassembly: *mut Disassembly = null_mut();
+        assert_eq!(
+            vcpu_disassemble_range(
+                instructions.as_ptr(),
+                instructions.len(),
+                4,
+                4,
+                &mut disassembly
+            ),
+            VcpuResult::OutOfRange
+        );
+        assert_eq!(
+            vcpu_disassemble_range(
+                instructions.as_ptr(),
+                instructions.len(),
+                4,
+                3,
+                &mut disassembly
+            ),
+            VcpuResult::Ok
+        );
+        assert_eq!(vcpu_disassembly_get_count(disassembly), 3);
+        let mut data: *const DisassemblyLine = null();
+        let mut data_len = 0;
+        vcpu_disassembly_get_data(disassembly, &mut data, &mut data_len);
+        let lines = std::slice::from_raw_parts(data, data_len);
+
+        assert_eq!((lines[0].address, lines[0].valid), (4, true));
+        assert_eq!((lines[0].has_target, lines[0].target), (true, 0));
+        assert_eq!(
+            CStr::from_ptr(lines[0].text).to_str().unwrap(),
+            "BNZ $T0, -4"
+        );
+        assert_eq!((lines[1].word, lines[1].valid), (0xFFFF_FFFF, false));
+        assert_eq!(CStr::from_ptr(lines[1].text).to_str().unwrap(), "???");
+        assert_eq!(CStr::from_ptr(lines[2].text).to_str().unwrap(), "HALT");
+        assert!(!lines[2].has_target);
+
+        vcpu_disassembly_destroy(disassembly);
+    }
+}
+
+#[test]
+fn version_and_capabilities() {
+    unsafe {
+        let (mut major, mut minor, mut patch) = (99, 99, 99);
+        vcpu_get_version(&mut major, &mut minor, &mut patch);
+        assert_eq!(
+            format!("{}.{}.{}", major, minor, patch),
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let capabilities = vcpu_get_capabilities();
+        for capability in [
+            CAPABILITY_IO_MEMORY,
+            CAPABILITY_DEBUGGER,
+            CAPABILITY_THREADS,
+        ] {
+            assert_ne!(capabilities & capability, 0);
+        }
+        assert_eq!(capabilities & (1 << 63), 0);
+    }
+}
+
+#[test]
+fn abi_version() {
+    unsafe {
+        assert_eq!(vcpu_get_abi_version(), ABI_VERSION);
+    }
+}
+
+/// The layouts of the structs are part of the ABI, changing them requires a new `ABI_VERSION`.
+#[test]
+fn struct_layouts() {
+    use std::mem::{align_of, size_of};
+    assert_eq!((size_of::<DebugEvent>(), align_of::<DebugEvent>()), (24, 8));
+    assert_eq!(
+        (size_of::<Diagnostic>(), align_of::<Diagnostic>()),
+        (16 + 2 * size_of::<usize>(), size_of::<usize>())
+    );
+    assert_eq!(
+        (size_of::<DisassemblyLine>(), align_of::<DisassemblyLine>()),
+        (16 + size_of::<usize>(), size_of::<usize>())
+    );
+}
+
+#[test]
+fn header_up_to_date() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/vcpu.h"));
+    let checked_in = include_str!("../include/vcpu.h");
+    assert!(
+        generated == checked_in,
+        "include/vcpu.h is outdated, run generate_bindings.sh"
+    );
+}
+
+#[test]
+fn last_error_message() {
+    unsafe {
+        let source = get_c_str(".data\n.instructions\nSTUFF\nHALT");
+        let mut executable: *mut Executable = null_mut();
+        assert_eq!(
+            vcpu_executable_assemble(source.as_ptr(), 0, &mut executable, null_mut(), null_mut()),
+            VcpuResult::AssemblerError
+        );
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert!(message.contains("--> 3:1"), "{}", message);
+
+        let memory = vcpu_memory_create_plain(16);
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            vcpu_memory_read(memory, buffer.as_mut_ptr(), 12, 8),
+            VcpuResult::OutOfRange
+        );
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert!(message.contains("0x0000000C"), "{}", message);
+
+        let composite = vcpu_memory_create_comp();
+        assert_eq!(
+            vcpu_memory_comp_get_fragment_count(memory, &mut 0),
+            VcpuResult::InvalidType
+        );
+        let message = CStr::from_ptr(vcpu_get_last_error_message())
+            .to_str()
+            .unwrap();
+        assert!(message.contains("composite"), "{}", message);
+
+        let mut description: *const c_char = null();
+        assert_eq!(
+            vcpu_result_get_description(VcpuResult::OutOfRange as i32, &mut description),
+            VcpuResult::Ok
+        );
+        assert_eq!(CStr::from_ptr(description).to_str().unwrap(), "OutOfRange");
+
+        vcpu_memory_destroy(composite);
+        vcpu_memory_destroy(memory);
+    }
+}
+
 #[test]
 fn get_register_name_valid() {
     unsafe {
@@ -186,6 +1001,58 @@ fn get_register_name_invalid() {
     }
 }
 
+#[test]
+fn access_registers() {
+    unsafe {
+        let processor = vcpu_processor_create();
+        let name = get_c_str("$t1");
+        let mut value = 0;
+
+        assert_eq!(
+            vcpu_processor_set_register_by_name(processor, name.as_ptr(), -5),
+            VcpuResult::Ok
+        );
+        assert_eq!(
+            vcpu_processor_get_register(processor, RegisterId::T1 as u32, &mut value),
+            VcpuResult::Ok
+        );
+        assert_eq!(value, -5);
+
+        assert_eq!(
+            vcpu_processor_set_register(processor, RegisterId::SP as u32, 64),
+            VcpuResult::Ok
+        );
+        let name = get_c_str("SP");
+        assert_eq!(
+            vcpu_processor_get_register_by_name(processor, name.as_ptr(), &mut value),
+            VcpuResult::Ok
+        );
+        assert_eq!(value, 64);
+
+        let mut index = 0;
+        assert_eq!(
+            vcpu_register_get_index(name.as_ptr(), &mut index),
+            VcpuResult::Ok
+        );
+        assert_eq!(index, RegisterId::SP as u32);
+
+        let unknown = get_c_str("T99");
+        assert_eq!(
+            vcpu_processor_get_register_by_name(processor, unknown.as_ptr(), &mut value),
+            VcpuResult::UnknownName
+        );
+        assert_eq!(
+            vcpu_register_get_index(unknown.as_ptr(), &mut index),
+            VcpuResult::UnknownName
+        );
+
+        vcpu_processor_set_program_counter(processor, 12);
+        assert_eq!(vcpu_processor_get_program_counter(processor), 12);
+
+        vcpu_processor_destroy(processor);
+    }
+}
+
 extern "C" fn can_write_dummy(
     _data: *const u8,
     _data_len: usize,
@@ -234,7 +1101,8 @@ halt";
         vcpu_executable_get_instructions(executable, &mut instr, &mut instr_len);
 
         let plain_mem = vcpu_memory_create_plain(1024);
-        let io_mem = vcpu_memory_create_io(1, can_write_dummy, on_write_dummy, null_mut());
+        let io_mem =
+            vcpu_memory_create_io(1, Some(can_write_dummy), Some(on_write_dummy), null_mut());
         let comp_mem = vcpu_memory_create_comp();
 
         let main_key = get_c_str("main");
@@ -278,7 +1146,8 @@ halt";
 fn access_comp_mem() {
     unsafe {
         let plain_mem = vcpu_memory_create_plain(1024);
-        let io_mem = vcpu_memory_create_io(1, can_write_dummy, on_write_dummy, null_mut());
+        let io_mem =
+            vcpu_memory_create_io(1, Some(can_write_dummy), Some(on_write_dummy), null_mut());
         let comp_mem = vcpu_memory_create_comp();
 
         let main_key = get_c_str("main");
@@ -306,10 +1175,124 @@ fn access_comp_mem() {
     }
 }
 
+#[test]
+fn bulk_memory_access() {
+    unsafe {
+        let plain_mem = vcpu_memory_create_plain(64);
+        let comp_mem = vcpu_memory_create_comp();
+        let key = get_c_str("main");
+        assert_eq!(
+            vcpu_memory_comp_mount(comp_mem, 0x100, key.as_ptr(), plain_mem),
+            VcpuResult::Ok
+        );
+
+        let mut length = 0;
+        assert_eq!(
+            vcpu_memory_get_length(plain_mem, &mut length),
+            VcpuResult::Ok
+        );
+        assert_eq!(length, 64);
+        assert_eq!(
+            vcpu_memory_get_length(comp_mem, &mut length),
+            VcpuResult::Ok
+        );
+        assert_eq!(length, 0x140);
+
+        let data = [1u8, 2, 3, 4, 5];
+        assert_eq!(
+            vcpu_memory_write(comp_mem, data.as_ptr(), 0x102, data.len() as u32),
+            VcpuResult::Ok
+        );
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            vcpu_memory_read(plain_mem, buffer.as_mut_ptr(), 0, 8),
+            VcpuResult::Ok
+        );
+        assert_eq!(buffer, [0, 0, 1, 2, 3, 4, 5, 0]);
+        assert_eq!(
+            vcpu_memory_read(comp_mem, buffer.as_mut_ptr(), 0x103, 2),
+            VcpuResult::Ok
+        );
+        assert_eq!(buffer[..2], [2, 3]);
+
+        assert_eq!(
+            vcpu_memory_read(comp_mem, buffer.as_mut_ptr(), 0x13C, 8),
+            VcpuResult::OutOfRange
+        );
+        assert_eq!(
+            vcpu_memory_write(comp_mem, data.as_ptr(), 0, data.len() as u32),
+            VcpuResult::OutOfRange
+        );
+
+        vcpu_memory_destroy(comp_mem);
+        vcpu_memory_destroy(plain_mem);
+    }
+}
+
+/// Only allows writing to the first byte.
+extern "C" fn can_write_first(
+    _data: *const u8,
+    _data_len: usize,
+    address: u32,
+    _size: u32,
+    _user_data: *mut c_void,
+) -> bool {
+    address == 0
+}
+
+/// Counts the writes in the `u32` pointed to by `user_data`.
+extern "C" fn on_write_count(
+    _data: *const u8,
+    _data_len: usize,
+    _address: u32,
+    _size: u32,
+    user_data: *mut c_void,
+) {
+    unsafe {
+        *(user_data as *mut u32) += 1;
+    }
+}
+
+#[test]
+fn io_memory_callbacks() {
+    unsafe {
+        let mut writes = 0u32;
+        let io_mem = vcpu_memory_create_io(2, None, None, null_mut());
+        assert_eq!((*io_mem).write_byte(1, 7), Ok(()));
+        assert_eq!((*io_mem).read_byte(1), Ok(7));
+
+        assert_eq!(
+            vcpu_memory_io_set_handler(
+                io_mem,
+                Some(can_write_first),
+                Some(on_write_count),
+                &mut writes as *mut u32 as *mut c_void
+            ),
+            VcpuResult::Ok
+        );
+        assert_eq!((*io_mem).read_byte(1), Ok(7));
+        assert_eq!((*io_mem).write_byte(0, 1), Ok(()));
+        assert_eq!((*io_mem).write_byte(1, 2), Ok(()));
+        assert_eq!((*io_mem).read_byte(0), Ok(1));
+        assert_eq!((*io_mem).read_byte(1), Ok(7));
+        assert_eq!(writes, 1);
+
+        let plain_mem = vcpu_memory_create_plain(1);
+        assert_eq!(
+            vcpu_memory_io_set_handler(plain_mem, None, None, null_mut()),
+            VcpuResult::InvalidType
+        );
+
+        vcpu_memory_destroy(plain_mem);
+        vcpu_memory_destroy(io_mem);
+    }
+}
+
 #[test]
 fn write_io_memory() {
     unsafe {
-        let io_mem = vcpu_memory_create_io(1, can_write_dummy, on_write_dummy, null_mut());
+        let io_mem =
+            vcpu_memory_create_io(1, Some(can_write_dummy), Some(on_write_dummy), null_mut());
 
         assert_eq!((*io_mem).write_byte(0, 1), Ok(()));
         assert_eq!((*io_mem).read_byte(0), Ok(1));
@@ -336,3 +1319,144 @@ fn get_exit_code_desc() {
         assert_eq!(CStr::from_ptr(name).to_str(), Ok("BadProgramCounter"));
     }
 }
+
+#[test]
+fn executable_sections() {
+    unsafe {
+        let mut executable = Executable::from(0x100, vec![0; 4], vec![1, 2]);
+        executable
+            .add_section(vex::Section::zero_fill(0x200, 16))
+            .unwrap();
+        let executable = &executable as *const Executable;
+
+        assert_eq!(vcpu_executable_get_section_count(executable), 2);
+        assert_eq!(vcpu_executable_get_memory_size(executable), 0x210);
+
+        let (mut address, mut size, mut flags) = (0, 0, 0);
+        let mut data: *const u8 = null();
+        let mut data_len: usize = 0;
+        let mut get_section = |index| {
+            vcpu_executable_get_section(
+                executable,
+                index,
+                &mut address,
+                &mut size,
+                &mut flags,
+                &mut data,
+                &mut data_len,
+            )
+        };
+        assert_eq!(get_section(1), VcpuResult::Ok);
+        assert_eq!(get_section(2), VcpuResult::OutOfRange);
+        assert_eq!((address, size, flags, data_len), (0x200, 16, 2, 0));
+    }
+}
+
+fn create_machine(source: &str, ram_size: u32, devices: *const c_char) -> *mut Machine {
+    let executable = vasm::assemble_program(source, 0).unwrap().executable;
+    let mut machine: *mut Machine = null_mut();
+    unsafe {
+        assert_eq!(
+            vcpu_machine_create(&executable, ram_size, devices, &mut machine),
+            VcpuResult::Ok
+        );
+    }
+    machine
+}
+
+#[test]
+fn machine_runs_program() {
+    unsafe {
+        let machine = create_machine(
+            ".include <std/uart.vasm>
+.data
+text:   .byte 104, 105, 0
+.instructions
+start:  LDA $A0, text
+        JL uart_puts
+        LI $V0, 3
+        HALT
+.entry start",
+            1024,
+            null(),
+        );
+        assert_eq!(vcpu_machine_run(machine, 2), -1);
+        assert_eq!(vcpu_machine_get_executed(machine), 2);
+        assert_eq!(vcpu_machine_run(machine, 0), 0);
+
+        let mut value = 0;
+        assert_eq!(
+            vcpu_machine_get_register(machine, RegisterId::V0 as u32, &mut value),
+            VcpuResult::Ok
+        );
+        assert_eq!(value, 3);
+
+        assert_eq!(vcpu_machine_get_console_len(machine), 2);
+        let mut buf = [0u8; 1];
+        assert_eq!(vcpu_machine_read_console(machine, buf.as_mut_ptr(), 1), 1);
+        assert_eq!(&buf, b"h");
+        assert_eq!(vcpu_machine_read_console(machine, buf.as_mut_ptr(), 1), 1);
+        assert_eq!(&buf, b"i");
+        assert_eq!(vcpu_machine_get_console_len(machine), 0);
+
+        assert_eq!(vcpu_machine_reset(machine), VcpuResult::Ok);
+        assert_eq!(vcpu_machine_get_state(machine), -1);
+        assert_eq!(vcpu_machine_get_executed(machine), 0);
+
+        vcpu_machine_destroy(machine);
+    }
+}
+
+#[test]
+fn machine_arguments_and_memory() {
+    unsafe {
+        let machine = create_machine(".data\n.instructions\nHALT", 0, null());
+        let (first, second) = (get_c_str("prog"), get_c_str("x"));
+        let args = [first.as_ptr(), second.as_ptr()];
+        assert_eq!(
+            vcpu_machine_set_arguments(machine, args.as_ptr(), 2, null(), 0),
+            VcpuResult::Ok
+        );
+        let mut argc = 0;
+        vcpu_machine_get_register(machine, RegisterId::A0 as u32, &mut argc);
+        assert_eq!(argc, 2);
+
+        assert_eq!(
+            vcpu_machine_write_memory(machine, 0x100, [1u8, 2].as_ptr(), 2),
+            VcpuResult::Ok
+        );
+        let mut bytes = [0u8; 2];
+        assert_eq!(
+            vcpu_machine_read_memory(machine, 0x100, bytes.as_mut_ptr(), 2),
+            VcpuResult::Ok
+        );
+        assert_eq!(bytes, [1, 2]);
+        assert_eq!(
+            vcpu_machine_read_memory(machine, 0x8000_0000, bytes.as_mut_ptr(), 2),
+            VcpuResult::OutOfRange
+        );
+
+        vcpu_machine_destroy(machine);
+    }
+}
+
+#[test]
+fn machine_invalid_devices() {
+    unsafe {
+        let executable = vasm::assemble_program(".data\n.instructions\nHALT", 0)
+            .unwrap()
+            .executable;
+        let mut machine: *mut Machine = null_mut();
+        let devices = get_c_str("tape@0x1000");
+        assert_eq!(
+            vcpu_machine_create(&executable, 0, devices.as_ptr(), &mut machine),
+            VcpuResult::UnknownName
+        );
+        let devices = get_c_str("uart@0x100");
+        assert_eq!(
+            vcpu_machine_create(&executable, 0, devices.as_ptr(), &mut machine),
+            VcpuResult::OutOfRange
+        );
+        assert!(machine.is_null());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::result::contain;
 use std::ffi::c_void;
 
 pub unsafe fn into_ptr<T>(t: T) -> *mut T {
@@ -14,6 +15,8 @@ pub unsafe extern "C" fn vcpu_memcpy(
     src: *const c_void,
     length: usize,
 ) -> *mut c_void {
-    std::ptr::copy_nonoverlapping(src, dst, length);
-    dst
+    contain(|| {
+        std::ptr::copy_nonoverlapping(src, dst, length);
+        dst
+    })
 }
@@ -0,0 +1,79 @@
+use crate::result::contain;
+
+/// Version of the binary interface, i.e. the signatures of the functions and the layout of the `#[repr(C)]`
+/// enums and structs in `vcpu.h`. Within one ABI version, functions are only added and existing signatures and
+/// layouts never change. Hosts allocate the structs themselves, so adding a field to one is an ABI change as well.
+pub const ABI_VERSION: u32 = 1;
+
+/// The instructions of the floating point unit (`FLOP`).
+pub const CAPABILITY_FLOATING_POINT: u64 = 1 << 0;
+/// The control and status register instructions (`CSRR`, `CSRW`, `CSRS` and `CSRC`).
+pub const CAPABILITY_CONTROL_REGISTERS: u64 = 1 << 1;
+/// The vector instructions (`VEC`) and vector registers.
+pub const CAPABILITY_VECTOR: u64 = 1 << 2;
+/// Interrupts, `IRET` and `vcpu_processor_raise_interrupt`, with the shadow register bank swapped in on entry.
+pub const CAPABILITY_INTERRUPTS: u64 = 1 << 3;
+/// Memory created with `vcpu_memory_create_plain`.
+pub const CAPABILITY_PLAIN_MEMORY: u64 = 1 << 8;
+/// Memory created with `vcpu_memory_create_io`, whose writes call back into the host.
+pub const CAPABILITY_IO_MEMORY: u64 = 1 << 9;
+/// Memory created with `vcpu_memory_create_comp`, which consists of fragments of other memory.
+pub const CAPABILITY_COMPOSITE_MEMORY: u64 = 1 << 10;
+/// Assembling source, including structured diagnostics.
+pub const CAPABILITY_ASSEMBLER: u64 = 1 << 16;
+/// Loading and saving executables in the vex format.
+pub const CAPABILITY_VEX: u64 = 1 << 17;
+/// Breakpoints and `vcpu_debugger_run_until_event`.
+pub const CAPABILITY_DEBUGGER: u64 = 1 << 18;
+/// Saving and loading the state of a processor and its memory.
+pub const CAPABILITY_SAVE_STATE: u64 = 1 << 19;
+/// Handles which can be used from multiple threads and `vcpu_processor_request_stop`.
+pub const CAPABILITY_THREADS: u64 = 1 << 20;
+/// Disassembling instructions.
+pub const CAPABILITY_DISASSEMBLER: u64 = 1 << 21;
+/// Messages of failed calls with `vcpu_get_last_error_message`, including panics.
+pub const CAPABILITY_LAST_ERROR: u64 = 1 << 22;
+/// Running whole programs with the `vcpu_machine_*` functions.
+pub const CAPABILITY_MACHINE: u64 = 1 << 23;
+
+/// Writes the version of this library. Versions with the same major version are compatible, as long as the
+/// minor version is at least the one the host was built against.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_get_version(major: *mut u32, minor: *mut u32, patch: *mut u32) {
+    contain(|| {
+        *major = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap();
+        *minor = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap();
+        *patch = env!("CARGO_PKG_VERSION_PATCH").parse().unwrap();
+    })
+}
+
+/// Returns the [`ABI_VERSION`](constant.ABI_VERSION.html) of this library, which has to be equal to the one
+/// of the header the host was built with.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_get_abi_version() -> u32 {
+    contain(|| ABI_VERSION)
+}
+
+/// Returns the `CAPABILITY_*` bits of everything this library supports, so that hosts can check for features
+/// of newer versions at runtime. Bits 0 to 7 are instruction set extensions, bits 8 to 15 kinds of memory and
+/// bits 16 and above groups of functions.
+#[no_mangle]
+pub unsafe extern "C" fn vcpu_get_capabilities() -> u64 {
+    contain(|| {
+        CAPABILITY_FLOATING_POINT
+            | CAPABILITY_CONTROL_REGISTERS
+            | CAPABILITY_VECTOR
+            | CAPABILITY_INTERRUPTS
+            | CAPABILITY_PLAIN_MEMORY
+            | CAPABILITY_IO_MEMORY
+            | CAPABILITY_COMPOSITE_MEMORY
+            | CAPABILITY_ASSEMBLER
+            | CAPABILITY_VEX
+            | CAPABILITY_DEBUGGER
+            | CAPABILITY_SAVE_STATE
+            | CAPABILITY_THREADS
+            | CAPABILITY_DISASSEMBLER
+            | CAPABILITY_LAST_ERROR
+            | CAPABILITY_MACHINE
+    })
+}
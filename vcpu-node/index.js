@@ -0,0 +1,196 @@
+// Embeds the emulator in Node.js, Electron or the extension host of VS Code.
+//
+// The machine runs in the native addon vcpu.node, which is built from src/lib.rs with `npm run build`. N-API
+// is stable across versions, so the addon works in every Node and Electron version since Node 16.
+//
+//     import { Machine } from "vcpu-node";
+//
+//     const machine = new Machine();
+//     machine.on("console", (text) => process.stdout.write(text));
+//     machine.on("halt", (status) => console.log(`exited with ${status}`));
+//     machine.assemble(source);
+//     await machine.start();
+//
+// Events:
+//   console (text)              the program wrote to the UART
+//   halt (status)               the program executed HALT, status is the lowest byte of $V0
+//   fault (exitCode, address)   the program stopped because of an error, see ExitCode
+//   pause (executed)            start() stopped because of stop() or its instruction limit
+
+import { EventEmitter } from "node:events";
+import { createRequire } from "node:module";
+
+const { Vcpu } = createRequire(import.meta.url)("./vcpu.node");
+
+// Instructions executed between two turns of the event loop while running with start().
+const SLICE_INSTRUCTIONS = 100000;
+
+export const ExitCode = Object.freeze({
+    HALTED: 0,
+    DIVISION_BY_ZERO: 1,
+    BAD_MEMORY_ACCESS: 2,
+    BAD_ALIGNMENT: 3,
+    BAD_JUMP: 4,
+    INVALID_OPCODE: 5,
+    BAD_PROGRAM_COUNTER: 6,
+    TERMINATED: 7,
+    OVERFLOW: 8,
+});
+
+const EXIT_CODE_NAMES = Object.fromEntries(Object.entries(ExitCode).map(([name, code]) => [code, name]));
+
+export class Machine extends EventEmitter {
+    // Options: ramSize in bytes and framebuffer ({ address, width, height }).
+    constructor({ ramSize, framebuffer } = {}) {
+        super();
+        this.vcpu = new Vcpu(ramSize);
+        if (framebuffer) {
+            this.vcpu.setFramebuffer(framebuffer.address, framebuffer.width, framebuffer.height);
+        }
+        this.registerNames = this.vcpu.registerNames();
+        this.running = null;
+        this.stopRequested = false;
+        this.stopped = false;
+    }
+
+    // Assembles and loads a program, or throws an error with the diagnostics of the assembler.
+    assemble(source) {
+        this.vcpu.assemble(source);
+        this.stopped = false;
+    }
+
+    loadVex(bytes) {
+        this.vcpu.loadVex(bytes);
+        this.stopped = false;
+    }
+
+    // Restarts the program with a new machine.
+    reset() {
+        this.vcpu.reset();
+        this.stopped = false;
+    }
+
+    // The exit code, null while the program is running, or undefined if no program is loaded.
+    get state() {
+        return this.vcpu.loaded ? this.vcpu.state : undefined;
+    }
+
+    get executed() {
+        return this.vcpu.executed;
+    }
+
+    get programCounter() {
+        return this.vcpu.programCounter;
+    }
+
+    // Executes a single instruction and returns the state afterwards.
+    step() {
+        return this.report(this.vcpu.step());
+    }
+
+    // Executes at most maxInstructions instructions without giving control back to the event loop.
+    run(maxInstructions) {
+        return this.report(this.vcpu.run(maxInstructions));
+    }
+
+    // Runs in slices until the program stops, stop() is called or maxInstructions were executed, and resolves
+    // with the state. Events are emitted after every slice.
+    start({ maxInstructions = Infinity, sliceInstructions = SLICE_INSTRUCTIONS } = {}) {
+        if (this.running) {
+            return this.running;
+        }
+        this.stopRequested = false;
+        const limit = this.executed + maxInstructions;
+        this.running = new Promise((resolve, reject) => {
+            const slice = () => {
+                try {
+                    const remaining = limit - this.executed;
+                    const state = this.run(Math.min(sliceInstructions, remaining));
+                    if (state === null && !this.stopRequested && this.executed < limit) {
+                        setImmediate(slice);
+                        return;
+                    }
+                    if (state === null) {
+                        this.emit("pause", this.executed);
+                    }
+                    this.running = null;
+                    resolve(state);
+                } catch (err) {
+                    this.running = null;
+                    reject(err);
+                }
+            };
+            setImmediate(slice);
+        });
+        return this.running;
+    }
+
+    // Stops start() after the current slice.
+    stop() {
+        this.stopRequested = true;
+    }
+
+    // Returns a register by name ("SP" or "$SP") or index.
+    register(register) {
+        return this.vcpu.register(this.registerIndex(register));
+    }
+
+    setRegister(register, value) {
+        this.vcpu.setRegister(this.registerIndex(register), value);
+    }
+
+    // All registers by name, and PC.
+    get registers() {
+        const registers = { PC: this.programCounter };
+        this.registerNames.forEach((name, index) => {
+            registers[name] = this.vcpu.register(index);
+        });
+        return registers;
+    }
+
+    readMemory(address, length) {
+        return this.vcpu.readMemory(address, length);
+    }
+
+    writeMemory(address, bytes) {
+        this.vcpu.writeMemory(address, bytes);
+    }
+
+    // Copy of the RGBA pixels of the framebuffer.
+    framebuffer() {
+        return this.vcpu.framebuffer();
+    }
+
+    registerIndex(register) {
+        if (typeof register === "number") {
+            return register;
+        }
+        const index = this.registerNames.indexOf(register.replace(/^\$/, "").toUpperCase());
+        if (index < 0) {
+            throw new Error(`Unknown register ${register}`);
+        }
+        return index;
+    }
+
+    report(state) {
+        const text = this.vcpu.takeConsole();
+        if (text) {
+            this.emit("console", text);
+        }
+        if (state === null || this.stopped) {
+            return state;
+        }
+        this.stopped = true;
+        if (state === ExitCode.HALTED) {
+            this.emit("halt", this.register("V0") & 0xff);
+        } else {
+            this.emit("fault", state, this.programCounter);
+        }
+        return state;
+    }
+}
+
+// Name of an exit code, e.g. "DIVISION_BY_ZERO".
+export function exitCodeName(code) {
+    return EXIT_CODE_NAMES[code];
+}
@@ -0,0 +1,163 @@
+//! Node.js addon of the VCPU, built with [napi-rs](https://napi.rs) as `vcpu.node`:
+//!
+//! ```text
+//! npm install
+//! npm run build
+//! ```
+//!
+//! The addon runs a [`Playground`](../vcpu_wasm/struct.Playground.html) natively as instances of the `Vcpu`
+//! class, which `index.js` wraps into the `Machine` event emitter. States are the number of the exit code, or
+//! `null` while the program is running. Methods which need a loaded program, and all other failures, throw an
+//! `Error` with the message.
+
+use napi::bindgen_prelude::{Buffer, Uint8Array, Uint8ClampedArray};
+use napi::{Error, Result};
+use napi_derive::napi;
+use num::FromPrimitive;
+use vcpu::{enum_to_u32, ExitCode, RegisterId, REGISTER_COUNT};
+use vcpu_wasm::{Framebuffer, Playground, DEFAULT_RAM_SIZE};
+
+fn error(message: String) -> Error {
+    Error::from_reason(message)
+}
+
+fn state(exit_code: Option<ExitCode>) -> Option<u32> {
+    exit_code.map(enum_to_u32)
+}
+
+/// A playground which JavaScript owns.
+#[napi]
+pub struct Vcpu {
+    playground: Playground,
+}
+
+#[napi]
+impl Vcpu {
+    /// Creates a playground with `ram_size` bytes of RAM, or its default size.
+    #[napi(constructor)]
+    pub fn new(ram_size: Option<u32>) -> Vcpu {
+        Vcpu {
+            playground: Playground::new(ram_size.unwrap_or(DEFAULT_RAM_SIZE)),
+        }
+    }
+
+    /// Sets the framebuffer of the next program which is loaded or reset, or removes it if `width` or `height`
+    /// is 0.
+    #[napi]
+    pub fn set_framebuffer(&mut self, address: u32, width: u32, height: u32) {
+        let framebuffer = if width == 0 || height == 0 {
+            None
+        } else {
+            Some(Framebuffer {
+                address,
+                width,
+                height,
+            })
+        };
+        self.playground.set_framebuffer(framebuffer);
+    }
+
+    /// Assembles and loads the program, or throws an error with the diagnostics.
+    #[napi]
+    pub fn assemble(&mut self, source: String) -> Result<()> {
+        self.playground.assemble(&source).map_err(error)
+    }
+
+    #[napi]
+    pub fn load_vex(&mut self, bytes: Uint8Array) -> Result<()> {
+        self.playground.load_vex(&bytes).map_err(error)
+    }
+
+    /// Restarts the program with a new machine.
+    #[napi]
+    pub fn reset(&mut self) -> Result<()> {
+        self.playground.reset().map_err(error)
+    }
+
+    #[napi]
+    pub fn step(&mut self) -> Result<Option<u32>> {
+        self.playground.step().map(state).map_err(error)
+    }
+
+    /// Executes at most `max_instructions` instructions.
+    #[napi]
+    pub fn run(&mut self, max_instructions: u32) -> Result<Option<u32>> {
+        self.playground
+            .run(u64::from(max_instructions))
+            .map(state)
+            .map_err(error)
+    }
+
+    #[napi(getter)]
+    pub fn loaded(&self) -> bool {
+        self.playground.machine().is_some()
+    }
+
+    /// The state of the loaded program.
+    #[napi(getter)]
+    pub fn state(&self) -> Option<u32> {
+        self.playground
+            .machine()
+            .and_then(|machine| state(machine.processor().state()))
+    }
+
+    /// Number of instructions executed since the program was loaded or reset.
+    #[napi(getter)]
+    pub fn executed(&self) -> f64 {
+        self.playground
+            .machine()
+            .map_or(0.0, |machine| machine.executed() as f64)
+    }
+
+    #[napi(getter)]
+    pub fn program_counter(&self) -> u32 {
+        self.playground
+            .machine()
+            .map_or(0, |machine| machine.processor().program_counter())
+    }
+
+    /// Names of the registers, in the order of their indices.
+    #[napi]
+    pub fn register_names(&self) -> Vec<String> {
+        (0..REGISTER_COUNT as u32)
+            .filter_map(RegisterId::from_u32)
+            .map(|id| format!("{:?}", id))
+            .collect()
+    }
+
+    /// Returns the register with the given index, or `null` if there is none or no program is loaded.
+    #[napi]
+    pub fn register(&self, index: u32) -> Option<u32> {
+        self.playground.register(index)
+    }
+
+    #[napi]
+    pub fn set_register(&mut self, index: u32, value: u32) -> Result<()> {
+        self.playground.set_register(index, value).map_err(error)
+    }
+
+    #[napi]
+    pub fn read_memory(&self, address: u32, length: u32) -> Result<Buffer> {
+        self.playground
+            .read_memory(address, length)
+            .map(Buffer::from)
+            .map_err(error)
+    }
+
+    #[napi]
+    pub fn write_memory(&mut self, address: u32, bytes: Uint8Array) -> Result<()> {
+        self.playground.write_memory(address, &bytes).map_err(error)
+    }
+
+    /// Returns everything the program wrote to the UART since the last call.
+    #[napi]
+    pub fn take_console(&self) -> String {
+        String::from_utf8_lossy(&self.playground.take_console()).into_owned()
+    }
+
+    /// Copy of the RGBA pixels of the framebuffer, which is empty if the machine has none.
+    #[napi]
+    pub fn framebuffer(&self) -> Uint8ClampedArray {
+        Uint8ClampedArray::new(self.playground.pixels().to_vec())
+    }
+}
@@ -0,0 +1,59 @@
+// Needs the native addon, see `npm run build`.
+
+import assert from "node:assert/strict";
+import { existsSync } from "node:fs";
+import { test } from "node:test";
+
+const built = existsSync(new URL("../vcpu.node", import.meta.url));
+const skip = !built && "vcpu.node is not built";
+const { ExitCode, Machine, exitCodeName } = built ? await import("../index.js") : {};
+
+const HELLO = `.include <std/uart.vasm>
+.data
+.instructions
+        LI $A0, 72
+        JL uart_putc
+        LI $A0, 105
+        JL uart_putc
+        LI $V0, 7
+        HALT`;
+
+test("runs a program and emits its events", { skip }, async () => {
+    const machine = new Machine();
+    let console = "";
+    const halts = [];
+    machine.on("console", (text) => (console += text));
+    machine.on("halt", (status) => halts.push(status));
+
+    machine.assemble(HELLO);
+    assert.equal(machine.state, null);
+    assert.equal(await machine.start({ sliceInstructions: 2 }), ExitCode.HALTED);
+    assert.equal(console, "Hi");
+    assert.deepEqual(halts, [7]);
+    assert.equal(machine.register("$V0"), 7);
+    assert.equal(machine.step(), ExitCode.HALTED);
+    assert.deepEqual(halts, [7]);
+
+    machine.reset();
+    assert.equal(machine.executed, 0);
+    machine.setRegister("T0", 0xffffffff);
+    assert.equal(machine.registers.T0, 0xffffffff);
+    assert.throws(() => machine.register("XY"), /Unknown register/);
+});
+
+test("pauses and reports faults", { skip }, async () => {
+    const machine = new Machine({ ramSize: 256 });
+    const faults = [];
+    machine.on("fault", (code) => faults.push(exitCodeName(code)));
+    assert.throws(() => machine.assemble(".data\n.instructions\nSTUFF"), /3:1/);
+
+    machine.assemble(".data\n.instructions\nloop: JMP loop");
+    assert.equal(await machine.start({ maxInstructions: 10 }), null);
+    assert.equal(machine.executed, 10);
+
+    machine.assemble(".data\n.instructions\nLI $T0, 0\nDIV $T1, $T1, $T0\nHALT");
+    assert.equal(machine.run(10), ExitCode.DIVISION_BY_ZERO);
+    assert.deepEqual(faults, ["DIVISION_BY_ZERO"]);
+    machine.writeMemory(16, Uint8Array.of(1, 2));
+    assert.deepEqual([...machine.readMemory(16, 2)], [1, 2]);
+});
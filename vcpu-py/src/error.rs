@@ -0,0 +1,55 @@
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+create_exception!(
+    vcpu,
+    VcpuError,
+    PyException,
+    "A failed call, whose `result` tells why it failed."
+);
+create_exception!(
+    vcpu,
+    AssemblerError,
+    VcpuError,
+    "Assembling failed, `diagnostics` contains the error and the warnings before it."
+);
+
+/// The reason of a `VcpuError`, with the same values as the results of vcpu-interop.
+#[pyclass(eq, eq_int, frozen, module = "vcpu")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Result {
+    #[pyo3(name = "INVALID_TYPE")]
+    InvalidType = 1,
+    #[pyo3(name = "ASSEMBLER_ERROR")]
+    AssemblerError = 3,
+    #[pyo3(name = "MEMORY_IN_USE")]
+    MemoryInUse = 4,
+    #[pyo3(name = "FRAGMENT_INTERSECTION")]
+    FragmentIntersection = 5,
+    #[pyo3(name = "KEY_ALREADY_EXISTS")]
+    KeyAlreadyExists = 6,
+    #[pyo3(name = "OUT_OF_RANGE")]
+    OutOfRange = 7,
+    #[pyo3(name = "EXECUTABLE_LOAD_FAILED")]
+    ExecutableLoadFailed = 8,
+    #[pyo3(name = "EXECUTABLE_SAVE_FAILED")]
+    ExecutableSaveFailed = 9,
+    #[pyo3(name = "UNKNOWN_NAME")]
+    UnknownName = 10,
+}
+
+/// Creates a `VcpuError` with `result` and `message`.
+pub fn fail<S: Into<String>>(result: Result, message: S) -> PyErr {
+    let err = VcpuError::new_err(message.into());
+    with_attribute(err, "result", result)
+}
+
+/// Sets the attribute `name` of the exception of `err`, which `create_exception!` classes cannot declare.
+pub fn with_attribute<T: for<'py> IntoPyObject<'py>>(err: PyErr, name: &str, value: T) -> PyErr {
+    Python::attach(|py| {
+        // setting an attribute of an exception instance only fails if Python is out of memory
+        let _ = err.value(py).setattr(name, value);
+    });
+    err
+}
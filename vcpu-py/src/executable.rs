@@ -0,0 +1,157 @@
+use crate::error::{fail, with_attribute, AssemblerError, Result};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use vasm::Options;
+use vex::{ReadVexExt, WriteVexExt};
+
+/// An error or warning of the assembler. Lines and columns start at 1, the end is exclusive.
+#[pyclass(frozen, get_all, module = "vcpu")]
+#[derive(Clone)]
+pub struct Diagnostic {
+    is_error: bool,
+    line: u32,
+    column: u32,
+    end_line: u32,
+    end_column: u32,
+    message: String,
+}
+
+impl Diagnostic {
+    fn new(diagnostic: &vasm::Diagnostic) -> Diagnostic {
+        Diagnostic {
+            is_error: diagnostic.severity == vasm::DiagnosticSeverity::Error,
+            line: diagnostic.start.line as u32,
+            column: diagnostic.start.column as u32,
+            end_line: diagnostic.end.line as u32,
+            end_column: diagnostic.end.column as u32,
+            message: diagnostic.message.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl Diagnostic {
+    fn __repr__(&self) -> String {
+        format!(
+            "Diagnostic(is_error={}, line={}, column={}, end_line={}, end_column={}, message={:?})",
+            if self.is_error { "True" } else { "False" },
+            self.line,
+            self.column,
+            self.end_line,
+            self.end_column,
+            self.message
+        )
+    }
+}
+
+#[pyclass(frozen, module = "vcpu")]
+pub struct Executable {
+    pub inner: vex::Executable,
+    /// The start line and line count of every instruction, if the executable was assembled.
+    #[pyo3(get)]
+    source_map: Vec<(u32, u32)>,
+    /// The warnings of the assembler, if the executable was assembled.
+    #[pyo3(get)]
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Executable {
+    fn new(inner: vex::Executable) -> Executable {
+        Executable {
+            inner,
+            source_map: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+#[pymethods]
+impl Executable {
+    /// Assembles `source`, or raises an `AssemblerError` with its diagnostics.
+    #[staticmethod]
+    #[pyo3(signature = (source, data_offset=0))]
+    fn assemble(source: &str, data_offset: u32) -> PyResult<Executable> {
+        let options = Options {
+            data_offset,
+            ..Options::default()
+        };
+        match vasm::assemble(source, &options) {
+            Ok(assembly) => Ok(Executable {
+                source_map: assembly
+                    .source_map
+                    .iter()
+                    .map(|item| (item.start_line, item.line_count))
+                    .collect(),
+                diagnostics: assembly.diagnostics().iter().map(Diagnostic::new).collect(),
+                inner: assembly.executable,
+            }),
+            Err(errors) => {
+                let message = errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let diagnostics: Vec<Diagnostic> = errors.iter().map(Diagnostic::new).collect();
+                let err = with_attribute(
+                    AssemblerError::new_err(message),
+                    "result",
+                    Result::AssemblerError,
+                );
+                Err(with_attribute(err, "diagnostics", diagnostics))
+            }
+        }
+    }
+
+    #[staticmethod]
+    fn load_vex(mut data: &[u8]) -> PyResult<Executable> {
+        data.read_vex().map(Executable::new).map_err(|err| {
+            fail(
+                Result::ExecutableLoadFailed,
+                format!("Loading the vexfile failed: {}", err),
+            )
+        })
+    }
+
+    fn save_vex<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let mut data = Vec::with_capacity(self.inner.required_size());
+        data.write_vex(&self.inner).map_err(|err| {
+            fail(
+                Result::ExecutableSaveFailed,
+                format!("Saving the vexfile failed: {}", err),
+            )
+        })?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    #[getter]
+    fn entry_point(&self) -> u32 {
+        self.inner.entry_point()
+    }
+
+    #[getter]
+    fn memory_size(&self) -> u32 {
+        self.inner.memory_size()
+    }
+
+    #[getter]
+    fn instructions<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.inner.instructions())
+    }
+
+    /// The address and data of every section, which is zero filled up to the size of the section.
+    #[getter]
+    fn sections<'py>(&self, py: Python<'py>) -> Vec<(u32, Bound<'py, PyBytes>)> {
+        self.inner
+            .sections()
+            .iter()
+            .map(|section| (section.address(), PyBytes::new(py, &section_data(section))))
+            .collect()
+    }
+}
+
+/// Returns the contents of `section`, zero filled up to its size.
+pub fn section_data(section: &vex::Section) -> Vec<u8> {
+    let mut data = section.bytes().to_vec();
+    data.resize(section.size() as usize, 0);
+    data
+}
@@ -0,0 +1,77 @@
+//! Python bindings for the VCPU, built as a native extension module with [maturin](https://www.maturin.rs):
+//!
+//! ```text
+//! pip install maturin
+//! maturin develop --release
+//! ```
+//!
+//! ```python
+//! import vcpu
+//!
+//! executable = vcpu.Executable.assemble(source)
+//! machine = vcpu.Machine(executable)
+//! machine.run(max_instructions=1000)
+//! print(machine.state, machine.processor["V0"])
+//! ```
+//!
+//! Failed calls raise a `VcpuError` with the `result` which tells why, assembling raises an `AssemblerError` with
+//! the `diagnostics` of the assembler.
+
+mod error;
+mod executable;
+mod machine;
+mod memory;
+mod processor;
+
+use crate::processor::RegisterKey;
+use pyo3::prelude::*;
+
+/// The version of the module as a tuple of major, minor and patch version.
+#[pyfunction]
+fn version() -> (u32, u32, u32) {
+    (
+        env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+        env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+        env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+    )
+}
+
+#[pyfunction]
+fn register_names() -> Vec<String> {
+    processor::register_names()
+}
+
+#[pyfunction]
+fn register_index(name: &str) -> PyResult<u32> {
+    let id = RegisterKey::Name(name.to_owned()).id()?;
+    Ok(vcpu::register_index(id) as u32)
+}
+
+/// The VASM source of the instruction `word`, or None if it has no mnemonic.
+#[pyfunction]
+fn disassemble(word: u32) -> Option<String> {
+    vasm::disassemble(word)
+}
+
+#[pymodule]
+#[pyo3(name = "vcpu")]
+fn vcpu_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = module.py();
+    module.add("VcpuError", py.get_type::<error::VcpuError>())?;
+    module.add("AssemblerError", py.get_type::<error::AssemblerError>())?;
+    module.add_class::<error::Result>()?;
+    module.add_class::<processor::ExitCode>()?;
+    module.add_class::<processor::Processor>()?;
+    module.add_class::<processor::Debugger>()?;
+    module.add_class::<memory::Memory>()?;
+    module.add_class::<executable::Diagnostic>()?;
+    module.add_class::<executable::Executable>()?;
+    module.add_class::<machine::DebugEventKind>()?;
+    module.add_class::<machine::DebugEvent>()?;
+    module.add_class::<machine::Machine>()?;
+    module.add_function(wrap_pyfunction!(version, module)?)?;
+    module.add_function(wrap_pyfunction!(register_names, module)?)?;
+    module.add_function(wrap_pyfunction!(register_index, module)?)?;
+    module.add_function(wrap_pyfunction!(disassemble, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use crate::executable::{section_data, Executable};
+use crate::memory::{Memory, Variant};
+use crate::processor::{Debugger, ExitCode, Processor};
+use pyo3::prelude::*;
+use vcpu::{Endian, WORD_BYTES};
+
+use byteorder::ByteOrder;
+
+/// Number of instructions a run executes between checks for signals, so that e.g. Ctrl+C stops a long run with a
+/// `KeyboardInterrupt`, after which the run can be continued.
+const RUN_BATCH: u64 = 4096;
+
+#[pyclass(eq, eq_int, frozen, module = "vcpu")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DebugEventKind {
+    /// The program counter reached a breakpoint, whose instruction was not executed yet.
+    #[pyo3(name = "BREAKPOINT")]
+    Breakpoint = 0,
+    /// The program executed `HALT`.
+    #[pyo3(name = "HALTED")]
+    Halted = 1,
+    /// The processor stopped with an exit code other than `HALTED`.
+    #[pyo3(name = "FAULT")]
+    Fault = 2,
+    /// The maximum number of instructions was executed without another event.
+    #[pyo3(name = "PAUSED")]
+    Paused = 3,
+}
+
+/// What made `Machine.run_until_event` return.
+#[pyclass(frozen, get_all, module = "vcpu")]
+pub struct DebugEvent {
+    kind: DebugEventKind,
+    /// Program counter after the event, i.e. the address of the breakpoint or of the next instruction.
+    address: u32,
+    /// Exit code of the processor, None if it is still running.
+    exit_code: Option<ExitCode>,
+    /// Number of instructions executed by this call.
+    executed: u64,
+}
+
+#[pymethods]
+impl DebugEvent {
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "DebugEvent(kind={}, address={}, exit_code={}, executed={})",
+            self.kind.into_pyobject(py)?.as_any().repr()?,
+            self.address,
+            self.exit_code.into_pyobject(py)?.repr()?,
+            self.executed
+        ))
+    }
+}
+
+/// A processor running an executable, whose sections are loaded into the memory.
+///
+/// Without `memory`, a plain memory of the size the executable requests is created.
+#[pyclass(unsendable, module = "vcpu")]
+pub struct Machine {
+    #[pyo3(get)]
+    executable: Py<Executable>,
+    #[pyo3(get)]
+    memory: Py<Memory>,
+    #[pyo3(get)]
+    processor: Py<Processor>,
+    #[pyo3(get)]
+    debugger: Py<Debugger>,
+    /// Number of instructions executed since the last reset, including the one which stopped the processor.
+    #[pyo3(get)]
+    executed: u64,
+    instructions: Vec<u8>,
+}
+
+impl Machine {
+    /// Executes at most `count` instructions, until the processor stops or `stop` returns true before an
+    /// instruction. Returns the number of executed instructions.
+    fn run_while<F: FnMut(&vcpu::Processor, u64) -> bool>(
+        &mut self,
+        py: Python,
+        count: Option<u64>,
+        mut stop: F,
+    ) -> PyResult<u64> {
+        let mut processor = self.processor.borrow_mut(py);
+        let memory = self.memory.borrow(py);
+        let mut variant = memory.shared.borrow_mut()?;
+        let mut executed = 0;
+        let result = loop {
+            // the handler of an interrupt is entered before `stop` looks at the program counter
+            processor.inner.take_interrupt();
+            if processor.inner.is_stopped()
+                || count == Some(executed)
+                || stop(&processor.inner, executed)
+            {
+                break Ok(executed);
+            }
+            processor
+                .inner
+                .tick(&self.instructions, variant.storage_mut());
+            executed += 1;
+            if executed % RUN_BATCH == 0 {
+                if let Err(err) = py.check_signals() {
+                    break Err(err);
+                }
+            }
+        };
+        self.executed += executed;
+        result
+    }
+
+    fn load_sections(&self, variant: &mut Variant) -> PyResult<()> {
+        for section in self.executable.get().inner.sections() {
+            variant.write_bytes(section.address(), &section_data(section))?;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl Machine {
+    #[new]
+    #[pyo3(signature = (executable, memory=None))]
+    fn new(
+        py: Python,
+        executable: Py<Executable>,
+        memory: Option<Py<Memory>>,
+    ) -> PyResult<Machine> {
+        let memory = match memory {
+            Some(memory) => memory,
+            None => {
+                let size = executable.get().inner.memory_size();
+                Py::new(py, Memory::new(Variant::Plain(vec![0; size as usize])))?
+            }
+        };
+        let mut machine = Machine {
+            instructions: executable.get().inner.instructions().to_vec(),
+            executable,
+            memory,
+            processor: Py::new(py, Processor::new())?,
+            debugger: Py::new(py, Debugger::default())?,
+            executed: 0,
+        };
+        machine.reset(py)?;
+        Ok(machine)
+    }
+
+    /// Restarts the program at its entry point, with the initial contents of its sections.
+    fn reset(&mut self, py: Python) -> PyResult<()> {
+        {
+            let memory = self.memory.borrow(py);
+            let mut variant = memory.shared.borrow_mut()?;
+            self.load_sections(&mut variant)?;
+        }
+        let mut processor = self.processor.borrow_mut(py);
+        processor.inner.reset();
+        processor
+            .inner
+            .set_program_counter(self.executable.get().inner.entry_point());
+        self.executed = 0;
+        Ok(())
+    }
+
+    #[getter]
+    fn state(&self, py: Python) -> Option<ExitCode> {
+        self.processor.borrow(py).state()
+    }
+
+    /// Executes a single instruction and returns the state afterwards.
+    fn step(&mut self, py: Python) -> PyResult<Option<ExitCode>> {
+        self.run_while(py, Some(1), |_, _| false)?;
+        Ok(self.state(py))
+    }
+
+    /// Executes at most `max_instructions` instructions and returns the state afterwards.
+    #[pyo3(signature = (max_instructions=None))]
+    fn run(&mut self, py: Python, max_instructions: Option<u64>) -> PyResult<Option<ExitCode>> {
+        self.run_while(py, max_instructions, |_, _| false)?;
+        Ok(self.state(py))
+    }
+
+    /// Runs until a breakpoint of `debugger` is reached, the processor stops or `max_instructions` were executed.
+    /// The instruction at the program counter is always executed, even if it has a breakpoint, so that the
+    /// program can be continued after a breakpoint.
+    #[pyo3(signature = (max_instructions=None))]
+    fn run_until_event(
+        &mut self,
+        py: Python,
+        max_instructions: Option<u64>,
+    ) -> PyResult<DebugEvent> {
+        let debugger = self.debugger.clone_ref(py);
+        let debugger = debugger.borrow(py);
+        let at_breakpoint = |processor: &vcpu::Processor| {
+            debugger.breakpoints.contains(&processor.program_counter())
+        };
+        let executed = self.run_while(py, max_instructions, |processor, executed| {
+            executed > 0 && at_breakpoint(processor)
+        })?;
+        let processor = self.processor.borrow(py);
+        let kind = match processor.inner.state() {
+            Some(vcpu::ExitCode::Halted) => DebugEventKind::Halted,
+            Some(_) => DebugEventKind::Fault,
+            None if executed > 0 && at_breakpoint(&processor.inner) => DebugEventKind::Breakpoint,
+            None => DebugEventKind::Paused,
+        };
+        Ok(DebugEvent {
+            kind,
+            address: processor.inner.program_counter(),
+            exit_code: processor.state(),
+            executed,
+        })
+    }
+
+    /// Executes instructions one by one and calls `hook(machine, address)` before each of them, which can stop the
+    /// trace by returning False. Much slower than `run`, but sees every instruction.
+    #[pyo3(signature = (hook, max_instructions=None))]
+    fn trace(
+        slf: &Bound<'_, Self>,
+        hook: &Bound<'_, PyAny>,
+        max_instructions: Option<u64>,
+    ) -> PyResult<Option<ExitCode>> {
+        let py = slf.py();
+        let mut remaining = max_instructions;
+        while remaining != Some(0) {
+            let processor = slf.borrow().processor.clone_ref(py);
+            if processor.borrow(py).inner.is_stopped() {
+                break;
+            }
+            let address = processor.borrow(py).inner.program_counter();
+            if let Ok(false) = hook.call1((slf, address))?.extract::<bool>() {
+                break;
+            }
+            slf.borrow_mut().step(py)?;
+            remaining = remaining.map(|count| count - 1);
+        }
+        Ok(slf.borrow().state(py))
+    }
+
+    /// Disassembly of the instruction at the program counter, None if it is outside of the program.
+    fn current_instruction(&self, py: Python) -> Option<String> {
+        let address = self.processor.borrow(py).inner.program_counter() as usize;
+        let word = self
+            .instructions
+            .get(address..address.checked_add(WORD_BYTES as usize)?)?;
+        vasm::disassemble(Endian::read_u32(word))
+    }
+}
@@ -0,0 +1,317 @@
+use crate::error::{fail, Result};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+use vcpu::{CompositeMemory, IOHandler, IOMemory, MountError, Storage, StorageMut};
+
+/// Calls the Python callbacks of IO memory. Exceptions cannot propagate through the processor, so they are
+/// reported with `sys.unraisablehook`, and a write whose `can_write` raised is not executed.
+pub struct Callbacks {
+    on_write: Option<Py<PyAny>>,
+    can_write: Option<Py<PyAny>>,
+}
+
+impl Callbacks {
+    fn call(callback: &Py<PyAny>, memory: &[u8], address: u32, size: u32) -> Option<bool> {
+        Python::attach(|py| {
+            callback
+                .call1(py, (PyBytes::new(py, memory), address, size))
+                .and_then(|result| result.is_truthy(py))
+                .map_err(|err| err.write_unraisable(py, Some(callback.bind(py))))
+                .ok()
+        })
+    }
+}
+
+impl IOHandler for Callbacks {
+    fn can_write(&self, memory: &[u8], address: u32, size: u32) -> bool {
+        match &self.can_write {
+            Some(can_write) => Callbacks::call(can_write, memory, address, size).unwrap_or(false),
+            None => true,
+        }
+    }
+
+    fn on_write(&self, memory: &[u8], address: u32, size: u32) {
+        if let Some(on_write) = &self.on_write {
+            Callbacks::call(on_write, memory, address, size);
+        }
+    }
+}
+
+pub enum Variant {
+    Plain(Vec<u8>),
+    IO(IOMemory<Callbacks>),
+    Composite(CompositeMemory),
+}
+
+impl Variant {
+    fn kind(&self) -> &'static str {
+        match self {
+            Variant::Plain(_) => "plain",
+            Variant::IO(_) => "IO",
+            Variant::Composite(_) => "composite",
+        }
+    }
+
+    fn storage(&self) -> &dyn Storage {
+        match self {
+            Variant::Plain(inner) => inner,
+            Variant::IO(inner) => inner,
+            Variant::Composite(inner) => inner,
+        }
+    }
+
+    pub fn storage_mut(&mut self) -> &mut dyn StorageMut {
+        match self {
+            Variant::Plain(inner) => inner,
+            Variant::IO(inner) => inner,
+            Variant::Composite(inner) => inner,
+        }
+    }
+
+    /// Writes `data` at `address` like the host, i.e. without calling the callbacks of IO memory. The range has
+    /// to lie within a single fragment of composite memory.
+    pub fn write_bytes(&mut self, address: u32, data: &[u8]) -> PyResult<()> {
+        let length = data.len() as u32;
+        if !self.storage().check_range(address, length) {
+            return Err(out_of_range(address, length));
+        }
+        let slice = match self {
+            Variant::Plain(inner) => inner.as_mut_slice(),
+            Variant::IO(inner) => inner.data_mut(),
+            Variant::Composite(inner) => {
+                for (address, byte) in (address..).zip(data) {
+                    inner.write_byte(address, *byte).map_err(|_| in_use())?;
+                }
+                return Ok(());
+            }
+        };
+        slice[address as usize..][..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_bytes(&self, address: u32, length: u32) -> PyResult<Vec<u8>> {
+        if !self.storage().check_range(address, length) {
+            return Err(out_of_range(address, length));
+        }
+        let slice = match self {
+            Variant::Plain(inner) => inner.as_slice(),
+            Variant::IO(inner) => inner.data(),
+            Variant::Composite(inner) => {
+                return (address..address + length)
+                    .map(|address| inner.read_byte(address).map_err(|_| in_use()))
+                    .collect();
+            }
+        };
+        Ok(slice[address as usize..][..length as usize].to_vec())
+    }
+}
+
+fn invalid_type(variant: &Variant, expected: &str) -> PyErr {
+    fail(
+        Result::InvalidType,
+        format!(
+            "Expected {} memory instead of {} memory",
+            expected,
+            variant.kind()
+        ),
+    )
+}
+
+fn out_of_range(address: u32, length: u32) -> PyErr {
+    fail(
+        Result::OutOfRange,
+        format!("Cannot access {} bytes at 0x{:08X}", length, address),
+    )
+}
+
+fn in_use() -> PyErr {
+    fail(Result::MemoryInUse, "The memory is already in use")
+}
+
+/// A memory which is shared by its Python object and the composite memories it is mounted in. Using it while
+/// it is in use, e.g. from an IO callback of a machine running on it, fails with `MEMORY_IN_USE`.
+#[derive(Clone)]
+pub struct Shared(Rc<RefCell<Variant>>);
+
+impl Shared {
+    pub fn borrow(&self) -> PyResult<Ref<'_, Variant>> {
+        self.0.try_borrow().map_err(|_| in_use())
+    }
+
+    pub fn borrow_mut(&self) -> PyResult<RefMut<'_, Variant>> {
+        self.0.try_borrow_mut().map_err(|_| in_use())
+    }
+}
+
+impl Storage for Shared {
+    fn length(&self) -> u32 {
+        self.0
+            .try_borrow()
+            .map_or(0, |variant| variant.storage().length())
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        self.0
+            .try_borrow()
+            .is_ok_and(|variant| variant.storage().check_range(address, length))
+    }
+
+    fn read(&self, address: u32, size: u32) -> std::result::Result<u32, ()> {
+        self.0
+            .try_borrow()
+            .map_err(|_| ())
+            .and_then(|variant| variant.storage().read(address, size))
+    }
+}
+
+impl StorageMut for Shared {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> std::result::Result<(), ()> {
+        self.0
+            .try_borrow_mut()
+            .map_err(|_| ())
+            .and_then(|mut variant| variant.storage_mut().write(address, size, value))
+    }
+}
+
+/// Main memory of a processor, which is plain, calls back into Python on writes, or is composed of fragments
+/// of other memory.
+#[pyclass(unsendable, module = "vcpu")]
+pub struct Memory {
+    pub shared: Shared,
+}
+
+impl Memory {
+    pub fn new(variant: Variant) -> Memory {
+        Memory {
+            shared: Shared(Rc::new(RefCell::new(variant))),
+        }
+    }
+
+    fn get(&self, address: u32, size: u32) -> PyResult<u32> {
+        self.shared
+            .borrow()?
+            .storage()
+            .read(address, size)
+            .map_err(|_| out_of_range(address, size))
+    }
+
+    fn set(&self, address: u32, size: u32, value: u32) -> PyResult<()> {
+        self.shared
+            .borrow_mut()?
+            .storage_mut()
+            .write(address, size, value)
+            .map_err(|_| out_of_range(address, size))
+    }
+}
+
+#[pymethods]
+impl Memory {
+    #[staticmethod]
+    fn plain(size: u32) -> Memory {
+        Memory::new(Variant::Plain(vec![0; size as usize]))
+    }
+
+    /// Memory which calls `on_write(data, address, size)` after every write of the program. Writes are only
+    /// executed if `can_write(data, address, size)` returns True. `data` is the whole memory.
+    #[staticmethod]
+    #[pyo3(signature = (size, on_write=None, can_write=None))]
+    fn io(size: u32, on_write: Option<Py<PyAny>>, can_write: Option<Py<PyAny>>) -> Memory {
+        Memory::new(Variant::IO(IOMemory::new(
+            size,
+            Callbacks {
+                on_write,
+                can_write,
+            },
+        )))
+    }
+
+    #[staticmethod]
+    fn composite() -> Memory {
+        Memory::new(Variant::Composite(CompositeMemory::new()))
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        Ok(self.shared.borrow()?.storage().length() as usize)
+    }
+
+    fn read<'py>(
+        &self,
+        py: Python<'py>,
+        address: u32,
+        length: u32,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.shared.borrow()?.read_bytes(address, length)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    fn write(&self, address: u32, data: &[u8]) -> PyResult<()> {
+        self.shared.borrow_mut()?.write_bytes(address, data)
+    }
+
+    fn word(&self, address: u32) -> PyResult<u32> {
+        self.get(address, vcpu::WORD_BYTES)
+    }
+
+    fn half(&self, address: u32) -> PyResult<u16> {
+        Ok(self.get(address, vcpu::HALF_BYTES)? as u16)
+    }
+
+    fn byte(&self, address: u32) -> PyResult<u8> {
+        Ok(self.get(address, vcpu::BYTE_BYTES)? as u8)
+    }
+
+    fn set_word(&self, address: u32, value: u32) -> PyResult<()> {
+        self.set(address, vcpu::WORD_BYTES, value)
+    }
+
+    fn set_half(&self, address: u32, value: u16) -> PyResult<()> {
+        self.set(address, vcpu::HALF_BYTES, value.into())
+    }
+
+    fn set_byte(&self, address: u32, value: u8) -> PyResult<()> {
+        self.set(address, vcpu::BYTE_BYTES, value.into())
+    }
+
+    fn resize(&self, size: u32) -> PyResult<()> {
+        match &mut *self.shared.borrow_mut()? {
+            Variant::Plain(inner) => inner.resize(size as usize, 0),
+            Variant::IO(inner) => inner.resize(size),
+            other => return Err(invalid_type(other, "plain or IO")),
+        }
+        Ok(())
+    }
+
+    /// Mounts `fragment` at `address` of this composite memory, which shares it with the Python object.
+    fn mount(&self, address: u32, key: &str, fragment: &Memory) -> PyResult<()> {
+        match &mut *self.shared.borrow_mut()? {
+            Variant::Composite(inner) => inner
+                .mount(address, key, fragment.shared.clone())
+                .map_err(|err| match err {
+                    MountError::FragmentIntersection => fail(
+                        Result::FragmentIntersection,
+                        format!(
+                            "Fragment \"{}\" at 0x{:08X} intersects another fragment",
+                            key, address
+                        ),
+                    ),
+                    MountError::KeyAlreadyExists => fail(
+                        Result::KeyAlreadyExists,
+                        format!("A fragment is already mounted as \"{}\"", key),
+                    ),
+                }),
+            other => Err(invalid_type(other, "composite")),
+        }
+    }
+
+    fn unmount(&self, key: &str) -> PyResult<()> {
+        match &mut *self.shared.borrow_mut()? {
+            Variant::Composite(inner) => {
+                inner.unmount(key);
+                Ok(())
+            }
+            other => Err(invalid_type(other, "composite")),
+        }
+    }
+}
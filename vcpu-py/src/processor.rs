@@ -0,0 +1,178 @@
+use crate::error::{fail, Result};
+use num_traits::FromPrimitive;
+use pyo3::prelude::*;
+use std::collections::BTreeSet;
+use vcpu::{RegisterId, REGISTER_COUNT};
+
+#[pyclass(eq, eq_int, frozen, module = "vcpu")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExitCode {
+    #[pyo3(name = "HALTED")]
+    Halted = 0,
+    #[pyo3(name = "DIVISION_BY_ZERO")]
+    DivisionByZero = 1,
+    #[pyo3(name = "BAD_MEMORY_ACCESS")]
+    BadMemoryAccess = 2,
+    #[pyo3(name = "BAD_ALIGNMENT")]
+    BadAlignment = 3,
+    #[pyo3(name = "BAD_JUMP")]
+    BadJump = 4,
+    #[pyo3(name = "INVALID_OPCODE")]
+    InvalidOpcode = 5,
+    #[pyo3(name = "BAD_PROGRAM_COUNTER")]
+    BadProgramCounter = 6,
+    #[pyo3(name = "TERMINATED")]
+    Terminated = 7,
+    #[pyo3(name = "OVERFLOW")]
+    Overflow = 8,
+}
+
+impl From<vcpu::ExitCode> for ExitCode {
+    fn from(code: vcpu::ExitCode) -> ExitCode {
+        match code {
+            vcpu::ExitCode::Halted => ExitCode::Halted,
+            vcpu::ExitCode::DivisionByZero => ExitCode::DivisionByZero,
+            vcpu::ExitCode::BadMemoryAccess => ExitCode::BadMemoryAccess,
+            vcpu::ExitCode::BadAlignment => ExitCode::BadAlignment,
+            vcpu::ExitCode::BadJump => ExitCode::BadJump,
+            vcpu::ExitCode::InvalidOpcode => ExitCode::InvalidOpcode,
+            vcpu::ExitCode::BadProgramCounter => ExitCode::BadProgramCounter,
+            vcpu::ExitCode::Terminated => ExitCode::Terminated,
+            vcpu::ExitCode::Overflow => ExitCode::Overflow,
+        }
+    }
+}
+
+/// A register, given by its index or by its name, which is case insensitive and may start with `$`.
+#[derive(FromPyObject)]
+pub enum RegisterKey {
+    Index(u32),
+    Name(String),
+}
+
+impl RegisterKey {
+    pub fn id(&self) -> PyResult<RegisterId> {
+        match self {
+            RegisterKey::Index(index) => RegisterId::from_u32(*index).ok_or_else(|| {
+                fail(
+                    Result::OutOfRange,
+                    format!("Register index {} is out of range", index),
+                )
+            }),
+            RegisterKey::Name(name) => {
+                let register = name.strip_prefix('$').unwrap_or(name);
+                register
+                    .to_uppercase()
+                    .parse()
+                    .map_err(|_| fail(Result::UnknownName, format!("Unknown register {}", name)))
+            }
+        }
+    }
+}
+
+/// Names of the registers, in the order of their indices.
+pub fn register_names() -> Vec<String> {
+    (0..REGISTER_COUNT as u32)
+        .filter_map(RegisterId::from_u32)
+        .map(|id| format!("{:?}", id))
+        .collect()
+}
+
+/// Registers and program counter of a processor. Registers can be indexed by number or name, and are returned as
+/// unsigned integers.
+#[pyclass(unsendable, module = "vcpu")]
+pub struct Processor {
+    pub inner: vcpu::Processor,
+}
+
+#[pymethods]
+impl Processor {
+    #[new]
+    pub fn new() -> Processor {
+        Processor {
+            inner: vcpu::Processor::new(),
+        }
+    }
+
+    fn __getitem__(&self, register: RegisterKey) -> PyResult<u32> {
+        Ok(self.inner.register(register.id()?).u())
+    }
+
+    /// Sets a register to `value`, which may also be negative.
+    fn __setitem__(&mut self, register: RegisterKey, value: i64) -> PyResult<()> {
+        if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+            return Err(fail(
+                Result::OutOfRange,
+                format!("{} does not fit into a register", value),
+            ));
+        }
+        self.inner.register_mut(register.id()?).set_u(value as u32);
+        Ok(())
+    }
+
+    #[getter]
+    fn registers(&self) -> Vec<u32> {
+        self.inner
+            .registers()
+            .iter()
+            .map(|register| register.u())
+            .collect()
+    }
+
+    #[getter]
+    fn program_counter(&self) -> u32 {
+        self.inner.program_counter()
+    }
+
+    #[setter]
+    fn set_program_counter(&mut self, value: u32) {
+        self.inner.set_program_counter(value)
+    }
+
+    /// The exit code, or None if the processor is still running.
+    #[getter]
+    pub fn state(&self) -> Option<ExitCode> {
+        self.inner.state().map(ExitCode::from)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+}
+
+/// The breakpoints of a machine, which stop `Machine.run_until_event`.
+#[pyclass(module = "vcpu")]
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u32>,
+}
+
+#[pymethods]
+impl Debugger {
+    #[new]
+    fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Returns whether the breakpoint was added, i.e. there was none at `address` yet.
+    fn add_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns whether there was a breakpoint at `address`.
+    fn remove_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    fn has_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear()
+    }
+
+    fn __len__(&self) -> usize {
+        self.breakpoints.len()
+    }
+}
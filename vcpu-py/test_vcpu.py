@@ -0,0 +1,119 @@
+"""Run with `python -m unittest` in this directory, after installing the module with `maturin develop`."""
+
+import unittest
+import unittest.mock
+
+import vcpu
+
+SOURCE = """.data
+value: .word 40
+result: .word 0
+.instructions
+        LDA $T0, value
+        LW $T1, 0($T0)
+        ADDI $V0, $T1, 2
+        SW $V0, 4($T0)
+        HALT"""
+
+
+class MachineTest(unittest.TestCase):
+    def test_run(self):
+        machine = vcpu.Machine(vcpu.Executable.assemble(SOURCE))
+        self.assertIsNone(machine.state)
+        self.assertEqual(machine.run(), vcpu.ExitCode.HALTED)
+        self.assertEqual(machine.processor["V0"], 42)
+        self.assertEqual(machine.memory.word(4), 42)
+        self.assertEqual(machine.executed, len(machine.executable.instructions) // 4)
+
+        machine.reset()
+        self.assertEqual(machine.memory.word(4), 0)
+        self.assertEqual(machine.current_instruction(), first_instruction(SOURCE))
+
+    def test_trace_and_breakpoints(self):
+        machine = vcpu.Machine(vcpu.Executable.assemble(SOURCE))
+        addresses = []
+        machine.trace(lambda _, address: addresses.append(address))
+        self.assertEqual(machine.state, vcpu.ExitCode.HALTED)
+        self.assertEqual(machine.executed, len(addresses))
+        self.assertEqual(addresses[:3], [0, 4, 8])
+
+        machine.reset()
+        machine.debugger.add_breakpoint(addresses[-1])
+        event = machine.run_until_event()
+        self.assertEqual(event.kind, vcpu.DebugEventKind.BREAKPOINT)
+        self.assertEqual(event.address, addresses[-1])
+        self.assertIsNone(event.exit_code)
+
+    def test_io_memory(self):
+        writes = []
+        memory = vcpu.Memory.io(8, on_write=lambda data, address, size: writes.append((address, size)))
+        machine = vcpu.Machine(vcpu.Executable.assemble(SOURCE), memory)
+        writes.clear()
+        machine.run()
+        self.assertEqual(writes, [(4, 4)])
+
+        composite = vcpu.Memory.composite()
+        composite.mount(0x100, "ram", vcpu.Memory.plain(16))
+        composite.set_byte(0x101, 7)
+        self.assertEqual(composite.read(0x100, 2), b"\x00\x07")
+        with self.assertRaises(vcpu.VcpuError) as error:
+            composite.mount(0x108, "other", vcpu.Memory.plain(16))
+        self.assertEqual(error.exception.result, vcpu.Result.FRAGMENT_INTERSECTION)
+
+    def test_callback_errors(self):
+        def can_write(data, address, size):
+            raise ValueError("read-only")
+
+        def write_running(data, address, size):
+            memory.set_word(0, 1)
+
+        memory = vcpu.Memory.io(8, can_write=can_write)
+        machine = vcpu.Machine(vcpu.Executable.assemble(SOURCE), memory)
+        with unittest.mock.patch("sys.unraisablehook") as hook:
+            machine.run()
+        self.assertIsInstance(hook.call_args.args[0].exc_value, ValueError)
+        self.assertEqual(memory.word(4), 0)
+
+        memory = vcpu.Memory.io(8, on_write=write_running)
+        machine = vcpu.Machine(vcpu.Executable.assemble(SOURCE), memory)
+        with unittest.mock.patch("sys.unraisablehook") as hook:
+            machine.run()
+        self.assertEqual(hook.call_args.args[0].exc_value.result, vcpu.Result.MEMORY_IN_USE)
+        self.assertEqual(memory.word(0), 40)
+
+    def test_registers(self):
+        processor = vcpu.Processor()
+        processor["T0"] = 0xFFFFFFFF
+        self.assertEqual(processor[vcpu.register_index("T0")], 0xFFFFFFFF)
+        self.assertEqual(len(processor.registers), len(vcpu.register_names()))
+        with self.assertRaises(vcpu.VcpuError):
+            processor["NOPE"]
+
+
+class AssemblerTest(unittest.TestCase):
+    def test_errors(self):
+        with self.assertRaises(vcpu.AssemblerError) as error:
+            vcpu.Executable.assemble(".data\n.instructions\nSTUFF\nHALT")
+        diagnostic = error.exception.diagnostics[0]
+        self.assertTrue(diagnostic.is_error)
+        self.assertEqual((diagnostic.line, diagnostic.column), (3, 1))
+
+    def test_vex_and_source_map(self):
+        executable = vcpu.Executable.assemble(SOURCE)
+        self.assertEqual(len(executable.source_map), len(executable.instructions) // 4)
+        copy = vcpu.Executable.load_vex(executable.save_vex())
+        self.assertEqual(copy.instructions, executable.instructions)
+        self.assertEqual(copy.sections, executable.sections)
+
+    def test_disassemble(self):
+        self.assertEqual(first_instruction(".data\n.instructions\nHALT"), "HALT")
+        self.assertIsNone(vcpu.disassemble(0xFFFFFFFF))
+
+
+def first_instruction(source):
+    instructions = vcpu.Executable.assemble(source).instructions
+    return vcpu.disassemble(int.from_bytes(instructions[:4], "little"))
+
+
+if __name__ == "__main__":
+    unittest.main()
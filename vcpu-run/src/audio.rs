@@ -0,0 +1,176 @@
+//! Sound output, which frontends play on the host.
+//!
+//! An `audio` device is a block of four words:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `SAMPLE`  | Writing queues the lowest 16 bits as a signed PCM sample into the FIFO of the device. |
+//! | 4 | `RATE`    | Samples per second, 22050 after reset. |
+//! | 8 | `CONTROL` | Bit 0 starts playback. While it is clear, the FIFO keeps its samples. |
+//! | 12 | `LEVEL`  | Number of samples in the FIFO, updated by the runner before the next instruction. |
+//!
+//! The FIFO holds [`FIFO_SAMPLES`](constant.FIFO_SAMPLES.html) samples, further samples are dropped. The device
+//! plays samples from the FIFO into the ring buffer of its [`AudioOutput`](struct.AudioOutput.html), from which
+//! the frontend takes them. Once the frontend sets the clock of the processor with
+//! [`AudioOutput::set_clock`](struct.AudioOutput.html#method.set_clock), the device plays the samples at the
+//! sample rate of the executed instructions, so a program which keeps the FIFO filled by polling `LEVEL` sounds
+//! the same at every speed of the host. Without a clock, samples are played as soon as they are queued.
+//!
+//! If the frontend does not take the samples in time, the oldest samples of the ring buffer are overwritten.
+
+use crate::config::Device;
+use byteorder::ByteOrder;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use vcpu::Endian;
+
+/// Offset of the register which queues a sample when it is written.
+pub const SAMPLE: u32 = 0;
+pub const RATE: u32 = 4;
+pub const CONTROL: u32 = 8;
+pub const LEVEL: u32 = 12;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = 16;
+
+/// Bit of `CONTROL` which starts playback.
+pub const CONTROL_PLAY: u32 = 1;
+
+/// Sample rate after reset.
+pub const DEFAULT_SAMPLE_RATE: u32 = 22050;
+/// Number of samples the FIFO of the device holds.
+pub const FIFO_SAMPLES: u32 = 1024;
+/// Number of samples the ring buffer holds for the frontend, which is more than a second at the default rate.
+pub const RING_SAMPLES: usize = 1 << 15;
+
+struct State {
+    fifo: VecDeque<i16>,
+    ring: VecDeque<i16>,
+    rate: u32,
+    playing: bool,
+    clock_hz: Option<u64>,
+    /// Sample rate times the instructions executed since the last sample was played, modulo the clock.
+    phase: u64,
+    /// `LEVEL` as it was last stored into the memory of the device.
+    published_level: u32,
+    dropped: u64,
+}
+
+/// The host side of an audio device, which the machine and the frontend share.
+#[derive(Clone)]
+pub struct AudioOutput {
+    device: Device,
+    state: Rc<RefCell<State>>,
+}
+
+impl AudioOutput {
+    pub(crate) fn new(device: Device) -> AudioOutput {
+        AudioOutput {
+            device,
+            state: Rc::new(RefCell::new(State {
+                fifo: VecDeque::new(),
+                ring: VecDeque::new(),
+                rate: DEFAULT_SAMPLE_RATE,
+                playing: false,
+                clock_hz: None,
+                phase: 0,
+                published_level: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Sets the instructions the processor executes per second, which paces the playback, or `None` to play
+    /// samples as soon as they are queued.
+    pub fn set_clock(&self, clock_hz: Option<u64>) {
+        let mut state = self.state.borrow_mut();
+        state.clock_hz = clock_hz.filter(|hz| *hz > 0);
+        state.phase = 0;
+    }
+
+    /// Samples per second the program set.
+    pub fn sample_rate(&self) -> u32 {
+        self.state.borrow().rate
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.borrow().playing
+    }
+
+    /// Number of samples in the ring buffer.
+    pub fn available(&self) -> usize {
+        self.state.borrow().ring.len()
+    }
+
+    /// Removes and returns up to `max` samples from the ring buffer, oldest first.
+    pub fn take(&self, max: usize) -> Vec<i16> {
+        let mut state = self.state.borrow_mut();
+        let count = max.min(state.ring.len());
+        state.ring.drain(..count).collect()
+    }
+
+    /// Number of samples which were lost because the FIFO was full or the frontend did not take them in time.
+    pub fn dropped(&self) -> u64 {
+        self.state.borrow().dropped
+    }
+
+    /// Handles a write of the program to a register of the device.
+    pub(crate) fn on_write(&self, memory: &[u8], address: u32) {
+        let mut state = self.state.borrow_mut();
+        let value = Endian::read_u32(&memory[(address & !3) as usize..]);
+        match address & !3 {
+            SAMPLE => {
+                if state.fifo.len() < FIFO_SAMPLES as usize {
+                    state.fifo.push_back(value as u16 as i16);
+                } else {
+                    state.dropped += 1;
+                }
+            }
+            RATE => {
+                state.rate = value;
+                state.phase = 0;
+            }
+            CONTROL => state.playing = value & CONTROL_PLAY != 0,
+            _ => {}
+        }
+    }
+
+    /// Plays the samples which are due after one more instruction, and returns the new `LEVEL` if it
+    /// changed since it was returned the last time.
+    pub(crate) fn tick(&self) -> Option<u32> {
+        let mut state = self.state.borrow_mut();
+        if state.playing {
+            let due = match state.clock_hz {
+                Some(clock_hz) => {
+                    state.phase += u64::from(state.rate);
+                    let due = state.phase / clock_hz;
+                    state.phase %= clock_hz;
+                    due as usize
+                }
+                None => state.fifo.len(),
+            };
+            for _ in 0..due {
+                // a FIFO which runs empty plays silence, which is not stored
+                let sample = match state.fifo.pop_front() {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                if state.ring.len() == RING_SAMPLES {
+                    state.ring.pop_front();
+                    state.dropped += 1;
+                }
+                state.ring.push_back(sample);
+            }
+        }
+        let level = state.fifo.len() as u32;
+        if level == state.published_level {
+            return None;
+        }
+        state.published_level = level;
+        Some(level)
+    }
+}
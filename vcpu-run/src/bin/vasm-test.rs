@@ -0,0 +1,115 @@
+//! Runs every assembly file in a directory which contains expectations and reports which of them failed.
+//!
+//! See `vcpu_run::expect` for the expectations. Files without any are skipped, so that files which are only
+//! included by tests can be kept next to them.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{App, Arg};
+use std::path::{Path, PathBuf};
+use vcpu_run::config::MachineConfig;
+use vcpu_run::expect;
+use vcpu_run::ERROR_STATUS;
+
+/// Status if any test failed.
+const FAILED_STATUS: i32 = 1;
+
+fn main() {
+    let matches = App::new("vasm-test")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Runs VCPU assembly tests and checks their expectations.")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the directories and files to test")
+                .multiple(true)
+                .default_value("."),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size, devices and limits from a machine file"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Prints the tests which passed as well"),
+        )
+        .get_matches();
+
+    let config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    let mut files = Vec::new();
+    for input in matches.values_of("INPUT").unwrap() {
+        let path = Path::new(input);
+        if path.is_dir() {
+            collect_files(path, &mut files).unwrap_or_else(|err| {
+                fail(&format!("Reading directory \"{}\" failed: {}", input, err))
+            });
+        } else {
+            files.push(path.to_owned());
+        }
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let path = file.to_string_lossy();
+        match expect::run_file(&path, &config) {
+            Ok(Some(failures)) if failures.is_empty() => {
+                passed += 1;
+                if matches.is_present("verbose") {
+                    println!("PASS {}", path);
+                }
+            }
+            Ok(Some(failures)) => {
+                failed += 1;
+                println!("FAIL {}", path);
+                for failure in failures {
+                    println!("    {}", failure);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}", path);
+                for line in err.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        std::process::exit(FAILED_STATUS);
+    }
+}
+
+/// Adds all `.vasm` files in `directory` and its subdirectories to `files`, in the order of their paths.
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "vasm")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
@@ -0,0 +1,422 @@
+//! Debugs a VCPU program interactively, with breakpoints, single-stepping and inspection of the registers
+//! and memory. Symbols and source lines are taken from the debug information of the program.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{App, AppSettings, Arg};
+use std::cell::RefCell;
+use std::io::prelude::*;
+use std::rc::Rc;
+use vcpu::WORD_BYTES;
+use vcpu_run::config::{parse_size, Device, MachineConfig};
+use vcpu_run::debugger::{Debugger, Session};
+use vcpu_run::script::{Flow, Script, ScriptRunner};
+use vcpu_run::*;
+
+const HELP: &str = "Commands:
+  run, continue, c          Runs until a breakpoint is reached or the program stops
+  step, s [COUNT]           Executes one or COUNT instructions
+  next, n [COUNT]           Executes one or COUNT instructions, running called functions until they return
+  finish                    Runs until the current function returns
+  until, u EXPR             Runs until the instruction at an address is reached
+  break, b EXPR [if COND]   Sets a breakpoint at an instruction address, or changes its condition
+  delete, d EXPR            Removes a breakpoint
+  breakpoints               Lists all breakpoints
+  regs, r                   Prints the registers
+  backtrace, bt             Prints the functions which are being executed
+  print, p EXPR             Prints the value of an expression
+  x EXPR [COUNT]            Prints COUNT words of memory (default: 4)
+  disas [EXPR] [COUNT]      Disassembles COUNT instructions (default: around the program counter)
+  screenshot FILE           Saves the framebuffer of the machine as a PNG file
+  devices                   Lists the devices of the machine
+  attach KIND@ADDRESS       Attaches a device, e.g. serial@0xFFFF1000, until the program restarts
+  detach EXPR               Detaches the device at an address
+  restart                   Starts the program from the beginning, keeping the breakpoints
+  source FILE               Runs a Rhai script which controls the debugger
+  help, h                   Prints this help
+  quit, q                   Exits the debugger
+
+Expressions are like in the assembler and may use numbers, registers ($SP), pc, symbols and words in
+memory ([$SP+4]), e.g. main+8. A breakpoint with a condition only stops if it is not zero, and conditions
+may use the number of hits of the breakpoint, e.g. hits == 100 or $T3 == 0 && [$SP-4] > 2.
+An empty line repeats the previous command.";
+
+fn main() {
+    let matches = App::new("vdb")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Debugs VCPU programs.")
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the vexfile, ELF file or assembly source to debug")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("ARGS")
+                .help("Sets the arguments passed to the program")
+                .multiple(true)
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("debug_info")
+                .short("g")
+                .long("debug_info")
+                .takes_value(true)
+                .value_name("DEBUG_INFO")
+                .help("Reads symbols and lines from this file instead of the debug information embedded in the input"),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=VALUE")
+                .help("Passes an environment string to the program"),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size, devices and limits from a machine file"),
+        )
+        .arg(
+            Arg::with_name("ram")
+                .long("ram")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("Sets the size of the RAM in bytes, optionally with a K or M suffix"),
+        )
+        .arg(
+            Arg::with_name("script")
+                .long("script")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Runs a Rhai script before reading commands, see the documentation of vcpu_run::script"),
+        )
+        .get_matches();
+
+    let input = matches.value_of("INPUT").unwrap();
+    let Loaded {
+        executable,
+        warnings,
+    } = load(input).unwrap_or_else(|err| fail(&err));
+    for warning in warnings.iter() {
+        eprintln!("Warning:\n{}", warning);
+    }
+    let debug_info = match matches.value_of("debug_info") {
+        Some(path) => Some(vex::debug::read_file(path).unwrap_or_else(|err| {
+            fail(&format!(
+                "Reading debug information \"{}\" failed: {}",
+                path, err
+            ))
+        })),
+        None => executable.debug_info().cloned(),
+    };
+    let mut config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    if let Some(value) = matches.value_of("ram") {
+        config.ram_size = Some(parse_size(value).unwrap());
+    }
+    let args = std::iter::once(input)
+        .chain(matches.values_of("ARGS").into_iter().flatten())
+        .map(String::from)
+        .collect();
+    let env = matches
+        .values_of("env")
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect();
+    let session = Rc::new(Session::new(executable, config, args, env));
+    let machine = session
+        .start(Box::new(std::io::stdout()))
+        .unwrap_or_else(|err| fail(&err));
+    let debugger = Rc::new(RefCell::new(Debugger::new(machine, debug_info)));
+    if debugger.borrow().debug_info().is_none() {
+        println!("No debug information, symbols are not available.");
+    }
+    println!(
+        "{}",
+        debugger
+            .borrow()
+            .describe_instruction(program_counter(&debugger.borrow()))
+    );
+
+    if let Some(path) = matches.value_of("script") {
+        match run_script(&debugger, &session, path) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => return,
+            Err(err) => fail(&err),
+        }
+    }
+
+    let stdin = std::io::stdin();
+    let mut previous = String::new();
+    loop {
+        print!("(vdb) ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = match line.trim() {
+            "" => previous.clone(),
+            line => line.to_owned(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+        match execute(&debugger, &session, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => println!("{}", err),
+        }
+        previous = line;
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
+
+fn program_counter(debugger: &Debugger) -> u32 {
+    debugger.machine().processor().program_counter()
+}
+
+fn run_script(
+    debugger: &Rc<RefCell<Debugger>>,
+    session: &Rc<Session>,
+    path: &str,
+) -> Result<Flow, String> {
+    let script = Script::read_file(path)?;
+    let console = || -> Box<dyn Write> { Box::new(std::io::stdout()) };
+    ScriptRunner::new(
+        debugger.clone(),
+        session.clone(),
+        Box::new(std::io::stdout()),
+        Box::new(console),
+    )
+    .run(&script)
+}
+
+/// Prints why the program stopped, and where and how it got there unless it halted.
+fn report_stop(debugger: &Debugger, stop: Stop) {
+    let machine = debugger.machine();
+    match describe_stop(machine, stop) {
+        Some(message) => {
+            println!("{}", message);
+            println!(
+                "{}",
+                debugger.describe_instruction(program_counter(debugger))
+            );
+            print!("{}", debugger.format_backtrace());
+        }
+        None => println!("Program halted with status {}", exit_status(machine, stop)),
+    }
+}
+
+/// Describes the instruction of a breakpoint, its condition and how often it was reached.
+fn describe_breakpoint(debugger: &Debugger, address: u32) -> String {
+    let mut text = debugger.describe_instruction(address);
+    if let Some(breakpoint) = debugger.breakpoint(address) {
+        if let Some(condition) = &breakpoint.condition {
+            text.push_str(&format!(" if {}", condition));
+        }
+        if breakpoint.hits > 0 {
+            text.push_str(&format!(", reached {} times", breakpoint.hits));
+        }
+    }
+    text
+}
+
+/// Prints why the program stopped, or the next instruction if it did not.
+fn report_step(debugger: &Debugger, stop: Option<Stop>) {
+    match stop {
+        Some(stop) => report_stop(debugger, stop),
+        None => println!(
+            "{}",
+            debugger.describe_instruction(program_counter(debugger))
+        ),
+    }
+}
+
+fn ensure_running(debugger: &Debugger) -> Result<(), String> {
+    match debugger.machine().processor().state() {
+        Some(exit_code) => Err(format!(
+            "The program has stopped ({:?}), use restart to run it again",
+            exit_code
+        )),
+        None => Ok(()),
+    }
+}
+
+fn parse_count(argument: Option<&str>, default: u32) -> Result<u32, String> {
+    match argument {
+        Some(count) => count
+            .parse()
+            .map_err(|_| format!("Invalid count {}", count)),
+        None => Ok(default),
+    }
+}
+
+/// Executes a command line and returns false if the debugger should exit.
+fn execute(
+    debugger: &Rc<RefCell<Debugger>>,
+    session: &Rc<Session>,
+    line: &str,
+) -> Result<bool, String> {
+    let (command, arguments) = match line.split_once(char::is_whitespace) {
+        Some((command, arguments)) => (command, arguments.trim()),
+        None => (line, ""),
+    };
+    // scripts share the debugger, so it must not be borrowed while they run
+    if command == "source" {
+        return Ok(run_script(debugger, session, arguments)? == Flow::Continue);
+    }
+    let debugger = &mut *debugger.borrow_mut();
+    let mut words = arguments.split_whitespace();
+
+    match command {
+        "run" | "continue" | "c" => {
+            ensure_running(debugger)?;
+            match debugger.resume(&session.config.limits) {
+                Some(stop) => report_stop(debugger, stop),
+                None => println!(
+                    "Breakpoint reached\n{}",
+                    debugger.describe_instruction(program_counter(debugger))
+                ),
+            }
+        }
+        "step" | "s" => {
+            let count = parse_count(words.next(), 1)?;
+            let stop = debugger.step(count.into());
+            report_step(debugger, stop);
+        }
+        "next" | "n" => {
+            let count = parse_count(words.next(), 1)?;
+            ensure_running(debugger)?;
+            let mut stop = None;
+            for _ in 0..count {
+                stop = debugger.step_over(&session.config.limits);
+                if stop.is_some() || debugger.has_breakpoint(program_counter(debugger)) {
+                    break;
+                }
+            }
+            report_step(debugger, stop);
+        }
+        "finish" => {
+            ensure_running(debugger)?;
+            let stop = debugger.step_out(&session.config.limits)?;
+            report_step(debugger, stop);
+        }
+        "until" | "u" => {
+            let address = debugger.eval(arguments)?;
+            ensure_running(debugger)?;
+            let stop = debugger.run_to(address, &session.config.limits);
+            report_step(debugger, stop);
+        }
+        "break" | "b" => {
+            let (expression, condition) = match arguments.split_once(" if ") {
+                Some((expression, condition)) => (expression, Some(condition.trim())),
+                None => (arguments, None),
+            };
+            let address = debugger.eval(expression)?;
+            if address % WORD_BYTES != 0 {
+                return Err(format!("0x{:08X} is not an instruction address", address));
+            }
+            let added = debugger.add_breakpoint(address);
+            if condition.is_some() || added {
+                if let Err(err) = debugger.set_condition(address, condition) {
+                    if added {
+                        debugger.remove_breakpoint(address);
+                    }
+                    return Err(err);
+                }
+                println!("Breakpoint at {}", describe_breakpoint(debugger, address));
+            } else {
+                println!("There already is a breakpoint at 0x{:08X}", address);
+            }
+        }
+        "delete" | "d" => {
+            let address = debugger.eval(arguments)?;
+            if !debugger.remove_breakpoint(address) {
+                return Err(format!("There is no breakpoint at 0x{:08X}", address));
+            }
+        }
+        "breakpoints" => {
+            let breakpoints: Vec<u32> = debugger.breakpoints().collect();
+            if breakpoints.is_empty() {
+                println!("No breakpoints");
+            }
+            for address in breakpoints {
+                println!("{}", describe_breakpoint(debugger, address));
+            }
+        }
+        "regs" | "r" => print!("{}", debugger.registers()),
+        "backtrace" | "bt" => print!("{}", debugger.format_backtrace()),
+        "print" | "p" => {
+            let value = debugger.eval(arguments)?;
+            match debugger.symbolize(value) {
+                Some(name) => println!("0x{:08X} ({}) <{}>", value, value as i32, name),
+                None => println!("0x{:08X} ({})", value, value as i32),
+            }
+        }
+        "x" => {
+            let address = debugger.eval(words.next().unwrap_or(""))?;
+            let count = parse_count(words.next(), 4)?;
+            print!("{}", debugger.memory(address, count));
+        }
+        "disas" => {
+            let (start, count) = match words.next() {
+                Some(expression) => (debugger.eval(expression)?, parse_count(words.next(), 8)?),
+                None => (program_counter(debugger).saturating_sub(4 * WORD_BYTES), 9),
+            };
+            let pc = program_counter(debugger);
+            for i in 0..count {
+                let address = start.wrapping_add(i * WORD_BYTES);
+                let marker = if address == pc { "=>" } else { "  " };
+                println!("{} {}", marker, debugger.describe_instruction(address));
+            }
+        }
+        "screenshot" => {
+            session.screenshot(debugger.machine(), arguments)?;
+            println!("Saved the framebuffer to {}", arguments);
+        }
+        "devices" => {
+            for device in debugger.machine().devices() {
+                println!("{}", device);
+            }
+        }
+        "attach" => {
+            let device: Device = arguments.parse()?;
+            debugger.machine_mut().attach_device(device)?;
+            println!("Attached {}", device);
+        }
+        "detach" => {
+            let address = debugger.eval(arguments)?;
+            let device = debugger.machine_mut().detach_device(address)?;
+            println!("Detached {}", device);
+        }
+        "restart" => {
+            debugger.set_machine(session.start(Box::new(std::io::stdout()))?);
+            println!(
+                "{}",
+                debugger.describe_instruction(program_counter(debugger))
+            );
+        }
+        "help" | "h" => println!("{}", HELP),
+        "quit" | "q" => return Ok(false),
+        _ => return Err(format!("Unknown command \"{}\", try help", command)),
+    }
+    Ok(true)
+}
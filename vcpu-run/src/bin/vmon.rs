@@ -0,0 +1,315 @@
+//! Shows a VCPU program in a full screen terminal monitor while it runs or steps: the registers,
+//! the disassembly around the program counter, a view of the memory and the console output.
+//!
+//! The limits of the machine file are not applied, since the program can be paused at any time.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{App, AppSettings, Arg};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::DefaultTerminal;
+use std::io;
+use std::time::{Duration, Instant};
+use vcpu_run::config::{parse_size, MachineConfig};
+use vcpu_run::debugger::{Debugger, Session};
+use vcpu_run::monitor::{render, ConsoleBuffer, View, MEMORY_LINE_BYTES};
+use vcpu_run::*;
+
+/// Number of instructions executed between two checks for input.
+const CHUNK: u64 = 1 << 14;
+/// Time between two redraws while the program is running.
+const FRAME_TIME: Duration = Duration::from_millis(50);
+const KEYS: &str =
+    "s step  S step 100  c run/pause  b breakpoint  g memory  j/k scroll  r restart  q quit";
+
+/// Text which is being entered in the last line.
+enum Prompt {
+    Breakpoint(String),
+    Memory(String),
+}
+
+struct Monitor {
+    session: Session,
+    debugger: Debugger,
+    console: ConsoleBuffer,
+    memory_address: u32,
+    running: bool,
+    prompt: Option<Prompt>,
+    message: String,
+}
+
+impl Monitor {
+    fn status(&self) -> String {
+        let machine = self.debugger.machine();
+        let state = match machine.processor().state() {
+            Some(exit_code) => {
+                let stop = Stop::Exit(exit_code);
+                match describe_stop(machine, stop) {
+                    Some(message) => message,
+                    None => {
+                        format!("Program halted with status {}", exit_status(machine, stop))
+                    }
+                }
+            }
+            None if self.running => "Running".to_owned(),
+            None => "Paused".to_owned(),
+        };
+        format!("vmon  {}  {}", self.session.args[0], state)
+    }
+
+    fn footer(&self) -> String {
+        match &self.prompt {
+            Some(Prompt::Breakpoint(text)) => format!("Toggle breakpoint at: {}_", text),
+            Some(Prompt::Memory(text)) => format!("Show memory at: {}_", text),
+            None if self.message.is_empty() => KEYS.to_owned(),
+            None => format!("{}  ({})", KEYS, self.message),
+        }
+    }
+
+    fn draw(&self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let status = self.status();
+        let footer = self.footer();
+        let view = View {
+            memory_address: self.memory_address,
+            status: &status,
+            footer: &footer,
+        };
+        terminal.draw(|frame| render(frame, &self.debugger, &self.console, &view))?;
+        Ok(())
+    }
+
+    fn restart(&mut self) -> Result<(), String> {
+        self.console.clear();
+        let machine = self.session.start(Box::new(self.console.clone()))?;
+        self.debugger.set_machine(machine);
+        self.running = false;
+        Ok(())
+    }
+
+    /// Runs the program for a while, pausing it at breakpoints and when it stops.
+    fn run_chunk(&mut self) {
+        let limits = Limits {
+            max_instructions: Some(self.debugger.machine().executed() + CHUNK),
+            timeout: None,
+        };
+        match self.debugger.resume(&limits) {
+            Some(Stop::InstructionLimit) => {}
+            None => {
+                self.running = false;
+                self.message = "Breakpoint reached".to_owned();
+            }
+            Some(_) => self.running = false,
+        }
+    }
+
+    /// Handles a key and returns false if the monitor should exit.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        let interrupt =
+            key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+        if let Some(prompt) = &mut self.prompt {
+            let text = match prompt {
+                Prompt::Breakpoint(text) | Prompt::Memory(text) => text,
+            };
+            match key.code {
+                _ if interrupt => self.prompt = None,
+                KeyCode::Enter => {
+                    let prompt = self.prompt.take().unwrap();
+                    self.submit(prompt);
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) if !c.is_control() => text.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        self.message.clear();
+        if interrupt {
+            if !self.running {
+                return false;
+            }
+            self.running = false;
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('c') => self.running = !self.running && !self.stopped(),
+            KeyCode::Char(c @ ('s' | 'S')) => {
+                self.running = false;
+                self.debugger.step(if c == 's' { 1 } else { 100 });
+            }
+            KeyCode::Char('b') => self.prompt = Some(Prompt::Breakpoint(String::new())),
+            KeyCode::Char('g') => self.prompt = Some(Prompt::Memory(String::new())),
+            KeyCode::Char('j') => {
+                self.memory_address = self.memory_address.wrapping_add(MEMORY_LINE_BYTES)
+            }
+            KeyCode::Char('k') => {
+                self.memory_address = self.memory_address.wrapping_sub(MEMORY_LINE_BYTES)
+            }
+            KeyCode::Char('r') => {
+                if let Err(err) = self.restart() {
+                    self.message = err;
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Runs the program and handles the keys until the user quits.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let mut last_frame: Option<Instant> = None;
+        loop {
+            if self.running {
+                self.run_chunk();
+            }
+            if !self.running || last_frame.is_none_or(|time| time.elapsed() >= FRAME_TIME) {
+                self.draw(terminal)?;
+                last_frame = Some(Instant::now());
+            }
+            let timeout = if self.running {
+                Duration::from_millis(0)
+            } else {
+                Duration::from_millis(100)
+            };
+            if !event::poll(timeout)? {
+                continue;
+            }
+            // handles all keys which are waiting before drawing again
+            loop {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.key(key) {
+                        return Ok(());
+                    }
+                }
+                if !event::poll(Duration::from_millis(0))? {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.debugger.machine().processor().is_stopped()
+    }
+
+    fn submit(&mut self, prompt: Prompt) {
+        let result = match prompt {
+            Prompt::Breakpoint(text) => self.debugger.eval(&text).map(|address| {
+                if !self.debugger.add_breakpoint(address) {
+                    self.debugger.remove_breakpoint(address);
+                }
+            }),
+            Prompt::Memory(text) => self
+                .debugger
+                .eval(&text)
+                .map(|address| self.memory_address = address),
+        };
+        if let Err(err) = result {
+            self.message = err;
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
+
+fn main() {
+    let matches = App::new("vmon")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Shows VCPU programs in a terminal monitor.")
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the vexfile, ELF file or assembly source to run")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("ARGS")
+                .help("Sets the arguments passed to the program")
+                .multiple(true)
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("debug_info")
+                .short("g")
+                .long("debug_info")
+                .takes_value(true)
+                .value_name("DEBUG_INFO")
+                .help("Reads symbols and lines from this file instead of the debug information embedded in the input"),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size and devices from a machine file"),
+        )
+        .arg(
+            Arg::with_name("ram")
+                .long("ram")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("Sets the size of the RAM in bytes, optionally with a K or M suffix"),
+        )
+        .get_matches();
+
+    let input = matches.value_of("INPUT").unwrap();
+    let Loaded {
+        executable,
+        warnings,
+    } = load(input).unwrap_or_else(|err| fail(&err));
+    for warning in warnings.iter() {
+        eprintln!("Warning:\n{}", warning);
+    }
+    let debug_info = match matches.value_of("debug_info") {
+        Some(path) => Some(vex::debug::read_file(path).unwrap_or_else(|err| {
+            fail(&format!(
+                "Reading debug information \"{}\" failed: {}",
+                path, err
+            ))
+        })),
+        None => executable.debug_info().cloned(),
+    };
+    let mut config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    if let Some(value) = matches.value_of("ram") {
+        config.ram_size = Some(parse_size(value).unwrap());
+    }
+    let args = std::iter::once(input)
+        .chain(matches.values_of("ARGS").into_iter().flatten())
+        .map(String::from)
+        .collect();
+    let session = Session::new(executable, config, args, Vec::new());
+    let console = ConsoleBuffer::default();
+    let machine = session
+        .start(Box::new(console.clone()))
+        .unwrap_or_else(|err| fail(&err));
+
+    let mut monitor = Monitor {
+        session,
+        debugger: Debugger::new(machine, debug_info),
+        console,
+        memory_address: 0,
+        running: false,
+        prompt: None,
+        message: String::new(),
+    };
+    let mut terminal = ratatui::try_init().unwrap_or_else(|err| fail(&err.to_string()));
+    let result = monitor.run(&mut terminal);
+    ratatui::restore();
+    if let Err(err) = result {
+        fail(&format!("Terminal error: {}", err));
+    }
+}
@@ -0,0 +1,63 @@
+//! Serves the remote control protocol of `vcpu_run::remote`, so that other programs can drive machines.
+//!
+//! Clients can load any file the server can read, so the server only listens on the local host by default.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{App, Arg};
+use std::net::TcpListener;
+use vcpu_run::config::MachineConfig;
+use vcpu_run::{load, ERROR_STATUS};
+
+fn main() {
+    let matches = App::new("vremote")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Controls VCPU machines with JSON-RPC over TCP.")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets a program which is loaded for every client")
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:7811")
+                .help("Sets the address and port to listen on"),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size, devices and limits from a machine file"),
+        )
+        .get_matches();
+
+    let config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    // every client loads the program again, so it is only checked once here
+    if let Some(input) = matches.value_of("INPUT") {
+        let warnings = load(input).unwrap_or_else(|err| fail(&err)).warnings;
+        for warning in warnings.iter() {
+            eprintln!("Warning:\n{}", warning);
+        }
+    }
+    let address = matches.value_of("listen").unwrap();
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|err| fail(&format!("Listening on {} failed: {}", address, err)));
+    eprintln!("Listening on {}", address);
+    if let Err(err) = vcpu_run::remote::serve(listener, &config, matches.value_of("INPUT")) {
+        fail(&err);
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
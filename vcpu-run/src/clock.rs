@@ -0,0 +1,39 @@
+//! The host time which the runner measures timeouts and paces programs with.
+//!
+//! All reads of the host clock and all sleeps of the runner go through the
+//! [`ClockSource`](../../vcpu/trait.ClockSource.html) of the machine, see
+//! [`Machine::set_clock_source`](../struct.Machine.html#method.set_clock_source). The default is the
+//! [`SystemClock`](../../vcpu/struct.SystemClock.html). Tests replace it with a [`FakeClock`](struct.FakeClock.html),
+//! which only advances when it is told to or when the runner sleeps, so runs with timeouts or at a
+//! [`run_realtime`](../struct.Machine.html#method.run_realtime) speed are the same every time and
+//! finish as fast as the host can execute them.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+pub use vcpu::{ClockSource, SystemClock};
+
+/// A clock which stands still until it is advanced. Sleeping advances it at once, and clones share the time.
+#[derive(Clone, Default)]
+pub struct FakeClock(Rc<Cell<Duration>>);
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock::default()
+    }
+
+    /// Moves the clock forward by `duration`, e.g. to let a timeout expire.
+    pub fn advance(&self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+impl ClockSource for FakeClock {
+    fn now(&self) -> Duration {
+        self.0.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
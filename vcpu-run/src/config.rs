@@ -0,0 +1,367 @@
+//! Configuration of the simulated hardware, read from a machine file.
+//!
+//! Machine files are [TOML](https://toml.io) with the following keys, and a `[[device]]` table for every device.
+//! Sizes and addresses are integers or strings like `"64K"`, see [`parse_size`](fn.parse_size.html):
+//!
+//! ```toml
+//! ram = "64K"
+//! max-instructions = 1_000_000
+//! timeout = 2.5
+//! semihosting-root = "fixtures"
+//! isa = "-muldiv,-float"
+//! framebuffer = "0x100000:320x200"
+//! flash-file = "settings.bin"
+//! flash-endurance = 10_000
+//! reset-vector = 0xFFF00000
+//! disk-file = "boot.img"
+//! device-change-interrupt = 7
+//!
+//! [[device]]
+//! kind = "uart"
+//! address = 0xFFFF0000
+//! ```
+//!
+//! `isa` restricts the instructions and registers the processor implements, e.g. `"base,regs16"` or
+//! `"base,regs:ZERO/V0/ACC=T0/SP/RA"`, see [`IsaProfile`](../../vcpu/struct.IsaProfile.html). `framebuffer` mounts a
+//! [`Framebuffer`](../frame/struct.Framebuffer.html), which the runners can save as a screenshot.
+//! `flash-file` is the image the first `flash` device is loaded from and saved to, and `flash-endurance` the
+//! number of erases after which its pages wear out, see the [`flash`](../flash/index.html) module.
+//! `reset-vector` makes the machine boot the program as a ROM at that address, see
+//! [`Machine::boot`](../struct.Machine.html#method.boot), and `disk-file` is the image the first `disk` device is
+//! loaded from and saved to, see the [`disk`](../disk/index.html) module. `device-change-interrupt` is the
+//! interrupt line the debugger raises when it attaches or detaches a device, see
+//! [`Machine::set_device_change_interrupt`](../struct.Machine.html#method.set_device_change_interrupt).
+
+use crate::audio;
+use crate::disk;
+use crate::flash;
+use crate::frame::Framebuffer;
+use crate::gpio;
+use crate::machine::Limits;
+use crate::network;
+use crate::plugin;
+use crate::power;
+use crate::semihosting;
+use crate::serial;
+use serde::de::{self, Deserializer, Error as _, Visitor};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use vcpu::IsaProfile;
+
+/// Kind of a memory mapped device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// A transmit register, which sends the lowest byte of every value stored in it to the console.
+    Uart,
+    /// Gives the program access to files and the standard streams of the host, see the
+    /// [`semihosting`](../semihosting/index.html) module.
+    Semihosting,
+    /// Plays samples on the host, see the [`audio`](../audio/index.html) module.
+    Audio,
+    /// A serial port which a terminal connects to over TCP, see the [`serial`](../serial/index.html) module.
+    Serial,
+    /// Sends packets to other machines, see the [`network`](../network/index.html) module.
+    Network,
+    /// Pins which the program drives or reads, see the [`gpio`](../gpio/index.html) module.
+    Gpio,
+    /// Non-volatile memory, see the [`flash`](../flash/index.html) module.
+    Flash,
+    /// Switches the machine off or restarts it, see the [`power`](../power/index.html) module.
+    Power,
+    /// Sectors which the program reads and writes, see the [`disk`](../disk/index.html) module.
+    Disk,
+    /// A device registered by a [`plugin`](../plugin/index.html), with its name.
+    Plugin(&'static str),
+}
+
+impl DeviceKind {
+    /// The devices which are implemented by this crate.
+    pub const BUILTIN: [DeviceKind; 9] = [
+        DeviceKind::Uart,
+        DeviceKind::Semihosting,
+        DeviceKind::Audio,
+        DeviceKind::Serial,
+        DeviceKind::Network,
+        DeviceKind::Gpio,
+        DeviceKind::Flash,
+        DeviceKind::Power,
+        DeviceKind::Disk,
+    ];
+
+    /// Number of bytes the device occupies in the address space.
+    pub fn size(self) -> u32 {
+        match self {
+            DeviceKind::Uart => 4,
+            DeviceKind::Semihosting => semihosting::SIZE,
+            DeviceKind::Audio => audio::SIZE,
+            DeviceKind::Serial => serial::SIZE,
+            DeviceKind::Network => network::SIZE,
+            DeviceKind::Gpio => gpio::SIZE,
+            DeviceKind::Flash => flash::SIZE,
+            DeviceKind::Power => power::SIZE,
+            DeviceKind::Disk => disk::SIZE,
+            DeviceKind::Plugin(name) => plugin::find(name).map_or(0, |plugin| plugin.size()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            DeviceKind::Uart => "uart",
+            DeviceKind::Semihosting => "semihosting",
+            DeviceKind::Audio => "audio",
+            DeviceKind::Serial => "serial",
+            DeviceKind::Network => "network",
+            DeviceKind::Gpio => "gpio",
+            DeviceKind::Flash => "flash",
+            DeviceKind::Power => "power",
+            DeviceKind::Disk => "disk",
+            DeviceKind::Plugin(name) => name,
+        })
+    }
+}
+
+impl FromStr for DeviceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uart" => Ok(DeviceKind::Uart),
+            "semihosting" => Ok(DeviceKind::Semihosting),
+            "audio" => Ok(DeviceKind::Audio),
+            "serial" => Ok(DeviceKind::Serial),
+            "network" => Ok(DeviceKind::Network),
+            "gpio" => Ok(DeviceKind::Gpio),
+            "flash" => Ok(DeviceKind::Flash),
+            "power" => Ok(DeviceKind::Power),
+            "disk" => Ok(DeviceKind::Disk),
+            _ => match plugin::find(s) {
+                Some(plugin) => Ok(DeviceKind::Plugin(plugin.name())),
+                None => Err(format!("Unknown device \"{}\"", s)),
+            },
+        }
+    }
+}
+
+/// A device mounted at an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub address: u32,
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@0x{:08X}", self.kind, self.address)
+    }
+}
+
+/// Parses a device like `uart@0xFFFF0000`.
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, address) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Expected KIND@ADDRESS instead of \"{}\"", s))?;
+        Ok(Device {
+            kind: kind.parse()?,
+            address: parse_size(address)?,
+        })
+    }
+}
+
+/// The machine a program runs on. Settings which are not given are left to the runner.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MachineConfig {
+    pub ram_size: Option<u32>,
+    pub devices: Vec<Device>,
+    pub limits: Limits,
+    /// Directory the semihosting devices can open files in.
+    pub semihosting_root: Option<PathBuf>,
+    /// Instruction groups the processor implements.
+    pub isa: IsaProfile,
+    pub framebuffer: Option<Framebuffer>,
+    /// Image of the first flash device.
+    pub flash_file: Option<PathBuf>,
+    pub flash_endurance: Option<u32>,
+    /// Address of the boot ROM, which the program is mounted as instead of being loaded into the RAM.
+    pub reset_vector: Option<u32>,
+    /// Image of the first disk device.
+    pub disk_file: Option<PathBuf>,
+    /// Interrupt line which is raised when a device is attached or detached.
+    pub device_change_interrupt: Option<u32>,
+}
+
+/// The contents of a machine file, before they are turned into a [`MachineConfig`](struct.MachineConfig.html).
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct MachineFile {
+    ram: Option<Size>,
+    max_instructions: Option<u64>,
+    timeout: Option<Seconds>,
+    semihosting_root: Option<PathBuf>,
+    isa: Option<Parsed<IsaProfile>>,
+    framebuffer: Option<Parsed<Framebuffer>>,
+    flash_file: Option<PathBuf>,
+    flash_endurance: Option<u32>,
+    reset_vector: Option<Size>,
+    disk_file: Option<PathBuf>,
+    device_change_interrupt: Option<u32>,
+    #[serde(default)]
+    device: Vec<DeviceTable>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceTable {
+    kind: Parsed<DeviceKind>,
+    address: Size,
+}
+
+/// A size, which is either an integer or a string like `"64K"`.
+struct Size(u32);
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Size, D::Error> {
+        struct SizeVisitor;
+
+        impl<'de> Visitor<'de> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a size")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Size, E> {
+                u32::try_from(value)
+                    .map(Size)
+                    .map_err(|_| E::custom(format!("Size {} is too large", value)))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Size, E> {
+                parse_size(value).map(Size).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+/// A number of seconds, which may have a fractional part.
+struct Seconds(Duration);
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Seconds, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        if seconds >= 0.0 && seconds.is_finite() {
+            Ok(Seconds(Duration::from_secs_f64(seconds)))
+        } else {
+            Err(D::Error::custom("Expected a number of seconds"))
+        }
+    }
+}
+
+/// A value which is parsed from a string, e.g. the kind of a device.
+struct Parsed<T>(T);
+
+impl<'de, T: FromStr<Err = String>> Deserialize<'de> for Parsed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Parsed<T>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map(Parsed).map_err(D::Error::custom)
+    }
+}
+
+impl MachineConfig {
+    /// Parses the contents of a machine file.
+    pub fn parse(text: &str) -> Result<MachineConfig, String> {
+        let file: MachineFile = toml::from_str(text).map_err(|err| match err.span() {
+            Some(span) => {
+                let line = text[..span.start].matches('\n').count() + 1;
+                format!("line {}: {}", line, err.message())
+            }
+            None => err.message().to_owned(),
+        })?;
+        Ok(MachineConfig {
+            ram_size: file.ram.map(|Size(size)| size),
+            devices: file
+                .device
+                .into_iter()
+                .map(|device| Device {
+                    kind: device.kind.0,
+                    address: device.address.0,
+                })
+                .collect(),
+            limits: Limits {
+                max_instructions: file.max_instructions,
+                timeout: file.timeout.map(|Seconds(timeout)| timeout),
+            },
+            semihosting_root: file.semihosting_root,
+            isa: file.isa.map(|Parsed(isa)| isa).unwrap_or_default(),
+            framebuffer: file.framebuffer.map(|Parsed(framebuffer)| framebuffer),
+            flash_file: file.flash_file,
+            flash_endurance: file.flash_endurance,
+            reset_vector: file.reset_vector.map(|Size(address)| address),
+            disk_file: file.disk_file,
+            device_change_interrupt: file.device_change_interrupt,
+        })
+    }
+
+    /// Reads and parses the machine file at `path`.
+    pub fn read_file(path: &str) -> Result<MachineConfig, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Reading machine file \"{}\" failed: {}", path, err))?;
+        MachineConfig::parse(&text).map_err(|err| format!("{}:{}", path, err))
+    }
+}
+
+/// Parses a size like `4096`, `0x1000`, `64K` or `1M`.
+pub fn parse_size(value: &str) -> Result<u32, String> {
+    let (digits, unit) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 1 << 20),
+        _ => (value, 1),
+    };
+    let number = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u32::from_str_radix(&digits[2..], 16)
+    } else {
+        digits.parse::<u32>()
+    };
+    number
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .ok_or_else(|| format!("\"{}\" is not a valid size", value))
+}
+
+/// Parses a timeout in seconds, which may have a fractional part.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("\"{}\" is not a valid number of seconds", value))
+}
+
+/// Parses a clock frequency like `1000`, `50k`, `2.5M` or `1GHz`, whose suffixes are decimal.
+pub fn parse_frequency(value: &str) -> Result<u64, String> {
+    let digits = value.strip_suffix("Hz").unwrap_or(value);
+    let (digits, unit) = match digits.chars().last() {
+        Some('k') | Some('K') => (&digits[..digits.len() - 1], 1e3),
+        Some('M') => (&digits[..digits.len() - 1], 1e6),
+        Some('G') => (&digits[..digits.len() - 1], 1e9),
+        _ => (digits, 1.0),
+    };
+    digits
+        .parse::<f64>()
+        .ok()
+        .map(|number| number * unit)
+        .filter(|hz| *hz >= 1.0 && *hz <= u64::MAX as f64)
+        .map(|hz| hz as u64)
+        .ok_or_else(|| format!("\"{}\" is not a valid frequency", value))
+}
@@ -0,0 +1,306 @@
+//! Checks how programs access the registers of devices, e.g. to find bugs in drivers which happen to work with
+//! the runner but would fail on real hardware.
+//!
+//! Every device kind has a map of its registers, see [`register_map`](fn.register_map.html), and plugins can
+//! provide one with [`DevicePlugin::registers`](../plugin/trait.DevicePlugin.html#method.registers). A
+//! [`ContractChecker`](struct.ContractChecker.html) subscribes to the loads and stores of an
+//! [`EventBus`](../events/struct.EventBus.html) and reports accesses which break the map:
+//!
+//! - accesses to addresses of a device which belong to no register,
+//! - registers which are accessed partially or with a size they do not allow, e.g. a byte of a word register,
+//! - loads of write-only registers and stores to read-only ones.
+//!
+//! Bytes of buffers can be accessed with any size.
+
+use crate::config::{Device, DeviceKind};
+use crate::debugger::nearest_label;
+use crate::events::{Event, EventListener};
+use crate::{audio, disk, flash, gpio, network, plugin, power, semihosting, serial};
+use std::fmt;
+use std::io::prelude::*;
+use vex::debug::DebugInfo;
+
+/// How the program may access a register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+/// A register or buffer of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub name: &'static str,
+    /// Address relative to the start of the device.
+    pub offset: u32,
+    pub length: u32,
+    pub access: Access,
+    /// Sizes of the accesses the register allows. Accesses to a register start at its offset, accesses to a
+    /// buffer at any multiple of their size.
+    pub sizes: &'static [u32],
+    pub buffer: bool,
+}
+
+impl Register {
+    /// A word register, which is always accessed as a whole.
+    pub const fn word(name: &'static str, offset: u32, access: Access) -> Register {
+        Register {
+            name,
+            offset,
+            length: 4,
+            access,
+            sizes: &[4],
+            buffer: false,
+        }
+    }
+
+    /// A buffer of `length` bytes.
+    pub const fn buffer(name: &'static str, offset: u32, length: u32, access: Access) -> Register {
+        Register {
+            name,
+            offset,
+            length,
+            access,
+            sizes: &[1, 2, 4],
+            buffer: true,
+        }
+    }
+
+    fn contains(&self, offset: u32) -> bool {
+        offset >= self.offset && offset - self.offset < self.length
+    }
+
+    /// Whether an access of `size` bytes at `offset`, which lies within the register, is allowed.
+    fn allows(&self, offset: u32, size: u32) -> bool {
+        let start = offset - self.offset;
+        let aligned = if self.buffer {
+            start.is_multiple_of(size)
+        } else {
+            start == 0
+        };
+        aligned && self.sizes.contains(&size) && start + size <= self.length
+    }
+}
+
+/// Returns the registers of a kind of device, or none if its accesses are not checked.
+pub fn register_map(kind: DeviceKind) -> Vec<Register> {
+    use Access::*;
+    match kind {
+        // std/uart.vasm stores bytes, other programs words
+        DeviceKind::Uart => vec![Register {
+            sizes: &[1, 4],
+            ..Register::word("TX", 0, WriteOnly)
+        }],
+        DeviceKind::Semihosting => vec![
+            Register::word("OPERATION", semihosting::OPERATION, ReadWrite),
+            Register::word("ARG0", semihosting::ARG0, ReadWrite),
+            Register::word("ARG1", semihosting::ARG1, ReadWrite),
+            Register::word("ARG2", semihosting::ARG2, ReadWrite),
+            Register::word("RESULT", semihosting::RESULT, ReadOnly),
+        ],
+        DeviceKind::Audio => vec![
+            Register::word("SAMPLE", audio::SAMPLE, WriteOnly),
+            Register::word("RATE", audio::RATE, ReadWrite),
+            Register::word("CONTROL", audio::CONTROL, ReadWrite),
+            Register::word("LEVEL", audio::LEVEL, ReadOnly),
+        ],
+        DeviceKind::Serial => vec![
+            Register {
+                sizes: &[1, 4],
+                ..Register::word("TX", serial::TX, WriteOnly)
+            },
+            Register::word("RX", serial::RX, ReadOnly),
+            Register::word("STATUS", serial::STATUS, ReadOnly),
+            Register::word("NEXT", serial::NEXT, WriteOnly),
+        ],
+        DeviceKind::Network => vec![
+            Register::word("STATUS", network::STATUS, ReadOnly),
+            Register::word("STATION", network::STATION, ReadOnly),
+            Register::word("DESTINATION", network::DESTINATION, ReadWrite),
+            Register::word("SEND", network::SEND, WriteOnly),
+            Register::word("RX_LENGTH", network::RX_LENGTH, ReadOnly),
+            Register::word("RX_SOURCE", network::RX_SOURCE, ReadOnly),
+            Register::word("NEXT", network::NEXT, WriteOnly),
+            Register::word("INTERRUPT", network::INTERRUPT, ReadWrite),
+            Register::buffer("TX_BUFFER", network::TX_BUFFER, network::MTU, ReadWrite),
+            Register::buffer("RX_BUFFER", network::RX_BUFFER, network::MTU, ReadOnly),
+        ],
+        DeviceKind::Gpio => vec![
+            Register::word("DIRECTION", gpio::DIRECTION, ReadWrite),
+            Register::word("OUTPUT", gpio::OUTPUT, ReadWrite),
+            Register::word("INPUT", gpio::INPUT, ReadOnly),
+            Register::word("EDGE_ENABLE", gpio::EDGE_ENABLE, ReadWrite),
+            Register::word("PENDING", gpio::PENDING, ReadOnly),
+            Register::word("CLEAR", gpio::CLEAR, WriteOnly),
+            Register::word("INTERRUPT", gpio::INTERRUPT, ReadWrite),
+        ],
+        DeviceKind::Flash => vec![
+            Register::word("ERASE", flash::ERASE, WriteOnly),
+            Register::word("PAGE", flash::PAGE, ReadWrite),
+            Register::word("WEAR", flash::WEAR, ReadOnly),
+            Register::word("ERRORS", flash::ERRORS, ReadOnly),
+            Register::buffer("DATA", flash::DATA, flash::IMAGE_BYTES, ReadWrite),
+        ],
+        DeviceKind::Power => vec![
+            Register::word("COMMAND", power::COMMAND, WriteOnly),
+            Register::word("STATUS", power::STATUS, ReadWrite),
+            Register::word("BOOTS", power::BOOTS, ReadOnly),
+        ],
+        DeviceKind::Disk => vec![
+            Register::word("COMMAND", disk::COMMAND, WriteOnly),
+            Register::word("SECTOR", disk::SECTOR, ReadWrite),
+            Register::word("SECTORS", disk::SECTORS, ReadOnly),
+            Register::word("STATUS", disk::STATUS, ReadOnly),
+            Register::buffer("BUFFER", disk::BUFFER, disk::SECTOR_BYTES, ReadWrite),
+        ],
+        DeviceKind::Plugin(name) => {
+            plugin::find(name).map_or_else(Vec::new, |plugin| plugin.registers())
+        }
+    }
+}
+
+/// What is wrong with an access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The address belongs to no register.
+    Unmapped,
+    /// The register does not allow the size or only a part of it was accessed.
+    Size(&'static str),
+    ReadOfWriteOnly(&'static str),
+    WriteOfReadOnly(&'static str),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Unmapped => write!(f, "no register"),
+            Problem::Size(name) => write!(f, "wrong size for {}", name),
+            Problem::ReadOfWriteOnly(name) => write!(f, "read of write-only {}", name),
+            Problem::WriteOfReadOnly(name) => write!(f, "write of read-only {}", name),
+        }
+    }
+}
+
+/// An access which breaks the register map, together with the number of times it happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// Address of the instruction which accessed the device.
+    pub pc: u32,
+    pub device: Device,
+    /// Address relative to the start of the device.
+    pub offset: u32,
+    pub size: u32,
+    pub store: bool,
+    pub problem: Problem,
+    pub count: u64,
+}
+
+/// Checks the accesses to the devices of a machine against their register maps.
+pub struct ContractChecker {
+    devices: Vec<(Device, Vec<Register>)>,
+    violations: Vec<Violation>,
+}
+
+impl ContractChecker {
+    /// Creates a checker for `devices`, usually those of
+    /// [`Machine::devices`](../struct.Machine.html#method.devices). Devices without a register map are not
+    /// checked.
+    pub fn new(devices: &[Device]) -> ContractChecker {
+        ContractChecker {
+            devices: devices
+                .iter()
+                .map(|device| (*device, register_map(device.kind)))
+                .filter(|(_, registers)| !registers.is_empty())
+                .collect(),
+            violations: Vec::new(),
+        }
+    }
+
+    /// The violations, in the order they first happened.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    fn check(&mut self, pc: u32, address: u32, size: u32, store: bool) {
+        let (device, registers) = match self.devices.iter().find(|(device, _)| {
+            address >= device.address && address - device.address < device.kind.size()
+        }) {
+            Some((device, registers)) => (*device, registers),
+            None => return,
+        };
+        let offset = address - device.address;
+        let problem = match registers.iter().find(|register| register.contains(offset)) {
+            None => Problem::Unmapped,
+            Some(register) if !register.allows(offset, size) => Problem::Size(register.name),
+            Some(register) => match (register.access, store) {
+                (Access::WriteOnly, false) => Problem::ReadOfWriteOnly(register.name),
+                (Access::ReadOnly, true) => Problem::WriteOfReadOnly(register.name),
+                _ => return,
+            },
+        };
+        let same = |violation: &&mut Violation| {
+            violation.pc == pc
+                && violation.device == device
+                && violation.offset == offset
+                && violation.size == size
+                && violation.store == store
+        };
+        match self.violations.iter_mut().find(same) {
+            Some(violation) => violation.count += 1,
+            None => self.violations.push(Violation {
+                pc,
+                device,
+                offset,
+                size,
+                store,
+                problem,
+                count: 1,
+            }),
+        }
+    }
+
+    /// Writes the violations with the instructions which caused them.
+    pub fn write_report<W: Write>(
+        &self,
+        writer: &mut W,
+        debug_info: Option<&DebugInfo>,
+    ) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "{} accesses to devices broke their register maps",
+            self.violations.len()
+        )?;
+        if !self.violations.is_empty() {
+            writeln!(writer)?;
+            writeln!(writer, "  Count      Address     Access")?;
+        }
+        for violation in self.violations.iter() {
+            let mut line = format!("  {:<10} 0x{:08X}", violation.count, violation.pc);
+            if let Some(label) = debug_info.and_then(|info| nearest_label(info, violation.pc)) {
+                line.push_str(&format!(" <{}>", label));
+            }
+            writeln!(
+                writer,
+                "{}  {} of {} bytes at {}+0x{:X}: {}",
+                line,
+                if violation.store { "store" } else { "load" },
+                violation.size,
+                violation.device,
+                violation.offset,
+                violation.problem
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl EventListener for ContractChecker {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::Load { pc, address, size } => self.check(pc, address, size, false),
+            Event::Store { pc, address, size } => self.check(pc, address, size, true),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,572 @@
+//! State of an interactive debugging session, which is driven by the `vdb` command line or the `vmon` monitor.
+//!
+//! Addresses can be given as expressions with the syntax of assembler expressions, which may also contain
+//! registers (`$SP`), words in memory (`[$SP+4]`), the program counter (`pc`) and the names of symbols from
+//! the debug information, e.g. `main+8` or `$FP-4`, see [`vasm::ToolExpression`](../../vasm/struct.ToolExpression.html).
+//!
+//! Breakpoints may have a condition, an expression like `$T3 == 0 && [counter] > 4`, and the program only
+//! stops at the breakpoint if it is not zero when the breakpoint is reached. In conditions, `hits` is the number
+//! of times the breakpoint was reached, including this time, e.g. `hits == 100`. A condition which cannot be
+//! evaluated, e.g. because of an unreadable address, stops the program.
+//!
+//! Backtraces rely on the calling convention: functions are called with `JL` or `JLR` and push `$RA` before
+//! they call others. Return addresses are taken from `$RA`, unless the current function already pushed it, and
+//! the stack. A function starts at the target of a
+//! `JL` and ends before the next one, and a return address is only accepted if the
+//! instruction before it calls the function of the frame below it, so stale values are skipped.
+//!
+//! `next` steps over calls, `finish` runs until the current function returned to the caller in its backtrace,
+//! and `until` runs to an address. They stop at the return address only once the stack pointer is back where
+//! it was, so recursive calls returning to the same address are skipped, and breakpoints on the way still stop
+//! the program.
+
+use crate::config::MachineConfig;
+use crate::disk;
+use crate::flash;
+use crate::machine::{Limits, Machine, Stop};
+use crate::{DEFAULT_DEVICE, DEFAULT_RAM_SIZE};
+use num::FromPrimitive;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use vasm::{EvalContext, ToolExpression};
+use vcpu::{
+    Opcode, RegisterId, Storage, Word, OPCODE_MASK, OPCODE_OFFSET, RD_MASK, RD_OFFSET,
+    REGISTER_COUNT, RETURN_ADDRESS, RS1_MASK, RS1_OFFSET, STACK_POINTER, WORD_BYTES,
+};
+use vex::debug::{DebugInfo, SymbolKind};
+use vex::Executable;
+
+/// A program together with everything needed to start it, possibly several times.
+pub struct Session {
+    pub executable: Executable,
+    pub config: MachineConfig,
+    pub ram_size: u32,
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+}
+
+impl Session {
+    /// Creates a session, using the default RAM size and device unless `config` sets them.
+    pub fn new(
+        executable: Executable,
+        mut config: MachineConfig,
+        args: Vec<String>,
+        env: Vec<String>,
+    ) -> Session {
+        if config.devices.is_empty() {
+            config.devices.push(DEFAULT_DEVICE);
+        }
+        let ram_size = config
+            .ram_size
+            .unwrap_or_else(|| DEFAULT_RAM_SIZE.max(executable.memory_size()));
+        Session {
+            executable,
+            config,
+            ram_size,
+            args,
+            env,
+        }
+    }
+
+    /// Creates a machine which is about to execute the first instruction of the program.
+    pub fn start(&self, console: Box<dyn Write>) -> Result<Machine, String> {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        let env: Vec<&str> = self.env.iter().map(String::as_str).collect();
+        self.start_with_arguments(console, &args, &env)
+    }
+
+    /// Creates a machine like [`start`](#method.start), but passes other arguments and environment strings
+    /// than those of the session.
+    pub fn start_with_arguments(
+        &self,
+        console: Box<dyn Write>,
+        args: &[&str],
+        env: &[&str],
+    ) -> Result<Machine, String> {
+        let mut machine = match self.config.reset_vector {
+            Some(reset_vector) => Machine::boot(
+                &self.executable,
+                reset_vector,
+                self.ram_size,
+                &self.config.devices,
+                console,
+            )?,
+            None => Machine::new(
+                &self.executable,
+                self.ram_size,
+                &self.config.devices,
+                console,
+            )?,
+        };
+        machine.set_semihosting_root(self.config.semihosting_root.clone());
+        machine.set_device_change_interrupt(self.config.device_change_interrupt)?;
+        machine.processor_mut().set_profile(self.config.isa.clone());
+        if let Some(framebuffer) = self.config.framebuffer {
+            framebuffer.mount(&mut machine)?;
+        }
+        flash::load_image(
+            &machine,
+            self.config.flash_file.as_deref(),
+            self.config.flash_endurance,
+        )?;
+        disk::load_image(&machine, self.config.disk_file.as_deref())?;
+        machine.pass_arguments(args, env)?;
+        Ok(machine)
+    }
+
+    /// Saves the framebuffer of `machine`, which was started by this session, as a PNG file at `path`.
+    pub fn screenshot(&self, machine: &Machine, path: &str) -> Result<(), String> {
+        if path.is_empty() {
+            return Err("Expected the path of the screenshot".to_owned());
+        }
+        match self.config.framebuffer {
+            Some(framebuffer) => framebuffer.screenshot(machine, path),
+            None => Err("The machine has no framebuffer".to_owned()),
+        }
+    }
+}
+
+/// Most words above the stack pointer a backtrace looks at for return addresses.
+const MAX_BACKTRACE_WORDS: u32 = 4096;
+/// Most frames of a backtrace, in case of a recursion which is deeper.
+const MAX_BACKTRACE_FRAMES: usize = 64;
+
+/// Returns the nearest label of an instruction at or before `address`, e.g. `main+0x8`.
+pub(crate) fn nearest_label(debug_info: &DebugInfo, address: u32) -> Option<String> {
+    let symbol = debug_info
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.kind == SymbolKind::Instruction && symbol.address <= address)
+        .max_by_key(|symbol| symbol.address)?;
+    Some(match address - symbol.address {
+        0 => symbol.name.clone(),
+        offset => format!("{}+0x{:X}", symbol.name, offset),
+    })
+}
+
+/// Returns the function called by the instruction `word` at `address`, if it is a `JL`.
+fn call_target(word: Word, address: u32) -> Option<u32> {
+    match Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+        Some(Opcode::JL) => vasm::jump_target(word, address),
+        _ => None,
+    }
+}
+
+/// Returns whether `word` stores $RA on the stack, like `PUSH $RA`.
+fn saves_return_address(word: Word) -> bool {
+    let register = |mask: u32, offset: u32| RegisterId::from_u32((word & mask) >> offset);
+    Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) == Some(Opcode::SW)
+        && register(RD_MASK, RD_OFFSET) == Some(RETURN_ADDRESS)
+        && register(RS1_MASK, RS1_OFFSET) == Some(STACK_POINTER)
+}
+
+/// Provides the values of registers, memory and symbols to expressions, see
+/// [`vasm::ToolExpression`](../../vasm/struct.ToolExpression.html).
+pub struct MachineContext<'a> {
+    pub machine: &'a Machine,
+    pub debug_info: Option<&'a DebugInfo>,
+    /// Value of `pc`, usually the program counter.
+    pub pc: u32,
+    /// Value of `hits` in the condition of a breakpoint.
+    pub hits: Option<u64>,
+}
+
+impl<'a> MachineContext<'a> {
+    pub fn new(machine: &'a Machine, debug_info: Option<&'a DebugInfo>) -> MachineContext<'a> {
+        MachineContext {
+            machine,
+            debug_info,
+            pc: machine.processor().program_counter(),
+            hits: None,
+        }
+    }
+}
+
+impl EvalContext for MachineContext<'_> {
+    fn symbol(&self, name: &str) -> Option<u32> {
+        match name {
+            "pc" => Some(self.pc),
+            "hits" => self.hits.map(|hits| hits as u32),
+            _ => self
+                .debug_info
+                .and_then(|info| info.symbol(name))
+                .map(|symbol| symbol.address),
+        }
+    }
+
+    fn register(&self, register: RegisterId) -> Result<u32, String> {
+        Ok(self.machine.processor().register(register).u())
+    }
+
+    fn memory(&self, address: u32) -> Result<u32, String> {
+        self.machine
+            .memory()
+            .read_word(address)
+            .map_err(|_| format!("Address 0x{:08X} cannot be read", address))
+    }
+}
+
+/// A breakpoint at an instruction address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Breakpoint {
+    /// The program only stops if this condition is true.
+    pub condition: Option<String>,
+    parsed: Option<ToolExpression>,
+    /// Number of times the program reached the breakpoint, whether it stopped or not.
+    pub hits: u64,
+}
+
+/// A machine under the control of the debugger, together with its breakpoints.
+pub struct Debugger {
+    machine: Machine,
+    debug_info: Option<DebugInfo>,
+    breakpoints: BTreeMap<u32, Breakpoint>,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, debug_info: Option<DebugInfo>) -> Debugger {
+        Debugger {
+            machine,
+            debug_info,
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Replaces the machine, e.g. to restart the program, and keeps the breakpoints but resets their hits.
+    pub fn set_machine(&mut self, machine: Machine) {
+        self.machine = machine;
+        for breakpoint in self.breakpoints.values_mut() {
+            breakpoint.hits = 0;
+        }
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    /// Instruction addresses the program stops at, in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u32> + '_ {
+        self.breakpoints.keys().cloned()
+    }
+
+    pub fn has_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.contains_key(&address)
+    }
+
+    pub fn breakpoint(&self, address: u32) -> Option<&Breakpoint> {
+        self.breakpoints.get(&address)
+    }
+
+    /// Adds a breakpoint without a condition and returns false if there already was one at `address`.
+    pub fn add_breakpoint(&mut self, address: u32) -> bool {
+        if self.breakpoints.contains_key(&address) {
+            return false;
+        }
+        self.breakpoints.insert(address, Breakpoint::default());
+        true
+    }
+
+    /// Sets or removes the condition of the breakpoint at `address`, after checking that it can be parsed and
+    /// only refers to known symbols.
+    pub fn set_condition(&mut self, address: u32, condition: Option<&str>) -> Result<(), String> {
+        let parsed = match condition {
+            Some(condition) => {
+                let parsed: ToolExpression = condition.parse()?;
+                let mut context = self.context();
+                context.hits = Some(0);
+                parsed.check_symbols(&context)?;
+                Some(parsed)
+            }
+            None => None,
+        };
+        match self.breakpoints.get_mut(&address) {
+            Some(breakpoint) => {
+                breakpoint.condition = condition.map(str::to_owned);
+                breakpoint.parsed = parsed;
+                Ok(())
+            }
+            None => Err(format!("There is no breakpoint at 0x{:08X}", address)),
+        }
+    }
+
+    /// Removes a breakpoint and returns false if there was none at `address`.
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    /// Counts that the program reached `address` and returns whether it has to stop there, because there is a
+    /// breakpoint whose condition is true.
+    pub fn hit_breakpoint(&mut self, address: u32) -> bool {
+        let breakpoint = match self.breakpoints.get_mut(&address) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        breakpoint.hits += 1;
+        let breakpoint = &self.breakpoints[&address];
+        match &breakpoint.parsed {
+            Some(condition) => {
+                let mut context = self.context();
+                context.hits = Some(breakpoint.hits);
+                condition.evaluate(&context) != Ok(0)
+            }
+            None => true,
+        }
+    }
+
+    /// Executes up to `count` instructions and returns why the program stopped, if it did.
+    pub fn step(&mut self, count: u64) -> Option<Stop> {
+        (0..count).find_map(|_| self.machine.step().map(Stop::Exit))
+    }
+
+    /// Runs the program until it reaches a breakpoint whose condition is true, in which case `None` is
+    /// returned, or stops.
+    pub fn resume(&mut self, limits: &Limits) -> Option<Stop> {
+        loop {
+            let breakpoints = &self.breakpoints;
+            let stop = self
+                .machine
+                .run_until(limits, |address| breakpoints.contains_key(&address));
+            if stop.is_some() || self.hit_breakpoint(self.machine.processor().program_counter()) {
+                return stop;
+            }
+        }
+    }
+
+    /// Runs the program until it is about to execute the instruction at `address`, reaches a breakpoint whose
+    /// condition is true, in which case `None` is returned, or stops. `address` itself works like a temporary
+    /// breakpoint.
+    pub fn run_to(&mut self, address: u32, limits: &Limits) -> Option<Stop> {
+        self.run_to_frame(address, 0, limits)
+    }
+
+    /// Executes the next instruction and, if it calls a function, runs until the function returned, unless a
+    /// breakpoint is reached on the way.
+    pub fn step_over(&mut self, limits: &Limits) -> Option<Stop> {
+        let processor = self.machine.processor();
+        let pc = processor.program_counter();
+        let is_call = self.machine.instruction_at(pc).is_some_and(|word| {
+            matches!(
+                Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET),
+                Some(Opcode::JL) | Some(Opcode::JLR)
+            )
+        });
+        if !is_call {
+            return self.step(1);
+        }
+        let stack_pointer = processor.register(STACK_POINTER).u();
+        self.run_to_frame(pc.wrapping_add(WORD_BYTES), stack_pointer, limits)
+    }
+
+    /// Runs until the current function returned to its caller, which is found like in a
+    /// [`backtrace`](#method.backtrace), unless a breakpoint is reached on the way. Fails if the caller is
+    /// not known, e.g. in the outermost function.
+    pub fn step_out(&mut self, limits: &Limits) -> Result<Option<Stop>, String> {
+        let return_address = match self.backtrace().get(1) {
+            Some(address) => *address,
+            None => return Err("The caller of the current function is not known".to_owned()),
+        };
+        let stack_pointer = self.machine.processor().register(STACK_POINTER).u();
+        Ok(self.run_to_frame(return_address, stack_pointer, limits))
+    }
+
+    /// Runs until the program is about to execute the instruction at `address` with a stack pointer of at least
+    /// `stack_pointer`, so that recursive calls which return to the same address are skipped.
+    fn run_to_frame(&mut self, address: u32, stack_pointer: u32, limits: &Limits) -> Option<Stop> {
+        loop {
+            let breakpoints = &self.breakpoints;
+            let stop = self
+                .machine
+                .run_until(limits, |pc| pc == address || breakpoints.contains_key(&pc));
+            if stop.is_some() {
+                return stop;
+            }
+            let processor = self.machine.processor();
+            let pc = processor.program_counter();
+            let arrived = pc == address && processor.register(STACK_POINTER).u() >= stack_pointer;
+            if self.hit_breakpoint(pc) || arrived {
+                return None;
+            }
+        }
+    }
+
+    /// Returns the context in which expressions are evaluated.
+    pub fn context(&self) -> MachineContext<'_> {
+        MachineContext::new(&self.machine, self.debug_info.as_ref())
+    }
+
+    /// Evaluates an expression, see [`vasm::ToolExpression`](../../vasm/struct.ToolExpression.html).
+    pub fn eval(&self, expression: &str) -> Result<u32, String> {
+        vasm::evaluate_expression(expression, &self.context())
+    }
+
+    /// Names an instruction address relative to the closest label before it, e.g. `loop+0x8`.
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        nearest_label(self.debug_info.as_ref()?, address)
+    }
+
+    /// Returns the source file and line of the instruction at `address`, e.g. `main.vasm:12`.
+    pub fn source_line(&self, address: u32) -> Option<String> {
+        let info = self.debug_info.as_ref()?;
+        let entry = info.line_at(address)?;
+        let file = info
+            .files
+            .get(entry.file as usize)
+            .map_or("?", String::as_str);
+        Some(format!("{}:{}", file, entry.line))
+    }
+
+    /// Describes the instruction at `address`: its address, label, disassembly and source line.
+    pub fn describe_instruction(&self, address: u32) -> String {
+        let mut text = format!("0x{:08X}", address);
+        if let Some(name) = self.symbolize(address) {
+            text.push_str(&format!(" <{}>", name));
+        }
+        match self.machine.instruction_at(address) {
+            Some(word) => {
+                let registers = self.machine.processor().profile().registers();
+                text.push_str(&format!(
+                    "  {}",
+                    vasm::disassemble_with(word, registers).unwrap_or_else(|| "???".to_owned())
+                ));
+                if let Some(target) = vasm::jump_target(word, address) {
+                    text.push_str(&format!("  # 0x{:08X}", target));
+                    if let Some(name) = self.symbolize(target) {
+                        text.push_str(&format!(" <{}>", name));
+                    }
+                }
+            }
+            None => text.push_str("  <outside of the program>"),
+        }
+        if let Some(line) = self.source_line(address) {
+            text.push_str(&format!("  ({})", line));
+        }
+        text
+    }
+
+    /// Returns the program counter followed by the return addresses of the functions which are being executed,
+    /// innermost first.
+    pub fn backtrace(&self) -> Vec<u32> {
+        let processor = self.machine.processor();
+        let stack_pointer = processor.register(STACK_POINTER).u();
+        let memory = self.machine.memory();
+        let stack = (0..MAX_BACKTRACE_WORDS)
+            .map_while(|i| {
+                let address = stack_pointer.checked_add(i * WORD_BYTES)?;
+                memory.read_word(address).ok()
+            })
+            .collect::<Vec<_>>();
+
+        let mut functions = BTreeSet::new();
+        let instruction_count = self.machine.instructions().len() as u32 / WORD_BYTES;
+        for address in (0..instruction_count).map(|i| i * WORD_BYTES) {
+            let word = self.machine.instruction_at(address).unwrap();
+            if let Some(target) = call_target(word, address) {
+                functions.insert(target);
+            }
+        }
+        let function_of = |address: u32| functions.range(..=address).next_back().copied();
+
+        let pc = processor.program_counter();
+        let mut frames = vec![pc];
+        // Once the function pushed $RA, the same address is on the stack, and it is only taken from there.
+        let saved = function_of(pc).is_some_and(|start| {
+            (start..pc).step_by(WORD_BYTES as usize).any(|address| {
+                self.machine
+                    .instruction_at(address)
+                    .is_some_and(saves_return_address)
+            })
+        });
+        let return_address = Some(processor.register(RETURN_ADDRESS).u()).filter(|_| !saved);
+        let candidates = return_address.into_iter().chain(stack);
+        for candidate in candidates {
+            if frames.len() == MAX_BACKTRACE_FRAMES {
+                break;
+            }
+            let call = match candidate.checked_sub(WORD_BYTES) {
+                Some(call) if candidate % WORD_BYTES == 0 => call,
+                _ => continue,
+            };
+            let word = match self.machine.instruction_at(call) {
+                Some(word) => word,
+                None => continue,
+            };
+            let accepted = match Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+                Some(Opcode::JL) => {
+                    let callee = function_of(*frames.last().unwrap());
+                    callee.is_some() && call_target(word, call) == callee
+                }
+                // The target of an indirect call is not known anymore.
+                Some(Opcode::JLR) => true,
+                _ => false,
+            };
+            if accepted {
+                frames.push(candidate);
+            }
+        }
+        frames
+    }
+
+    /// Formats a backtrace with one frame per line, e.g. `#1 0x00000010 <main+0x8>  (main.vasm:5)`.
+    pub fn format_backtrace(&self) -> String {
+        let mut text = String::new();
+        for (i, address) in self.backtrace().into_iter().enumerate() {
+            text.push_str(&format!("#{:<2} 0x{:08X}", i, address));
+            if let Some(name) = self.symbolize(address) {
+                text.push_str(&format!(" <{}>", name));
+            }
+            if let Some(line) = self.source_line(address) {
+                text.push_str(&format!("  ({})", line));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Formats the program counter and the registers of the profile, four per line.
+    pub fn registers(&self) -> String {
+        let processor = self.machine.processor();
+        let registers = processor.profile().registers();
+        let mut text = format!("PC   {:08X}\n", processor.program_counter());
+        let names = (0..REGISTER_COUNT)
+            .map(|i| RegisterId::from_usize(i).unwrap())
+            .filter_map(|id| Some((id, registers.name(id)?)));
+        for (i, (id, name)) in names.enumerate() {
+            text.push_str(&format!("{:<4} {:08X}", name, processor.register(id).u()));
+            text.push(if i % 4 == 3 { '\n' } else { ' ' });
+        }
+        if !text.ends_with('\n') {
+            text.pop();
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Formats `count` words of main memory starting at `address`, four per line.
+    /// Words which cannot be read are shown as `????????`.
+    pub fn memory(&self, address: u32, count: u32) -> String {
+        let memory = self.machine.memory();
+        let mut text = String::new();
+        for i in 0..count {
+            let word_address = address.wrapping_add(i * WORD_BYTES);
+            if i % 4 == 0 {
+                text.push_str(&format!("{:08X}:", word_address));
+            }
+            match memory.read_word(word_address) {
+                Ok(word) => text.push_str(&format!(" {:08X}", word)),
+                Err(()) => text.push_str(" ????????"),
+            }
+            if i % 4 == 3 || i + 1 == count {
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
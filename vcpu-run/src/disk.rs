@@ -0,0 +1,227 @@
+//! A disk which the program reads and writes in sectors, e.g. to load a program from it with a boot ROM, see
+//! [`Machine::boot`](../struct.Machine.html#method.boot).
+//!
+//! A `disk` device has registers followed by a buffer of one sector:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `COMMAND` | Writing [`READ`](constant.READ.html) copies the selected sector into `BUFFER`, [`WRITE`](constant.WRITE.html) copies `BUFFER` into it. |
+//! | 4 | `SECTOR`  | Selects the sector of the next command. |
+//! | 8 | `SECTORS` | Number of sectors of the disk. |
+//! | 12 | `STATUS` | [`OK`](constant.OK.html) after a command which succeeded, [`FAILED`](constant.FAILED.html) after one which did not. |
+//! | 16 | `BUFFER` | [`SECTOR_BYTES`](constant.SECTOR_BYTES.html) bytes. |
+//!
+//! [`DiskImage::load`](struct.DiskImage.html#method.load) and [`DiskImage::save`](struct.DiskImage.html#method.save)
+//! read and write the sectors as a raw image, which machine files set with `disk-file`.
+//! [`boot_image`](fn.boot_image.html) creates an image which the example boot ROM `roms/boot.vasm` loads.
+
+use crate::config::Device;
+use crate::machine::{DeviceLog, DeviceWrite, Machine};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use vcpu::{Endian, Storage, StorageMut, WORD_BYTES};
+
+use byteorder::ByteOrder;
+
+/// Offset of the register which performs a command when it is written.
+pub const COMMAND: u32 = 0;
+pub const SECTOR: u32 = 4;
+pub const SECTORS: u32 = 8;
+pub const STATUS: u32 = 12;
+pub const BUFFER: u32 = 16;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = BUFFER + SECTOR_BYTES;
+
+pub const SECTOR_BYTES: u32 = 512;
+/// Number of sectors of every disk.
+pub const SECTOR_COUNT: u32 = 2048;
+/// Number of bytes of all sectors, which is the largest size of an image file.
+pub const IMAGE_BYTES: u32 = SECTOR_COUNT * SECTOR_BYTES;
+
+/// Commands of `COMMAND`, other values fail.
+pub const READ: u32 = 1;
+pub const WRITE: u32 = 2;
+
+/// Values of `STATUS`.
+pub const OK: u32 = 0;
+pub const FAILED: u32 = 1;
+
+struct State {
+    registers: [u8; BUFFER as usize],
+    buffer: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl State {
+    fn register(&self, offset: u32) -> u32 {
+        self.registers.read_word(offset).unwrap()
+    }
+
+    /// Performs `command` on the selected sector and returns the status.
+    fn perform(&mut self, command: u32) -> u32 {
+        let sector = self.register(SECTOR);
+        if sector >= SECTOR_COUNT {
+            return FAILED;
+        }
+        let start = (sector * SECTOR_BYTES) as usize;
+        let sector = &mut self.data[start..start + SECTOR_BYTES as usize];
+        match command {
+            READ => self.buffer.copy_from_slice(sector),
+            WRITE => sector.copy_from_slice(&self.buffer),
+            _ => return FAILED,
+        }
+        OK
+    }
+}
+
+/// The host side of a disk device, which the machine and the runner share.
+#[derive(Clone)]
+pub struct DiskImage {
+    device: Device,
+    state: Rc<RefCell<State>>,
+}
+
+impl DiskImage {
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Returns a copy of all sectors.
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.borrow().data.clone()
+    }
+
+    /// Reads the sectors from the image at `path`. A shorter image only fills the first sectors, the others are
+    /// zero.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let image = std::fs::read(path)
+            .map_err(|err| format!("Reading disk image \"{}\" failed: {}", path.display(), err))?;
+        self.set_contents(&image).map_err(|_| {
+            format!(
+                "Disk image \"{}\" has more than {} bytes",
+                path.display(),
+                IMAGE_BYTES
+            )
+        })
+    }
+
+    /// Replaces the first sectors with `image` and clears the others, or fails if `image` does not fit.
+    pub fn set_contents(&self, image: &[u8]) -> Result<(), ()> {
+        if image.len() > IMAGE_BYTES as usize {
+            return Err(());
+        }
+        let mut state = self.state.borrow_mut();
+        state.data[..image.len()].copy_from_slice(image);
+        for byte in state.data[image.len()..].iter_mut() {
+            *byte = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the sectors to the image at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, &self.state.borrow().data)
+            .map_err(|err| format!("Writing disk image \"{}\" failed: {}", path.display(), err))
+    }
+}
+
+/// Loads the image at `path` into the first disk device of `machine`, as machine files do with `disk-file`.
+pub fn load_image(machine: &Machine, path: Option<&Path>) -> Result<(), String> {
+    match path {
+        Some(path) => machine
+            .disks()
+            .first()
+            .ok_or_else(|| "The machine has no disk device".to_owned())?
+            .load(path),
+        None => Ok(()),
+    }
+}
+
+/// Creates a disk image which the example boot ROM loads into the RAM and jumps to: the first word is the number
+/// of bytes of `program`, which follow it. `program` is padded to whole instructions.
+pub fn boot_image(program: &[u8]) -> Vec<u8> {
+    let length = program.len().div_ceil(WORD_BYTES as usize) * WORD_BYTES as usize;
+    let mut image = vec![0; WORD_BYTES as usize + length];
+    Endian::write_u32(&mut image, length as u32);
+    image[WORD_BYTES as usize..][..program.len()].copy_from_slice(program);
+    image
+}
+
+/// The memory of a disk device, which is mounted into the address space.
+pub(crate) struct Disk {
+    image: DiskImage,
+    device_log: DeviceLog,
+}
+
+impl Disk {
+    /// Creates the memory of `device` with zeroed sectors, and the host side which shares it.
+    pub(crate) fn new(device: Device, device_log: DeviceLog) -> (Disk, DiskImage) {
+        let mut registers = [0; BUFFER as usize];
+        registers.write_word(SECTORS, SECTOR_COUNT).unwrap();
+        let image = DiskImage {
+            device,
+            state: Rc::new(RefCell::new(State {
+                registers,
+                buffer: vec![0; SECTOR_BYTES as usize],
+                data: vec![0; IMAGE_BYTES as usize],
+            })),
+        };
+        let disk = Disk {
+            image: image.clone(),
+            device_log,
+        };
+        (disk, image)
+    }
+}
+
+impl Storage for Disk {
+    fn length(&self) -> u32 {
+        SIZE
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        address
+            .checked_add(length)
+            .is_some_and(|end| end <= SIZE && (address >= BUFFER || end <= BUFFER))
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        let state = self.image.state.borrow();
+        if address >= BUFFER {
+            return state.buffer.read(address - BUFFER, size);
+        }
+        state.registers.read(address, size)
+    }
+}
+
+impl StorageMut for Disk {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        if !self.check_range(address, size) {
+            return Err(());
+        }
+        self.device_log.push(DeviceWrite {
+            device: self.image.device,
+            offset: address,
+            size,
+            value,
+        });
+        let mut state = self.image.state.borrow_mut();
+        if address >= BUFFER {
+            return state.buffer.write(address - BUFFER, size, value);
+        }
+        // the number of sectors and the status are only changed by the disk itself
+        if address >= SECTORS {
+            return Ok(());
+        }
+        state.registers.write(address, size, value)?;
+        if address == COMMAND {
+            let command = state.register(COMMAND);
+            let status = state.perform(command);
+            state.registers.write_word(STATUS, status)?;
+        }
+        Ok(())
+    }
+}
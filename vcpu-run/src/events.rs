@@ -0,0 +1,149 @@
+//! Events of the simulated microarchitecture, which models of the hardware subscribe to instead of each decoding
+//! the executed instructions themselves.
+//!
+//! An [`EventBus`](struct.EventBus.html) turns every executed instruction into the events a processor would
+//! produce for it, in the order of a pipeline:
+//!
+//! 1. [`Fetch`](enum.Event.html#variant.Fetch) of the instruction,
+//! 2. [`Load`](enum.Event.html#variant.Load) or [`Store`](enum.Event.html#variant.Store) if it accesses the
+//!    memory,
+//! 3. [`Branch`](enum.Event.html#variant.Branch) if it is a conditional branch or a jump, once its direction and
+//!    target are known,
+//! 4. [`Retire`](enum.Event.html#variant.Retire) when it is complete.
+//!
+//! Like the other observers of a run, the bus also sees the instruction which stops the processor. `HALT` retires,
+//! but an instruction which fails is only fetched. The listeners are called in the order they subscribed.
+
+use num::FromPrimitive;
+use vcpu::{
+    Opcode, Processor, RegisterId, Word, IMMEDIATE_MASK, IMMEDIATE_OFFSET, OPCODE_MASK,
+    OPCODE_OFFSET, REGISTER_COUNT, RS1_MASK, RS1_OFFSET,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The instruction `word` was fetched from `address`.
+    Fetch { address: u32, word: Word },
+    /// The instruction at `pc` read `size` bytes of memory at `address`.
+    Load { pc: u32, address: u32, size: u32 },
+    /// The instruction at `pc` wrote `size` bytes of memory at `address`.
+    Store { pc: u32, address: u32, size: u32 },
+    /// The branch or jump at `address` was resolved. `target` is where it jumps to if it is taken, and only
+    /// conditional branches (see [`Opcode::is_branch`](../../vcpu/enum.Opcode.html#method.is_branch)) can be not
+    /// taken.
+    Branch {
+        address: u32,
+        opcode: Opcode,
+        target: u32,
+        taken: bool,
+    },
+    /// The instruction `word` at `address` completed. This is the last event of every instruction.
+    Retire { address: u32, word: Word },
+}
+
+/// A model which reacts to the events of the executed instructions.
+pub trait EventListener {
+    fn notify(&mut self, event: &Event);
+}
+
+/// Publishes the events of the instructions a machine executes to the subscribed listeners, see
+/// [`Machine::run_observed`](../struct.Machine.html#method.run_observed).
+pub struct EventBus<'a> {
+    listeners: Vec<&'a mut dyn EventListener>,
+    /// The registers before the instruction which is recorded next, to compute its addresses.
+    values: [u32; REGISTER_COUNT],
+}
+
+impl<'a> EventBus<'a> {
+    /// Creates a bus for the `processor` before it executes its next instruction.
+    pub fn new(processor: &Processor) -> EventBus<'a> {
+        let mut bus = EventBus {
+            listeners: Vec::new(),
+            values: [0; REGISTER_COUNT],
+        };
+        bus.save_registers(processor);
+        bus
+    }
+
+    pub fn subscribe(&mut self, listener: &'a mut dyn EventListener) {
+        self.listeners.push(listener);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Passes an event to all listeners.
+    pub fn publish(&mut self, event: Event) {
+        for listener in self.listeners.iter_mut() {
+            listener.notify(&event);
+        }
+    }
+
+    fn save_registers(&mut self, processor: &Processor) {
+        for (i, value) in self.values.iter_mut().enumerate() {
+            *value = processor.register(RegisterId::from_usize(i).unwrap()).u();
+        }
+    }
+
+    /// Publishes the events of the instruction `word` at `address`, which the `processor` just executed.
+    pub fn record(&mut self, address: u32, word: Word, processor: &Processor) {
+        let opcode = match Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+            Some(opcode) => opcode,
+            None => return,
+        };
+        self.publish(Event::Fetch { address, word });
+        if processor
+            .state()
+            .is_some_and(|exit_code| exit_code.is_error())
+        {
+            return;
+        }
+
+        let base = self.values[((word & RS1_MASK) >> RS1_OFFSET) as usize];
+        let immediate = ((word & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as i16;
+        let target = base.wrapping_add(immediate as i32 as u32);
+        let size = access_size(opcode);
+        match opcode {
+            Opcode::LB | Opcode::LH | Opcode::LW => self.publish(Event::Load {
+                pc: address,
+                address: target,
+                size,
+            }),
+            Opcode::SB | Opcode::SH | Opcode::SW => self.publish(Event::Store {
+                pc: address,
+                address: target,
+                size,
+            }),
+            _ => {}
+        }
+
+        let next = processor.program_counter();
+        let branch = match opcode {
+            opcode if opcode.is_branch() => {
+                vasm::jump_target(word, address).map(|target| (target, next == target))
+            }
+            Opcode::JMP | Opcode::JL | Opcode::JR | Opcode::JLR => Some((next, true)),
+            _ => None,
+        };
+        if let Some((target, taken)) = branch {
+            self.publish(Event::Branch {
+                address,
+                opcode,
+                target,
+                taken,
+            });
+        }
+
+        self.publish(Event::Retire { address, word });
+        self.save_registers(processor);
+    }
+}
+
+fn access_size(opcode: Opcode) -> u32 {
+    match opcode {
+        Opcode::LB | Opcode::SB => 1,
+        Opcode::LH | Opcode::SH => 2,
+        _ => 4,
+    }
+}
@@ -0,0 +1,279 @@
+//! Expectations about the state of the machine after a program ran, written as comments in its source.
+//!
+//! Every comment starting with `EXPECT:` contains one condition, which is checked once the program stopped:
+//!
+//! ```text
+//! # EXPECT: T2 == 106
+//! # EXPECT: $V0 == -1
+//! # EXPECT: [table+4] == 0x01 0x02 0x03
+//! # EXPECT: [buffer] == "Hi\n"
+//! # EXPECT: output == "Hello, world!\n"
+//! # EXPECT: status == 129
+//! ```
+//!
+//! Registers are compared with a value, memory with a list of bytes or a string starting at an address,
+//! and `output` with everything the program wrote to the console. Values and addresses are expressions like
+//! in the debugger, so they may use symbols and registers. `status` is the status a runner would exit with,
+//! see the [crate documentation](../index.html). Unless a status is expected, the program must halt.
+
+use crate::config::MachineConfig;
+use crate::debugger::{Debugger, Session};
+use crate::machine::{Limits, Stop};
+use crate::monitor::ConsoleBuffer;
+use crate::{describe_stop, exit_status};
+use vcpu::{ExitCode, RegisterId, Storage};
+
+/// Marker of the comments which contain expectations.
+const MARKER: &str = "EXPECT:";
+
+/// Instruction limit of test programs, unless the machine sets one, so that endless loops fail.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// A register has the value of an expression.
+    Register(RegisterId, String),
+    /// The memory at the address of an expression starts with these bytes.
+    Memory(String, Vec<u8>),
+    /// The console output is exactly this.
+    Output(Vec<u8>),
+    /// The runner exits with this status.
+    Status(i32),
+}
+
+/// A condition together with the line it was written on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expectation {
+    pub line: usize,
+    pub condition: Condition,
+}
+
+/// Reads all expectations from assembly source.
+pub fn parse(source: &str) -> Result<Vec<Expectation>, String> {
+    let mut expectations = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let comment = match line.find('#') {
+            Some(start) => line[start + 1..].trim_start(),
+            None => continue,
+        };
+        if let Some(condition) = comment.strip_prefix(MARKER) {
+            let condition = parse_condition(condition.trim())
+                .map_err(|err| format!("line {}: {}", number + 1, err))?;
+            expectations.push(Expectation {
+                line: number + 1,
+                condition,
+            });
+        }
+    }
+    Ok(expectations)
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let (target, value) = text
+        .split_once("==")
+        .ok_or_else(|| format!("Expected TARGET == VALUE instead of \"{}\"", text))?;
+    let (target, value) = (target.trim(), value.trim());
+    if value.is_empty() {
+        return Err("Expected a value".to_owned());
+    }
+    if let Some(address) = target
+        .strip_prefix('[')
+        .and_then(|target| target.strip_suffix(']'))
+    {
+        return Ok(Condition::Memory(
+            address.trim().to_owned(),
+            parse_bytes(value)?,
+        ));
+    }
+    match target {
+        "output" => Ok(Condition::Output(parse_string(value)?)),
+        "status" => value
+            .parse()
+            .map(Condition::Status)
+            .map_err(|_| format!("Invalid status {}", value)),
+        _ => {
+            let name = target.strip_prefix('$').unwrap_or(target);
+            let id = name
+                .to_uppercase()
+                .parse::<RegisterId>()
+                .map_err(|_| format!("Unknown register {}", target))?;
+            Ok(Condition::Register(id, value.to_owned()))
+        }
+    }
+}
+
+/// Parses a string with the escape sequences of the assembler, or bytes separated by whitespace.
+fn parse_bytes(text: &str) -> Result<Vec<u8>, String> {
+    if text.starts_with('"') {
+        return parse_string(text);
+    }
+    text.split_whitespace()
+        .map(|byte| {
+            let value = match byte.strip_prefix("0x").or_else(|| byte.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => byte.parse(),
+            };
+            value.map_err(|_| format!("Invalid byte {}", byte))
+        })
+        .collect()
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or_else(|| format!("Invalid string {}", text))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err(format!("Unknown escape sequence in {}", text)),
+            }
+        } else {
+            c
+        };
+        let mut buffer = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Formats bytes as a string, escaping everything which is not printable ASCII.
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            b'\\' => text.push_str("\\\\"),
+            b'"' => text.push_str("\\\""),
+            0x20..=0x7E => text.push(*byte as char),
+            _ => text.push_str(&format!("\\x{:02X}", byte)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+/// Assembles and runs the program at `path` and checks its expectations.
+///
+/// Returns the failed expectations, each described with its line, or `None` if the file has no expectations.
+pub fn run_file(path: &str, config: &MachineConfig) -> Result<Option<Vec<String>>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("Reading input file \"{}\" failed: {}", path, err))?;
+    let expectations = parse(&source)?;
+    if expectations.is_empty() {
+        return Ok(None);
+    }
+    // expectations only check the behavior, so the warnings of the assembler are not reported
+    let executable = crate::load(path)?.executable;
+    let debug_info = executable.debug_info().cloned();
+    let session = Session::new(
+        executable,
+        config.clone(),
+        vec![path.to_owned()],
+        Vec::new(),
+    );
+    let console = ConsoleBuffer::default();
+    let machine = session.start(Box::new(console.clone()))?;
+    let mut debugger = Debugger::new(machine, debug_info);
+
+    let limits = Limits {
+        max_instructions: Some(
+            config
+                .limits
+                .max_instructions
+                .unwrap_or(DEFAULT_MAX_INSTRUCTIONS),
+        ),
+        ..config.limits
+    };
+    let stop = debugger.machine_mut().run(&limits);
+    Ok(Some(check(&debugger, stop, &console.take(), &expectations)))
+}
+
+/// Checks the `expectations` against the state of the machine after the program stopped.
+pub fn check(
+    debugger: &Debugger,
+    stop: Stop,
+    output: &[u8],
+    expectations: &[Expectation],
+) -> Vec<String> {
+    let machine = debugger.machine();
+    let mut failures = Vec::new();
+    let expects_status = expectations
+        .iter()
+        .any(|expectation| matches!(expectation.condition, Condition::Status(_)));
+    if !expects_status && stop != Stop::Exit(ExitCode::Halted) {
+        failures.push(describe_stop(machine, stop).unwrap());
+    }
+
+    for expectation in expectations {
+        let failure = match &expectation.condition {
+            Condition::Register(id, value) => match debugger.eval(value) {
+                Ok(value) => {
+                    let actual = machine.processor().register(*id).u();
+                    if actual != value {
+                        Some(format!(
+                            "${:?} is 0x{:08X} ({}) instead of 0x{:08X} ({})",
+                            id, actual, actual as i32, value, value as i32
+                        ))
+                    } else {
+                        None
+                    }
+                }
+                Err(err) => Some(err),
+            },
+            Condition::Memory(address, bytes) => match debugger.eval(address) {
+                Ok(start) => {
+                    let actual: Result<Vec<u8>, ()> = (0..bytes.len() as u32)
+                        .map(|i| machine.memory().read_byte(start.wrapping_add(i)))
+                        .collect();
+                    match actual {
+                        Ok(actual) if actual == *bytes => None,
+                        Ok(actual) => Some(format!(
+                            "[{}] is {} instead of {}",
+                            address,
+                            escape(&actual),
+                            escape(bytes)
+                        )),
+                        Err(()) => Some(format!("[{}] cannot be read at 0x{:08X}", address, start)),
+                    }
+                }
+                Err(err) => Some(err),
+            },
+            Condition::Output(expected) => {
+                if output != &expected[..] {
+                    Some(format!(
+                        "output is {} instead of {}",
+                        escape(output),
+                        escape(expected)
+                    ))
+                } else {
+                    None
+                }
+            }
+            Condition::Status(status) => {
+                let actual = exit_status(machine, stop);
+                if actual != *status {
+                    let mut failure = format!("status is {} instead of {}", actual, status);
+                    if let Some(reason) = describe_stop(machine, stop) {
+                        failure = format!("{} ({})", failure, reason);
+                    }
+                    Some(failure)
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(failure) = failure {
+            failures.push(format!("line {}: {}", expectation.line, failure));
+        }
+    }
+    failures
+}
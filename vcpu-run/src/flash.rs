@@ -0,0 +1,237 @@
+//! Non-volatile memory which keeps its contents in a host file between runs, e.g. for firmware which stores
+//! its settings.
+//!
+//! A `flash` device has registers followed by [`PAGES`](constant.PAGES.html) pages of
+//! [`PAGE_BYTES`](constant.PAGE_BYTES.html) bytes each:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `ERASE`  | Writing a page number sets all bytes of the page to `0xFF`. |
+//! | 4 | `PAGE`   | Selects the page which `WEAR` reports. |
+//! | 8 | `WEAR`   | Number of times the selected page was erased. |
+//! | 12 | `ERRORS` | Number of writes which tried to set bits and of erases which failed. |
+//! | 16 | `DATA`  | The pages. |
+//!
+//! Like real flash memory, writes can only clear bits: a write stores the old value AND the written one, so a
+//! page has to be erased before it can be written again. With an endurance, see
+//! [`FlashMemory::set_endurance`](struct.FlashMemory.html#method.set_endurance), pages which were erased that
+//! often are worn out and keep their contents when they are erased again.
+//!
+//! [`FlashMemory::load`](struct.FlashMemory.html#method.load) and
+//! [`FlashMemory::save`](struct.FlashMemory.html#method.save) read and write the pages as a raw image, which
+//! machine files set with `flash-file`. The wear only counts the erases since the machine was created.
+
+use crate::config::Device;
+use crate::machine::{DeviceLog, DeviceWrite, Machine};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use vcpu::{Storage, StorageMut};
+
+pub const ERASE: u32 = 0;
+pub const PAGE: u32 = 4;
+pub const WEAR: u32 = 8;
+pub const ERRORS: u32 = 12;
+pub const DATA: u32 = 16;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = DATA + IMAGE_BYTES;
+
+pub const PAGES: u32 = 64;
+pub const PAGE_BYTES: u32 = 1024;
+/// Number of bytes of all pages, which is the size of an image file.
+pub const IMAGE_BYTES: u32 = PAGES * PAGE_BYTES;
+/// Value of the bytes of an erased page.
+pub const ERASED: u8 = 0xFF;
+
+struct State {
+    registers: [u8; DATA as usize],
+    data: Vec<u8>,
+    wear: Vec<u32>,
+    endurance: Option<u32>,
+    errors: u32,
+}
+
+impl State {
+    fn register(&self, offset: u32) -> u32 {
+        self.registers.read_word(offset).unwrap()
+    }
+
+    fn erase(&mut self, page: u32) {
+        let worn_out = |wear| match self.endurance {
+            Some(endurance) => wear >= endurance,
+            None => false,
+        };
+        if page >= PAGES || worn_out(self.wear[page as usize]) {
+            self.errors += 1;
+            return;
+        }
+        self.wear[page as usize] += 1;
+        let start = (page * PAGE_BYTES) as usize;
+        for byte in self.data[start..start + PAGE_BYTES as usize].iter_mut() {
+            *byte = ERASED;
+        }
+    }
+}
+
+/// The host side of a flash device, which the machine and the runner share.
+#[derive(Clone)]
+pub struct FlashMemory {
+    device: Device,
+    state: Rc<RefCell<State>>,
+}
+
+impl FlashMemory {
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Returns a copy of all pages.
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.borrow().data.clone()
+    }
+
+    /// Number of times `page` was erased.
+    pub fn wear(&self, page: u32) -> Option<u32> {
+        self.state.borrow().wear.get(page as usize).copied()
+    }
+
+    /// Sets how often a page can be erased before it wears out, or `None` if it never does.
+    pub fn set_endurance(&self, endurance: Option<u32>) {
+        self.state.borrow_mut().endurance = endurance;
+    }
+
+    /// Reads the pages from the image at `path`. A file which does not exist leaves the pages erased, and a
+    /// shorter file only fills the first pages.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let image = match std::fs::read(path) {
+            Ok(image) => image,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(format!(
+                    "Reading flash image \"{}\" failed: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+        if image.len() > IMAGE_BYTES as usize {
+            return Err(format!(
+                "Flash image \"{}\" has more than {} bytes",
+                path.display(),
+                IMAGE_BYTES
+            ));
+        }
+        self.state.borrow_mut().data[..image.len()].copy_from_slice(&image);
+        Ok(())
+    }
+
+    /// Writes the pages to the image at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, &self.state.borrow().data)
+            .map_err(|err| format!("Writing flash image \"{}\" failed: {}", path.display(), err))
+    }
+}
+
+/// Loads the image at `path` into the first flash device of `machine` and sets its endurance, as machine
+/// files do with `flash-file` and `flash-endurance`.
+pub fn load_image(
+    machine: &Machine,
+    path: Option<&Path>,
+    endurance: Option<u32>,
+) -> Result<(), String> {
+    if path.is_none() && endurance.is_none() {
+        return Ok(());
+    }
+    let memory = machine
+        .flash_memories()
+        .first()
+        .ok_or_else(|| "The machine has no flash device".to_owned())?;
+    memory.set_endurance(endurance);
+    match path {
+        Some(path) => memory.load(path),
+        None => Ok(()),
+    }
+}
+
+/// The memory of a flash device, which is mounted into the address space.
+pub(crate) struct Flash {
+    memory: FlashMemory,
+    device_log: DeviceLog,
+}
+
+impl Flash {
+    /// Creates the memory of `device` with erased pages, and the host side which shares it.
+    pub(crate) fn new(device: Device, device_log: DeviceLog) -> (Flash, FlashMemory) {
+        let memory = FlashMemory {
+            device,
+            state: Rc::new(RefCell::new(State {
+                registers: [0; DATA as usize],
+                data: vec![ERASED; IMAGE_BYTES as usize],
+                wear: vec![0; PAGES as usize],
+                endurance: None,
+                errors: 0,
+            })),
+        };
+        let flash = Flash {
+            memory: memory.clone(),
+            device_log,
+        };
+        (flash, memory)
+    }
+}
+
+impl Storage for Flash {
+    fn length(&self) -> u32 {
+        SIZE
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        address
+            .checked_add(length)
+            .is_some_and(|end| end <= SIZE && (address >= DATA || end <= DATA))
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        let state = self.memory.state.borrow();
+        if address >= DATA {
+            return state.data.read(address - DATA, size);
+        }
+        let mut registers = state.registers;
+        let page = state.register(PAGE) as usize;
+        let wear = state.wear.get(page).copied().unwrap_or(0);
+        registers.write(WEAR, 4, wear)?;
+        registers.write(ERRORS, 4, state.errors)?;
+        registers.read(address, size)
+    }
+}
+
+impl StorageMut for Flash {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        if !self.check_range(address, size) {
+            return Err(());
+        }
+        self.device_log.push(DeviceWrite {
+            device: self.memory.device,
+            offset: address,
+            size,
+            value,
+        });
+        let mut state = self.memory.state.borrow_mut();
+        if address >= DATA {
+            let offset = address - DATA;
+            let old = state.data.read(offset, size)?;
+            if value & !old != 0 {
+                state.errors += 1;
+            }
+            return state.data.write(offset, size, old & value);
+        }
+        state.registers.write(address, size, value)?;
+        if address == ERASE {
+            let page = state.register(ERASE);
+            state.erase(page);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! A driver for frontends which show the machine in a loop of frames, e.g. a window redrawn at 60 Hz.
+//!
+//! Every call of [`FrameDriver::run_frame`](struct.FrameDriver.html#method.run_frame) applies the input which
+//! was queued since the last frame, executes one frame worth of instructions and returns what the program sent
+//! to its devices and console in the meantime. Afterwards the frontend presents the frame, e.g. by copying a
+//! framebuffer, and the memory of the machine does not change until the next frame. Input only changes between
+//! frames, so a program sees the same input for the same frames in every run.
+//!
+//! A [`Framebuffer`](struct.Framebuffer.html) is memory which the program draws into and the frontend shows,
+//! with RGBA pixels row by row. Its contents can be saved as a PNG file with
+//! [`Framebuffer::screenshot`](struct.Framebuffer.html#method.screenshot).
+
+use crate::config::parse_size;
+use crate::machine::{DeviceWrite, Limits, Machine, Stop};
+use crate::monitor::ConsoleBuffer;
+use crate::png;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use vcpu::{ExitCode, Storage, StorageMut};
+
+/// Frames per second of a GUI loop which is synchronized to a common display.
+pub const DEFAULT_FRAME_RATE: u32 = 60;
+
+/// Bytes of every pixel of the framebuffer, which are red, green, blue and alpha.
+pub const PIXEL_BYTES: u32 = 4;
+
+/// Memory which the program and the frontend share, mounted like a device.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new(size: u32) -> SharedBuffer {
+        SharedBuffer(Rc::new(RefCell::new(vec![0; size as usize])))
+    }
+
+    /// Address of the bytes, which stays valid as long as the buffer exists.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.borrow().as_ptr()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Storage for SharedBuffer {
+    fn length(&self) -> u32 {
+        self.0.borrow().length()
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        self.0.borrow().check_range(address, length)
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        self.0.borrow().read(address, size)
+    }
+}
+
+impl StorageMut for SharedBuffer {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        self.0.borrow_mut().write(address, size, value)
+    }
+}
+
+/// Placement and size of the framebuffer in the address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub address: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Framebuffer {
+    pub fn size(&self) -> u32 {
+        self.width * self.height * PIXEL_BYTES
+    }
+
+    /// Mounts the pixels into the memory of `machine` and returns them.
+    pub fn mount(&self, machine: &mut Machine) -> Result<SharedBuffer, String> {
+        let pixels = SharedBuffer::new(self.size());
+        machine
+            .memory_mut()
+            .mount(self.address, "framebuffer", pixels.clone())
+            .map_err(|_| "The framebuffer overlaps the RAM or a device".to_owned())?;
+        Ok(pixels)
+    }
+
+    /// Returns the pixels which are currently in the memory of `machine`, wherever the framebuffer is mounted.
+    pub fn capture(&self, machine: &Machine) -> Result<Vec<u8>, String> {
+        let memory = machine.memory();
+        (0..self.size())
+            .map(|offset| {
+                let address = self.address.wrapping_add(offset);
+                memory
+                    .read_byte(address)
+                    .map_err(|_| format!("Address 0x{:08X} cannot be read", address))
+            })
+            .collect()
+    }
+
+    /// Saves the current pixels as a PNG file at `path`.
+    pub fn screenshot<P: AsRef<Path>>(&self, machine: &Machine, path: P) -> Result<(), String> {
+        png::write(path, self.width, self.height, &self.capture(machine)?)
+    }
+}
+
+/// Parses a framebuffer like `0x10000:320x200`.
+impl FromStr for Framebuffer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid framebuffer \"{}\", expected ADDRESS:WIDTHxHEIGHT",
+                s
+            )
+        };
+        let (address, size) = s.split_once(':').ok_or_else(invalid)?;
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let framebuffer = Framebuffer {
+            address: parse_size(address)?,
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+        };
+        let pixels = u64::from(framebuffer.width) * u64::from(framebuffer.height);
+        if pixels == 0 || pixels * u64::from(PIXEL_BYTES) > u64::from(u32::MAX) {
+            return Err(invalid());
+        }
+        Ok(framebuffer)
+    }
+}
+
+/// What happened during a frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Number of the frame, starting at 0.
+    pub number: u64,
+    /// Instructions executed during the frame.
+    pub executed: u64,
+    /// The exit code if the program stopped, or `None` if it is still running.
+    pub exit_code: Option<ExitCode>,
+    /// Output to the console during the frame, if the driver has the console of the machine.
+    pub console: Vec<u8>,
+    /// Writes to the devices during the frame.
+    pub device_writes: Vec<DeviceWrite>,
+}
+
+/// A value stored into memory before the next frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Input {
+    address: u32,
+    size: u32,
+    value: u32,
+}
+
+/// Runs a machine one frame at a time.
+pub struct FrameDriver {
+    instructions_per_frame: u64,
+    frames: u64,
+    console: Option<ConsoleBuffer>,
+    inputs: Vec<Input>,
+}
+
+impl FrameDriver {
+    /// Creates a driver which executes at most `instructions_per_frame` instructions in every frame.
+    pub fn new(instructions_per_frame: u64) -> FrameDriver {
+        FrameDriver {
+            instructions_per_frame: instructions_per_frame.max(1),
+            frames: 0,
+            console: None,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Creates a driver for a machine whose processor runs at `clock_hz` instructions per second, with
+    /// `frame_rate` frames per second.
+    pub fn for_clock(clock_hz: u64, frame_rate: u32) -> FrameDriver {
+        FrameDriver::new(clock_hz / u64::from(frame_rate.max(1)))
+    }
+
+    pub fn instructions_per_frame(&self) -> u64 {
+        self.instructions_per_frame
+    }
+
+    /// Number of frames run so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Sets the console the machine writes to, whose output is then returned with every frame.
+    pub fn set_console(&mut self, console: Option<ConsoleBuffer>) {
+        self.console = console;
+    }
+
+    /// Stores `value` with `size` bytes at `address` before the next frame, e.g. into the register of an
+    /// input device. Inputs are stored in the order they were queued.
+    pub fn queue_input(&mut self, address: u32, size: u32, value: u32) {
+        self.inputs.push(Input {
+            address,
+            size,
+            value,
+        });
+    }
+
+    /// Applies the queued input and runs the next frame, which ends early if the program stops.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<Frame, String> {
+        for input in self.inputs.drain(..) {
+            machine
+                .memory_mut()
+                .write(input.address, input.size, input.value)
+                .map_err(|_| format!("Address 0x{:08X} cannot be written", input.address))?;
+        }
+
+        let device_log = machine.device_log();
+        let start = machine.executed();
+        let limits = Limits {
+            max_instructions: Some(start.saturating_add(self.instructions_per_frame)),
+            ..Limits::default()
+        };
+        let exit_code = match machine.run(&limits) {
+            Stop::Exit(exit_code) => Some(exit_code),
+            Stop::InstructionLimit | Stop::Timeout => None,
+        };
+
+        let frame = Frame {
+            number: self.frames,
+            executed: machine.executed() - start,
+            exit_code,
+            console: self
+                .console
+                .as_ref()
+                .map_or_else(Vec::new, ConsoleBuffer::take),
+            device_writes: device_log.take(),
+        };
+        self.frames += 1;
+        Ok(frame)
+    }
+}
@@ -0,0 +1,152 @@
+//! Text dumps of the state of a machine, which are compared with golden files for regression tests.
+//!
+//! A dump has one line for the state of the processor, the program counter, the number of executed instructions
+//! and every register, followed by the selected memory ranges with 16 bytes per line:
+//!
+//! ```text
+//! state Halted
+//! pc 0x0000000C
+//! executed 4
+//! $ZERO 0x00000000
+//! ...
+//! memory 0x00000100..0x00000110
+//! 0x00000100 48 69 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+//! ```
+//!
+//! The format never depends on anything but the machine, so two dumps only differ where the machines do.
+//! Bytes which cannot be read are written as `??`.
+
+use crate::machine::Machine;
+use crate::monitor::MEMORY_LINE_BYTES;
+use num::FromPrimitive;
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+use vcpu::{RegisterId, Storage, REGISTER_COUNT};
+
+/// Environment variable which makes [`compare`](fn.compare.html) write the golden files instead of reading them.
+pub const UPDATE_VARIABLE: &str = "VCPU_UPDATE_GOLDEN";
+
+/// Returns the dump of the processor of `machine` and the memory in `ranges`.
+pub fn dump(machine: &Machine, ranges: &[Range<u32>]) -> String {
+    let processor = machine.processor();
+    let mut text = String::new();
+    match processor.state() {
+        Some(exit_code) => writeln!(text, "state {:?}", exit_code),
+        None => writeln!(text, "state Running"),
+    }
+    .unwrap();
+    writeln!(text, "pc 0x{:08X}", processor.program_counter()).unwrap();
+    writeln!(text, "executed {}", machine.executed()).unwrap();
+    for i in 0..REGISTER_COUNT {
+        let id = RegisterId::from_usize(i).unwrap();
+        writeln!(text, "${:?} 0x{:08X}", id, processor.register(id).u()).unwrap();
+    }
+
+    for range in ranges {
+        writeln!(text, "memory 0x{:08X}..0x{:08X}", range.start, range.end).unwrap();
+        let mut address = range.start;
+        while address < range.end {
+            write!(text, "0x{:08X}", address).unwrap();
+            let end = range.end.min(address.saturating_add(MEMORY_LINE_BYTES));
+            for byte_address in address..end {
+                match machine.memory().read_byte(byte_address) {
+                    Ok(byte) => write!(text, " {:02X}", byte),
+                    Err(()) => write!(text, " ??"),
+                }
+                .unwrap();
+            }
+            text.push('\n');
+            address = end;
+        }
+    }
+    text
+}
+
+/// Compares `actual` with the golden file at `path`.
+///
+/// If the environment variable [`UPDATE_VARIABLE`](constant.UPDATE_VARIABLE.html) is set, the golden file is
+/// written instead. Otherwise the error lists every line which differs, with the line of the golden file
+/// marked with `-` and the actual line with `+`.
+pub fn compare<P: AsRef<Path>>(actual: &str, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_VARIABLE).is_some() {
+        return std::fs::write(path, actual)
+            .map_err(|err| format!("Writing golden file \"{}\" failed: {}", path.display(), err));
+    }
+    let expected = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "Reading golden file \"{}\" failed: {} (set {} to create it)",
+            path.display(),
+            err,
+            UPDATE_VARIABLE
+        )
+    })?;
+    let differences = diff(&expected, actual);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Dump differs from golden file \"{}\" (set {} to update it):\n{}",
+            path.display(),
+            UPDATE_VARIABLE,
+            differences
+        ))
+    }
+}
+
+/// Compares the PNG file `actual`, e.g. a screenshot encoded with [`png::encode`](../png/fn.encode.html), with
+/// the golden image at `path`. Like for [`compare`](fn.compare.html), setting
+/// [`UPDATE_VARIABLE`](constant.UPDATE_VARIABLE.html) writes the golden image instead.
+pub fn compare_image<P: AsRef<Path>>(actual: &[u8], path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_VARIABLE).is_some() {
+        return std::fs::write(path, actual).map_err(|err| {
+            format!(
+                "Writing golden image \"{}\" failed: {}",
+                path.display(),
+                err
+            )
+        });
+    }
+    let expected = std::fs::read(path).map_err(|err| {
+        format!(
+            "Reading golden image \"{}\" failed: {} (set {} to create it)",
+            path.display(),
+            err,
+            UPDATE_VARIABLE
+        )
+    })?;
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!(
+            "Image differs from golden image \"{}\" (set {} to update it)",
+            path.display(),
+            UPDATE_VARIABLE
+        ))
+    }
+}
+
+/// Lists the lines which differ between `expected` and `actual`, which are compared line by line since the
+/// lines of dumps are always in the same order.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut expected = expected.lines();
+    let mut actual = actual.lines();
+    let mut text = String::new();
+    for number in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => break,
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    writeln!(text, "{:>5} - {}", number, expected).unwrap();
+                }
+                if let Some(actual) = actual {
+                    writeln!(text, "{:>5} + {}", number, actual).unwrap();
+                }
+            }
+        }
+    }
+    text
+}
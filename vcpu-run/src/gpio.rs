@@ -0,0 +1,161 @@
+//! General purpose I/O pins, e.g. for firmware which blinks LEDs, reads buttons or bit-bangs a protocol.
+//!
+//! A `gpio` device controls 32 pins, one per bit of its registers:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `DIRECTION` | Pins whose bit is set are outputs, the others inputs. All pins are inputs after reset. |
+//! | 4 | `OUTPUT`    | Levels the program drives on its output pins. |
+//! | 8 | `INPUT`     | Levels of all pins, the driven ones for outputs and the ones of the host for inputs. |
+//! | 12 | `EDGE_ENABLE` | Input pins whose changes are latched in `PENDING`. |
+//! | 16 | `PENDING`  | Input pins which changed since their bit was cleared. |
+//! | 20 | `CLEAR`    | Writing clears the set bits in `PENDING`. |
+//! | 24 | `INTERRUPT` | Interrupt line which is raised while a bit of `PENDING` is set, [`NO_INTERRUPT`](../constant.NO_INTERRUPT.html) by default. |
+//!
+//! Programs either poll `PENDING` or enable the line in `INTERRUPT`, whose handler writes the pins it handled
+//! to `CLEAR`, see [`ControlRegisters`](../../vcpu/struct.ControlRegisters.html). `INPUT`, `PENDING` and the
+//! interrupt line are updated by the runner before the next instruction.
+//!
+//! The host drives the input pins with [`GpioPins::set_input`](struct.GpioPins.html#method.set_input) and is
+//! notified of the output pins with [`GpioPins::on_change`](struct.GpioPins.html#method.on_change).
+
+use crate::config::Device;
+use byteorder::ByteOrder;
+use std::cell::RefCell;
+use std::rc::Rc;
+use vcpu::Endian;
+
+pub const DIRECTION: u32 = 0;
+pub const OUTPUT: u32 = 4;
+pub const INPUT: u32 = 8;
+pub const EDGE_ENABLE: u32 = 12;
+pub const PENDING: u32 = 16;
+pub const CLEAR: u32 = 20;
+pub const INTERRUPT: u32 = 24;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = 28;
+
+/// Number of pins of a device.
+pub const PINS: u32 = 32;
+
+#[derive(Default)]
+struct State {
+    direction: u32,
+    output: u32,
+    /// Levels the host drives on the pins, which only matter for inputs.
+    input: u32,
+    edge_enable: u32,
+    pending: u32,
+    /// `INPUT` and `PENDING` as they were last stored into the memory of the device.
+    published: Option<(u32, u32)>,
+}
+
+impl State {
+    fn levels(&self) -> u32 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+}
+
+/// Called with the pin and its new level whenever an output pin changes.
+pub type PinCallback = Box<dyn FnMut(u32, bool)>;
+
+/// The host side of a GPIO device, which the machine and the simulated hardware share.
+#[derive(Clone)]
+pub struct GpioPins {
+    device: Device,
+    state: Rc<RefCell<State>>,
+    callbacks: Rc<RefCell<Vec<PinCallback>>>,
+}
+
+impl GpioPins {
+    pub(crate) fn new(device: Device) -> GpioPins {
+        GpioPins {
+            device,
+            state: Rc::default(),
+            callbacks: Rc::default(),
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Levels of all pins, like the `INPUT` register.
+    pub fn levels(&self) -> u32 {
+        self.state.borrow().levels()
+    }
+
+    /// Input pins which changed since the program cleared them, like the `PENDING` register.
+    pub fn pending(&self) -> u32 {
+        self.state.borrow().pending
+    }
+
+    /// Pins the program configured as outputs.
+    pub fn outputs(&self) -> u32 {
+        self.state.borrow().direction
+    }
+
+    /// Level of a single pin.
+    pub fn level(&self, pin: u32) -> bool {
+        pin < PINS && self.levels() & (1 << pin) != 0
+    }
+
+    /// Drives `pin` to `level` from the host. This only changes the level of the pin while it is an input.
+    pub fn set_input(&self, pin: u32, level: bool) {
+        if pin >= PINS {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        let before = state.levels();
+        if level {
+            state.input |= 1 << pin;
+        } else {
+            state.input &= !(1 << pin);
+        }
+        let changed = (before ^ state.levels()) & !state.direction;
+        state.pending |= changed & state.edge_enable;
+    }
+
+    /// Registers a callback for the changes of the output pins.
+    pub fn on_change<F: FnMut(u32, bool) + 'static>(&self, callback: F) {
+        self.callbacks.borrow_mut().push(Box::new(callback));
+    }
+
+    /// Handles a write of the program to a register of the device.
+    pub(crate) fn on_write(&self, memory: &[u8], address: u32) {
+        let value = Endian::read_u32(&memory[(address & !3) as usize..]);
+        let (before, after, outputs) = {
+            let mut state = self.state.borrow_mut();
+            let before = state.levels();
+            match address & !3 {
+                DIRECTION => state.direction = value,
+                OUTPUT => state.output = value,
+                EDGE_ENABLE => state.edge_enable = value,
+                CLEAR => state.pending &= !value,
+                _ => {}
+            }
+            (before, state.levels(), state.direction)
+        };
+        // the borrow ends before the callbacks, which may drive the inputs in response
+        let changed = (before ^ after) & outputs;
+        if changed != 0 {
+            let mut callbacks = self.callbacks.borrow_mut();
+            for pin in (0..PINS).filter(|pin| changed & (1 << pin) != 0) {
+                for callback in callbacks.iter_mut() {
+                    callback(pin, after & (1 << pin) != 0);
+                }
+            }
+        }
+    }
+
+    /// Returns the new `INPUT` and `PENDING` if they changed since they were returned the last time.
+    pub(crate) fn registers(&self) -> Option<(u32, u32)> {
+        let mut state = self.state.borrow_mut();
+        let registers = (state.levels(), state.pending);
+        if state.published == Some(registers) {
+            return None;
+        }
+        state.published = Some(registers);
+        Some(registers)
+    }
+}
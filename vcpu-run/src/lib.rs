@@ -0,0 +1,156 @@
+//! Runs VCPU programs from vexfiles, ELF files or assembly source on a simulated machine.
+//!
+//! The program gets a RAM starting at address 0 and the devices of the machine, which are read from a machine file
+//! (see the [`config`](config/index.html) module) and can be changed on the command line. Unless any devices are
+//! configured, there is a UART at `0xFFFF0000`, the address used by `std/uart.vasm`.
+//! The stack pointer starts at the end of the RAM, below the command line arguments and environment strings
+//! passed to the program (see `std/args.vasm`). The first argument is the path of the program.
+//! With a reset vector (`--reset-vector` or `reset-vector` in the machine file), the program is a boot ROM instead,
+//! which runs from its address and can load the actual program from a [`disk`](disk/index.html) into the RAM,
+//! like the example `roms/boot.vasm` (see [`Machine::boot`](struct.Machine.html#method.boot)).
+//!
+//! If the program halts or shuts the machine down with a [`power`](power/index.html) device, runners exit with the
+//! lowest byte of `$V0` as its status.
+//! If it stops because of an error, the status is 128 plus the number of
+//! the exit code, e.g. 129 for a division by zero. Programs which exceed the instruction limit or the timeout
+//! are stopped with status 124, and errors of the runner itself exit with status 125.
+//!
+//! This crate contains the `vcpu-run` runner, the `vdb` debugger, the `vmon` terminal monitor, the `vremote`
+//! server for the [`remote`](remote/index.html) protocol and the `vasm-test` runner for programs with
+//! [`expect`](expect/index.html)ations, which all share the [`Machine`](struct.Machine.html).
+
+pub mod audio;
+pub mod clock;
+pub mod config;
+pub mod contract;
+pub mod debugger;
+pub mod disk;
+pub mod events;
+pub mod expect;
+pub mod flash;
+pub mod frame;
+pub mod golden;
+pub mod gpio;
+pub mod loader;
+mod machine;
+pub mod monitor;
+pub mod network;
+pub mod pipeline;
+pub mod plugin;
+pub mod png;
+pub mod pool;
+pub mod power;
+pub mod predictor;
+pub mod profiler;
+pub mod remote;
+pub mod script;
+pub mod semihosting;
+pub mod serial;
+pub mod statistics;
+pub mod taint;
+#[cfg(test)]
+mod test;
+pub mod trace;
+
+use config::{Device, DeviceKind};
+pub use machine::{DeviceLog, DeviceWrite, Limits, Machine, Stop};
+use vcpu::{enum_to_u32, ExitCode, RegisterId};
+use vex::Executable;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+
+/// RAM size used unless the program needs more.
+pub const DEFAULT_RAM_SIZE: u32 = 1 << 20;
+/// Device used if the machine has no other devices.
+pub const DEFAULT_DEVICE: Device = Device {
+    kind: DeviceKind::Uart,
+    address: 0xFFFF_0000,
+};
+
+/// Value of the `INTERRUPT` register of a device which raises no interrupt, which devices start with.
+pub const NO_INTERRUPT: u32 = 0xFFFF_FFFF;
+
+/// Status for programs which were stopped because they exceeded a limit.
+pub const LIMIT_STATUS: i32 = 124;
+/// Status for errors of the runner, which are not caused by the program.
+pub const ERROR_STATUS: i32 = 125;
+/// Added to the exit code of programs which stop because of an error.
+pub const FAULT_STATUS: i32 = 128;
+
+/// An executable read by [`load`](fn.load.html).
+pub struct Loaded {
+    pub executable: Executable,
+    /// Warnings of the assembler if the file was assembly source, which runners print.
+    pub warnings: Vec<vasm::Warning>,
+}
+
+/// Reads the executable at `path`, which is assembled first if it is neither a vexfile nor an ELF file.
+pub fn load(path: &str) -> Result<Loaded, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|err| format!("Reading input file \"{}\" failed: {}", path, err))?;
+    let executable = if bytes.starts_with(vex::MAGIC) {
+        Executable::from_bytes(&bytes)
+    } else if bytes.starts_with(ELF_MAGIC) {
+        vex::elf::read(&mut &bytes[..])
+    } else {
+        return assemble(path, &bytes);
+    };
+    executable
+        .map(|executable| Loaded {
+            executable,
+            warnings: Vec::new(),
+        })
+        .map_err(|err| format!("Reading input file \"{}\" failed: {}", path, err))
+}
+
+fn assemble(path: &str, bytes: &[u8]) -> Result<Loaded, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| {
+        format!(
+            "Input file \"{}\" is neither an executable nor assembly source",
+            path
+        )
+    })?;
+    let source = vasm::Source { name: path, text };
+    let assembly = vasm::assemble_sources(&[source], &vasm::Options::default())
+        .map_err(|err| format!("Parsing input failed:\n{}", err))?;
+    let mut executable = assembly.executable;
+    executable.set_debug_info(Some(assembly.debug_info));
+    Ok(Loaded {
+        executable,
+        warnings: assembly.warnings,
+    })
+}
+
+/// Returns the status runners exit with after the program stopped.
+pub fn exit_status(machine: &Machine, stop: Stop) -> i32 {
+    match stop {
+        Stop::Exit(ExitCode::Halted) => {
+            (machine.processor().register(RegisterId::V0).u() & 0xFF) as i32
+        }
+        Stop::InstructionLimit | Stop::Timeout => LIMIT_STATUS,
+        Stop::Exit(exit_code) => FAULT_STATUS + enum_to_u32(exit_code) as i32,
+    }
+}
+
+/// Describes why the program stopped, unless it halted.
+pub fn describe_stop(machine: &Machine, stop: Stop) -> Option<String> {
+    let reason = match stop {
+        Stop::Exit(ExitCode::Halted) => return None,
+        Stop::InstructionLimit => format!(
+            "executed {} instructions without halting",
+            machine.executed()
+        ),
+        Stop::Timeout => format!("timed out after {} instructions", machine.executed()),
+        Stop::Exit(exit_code) => {
+            match machine.processor().fault().and_then(|fault| fault.address) {
+                Some(address) => format!("{:?} (address 0x{:08X})", exit_code, address),
+                None => format!("{:?}", exit_code),
+            }
+        }
+    };
+    Some(format!(
+        "Program stopped at 0x{:08X}: {}",
+        machine.processor().program_counter(),
+        reason
+    ))
+}
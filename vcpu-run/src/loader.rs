@@ -0,0 +1,123 @@
+//! Loads the sections of an executable into memory and prepares the processor to run it.
+//!
+//! [`load`](fn.load.html) stores every section of an [`Executable`](../../vex/struct.Executable.html) at its
+//! address, fills zero-fill sections (bss) with zeros and checks that each of them fits into the memory it lands
+//! in, e.g. the RAM of a [`Machine`](../struct.Machine.html). It then sets the program counter to the entry point
+//! and the stack pointer to the top of the stack, and returns a [`Manifest`](struct.Manifest.html) of where
+//! everything went, which tools can show or check against.
+
+use std::fmt;
+use vcpu::{Processor, StorageMut, STACK_POINTER};
+use vex::{Executable, Section, SectionFlags};
+
+/// Where a section was loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub address: u32,
+    pub size: u32,
+    pub flags: SectionFlags,
+}
+
+impl Placement {
+    /// Whether `address` lies within the section.
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.address && address - self.address < self.size
+    }
+}
+
+/// What [`load`](fn.load.html) put where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub entry_point: u32,
+    pub stack_pointer: u32,
+    /// The sections in the order of the executable.
+    pub sections: Vec<Placement>,
+}
+
+impl Manifest {
+    /// Returns the section which `address` belongs to.
+    pub fn section_at(&self, address: u32) -> Option<&Placement> {
+        self.sections
+            .iter()
+            .find(|placement| placement.contains(address))
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "entry point 0x{:08X}", self.entry_point)?;
+        writeln!(f, "stack pointer 0x{:08X}", self.stack_pointer)?;
+        for placement in self.sections.iter() {
+            write!(
+                f,
+                "0x{:08X}-0x{:08X} {:>10} bytes",
+                placement.address,
+                u64::from(placement.address) + u64::from(placement.size),
+                placement.size
+            )?;
+            if placement.flags.read_only {
+                write!(f, " read-only")?;
+            }
+            if placement.flags.zero_fill {
+                write!(f, " zero-fill")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads `executable` into `memory` and sets up `processor` to run it with the stack starting at
+/// `stack_pointer`.
+///
+/// # Errors
+/// Returns an error if a section does not fit into the memory at its address or the memory rejects the stores,
+/// e.g. because it is read-only. The sections before it have been stored already.
+pub fn load(
+    executable: &Executable,
+    memory: &mut dyn StorageMut,
+    processor: &mut Processor,
+    stack_pointer: u32,
+) -> Result<Manifest, String> {
+    let sections = place_sections(executable.sections(), memory)?;
+    processor.set_program_counter(executable.entry_point());
+    processor.register_mut(STACK_POINTER).set_u(stack_pointer);
+    Ok(Manifest {
+        entry_point: executable.entry_point(),
+        stack_pointer,
+        sections,
+    })
+}
+
+/// Stores `sections` at their addresses, without touching the processor, e.g. to load the data of a program
+/// again.
+pub fn place_sections(
+    sections: &[Section],
+    memory: &mut dyn StorageMut,
+) -> Result<Vec<Placement>, String> {
+    sections
+        .iter()
+        .map(|section| {
+            let placement = Placement {
+                address: section.address(),
+                size: section.size(),
+                flags: section.flags(),
+            };
+            if placement.size > 0 && !memory.check_range(placement.address, placement.size) {
+                return Err(format!(
+                    "The section at 0x{:08X} with {} bytes does not fit into the memory",
+                    placement.address, placement.size
+                ));
+            }
+            let bytes = section.bytes();
+            for offset in 0..placement.size {
+                let address = placement.address + offset;
+                let byte = bytes.get(offset as usize).copied().unwrap_or(0);
+                memory
+                    .write_byte(address, byte)
+                    .map_err(|_| format!("Cannot store the section at 0x{:08X}", address))?;
+            }
+            Ok(placement)
+        })
+        .collect()
+}
@@ -0,0 +1,1077 @@
+use crate::audio::{self, AudioOutput};
+use crate::clock::{ClockSource, SystemClock};
+use crate::config::{Device, DeviceKind};
+use crate::disk::{Disk, DiskImage};
+use crate::flash::{Flash, FlashMemory};
+use crate::gpio::{self, GpioPins};
+use crate::loader::{self, Manifest};
+use crate::network::{self, NetworkPort, UdpLink};
+use crate::plugin::{self, PluginMemory};
+use crate::power::{self, Command};
+use crate::semihosting::{self, Request, Semihost};
+use crate::serial::{self, SerialPort, TcpBridge};
+use crate::NO_INTERRUPT;
+use byteorder::ByteOrder;
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use vcpu::*;
+use vex::{Executable, Section};
+
+/// Number of instructions between two checks of the timeout, since reading the clock is slow.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 12;
+/// Number of slices per second in which [`Machine::run_realtime`](struct.Machine.html#method.run_realtime)
+/// executes the instructions, and sleeps between them if it is ahead of the clock.
+const REALTIME_SLICES: u64 = 100;
+/// Longest delay which [`Machine::run_realtime`](struct.Machine.html#method.run_realtime) catches up on, so
+/// that the program does not run at full speed for a long time after the host was suspended.
+const MAX_REALTIME_LAG: Duration = Duration::from_millis(250);
+
+/// Limits which stop a program that runs for too long.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+/// Why a program stopped running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The processor stopped by itself.
+    Exit(ExitCode),
+    /// The program executed the maximum number of instructions without stopping.
+    InstructionLimit,
+    /// The program ran longer than the timeout.
+    Timeout,
+}
+
+/// A value the program stored into a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceWrite {
+    pub device: Device,
+    /// Address relative to the start of the device.
+    pub offset: u32,
+    /// Number of bytes written.
+    pub size: u32,
+    pub value: u32,
+}
+
+/// Writes to the devices of a machine, which are only recorded once the log was requested with
+/// [`Machine::device_log`](struct.Machine.html#method.device_log).
+#[derive(Clone, Default)]
+pub struct DeviceLog(Rc<RefCell<Option<Vec<DeviceWrite>>>>);
+
+impl DeviceLog {
+    pub(crate) fn push(&self, write: DeviceWrite) {
+        if let Some(writes) = self.0.borrow_mut().as_mut() {
+            writes.push(write);
+        }
+    }
+
+    /// Whether any writes were recorded since they were taken the last time.
+    pub fn has_writes(&self) -> bool {
+        self.0
+            .borrow()
+            .as_ref()
+            .is_some_and(|writes| !writes.is_empty())
+    }
+
+    /// Removes and returns the writes recorded so far.
+    pub fn take(&self) -> Vec<DeviceWrite> {
+        self.0
+            .borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+/// A processor together with the memory and devices a program runs on.
+pub struct Machine {
+    processor: Processor,
+    memory: CompositeMemory,
+    instructions: ProgramMemory,
+    /// Whether the instructions are fetched from the memory instead, since the machine boots from a ROM.
+    fetch_from_memory: bool,
+    /// End of the memory used by the program itself, which arguments must not overwrite.
+    program_end: u32,
+    ram_size: u32,
+    entry_point: u32,
+    /// The sections of the program, which a reboot loads again.
+    sections: Vec<Section>,
+    manifest: Manifest,
+    /// Arguments and environment strings passed to the program, which a reboot passes again.
+    arguments: Option<(Vec<String>, Vec<String>)>,
+    executed: u64,
+    clock: Rc<dyn ClockSource>,
+    devices: Vec<Device>,
+    /// Output of the UARTs.
+    console: Rc<RefCell<Box<dyn Write>>>,
+    device_log: DeviceLog,
+    semihost: Semihost,
+    /// Operation requested from a semihosting device by the last instruction.
+    semihosting_request: Rc<Cell<Option<Request>>>,
+    audio_outputs: Vec<AudioOutput>,
+    serial_ports: Vec<SerialPort>,
+    /// Connections of the serial ports which listen for clients, with the index of their port.
+    serial_bridges: Vec<(usize, TcpBridge)>,
+    network_ports: Vec<NetworkPort>,
+    /// UDP links of the network devices, with the index of their port.
+    network_links: Vec<(usize, UdpLink)>,
+    gpio_pins: Vec<GpioPins>,
+    flash_memories: Vec<FlashMemory>,
+    disks: Vec<DiskImage>,
+    /// Command written to a power device by the last instruction.
+    power_command: Rc<Cell<Option<Command>>>,
+    power_devices: Vec<Device>,
+    boots: u32,
+    /// Interrupt line which is raised when a device is attached or detached.
+    device_change_line: Option<u32>,
+}
+
+impl Machine {
+    /// Loads `executable` into `ram_size` bytes of RAM, which start at address 0, and mounts the `devices`.
+    ///
+    /// All UARTs and the stdout of the semihosting devices share the `console`. The program starts at its entry point, with the stack pointer
+    /// at the end of the RAM.
+    pub fn new(
+        executable: &Executable,
+        ram_size: u32,
+        devices: &[Device],
+        console: Box<dyn Write>,
+    ) -> Result<Machine, String> {
+        if ram_size < executable.memory_size() {
+            return Err(format!(
+                "The program needs {} bytes of RAM, but only {} are available",
+                executable.memory_size(),
+                ram_size
+            ));
+        }
+
+        let mut memory = CompositeMemory::new();
+        memory
+            .mount(0, "ram", vec![0u8; ram_size as usize])
+            .unwrap();
+        let mut processor = Processor::new();
+        let manifest = loader::load(executable, &mut memory, &mut processor, ram_size)?;
+
+        let console = Rc::new(RefCell::new(console));
+        let mut machine = Machine {
+            processor,
+            memory,
+            instructions: ProgramMemory::new(executable.instructions().to_vec()),
+            fetch_from_memory: false,
+            program_end: executable.memory_size(),
+            ram_size,
+            entry_point: executable.entry_point(),
+            sections: executable.sections().to_vec(),
+            manifest,
+            arguments: None,
+            executed: 0,
+            clock: Rc::new(SystemClock::new()),
+            devices: Vec::new(),
+            console: Rc::clone(&console),
+            device_log: DeviceLog::default(),
+            semihost: Semihost::new(console),
+            semihosting_request: Rc::new(Cell::new(None)),
+            audio_outputs: Vec::new(),
+            serial_ports: Vec::new(),
+            serial_bridges: Vec::new(),
+            network_ports: Vec::new(),
+            network_links: Vec::new(),
+            gpio_pins: Vec::new(),
+            flash_memories: Vec::new(),
+            disks: Vec::new(),
+            power_command: Rc::new(Cell::new(None)),
+            power_devices: Vec::new(),
+            boots: 0,
+            device_change_line: None,
+        };
+        for device in devices {
+            machine.attach_device(*device)?;
+        }
+        Ok(machine)
+    }
+
+    /// Creates a machine which boots from `rom`: the instructions of `rom` are mounted read-only at
+    /// `reset_vector`, where the processor starts, and all instructions are fetched from the memory, so that the
+    /// ROM can load a program into the RAM and jump to it, e.g. from a [`disk`](disk/index.html) device.
+    ///
+    /// The RAM of `ram_size` bytes starts at address 0 and is cleared, and the stack pointer is at its end. A
+    /// reboot starts at the reset vector again.
+    pub fn boot(
+        rom: &Executable,
+        reset_vector: u32,
+        ram_size: u32,
+        devices: &[Device],
+        console: Box<dyn Write>,
+    ) -> Result<Machine, String> {
+        if rom.instructions().is_empty() {
+            return Err("The boot ROM has no instructions".to_owned());
+        }
+        if rom.sections().iter().any(|section| section.size() > 0) {
+            return Err("The boot ROM has data, but only its instructions are mounted".to_owned());
+        }
+        let mut machine = Machine::new(&Executable::new(Vec::new()), ram_size, &[], console)?;
+        let handler = DelegateIOHandler::new(|_, _, _| false, |_, _, _| {});
+        let mut memory = IOMemory::new(rom.instructions().len() as u32, handler);
+        memory.data_mut().copy_from_slice(rom.instructions());
+        machine
+            .memory
+            .mount(reset_vector, "rom", memory)
+            .map_err(|_| format!("The boot ROM at 0x{:08X} overlaps the RAM", reset_vector))?;
+        machine.fetch_from_memory = true;
+        machine.entry_point = reset_vector;
+        machine.processor.set_reset_vector(reset_vector);
+        machine.processor.set_program_counter(reset_vector);
+        for device in devices {
+            machine.attach_device(*device)?;
+        }
+        Ok(machine)
+    }
+
+    /// Mounts `device` and creates its host side, e.g. to attach a console to a paused program. Programs learn
+    /// about new devices by probing them, e.g. when the
+    /// [device change interrupt](#method.set_device_change_interrupt) is raised.
+    pub fn attach_device(&mut self, device: Device) -> Result<(), String> {
+        let mounted = match device.kind {
+            DeviceKind::Uart => {
+                let console = Rc::clone(&self.console);
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        if address == 0 {
+                            // the program cannot do anything about a closed output, so it keeps running
+                            let mut console = console.borrow_mut();
+                            let _ = console
+                                .write_all(&memory[..1])
+                                .and_then(|_| console.flush());
+                        }
+                    },
+                );
+                let key = device.to_string();
+                self.memory.mount(
+                    device.address,
+                    &key,
+                    IOMemory::new(device.kind.size(), handler),
+                )
+            }
+            DeviceKind::Semihosting => {
+                let requests = Rc::clone(&self.semihosting_request);
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // results are stored by the machine itself
+                        if address == semihosting::RESULT {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        if address == semihosting::OPERATION {
+                            let arg = |offset| memory.read_word(offset).unwrap();
+                            requests.set(Some(Request {
+                                device: device.address,
+                                operation: arg(semihosting::OPERATION),
+                                args: [
+                                    arg(semihosting::ARG0),
+                                    arg(semihosting::ARG1),
+                                    arg(semihosting::ARG2),
+                                ],
+                            }));
+                        }
+                    },
+                );
+                let key = device.to_string();
+                self.memory.mount(
+                    device.address,
+                    &key,
+                    IOMemory::new(device.kind.size(), handler),
+                )
+            }
+            DeviceKind::Audio => {
+                let output = AudioOutput::new(device);
+                self.audio_outputs.push(output.clone());
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // the level is stored by the machine itself
+                        if address == audio::LEVEL {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        output.on_write(memory, address);
+                    },
+                );
+                let key = device.to_string();
+                let mut registers = IOMemory::new(device.kind.size(), handler);
+                Endian::write_u32(
+                    &mut registers.data_mut()[audio::RATE as usize..],
+                    audio::DEFAULT_SAMPLE_RATE,
+                );
+                self.memory.mount(device.address, &key, registers)
+            }
+            DeviceKind::Serial => {
+                let port = SerialPort::new(device);
+                self.serial_ports.push(port.clone());
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // the received byte and the status are stored by the machine itself
+                        if address == serial::RX || address == serial::STATUS {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        port.on_write(memory, address);
+                    },
+                );
+                let key = device.to_string();
+                self.memory.mount(
+                    device.address,
+                    &key,
+                    IOMemory::new(device.kind.size(), handler),
+                )
+            }
+            DeviceKind::Network => {
+                let port = NetworkPort::new(device);
+                self.network_ports.push(port.clone());
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // the other registers and the received packet are stored by the machine itself
+                        if !NetworkPort::is_written_by_program(address) {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        port.on_write(memory, address);
+                    },
+                );
+                let key = device.to_string();
+                let mut registers = IOMemory::new(device.kind.size(), handler);
+                Endian::write_u32(
+                    &mut registers.data_mut()[network::INTERRUPT as usize..],
+                    NO_INTERRUPT,
+                );
+                self.memory.mount(device.address, &key, registers)
+            }
+            DeviceKind::Gpio => {
+                let pins = GpioPins::new(device);
+                self.gpio_pins.push(pins.clone());
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // the levels and the pending changes are stored by the machine itself
+                        if address == gpio::INPUT || address == gpio::PENDING {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        pins.on_write(memory, address);
+                    },
+                );
+                let key = device.to_string();
+                let mut registers = IOMemory::new(device.kind.size(), handler);
+                Endian::write_u32(
+                    &mut registers.data_mut()[gpio::INTERRUPT as usize..],
+                    NO_INTERRUPT,
+                );
+                self.memory.mount(device.address, &key, registers)
+            }
+            DeviceKind::Flash => {
+                let (flash, host) = Flash::new(device, self.device_log.clone());
+                self.flash_memories.push(host);
+                self.memory
+                    .mount(device.address, &device.to_string(), flash)
+            }
+            DeviceKind::Disk => {
+                let (disk, image) = Disk::new(device, self.device_log.clone());
+                self.disks.push(image);
+                self.memory.mount(device.address, &device.to_string(), disk)
+            }
+            DeviceKind::Power => {
+                self.power_devices.push(device);
+                let commands = Rc::clone(&self.power_command);
+                let device_log = self.device_log.clone();
+                let handler = DelegateIOHandler::new(
+                    |_, _, _| true,
+                    move |memory, address, size| {
+                        // the number of boots is stored by the machine itself
+                        if address == power::BOOTS {
+                            return;
+                        }
+                        device_log.push(DeviceWrite {
+                            device,
+                            offset: address,
+                            size,
+                            value: memory.read(address, size).unwrap(),
+                        });
+                        if address == power::COMMAND {
+                            let word = |offset| memory.read_word(offset).unwrap();
+                            if let Some(command) =
+                                Command::decode(word(power::COMMAND), word(power::STATUS))
+                            {
+                                commands.set(Some(command));
+                            }
+                        }
+                    },
+                );
+                let key = device.to_string();
+                self.memory.mount(
+                    device.address,
+                    &key,
+                    IOMemory::new(device.kind.size(), handler),
+                )
+            }
+            DeviceKind::Plugin(name) => {
+                let plugin =
+                    plugin::find(name).ok_or_else(|| format!("Unknown device \"{}\"", name))?;
+                let memory = PluginMemory::new(device, plugin, self.device_log.clone());
+                self.memory
+                    .mount(device.address, &device.to_string(), memory)
+            }
+        };
+        mounted.map_err(|_| format!("Device {} overlaps the RAM or another device", device))?;
+        self.devices.push(device);
+        self.store_serial_registers();
+        self.store_network_registers();
+        self.device_changed();
+        Ok(())
+    }
+
+    /// Unmounts the device at `address` and drops its host side, together with the TCP listener or UDP link it
+    /// may have. Returns the device which was detached.
+    pub fn detach_device(&mut self, address: u32) -> Result<Device, String> {
+        let index = self
+            .devices
+            .iter()
+            .position(|device| device.address == address)
+            .ok_or_else(|| format!("There is no device at 0x{:08X}", address))?;
+        let device = self.devices.remove(index);
+        self.memory.unmount(&device.to_string());
+
+        self.audio_outputs
+            .retain(|output| output.device() != device);
+        if let Some(index) = self
+            .serial_ports
+            .iter()
+            .position(|port| port.device() == device)
+        {
+            self.serial_ports.remove(index);
+            remove_connection(&mut self.serial_bridges, index);
+        }
+        if let Some(index) = self
+            .network_ports
+            .iter()
+            .position(|port| port.device() == device)
+        {
+            self.network_ports.remove(index);
+            remove_connection(&mut self.network_links, index);
+        }
+        self.gpio_pins.retain(|pins| pins.device() != device);
+        self.flash_memories
+            .retain(|memory| memory.device() != device);
+        self.disks.retain(|disk| disk.device() != device);
+        self.power_devices.retain(|power| *power != device);
+        self.device_changed();
+        Ok(device)
+    }
+
+    /// Selects the interrupt `line` which is raised whenever a device is attached or detached, so that the
+    /// program can probe its devices again. The handler clears the line in `IP`, and no device should be connected
+    /// to it. By default, no line is raised.
+    pub fn set_device_change_interrupt(&mut self, line: Option<u32>) -> Result<(), String> {
+        if let Some(line) = line.filter(|line| *line >= INTERRUPT_LINES) {
+            return Err(format!("There is no interrupt line {}", line));
+        }
+        self.device_change_line = line;
+        Ok(())
+    }
+
+    pub fn device_change_interrupt(&self) -> Option<u32> {
+        self.device_change_line
+    }
+
+    fn device_changed(&mut self) {
+        if let Some(line) = self.device_change_line {
+            self.processor.raise_interrupt(line);
+        }
+    }
+
+    /// The mounted devices, in the order they were attached.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    /// Main memory of the machine, including the devices.
+    pub fn memory(&self) -> &CompositeMemory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut CompositeMemory {
+        &mut self.memory
+    }
+
+    /// The host sides of the audio devices, in the order of the devices.
+    pub fn audio_outputs(&self) -> &[AudioOutput] {
+        &self.audio_outputs
+    }
+
+    pub fn instructions(&self) -> &[u8] {
+        self.instructions.as_ref()
+    }
+
+    /// The instructions of the program, which can be changed while the program is paused, e.g. to load code
+    /// into a program which was started without it. A machine which boots from a ROM has none.
+    pub fn program(&self) -> &ProgramMemory {
+        &self.instructions
+    }
+
+    pub fn program_mut(&mut self) -> &mut ProgramMemory {
+        &mut self.instructions
+    }
+
+    /// Passes command line arguments and environment strings (`NAME=VALUE`) to the program.
+    ///
+    /// The strings are stored zero terminated directly below the stack, together with two arrays of pointers
+    /// to them, each of which ends with a null pointer. The program starts with the number of arguments in `$A0`,
+    /// the address of the argument array in `$A1` and the address of the environment array in `$A2`.
+    /// The stack pointer is moved below the stored data.
+    pub fn pass_arguments(&mut self, args: &[&str], env: &[&str]) -> Result<(), String> {
+        let strings = args.iter().chain(env.iter());
+        let pointers = (args.len() + env.len() + 2) as u64 * u64::from(WORD_BYTES);
+        let size = strings.map(|s| s.len() as u64 + 1).sum::<u64>() + pointers;
+
+        let end = u64::from(self.processor.register(RegisterId::SP).u());
+        let start = end
+            .checked_sub(size)
+            .map(|start| start / u64::from(WORD_BYTES) * u64::from(WORD_BYTES))
+            .filter(|start| *start >= u64::from(self.program_end))
+            .ok_or_else(|| {
+                format!(
+                    "The arguments need {} bytes, which do not fit into the RAM",
+                    size
+                )
+            })? as u32;
+
+        let argv = start;
+        let envp = argv + (args.len() as u32 + 1) * WORD_BYTES;
+        let mut string = start + pointers as u32;
+        for (array, strings) in [(argv, args), (envp, env)].iter() {
+            let mut pointer = *array;
+            for s in strings.iter() {
+                self.memory.write_word(pointer, string).unwrap();
+                pointer += WORD_BYTES;
+                for byte in s.bytes().chain(std::iter::once(0)) {
+                    self.memory.write_byte(string, byte).unwrap();
+                    string += 1;
+                }
+            }
+            self.memory.write_word(pointer, 0).unwrap();
+        }
+
+        let processor = &mut self.processor;
+        processor
+            .register_mut(RegisterId::A0)
+            .set_u(args.len() as u32);
+        processor.register_mut(RegisterId::A1).set_u(argv);
+        processor.register_mut(RegisterId::A2).set_u(envp);
+        processor.register_mut(RegisterId::SP).set_u(start);
+        let owned = |strings: &[&str]| strings.iter().map(|s| s.to_string()).collect();
+        self.arguments = Some((owned(args), owned(env)));
+        Ok(())
+    }
+
+    /// Restarts the program as if the machine was switched off and on again: the processor is reset, the RAM
+    /// cleared and the program loaded into it again, and the arguments passed with
+    /// [`pass_arguments`](#method.pass_arguments) are passed again. The devices keep their state.
+    pub fn reboot(&mut self) {
+        self.memory.unmount("ram");
+        self.memory
+            .mount(0, "ram", vec![0u8; self.ram_size as usize])
+            .unwrap();
+        // the sections fitted before, so they fit again
+        loader::place_sections(&self.sections, &mut self.memory).unwrap();
+
+        self.processor.reset();
+        self.processor.set_program_counter(self.entry_point);
+        self.processor
+            .register_mut(STACK_POINTER)
+            .set_u(self.ram_size);
+        if let Some((args, env)) = self.arguments.take() {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let env: Vec<&str> = env.iter().map(String::as_str).collect();
+            // the arguments fitted before, so they fit again
+            self.pass_arguments(&args, &env).unwrap();
+        }
+
+        self.boots += 1;
+        for device in self.power_devices.iter() {
+            self.memory
+                .write_word(device.address + power::BOOTS, self.boots)
+                .unwrap();
+        }
+    }
+
+    /// Where the sections of the program were loaded.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Number of times the program was restarted by [`reboot`](#method.reboot).
+    pub fn boots(&self) -> u32 {
+        self.boots
+    }
+
+    /// Starts recording the writes to devices and returns the log they are recorded in.
+    pub fn device_log(&self) -> DeviceLog {
+        let mut writes = self.device_log.0.borrow_mut();
+        if writes.is_none() {
+            *writes = Some(Vec::new());
+        }
+        self.device_log.clone()
+    }
+
+    /// Number of instructions executed so far, including one which stopped the processor.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// The clock which timeouts are measured with and [`run_realtime`](#method.run_realtime) paces the program
+    /// with.
+    pub fn clock_source(&self) -> Rc<dyn ClockSource> {
+        Rc::clone(&self.clock)
+    }
+
+    /// Replaces the clock, which is the [`SystemClock`](clock/struct.SystemClock.html) by default, e.g. with a
+    /// [`FakeClock`](clock/struct.FakeClock.html) in tests.
+    pub fn set_clock_source(&mut self, clock: Rc<dyn ClockSource>) {
+        self.clock = clock;
+    }
+
+    /// Sets the directory the semihosting devices can open files in, without one they can only use the
+    /// standard streams.
+    pub fn set_semihosting_root(&mut self, root: Option<PathBuf>) {
+        self.semihost.set_root(root);
+    }
+
+    /// Replaces the stderr of the semihosting devices, which is the stderr of the runner by default.
+    pub fn set_semihosting_errors(&mut self, errors: Box<dyn Write>) {
+        self.semihost.set_error_output(errors);
+    }
+
+    /// Executes a single instruction, unless the processor has stopped already, and performs the semihosting
+    /// operation or power command it requested.
+    pub fn step(&mut self) -> Option<ExitCode> {
+        let stopped = self.processor.is_stopped();
+        let mut exit_code = if self.fetch_from_memory {
+            self.processor.tick_from_storage(&mut self.memory)
+        } else {
+            self.processor.tick(&self.instructions, &mut self.memory)
+        };
+        // an instruction which stops the processor counts as well, unless there was none to fetch
+        if !stopped && exit_code != Some(ExitCode::BadProgramCounter) {
+            self.executed += 1;
+        }
+        if let Some(request) = self.semihosting_request.take() {
+            let result = self.semihost.handle(&request, &mut self.memory);
+            self.memory
+                .write_word(request.device + semihosting::RESULT, result as u32)
+                .unwrap();
+        }
+        match self.power_command.take() {
+            Some(Command::Shutdown(status)) => {
+                self.processor.register_mut(RegisterId::V0).set_u(status);
+                self.processor.set_state(Some(ExitCode::Halted));
+                exit_code = Some(ExitCode::Halted);
+            }
+            Some(Command::Reboot) => self.reboot(),
+            None => {}
+        }
+        for output in self.audio_outputs.iter() {
+            if let Some(level) = output.tick() {
+                self.memory
+                    .write_word(output.device().address + audio::LEVEL, level)
+                    .unwrap();
+            }
+        }
+        // a program which stops may have sent bytes since the last poll
+        let poll = exit_code.is_some() || self.executed.is_multiple_of(serial::POLL_INTERVAL);
+        if poll && !self.serial_bridges.is_empty() {
+            for (index, bridge) in self.serial_bridges.iter_mut() {
+                bridge.poll(&self.serial_ports[*index]);
+            }
+        }
+        self.store_serial_registers();
+        if !self.network_links.is_empty()
+            && (exit_code.is_some() || self.executed.is_multiple_of(network::POLL_INTERVAL))
+        {
+            for (index, link) in self.network_links.iter_mut() {
+                link.poll(&self.network_ports[*index]);
+            }
+        }
+        self.store_network_registers();
+        self.store_gpio_registers();
+        self.update_interrupts();
+        exit_code
+    }
+
+    /// Raises the interrupt lines of the devices which request an interrupt, and lowers the other lines which
+    /// devices are connected to, so that devices can share a line.
+    fn update_interrupts(&mut self) {
+        let mut connected = 0u32;
+        let mut raised = 0u32;
+        for port in self.network_ports.iter() {
+            let address = port.device().address + network::INTERRUPT;
+            if let Some(line) = self.interrupt_line(address) {
+                connected |= 1 << line;
+                if port.has_packet() {
+                    raised |= 1 << line;
+                }
+            }
+        }
+        for pins in self.gpio_pins.iter() {
+            let address = pins.device().address + gpio::INTERRUPT;
+            if let Some(line) = self.interrupt_line(address) {
+                connected |= 1 << line;
+                if pins.pending() != 0 {
+                    raised |= 1 << line;
+                }
+            }
+        }
+        if connected != 0 {
+            let csrs = self.processor.control_registers_mut();
+            let ip = csrs.ip() & !connected | raised;
+            csrs.set_ip(ip);
+        }
+    }
+
+    /// Returns the interrupt line a device register at `address` selects, if it selects one.
+    fn interrupt_line(&self, address: u32) -> Option<u32> {
+        self.memory
+            .read_word(address)
+            .ok()
+            .filter(|line| *line < INTERRUPT_LINES)
+    }
+
+    fn store_gpio_registers(&mut self) {
+        for pins in self.gpio_pins.iter() {
+            if let Some((input, pending)) = pins.registers() {
+                let address = pins.device().address;
+                self.memory
+                    .write_word(address + gpio::INPUT, input)
+                    .unwrap();
+                self.memory
+                    .write_word(address + gpio::PENDING, pending)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// The host sides of the flash devices, in the order of the devices.
+    pub fn flash_memories(&self) -> &[FlashMemory] {
+        &self.flash_memories
+    }
+
+    /// The host sides of the disk devices, in the order of the devices.
+    pub fn disks(&self) -> &[DiskImage] {
+        &self.disks
+    }
+
+    /// The host sides of the GPIO devices, in the order of the devices.
+    pub fn gpio_pins(&self) -> &[GpioPins] {
+        &self.gpio_pins
+    }
+
+    fn store_network_registers(&mut self) {
+        for port in self.network_ports.iter() {
+            if let Some((registers, buffer)) = port.registers() {
+                let address = port.device().address;
+                for (i, value) in registers.iter().enumerate() {
+                    let offset = i as u32 * WORD_BYTES;
+                    if !NetworkPort::is_written_by_program(offset) {
+                        self.memory.write_word(address + offset, *value).unwrap();
+                    }
+                }
+                for (i, byte) in buffer.iter().enumerate() {
+                    self.memory
+                        .write_byte(address + network::RX_BUFFER + i as u32, *byte)
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    /// The host sides of the network devices, in the order of the devices.
+    pub fn network_ports(&self) -> &[NetworkPort] {
+        &self.network_ports
+    }
+
+    /// Connects the next network device which is not linked yet to other processes: it becomes `station`,
+    /// receives UDP datagrams at `address` and sends its packets to all `peers`. Returns the address it is
+    /// bound to.
+    pub fn link_network(
+        &mut self,
+        station: u32,
+        address: &str,
+        peers: &[&str],
+    ) -> Result<SocketAddr, String> {
+        let index = unconnected(&self.network_links, self.network_ports.len())
+            .ok_or_else(|| "The machine has no network device to link".to_owned())?;
+        let port = &self.network_ports[index];
+        let link = UdpLink::bind(address, peers)?;
+        let local = link
+            .local_addr()
+            .ok_or_else(|| format!("Binding to {} failed", address))?;
+        port.set_station(station);
+        self.network_links.push((index, link));
+        Ok(local)
+    }
+
+    fn store_serial_registers(&mut self) {
+        for port in self.serial_ports.iter() {
+            if let Some((rx, status)) = port.registers() {
+                let address = port.device().address;
+                self.memory.write_word(address + serial::RX, rx).unwrap();
+                self.memory
+                    .write_word(address + serial::STATUS, status)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// The host sides of the serial devices, in the order of the devices.
+    pub fn serial_ports(&self) -> &[SerialPort] {
+        &self.serial_ports
+    }
+
+    /// Connects the next serial device which is not connected yet to the clients of a TCP listener at
+    /// `address`, and returns the address it listens at.
+    pub fn listen_serial(&mut self, address: &str) -> Result<SocketAddr, String> {
+        let index = unconnected(&self.serial_bridges, self.serial_ports.len())
+            .ok_or_else(|| "The machine has no serial device to listen with".to_owned())?;
+        let bridge = TcpBridge::listen(address)?;
+        let local = bridge
+            .local_addr()
+            .ok_or_else(|| format!("Listening at {} failed", address))?;
+        self.serial_bridges.push((index, bridge));
+        Ok(local)
+    }
+
+    /// Runs the program until it halts, fails or exceeds one of the `limits`.
+    pub fn run(&mut self, limits: &Limits) -> Stop {
+        self.run_loop(limits, |_| false, |_, _, _| {}).unwrap()
+    }
+
+    /// Runs the program like [`run`](#method.run), but also stops before executing an instruction
+    /// for whose address `stop_at` returns true, in which case `None` is returned.
+    ///
+    /// The first instruction is always executed, so that running can continue after such a stop.
+    pub fn run_until<F: FnMut(u32) -> bool>(
+        &mut self,
+        limits: &Limits,
+        stop_at: F,
+    ) -> Option<Stop> {
+        self.run_loop(limits, stop_at, |_, _, _| {})
+    }
+
+    /// Runs the program like [`run`](#method.run) and calls `observe` after every executed instruction,
+    /// with its address, the instruction itself and the processor after executing it. This includes the
+    /// instruction which stops the processor, e.g. `HALT`.
+    pub fn run_observed<F: FnMut(u32, Word, &Processor)>(
+        &mut self,
+        limits: &Limits,
+        mut observe: F,
+    ) -> Stop {
+        self.run_loop(
+            limits,
+            |_| false,
+            |address, word, machine| observe(address, word, machine.processor()),
+        )
+        .unwrap()
+    }
+
+    /// Runs the program like [`run_observed`](#method.run_observed), but passes the whole machine to `observe`,
+    /// e.g. so that it can read the memory.
+    pub fn run_inspected<F: FnMut(u32, Word, &Machine)>(
+        &mut self,
+        limits: &Limits,
+        observe: F,
+    ) -> Stop {
+        self.run_loop(limits, |_| false, observe).unwrap()
+    }
+
+    /// Runs the program like [`run`](#method.run), but paces it to execute `target_hz` instructions per second
+    /// of the host clock, so that a program which talks to a person, e.g. over a UART, runs at a believable
+    /// speed.
+    ///
+    /// When the host cannot keep up, e.g. because it was paused, the program runs as fast as possible until it
+    /// caught up again, but it never makes up for more than a quarter of a second.
+    pub fn run_realtime(&mut self, limits: &Limits, target_hz: u64) -> Stop {
+        let hz = u128::from(target_hz.max(1));
+        let nanos = |instructions: u64| {
+            Duration::from_nanos((u128::from(instructions) * 1_000_000_000 / hz) as u64)
+        };
+        let slice = (target_hz / REALTIME_SLICES).max(1);
+        let max_lag = ((MAX_REALTIME_LAG.as_nanos() * hz / 1_000_000_000) as u64).max(slice);
+        let clock = Rc::clone(&self.clock);
+        let started = clock.now();
+        let deadline = limits.timeout.map(|timeout| started + timeout);
+        // the clock started at `origin` with `first` executed instructions
+        let (mut origin, mut first) = (started, self.executed);
+        loop {
+            let now = clock.now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Stop::Timeout;
+            }
+            let elapsed = (now - origin).as_nanos() * hz / 1_000_000_000;
+            let mut due = first.saturating_add(elapsed as u64);
+            if due > self.executed.saturating_add(max_lag) {
+                // the host fell too far behind, so the clock starts over
+                due = self.executed.saturating_add(max_lag);
+                origin = now;
+                first = due;
+            }
+            if due <= self.executed {
+                let mut wake = origin + nanos(self.executed + slice - first);
+                if let Some(deadline) = deadline {
+                    wake = wake.min(deadline);
+                }
+                clock.sleep(wake.saturating_sub(now));
+                continue;
+            }
+
+            let mut end = due;
+            if let Some(max) = limits.max_instructions {
+                end = end.min(max);
+            }
+            let slice_limits = Limits {
+                max_instructions: Some(end),
+                timeout: deadline.map(|deadline| deadline.saturating_sub(now)),
+            };
+            let stop = self
+                .run_loop(&slice_limits, |_| false, |_, _, _| {})
+                .unwrap();
+            let limit_reached = limits
+                .max_instructions
+                .is_some_and(|max| self.executed >= max);
+            match stop {
+                Stop::InstructionLimit if !limit_reached => {}
+                stop => return stop,
+            }
+        }
+    }
+
+    fn run_loop<S, O>(&mut self, limits: &Limits, mut stop_at: S, mut observe: O) -> Option<Stop>
+    where
+        S: FnMut(u32) -> bool,
+        O: FnMut(u32, Word, &Machine),
+    {
+        let deadline = limits.timeout.map(|timeout| self.clock.now() + timeout);
+        let mut first = true;
+        loop {
+            // the handler of an interrupt is entered first, so that pc is the instruction which is executed, and
+            // a breakpoint at the handler is reached even if the run resumed at another breakpoint
+            let interrupted = self.processor.take_interrupt();
+            let pc = self.processor.program_counter();
+            if (!first || interrupted) && stop_at(pc) {
+                return None;
+            }
+            first = false;
+            if limits
+                .max_instructions
+                .is_some_and(|max| self.executed >= max)
+            {
+                return Some(Stop::InstructionLimit);
+            }
+            if let Some(deadline) = deadline {
+                if self.executed.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+                    && self.clock.now() >= deadline
+                {
+                    return Some(Stop::Timeout);
+                }
+            }
+            let word = self.instruction_at(pc);
+            let executed = self.executed;
+            let exit_code = self.step();
+            // an instruction which was executed always exists, including one which stopped the processor
+            if self.executed > executed {
+                observe(pc, word.unwrap(), self);
+            }
+            if let Some(exit_code) = exit_code {
+                return Some(Stop::Exit(exit_code));
+            }
+        }
+    }
+
+    /// Returns the instruction at `address`, if it lies within the program, or within the memory if the machine
+    /// boots from a ROM.
+    pub fn instruction_at(&self, address: u32) -> Option<Word> {
+        if self.fetch_from_memory {
+            return self.memory.read_word(address).ok();
+        }
+        let start = address as usize;
+        self.instructions()
+            .get(start..start + WORD_BYTES as usize)
+            .map(Endian::read_u32)
+    }
+}
+
+/// Removes the connection of the port at `index`, and moves the ports after it down by one.
+fn remove_connection<T>(connections: &mut Vec<(usize, T)>, index: usize) {
+    connections.retain(|(port, _)| *port != index);
+    for (port, _) in connections.iter_mut() {
+        if *port > index {
+            *port -= 1;
+        }
+    }
+}
+
+/// Returns the index of the first of `ports` ports which has no connection.
+fn unconnected<T>(connections: &[(usize, T)], ports: usize) -> Option<usize> {
+    (0..ports).find(|index| connections.iter().all(|(port, _)| port != index))
+}
@@ -0,0 +1,653 @@
+//! Runs a VCPU program from a vexfile, an ELF file or assembly source.
+//!
+//! See the documentation of the library for the machine the program runs on and the exit statuses.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{AppSettings, Arg};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use vcpu::IsaProfile;
+use vcpu_run::config::{parse_frequency, parse_size, parse_timeout, Device, MachineConfig};
+use vcpu_run::contract::ContractChecker;
+use vcpu_run::disk;
+use vcpu_run::events::EventBus;
+use vcpu_run::flash;
+use vcpu_run::frame::Framebuffer;
+use vcpu_run::pipeline::{self, Pipeline};
+use vcpu_run::predictor::{self, PredictorKind, PredictorSimulation};
+use vcpu_run::profiler::Profiler;
+use vcpu_run::statistics::{Statistics, StatisticsFormat};
+use vcpu_run::taint::{TaintSource, TaintTracker};
+use vcpu_run::trace::{run_traced, TraceFilter, TraceFormat, Tracer};
+use vcpu_run::*;
+use vex::Executable;
+
+/// Number of functions and lines in the profile report.
+const PROFILE_ENTRIES: usize = 20;
+
+fn main() {
+    let matches = app_from_crate!()
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the vexfile, ELF file or assembly source to run")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("ARGS")
+                .help("Sets the arguments passed to the program")
+                .multiple(true)
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=VALUE")
+                .validator(|value| {
+                    if value.contains('=') {
+                        Ok(())
+                    } else {
+                        Err("expected NAME=VALUE".to_owned())
+                    }
+                })
+                .help("Passes an environment string to the program"),
+        )
+        .arg(
+            Arg::with_name("inherit_env")
+                .long("inherit-env")
+                .help("Passes the environment of the runner to the program, before the strings given with --env"),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size, devices and limits from a machine file"),
+        )
+        .arg(
+            Arg::with_name("ram")
+                .long("ram")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help(
+                    "Sets the size of the RAM in bytes, optionally with a K or M suffix \
+                     (default: 1M, or the size the program needs if it is larger)",
+                ),
+        )
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KIND@ADDRESS")
+                .validator(|value| value.parse::<Device>().map(|_| ()))
+                .help("Adds a device to the machine, e.g. uart@0xFFFF0000"),
+        )
+        .arg(
+            Arg::with_name("semihosting_root")
+                .long("semihosting-root")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Lets semihosting devices open files in this directory"),
+        )
+        .arg(
+            Arg::with_name("serial_listen")
+                .long("serial-listen")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("HOST:PORT")
+                .help("Lets a terminal connect to the next serial device over TCP, e.g. 127.0.0.1:2323"),
+        )
+        .arg(
+            Arg::with_name("network_bind")
+                .long("network-bind")
+                .takes_value(true)
+                .value_name("HOST:PORT")
+                .requires("network_station")
+                .help("Links the network device to other runners, receiving UDP datagrams at this address"),
+        )
+        .arg(
+            Arg::with_name("network_station")
+                .long("network-station")
+                .takes_value(true)
+                .value_name("STATION")
+                .requires("network_bind")
+                .validator(|value| value.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets the station number of the linked network device"),
+        )
+        .arg(
+            Arg::with_name("network_peer")
+                .long("network-peer")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("HOST:PORT")
+                .requires("network_bind")
+                .help("Sends the packets of the network device to the runner bound to this address"),
+        )
+        .arg(
+            Arg::with_name("flash")
+                .long("flash")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Loads the flash device from this image, and saves it after the program stopped"),
+        )
+        .arg(
+            Arg::with_name("disk")
+                .long("disk")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Loads the disk device from this image, and saves it after the program stopped"),
+        )
+        .arg(
+            Arg::with_name("reset_vector")
+                .long("reset-vector")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("Boots the program as a ROM at this address, which can load another program from a disk"),
+        )
+        .arg(
+            Arg::with_name("isa")
+                .long("isa")
+                .takes_value(true)
+                .value_name("PROFILE")
+                .validator(|value| value.parse::<IsaProfile>().map(|_| ()))
+                .help(
+                    "Restricts the instruction set, e.g. base,+shift or -muldiv,-float \
+                     (groups: muldiv, subword, float, shift, vector, shadow), and the registers with regs16 \
+                     or a table like regs:ZERO/V0/ACC=T0/SP/RA",
+                ),
+        )
+        .arg(
+            Arg::with_name("framebuffer")
+                .long("framebuffer")
+                .takes_value(true)
+                .value_name("ADDRESS:WIDTHxHEIGHT")
+                .validator(|value| value.parse::<Framebuffer>().map(|_| ()))
+                .help("Mounts a framebuffer with RGBA pixels, e.g. 0x100000:320x200"),
+        )
+        .arg(
+            Arg::with_name("screenshot")
+                .long("screenshot")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Saves the framebuffer as a PNG file after the program stopped"),
+        )
+        .arg(
+            Arg::with_name("max_instructions")
+                .long("max-instructions")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "expected a number of instructions".to_owned())
+                })
+                .help("Stops the program after executing this many instructions"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|value| parse_timeout(&value).map(|_| ()))
+                .help("Stops the program after running for this many seconds"),
+        )
+        .arg(
+            Arg::with_name("realtime")
+                .long("realtime")
+                .takes_value(true)
+                .value_name("HZ")
+                .validator(|value| parse_frequency(&value).map(|_| ()))
+                .conflicts_with_all(&[
+                    "profile",
+                    "folded",
+                    "stats",
+                    "branch_predictor",
+                    "pipeline",
+                    "taint",
+                    "check_mmio",
+                    "trace",
+                ])
+                .help(
+                    "Paces the program to execute this many instructions per second, \
+                     e.g. 1000 or 2.5M, instead of running as fast as possible",
+                ),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Prints the functions and source lines which executed the most instructions"),
+        )
+        .arg(
+            Arg::with_name("folded")
+                .long("folded")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the executed instructions per call stack as folded stacks for flame graphs"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the number of executed instructions per mnemonic and address and the branch statistics"),
+        )
+        .arg(
+            Arg::with_name("stats_format")
+                .long("stats-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["json", "csv"])
+                .requires("stats")
+                .help("Sets the format of the statistics (default: csv for .csv files, json otherwise)"),
+        )
+        .arg(
+            Arg::with_name("branch_predictor")
+                .long("branch-predictor")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("MODEL")
+                .validator(|value| value.parse::<PredictorKind>().map(|_| ()))
+                .help("Simulates a branch predictor (always-taken, bimodal[:BITS] or gshare[:BITS]) and prints its accuracy"),
+        )
+        .arg(
+            Arg::with_name("mispredict_penalty")
+                .long("mispredict-penalty")
+                .takes_value(true)
+                .value_name("CYCLES")
+                .requires("branch_predictor")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "expected a number of cycles".to_owned())
+                })
+                .help("Sets the cycles a mispredicted branch costs (default: 2)"),
+        )
+        .arg(
+            Arg::with_name("pipeline")
+                .long("pipeline")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the cycles and hazards of the instructions in a 5-stage pipeline with a diagram"),
+        )
+        .arg(
+            Arg::with_name("pipeline_window")
+                .long("pipeline-window")
+                .takes_value(true)
+                .value_name("START[:COUNT]")
+                .requires("pipeline")
+                .validator(|value| pipeline::parse_window(&value).map(|_| ()))
+                .help("Sets the instructions in the pipeline diagram (default: the first 32)"),
+        )
+        .arg(
+            Arg::with_name("no_forwarding")
+                .long("no-forwarding")
+                .requires("pipeline")
+                .help("Models the pipeline without forwarding"),
+        )
+        .arg(
+            Arg::with_name("taint")
+                .long("taint")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SOURCE")
+                .validator(|value| value.parse::<TaintSource>().map(|_| ()))
+                .help("Follows data from a register ($A0), device (uart@0xFFFF0000) or memory (ADDRESS[:SIZE]) and prints which instructions used it"),
+        )
+        .arg(
+            Arg::with_name("check_mmio")
+                .long("check-mmio")
+                .help("Prints the accesses to devices which do not match their registers, e.g. reads of write-only registers"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes every executed instruction and device write to a trace file"),
+        )
+        .arg(
+            Arg::with_name("trace_format")
+                .long("trace-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["chrome", "csv"])
+                .help("Sets the format of the trace (default: csv for .csv files, chrome otherwise)"),
+        )
+        .arg(
+            Arg::with_name("trace_filter")
+                .long("trace-filter")
+                .takes_value(true)
+                .value_name("EXPR")
+                .requires("trace")
+                .help("Only traces instructions for which the expression is not zero, e.g. \"pc >= main && [$SP] != 0\""),
+        )
+        .get_matches();
+
+    let input = matches.value_of("INPUT").unwrap();
+    let Loaded {
+        executable,
+        warnings,
+    } = load(input).unwrap_or_else(|err| fail(&err));
+    for warning in warnings.iter() {
+        eprintln!("Warning:\n{}", warning);
+    }
+    let mut config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    if let Some(value) = matches.value_of("ram") {
+        config.ram_size = Some(parse_size(value).unwrap());
+    }
+    for value in matches.values_of("device").into_iter().flatten() {
+        config.devices.push(value.parse().unwrap());
+    }
+    if let Some(value) = matches.value_of("semihosting_root") {
+        config.semihosting_root = Some(value.into());
+    }
+    if let Some(value) = matches.value_of("isa") {
+        config.isa = value.parse().unwrap();
+    }
+    if let Some(value) = matches.value_of("flash") {
+        config.flash_file = Some(value.into());
+    }
+    if let Some(value) = matches.value_of("disk") {
+        config.disk_file = Some(value.into());
+    }
+    if let Some(value) = matches.value_of("reset_vector") {
+        config.reset_vector = Some(parse_size(value).unwrap());
+    }
+    if let Some(value) = matches.value_of("framebuffer") {
+        config.framebuffer = Some(value.parse().unwrap());
+    }
+    if let Some(value) = matches.value_of("max_instructions") {
+        config.limits.max_instructions = Some(value.parse().unwrap());
+    }
+    if let Some(value) = matches.value_of("timeout") {
+        config.limits.timeout = Some(parse_timeout(value).unwrap());
+    }
+    if config.devices.is_empty() {
+        config.devices.push(DEFAULT_DEVICE);
+    }
+    let ram_size = config
+        .ram_size
+        .unwrap_or_else(|| DEFAULT_RAM_SIZE.max(executable.memory_size()));
+
+    let console = Box::new(std::io::stdout());
+    let machine = match config.reset_vector {
+        Some(reset_vector) => Machine::boot(
+            &executable,
+            reset_vector,
+            ram_size,
+            &config.devices,
+            console,
+        ),
+        None => Machine::new(&executable, ram_size, &config.devices, console),
+    };
+    let mut machine = machine.unwrap_or_else(|err| fail(&err));
+    machine.set_semihosting_root(config.semihosting_root.clone());
+    machine.processor_mut().set_profile(config.isa.clone());
+    flash::load_image(
+        &machine,
+        config.flash_file.as_deref(),
+        config.flash_endurance,
+    )
+    .unwrap_or_else(|err| fail(&err));
+    disk::load_image(&machine, config.disk_file.as_deref()).unwrap_or_else(|err| fail(&err));
+    for address in matches.values_of("serial_listen").into_iter().flatten() {
+        let local = machine
+            .listen_serial(address)
+            .unwrap_or_else(|err| fail(&err));
+        eprintln!("Serial device listening at {}", local);
+    }
+    if let Some(address) = matches.value_of("network_bind") {
+        let station = matches
+            .value_of("network_station")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let peers: Vec<&str> = matches
+            .values_of("network_peer")
+            .into_iter()
+            .flatten()
+            .collect();
+        machine
+            .link_network(station, address, &peers)
+            .unwrap_or_else(|err| fail(&err));
+    }
+    if let Some(framebuffer) = config.framebuffer {
+        framebuffer
+            .mount(&mut machine)
+            .unwrap_or_else(|err| fail(&err));
+    }
+    if matches.is_present("screenshot") && config.framebuffer.is_none() {
+        fail("A screenshot needs a framebuffer, see --framebuffer");
+    }
+
+    let args: Vec<&str> = std::iter::once(input)
+        .chain(matches.values_of("ARGS").into_iter().flatten())
+        .collect();
+    let mut env = Vec::new();
+    if matches.is_present("inherit_env") {
+        env.extend(std::env::vars().map(|(name, value)| format!("{}={}", name, value)));
+    }
+    env.extend(
+        matches
+            .values_of("env")
+            .into_iter()
+            .flatten()
+            .map(String::from),
+    );
+    let env: Vec<&str> = env.iter().map(String::as_str).collect();
+    machine
+        .pass_arguments(&args, &env)
+        .unwrap_or_else(|err| fail(&err));
+
+    let mut profiler = if matches.is_present("profile") || matches.is_present("folded") {
+        Some(Profiler::new(executable.entry_point()))
+    } else {
+        None
+    };
+    let mut statistics = matches.value_of("stats").map(|_| Statistics::new());
+    let mut predictors: Vec<PredictorSimulation> = matches
+        .values_of("branch_predictor")
+        .into_iter()
+        .flatten()
+        .map(|model| PredictorSimulation::new(model.parse().unwrap()))
+        .collect();
+    let mut pipeline = matches.value_of("pipeline").map(|_| {
+        let window = matches
+            .value_of("pipeline_window")
+            .map_or(0..pipeline::DEFAULT_WINDOW, |value| {
+                pipeline::parse_window(value).unwrap()
+            });
+        Pipeline::new(!matches.is_present("no_forwarding"), window)
+    });
+    let sources: Vec<TaintSource> = matches
+        .values_of("taint")
+        .into_iter()
+        .flatten()
+        .map(|source| source.parse().unwrap())
+        .collect();
+    let mut taint = if sources.is_empty() {
+        None
+    } else {
+        Some(TaintTracker::new(&machine, &sources))
+    };
+    let mut contract = if matches.is_present("check_mmio") {
+        Some(ContractChecker::new(machine.devices()))
+    } else {
+        None
+    };
+    let mut bus = EventBus::new(machine.processor());
+    if let Some(contract) = contract.as_mut() {
+        bus.subscribe(contract);
+    }
+    if let Some(statistics) = statistics.as_mut() {
+        bus.subscribe(statistics);
+    }
+    for simulation in predictors.iter_mut() {
+        bus.subscribe(simulation);
+    }
+    if let Some(pipeline) = pipeline.as_mut() {
+        bus.subscribe(pipeline);
+    }
+    let observing = profiler.is_some() || taint.is_some() || !bus.is_empty();
+    let mut observe = |address, word, machine: &Machine| {
+        if let Some(taint) = taint.as_mut() {
+            taint.record(address, word, machine);
+        }
+        let processor = machine.processor();
+        if let Some(profiler) = profiler.as_mut() {
+            profiler.record(address, word, processor);
+        }
+        bus.record(address, word, processor);
+    };
+    let stop = match matches.value_of("trace") {
+        Some(path) => {
+            let format = match matches.value_of("trace_format") {
+                Some(format) => format.parse().unwrap(),
+                None if path.ends_with(".csv") => TraceFormat::Csv,
+                None => TraceFormat::Chrome,
+            };
+            let mut filter = matches.value_of("trace_filter").map(|expression| {
+                TraceFilter::new(expression, executable.debug_info().cloned())
+                    .unwrap_or_else(|err| fail(&err))
+            });
+            let mut trace = || -> std::io::Result<Stop> {
+                let writer = BufWriter::new(File::create(path)?);
+                let mut tracer = Tracer::new(writer, format)?;
+                tracer.set_filter(filter.take());
+                let (stop, result) =
+                    run_traced(&mut machine, &config.limits, &mut tracer, &mut observe);
+                result?;
+                tracer.finish()?;
+                Ok(stop)
+            };
+            trace()
+                .unwrap_or_else(|err| fail(&format!("Writing trace \"{}\" failed: {}", path, err)))
+        }
+        None if observing => machine.run_inspected(&config.limits, observe),
+        None => match matches.value_of("realtime") {
+            Some(value) => machine.run_realtime(&config.limits, parse_frequency(value).unwrap()),
+            None => machine.run(&config.limits),
+        },
+    };
+    if let Some(profiler) = &profiler {
+        write_profile(profiler, &executable, &matches).unwrap_or_else(|err| fail(&err));
+    }
+    if let Some(statistics) = &statistics {
+        write_statistics(statistics, &executable, &matches).unwrap_or_else(|err| fail(&err));
+    }
+    if !predictors.is_empty() {
+        let penalty = matches
+            .value_of("mispredict_penalty")
+            .map_or(predictor::DEFAULT_PENALTY, |value| value.parse().unwrap());
+        let stderr = std::io::stderr();
+        predictor::write_report(&mut stderr.lock(), &predictors, machine.executed(), penalty)
+            .unwrap_or_else(|err| fail(&format!("Writing the branch prediction failed: {}", err)));
+    }
+    if let (Some(pipeline), Some(path)) = (&pipeline, matches.value_of("pipeline")) {
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            pipeline.write_diagram(&mut writer)?;
+            writer.flush()
+        };
+        write().unwrap_or_else(|err| {
+            fail(&format!(
+                "Writing the pipeline \"{}\" failed: {}",
+                path, err
+            ))
+        });
+    }
+    if let Some(taint) = &taint {
+        let stderr = std::io::stderr();
+        taint
+            .write_report(&mut stderr.lock(), executable.debug_info())
+            .unwrap_or_else(|err| fail(&format!("Writing the taint report failed: {}", err)));
+    }
+    if let Some(contract) = &contract {
+        let stderr = std::io::stderr();
+        contract
+            .write_report(&mut stderr.lock(), executable.debug_info())
+            .unwrap_or_else(|err| fail(&format!("Writing the MMIO report failed: {}", err)));
+    }
+    if let (Some(path), Some(memory)) = (&config.flash_file, machine.flash_memories().first()) {
+        memory.save(path).unwrap_or_else(|err| fail(&err));
+    }
+    if let (Some(path), Some(disk)) = (&config.disk_file, machine.disks().first()) {
+        disk.save(path).unwrap_or_else(|err| fail(&err));
+    }
+    if let (Some(framebuffer), Some(path)) = (config.framebuffer, matches.value_of("screenshot")) {
+        framebuffer
+            .screenshot(&machine, path)
+            .unwrap_or_else(|err| fail(&err));
+    }
+    if let Some(message) = describe_stop(&machine, stop) {
+        eprintln!("{}", message);
+    }
+    std::process::exit(exit_status(&machine, stop));
+}
+
+fn write_profile(
+    profiler: &Profiler,
+    executable: &Executable,
+    matches: &clap::ArgMatches,
+) -> Result<(), String> {
+    let debug_info = executable.debug_info();
+    if matches.is_present("profile") {
+        let stderr = std::io::stderr();
+        profiler
+            .write_report(&mut stderr.lock(), debug_info, PROFILE_ENTRIES)
+            .map_err(|err| format!("Writing the profile failed: {}", err))?;
+    }
+    if let Some(path) = matches.value_of("folded") {
+        let write = |path| -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            profiler.write_folded(&mut writer, debug_info)?;
+            writer.flush()
+        };
+        write(path).map_err(|err| format!("Writing folded stacks \"{}\" failed: {}", path, err))?;
+    }
+    Ok(())
+}
+
+fn write_statistics(
+    statistics: &Statistics,
+    executable: &Executable,
+    matches: &clap::ArgMatches,
+) -> Result<(), String> {
+    let path = matches.value_of("stats").unwrap();
+    let format = match matches.value_of("stats_format") {
+        Some(format) => format.parse().unwrap(),
+        None if path.ends_with(".csv") => StatisticsFormat::Csv,
+        None => StatisticsFormat::Json,
+    };
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        statistics.write(&mut writer, format, executable.debug_info())?;
+        writer.flush()
+    };
+    write().map_err(|err| format!("Writing statistics \"{}\" failed: {}", path, err))
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
@@ -0,0 +1,185 @@
+//! Screen layout of the `vmon` terminal monitor.
+//!
+//! The screen is drawn with [ratatui](https://ratatui.rs) into a frame of any backend, the binary uses the
+//! crossterm backend, which works in the terminals of every platform.
+
+use crate::debugger::Debugger;
+use num::FromPrimitive;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use vcpu::{RegisterId, Storage, REGISTER_COUNT, WORD_BYTES};
+
+/// Number of bytes in a line of the memory view.
+pub const MEMORY_LINE_BYTES: u32 = 16;
+const MEMORY_LINES: usize = 4;
+const REGISTER_ROWS: usize = REGISTER_COUNT / 2;
+const REGISTER_WIDTH: u16 = 30;
+
+/// Console output of the program, kept in memory so that it can be shown inside the monitor.
+#[derive(Clone, Default)]
+pub struct ConsoleBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl ConsoleBuffer {
+    /// Returns the last `count` lines of output, with control characters replaced.
+    pub fn last_lines(&self, count: usize) -> Vec<String> {
+        let bytes = self.0.borrow();
+        let text = String::from_utf8_lossy(&bytes);
+        let lines: Vec<&str> = text.split('\n').collect();
+        lines[lines.len().saturating_sub(count)..]
+            .iter()
+            .map(|line| {
+                line.chars()
+                    .map(|c| if c.is_control() { '.' } else { c })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    /// Removes and returns all output written so far.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for ConsoleBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What the monitor shows apart from the machine itself.
+pub struct View<'a> {
+    /// Start of the memory view.
+    pub memory_address: u32,
+    /// Shown in the first line, e.g. whether the program is running.
+    pub status: &'a str,
+    /// Shown in the last line, e.g. the keys or a prompt.
+    pub footer: &'a str,
+}
+
+/// Cuts or pads `text` to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    format!("{:<width$.width$}", text, width = width)
+}
+
+/// Draws `lines` below a title.
+fn pane(frame: &mut Frame, area: Rect, title: &str, lines: Vec<Line>) {
+    let block = Block::new()
+        .borders(Borders::TOP)
+        .title(format!(" {} ", title));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Draws the whole screen into `frame`.
+pub fn render(frame: &mut Frame, debugger: &Debugger, console: &ConsoleBuffer, view: &View) {
+    let machine = debugger.machine();
+    let processor = machine.processor();
+    let pc = processor.program_counter();
+
+    // registers on the left, disassembly around the program counter on the right
+    let pane_height = REGISTER_ROWS + 1;
+    let [status_area, top_area, memory_area, console_area, footer_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(pane_height as u16 + 1),
+        Constraint::Length(MEMORY_LINES as u16 + 1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [registers_area, code_area] =
+        Layout::horizontal([Constraint::Length(REGISTER_WIDTH), Constraint::Min(0)])
+            .spacing(1)
+            .areas(top_area);
+    frame.render_widget(Paragraph::new(view.status), status_area);
+
+    let registers = (0..pane_height)
+        .map(|row| match row {
+            0 => format!("PC   {:08X}  executed {}", pc, machine.executed()),
+            row => {
+                let register = |i: usize| {
+                    let id = RegisterId::from_usize(i).unwrap();
+                    format!(
+                        "{:<4} {:08X}",
+                        format!("{:?}", id),
+                        processor.register(id).u()
+                    )
+                };
+                format!(
+                    "{}    {}",
+                    register(row - 1),
+                    register(row - 1 + REGISTER_ROWS)
+                )
+            }
+        })
+        .map(Line::from)
+        .collect();
+    pane(frame, registers_area, "Registers", registers);
+
+    let first = pc.saturating_sub(((pane_height as u32 - 1) / 2) * WORD_BYTES);
+    let code = (0..pane_height as u32)
+        .map(|row| {
+            let address = first.wrapping_add(row * WORD_BYTES);
+            let marker = match (address == pc, debugger.has_breakpoint(address)) {
+                (true, true) => "*>",
+                (true, false) => "=>",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+            let text = format!("{} {}", marker, debugger.describe_instruction(address));
+            if address == pc {
+                // padded, so that the whole line is highlighted
+                let text = fit(&text, code_area.width as usize);
+                Line::styled(text, Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                Line::from(text)
+            }
+        })
+        .collect();
+    pane(frame, code_area, "Code", code);
+
+    let memory = machine.memory();
+    let memory_lines = (0..MEMORY_LINES as u32)
+        .map(|row| {
+            let address = view.memory_address.wrapping_add(row * MEMORY_LINE_BYTES);
+            let bytes: Vec<Option<u8>> = (0..MEMORY_LINE_BYTES)
+                .map(|i| memory.read_byte(address.wrapping_add(i)).ok())
+                .collect();
+            let hex: Vec<String> = bytes
+                .iter()
+                .map(|byte| byte.map_or("??".to_owned(), |byte| format!("{:02X}", byte)))
+                .collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|byte| match byte {
+                    Some(byte) if byte.is_ascii_graphic() || *byte == b' ' => *byte as char,
+                    _ => '.',
+                })
+                .collect();
+            Line::from(format!("{:08X}  {}  |{}|", address, hex.join(" "), ascii))
+        })
+        .collect();
+    pane(frame, memory_area, "Memory", memory_lines);
+
+    let console_height = console_area.height.saturating_sub(1) as usize;
+    let output = console
+        .last_lines(console_height)
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    pane(frame, console_area, "Console", output);
+    frame.render_widget(Paragraph::new(view.footer), footer_area);
+}
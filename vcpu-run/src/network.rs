@@ -0,0 +1,355 @@
+//! A packet device which connects several machines, e.g. for exercises with distributed algorithms.
+//!
+//! A `network` device has registers followed by a buffer for the packet to send and one for the received packet:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `STATUS`      | Bit 0 is set while a received packet is in the receive buffer. |
+//! | 4 | `STATION`     | Number of this machine in the network. |
+//! | 8 | `DESTINATION` | Station the next packet is sent to, or `0xFFFFFFFF` for all other stations. |
+//! | 12 | `SEND`       | Writing a length sends that many bytes of the transmit buffer. |
+//! | 16 | `RX_LENGTH`  | Length of the received packet, 0 if there is none. |
+//! | 20 | `RX_SOURCE`  | Station which sent the received packet. |
+//! | 24 | `NEXT`       | Writing replaces the received packet with the next one. |
+//! | 28 | `INTERRUPT`  | Interrupt line which is raised while a packet was received, [`NO_INTERRUPT`](../constant.NO_INTERRUPT.html) by default. |
+//! | 32 | `TX_BUFFER`  | [`MTU`](constant.MTU.html) bytes of the packet to send. |
+//! | 288 | `RX_BUFFER` | [`MTU`](constant.MTU.html) bytes of the received packet. |
+//!
+//! Programs either poll `STATUS` or enable the line in `INTERRUPT`, whose handler reads the packets until
+//! `STATUS` is clear, see [`ControlRegisters`](../../vcpu/struct.ControlRegisters.html). The registers, the
+//! receive buffer and the interrupt line are updated by the runner before the next instruction. At most [`QUEUE_PACKETS`](constant.QUEUE_PACKETS.html) packets
+//! wait to be received, further packets are dropped like on a real network.
+//!
+//! Machines in the same process are connected with a [`Hub`](struct.Hub.html), which
+//! [`run_connected`](fn.run_connected.html) runs in turns. Machines in different processes exchange UDP
+//! datagrams, see [`Machine::link_network`](../struct.Machine.html#method.link_network). A datagram holds
+//! the source and destination station as big endian words, followed by the packet.
+
+use crate::config::Device;
+use crate::machine::{Limits, Machine, Stop};
+use byteorder::ByteOrder;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::rc::Rc;
+use vcpu::Endian;
+
+pub const STATUS: u32 = 0;
+pub const STATION: u32 = 4;
+pub const DESTINATION: u32 = 8;
+/// Offset of the register which sends a packet when it is written.
+pub const SEND: u32 = 12;
+pub const RX_LENGTH: u32 = 16;
+pub const RX_SOURCE: u32 = 20;
+pub const NEXT: u32 = 24;
+pub const INTERRUPT: u32 = 28;
+pub const TX_BUFFER: u32 = 32;
+pub const RX_BUFFER: u32 = TX_BUFFER + MTU;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = RX_BUFFER + MTU;
+
+/// Largest packet in bytes.
+pub const MTU: u32 = 256;
+/// Bit of `STATUS` which is set while a packet was received.
+pub const STATUS_RECEIVED: u32 = 1;
+/// Destination which sends a packet to all other stations.
+pub const BROADCAST: u32 = 0xFFFF_FFFF;
+/// Most packets which wait to be received.
+pub const QUEUE_PACKETS: usize = 64;
+/// Number of instructions between two polls of a UDP link.
+pub const POLL_INTERVAL: u64 = 1 << 10;
+
+/// Bytes of the header of a UDP datagram, before the packet.
+const DATAGRAM_HEADER: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub source: u32,
+    pub destination: u32,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    /// Whether `station` receives the packet.
+    pub fn is_for(&self, station: u32) -> bool {
+        self.source != station && (self.destination == station || self.destination == BROADCAST)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    station: u32,
+    inbox: VecDeque<Packet>,
+    outbox: Vec<Packet>,
+    /// Whether the registers and the receive buffer changed since they were stored into the memory.
+    changed: bool,
+    dropped: u64,
+}
+
+/// The host side of a network device, which the machine and the network share.
+#[derive(Clone)]
+pub struct NetworkPort {
+    device: Device,
+    state: Rc<RefCell<State>>,
+}
+
+impl NetworkPort {
+    pub(crate) fn new(device: Device) -> NetworkPort {
+        let port = NetworkPort {
+            device,
+            state: Rc::default(),
+        };
+        port.state.borrow_mut().changed = true;
+        port
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    pub fn station(&self) -> u32 {
+        self.state.borrow().station
+    }
+
+    pub fn set_station(&self, station: u32) {
+        let mut state = self.state.borrow_mut();
+        state.station = station;
+        state.changed = true;
+    }
+
+    /// Queues a packet to be received by the program, unless the queue is full.
+    pub fn deliver(&self, packet: Packet) {
+        let mut state = self.state.borrow_mut();
+        if state.inbox.len() < QUEUE_PACKETS {
+            state.inbox.push_back(packet);
+            state.changed = true;
+        } else {
+            state.dropped += 1;
+        }
+    }
+
+    /// Whether a packet waits to be received by the program.
+    pub fn has_packet(&self) -> bool {
+        !self.state.borrow().inbox.is_empty()
+    }
+
+    /// Removes and returns the packets the program sent since they were taken the last time.
+    pub fn take_sent(&self) -> Vec<Packet> {
+        std::mem::take(&mut self.state.borrow_mut().outbox)
+    }
+
+    /// Number of packets which were dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.borrow().dropped
+    }
+
+    /// Handles a write of the program to a register of the device.
+    pub(crate) fn on_write(&self, memory: &[u8], address: u32) {
+        let word = |offset: u32| Endian::read_u32(&memory[offset as usize..]);
+        let mut state = self.state.borrow_mut();
+        match address & !3 {
+            SEND => {
+                let length = word(SEND).min(MTU) as usize;
+                let start = TX_BUFFER as usize;
+                let packet = Packet {
+                    source: state.station,
+                    destination: word(DESTINATION),
+                    data: memory[start..start + length].to_vec(),
+                };
+                state.outbox.push(packet);
+            }
+            NEXT => {
+                state.inbox.pop_front();
+                state.changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the program stores into `offset` itself, instead of the machine.
+    pub(crate) fn is_written_by_program(offset: u32) -> bool {
+        match offset & !3 {
+            DESTINATION | SEND | NEXT | INTERRUPT => true,
+            _ => (TX_BUFFER..RX_BUFFER).contains(&offset),
+        }
+    }
+
+    /// Returns the words to store into the registers from `STATUS` to `RX_SOURCE` and the receive buffer, if they
+    /// changed since they were returned the last time.
+    pub(crate) fn registers(&self) -> Option<([u32; 6], Vec<u8>)> {
+        let mut state = self.state.borrow_mut();
+        if !state.changed {
+            return None;
+        }
+        state.changed = false;
+        let mut buffer = vec![0; MTU as usize];
+        let registers = match state.inbox.front() {
+            Some(packet) => {
+                buffer[..packet.data.len()].copy_from_slice(&packet.data);
+                [
+                    STATUS_RECEIVED,
+                    state.station,
+                    0,
+                    0,
+                    packet.data.len() as u32,
+                    packet.source,
+                ]
+            }
+            None => [0, state.station, 0, 0, 0, 0],
+        };
+        Some((registers, buffer))
+    }
+}
+
+/// Connects the network devices of machines in the same process.
+#[derive(Default)]
+pub struct Hub {
+    ports: Vec<NetworkPort>,
+}
+
+impl Hub {
+    pub fn new() -> Hub {
+        Hub::default()
+    }
+
+    /// Connects the network devices of `machine`, which get the next station numbers starting at 0.
+    pub fn attach(&mut self, machine: &Machine) {
+        for port in machine.network_ports() {
+            port.set_station(self.ports.len() as u32);
+            self.ports.push(port.clone());
+        }
+    }
+
+    /// Passes the packets which were sent since the last call to the stations they are for.
+    pub fn deliver(&mut self) {
+        let packets: Vec<Packet> = self.ports.iter().flat_map(NetworkPort::take_sent).collect();
+        for packet in packets.iter() {
+            for port in self
+                .ports
+                .iter()
+                .filter(|port| packet.is_for(port.station()))
+            {
+                port.deliver(packet.clone());
+            }
+        }
+    }
+}
+
+/// Runs all `machines`, which are attached to `hub`, in turns of `instructions_per_turn` instructions, and
+/// delivers the packets after every turn. Returns why each machine stopped once all did.
+///
+/// The limits apply to every machine on its own, so a machine which exceeds them stops while the others
+/// keep running. The timeout is measured with the clock source of the first machine.
+pub fn run_connected(
+    machines: &mut [Machine],
+    hub: &mut Hub,
+    instructions_per_turn: u64,
+    limits: &Limits,
+) -> Vec<Stop> {
+    let mut stops: Vec<Option<Stop>> = vec![None; machines.len()];
+    let clock = match machines.first() {
+        Some(machine) => machine.clock_source(),
+        None => return Vec::new(),
+    };
+    let deadline = limits.timeout.map(|timeout| clock.now() + timeout);
+    while stops.iter().any(Option::is_none) {
+        for (machine, stop) in machines.iter_mut().zip(stops.iter_mut()) {
+            if stop.is_some() {
+                continue;
+            }
+            let mut turn = Limits {
+                max_instructions: Some(machine.executed() + instructions_per_turn.max(1)),
+                timeout: None,
+            };
+            if let Some(max) = limits.max_instructions {
+                turn.max_instructions = turn.max_instructions.map(|end| end.min(max));
+            }
+            *stop = match machine.run(&turn) {
+                Stop::InstructionLimit
+                    if limits
+                        .max_instructions
+                        .is_some_and(|max| machine.executed() >= max) =>
+                {
+                    Some(Stop::InstructionLimit)
+                }
+                Stop::InstructionLimit
+                    if deadline.is_some_and(|deadline| clock.now() >= deadline) =>
+                {
+                    Some(Stop::Timeout)
+                }
+                Stop::InstructionLimit => None,
+                stop => Some(stop),
+            };
+        }
+        hub.deliver();
+    }
+    stops.into_iter().map(Option::unwrap).collect()
+}
+
+/// Connects a network device to machines in other processes over UDP.
+pub struct UdpLink {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+}
+
+impl UdpLink {
+    /// Binds to `address` and sends the packets to all `peers`, which are the addresses other machines bound to.
+    pub fn bind(address: &str, peers: &[&str]) -> Result<UdpLink, String> {
+        let error = |err: std::io::Error| format!("Binding to {} failed: {}", address, err);
+        let socket = UdpSocket::bind(address).map_err(error)?;
+        socket.set_nonblocking(true).map_err(error)?;
+        let peers = peers
+            .iter()
+            .map(|peer| {
+                peer.parse()
+                    .map_err(|_| format!("Invalid address of a peer \"{}\"", peer))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(UdpLink { socket, peers })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    /// Sends the packets `port` sent and delivers the datagrams which arrived, without blocking.
+    pub fn poll(&mut self, port: &NetworkPort) {
+        for packet in port.take_sent() {
+            let mut datagram = Vec::with_capacity(DATAGRAM_HEADER + packet.data.len());
+            datagram.extend_from_slice(&packet.source.to_be_bytes());
+            datagram.extend_from_slice(&packet.destination.to_be_bytes());
+            datagram.extend_from_slice(&packet.data);
+            for peer in self.peers.iter() {
+                // a datagram which cannot be sent is lost, like on a real network
+                let _ = self.socket.send_to(&datagram, peer);
+            }
+        }
+
+        let mut buffer = [0; DATAGRAM_HEADER + MTU as usize];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, _)) if length >= DATAGRAM_HEADER => {
+                    let word = |offset: usize| {
+                        u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+                    };
+                    let packet = Packet {
+                        source: word(0),
+                        destination: word(4),
+                        data: buffer[DATAGRAM_HEADER..length].to_vec(),
+                    };
+                    if packet.is_for(port.station()) {
+                        port.deliver(packet);
+                    }
+                }
+                Ok(_) => {}
+                // reported for a datagram which a peer which is not running yet did not receive
+                Err(err)
+                    if err.kind() == ErrorKind::ConnectionRefused
+                        || err.kind() == ErrorKind::ConnectionReset => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
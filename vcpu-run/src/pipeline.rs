@@ -0,0 +1,326 @@
+//! A model of the classic 5-stage pipeline, which shows how long the executed instructions would take on it.
+//!
+//! The stages are instruction fetch (`IF`), decode and register read (`ID`), execute (`EX`), memory access
+//! (`ME`) and write back (`WB`). The model is in order with a single instruction per stage:
+//!
+//! - An instruction stalls in `ID` until the registers it reads are available. With forwarding, results of
+//!   `EX` can be used by the next instruction and loaded words one instruction later. Without it, they can
+//!   only be read in the cycle they are written back.
+//! - Branches are predicted not taken. Taken conditional branches, `JR` and `JLR` are resolved in `EX` and
+//!   flush the two instructions fetched after them, `JMP` and `JL` are resolved in `ID` and flush one.
+//!
+//! `HALT` is not counted, like in the other statistics. The diagram of a window of instructions has one line
+//! per instruction with its stages per cycle, in which `--` is a stall, and notes about the hazards.
+
+use crate::events::{Event, EventListener};
+use num::FromPrimitive;
+use std::io::prelude::*;
+use std::ops::Range;
+use vcpu::{
+    BankFunct, Opcode, RegisterId, Word, FUNCT_MASK, FUNCT_OFFSET, OPCODE_MASK, OPCODE_OFFSET,
+    RD_MASK, RD_OFFSET, REGISTER_COUNT, RETURN_ADDRESS, RS1_MASK, RS1_OFFSET, RS2_MASK, RS2_OFFSET,
+};
+
+/// Instructions in the diagram if the window has no count.
+pub const DEFAULT_WINDOW: u64 = 32;
+
+/// The cycles an instruction spent in the pipeline. Its `ME` and `WB` stages follow `EX` directly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineEntry {
+    pub address: u32,
+    pub word: Word,
+    pub fetch: u64,
+    pub decode: u64,
+    pub execute: u64,
+    /// Registers which were not available when the instruction could have been executed.
+    pub stalled_on: Vec<RegisterId>,
+    /// Registers whose values were forwarded instead of read from the register file.
+    pub forwarded: Vec<RegisterId>,
+    /// Instructions fetched after this one which were discarded because it jumped.
+    pub flushed: u64,
+}
+
+impl PipelineEntry {
+    pub fn stalls(&self) -> u64 {
+        self.execute - self.decode - 1
+    }
+
+    pub fn write_back(&self) -> u64 {
+        self.execute + 2
+    }
+}
+
+/// The registers an instruction reads and writes, and when its jumps are resolved.
+struct Operands {
+    reads: Vec<RegisterId>,
+    writes: Option<RegisterId>,
+    load: bool,
+    resolved_in_decode: bool,
+}
+
+fn operands(word: Word) -> Option<Operands> {
+    let register = |mask: u32, offset: u32| RegisterId::from_u32((word & mask) >> offset).unwrap();
+    let (rd, rs1, rs2) = (
+        register(RD_MASK, RD_OFFSET),
+        register(RS1_MASK, RS1_OFFSET),
+        register(RS2_MASK, RS2_OFFSET),
+    );
+    let opcode = Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET)?;
+    let (reads, writes) = match opcode {
+        Opcode::NOP | Opcode::HALT | Opcode::CALL | Opcode::JMP => (vec![], None),
+        Opcode::ALU | Opcode::FLOP => (vec![rs1, rs2], Some(rd)),
+        Opcode::LI | Opcode::LHI => (vec![], Some(rd)),
+        Opcode::SLO | Opcode::SHI => (vec![rd], Some(rd)),
+        Opcode::SB | Opcode::SH | Opcode::SW => (vec![rs1, rd], None),
+        Opcode::BEZ | Opcode::BNZ | Opcode::JR => (vec![rs1], None),
+        opcode if opcode.is_branch() => (vec![rd, rs1], None),
+        Opcode::JL => (vec![], Some(RETURN_ADDRESS)),
+        Opcode::JLR => (vec![rs1], Some(RETURN_ADDRESS)),
+        // only one of the registers is in the active bank
+        Opcode::BANK => match BankFunct::from_u32((word & FUNCT_MASK) >> FUNCT_OFFSET) {
+            Some(BankFunct::RDSH) => (vec![], Some(rd)),
+            Some(BankFunct::WRSH) => (vec![rs1], None),
+            _ => (vec![], None),
+        },
+        _ => (vec![rs1], Some(rd)),
+    };
+    let mut reads: Vec<RegisterId> = reads
+        .into_iter()
+        .filter(|r| *r != RegisterId::ZERO)
+        .collect();
+    reads.dedup();
+    Some(Operands {
+        reads,
+        writes: writes.filter(|r| *r != RegisterId::ZERO),
+        load: matches!(opcode, Opcode::LB | Opcode::LH | Opcode::LW),
+        resolved_in_decode: matches!(opcode, Opcode::JMP | Opcode::JL),
+    })
+}
+
+/// Runs the executed instructions through the pipeline model, as they retire on an
+/// [`EventBus`](../events/struct.EventBus.html).
+pub struct Pipeline {
+    forwarding: bool,
+    window: Range<u64>,
+    entries: Vec<PipelineEntry>,
+    /// First cycle in which `EX` can use the value of each register, by forwarding.
+    forwarded: [u64; REGISTER_COUNT],
+    /// Cycle in which each register is written back.
+    written: [u64; REGISTER_COUNT],
+    /// Whether the instruction which retires next jumped.
+    jumped: bool,
+    next_fetch: u64,
+    last_execute: u64,
+    instructions: u64,
+    cycles: u64,
+    stalls: u64,
+    flushed: u64,
+    forwards: u64,
+}
+
+impl Pipeline {
+    /// Creates a model which keeps the instructions with an index in `window` for the diagram.
+    pub fn new(forwarding: bool, window: Range<u64>) -> Pipeline {
+        Pipeline {
+            forwarding,
+            window,
+            entries: Vec::new(),
+            forwarded: [0; REGISTER_COUNT],
+            written: [0; REGISTER_COUNT],
+            jumped: false,
+            next_fetch: 0,
+            last_execute: 0,
+            instructions: 0,
+            cycles: 0,
+            stalls: 0,
+            flushed: 0,
+            forwards: 0,
+        }
+    }
+
+    fn retire(&mut self, address: u32, word: Word, jumped: bool) {
+        let operands = match operands(word) {
+            Some(operands) => operands,
+            None => return,
+        };
+        let fetch = self.next_fetch;
+        // ID becomes free when the instruction before moves on to EX
+        let decode = (fetch + 1).max(self.last_execute);
+        let earliest = decode + 1;
+        let available = |register: RegisterId| {
+            if self.forwarding {
+                self.forwarded[register as usize]
+            } else {
+                self.written[register as usize] + 1
+            }
+        };
+        let execute = operands
+            .reads
+            .iter()
+            .map(|r| available(*r))
+            .fold(earliest, u64::max);
+        let stalled_on: Vec<RegisterId> = operands
+            .reads
+            .iter()
+            .copied()
+            .filter(|r| available(*r) > earliest)
+            .collect();
+        let forwarded: Vec<RegisterId> = operands
+            .reads
+            .iter()
+            .copied()
+            .filter(|r| self.forwarding && self.written[*r as usize] + 1 > execute)
+            .collect();
+        if let Some(register) = operands.writes {
+            self.forwarded[register as usize] = execute + if operands.load { 2 } else { 1 };
+            self.written[register as usize] = execute + 2;
+        }
+
+        let (next_fetch, flushed) = match (jumped, operands.resolved_in_decode) {
+            (false, _) => (decode, 0),
+            (true, true) => (decode + 1, 1),
+            (true, false) => (execute + 1, 2),
+        };
+        self.next_fetch = next_fetch;
+        self.last_execute = execute;
+        self.cycles = execute + 3;
+        self.stalls += execute - earliest;
+        self.flushed += flushed;
+        self.forwards += forwarded.len() as u64;
+        if self.window.contains(&self.instructions) {
+            self.entries.push(PipelineEntry {
+                address,
+                word,
+                fetch,
+                decode,
+                execute,
+                stalled_on,
+                forwarded,
+                flushed,
+            });
+        }
+        self.instructions += 1;
+    }
+
+    /// Number of instructions recorded.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Number of cycles until the last instruction was written back.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Number of cycles instructions stalled in `ID`.
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    /// Number of instructions which were fetched and discarded.
+    pub fn flushed(&self) -> u64 {
+        self.flushed
+    }
+
+    /// Number of operands which were forwarded.
+    pub fn forwards(&self) -> u64 {
+        self.forwards
+    }
+
+    /// The instructions in the window.
+    pub fn entries(&self) -> &[PipelineEntry] {
+        &self.entries
+    }
+
+    /// Writes the totals and the diagram of the window.
+    pub fn write_diagram<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "Pipeline {} forwarding: {} instructions in {} cycles, CPI {:.3}",
+            if self.forwarding { "with" } else { "without" },
+            self.instructions,
+            self.cycles,
+            self.cycles as f64 / self.instructions.max(1) as f64
+        )?;
+        writeln!(
+            writer,
+            "{} stall cycles, {} flushed instructions, {} forwarded operands",
+            self.stalls, self.flushed, self.forwards
+        )?;
+        let (first, last) = match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => (first.fetch, last.write_back()),
+            _ => return Ok(()),
+        };
+        writeln!(writer)?;
+        let mut header = format!("{:<36}", "Cycle");
+        for cycle in first..=last {
+            header.push_str(&format!("{:<3}", cycle % 1000));
+        }
+        writeln!(writer, "{}", header.trim_end())?;
+        for entry in self.entries.iter() {
+            let text = vasm::disassemble(entry.word).unwrap_or_else(|| "???".to_owned());
+            let mut line = format!("0x{:08X}  {:<24}", entry.address, text);
+            for cycle in first..=entry.write_back() {
+                line.push_str(match cycle {
+                    c if c < entry.fetch => "   ",
+                    c if c == entry.fetch => "IF ",
+                    c if c == entry.decode => "ID ",
+                    c if c < entry.execute => "-- ",
+                    c if c == entry.execute => "EX ",
+                    c if c == entry.execute + 1 => "ME ",
+                    _ => "WB ",
+                });
+            }
+            let mut notes = Vec::new();
+            if !entry.stalled_on.is_empty() {
+                notes.push(format!(
+                    "stall {} ({})",
+                    entry.stalls(),
+                    register_list(&entry.stalled_on)
+                ));
+            }
+            if !entry.forwarded.is_empty() {
+                notes.push(format!("forward {}", register_list(&entry.forwarded)));
+            }
+            if entry.flushed > 0 {
+                notes.push(format!("flush {}", entry.flushed));
+            }
+            if !notes.is_empty() {
+                line.push(' ');
+                line.push_str(&notes.join(", "));
+            }
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl EventListener for Pipeline {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::Branch { taken, .. } => self.jumped = taken,
+            Event::Retire { address, word } => {
+                let jumped = std::mem::replace(&mut self.jumped, false);
+                self.retire(address, word, jumped);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn register_list(registers: &[RegisterId]) -> String {
+    let names: Vec<String> = registers.iter().map(|r| format!("${:?}", r)).collect();
+    names.join(", ")
+}
+
+/// Parses a window of instructions like `START[:COUNT]`, e.g. `100:20` for the diagram of 20 instructions after
+/// the first 100.
+pub fn parse_window(value: &str) -> Result<Range<u64>, String> {
+    let invalid = || format!("Invalid window \"{}\", expected START[:COUNT]", value);
+    let (start, count) = match value.split_once(':') {
+        Some((start, count)) => (start, count.parse().map_err(|_| invalid())?),
+        None => (value, DEFAULT_WINDOW),
+    };
+    let start: u64 = start.parse().map_err(|_| invalid())?;
+    Ok(start..start.saturating_add(count))
+}
@@ -0,0 +1,117 @@
+//! Devices which are implemented outside of this crate, e.g. the hardware of a course which ships its own
+//! runner.
+//!
+//! A plugin implements [`DevicePlugin`](trait.DevicePlugin.html) and is registered once with
+//! [`register`](fn.register.html) before the machine files are read. Machine files then use its name like the
+//! name of a built-in device:
+//!
+//! ```toml
+//! [[device]]
+//! kind = "timer"
+//! address = 0xFFFF2000
+//! ```
+//!
+//! The memory a plugin creates is mounted like the registers of the built-in devices, and the writes to it are
+//! recorded in the [`DeviceLog`](../struct.DeviceLog.html). Plugins which need a host side share their state
+//! between the memory and their own handles, like the built-in devices do.
+
+use crate::config::{Device, DeviceKind};
+use crate::contract::Register;
+use crate::machine::{DeviceLog, DeviceWrite};
+use std::sync::Mutex;
+use vcpu::{Storage, StorageMut};
+
+/// A kind of device which a machine can mount.
+pub trait DevicePlugin: Sync {
+    /// Name of the device in machine files and on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Number of bytes the device occupies in the address space.
+    fn size(&self) -> u32;
+
+    /// Creates the memory of `device`, which must be [`size`](#tymethod.size) bytes long.
+    fn create(&self, device: Device) -> Box<dyn StorageMut>;
+
+    /// The registers of the device, which the [`contract`](../contract/index.html) checker checks the accesses
+    /// against. Without registers, the accesses are not checked.
+    fn registers(&self) -> Vec<Register> {
+        Vec::new()
+    }
+}
+
+static PLUGINS: Mutex<Vec<&'static dyn DevicePlugin>> = Mutex::new(Vec::new());
+
+/// Registers `plugin`, unless a built-in device or another plugin already has its name.
+pub fn register(plugin: &'static dyn DevicePlugin) -> Result<(), String> {
+    let name = plugin.name();
+    let exists = || format!("There already is a device \"{}\"", name);
+    if builtin(name) {
+        return Err(exists());
+    }
+    let mut plugins = PLUGINS.lock().unwrap();
+    if plugins.iter().any(|other| other.name() == name) {
+        return Err(exists());
+    }
+    plugins.push(plugin);
+    Ok(())
+}
+
+/// Returns the plugin registered as `name`.
+pub fn find(name: &str) -> Option<&'static dyn DevicePlugin> {
+    let plugins = PLUGINS.lock().unwrap();
+    plugins.iter().find(|plugin| plugin.name() == name).copied()
+}
+
+fn builtin(name: &str) -> bool {
+    DeviceKind::BUILTIN
+        .iter()
+        .any(|kind| kind.to_string() == name)
+}
+
+/// The memory of a plugin device, which records the writes to it.
+pub(crate) struct PluginMemory {
+    device: Device,
+    memory: Box<dyn StorageMut>,
+    device_log: DeviceLog,
+}
+
+impl PluginMemory {
+    pub(crate) fn new(
+        device: Device,
+        plugin: &dyn DevicePlugin,
+        device_log: DeviceLog,
+    ) -> PluginMemory {
+        PluginMemory {
+            device,
+            memory: plugin.create(device),
+            device_log,
+        }
+    }
+}
+
+impl Storage for PluginMemory {
+    fn length(&self) -> u32 {
+        self.memory.length()
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        self.memory.check_range(address, length)
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        self.memory.read(address, size)
+    }
+}
+
+impl StorageMut for PluginMemory {
+    fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        self.memory.write(address, size, value)?;
+        self.device_log.push(DeviceWrite {
+            device: self.device,
+            offset: address,
+            size,
+            value,
+        });
+        Ok(())
+    }
+}
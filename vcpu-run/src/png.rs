@@ -0,0 +1,44 @@
+//! Writes RGBA images as PNG files with the [png](https://docs.rs/png) crate, e.g. screenshots of a framebuffer.
+//!
+//! The encoder settings are fixed, so the same pixels always give the same bytes. That makes the files usable
+//! as golden images, see [`golden::compare_image`](../golden/fn.compare_image.html).
+
+use ::png::{BitDepth, ColorType, Compression, Encoder};
+use std::path::Path;
+
+/// Encodes `width` x `height` pixels with 4 bytes each, row by row.
+pub fn encode(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || pixels.len() as u64 != u64::from(width) * u64::from(height) * 4
+    {
+        return Err(format!(
+            "{} bytes are no image of {}x{} pixels",
+            pixels.len(),
+            width,
+            height
+        ));
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = Encoder::new(&mut png, width, height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.set_compression(Compression::Default);
+    let failed = |err: ::png::EncodingError| format!("Encoding the image failed: {}", err);
+    let mut writer = encoder.write_header().map_err(failed)?;
+    writer.write_image_data(pixels).map_err(failed)?;
+    writer.finish().map_err(failed)?;
+    Ok(png)
+}
+
+/// Encodes the pixels like [`encode`](fn.encode.html) and writes them to the file at `path`.
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let png = encode(width, height, pixels)?;
+    std::fs::write(path, png)
+        .map_err(|err| format!("Writing image \"{}\" failed: {}", path.display(), err))
+}
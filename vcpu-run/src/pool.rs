@@ -0,0 +1,134 @@
+//! Runs a program many times in parallel with different inputs, e.g. to evaluate the individuals of a genetic
+//! program or the inputs of a fuzzer.
+//!
+//! A [`SimulationPool`](struct.SimulationPool.html) starts a fresh machine of its
+//! [`Session`](../debugger/struct.Session.html) for every [`Job`](struct.Job.html), so runs do not influence
+//! each other. The machines are created on the worker threads, since a machine cannot move between threads.
+//! Every run stops at the limits of the session, so programs which never halt should be given an instruction
+//! limit or a timeout.
+
+use crate::debugger::Session;
+use crate::exit_status;
+use crate::machine::Stop;
+use crate::monitor::ConsoleBuffer;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use vcpu::{Storage, StorageMut};
+
+/// The input of a single run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Job {
+    /// Arguments passed to the program, which replace those of the session.
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+    /// Bytes which are stored at their address before the program starts.
+    pub memory: Vec<(u32, Vec<u8>)>,
+}
+
+/// The result of a single run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub stop: Stop,
+    /// Status the runners would exit with, see [`exit_status`](../fn.exit_status.html).
+    pub status: i32,
+    pub executed: u64,
+    /// The bytes of the output ranges of the pool after the program stopped. Bytes which cannot be read are 0.
+    pub memory: Vec<Vec<u8>>,
+    /// Everything the program wrote to the console.
+    pub console: Vec<u8>,
+}
+
+/// Runs jobs on a number of threads.
+pub struct SimulationPool {
+    session: Session,
+    threads: usize,
+    outputs: Vec<Range<u32>>,
+}
+
+impl SimulationPool {
+    /// Creates a pool which runs the program of `session` on `threads` threads, or one per core if it is 0.
+    pub fn new(session: Session, threads: usize) -> SimulationPool {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        };
+        SimulationPool {
+            session,
+            threads,
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Sets the ranges of memory which are collected after every run.
+    pub fn set_outputs(&mut self, outputs: Vec<Range<u32>>) {
+        self.outputs = outputs;
+    }
+
+    /// Runs all `jobs` and returns their outcomes in the same order, or why a machine could not be started,
+    /// e.g. because the memory of the job lies outside of the RAM.
+    pub fn run(&self, jobs: &[Job]) -> Vec<Result<Outcome, String>> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; jobs.len()]);
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads.min(jobs.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let job = match jobs.get(index) {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let result = self.run_job(job);
+                    results.lock().unwrap()[index] = Some(result);
+                });
+            }
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(Option::unwrap)
+            .collect()
+    }
+
+    fn run_job(&self, job: &Job) -> Result<Outcome, String> {
+        let console = ConsoleBuffer::default();
+        let args: Vec<&str> = job.args.iter().map(String::as_str).collect();
+        let env: Vec<&str> = job.env.iter().map(String::as_str).collect();
+        let mut machine =
+            self.session
+                .start_with_arguments(Box::new(console.clone()), &args, &env)?;
+        for (address, bytes) in job.memory.iter() {
+            for (i, byte) in bytes.iter().enumerate() {
+                let at = address.wrapping_add(i as u32);
+                machine
+                    .memory_mut()
+                    .write_byte(at, *byte)
+                    .map_err(|_| format!("Cannot store the input at 0x{:08X}", at))?;
+            }
+        }
+
+        let stop = machine.run(&self.session.config.limits);
+        let memory = self
+            .outputs
+            .iter()
+            .map(|range| {
+                range
+                    .clone()
+                    .map(|address| machine.memory().read_byte(address).unwrap_or(0))
+                    .collect()
+            })
+            .collect();
+        Ok(Outcome {
+            stop,
+            status: exit_status(&machine, stop),
+            executed: machine.executed(),
+            memory,
+            console: console.take(),
+        })
+    }
+}
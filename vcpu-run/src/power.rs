@@ -0,0 +1,46 @@
+//! A power controller, which gives operating systems and other long running programs an orderly way to switch
+//! the machine off or to restart it.
+//!
+//! A `power` device is a block of three words:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `COMMAND` | Writing [`SHUTDOWN`](constant.SHUTDOWN.html) or [`REBOOT`](constant.REBOOT.html) performs the command. |
+//! | 4 | `STATUS`  | The exit status of a shutdown. |
+//! | 8 | `BOOTS`   | Number of times the machine was restarted. |
+//!
+//! The runner performs the command before the next instruction. A shutdown stops the machine like `HALT` with
+//! `STATUS` in `$V0`, so the runners exit with its lowest byte. A reboot resets the processor, loads the
+//! program into the RAM again and passes the same arguments to it, see
+//! [`Machine::reboot`](../struct.Machine.html#method.reboot). The devices keep their state, so e.g. a flash
+//! device still holds what the program stored in it. `BOOTS` is stored by the runner.
+
+/// Offset of the register which performs a command when it is written.
+pub const COMMAND: u32 = 0;
+pub const STATUS: u32 = 4;
+pub const BOOTS: u32 = 8;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = 12;
+
+/// Commands of `COMMAND`, other values are ignored.
+pub const SHUTDOWN: u32 = 1;
+pub const REBOOT: u32 = 2;
+
+/// A command the program wrote to a power device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Stops the machine with the exit status.
+    Shutdown(u32),
+    Reboot,
+}
+
+impl Command {
+    /// Decodes the value written to `COMMAND`, with the value of `STATUS`.
+    pub fn decode(command: u32, status: u32) -> Option<Command> {
+        match command {
+            SHUTDOWN => Some(Command::Shutdown(status)),
+            REBOOT => Some(Command::Reboot),
+            _ => None,
+        }
+    }
+}
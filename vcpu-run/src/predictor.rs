@@ -0,0 +1,280 @@
+//! Simulation of branch predictors, which guess the direction of every conditional branch before it executes.
+//!
+//! The models are:
+//!
+//! | Name           | Prediction                                                                           |
+//! |----------------|--------------------------------------------------------------------------------------|
+//! | `always-taken` | Every branch is taken                                                                |
+//! | `bimodal`      | A 2-bit saturating counter per branch, selected by the low bits of its address       |
+//! | `gshare`       | Like `bimodal`, but the address is combined with the directions of the last branches |
+//!
+//! The tables of `bimodal` and `gshare` have 2<sup>bits</sup> counters, which is given after the name like in
+//! `gshare:12`. Their counters start at weakly not taken.
+//!
+//! There is no timing model besides one instruction per cycle, so the cost of the mispredictions is estimated
+//! as a fixed penalty of cycles per misprediction, like the stages of a pipeline which have to be flushed.
+
+use crate::events::{Event, EventListener};
+use std::io::prelude::*;
+use std::str::FromStr;
+use vcpu::WORD_BYTES;
+
+/// Size of the tables if no number of bits is given.
+pub const DEFAULT_TABLE_BITS: u32 = 10;
+/// Largest number of bits of a table, which then has 4 Mi counters.
+pub const MAX_TABLE_BITS: u32 = 22;
+/// Cycles a misprediction costs by default, i.e. a branch which is resolved in the third stage.
+pub const DEFAULT_PENALTY: u64 = 2;
+
+/// A model which predicts whether branches are taken and learns from their outcomes.
+pub trait BranchPredictor {
+    /// Returns whether the branch at `address` is predicted to be taken.
+    fn predict(&self, address: u32) -> bool;
+
+    /// Updates the model with the direction the branch at `address` actually went.
+    fn update(&mut self, address: u32, taken: bool);
+}
+
+pub struct AlwaysTaken;
+
+impl BranchPredictor for AlwaysTaken {
+    fn predict(&self, _address: u32) -> bool {
+        true
+    }
+
+    fn update(&mut self, _address: u32, _taken: bool) {}
+}
+
+/// Saturating 2-bit counters, of which the values 2 and 3 predict taken.
+struct CounterTable {
+    counters: Vec<u8>,
+    mask: u32,
+}
+
+impl CounterTable {
+    fn new(bits: u32) -> CounterTable {
+        CounterTable {
+            counters: vec![1; 1 << bits],
+            mask: (1 << bits) - 1,
+        }
+    }
+
+    fn predict(&self, index: u32) -> bool {
+        self.counters[(index & self.mask) as usize] >= 2
+    }
+
+    fn update(&mut self, index: u32, taken: bool) {
+        let counter = &mut self.counters[(index & self.mask) as usize];
+        *counter = if taken {
+            (*counter + 1).min(3)
+        } else {
+            counter.saturating_sub(1)
+        };
+    }
+}
+
+pub struct Bimodal {
+    table: CounterTable,
+}
+
+impl Bimodal {
+    pub fn new(bits: u32) -> Bimodal {
+        Bimodal {
+            table: CounterTable::new(bits),
+        }
+    }
+}
+
+impl BranchPredictor for Bimodal {
+    fn predict(&self, address: u32) -> bool {
+        self.table.predict(address / WORD_BYTES)
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        self.table.update(address / WORD_BYTES, taken)
+    }
+}
+
+pub struct Gshare {
+    table: CounterTable,
+    /// Directions of the last branches, the latest in the lowest bit.
+    history: u32,
+}
+
+impl Gshare {
+    pub fn new(bits: u32) -> Gshare {
+        Gshare {
+            table: CounterTable::new(bits),
+            history: 0,
+        }
+    }
+
+    fn index(&self, address: u32) -> u32 {
+        (address / WORD_BYTES) ^ self.history
+    }
+}
+
+impl BranchPredictor for Gshare {
+    fn predict(&self, address: u32) -> bool {
+        self.table.predict(self.index(address))
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        self.table.update(self.index(address), taken);
+        self.history = (self.history << 1 | u32::from(taken)) & self.table.mask;
+    }
+}
+
+/// A predictor model and the size of its table, e.g. parsed from `gshare:12`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PredictorKind {
+    AlwaysTaken,
+    Bimodal(u32),
+    Gshare(u32),
+}
+
+impl PredictorKind {
+    pub fn create(self) -> Box<dyn BranchPredictor> {
+        match self {
+            PredictorKind::AlwaysTaken => Box::new(AlwaysTaken),
+            PredictorKind::Bimodal(bits) => Box::new(Bimodal::new(bits)),
+            PredictorKind::Gshare(bits) => Box::new(Gshare::new(bits)),
+        }
+    }
+}
+
+impl std::fmt::Display for PredictorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PredictorKind::AlwaysTaken => write!(f, "always-taken"),
+            PredictorKind::Bimodal(bits) => write!(f, "bimodal:{}", bits),
+            PredictorKind::Gshare(bits) => write!(f, "gshare:{}", bits),
+        }
+    }
+}
+
+impl FromStr for PredictorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, bits) = match s.split_once(':') {
+            Some((name, bits)) => match bits.parse() {
+                Ok(bits) if (1..=MAX_TABLE_BITS).contains(&bits) => (name, Some(bits)),
+                _ => {
+                    return Err(format!(
+                        "Invalid table size \"{}\", expected 1 to {} bits",
+                        bits, MAX_TABLE_BITS
+                    ))
+                }
+            },
+            None => (s, None),
+        };
+        let bits = bits.unwrap_or(DEFAULT_TABLE_BITS);
+        match name {
+            "always-taken" if s == name => Ok(PredictorKind::AlwaysTaken),
+            "bimodal" => Ok(PredictorKind::Bimodal(bits)),
+            "gshare" => Ok(PredictorKind::Gshare(bits)),
+            _ => Err(format!("Unknown branch predictor \"{}\"", s)),
+        }
+    }
+}
+
+/// Runs a predictor on the branches of a program, which are resolved on an
+/// [`EventBus`](../events/struct.EventBus.html).
+pub struct PredictorSimulation {
+    kind: PredictorKind,
+    predictor: Box<dyn BranchPredictor>,
+    branches: u64,
+    mispredicted: u64,
+}
+
+impl PredictorSimulation {
+    pub fn new(kind: PredictorKind) -> PredictorSimulation {
+        PredictorSimulation {
+            kind,
+            predictor: kind.create(),
+            branches: 0,
+            mispredicted: 0,
+        }
+    }
+
+    pub fn kind(&self) -> PredictorKind {
+        self.kind
+    }
+
+    /// Number of conditional branches executed.
+    pub fn branches(&self) -> u64 {
+        self.branches
+    }
+
+    pub fn mispredicted(&self) -> u64 {
+        self.mispredicted
+    }
+
+    /// Fraction of the branches which were predicted correctly, 1 if there were none.
+    pub fn accuracy(&self) -> f64 {
+        if self.branches == 0 {
+            1.0
+        } else {
+            (self.branches - self.mispredicted) as f64 / self.branches as f64
+        }
+    }
+
+    /// Cycles lost to mispredictions which cost `penalty` cycles each.
+    pub fn penalty_cycles(&self, penalty: u64) -> u64 {
+        self.mispredicted.saturating_mul(penalty)
+    }
+}
+
+impl EventListener for PredictorSimulation {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::Branch {
+                address,
+                opcode,
+                taken,
+                ..
+            } if opcode.is_branch() => {
+                self.branches += 1;
+                if self.predictor.predict(address) != taken {
+                    self.mispredicted += 1;
+                }
+                self.predictor.update(address, taken);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes the accuracy of every simulation and the cycles it would take to execute `instructions` with it.
+pub fn write_report<W: Write>(
+    writer: &mut W,
+    simulations: &[PredictorSimulation],
+    instructions: u64,
+    penalty: u64,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "Branch prediction with a penalty of {} cycles",
+        penalty
+    )?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "  Predictor        Branches   Mispredicted Accuracy  Cycles     CPI"
+    )?;
+    for simulation in simulations {
+        let cycles = instructions.saturating_add(simulation.penalty_cycles(penalty));
+        writeln!(
+            writer,
+            "  {:<16} {:<10} {:<12} {:>7.2}%  {:<10} {:.3}",
+            simulation.kind().to_string(),
+            simulation.branches(),
+            simulation.mispredicted(),
+            simulation.accuracy() * 100.0,
+            cycles,
+            cycles as f64 / instructions.max(1) as f64
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Counts which instructions, functions and source lines a program spends its time in.
+//!
+//! Every executed instruction is counted. Calls are recognized by the link register convention: `JL` and `JLR`
+//! enter a function, and a `JR` to the return address of a function on the call stack leaves it, together
+//! with any functions it called which did not return themselves.
+//!
+//! Besides a text report, the profile can be written as folded stacks, one line with the semicolon separated
+//! call stack and its instruction count per stack, which flame graph tools like `flamegraph.pl` or
+//! `inferno-flamegraph` read.
+
+use num::FromPrimitive;
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use vcpu::{Opcode, Processor, Word, OPCODE_MASK, OPCODE_OFFSET, WORD_BYTES};
+use vex::debug::{DebugInfo, SymbolKind};
+
+/// A function in the call tree, which is called from the function of its parent.
+struct Node {
+    function: u32,
+    /// Where the function returns to.
+    return_address: u32,
+    parent: usize,
+    children: HashMap<u32, usize>,
+    /// Instructions executed in this function itself.
+    count: u64,
+}
+
+/// How many instructions were executed in a function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub address: u32,
+    /// Instructions of the function itself.
+    pub self_count: u64,
+    /// Instructions of the function and all functions called by it.
+    pub total_count: u64,
+}
+
+/// Collects a profile while the program runs, see [`Machine::run_observed`](../struct.Machine.html#method.run_observed).
+pub struct Profiler {
+    counts: HashMap<u32, u64>,
+    /// The call tree, whose root is the entry point.
+    nodes: Vec<Node>,
+    current: usize,
+    total: u64,
+}
+
+const ROOT: usize = 0;
+
+impl Profiler {
+    pub fn new(entry_point: u32) -> Profiler {
+        Profiler {
+            counts: HashMap::new(),
+            nodes: vec![Node {
+                function: entry_point,
+                return_address: 0,
+                parent: ROOT,
+                children: HashMap::new(),
+                count: 0,
+            }],
+            current: ROOT,
+            total: 0,
+        }
+    }
+
+    /// Records the instruction `word` at `address`, which the `processor` just executed.
+    pub fn record(&mut self, address: u32, word: Word, processor: &Processor) {
+        *self.counts.entry(address).or_insert(0) += 1;
+        self.nodes[self.current].count += 1;
+        self.total += 1;
+
+        let pc = processor.program_counter();
+        match Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+            Some(Opcode::JL) | Some(Opcode::JLR) => {
+                let next = self.nodes.len();
+                let current = self.current;
+                let child = *self.nodes[current].children.entry(pc).or_insert(next);
+                if child == next {
+                    self.nodes.push(Node {
+                        function: pc,
+                        return_address: 0,
+                        parent: current,
+                        children: HashMap::new(),
+                        count: 0,
+                    });
+                }
+                self.nodes[child].return_address = address.wrapping_add(WORD_BYTES);
+                self.current = child;
+            }
+            Some(Opcode::JR) => {
+                let mut node = self.current;
+                while node != ROOT {
+                    if self.nodes[node].return_address == pc {
+                        self.current = self.nodes[node].parent;
+                        break;
+                    }
+                    node = self.nodes[node].parent;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Number of instructions recorded.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of times the instruction at each address was executed.
+    pub fn instruction_counts(&self) -> &HashMap<u32, u64> {
+        &self.counts
+    }
+
+    /// Returns the functions ordered by the instructions they executed themselves, most first.
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<u32, FunctionProfile> = HashMap::new();
+        let mut on_path = HashSet::new();
+        self.visit(ROOT, &mut on_path, &mut functions);
+        let mut functions: Vec<FunctionProfile> = functions.into_values().collect();
+        functions.sort_by_key(|f| (std::cmp::Reverse(f.self_count), f.address));
+        functions
+    }
+
+    /// Adds the counts of `node` and its subtree to `functions` and returns the total count of the subtree.
+    /// Recursive calls are only counted once for the total of a function.
+    fn visit(
+        &self,
+        node: usize,
+        on_path: &mut HashSet<u32>,
+        functions: &mut HashMap<u32, FunctionProfile>,
+    ) -> u64 {
+        let function = self.nodes[node].function;
+        let outermost = on_path.insert(function);
+        let mut total = self.nodes[node].count;
+        for child in self.nodes[node].children.values() {
+            total += self.visit(*child, on_path, functions);
+        }
+        if outermost {
+            on_path.remove(&function);
+        }
+        let profile = functions.entry(function).or_insert(FunctionProfile {
+            address: function,
+            self_count: 0,
+            total_count: 0,
+        });
+        profile.self_count += self.nodes[node].count;
+        if outermost {
+            profile.total_count += total;
+        }
+        total
+    }
+
+    /// Writes the hottest `count` functions and source lines.
+    pub fn write_report<W: Write>(
+        &self,
+        writer: &mut W,
+        debug_info: Option<&DebugInfo>,
+        count: usize,
+    ) -> std::io::Result<()> {
+        let percent = |value: u64| value as f64 * 100.0 / self.total.max(1) as f64;
+        writeln!(writer, "Profile of {} instructions", self.total)?;
+        writeln!(writer)?;
+        writeln!(writer, "  Self         %     Total        %  Function")?;
+        for function in self.functions().iter().take(count) {
+            writeln!(
+                writer,
+                "  {:<10} {:>5.1}%  {:<10} {:>5.1}%  {}",
+                function.self_count,
+                percent(function.self_count),
+                function.total_count,
+                percent(function.total_count),
+                function_name(debug_info, function.address)
+            )?;
+        }
+
+        if let Some(debug_info) = debug_info {
+            let mut lines: HashMap<(u32, u32), u64> = HashMap::new();
+            for (address, executed) in self.counts.iter() {
+                if let Some(entry) = debug_info.line_at(*address) {
+                    *lines.entry((entry.file, entry.line)).or_insert(0) += executed;
+                }
+            }
+            let mut lines: Vec<_> = lines.into_iter().collect();
+            lines.sort_by_key(|(line, executed)| (std::cmp::Reverse(*executed), *line));
+            writeln!(writer)?;
+            writeln!(writer, "  Count        %  Line")?;
+            for ((file, line), executed) in lines.into_iter().take(count) {
+                let file = debug_info
+                    .files
+                    .get(file as usize)
+                    .map_or("?", String::as_str);
+                writeln!(
+                    writer,
+                    "  {:<10} {:>5.1}%  {}:{}",
+                    executed,
+                    percent(executed),
+                    file,
+                    line
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the profile as folded stacks.
+    pub fn write_folded<W: Write>(
+        &self,
+        writer: &mut W,
+        debug_info: Option<&DebugInfo>,
+    ) -> std::io::Result<()> {
+        let mut stack = Vec::new();
+        self.write_folded_node(writer, debug_info, ROOT, &mut stack)
+    }
+
+    fn write_folded_node<W: Write>(
+        &self,
+        writer: &mut W,
+        debug_info: Option<&DebugInfo>,
+        node: usize,
+        stack: &mut Vec<String>,
+    ) -> std::io::Result<()> {
+        let node = &self.nodes[node];
+        stack.push(function_name(debug_info, node.function));
+        if node.count > 0 {
+            writeln!(writer, "{} {}", stack.join(";"), node.count)?;
+        }
+        let mut children: Vec<usize> = node.children.values().cloned().collect();
+        children.sort_unstable();
+        for child in children {
+            self.write_folded_node(writer, debug_info, child, stack)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+/// Names a function after its label, or its address if it has none.
+fn function_name(debug_info: Option<&DebugInfo>, address: u32) -> String {
+    debug_info
+        .and_then(|info| {
+            info.symbols
+                .iter()
+                .find(|symbol| symbol.kind == SymbolKind::Instruction && symbol.address == address)
+        })
+        .map_or_else(
+            || format!("0x{:08X}", address),
+            |symbol| symbol.name.clone(),
+        )
+}
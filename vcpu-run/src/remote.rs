@@ -0,0 +1,671 @@
+//! Remote control of a machine with [JSON-RPC 2.0](https://www.jsonrpc.org/specification) over TCP.
+//!
+//! Every request and response is a single line of JSON. Addresses may be numbers or expressions as in the
+//! [`debugger`](../debugger/index.html), e.g. `"main+8"`. The methods are:
+//!
+//! | Method           | Parameters                          | Result                                  |
+//! |------------------|-------------------------------------|-----------------------------------------|
+//! | `load`           | `path`, `args`, `env`               | `entry_point`, `memory_size`, `ram_size`, `warnings` |
+//! | `restart`        |                                     | the state                               |
+//! | `run`            | `max_instructions`                  | the state                               |
+//! | `step`           | `count` (default 1)                 | the state                               |
+//! | `state`          |                                     | `pc`, `executed`, `stopped`, `status`   |
+//! | `registers`      |                                     | the registers by name, and `PC`         |
+//! | `set_register`   | `register`, `value`                 | `null`                                  |
+//! | `read_memory`    | `address`, `length`                 | `bytes` as a hex string                 |
+//! | `write_memory`   | `address`, `bytes` as a hex string  | `null`                                  |
+//! | `break`          | `address`, `condition` (optional)   | the address                             |
+//! | `delete`         | `address`                           | the address                             |
+//! | `breakpoints`    |                                     | array of addresses                      |
+//! | `eval`           | `expression`                        | the value                               |
+//! | `disassemble`    | `address`, `count`                  | array of `address`, `word`, `text`      |
+//! | `subscribe`      | `events`                            | the subscribed events                   |
+//!
+//! After `subscribe`, the server sends notifications: `instruction` with the `pc` and `word` of every
+//! instruction before it is executed, and `console` with the `text` the program wrote to its UARTs.
+//! The `reason` in the result of `run` and `step` is `breakpoint`, `instruction-limit`, `timeout` or the exit code
+//! of the processor, e.g. `Halted`.
+
+use crate::config::MachineConfig;
+use crate::debugger::{Debugger, Session};
+use crate::machine::{Limits, Stop};
+use crate::monitor::ConsoleBuffer;
+use crate::{exit_status, load, Loaded};
+use num::FromPrimitive;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use vcpu::{RegisterId, Storage, StorageMut, REGISTER_COUNT, WORD_BYTES};
+
+/// Largest number of bytes a single `read_memory` request may return.
+const MAX_READ_LENGTH: u32 = 1 << 16;
+const VERSION: &str = "2.0";
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// Errors caused by the state of the machine, e.g. reading memory which does not exist.
+const MACHINE_ERROR: i32 = -32000;
+
+/// The error object of a response.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Error {
+    pub code: i32,
+    pub message: String,
+}
+
+fn invalid_params(message: &str) -> Error {
+    Error {
+        code: INVALID_PARAMS,
+        message: message.to_owned(),
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error {
+            code: MACHINE_ERROR,
+            message,
+        }
+    }
+}
+
+/// A request, or a notification if it has no `id`.
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Keeps an `id` which is `null`, since only a missing `id` makes the request a notification.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// The `result` or the `error` member of a response.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Result(Value),
+    Error(Error),
+}
+
+/// The response to a request with an `id`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Response {
+    pub jsonrpc: &'static str,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    pub id: Value,
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, Error>) -> Response {
+        Response {
+            jsonrpc: VERSION,
+            outcome: match result {
+                Ok(result) => Outcome::Result(result),
+                Err(error) => Outcome::Error(error),
+            },
+            id,
+        }
+    }
+}
+
+/// A notification the server sends after `subscribe`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+    pub jsonrpc: &'static str,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+pub enum Event {
+    Instruction { pc: u32, word: u32 },
+    Console { text: String },
+}
+
+/// An address parameter, which may be a number or an expression.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Address {
+    Number(u32),
+    Expression(String),
+}
+
+#[derive(Deserialize)]
+struct LoadParams {
+    path: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RunParams {
+    max_instructions: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct StepParams {
+    count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SetRegisterParams {
+    register: String,
+    value: Address,
+}
+
+#[derive(Deserialize)]
+struct ReadMemoryParams {
+    address: Address,
+    length: u32,
+}
+
+#[derive(Deserialize)]
+struct WriteMemoryParams {
+    address: Address,
+    bytes: String,
+}
+
+#[derive(Deserialize)]
+struct BreakParams {
+    address: Address,
+    condition: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    address: Address,
+}
+
+#[derive(Deserialize)]
+struct EvalParams {
+    expression: String,
+}
+
+#[derive(Deserialize)]
+struct DisassembleParams {
+    address: Option<Address>,
+    count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    events: Vec<EventKind>,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EventKind {
+    Instruction,
+    Console,
+}
+
+#[derive(Serialize)]
+struct Program {
+    entry_point: u32,
+    memory_size: u32,
+    ram_size: u32,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct State {
+    pc: u32,
+    executed: u64,
+    stopped: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Memory {
+    bytes: String,
+}
+
+#[derive(Serialize)]
+struct DisassembledLine {
+    address: u32,
+    word: u32,
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<String>,
+}
+
+/// Reads the parameters of a method, which may be left out if none of them are required.
+fn params<T: DeserializeOwned>(params: &Value) -> Result<T, Error> {
+    let params = match params {
+        Value::Null => Value::Object(Map::new()),
+        params => params.clone(),
+    };
+    serde_json::from_value(params).map_err(|err| invalid_params(&err.to_string()))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, Error> {
+    serde_json::to_value(value).map_err(|err| Error::from(err.to_string()))
+}
+
+/// State of a single client connection, which controls its own machine.
+pub struct Remote {
+    config: MachineConfig,
+    session: Option<Session>,
+    debugger: Option<Debugger>,
+    console: ConsoleBuffer,
+    trace_instructions: bool,
+    trace_console: bool,
+}
+
+impl Remote {
+    /// Creates a connection without a program, which machines are created from `config` for.
+    pub fn new(config: MachineConfig) -> Remote {
+        Remote {
+            config,
+            session: None,
+            debugger: None,
+            console: ConsoleBuffer::default(),
+            trace_instructions: false,
+            trace_console: false,
+        }
+    }
+
+    /// Loads the program at `path` and starts it with the given arguments. Returns the warnings of the assembler.
+    pub fn load(
+        &mut self,
+        path: &str,
+        args: Vec<String>,
+        env: Vec<String>,
+    ) -> Result<Vec<vasm::Warning>, String> {
+        let Loaded {
+            executable,
+            warnings,
+        } = load(path)?;
+        let debug_info = executable.debug_info().cloned();
+        let session = Session::new(executable, self.config.clone(), args, env);
+        self.console.clear();
+        let machine = session.start(Box::new(self.console.clone()))?;
+        self.debugger = Some(Debugger::new(machine, debug_info));
+        self.session = Some(session);
+        Ok(warnings)
+    }
+
+    /// Handles a request line and returns the response, unless the request was a notification.
+    ///
+    /// Notifications to the client are passed to `notify` while the request is handled.
+    pub fn handle(&mut self, line: &str, notify: &mut dyn FnMut(Notification)) -> Option<Response> {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => {
+                let code = if err.is_data() {
+                    INVALID_REQUEST
+                } else {
+                    PARSE_ERROR
+                };
+                let message = err.to_string();
+                return Some(Response::new(Value::Null, Err(Error { code, message })));
+            }
+        };
+        let method = match request.method {
+            Some(method) => method,
+            None => {
+                return Some(Response::new(
+                    request.id.unwrap_or(Value::Null),
+                    Err(Error {
+                        code: INVALID_REQUEST,
+                        message: "The request has no method".to_owned(),
+                    }),
+                ))
+            }
+        };
+        let result = self.call(&method, &request.params, notify);
+        request.id.map(|id| Response::new(id, result))
+    }
+
+    fn debugger(&self) -> Result<&Debugger, Error> {
+        self.debugger
+            .as_ref()
+            .ok_or_else(|| Error::from("No program is loaded".to_owned()))
+    }
+
+    fn debugger_mut(&mut self) -> Result<&mut Debugger, Error> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| Error::from("No program is loaded".to_owned()))
+    }
+
+    fn address(&self, address: &Address) -> Result<u32, Error> {
+        match address {
+            Address::Number(address) => Ok(*address),
+            Address::Expression(expression) => Ok(self.debugger()?.eval(expression)?),
+        }
+    }
+
+    fn call(
+        &mut self,
+        method: &str,
+        params: &Value,
+        notify: &mut dyn FnMut(Notification),
+    ) -> Result<Value, Error> {
+        match method {
+            "load" => {
+                let LoadParams { path, args, env } = self::params(params)?;
+                let args = std::iter::once(path.clone()).chain(args).collect();
+                let warnings = self.load(&path, args, env)?;
+                let session = self.session.as_ref().unwrap();
+                to_value(Program {
+                    entry_point: session.executable.entry_point(),
+                    memory_size: session.executable.memory_size(),
+                    ram_size: session.ram_size,
+                    warnings: warnings.iter().map(ToString::to_string).collect(),
+                })
+            }
+            "restart" => {
+                let machine = match &self.session {
+                    Some(session) => {
+                        self.console.clear();
+                        session.start(Box::new(self.console.clone()))?
+                    }
+                    None => return Err(Error::from("No program is loaded".to_owned())),
+                };
+                self.debugger_mut()?.set_machine(machine);
+                self.state(None)
+            }
+            "run" => {
+                let RunParams { max_instructions } = self::params(params)?;
+                let executed = self.debugger()?.machine().executed();
+                let mut limits = self.config.limits;
+                if let Some(count) = max_instructions {
+                    limits.max_instructions = Some(executed + count);
+                }
+                let stop = self.resume(&limits, notify)?;
+                self.state(Some(stop))
+            }
+            "step" => {
+                let StepParams { count } = self::params(params)?;
+                let limits = Limits {
+                    max_instructions: Some(
+                        self.debugger()?.machine().executed() + count.unwrap_or(1),
+                    ),
+                    timeout: None,
+                };
+                let stop = self.resume(&limits, notify)?;
+                self.state(Some(stop))
+            }
+            "state" => self.state(None),
+            "registers" => {
+                let processor = self.debugger()?.machine().processor();
+                // the members keep the order of the registers
+                let mut registers = Map::new();
+                registers.insert("PC".to_owned(), processor.program_counter().into());
+                let table = processor.profile().registers();
+                for i in 0..REGISTER_COUNT {
+                    let id = RegisterId::from_usize(i).unwrap();
+                    if let Some(name) = table.name(id) {
+                        registers.insert(name.to_owned(), processor.register(id).u().into());
+                    }
+                }
+                Ok(Value::Object(registers))
+            }
+            "set_register" => {
+                let SetRegisterParams { register, value } = self::params(params)?;
+                let name = register.to_uppercase();
+                let value = self.address(&value)?;
+                let processor = self.debugger_mut()?.machine_mut().processor_mut();
+                if name == "PC" {
+                    processor.set_program_counter(value);
+                } else {
+                    let id = name
+                        .parse::<RegisterId>()
+                        .map_err(|_| invalid_params(&format!("Unknown register {}", name)))?;
+                    processor.register_mut(id).set_u(value);
+                }
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                let ReadMemoryParams { address, length } = self::params(params)?;
+                let address = self.address(&address)?;
+                if length > MAX_READ_LENGTH {
+                    return Err(invalid_params(&format!(
+                        "length must be a number up to {}",
+                        MAX_READ_LENGTH
+                    )));
+                }
+                let memory = self.debugger()?.machine().memory();
+                let mut bytes = String::new();
+                for i in 0..length {
+                    let address = address.wrapping_add(i);
+                    let byte = memory.read_byte(address).map_err(|_| {
+                        Error::from(format!("Address 0x{:08X} cannot be read", address))
+                    })?;
+                    bytes.push_str(&format!("{:02x}", byte));
+                }
+                to_value(Memory { bytes })
+            }
+            "write_memory" => {
+                let WriteMemoryParams { address, bytes } = self::params(params)?;
+                let address = self.address(&address)?;
+                let bytes = parse_hex(&bytes).ok_or_else(|| invalid_params("bytes must be hex"))?;
+                let memory = self.debugger_mut()?.machine_mut().memory_mut();
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    let address = address.wrapping_add(i as u32);
+                    memory.write_byte(address, byte).map_err(|_| {
+                        Error::from(format!("Address 0x{:08X} cannot be written", address))
+                    })?;
+                }
+                Ok(Value::Null)
+            }
+            "break" => {
+                let BreakParams { address, condition } = self::params(params)?;
+                let address = self.address(&address)?;
+                let debugger = self.debugger_mut()?;
+                let added = debugger.add_breakpoint(address);
+                if let Err(err) = debugger.set_condition(address, condition.as_deref()) {
+                    if added {
+                        debugger.remove_breakpoint(address);
+                    }
+                    return Err(err.into());
+                }
+                to_value(address)
+            }
+            "delete" => {
+                let AddressParams { address } = self::params(params)?;
+                let address = self.address(&address)?;
+                if !self.debugger_mut()?.remove_breakpoint(address) {
+                    return Err(Error::from(format!(
+                        "There is no breakpoint at 0x{:08X}",
+                        address
+                    )));
+                }
+                to_value(address)
+            }
+            "breakpoints" => to_value(self.debugger()?.breakpoints().collect::<Vec<_>>()),
+            "eval" => {
+                let EvalParams { expression } = self::params(params)?;
+                to_value(self.debugger()?.eval(&expression)?)
+            }
+            "disassemble" => {
+                let DisassembleParams { address, count } = self::params(params)?;
+                let debugger = self.debugger()?;
+                let address = match address {
+                    Some(address) => self.address(&address)?,
+                    None => debugger.machine().processor().program_counter(),
+                };
+                let registers = debugger.machine().processor().profile().registers();
+                let mut lines = Vec::new();
+                for i in 0..count.unwrap_or(8).min(MAX_READ_LENGTH) {
+                    let address = address.wrapping_add(i * WORD_BYTES);
+                    let word = match debugger.machine().instruction_at(address) {
+                        Some(word) => word,
+                        None => break,
+                    };
+                    lines.push(DisassembledLine {
+                        address,
+                        word,
+                        text: vasm::disassemble_with(word, registers),
+                        symbol: debugger.symbolize(address),
+                        line: debugger.source_line(address),
+                    });
+                }
+                to_value(lines)
+            }
+            "subscribe" => {
+                let SubscribeParams { events } = self::params(params)?;
+                self.trace_instructions = events.contains(&EventKind::Instruction);
+                self.trace_console = events.contains(&EventKind::Console);
+                let subscribed: Vec<EventKind> = [EventKind::Instruction, EventKind::Console]
+                    .iter()
+                    .cloned()
+                    .filter(|event| events.contains(event))
+                    .collect();
+                to_value(subscribed)
+            }
+            _ => Err(Error {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method {}", method),
+            }),
+        }
+    }
+
+    /// Runs the program and returns `None` if it stopped at a breakpoint. Instructions are traced if
+    /// the client subscribed to them.
+    fn resume(
+        &mut self,
+        limits: &Limits,
+        notify: &mut dyn FnMut(Notification),
+    ) -> Result<Option<Stop>, Error> {
+        let trace_instructions = self.trace_instructions;
+        let debugger = self.debugger_mut()?;
+        let stop = if trace_instructions {
+            let mut first = true;
+            loop {
+                let pc = debugger.machine().processor().program_counter();
+                if !first && debugger.hit_breakpoint(pc) {
+                    break None;
+                }
+                let machine = debugger.machine();
+                if limits
+                    .max_instructions
+                    .is_some_and(|max| machine.executed() >= max)
+                {
+                    break Some(Stop::InstructionLimit);
+                }
+                first = false;
+                if let Some(word) = machine.instruction_at(pc) {
+                    notify(notification(Event::Instruction { pc, word }));
+                }
+                if let Some(exit_code) = debugger.machine_mut().step() {
+                    break Some(Stop::Exit(exit_code));
+                }
+            }
+        } else {
+            debugger.resume(limits)
+        };
+
+        if self.trace_console {
+            let text = self.console.take();
+            if !text.is_empty() {
+                notify(notification(Event::Console {
+                    text: String::from_utf8_lossy(&text).into_owned(),
+                }));
+            }
+        }
+        Ok(stop)
+    }
+
+    /// Describes the state of the machine, and why it stopped running after `run` or `step`.
+    fn state(&self, stop: Option<Option<Stop>>) -> Result<Value, Error> {
+        let machine = self.debugger()?.machine();
+        let exit_code = machine.processor().state();
+        to_value(State {
+            pc: machine.processor().program_counter(),
+            executed: machine.executed(),
+            stopped: exit_code.map(|exit_code| format!("{:?}", exit_code)),
+            status: exit_code.map(|exit_code| exit_status(machine, Stop::Exit(exit_code))),
+            reason: stop.map(|stop| match stop {
+                None => "breakpoint".to_owned(),
+                Some(Stop::InstructionLimit) => "instruction-limit".to_owned(),
+                Some(Stop::Timeout) => "timeout".to_owned(),
+                Some(Stop::Exit(exit_code)) => format!("{:?}", exit_code),
+            }),
+        })
+    }
+}
+
+fn notification(event: Event) -> Notification {
+    Notification {
+        jsonrpc: VERSION,
+        event,
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writeln!(writer)
+}
+
+fn serve_connection(stream: TcpStream, remote: &mut Remote) -> std::io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut result = Ok(());
+        let response = remote.handle(&line, &mut |notification| {
+            if result.is_ok() {
+                result = write_line(&mut writer, &notification);
+            }
+        });
+        result?;
+        if let Some(response) = response {
+            write_line(&mut writer, &response)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Serves clients one after another. Every client gets its own machine, with `program` already loaded
+/// if it is given.
+pub fn serve(
+    listener: TcpListener,
+    config: &MachineConfig,
+    program: Option<&str>,
+) -> Result<(), String> {
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|err| format!("Accepting a connection failed: {}", err))?;
+        let mut remote = Remote::new(config.clone());
+        if let Some(path) = program {
+            remote.load(path, vec![path.to_owned()], Vec::new())?;
+        }
+        // a client which disconnects in the middle of a request does not stop the server
+        let _ = serve_connection(stream, &mut remote);
+    }
+    Ok(())
+}
@@ -0,0 +1,615 @@
+//! Scripts which automate a debugging session, run by `vdb --script FILE` or the `source` command of `vdb`.
+//!
+//! Scripts are written in [Rhai](https://rhai.rs) and control the debugger with the functions below. Handlers
+//! are functions which run whenever an event happens while the program is running.
+//!
+//! ```text
+//! // counts the iterations
+//! on_break("loop", || print(`T0 is ${signed(reg("T0"))}`));
+//! on_write(0xFFFF0000, |write| print(`wrote ${hex(write.value)} at ${hex(pc())}`));
+//! on_exit(|| print(`finished after ${executed()} instructions`));
+//! set_reg("A0", 5);
+//! poke("buffer", 0x2A);
+//! run();
+//! assert_eq(reg("V0"), 120);
+//! ```
+//!
+//! The functions are:
+//!
+//! | Function                                 | Effect                                                                  |
+//! |------------------------------------------|-------------------------------------------------------------------------|
+//! | `run()`                                  | Runs until a breakpoint without handler is reached or the program stops |
+//! | `step()`, `step(COUNT)`                  | Executes one or COUNT instructions                                      |
+//! | `add_breakpoint(ADDR)`                   | Sets a breakpoint, which `add_breakpoint(ADDR, COND)` makes conditional |
+//! | `remove_breakpoint(ADDR)`                | Removes a breakpoint                                                    |
+//! | `reg(NAME)`, `set_reg(NAME, VALUE)`      | Reads or writes a register                                              |
+//! | `pc()`                                   | Returns the program counter                                             |
+//! | `peek(ADDR)`, `peek(ADDR, SIZE)`         | Reads a word, or SIZE bytes (1, 2 or 4), of the memory                  |
+//! | `poke(ADDR, VALUE)`, `poke(ADDR, VALUE, SIZE)` | Writes a word, or SIZE bytes, to the memory                       |
+//! | `eval(EXPR)`                             | Returns the value of a debugger expression                              |
+//! | `executed()`                             | Returns the number of executed instructions                             |
+//! | `registers()`                            | Returns the registers as text                                           |
+//! | `backtrace()`                            | Returns the functions which are being executed as text                  |
+//! | `memory(ADDR, COUNT)`                    | Returns COUNT words of memory as text                                   |
+//! | `hex(VALUE)`, `signed(VALUE)`            | Formats a value as 8 hexadecimal digits, or as a signed number          |
+//! | `assert_eq(A, B)`, `assert_ne(A, B)`     | Fails the script unless both values are equal, or different             |
+//! | `stop()`                                 | Stops the running program after the handler                             |
+//! | `screenshot(FILE)`                       | Saves the framebuffer as a PNG file                                     |
+//! | `attach(KIND@ADDRESS)`                   | Attaches a device until the program restarts                            |
+//! | `detach(ADDR)`                           | Detaches the device at an address                                       |
+//! | `restart()`                              | Starts the program from the beginning                                   |
+//! | `quit()`                                 | Ends the script and the debugger                                        |
+//! | `on_break(ADDR, FN)`                     | Calls FN whenever the program reaches an instruction                    |
+//! | `on_write(FN)`, `on_write(ADDR, FN)`     | Calls FN after the program wrote to any device, or to an address        |
+//! | `on_exit(FN)`                            | Calls FN after the program stopped                                      |
+//!
+//! Addresses are numbers, or strings with an expression like in the [`debugger`](../debugger/index.html), e.g.
+//! `"main+8"`. Values are unsigned 32 bit numbers, negative numbers are accepted as well. `print` writes to the
+//! output of the debugger. Handlers of `on_write` receive a map with the `address`, `value` and `size` of the
+//! write. Handlers are active until the script ends. If a breakpoint has a handler the program keeps running
+//! after it, unless the handler calls `stop()`.
+
+use crate::config::Device;
+use crate::debugger::{Debugger, Session};
+use crate::machine::{DeviceWrite, Stop};
+use crate::{describe_stop, exit_status};
+use rhai::{
+    Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, Map, NativeCallContext, Position, AST, INT,
+};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::rc::Rc;
+use vcpu::{RegisterId, Storage, StorageMut, WORD_BYTES};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub struct Script {
+    ast: AST,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, String> {
+        Engine::new_raw()
+            .compile(source)
+            .map(|ast| Script { ast })
+            .map_err(|err| match err.position().line() {
+                Some(line) => format!("line {}: {}", line, err.err_type()),
+                None => err.err_type().to_string(),
+            })
+    }
+
+    pub fn read_file(path: &str) -> Result<Script, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Reading script \"{}\" failed: {}", path, err))?;
+        Script::parse(&source).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+/// Whether the debugger should continue after a script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// The state of a running script, which its functions share.
+struct State {
+    output: Box<dyn Write>,
+    /// Creates the console of the machine when the program is restarted.
+    console: Box<dyn FnMut() -> Box<dyn Write>>,
+    on_break: Vec<(u32, FnPtr)>,
+    on_write: Vec<(Option<u32>, FnPtr)>,
+    on_exit: Vec<FnPtr>,
+    in_handler: bool,
+    stop_requested: bool,
+    quit_requested: bool,
+}
+
+/// What the functions of a script capture. Borrows are never held while a handler runs.
+#[derive(Clone)]
+struct Context {
+    debugger: Rc<RefCell<Debugger>>,
+    session: Rc<Session>,
+    state: Rc<RefCell<State>>,
+}
+
+/// Executes scripts on a debugger, printing to `output`.
+pub struct ScriptRunner {
+    engine: Engine,
+    context: Context,
+}
+
+impl ScriptRunner {
+    pub fn new(
+        debugger: Rc<RefCell<Debugger>>,
+        session: Rc<Session>,
+        output: Box<dyn Write>,
+        console: Box<dyn FnMut() -> Box<dyn Write>>,
+    ) -> ScriptRunner {
+        let context = Context {
+            debugger,
+            session,
+            state: Rc::new(RefCell::new(State {
+                output,
+                console,
+                on_break: Vec::new(),
+                on_write: Vec::new(),
+                on_exit: Vec::new(),
+                in_handler: false,
+                stop_requested: false,
+                quit_requested: false,
+            })),
+        };
+        let mut engine = Engine::new();
+        let state = context.state.clone();
+        engine.on_print(move |text| {
+            // print cannot fail in Rhai, a broken output only loses the text
+            let _ = writeln!(state.borrow_mut().output, "{}", text);
+        });
+        register_functions(&mut engine, &context);
+        ScriptRunner { engine, context }
+    }
+
+    /// Executes the script, beginning with no handlers, and stops at the first error.
+    pub fn run(&mut self, script: &Script) -> Result<Flow, String> {
+        let result = self.engine.run_ast(&script.ast);
+        let mut state = self.context.state.borrow_mut();
+        state.on_break.clear();
+        state.on_write.clear();
+        state.on_exit.clear();
+        state.in_handler = false;
+        state.stop_requested = false;
+        if std::mem::take(&mut state.quit_requested) {
+            return Ok(Flow::Quit);
+        }
+        match result {
+            Ok(()) => Ok(Flow::Continue),
+            Err(err) if matches!(*err, EvalAltResult::Exit(..)) => Ok(Flow::Continue),
+            Err(err) => Err(describe_error(*err)),
+        }
+    }
+}
+
+/// Formats the error which caused `err`, together with its line.
+fn describe_error(mut err: EvalAltResult) -> String {
+    while let EvalAltResult::ErrorInFunctionCall(_, _, inner, _) = err {
+        err = *inner;
+    }
+    let position = err.take_position();
+    let message = match err {
+        EvalAltResult::ErrorRuntime(value, _) => value.to_string(),
+        err => err.to_string(),
+    };
+    match position.line() {
+        Some(line) => format!("line {}: {}", line, message),
+        None => message,
+    }
+}
+
+/// Converts a number of a script to a word.
+fn word(value: INT) -> Result<u32, String> {
+    if value < INT::from(i32::MIN) || value > INT::from(u32::MAX) {
+        return Err(format!("{} does not fit into 32 bits", value));
+    }
+    Ok(value as u32)
+}
+
+fn parse_register(name: &str) -> Result<RegisterId, String> {
+    name.strip_prefix('$')
+        .unwrap_or(name)
+        .to_uppercase()
+        .parse()
+        .map_err(|_| format!("Unknown register {}", name))
+}
+
+fn parse_size(size: INT) -> Result<u32, String> {
+    match size {
+        1 | 2 | 4 => Ok(size as u32),
+        _ => Err(format!("Invalid size {}", size)),
+    }
+}
+
+impl Context {
+    /// Evaluates an address, which is a number or a debugger expression.
+    fn address(&self, address: Dynamic) -> Result<u32, String> {
+        if let Ok(value) = address.as_int() {
+            return word(value);
+        }
+        let type_name = address.type_name();
+        match address.into_immutable_string() {
+            Ok(expression) => self.debugger.borrow().eval(&expression),
+            Err(_) => Err(format!("Expected an address instead of {}", type_name)),
+        }
+    }
+
+    fn instruction_address(&self, address: Dynamic) -> Result<u32, String> {
+        let address = self.address(address)?;
+        if !address.is_multiple_of(WORD_BYTES) {
+            return Err(format!("0x{:08X} is not an instruction address", address));
+        }
+        Ok(address)
+    }
+
+    fn print(&self, text: &str) -> Result<(), String> {
+        writeln!(self.state.borrow_mut().output, "{}", text)
+            .map_err(|err| format!("Writing output failed: {}", err))
+    }
+
+    fn program_counter(&self) -> u32 {
+        self.debugger
+            .borrow()
+            .machine()
+            .processor()
+            .program_counter()
+    }
+
+    fn ensure_not_in_handler(&self) -> Result<(), String> {
+        if self.state.borrow().in_handler {
+            return Err("Handlers cannot run the program".to_owned());
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint(&self, address: Dynamic, condition: Option<&str>) -> Result<(), String> {
+        let address = self.instruction_address(address)?;
+        let mut debugger = self.debugger.borrow_mut();
+        let added = debugger.add_breakpoint(address);
+        if condition.is_some() {
+            if let Err(err) = debugger.set_condition(address, condition) {
+                if added {
+                    debugger.remove_breakpoint(address);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: Dynamic, size: INT) -> Result<INT, String> {
+        let (address, size) = (self.address(address)?, parse_size(size)?);
+        self.debugger
+            .borrow()
+            .machine()
+            .memory()
+            .read(address, size)
+            .map(INT::from)
+            .map_err(|_| format!("Address 0x{:08X} cannot be read", address))
+    }
+
+    fn poke(&self, address: Dynamic, value: INT, size: INT) -> Result<(), String> {
+        let (address, value, size) = (self.address(address)?, word(value)?, parse_size(size)?);
+        self.debugger
+            .borrow_mut()
+            .machine_mut()
+            .memory_mut()
+            .write(address, size, value)
+            .map_err(|_| format!("Address 0x{:08X} cannot be written", address))
+    }
+
+    fn call_handler(
+        &self,
+        call: &NativeCallContext,
+        handler: &FnPtr,
+        arguments: impl FuncArgs,
+    ) -> ScriptResult<()> {
+        self.state.borrow_mut().in_handler = true;
+        let result = handler.call_within_context::<Dynamic>(call, arguments);
+        self.state.borrow_mut().in_handler = false;
+        result.map(|_| ())
+    }
+
+    fn step(&self, call: &NativeCallContext, count: INT) -> ScriptResult<()> {
+        let count = u64::try_from(count).map_err(|_| format!("Invalid count {}", count))?;
+        self.ensure_not_in_handler()?;
+        let log = self.debugger.borrow().machine().device_log();
+        log.take();
+        let stop = self.debugger.borrow_mut().step(count);
+        self.handle_writes(call, log.take())?;
+        match stop {
+            Some(stop) => self.handle_stop(call, stop),
+            None => {
+                let description = self
+                    .debugger
+                    .borrow()
+                    .describe_instruction(self.program_counter());
+                Ok(self.print(&description)?)
+            }
+        }
+    }
+
+    fn handle_writes(
+        &self,
+        call: &NativeCallContext,
+        writes: Vec<DeviceWrite>,
+    ) -> ScriptResult<()> {
+        for write in writes {
+            let address = write.device.address.wrapping_add(write.offset);
+            let handlers: Vec<FnPtr> = self
+                .state
+                .borrow()
+                .on_write
+                .iter()
+                .filter(|(expected, _)| expected.is_none_or(|expected| expected == address))
+                .map(|(_, handler)| handler.clone())
+                .collect();
+            let mut map = Map::new();
+            map.insert("address".into(), INT::from(address).into());
+            map.insert("value".into(), INT::from(write.value).into());
+            map.insert("size".into(), INT::from(write.size).into());
+            for handler in handlers {
+                self.call_handler(call, &handler, (map.clone(),))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_stop(&self, call: &NativeCallContext, stop: Stop) -> ScriptResult<()> {
+        let message = {
+            let debugger = self.debugger.borrow();
+            let machine = debugger.machine();
+            describe_stop(machine, stop).unwrap_or_else(|| {
+                format!("Program halted with status {}", exit_status(machine, stop))
+            })
+        };
+        self.print(&message)?;
+        let handlers = self.state.borrow().on_exit.clone();
+        for handler in handlers {
+            self.call_handler(call, &handler, ())?;
+        }
+        Ok(())
+    }
+
+    /// Runs the program like the `continue` command of the debugger, but runs the handlers on the way.
+    fn resume(&self, call: &NativeCallContext) -> ScriptResult<()> {
+        self.ensure_not_in_handler()?;
+        if let Some(exit_code) = self.debugger.borrow().machine().processor().state() {
+            return Err(format!(
+                "The program has stopped ({:?}), use restart to run it again",
+                exit_code
+            )
+            .into());
+        }
+        // writes of the script itself, e.g. with poke, are not events
+        let log = self.debugger.borrow().machine().device_log();
+        log.take();
+        loop {
+            let (watch_writes, handled) = {
+                let state = self.state.borrow();
+                let handled: Vec<u32> =
+                    state.on_break.iter().map(|(address, _)| *address).collect();
+                (!state.on_write.is_empty(), handled)
+            };
+            let stop = {
+                let mut debugger = self.debugger.borrow_mut();
+                let breakpoints: Vec<u32> = debugger.breakpoints().chain(handled).collect();
+                let limits = self.session.config.limits;
+                debugger.machine_mut().run_until(&limits, |address| {
+                    breakpoints.contains(&address) || (watch_writes && log.has_writes())
+                })
+            };
+            self.handle_writes(call, log.take())?;
+            if let Some(stop) = stop {
+                return self.handle_stop(call, stop);
+            }
+
+            let pc = self.program_counter();
+            let handlers: Vec<FnPtr> = self
+                .state
+                .borrow()
+                .on_break
+                .iter()
+                .filter(|(address, _)| *address == pc)
+                .map(|(_, handler)| handler.clone())
+                .collect();
+            for handler in handlers {
+                self.call_handler(call, &handler, ())?;
+            }
+            let stop_requested = std::mem::take(&mut self.state.borrow_mut().stop_requested);
+            if stop_requested || self.debugger.borrow_mut().hit_breakpoint(pc) {
+                let description = self.debugger.borrow().describe_instruction(pc);
+                return Ok(self.print(&format!("Stopped at {}", description))?);
+            }
+        }
+    }
+}
+
+/// Registers the functions listed in the module documentation, which use `context`.
+fn register_functions(engine: &mut Engine, context: &Context) {
+    let c = context.clone();
+    engine.register_fn("run", move |call: NativeCallContext| c.resume(&call));
+    let c = context.clone();
+    engine.register_fn("step", move |call: NativeCallContext| c.step(&call, 1));
+    let c = context.clone();
+    engine.register_fn("step", move |call: NativeCallContext, count: INT| {
+        c.step(&call, count)
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "add_breakpoint",
+        move |address: Dynamic| -> ScriptResult<()> { Ok(c.add_breakpoint(address, None)?) },
+    );
+    let c = context.clone();
+    engine.register_fn(
+        "add_breakpoint",
+        move |address: Dynamic, condition: &str| -> ScriptResult<()> {
+            Ok(c.add_breakpoint(address, Some(condition))?)
+        },
+    );
+    let c = context.clone();
+    engine.register_fn(
+        "remove_breakpoint",
+        move |address: Dynamic| -> ScriptResult<()> {
+            let address = c.address(address)?;
+            if !c.debugger.borrow_mut().remove_breakpoint(address) {
+                return Err(format!("There is no breakpoint at 0x{:08X}", address).into());
+            }
+            Ok(())
+        },
+    );
+    let c = context.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        let id = parse_register(name)?;
+        let debugger = c.debugger.borrow();
+        Ok(INT::from(debugger.machine().processor().register(id).u()))
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: INT| -> ScriptResult<()> {
+            let (id, value) = (parse_register(name)?, word(value)?);
+            let mut debugger = c.debugger.borrow_mut();
+            debugger
+                .machine_mut()
+                .processor_mut()
+                .register_mut(id)
+                .set_u(value);
+            Ok(())
+        },
+    );
+    let c = context.clone();
+    engine.register_fn("pc", move || INT::from(c.program_counter()));
+    let c = context.clone();
+    engine.register_fn("peek", move |address: Dynamic| -> ScriptResult<INT> {
+        Ok(c.peek(address, INT::from(WORD_BYTES))?)
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "peek",
+        move |address: Dynamic, size: INT| -> ScriptResult<INT> { Ok(c.peek(address, size)?) },
+    );
+    let c = context.clone();
+    engine.register_fn(
+        "poke",
+        move |address: Dynamic, value: INT| -> ScriptResult<()> {
+            Ok(c.poke(address, value, INT::from(WORD_BYTES))?)
+        },
+    );
+    let c = context.clone();
+    engine.register_fn(
+        "poke",
+        move |address: Dynamic, value: INT, size: INT| -> ScriptResult<()> {
+            Ok(c.poke(address, value, size)?)
+        },
+    );
+    let c = context.clone();
+    engine.register_fn("eval", move |expression: &str| -> ScriptResult<INT> {
+        Ok(INT::from(c.debugger.borrow().eval(expression)?))
+    });
+    let c = context.clone();
+    engine.register_fn("executed", move || {
+        c.debugger.borrow().machine().executed() as INT
+    });
+    let c = context.clone();
+    engine.register_fn("registers", move || {
+        c.debugger.borrow().registers().trim_end().to_owned()
+    });
+    let c = context.clone();
+    engine.register_fn("backtrace", move || {
+        c.debugger.borrow().format_backtrace().trim_end().to_owned()
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "memory",
+        move |address: Dynamic, count: INT| -> ScriptResult<String> {
+            let address = c.address(address)?;
+            let count = u32::try_from(count).map_err(|_| format!("Invalid count {}", count))?;
+            Ok(c.debugger
+                .borrow()
+                .memory(address, count)
+                .trim_end()
+                .to_owned())
+        },
+    );
+    engine.register_fn("hex", |value: INT| -> ScriptResult<String> {
+        Ok(format!("0x{:08X}", word(value)?))
+    });
+    engine.register_fn("signed", |value: INT| -> ScriptResult<INT> {
+        Ok(INT::from(word(value)? as i32))
+    });
+    engine.register_fn("assert_eq", |left: INT, right: INT| -> ScriptResult<()> {
+        if left != right {
+            return Err(format!("Assertion failed: {} != {}", left, right).into());
+        }
+        Ok(())
+    });
+    engine.register_fn("assert_ne", |left: INT, right: INT| -> ScriptResult<()> {
+        if left == right {
+            return Err(format!("Assertion failed: {} == {}", left, right).into());
+        }
+        Ok(())
+    });
+    let c = context.clone();
+    engine.register_fn("stop", move || -> ScriptResult<()> {
+        let mut state = c.state.borrow_mut();
+        if !state.in_handler {
+            return Err("stop can only be used in handlers".into());
+        }
+        state.stop_requested = true;
+        Ok(())
+    });
+    let c = context.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        Ok(c.session.screenshot(c.debugger.borrow().machine(), path)?)
+    });
+    let c = context.clone();
+    engine.register_fn("attach", move |device: &str| -> ScriptResult<()> {
+        let device: Device = device.parse()?;
+        Ok(c.debugger
+            .borrow_mut()
+            .machine_mut()
+            .attach_device(device)?)
+    });
+    let c = context.clone();
+    engine.register_fn("detach", move |address: Dynamic| -> ScriptResult<()> {
+        let address = c.address(address)?;
+        c.debugger
+            .borrow_mut()
+            .machine_mut()
+            .detach_device(address)?;
+        Ok(())
+    });
+    let c = context.clone();
+    engine.register_fn("restart", move || -> ScriptResult<()> {
+        let console = (c.state.borrow_mut().console)();
+        let machine = c.session.start(console)?;
+        c.debugger.borrow_mut().set_machine(machine);
+        Ok(())
+    });
+    let c = context.clone();
+    engine.register_fn("quit", move || -> ScriptResult<()> {
+        let mut state = c.state.borrow_mut();
+        if state.in_handler {
+            return Err("Handlers cannot quit".into());
+        }
+        // the runner turns the termination into Flow::Quit
+        state.quit_requested = true;
+        Err(EvalAltResult::ErrorTerminated(Dynamic::UNIT, Position::NONE).into())
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "on_break",
+        move |address: Dynamic, handler: FnPtr| -> ScriptResult<()> {
+            let address = c.instruction_address(address)?;
+            c.state.borrow_mut().on_break.push((address, handler));
+            Ok(())
+        },
+    );
+    let c = context.clone();
+    engine.register_fn("on_write", move |handler: FnPtr| {
+        c.state.borrow_mut().on_write.push((None, handler));
+    });
+    let c = context.clone();
+    engine.register_fn(
+        "on_write",
+        move |address: Dynamic, handler: FnPtr| -> ScriptResult<()> {
+            let address = c.address(address)?;
+            c.state.borrow_mut().on_write.push((Some(address), handler));
+            Ok(())
+        },
+    );
+    let c = context.clone();
+    engine.register_fn("on_exit", move |handler: FnPtr| {
+        c.state.borrow_mut().on_exit.push(handler);
+    });
+}
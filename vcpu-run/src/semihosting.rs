@@ -0,0 +1,354 @@
+//! Host services for programs, e.g. tests which log to stderr or read fixtures from files.
+//!
+//! A `semihosting` device is a block of five words. The program stores the arguments of an operation into
+//! `ARG0` to `ARG2` and then the number of the operation into `OPERATION`. The runner performs it before the
+//! next instruction and stores the result into `RESULT`, which is -1 if the operation failed:
+//!
+//! | Operation | Arguments | Result |
+//! |-----------|-----------|--------|
+//! | 1 `open`  | zero terminated path, mode | file descriptor |
+//! | 2 `close` | file descriptor | 0 |
+//! | 3 `read`  | file descriptor, buffer, length | number of bytes read, 0 at the end of the file |
+//! | 4 `write` | file descriptor, buffer, length | number of bytes written |
+//! | 5 `seek`  | file descriptor, signed offset, origin | new position |
+//! | 6 `heap_init` | start, size | 0 |
+//! | 7 `alloc` | size | address, 0 if the heap is exhausted |
+//! | 8 `free`  | address | 0 |
+//! | 9 `realloc` | address, new size | new address, 0 if the heap is exhausted |
+//!
+//! File descriptors 0 to 2 are stdin, which is always at its end, stdout, which is the console the UARTs write
+//! to, and stderr. Files can only be opened below the root directory set with
+//! [`Machine::set_semihosting_root`](../struct.Machine.html#method.set_semihosting_root), using relative paths
+//! without `..`. Without a root, only the standard streams are available.
+//!
+//! The heap operations manage memory in the region of the RAM given to `heap_init`, so programs do not need
+//! their own allocator. Blocks are word aligned and allocated first fit. Calling `heap_init` again discards all
+//! blocks. Like in C, `realloc` of address 0 allocates, `realloc` to size 0 frees and returns 0, and a block
+//! which cannot be grown stays allocated. Freeing an address which is not allocated fails.
+//!
+//! `std/semihosting.vasm` contains routines for all operations.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use vcpu::{Storage, StorageMut, WORD_BYTES};
+
+/// Offset of the register which starts an operation when it is written.
+pub const OPERATION: u32 = 0;
+pub const ARG0: u32 = 4;
+pub const ARG1: u32 = 8;
+pub const ARG2: u32 = 12;
+pub const RESULT: u32 = 16;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = 20;
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// Modes of `open`.
+pub const MODE_READ: u32 = 0;
+/// Creates the file or truncates it.
+pub const MODE_WRITE: u32 = 1;
+/// Creates the file or appends to it.
+pub const MODE_APPEND: u32 = 2;
+
+/// Origins of `seek`.
+pub const SEEK_START: u32 = 0;
+pub const SEEK_CURRENT: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// Longest path `open` accepts, including the terminating zero.
+const MAX_PATH: u32 = 4096;
+/// Most bytes a single `read` or `write` transfers, programs have to repeat them for the rest.
+const MAX_TRANSFER: u32 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Open = 1,
+    Close = 2,
+    Read = 3,
+    Write = 4,
+    Seek = 5,
+    HeapInit = 6,
+    Alloc = 7,
+    Free = 8,
+    Realloc = 9,
+}
+
+impl Operation {
+    pub fn from_u32(number: u32) -> Option<Operation> {
+        [
+            Operation::Open,
+            Operation::Close,
+            Operation::Read,
+            Operation::Write,
+            Operation::Seek,
+            Operation::HeapInit,
+            Operation::Alloc,
+            Operation::Free,
+            Operation::Realloc,
+        ]
+        .iter()
+        .copied()
+        .find(|operation| *operation as u32 == number)
+    }
+}
+
+/// An operation the program requested from the device at `device`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub device: u32,
+    pub operation: u32,
+    pub args: [u32; 3],
+}
+
+/// The host side of the semihosting devices of a machine, which owns the files opened by the program.
+pub struct Semihost {
+    root: Option<PathBuf>,
+    console: Rc<RefCell<Box<dyn Write>>>,
+    errors: Box<dyn Write>,
+    files: BTreeMap<u32, File>,
+    heap: Heap,
+}
+
+impl Semihost {
+    /// Creates a host without a root directory, which writes stdout to `console` and stderr to the stderr of
+    /// the runner.
+    pub fn new(console: Rc<RefCell<Box<dyn Write>>>) -> Semihost {
+        Semihost {
+            root: None,
+            console,
+            errors: Box::new(std::io::stderr()),
+            files: BTreeMap::new(),
+            heap: Heap::default(),
+        }
+    }
+
+    pub fn set_root(&mut self, root: Option<PathBuf>) {
+        self.root = root;
+    }
+
+    pub fn set_error_output(&mut self, errors: Box<dyn Write>) {
+        self.errors = errors;
+    }
+
+    /// Performs `request` on `memory` and returns its result.
+    pub fn handle<M: StorageMut>(&mut self, request: &Request, memory: &mut M) -> i32 {
+        let [arg0, arg1, arg2] = request.args;
+        let result = match Operation::from_u32(request.operation) {
+            Some(Operation::Open) => self.open(memory, arg0, arg1),
+            Some(Operation::Close) => self.close(arg0),
+            Some(Operation::Read) => self.read(memory, arg0, arg1, arg2),
+            Some(Operation::Write) => self.write(memory, arg0, arg1, arg2),
+            Some(Operation::Seek) => self.seek(arg0, arg1 as i32, arg2),
+            Some(Operation::HeapInit) => Heap::new(arg0, arg1).map(|heap| {
+                self.heap = heap;
+                0
+            }),
+            Some(Operation::Alloc) => Some(self.heap.alloc(arg0).unwrap_or(0) as i32),
+            Some(Operation::Free) => self.heap.free(arg0).map(|_| 0),
+            Some(Operation::Realloc) => self
+                .heap
+                .realloc(memory, arg0, arg1)
+                .map(|address| address.unwrap_or(0) as i32),
+            None => None,
+        };
+        result.unwrap_or(-1)
+    }
+
+    fn open<M: Storage>(&mut self, memory: &M, path: u32, mode: u32) -> Option<i32> {
+        let path = self.resolve(&read_string(memory, path)?)?;
+        let mut options = OpenOptions::new();
+        match mode {
+            MODE_READ => options.read(true),
+            MODE_WRITE => options.write(true).create(true).truncate(true),
+            MODE_APPEND => options.append(true).create(true),
+            _ => return None,
+        };
+        let file = options.open(path).ok()?;
+        let fd = (STDERR + 1..).find(|fd| !self.files.contains_key(fd))?;
+        self.files.insert(fd, file);
+        Some(fd as i32)
+    }
+
+    /// Returns the path of `path` below the root, unless it could lead outside of it.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let root = self.root.as_ref()?;
+        let path = Path::new(path);
+        let inside = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if inside && !path.as_os_str().is_empty() {
+            Some(root.join(path))
+        } else {
+            None
+        }
+    }
+
+    fn close(&mut self, fd: u32) -> Option<i32> {
+        if fd <= STDERR {
+            return Some(0);
+        }
+        self.files.remove(&fd).map(|_| 0)
+    }
+
+    fn read<M: StorageMut>(
+        &mut self,
+        memory: &mut M,
+        fd: u32,
+        buffer: u32,
+        length: u32,
+    ) -> Option<i32> {
+        let file = match fd {
+            STDIN => return Some(0),
+            STDOUT | STDERR => return None,
+            _ => self.files.get_mut(&fd)?,
+        };
+        let mut bytes = vec![0; length.min(MAX_TRANSFER) as usize];
+        let count = file.read(&mut bytes).ok()?;
+        for (i, byte) in bytes[..count].iter().enumerate() {
+            memory
+                .write_byte(buffer.wrapping_add(i as u32), *byte)
+                .ok()?;
+        }
+        Some(count as i32)
+    }
+
+    fn write<M: Storage>(&mut self, memory: &M, fd: u32, buffer: u32, length: u32) -> Option<i32> {
+        let bytes = (0..length.min(MAX_TRANSFER))
+            .map(|i| memory.read_byte(buffer.wrapping_add(i)))
+            .collect::<Result<Vec<u8>, ()>>()
+            .ok()?;
+        let written = match fd {
+            STDOUT => {
+                let mut console = self.console.borrow_mut();
+                console.write_all(&bytes).and_then(|_| console.flush())
+            }
+            STDERR => self
+                .errors
+                .write_all(&bytes)
+                .and_then(|_| self.errors.flush()),
+            _ => self.files.get_mut(&fd)?.write_all(&bytes),
+        };
+        written.ok().map(|_| bytes.len() as i32)
+    }
+
+    fn seek(&mut self, fd: u32, offset: i32, origin: u32) -> Option<i32> {
+        let file = self.files.get_mut(&fd)?;
+        let position = match origin {
+            SEEK_START => SeekFrom::Start(u64::try_from(offset).ok()?),
+            SEEK_CURRENT => SeekFrom::Current(i64::from(offset)),
+            SEEK_END => SeekFrom::End(i64::from(offset)),
+            _ => return None,
+        };
+        i32::try_from(file.seek(position).ok()?).ok()
+    }
+}
+
+/// The blocks allocated by the program in the region of the heap.
+#[derive(Default)]
+struct Heap {
+    start: u32,
+    end: u32,
+    /// Sizes of the blocks by their address.
+    blocks: BTreeMap<u32, u32>,
+}
+
+fn align(value: u32) -> Option<u32> {
+    Some(value.checked_add(WORD_BYTES - 1)? / WORD_BYTES * WORD_BYTES)
+}
+
+impl Heap {
+    fn new(start: u32, size: u32) -> Option<Heap> {
+        let end = start.checked_add(size)?;
+        Some(Heap {
+            start: align(start)?.min(end),
+            end,
+            blocks: BTreeMap::new(),
+        })
+    }
+
+    /// Returns the address of a new block, or `None` if there is no gap which is large enough.
+    fn alloc(&mut self, size: u32) -> Option<u32> {
+        let size = align(size.max(1))?;
+        let mut candidate = self.start;
+        for (address, block) in self.blocks.iter() {
+            if address - candidate >= size {
+                break;
+            }
+            candidate = address + block;
+        }
+        if self.end - candidate < size {
+            return None;
+        }
+        self.blocks.insert(candidate, size);
+        Some(candidate)
+    }
+
+    fn free(&mut self, address: u32) -> Option<()> {
+        self.blocks.remove(&address).map(|_| ())
+    }
+
+    /// Resizes the block at `address`, moving it if it cannot grow. Returns `None` if there is no such block,
+    /// and `Some(None)` if the heap is exhausted.
+    fn realloc<M: StorageMut>(
+        &mut self,
+        memory: &mut M,
+        address: u32,
+        size: u32,
+    ) -> Option<Option<u32>> {
+        if address == 0 {
+            return Some(self.alloc(size));
+        }
+        let old_size = *self.blocks.get(&address)?;
+        if size == 0 {
+            self.blocks.remove(&address);
+            return Some(Some(0));
+        }
+        let new_size = match align(size) {
+            Some(new_size) => new_size,
+            None => return Some(None),
+        };
+        let limit = self
+            .blocks
+            .range(address + 1..)
+            .next()
+            .map_or(self.end, |(next, _)| *next);
+        if limit - address >= new_size {
+            self.blocks.insert(address, new_size);
+            return Some(Some(address));
+        }
+        let moved = match self.alloc(new_size) {
+            Some(moved) => moved,
+            None => return Some(None),
+        };
+        for i in 0..old_size {
+            let byte = memory.read_byte(address + i).ok();
+            if byte
+                .and_then(|byte| memory.write_byte(moved + i, byte).ok())
+                .is_none()
+            {
+                self.blocks.remove(&moved);
+                return None;
+            }
+        }
+        self.blocks.remove(&address);
+        Some(Some(moved))
+    }
+}
+
+fn read_string<M: Storage>(memory: &M, address: u32) -> Option<String> {
+    let mut bytes = Vec::new();
+    for i in 0..MAX_PATH {
+        match memory.read_byte(address.wrapping_add(i)).ok()? {
+            0 => return String::from_utf8(bytes).ok(),
+            byte => bytes.push(byte),
+        }
+    }
+    None
+}
@@ -0,0 +1,193 @@
+//! A serial port which the host connects to over TCP, so a terminal like `telnet` or `nc` can attach to the
+//! console of a program which runs headless.
+//!
+//! A `serial` device is a block of four words:
+//!
+//! | Offset | Register | |
+//! |--------|----------|-|
+//! | 0 | `TX`     | Writing sends the lowest byte. |
+//! | 4 | `RX`     | The oldest received byte, or `0xFFFFFFFF` if there is none. |
+//! | 8 | `STATUS` | Bit 0 is set while `RX` holds a byte, bit 1 while a client is connected. |
+//! | 12 | `NEXT`  | Writing replaces `RX` with the next received byte. |
+//!
+//! `RX` and `STATUS` are stored by the runner before the next instruction. Bytes only arrive while the machine
+//! runs, since it polls the connection every [`POLL_INTERVAL`](constant.POLL_INTERVAL.html) instructions and
+//! when the program stops, once it listens with
+//! [`Machine::listen_serial`](../struct.Machine.html#method.listen_serial).
+//!
+//! Only one client is connected at a time, and another client can connect after it disconnected. The last
+//! [`BUFFER_BYTES`](constant.BUFFER_BYTES.html) bytes the program sends while no client is connected are sent
+//! once one connects, so it sees e.g. the prompt of the program.
+
+use crate::config::Device;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+
+/// Offset of the register which sends a byte when it is written.
+pub const TX: u32 = 0;
+pub const RX: u32 = 4;
+pub const STATUS: u32 = 8;
+pub const NEXT: u32 = 12;
+/// Number of bytes the device occupies in the address space.
+pub const SIZE: u32 = 16;
+
+/// Bit of `STATUS` which is set while `RX` holds a byte.
+pub const STATUS_RECEIVED: u32 = 1;
+/// Bit of `STATUS` which is set while a client is connected.
+pub const STATUS_CONNECTED: u32 = 2;
+/// Value of `RX` if no byte was received.
+pub const NOTHING_RECEIVED: u32 = 0xFFFF_FFFF;
+
+/// Number of instructions between two polls of the connection.
+pub const POLL_INTERVAL: u64 = 1 << 10;
+/// Most bytes which are kept in each direction until they are sent or read.
+pub const BUFFER_BYTES: usize = 1 << 12;
+
+#[derive(Default)]
+struct State {
+    received: VecDeque<u8>,
+    transmitted: VecDeque<u8>,
+    connected: bool,
+    /// `RX` and `STATUS` as they were last stored into the memory of the device.
+    published: Option<(u32, u32)>,
+}
+
+/// The host side of a serial device, which the machine and the connection share.
+#[derive(Clone)]
+pub struct SerialPort {
+    device: Device,
+    state: Rc<RefCell<State>>,
+}
+
+impl SerialPort {
+    pub(crate) fn new(device: Device) -> SerialPort {
+        SerialPort {
+            device,
+            state: Rc::default(),
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.borrow().connected
+    }
+
+    /// Passes bytes to the program as if they were received. Bytes beyond
+    /// [`BUFFER_BYTES`](constant.BUFFER_BYTES.html) are dropped.
+    pub fn receive(&self, bytes: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let free = BUFFER_BYTES.saturating_sub(state.received.len());
+        state.received.extend(bytes.iter().take(free));
+    }
+
+    /// Removes and returns the bytes the program sent which were not passed to a client yet.
+    pub fn take_transmitted(&self) -> Vec<u8> {
+        self.state.borrow_mut().transmitted.drain(..).collect()
+    }
+
+    /// Handles a write of the program to a register of the device.
+    pub(crate) fn on_write(&self, memory: &[u8], address: u32) {
+        let mut state = self.state.borrow_mut();
+        match address {
+            TX => {
+                if state.transmitted.len() == BUFFER_BYTES {
+                    state.transmitted.pop_front();
+                }
+                state.transmitted.push_back(memory[TX as usize]);
+            }
+            NEXT => {
+                state.received.pop_front();
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the new `RX` and `STATUS` if they changed since they were returned the last time.
+    pub(crate) fn registers(&self) -> Option<(u32, u32)> {
+        let mut state = self.state.borrow_mut();
+        let (rx, received) = match state.received.front() {
+            Some(byte) => (u32::from(*byte), STATUS_RECEIVED),
+            None => (NOTHING_RECEIVED, 0),
+        };
+        let connected = if state.connected { STATUS_CONNECTED } else { 0 };
+        let registers = (rx, received | connected);
+        if state.published == Some(registers) {
+            return None;
+        }
+        state.published = Some(registers);
+        Some(registers)
+    }
+}
+
+/// Connects a serial port to the clients of a TCP listener.
+pub struct TcpBridge {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+}
+
+impl TcpBridge {
+    /// Listens at `address`, e.g. `127.0.0.1:2323`, or `127.0.0.1:0` for any free port.
+    pub fn listen(address: &str) -> Result<TcpBridge, String> {
+        let error = |err: std::io::Error| format!("Listening at {} failed: {}", address, err);
+        let listener = TcpListener::bind(address).map_err(error)?;
+        listener.set_nonblocking(true).map_err(error)?;
+        Ok(TcpBridge {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Accepts a waiting client and exchanges the bytes between it and `port`, without blocking.
+    pub fn poll(&mut self, port: &SerialPort) {
+        if self.client.is_none() {
+            if let Ok((client, _)) = self.listener.accept() {
+                if client.set_nonblocking(true).is_ok() {
+                    self.client = Some(client);
+                }
+            }
+        }
+        let connected = match self.client.as_mut() {
+            Some(client) => exchange(client, port),
+            None => false,
+        };
+        if !connected {
+            self.client = None;
+        }
+        port.state.borrow_mut().connected = connected;
+    }
+}
+
+/// Passes the bytes between the client and the port, and returns whether the client is still connected.
+fn exchange(client: &mut TcpStream, port: &SerialPort) -> bool {
+    let pending: Vec<u8> = port.state.borrow().transmitted.iter().copied().collect();
+    if !pending.is_empty() {
+        match client.write(&pending) {
+            Ok(written) => {
+                port.state.borrow_mut().transmitted.drain(..written);
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => return false,
+        }
+    }
+    let mut buffer = [0; 256];
+    loop {
+        match client.read(&mut buffer) {
+            Ok(0) => return false,
+            Ok(length) => port.receive(&buffer[..length]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
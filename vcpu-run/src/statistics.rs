@@ -0,0 +1,274 @@
+//! Counts of the executed instructions, e.g. for comparing implementations of an algorithm.
+//!
+//! The statistics contain how often every mnemonic and every address was executed, and for every conditional
+//! branch how often it was taken. As a simple measure of how predictable the branches are, they also count
+//! the mispredictions of a static predictor which assumes that backward branches, like those of loops, are
+//! taken and forward branches are not, see the [`predictor`](../predictor/index.html) module for better ones.
+//!
+//! They are written as JSON or as CSV with the columns `kind,key,count,taken`, where `kind` is `total`,
+//! `mnemonic`, `address` or `branch`, and `taken` is only set for branches.
+
+use crate::events::{Event, EventListener};
+use num::FromPrimitive;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::str::FromStr;
+use vcpu::{
+    AluFunct, FlopFunct, Opcode, Word, FUNCT_MASK, FUNCT_OFFSET, OPCODE_MASK, OPCODE_OFFSET,
+};
+use vex::debug::DebugInfo;
+
+/// File format of the statistics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatisticsFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for StatisticsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StatisticsFormat::Json),
+            "csv" => Ok(StatisticsFormat::Csv),
+            _ => Err(format!("Unknown statistics format \"{}\"", s)),
+        }
+    }
+}
+
+/// How often a conditional branch was executed and taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BranchStatistics {
+    pub executed: u64,
+    pub taken: u64,
+    /// Executions in which the branch did not go in the direction of its target, i.e. backward branches which
+    /// were not taken and forward branches which were.
+    pub mispredicted: u64,
+}
+
+/// The statistics as they are written in the JSON format.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub instructions: u64,
+    /// Counts of the mnemonics, which are written as an object.
+    #[serde(serialize_with = "ordered_map")]
+    pub mnemonics: Vec<(String, u64)>,
+    pub branches: BranchReport,
+    pub addresses: Vec<AddressCount>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BranchReport {
+    pub executed: u64,
+    pub taken: u64,
+    pub taken_rate: f64,
+    pub mispredicted: u64,
+    pub mispredict_rate: f64,
+    pub sites: Vec<BranchSite>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct BranchSite {
+    pub address: u32,
+    #[serde(flatten)]
+    pub statistics: BranchStatistics,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AddressCount {
+    pub address: u32,
+    pub count: u64,
+    /// Source line of the address, as `file:line`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+}
+
+/// Writes pairs as the members of an object, keeping their order.
+fn ordered_map<S: Serializer>(pairs: &[(String, u64)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(pairs.iter().map(|(key, value)| (key, value)))
+}
+
+/// Collects statistics while the program runs, from the events of an
+/// [`EventBus`](../events/struct.EventBus.html).
+#[derive(Default)]
+pub struct Statistics {
+    total: u64,
+    mnemonics: BTreeMap<String, u64>,
+    addresses: BTreeMap<u32, u64>,
+    branches: BTreeMap<u32, BranchStatistics>,
+}
+
+/// Returns the mnemonic of an instruction, which is the function for `ALU` and `FLOP` instructions.
+fn mnemonic(opcode: Opcode, word: Word) -> String {
+    let funct = (word & FUNCT_MASK) >> FUNCT_OFFSET;
+    let name = match opcode {
+        Opcode::ALU => AluFunct::from_u32(funct).map(|funct| funct.to_string()),
+        Opcode::FLOP => FlopFunct::from_u32(funct).map(|funct| funct.to_string()),
+        _ => None,
+    };
+    name.unwrap_or_else(|| opcode.to_string())
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics::default()
+    }
+
+    /// Number of instructions recorded.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of executed instructions by mnemonic.
+    pub fn mnemonics(&self) -> &BTreeMap<String, u64> {
+        &self.mnemonics
+    }
+
+    /// Number of times the instruction at each address was executed.
+    pub fn addresses(&self) -> &BTreeMap<u32, u64> {
+        &self.addresses
+    }
+
+    /// Conditional branches by their address.
+    pub fn branches(&self) -> &BTreeMap<u32, BranchStatistics> {
+        &self.branches
+    }
+
+    /// Returns the sums over all branches.
+    pub fn branch_totals(&self) -> BranchStatistics {
+        self.branches
+            .values()
+            .fold(BranchStatistics::default(), |sum, branch| {
+                BranchStatistics {
+                    executed: sum.executed + branch.executed,
+                    taken: sum.taken + branch.taken,
+                    mispredicted: sum.mispredicted + branch.mispredicted,
+                }
+            })
+    }
+
+    /// Returns the statistics as they are written in the JSON format. Mnemonics are ordered by their count,
+    /// most first, and addresses carry their source line if `debug_info` has one.
+    pub fn report(&self, debug_info: Option<&DebugInfo>) -> Report {
+        let rate = |count: u64, of: u64| count as f64 / of.max(1) as f64;
+        let mut mnemonics: Vec<_> = self
+            .mnemonics
+            .iter()
+            .map(|(mnemonic, count)| (mnemonic.clone(), *count))
+            .collect();
+        mnemonics.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        let totals = self.branch_totals();
+        Report {
+            instructions: self.total,
+            mnemonics,
+            branches: BranchReport {
+                executed: totals.executed,
+                taken: totals.taken,
+                taken_rate: rate(totals.taken, totals.executed),
+                mispredicted: totals.mispredicted,
+                mispredict_rate: rate(totals.mispredicted, totals.executed),
+                sites: self
+                    .branches
+                    .iter()
+                    .map(|(address, branch)| BranchSite {
+                        address: *address,
+                        statistics: *branch,
+                    })
+                    .collect(),
+            },
+            addresses: self
+                .addresses
+                .iter()
+                .map(|(address, count)| AddressCount {
+                    address: *address,
+                    count: *count,
+                    line: source_line(debug_info, *address),
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the statistics in the given `format`.
+    pub fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        format: StatisticsFormat,
+        debug_info: Option<&DebugInfo>,
+    ) -> std::io::Result<()> {
+        match format {
+            StatisticsFormat::Json => {
+                serde_json::to_writer(&mut *writer, &self.report(debug_info))?;
+                writeln!(writer)
+            }
+            StatisticsFormat::Csv => self.write_csv(writer),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let totals = self.branch_totals();
+        writeln!(writer, "kind,key,count,taken")?;
+        writeln!(writer, "total,instructions,{},", self.total)?;
+        writeln!(
+            writer,
+            "total,branches,{},{}",
+            totals.executed, totals.taken
+        )?;
+        writeln!(writer, "total,mispredicted,{},", totals.mispredicted)?;
+        for (mnemonic, count) in self.mnemonics.iter() {
+            writeln!(writer, "mnemonic,{},{},", mnemonic, count)?;
+        }
+        for (address, count) in self.addresses.iter() {
+            writeln!(writer, "address,0x{:08X},{},", address, count)?;
+        }
+        for (address, branch) in self.branches.iter() {
+            writeln!(
+                writer,
+                "branch,0x{:08X},{},{}",
+                address, branch.executed, branch.taken
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl EventListener for Statistics {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::Retire { address, word } => {
+                self.total += 1;
+                *self.addresses.entry(address).or_insert(0) += 1;
+                if let Some(opcode) = Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+                    *self.mnemonics.entry(mnemonic(opcode, word)).or_insert(0) += 1;
+                }
+            }
+            Event::Branch {
+                address,
+                opcode,
+                target,
+                taken,
+            } if opcode.is_branch() => {
+                let branch = self.branches.entry(address).or_default();
+                branch.executed += 1;
+                if taken {
+                    branch.taken += 1;
+                }
+                if taken != (target <= address) {
+                    branch.mispredicted += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn source_line(debug_info: Option<&DebugInfo>, address: u32) -> Option<String> {
+    let info = debug_info?;
+    let entry = info.line_at(address)?;
+    let file = info
+        .files
+        .get(entry.file as usize)
+        .map_or("?", String::as_str);
+    Some(format!("{}:{}", file, entry.line))
+}
@@ -0,0 +1,255 @@
+//! Data-flow analysis which follows tainted values, e.g. input from a device, through the program.
+//!
+//! Sources are registers, which are tainted when the program starts, and ranges of memory, whose bytes are
+//! tainted until the program overwrites them with untainted values. Ranges of devices stay tainted, as every
+//! load can return new input. Taint propagates with the data:
+//!
+//! - The result of an arithmetic instruction is tainted if one of its operands is, except for `SUB` and `XOR` of
+//!   a register with itself, which are always 0.
+//! - A load taints its register if one of the bytes it reads is tainted, and a store taints or clears the bytes
+//!   it writes. Addresses are not followed, so a tainted pointer does not taint the value it points to.
+//! - Immediates and return addresses are untainted.
+//!
+//! An instruction consumes tainted data if it reads a tainted register or byte. Branches and jumps which
+//! consume tainted data are where the input controls the flow of the program.
+
+use crate::config::{parse_size, Device};
+use crate::debugger::nearest_label;
+use crate::machine::Machine;
+use num::FromPrimitive;
+use std::collections::{BTreeMap, HashSet};
+use std::io::prelude::*;
+use std::ops::Range;
+use std::str::FromStr;
+use vcpu::{
+    AluFunct, Opcode, RegisterId, Word, FUNCT_MASK, FUNCT_OFFSET, IMMEDIATE_MASK, IMMEDIATE_OFFSET,
+    OPCODE_MASK, OPCODE_OFFSET, RD_MASK, RD_OFFSET, REGISTER_COUNT, RETURN_ADDRESS, RS1_MASK,
+    RS1_OFFSET, RS2_MASK, RS2_OFFSET,
+};
+use vex::debug::DebugInfo;
+
+/// Where tainted data comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaintSource {
+    Register(RegisterId),
+    /// Bytes of memory which are tainted until they are overwritten.
+    Memory(Range<u32>),
+    /// Bytes of a device, which stay tainted.
+    Device(Range<u32>),
+}
+
+/// Parses a register like `$T0`, a device like `uart@0xFFFF0000` or a range of memory like `0x1000:16`, whose
+/// size is 4 bytes if it is left out.
+impl FromStr for TaintSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(name) = s.strip_prefix('$') {
+            return name
+                .to_uppercase()
+                .parse()
+                .map(TaintSource::Register)
+                .map_err(|_| format!("Unknown register {}", s));
+        }
+        if s.contains('@') {
+            let device: Device = s.parse()?;
+            let end = device.address.saturating_add(device.kind.size());
+            return Ok(TaintSource::Device(device.address..end));
+        }
+        let (start, size) = match s.split_once(':') {
+            Some((start, size)) => (parse_size(start)?, parse_size(size)?),
+            None => (parse_size(s)?, 4),
+        };
+        Ok(TaintSource::Memory(start..start.saturating_add(size)))
+    }
+}
+
+/// How often an instruction consumed tainted data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaintedUse {
+    pub word: Word,
+    pub count: u64,
+}
+
+/// Follows tainted data while the program runs, see
+/// [`Machine::run_inspected`](../struct.Machine.html#method.run_inspected).
+pub struct TaintTracker {
+    registers: [bool; REGISTER_COUNT],
+    memory: HashSet<u32>,
+    devices: Vec<Range<u32>>,
+    /// The registers before the instruction which is recorded next, to compute its addresses.
+    values: [u32; REGISTER_COUNT],
+    uses: BTreeMap<u32, TaintedUse>,
+}
+
+fn register(word: Word, mask: u32, offset: u32) -> RegisterId {
+    RegisterId::from_u32((word & mask) >> offset).unwrap()
+}
+
+impl TaintTracker {
+    /// Creates a tracker for `machine` before it executes its next instruction.
+    pub fn new(machine: &Machine, sources: &[TaintSource]) -> TaintTracker {
+        let mut tracker = TaintTracker {
+            registers: [false; REGISTER_COUNT],
+            memory: HashSet::new(),
+            devices: Vec::new(),
+            values: [0; REGISTER_COUNT],
+            uses: BTreeMap::new(),
+        };
+        for source in sources {
+            match source {
+                TaintSource::Register(register) => tracker.registers[*register as usize] = true,
+                TaintSource::Memory(range) => tracker.memory.extend(range.clone()),
+                TaintSource::Device(range) => tracker.devices.push(range.clone()),
+            }
+        }
+        tracker.registers[RegisterId::ZERO as usize] = false;
+        tracker.save_registers(machine);
+        tracker
+    }
+
+    fn save_registers(&mut self, machine: &Machine) {
+        let processor = machine.processor();
+        for (i, value) in self.values.iter_mut().enumerate() {
+            *value = processor.register(RegisterId::from_usize(i).unwrap()).u();
+        }
+    }
+
+    pub fn is_register_tainted(&self, register: RegisterId) -> bool {
+        self.registers[register as usize]
+    }
+
+    pub fn is_byte_tainted(&self, address: u32) -> bool {
+        self.memory.contains(&address) || self.devices.iter().any(|range| range.contains(&address))
+    }
+
+    fn bytes_tainted(&self, address: u32, size: u32) -> bool {
+        (0..size).any(|i| self.is_byte_tainted(address.wrapping_add(i)))
+    }
+
+    /// Number of bytes of memory, not counting devices, which are tainted.
+    pub fn tainted_bytes(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// The instructions which consumed tainted data, by their address.
+    pub fn uses(&self) -> &BTreeMap<u32, TaintedUse> {
+        &self.uses
+    }
+
+    /// Propagates the taint through the instruction `word` at `address`, which `machine` just executed.
+    pub fn record(&mut self, address: u32, word: Word, machine: &Machine) {
+        let opcode = match Opcode::from_u32((word & OPCODE_MASK) >> OPCODE_OFFSET) {
+            Some(opcode) => opcode,
+            None => return,
+        };
+        let (rd, rs1, rs2) = (
+            register(word, RD_MASK, RD_OFFSET),
+            register(word, RS1_MASK, RS1_OFFSET),
+            register(word, RS2_MASK, RS2_OFFSET),
+        );
+        let registers = self.registers;
+        let tainted = |register: RegisterId| registers[register as usize];
+        let immediate = ((word & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as i16;
+        let target = self.values[rs1 as usize].wrapping_add(immediate as i32 as u32);
+        let size = match opcode {
+            Opcode::LB | Opcode::SB => 1,
+            Opcode::LH | Opcode::SH => 2,
+            _ => 4,
+        };
+
+        // whether the instruction consumed tainted data, and the new taint of the register it writes
+        let (consumed, result) = match opcode {
+            Opcode::NOP | Opcode::HALT | Opcode::CALL | Opcode::JMP => (false, None),
+            Opcode::ALU => {
+                let funct = AluFunct::from_u32((word & FUNCT_MASK) >> FUNCT_OFFSET);
+                let operands = tainted(rs1) || tainted(rs2);
+                let constant =
+                    rs1 == rs2 && matches!(funct, Some(AluFunct::SUB) | Some(AluFunct::XOR));
+                (operands, Some((rd, operands && !constant)))
+            }
+            Opcode::FLOP => {
+                let operands = tainted(rs1) || tainted(rs2);
+                (operands, Some((rd, operands)))
+            }
+            Opcode::LI | Opcode::LHI => (false, Some((rd, false))),
+            Opcode::SLO | Opcode::SHI => (tainted(rd), None),
+            Opcode::LB | Opcode::LH | Opcode::LW => {
+                let loaded = self.bytes_tainted(target, size);
+                (loaded || tainted(rs1), Some((rd, loaded)))
+            }
+            Opcode::SB | Opcode::SH | Opcode::SW => {
+                let stored = tainted(rd);
+                for i in 0..size {
+                    let byte = target.wrapping_add(i);
+                    if stored {
+                        self.memory.insert(byte);
+                    } else {
+                        self.memory.remove(&byte);
+                    }
+                }
+                (stored || tainted(rs1), None)
+            }
+            Opcode::BEZ | Opcode::BNZ | Opcode::JR => (tainted(rs1), None),
+            opcode if opcode.is_branch() => (tainted(rd) || tainted(rs1), None),
+            Opcode::JL => (false, Some((RETURN_ADDRESS, false))),
+            Opcode::JLR => (tainted(rs1), Some((RETURN_ADDRESS, false))),
+            _ => (tainted(rs1), Some((rd, tainted(rs1)))),
+        };
+        if let Some((register, taint)) = result {
+            if register != RegisterId::ZERO {
+                self.registers[register as usize] = taint;
+            }
+        }
+        if consumed {
+            self.uses
+                .entry(address)
+                .or_insert(TaintedUse { word, count: 0 })
+                .count += 1;
+        }
+        self.save_registers(machine);
+    }
+
+    /// Writes the instructions which consumed tainted data, and what is still tainted.
+    pub fn write_report<W: Write>(
+        &self,
+        writer: &mut W,
+        debug_info: Option<&DebugInfo>,
+    ) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "Tainted data was consumed by {} instructions",
+            self.uses.len()
+        )?;
+        if !self.uses.is_empty() {
+            writeln!(writer)?;
+            writeln!(writer, "  Count      Address     Instruction")?;
+        }
+        for (address, tainted_use) in self.uses.iter() {
+            let text = vasm::disassemble(tainted_use.word).unwrap_or_else(|| "???".to_owned());
+            let mut line = format!(
+                "  {:<10} 0x{:08X}  {:<24}",
+                tainted_use.count, address, text
+            );
+            if let Some(label) = debug_info.and_then(|info| nearest_label(info, *address)) {
+                line.push_str(&format!(" <{}>", label));
+            }
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+        let registers: Vec<String> = (0..REGISTER_COUNT)
+            .filter(|i| self.registers[*i])
+            .map(|i| format!("${:?}", RegisterId::from_usize(i).unwrap()))
+            .collect();
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "Tainted at the end: {} bytes of memory, registers {}",
+            self.memory.len(),
+            if registers.is_empty() {
+                "none".to_owned()
+            } else {
+                registers.join(", ")
+            }
+        )
+    }
+}
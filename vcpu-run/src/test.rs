@@ -0,0 +1,2895 @@
+use super::*;
+use config::{parse_frequency, parse_size, parse_timeout, MachineConfig};
+use ratatui::backend::TestBackend;
+use ratatui::style::Modifier;
+use ratatui::Terminal;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Duration;
+use vcpu::{ProgramError, Storage, REGISTER_COUNT};
+
+/// Console output which stays readable after it was handed to a machine.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn assemble(input: &str) -> Executable {
+    vasm::assemble_program(input, 0).unwrap().executable
+}
+
+#[test]
+fn run_with_console() {
+    let executable = assemble(
+        ".include <std/uart.vasm>
+.data
+text:   .byte 104, 105, 0
+.instructions
+start:  LDA $A0, text
+        JL uart_puts
+        LI $V0, 3
+        HALT
+.entry start",
+    );
+    let output = SharedOutput::default();
+    let mut machine = Machine::new(
+        &executable,
+        1024,
+        &[DEFAULT_DEVICE],
+        Box::new(output.clone()),
+    )
+    .unwrap();
+
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(&output.0.borrow()[..], b"hi");
+    assert_eq!(exit_status(&machine, Stop::Exit(ExitCode::Halted)), 3);
+    assert_eq!(machine.processor().register(RegisterId::SP).u(), 1024);
+}
+
+#[test]
+fn program_errors() {
+    let executable = assemble(
+        ".data
+.block 64
+.instructions
+        LI $T0, 1
+        DIV $T0, $T0, $ZERO",
+    );
+    assert_eq!(
+        Machine::new(
+            &executable,
+            32,
+            &[DEFAULT_DEVICE],
+            Box::new(SharedOutput::default())
+        )
+        .err()
+        .unwrap(),
+        "The program needs 64 bytes of RAM, but only 32 are available"
+    );
+    assert_eq!(
+        Machine::new(
+            &executable,
+            0xFFFF_0004,
+            &[DEFAULT_DEVICE],
+            Box::new(SharedOutput::default())
+        )
+        .err()
+        .unwrap(),
+        "Device uart@0xFFFF0000 overlaps the RAM or another device"
+    );
+
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::DivisionByZero)
+    );
+    assert_eq!(
+        exit_status(&machine, Stop::Exit(ExitCode::DivisionByZero)),
+        129
+    );
+}
+
+#[test]
+fn limits() {
+    let executable = assemble(
+        ".data
+.instructions
+loop:   ADDI $T0, $T0, 1
+        JMP loop",
+    );
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let limits = Limits {
+        max_instructions: Some(100),
+        ..Limits::default()
+    };
+    assert_eq!(machine.run(&limits), Stop::InstructionLimit);
+    assert_eq!(machine.executed(), 100);
+    assert_eq!(machine.processor().register(RegisterId::T0).i(), 50);
+    assert_eq!(exit_status(&machine, Stop::InstructionLimit), 124);
+
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(10)),
+        ..Limits::default()
+    };
+    assert_eq!(machine.run(&limits), Stop::Timeout);
+    assert!(machine.executed() > 100);
+
+    assert_eq!(parse_timeout("1.5"), Ok(Duration::from_millis(1500)));
+    assert!(parse_timeout("-1").is_err());
+    assert!(parse_timeout("soon").is_err());
+}
+
+#[test]
+fn realtime() {
+    let executable = assemble(
+        ".data
+.instructions
+loop:   ADDI $T0, $T0, 1
+        JMP loop",
+    );
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let limits = Limits {
+        max_instructions: Some(100),
+        ..Limits::default()
+    };
+    let started = std::time::Instant::now();
+    assert_eq!(machine.run_realtime(&limits, 2000), Stop::InstructionLimit);
+    assert_eq!(machine.executed(), 100);
+    // 100 instructions at 2 kHz take 50 ms
+    assert!(started.elapsed() >= Duration::from_millis(45));
+
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(30)),
+        ..Limits::default()
+    };
+    assert_eq!(machine.run_realtime(&limits, 1000), Stop::Timeout);
+    assert!(machine.executed() <= 100 + 40);
+
+    assert_eq!(parse_frequency("1000"), Ok(1000));
+    assert_eq!(parse_frequency("2.5M"), Ok(2_500_000));
+    assert_eq!(parse_frequency("50kHz"), Ok(50_000));
+    assert_eq!(parse_frequency("1GHz"), Ok(1_000_000_000));
+    assert!(parse_frequency("0").is_err());
+    assert!(parse_frequency("fast").is_err());
+}
+
+#[test]
+fn fake_clock() {
+    let executable = assemble(
+        ".data
+.instructions
+loop:   ADDI $T0, $T0, 1
+        JMP loop",
+    );
+    let mut machine = Machine::new(&executable, 64, &[], Box::new(std::io::sink())).unwrap();
+    let clock = clock::FakeClock::new();
+    machine.set_clock_source(Rc::new(clock.clone()));
+
+    // the runner sleeps on the fake clock, so 3 seconds at 1 kHz pass at once
+    let limits = Limits {
+        timeout: Some(Duration::from_secs(3)),
+        ..Limits::default()
+    };
+    let started = std::time::Instant::now();
+    assert_eq!(machine.run_realtime(&limits, 1000), Stop::Timeout);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // the slice which would be due at the timeout does not run anymore
+    assert_eq!(machine.executed(), 3000 - 10);
+    assert_eq!(clock::ClockSource::now(&clock), Duration::from_secs(3));
+
+    // the clock stands still while the program runs, until it is advanced
+    let limits = Limits {
+        max_instructions: Some(machine.executed() + 100_000),
+        timeout: Some(Duration::from_millis(10)),
+    };
+    assert_eq!(machine.run(&limits), Stop::InstructionLimit);
+    let limits = Limits {
+        max_instructions: None,
+        ..limits
+    };
+    // the timeout is checked every 4096 instructions
+    let end = machine.executed() + 2 * 4096;
+    let stop = machine.run_observed(&limits, |_, _, _| {
+        clock.advance(Duration::from_millis(1));
+    });
+    assert_eq!(stop, Stop::Timeout);
+    assert!(machine.executed() <= end);
+}
+
+#[test]
+fn frames() {
+    use frame::FrameDriver;
+
+    let executable = assemble(
+        ".data
+.instructions
+wait:   LW $T0, 0x100($ZERO)
+        BEZ $T0, wait
+        LWI $T1, 0xFFFF0000
+        SW $T0, 0($T1)
+        HALT",
+    );
+    let console = monitor::ConsoleBuffer::default();
+    let mut machine = Machine::new(
+        &executable,
+        1024,
+        &[DEFAULT_DEVICE],
+        Box::new(console.clone()),
+    )
+    .unwrap();
+    let mut driver = FrameDriver::new(10);
+    driver.set_console(Some(console));
+
+    let frame = driver.run_frame(&mut machine).unwrap();
+    assert_eq!(frame.number, 0);
+    assert_eq!(frame.executed, 10);
+    assert_eq!(frame.exit_code, None);
+    assert!(frame.console.is_empty() && frame.device_writes.is_empty());
+
+    driver.queue_input(0x100, 4, u32::from(b'A'));
+    let frame = driver.run_frame(&mut machine).unwrap();
+    assert_eq!(frame.number, 1);
+    assert!(frame.executed < 10);
+    assert_eq!(frame.exit_code, Some(ExitCode::Halted));
+    assert_eq!(frame.console, b"A");
+    assert_eq!(frame.device_writes.len(), 1);
+    assert_eq!(frame.device_writes[0].value, u32::from(b'A'));
+    assert_eq!(driver.frames(), 2);
+
+    driver.queue_input(0x8000_0000, 4, 0);
+    assert_eq!(
+        driver.run_frame(&mut machine),
+        Err("Address 0x80000000 cannot be written".to_owned())
+    );
+    assert_eq!(
+        FrameDriver::for_clock(6000, 60).instructions_per_frame(),
+        100
+    );
+}
+
+#[test]
+fn audio() {
+    // queues two samples, starts playback and waits until the FIFO is empty
+    let executable = assemble(
+        ".data
+.instructions
+start:  LWI $T0, 0xFFFF2000
+        LI $T1, -2
+        SW $T1, 0($T0)
+        LI $T1, 3
+        SH $T1, 0($T0)
+        LI $T1, 1
+        SW $T1, 8($T0)
+wait:   LW $T1, 12($T0)
+        BNZ $T1, wait
+        LW $S0, 4($T0)
+        HALT",
+    );
+    let device: Device = "audio@0xFFFF2000".parse().unwrap();
+    let run = |clock_hz| {
+        let mut machine =
+            Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+        let output = machine.audio_outputs()[0].clone();
+        output.set_clock(clock_hz);
+        assert_eq!(
+            machine.run(&Limits::default()),
+            Stop::Exit(ExitCode::Halted)
+        );
+        assert_eq!(machine.processor().register(RegisterId::S0).u(), 22050);
+        (machine.executed(), output)
+    };
+
+    // without a clock, the samples are played at once
+    let (executed, output) = run(None);
+    assert_eq!(executed, 12);
+    assert_eq!(output.device(), device);
+    assert!(output.is_playing());
+    assert_eq!(output.take(8), vec![-2, 3]);
+    assert_eq!(output.available(), 0);
+
+    // one sample every 10 instructions
+    let (executed, output) = run(Some(220_500));
+    assert!(executed > 20);
+    assert_eq!(output.take(1), vec![-2]);
+    assert_eq!(output.available(), 1);
+    assert_eq!(output.dropped(), 0);
+}
+
+#[test]
+fn serial() {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    // answers every received byte with the next one, and halts after the second
+    let executable = assemble(
+        ".data
+.instructions
+start:  LWI $T0, 0xFFFF3000
+        LI $S0, 2
+wait:   LW $T1, 8($T0)
+        ANDI $T1, $T1, 1
+        BEZ $T1, wait
+        LW $T1, 4($T0)
+        SW $ZERO, 12($T0)
+        ADDI $T1, $T1, 1
+        SW $T1, 0($T0)
+        SUBI $S0, $S0, 1
+        BNZ $S0, wait
+        LW $S1, 4($T0)
+        HALT",
+    );
+    let device: Device = "serial@0xFFFF3000".parse().unwrap();
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    assert_eq!(machine.serial_ports()[0].device(), device);
+    let address = machine.listen_serial("127.0.0.1:0").unwrap();
+    assert!(machine.listen_serial("127.0.0.1:0").is_err());
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"ae").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let limits = Limits {
+        timeout: Some(Duration::from_secs(5)),
+        ..Limits::default()
+    };
+    assert_eq!(machine.run(&limits), Stop::Exit(ExitCode::Halted));
+    assert_eq!(
+        machine.processor().register(RegisterId::S1).u(),
+        serial::NOTHING_RECEIVED
+    );
+    assert!(machine.serial_ports()[0].is_connected());
+    let mut answer = [0; 2];
+    client.read_exact(&mut answer).unwrap();
+    assert_eq!(&answer, b"bf");
+
+    // without a connection, received bytes come from the host
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    let port = machine.serial_ports()[0].clone();
+    port.receive(b"12");
+    assert_eq!(machine.run(&limits), Stop::Exit(ExitCode::Halted));
+    assert_eq!(port.take_transmitted(), b"23");
+    assert!(!port.is_connected());
+}
+
+#[test]
+fn network() {
+    use network::{Hub, UdpLink};
+
+    // station 0 sends "hi" to station 1, which answers with the first byte plus one
+    let ping = assemble(
+        ".data
+.instructions
+        LWI $T0, 0xFFFF4000
+        LI $T1, 1
+        SW $T1, 8($T0)
+        LI $T1, 0x6968
+        SH $T1, 32($T0)
+        LI $T1, 2
+        SW $T1, 12($T0)
+wait:   LW $T1, 0($T0)
+        BEZ $T1, wait
+        LW $S0, 20($T0)
+        LB $S1, 288($T0)
+        LW $S2, 16($T0)
+        HALT",
+    );
+    let pong = assemble(
+        ".data
+.instructions
+        LWI $T0, 0xFFFF4000
+wait:   LW $T1, 0($T0)
+        BEZ $T1, wait
+        LW $T1, 20($T0)
+        SW $T1, 8($T0)
+        LB $T1, 288($T0)
+        ADDI $T1, $T1, 1
+        SB $T1, 32($T0)
+        SW $ZERO, 24($T0)
+        LI $T1, 1
+        SW $T1, 12($T0)
+        LW $S2, 16($T0)
+        HALT",
+    );
+    let device: Device = "network@0xFFFF4000".parse().unwrap();
+    let start = |executable: &Executable| {
+        Machine::new(executable, 1024, &[device], Box::new(std::io::sink())).unwrap()
+    };
+    let check = |machines: &[Machine]| {
+        let register = |machine: &Machine, id| machine.processor().register(id).u();
+        assert_eq!(register(&machines[0], RegisterId::S0), 1);
+        assert_eq!(register(&machines[0], RegisterId::S1), u32::from(b'i'));
+        assert_eq!(register(&machines[0], RegisterId::S2), 1);
+        assert_eq!(register(&machines[1], RegisterId::S2), 0);
+    };
+    let limits = Limits {
+        max_instructions: Some(100_000),
+        timeout: Some(Duration::from_secs(5)),
+    };
+
+    let mut machines = vec![start(&ping), start(&pong)];
+    let mut hub = Hub::new();
+    hub.attach(&machines[0]);
+    hub.attach(&machines[1]);
+    assert_eq!(machines[1].network_ports()[0].station(), 1);
+    let stops = network::run_connected(&mut machines, &mut hub, 100, &limits);
+    assert_eq!(stops, vec![Stop::Exit(ExitCode::Halted); 2]);
+    check(&machines);
+
+    // the same over UDP, with the machines running in turns
+    let mut machines = vec![start(&ping), start(&pong)];
+    let probe = || {
+        UdpLink::bind("127.0.0.1:0", &[])
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    };
+    let free = [probe().to_string(), probe().to_string()];
+    for (i, machine) in machines.iter_mut().enumerate() {
+        let peer = free[1 - i].as_str();
+        machine.link_network(i as u32, &free[i], &[peer]).unwrap();
+    }
+    assert!(machines[0].link_network(0, "127.0.0.1:0", &[]).is_err());
+    let mut stops = vec![None, None];
+    for _ in 0..1000 {
+        for (machine, stop) in machines.iter_mut().zip(stops.iter_mut()) {
+            if stop.is_none() {
+                let turn = Limits {
+                    max_instructions: Some(machine.executed() + 2048),
+                    timeout: None,
+                };
+                *stop = Some(machine.run(&turn)).filter(|stop| *stop != Stop::InstructionLimit);
+            }
+        }
+        if stops.iter().all(Option::is_some) {
+            break;
+        }
+    }
+    assert_eq!(stops, vec![Some(Stop::Exit(ExitCode::Halted)); 2]);
+    check(&machines);
+}
+
+#[test]
+fn break_at_interrupt_handler() {
+    let assembly = vasm::assemble_program(
+        ".include <std/csr.vasm>
+.data
+.instructions
+        LIA $T1, handler
+        CSRW $ZERO, $T1, CSR_IVEC
+        LI $T1, 1
+        CSRS $ZERO, $T1, CSR_IE
+loop:   JMP loop
+handler: ADDI $S0, $S0, 1
+        HALT",
+        0,
+    )
+    .unwrap();
+    let handler = assembly.debug_info.symbol("handler").unwrap().address;
+    let start = |instructions| {
+        let mut machine =
+            Machine::new(&assembly.executable, 1024, &[], Box::new(std::io::sink())).unwrap();
+        let limits = Limits {
+            max_instructions: Some(instructions),
+            timeout: None,
+        };
+        assert_eq!(machine.run(&limits), Stop::InstructionLimit);
+        machine.processor_mut().raise_interrupt(0);
+        machine
+    };
+
+    // the breakpoint at the handler is reached before its first instruction
+    let mut machine = start(100);
+    assert_eq!(
+        machine.run_until(&Limits::default(), |address| address == handler),
+        None
+    );
+    assert_eq!(machine.processor().program_counter(), handler);
+    assert_eq!(machine.executed(), 100);
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 0);
+
+    // the first observed instruction after the interrupt is the one of the handler
+    let mut machine = start(101);
+    let mut observed = Vec::new();
+    let stop = machine.run_observed(&Limits::default(), |address, word, _| {
+        observed.push((address, word))
+    });
+    assert_eq!(stop, Stop::Exit(ExitCode::Halted));
+    assert_eq!(
+        observed[0],
+        (handler, machine.instruction_at(handler).unwrap())
+    );
+    assert_eq!(observed.len(), 2);
+}
+
+#[test]
+fn network_interrupt() {
+    // the handler, which runs on the shadow bank, adds up the first bytes of the packets in the registers of the
+    // main program, which waits for two of them
+    let executable = assemble(
+        ".include <std/csr.vasm>
+.data
+.instructions
+        LWI $T0, 0xFFFF4000
+        LIA $T1, handler
+        CSRW $ZERO, $T1, CSR_IVEC
+        LI $T1, 3
+        SW $T1, 28($T0)
+        LI $T1, 8
+        CSRS $ZERO, $T1, CSR_IE
+wait:   SLTI $T1, $S1, 2
+        BNZ $T1, wait
+        HALT
+handler: LWI $T0, 0xFFFF4000
+        LB $T2, 288($T0)
+        ADD $S0, $S0, $T2
+        ADDI $S1, $S1, 1
+        WRSH $S0, $S0
+        WRSH $S1, $S1
+        SW $ZERO, 24($T0)
+        IRET",
+    );
+    let device: Device = "network@0xFFFF4000".parse().unwrap();
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    let port = machine.network_ports()[0].clone();
+    let limits = Limits {
+        max_instructions: Some(1000),
+        timeout: None,
+    };
+    assert_eq!(machine.run(&limits), Stop::InstructionLimit);
+    assert!(!port.has_packet());
+    for byte in [5, 7].iter() {
+        port.deliver(network::Packet {
+            source: 1,
+            destination: 0,
+            data: vec![*byte],
+        });
+    }
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 12);
+    assert!(!port.has_packet());
+    assert_eq!(machine.processor().csr(vcpu::CSR_IP), Some(0));
+
+    // without a line in INTERRUPT, the device raises none
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    assert_eq!(
+        machine.memory().read_word(0xFFFF4000 + network::INTERRUPT),
+        Ok(NO_INTERRUPT)
+    );
+    machine.network_ports()[0].deliver(network::Packet {
+        source: 1,
+        destination: 0,
+        data: vec![1],
+    });
+    machine.step();
+    assert_eq!(machine.processor().csr(vcpu::CSR_IP), Some(0));
+}
+
+#[test]
+fn gpio() {
+    // waits for a change of pin 1, then drives pin 0 and reads all levels
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0xFFFF5000
+        LI $T1, 1
+        SW $T1, 0($T0)
+        LI $T1, 2
+        SW $T1, 12($T0)
+wait:   LW $T1, 16($T0)
+        BEZ $T1, wait
+        SW $T1, 20($T0)
+        LI $T1, 1
+        SW $T1, 4($T0)
+        LW $S0, 8($T0)
+        LW $S1, 16($T0)
+        HALT",
+    );
+    let device: Device = "gpio@0xFFFF5000".parse().unwrap();
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    let pins = machine.gpio_pins()[0].clone();
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    {
+        // pin 0 is wired to pin 2
+        let changes = Rc::clone(&changes);
+        let wired = pins.clone();
+        pins.on_change(move |pin, level| {
+            changes.borrow_mut().push((pin, level));
+            if pin == 0 {
+                wired.set_input(2, level);
+            }
+        });
+    }
+
+    assert_eq!(
+        machine.run(&Limits {
+            max_instructions: Some(100),
+            ..Limits::default()
+        }),
+        Stop::InstructionLimit
+    );
+    assert_eq!(pins.outputs(), 1);
+    pins.set_input(0, true);
+    pins.set_input(1, true);
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(*changes.borrow(), vec![(0, true)]);
+    let processor = machine.processor();
+    assert_eq!(processor.register(RegisterId::S0).u(), 0b111);
+    assert_eq!(processor.register(RegisterId::S1).u(), 0);
+    assert!(pins.level(2) && !pins.level(3));
+}
+
+#[test]
+fn gpio_interrupt() {
+    // the handler, which runs on the shadow bank, counts the changes of pin 1 in the registers of the main program,
+    // which waits for two of them
+    let executable = assemble(
+        ".include <std/csr.vasm>
+.data
+.instructions
+        LWI $T0, 0xFFFF5000
+        LIA $T1, handler
+        CSRW $ZERO, $T1, CSR_IVEC
+        LI $T1, 2
+        SW $T1, 12($T0)
+        LI $T1, 5
+        SW $T1, 24($T0)
+        LI $T1, 32
+        CSRS $ZERO, $T1, CSR_IE
+wait:   SLTI $T1, $S0, 2
+        BNZ $T1, wait
+        HALT
+handler: LWI $T0, 0xFFFF5000
+        LW $T1, 16($T0)
+        SW $T1, 20($T0)
+        ADDI $S0, $S0, 1
+        WRSH $S0, $S0
+        IRET",
+    );
+    let device: Device = "gpio@0xFFFF5000".parse().unwrap();
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    let pins = machine.gpio_pins()[0].clone();
+    // the limit counts all instructions the machine executed
+    let limits = |max| Limits {
+        max_instructions: Some(max),
+        timeout: None,
+    };
+    assert_eq!(machine.run(&limits(100)), Stop::InstructionLimit);
+    // a change of a pin without an enabled edge raises nothing
+    pins.set_input(0, true);
+    assert_eq!(machine.run(&limits(200)), Stop::InstructionLimit);
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 0);
+    pins.set_input(1, true);
+    assert_eq!(machine.run(&limits(300)), Stop::InstructionLimit);
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 1);
+    assert_eq!(pins.pending(), 0);
+    pins.set_input(1, false);
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 2);
+    assert_eq!(machine.processor().csr(vcpu::CSR_IP), Some(0));
+    assert_eq!(
+        machine.memory().read_word(0xFFFF5000 + gpio::INTERRUPT),
+        Ok(5)
+    );
+}
+
+#[test]
+fn flash() {
+    // counts the runs in the first word, and clears the bits of a byte with two writes
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0x80000000
+        LW $S0, 16($T0)
+        ADDI $S0, $S0, 1
+        SW $ZERO, 0($T0)
+        SW $S0, 16($T0)
+        LI $T1, 0x0F
+        SB $T1, 20($T0)
+        LI $T1, 0xF0
+        SB $T1, 20($T0)
+        LB $S1, 20($T0)
+        LW $S2, 12($T0)
+        SW $ZERO, 4($T0)
+        LW $S3, 8($T0)
+        HALT",
+    );
+    let path = std::env::temp_dir().join(format!("vcpu-flash-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = MachineConfig::parse(&format!(
+        "flash-file = \"{}\"\n[[device]]\nkind = \"flash\"\naddress = 0x80000000",
+        path.display()
+    ))
+    .unwrap();
+    let session = debugger::Session::new(executable, config, Vec::new(), Vec::new());
+    let run = || {
+        let mut machine = session.start(Box::new(std::io::sink())).unwrap();
+        assert_eq!(
+            machine.run(&Limits::default()),
+            Stop::Exit(ExitCode::Halted)
+        );
+        let memory = machine.flash_memories()[0].clone();
+        memory.save(&path).unwrap();
+        let register = |id| machine.processor().register(id).u();
+        assert_eq!(register(RegisterId::S1), 0);
+        assert_eq!(register(RegisterId::S2), 1);
+        assert_eq!(register(RegisterId::S3), 1);
+        (register(RegisterId::S0), memory)
+    };
+
+    assert_eq!(run().0, 0);
+    let (count, memory) = run();
+    assert_eq!(count, 1);
+    assert_eq!(memory.wear(0), Some(1));
+    assert_eq!(memory.wear(flash::PAGES), None);
+    let image = std::fs::read(&path).unwrap();
+    assert_eq!(image.len(), flash::IMAGE_BYTES as usize);
+    assert_eq!(image[..5], [1, 0, 0, 0, 0]);
+    assert_eq!(image[flash::PAGE_BYTES as usize], flash::ERASED);
+
+    // a worn out page keeps its contents
+    let mut machine = session.start(Box::new(std::io::sink())).unwrap();
+    let memory = machine.flash_memories()[0].clone();
+    memory.set_endurance(Some(0));
+    machine.run(&Limits::default());
+    assert_eq!(memory.wear(0), Some(0));
+    assert!(machine.processor().register(RegisterId::S2).u() > 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn disk() {
+    // writes a sector, reads another one and reads the first one back
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0x80000000
+        LW $S0, 8($T0)
+        LI $T1, 0x1234
+        SW $T1, 20($T0)
+        LI $T1, 3
+        SW $T1, 4($T0)
+        LI $T2, 2
+        SW $T2, 0($T0)
+        LI $T1, 1
+        SW $T1, 4($T0)
+        SW $T1, 0($T0)
+        LW $S1, 20($T0)
+        LI $T1, 3
+        SW $T1, 4($T0)
+        LI $T1, 1
+        SW $T1, 0($T0)
+        LW $S2, 20($T0)
+        LW $S3, 12($T0)
+        LI $T1, 2048
+        SW $T1, 4($T0)
+        LI $T1, 1
+        SW $T1, 0($T0)
+        LW $S4, 12($T0)
+        HALT",
+    );
+    let device = Device {
+        kind: DeviceKind::Disk,
+        address: 0x8000_0000,
+    };
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    let disk = machine.disks()[0].clone();
+    disk.set_contents(&[0xAA; disk::SECTOR_BYTES as usize * 2])
+        .unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    let register = |id| machine.processor().register(id).u();
+    assert_eq!(register(RegisterId::S0), disk::SECTOR_COUNT);
+    assert_eq!(register(RegisterId::S1), 0xAAAA_AAAA);
+    assert_eq!(register(RegisterId::S2), 0x1234);
+    assert_eq!(register(RegisterId::S3), disk::OK);
+    assert_eq!(register(RegisterId::S4), disk::FAILED);
+    let contents = disk.contents();
+    assert_eq!(contents.len(), disk::IMAGE_BYTES as usize);
+    assert_eq!(contents[3 * disk::SECTOR_BYTES as usize + 4], 0x34);
+    assert!(disk
+        .set_contents(&vec![0; disk::IMAGE_BYTES as usize + 1])
+        .is_err());
+
+    machine.detach_device(0x8000_0000).unwrap();
+    assert!(machine.disks().is_empty());
+}
+
+#[test]
+fn boot_rom() {
+    use vcpu::StorageMut;
+
+    let rom = assemble(include_str!("../roms/boot.vasm"));
+    // a program without data, which calls a function to show that it runs from the RAM
+    let program = assemble(
+        ".data
+.instructions
+        LI $A0, 111
+        JL putc
+        LI $A0, 107
+        JL putc
+        LI $V0, 5
+        HALT
+putc:   LHI $T0, 0xFFFF
+        SB $A0, 0($T0)
+        JR $RA",
+    );
+    let devices = [
+        DEFAULT_DEVICE,
+        Device {
+            kind: DeviceKind::Disk,
+            address: 0xFFFE_0000,
+        },
+    ];
+    let output = SharedOutput::default();
+    let mut machine =
+        Machine::boot(&rom, 0xFFF0_0000, 4096, &devices, Box::new(output.clone())).unwrap();
+    assert_eq!(machine.processor().program_counter(), 0xFFF0_0000);
+    assert_eq!(machine.processor().register(RegisterId::SP).u(), 4096);
+    assert!(machine.instructions().is_empty());
+    assert_eq!(
+        machine.instruction_at(0xFFF0_0000),
+        rom.instructions().read_word(0).ok()
+    );
+    machine.disks()[0]
+        .set_contents(&disk::boot_image(program.instructions()))
+        .unwrap();
+
+    let stop = machine.run(&Limits::default());
+    assert_eq!(stop, Stop::Exit(ExitCode::Halted));
+    assert_eq!(exit_status(&machine, stop), 5);
+    assert_eq!(&output.0.borrow()[..], b"ok");
+    let loaded = program.instructions().len() as u32 - 4;
+    assert_eq!(
+        machine.instruction_at(loaded),
+        program.instructions().read_word(loaded).ok()
+    );
+    // the ROM cannot be changed, and a reboot starts it again
+    machine.memory_mut().write_word(0xFFF0_0000, 0).unwrap();
+    assert_eq!(
+        machine.instruction_at(0xFFF0_0000),
+        rom.instructions().read_word(0).ok()
+    );
+    machine.reboot();
+    assert_eq!(machine.processor().program_counter(), 0xFFF0_0000);
+    assert_eq!(machine.run(&Limits::default()), stop);
+    assert_eq!(&output.0.borrow()[..], b"okok");
+
+    // an empty disk is a program without instructions, so the processor runs through the cleared RAM
+    let mut machine =
+        Machine::boot(&rom, 0xFFF0_0000, 4096, &devices, Box::new(std::io::sink())).unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::BadProgramCounter)
+    );
+    assert_eq!(machine.processor().program_counter(), 4096);
+
+    assert_eq!(
+        Machine::boot(&rom, 0, 4096, &devices, Box::new(std::io::sink()))
+            .err()
+            .unwrap(),
+        "The boot ROM at 0x00000000 overlaps the RAM"
+    );
+    assert_eq!(
+        Machine::boot(
+            &program,
+            0xFFFE_0000,
+            4096,
+            &devices,
+            Box::new(std::io::sink())
+        )
+        .err()
+        .unwrap(),
+        "Device disk@0xFFFE0000 overlaps the RAM or another device"
+    );
+}
+
+#[test]
+fn power() {
+    // clobbers its data and reboots, then shuts down with the reloaded value plus the number of arguments
+    let executable = assemble(
+        ".data
+marker: .word 5
+.instructions
+        LWI $T0, 0x80000000
+        LDA $T1, marker
+        LW $S0, 0($T1)
+        LW $S1, 8($T0)
+        BNZ $S1, off
+        SW $ZERO, 0($T1)
+        LI $T2, 2
+        SW $T2, 0($T0)
+        HALT
+off:    ADD $T2, $S0, $A0
+        SW $T2, 4($T0)
+        LI $T2, 1
+        SW $T2, 0($T0)
+        LI $V0, 99
+        HALT",
+    );
+    let device = Device {
+        kind: DeviceKind::Power,
+        address: 0x8000_0000,
+    };
+    let mut machine =
+        Machine::new(&executable, 4096, &[device], Box::new(std::io::sink())).unwrap();
+    machine.pass_arguments(&["power", "x"], &[]).unwrap();
+    let stop = machine.run(&Limits::default());
+    assert_eq!(stop, Stop::Exit(ExitCode::Halted));
+    assert_eq!(exit_status(&machine, stop), 7);
+    assert_eq!(machine.boots(), 1);
+    assert_eq!(machine.processor().register(RegisterId::S1).u(), 1);
+    assert_eq!(machine.executed(), 10 + 11);
+    // a stopped machine stays stopped
+    assert_eq!(machine.step(), Some(ExitCode::Halted));
+}
+
+#[test]
+fn hot_plug() {
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0xFFFF0000
+        LI $T1, 65
+        SW $T1, 0($T0)
+        HALT",
+    );
+    let output = SharedOutput::default();
+    let mut machine = Machine::new(&executable, 1024, &[], Box::new(output.clone())).unwrap();
+    for _ in 0..3 {
+        assert_eq!(machine.step(), None);
+    }
+    assert!(machine
+        .attach_device(Device {
+            kind: DeviceKind::Uart,
+            address: 0x200,
+        })
+        .is_err());
+    machine.attach_device(DEFAULT_DEVICE).unwrap();
+    assert!(machine.attach_device(DEFAULT_DEVICE).is_err());
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(&output.0.borrow()[..], b"A");
+
+    // the connections move with the ports which remain
+    let serial = |address| Device {
+        kind: DeviceKind::Serial,
+        address,
+    };
+    machine.attach_device(serial(0x8000_0000)).unwrap();
+    machine.attach_device(serial(0x8000_0100)).unwrap();
+    machine.listen_serial("127.0.0.1:0").unwrap();
+    assert_eq!(machine.detach_device(0x8000_0000), Ok(serial(0x8000_0000)));
+    assert!(machine.detach_device(0x8000_0000).is_err());
+    machine.listen_serial("127.0.0.1:0").unwrap();
+    assert_eq!(machine.serial_ports()[0].device(), serial(0x8000_0100));
+    assert_eq!(machine.devices(), [DEFAULT_DEVICE, serial(0x8000_0100)]);
+    assert!(machine.memory().read_word(0x8000_0000).is_err());
+}
+
+#[test]
+fn device_change_interrupt() {
+    // the handler, which runs on the shadow bank, counts the changes in the registers of the main program, which
+    // waits for two of them
+    let executable = assemble(
+        ".include <std/csr.vasm>
+.data
+.instructions
+        LIA $T1, handler
+        CSRW $ZERO, $T1, CSR_IVEC
+        LI $T1, 128
+        CSRS $ZERO, $T1, CSR_IE
+wait:   SLTI $T1, $S0, 2
+        BNZ $T1, wait
+        HALT
+handler: LI $T1, 128
+        CSRC $ZERO, $T1, CSR_IP
+        ADDI $S0, $S0, 1
+        WRSH $S0, $S0
+        IRET",
+    );
+    let mut machine = Machine::new(&executable, 1024, &[], Box::new(std::io::sink())).unwrap();
+    assert!(machine.set_device_change_interrupt(Some(32)).is_err());
+    machine.set_device_change_interrupt(Some(7)).unwrap();
+    let limits = |max| Limits {
+        max_instructions: Some(max),
+        timeout: None,
+    };
+    assert_eq!(machine.run(&limits(100)), Stop::InstructionLimit);
+    machine.attach_device(DEFAULT_DEVICE).unwrap();
+    // a device which cannot be attached changes nothing
+    assert!(machine.attach_device(DEFAULT_DEVICE).is_err());
+    assert_eq!(machine.run(&limits(200)), Stop::InstructionLimit);
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 1);
+    machine.detach_device(DEFAULT_DEVICE.address).unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 2);
+    assert_eq!(machine.processor().csr(vcpu::CSR_IP), Some(0));
+}
+
+#[test]
+fn code_loading() {
+    // the program ends without a HALT until one is loaded behind it
+    let executable = assemble(
+        ".data
+.instructions
+        LI $V0, 1",
+    );
+    let code = assemble(
+        ".data
+.instructions
+        LI $V0, 2
+        HALT",
+    );
+    let mut machine = Machine::new(&executable, 1024, &[], Box::new(std::io::sink())).unwrap();
+    let program = machine.program_mut();
+    assert_eq!(program.patch(0, &code.instructions()[..4]), Ok(()));
+    assert_eq!(
+        program.patch(4, &code.instructions()[4..]),
+        Err(ProgramError::OutOfRange)
+    );
+    assert_eq!(program.load(4, &code.instructions()[4..]), Ok(()));
+    assert_eq!(program.generation(), 2);
+    assert_eq!(machine.instructions(), code.instructions());
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(machine.processor().register(RegisterId::V0).u(), 2);
+
+    machine.program_mut().set_read_only(true);
+    assert_eq!(
+        machine.program_mut().load(8, &[0; 4]),
+        Err(ProgramError::ReadOnly)
+    );
+    assert_eq!(machine.program().generation(), 2);
+}
+
+#[test]
+fn loader() {
+    let mut executable = vex::Executable::new(vec![0; 8]);
+    executable.set_entry_point(4);
+    executable
+        .add_section(vex::Section::new(0x10, vec![1, 2, 3]))
+        .unwrap();
+    executable
+        .add_section(vex::Section::read_only(0x20, vec![4]))
+        .unwrap();
+    executable
+        .add_section(vex::Section::zero_fill(0x100, 8))
+        .unwrap();
+
+    // zero-fill sections are cleared even if the memory was not
+    let mut memory = vcpu::CompositeMemory::new();
+    memory.mount(0, "ram", vec![0xFFu8; 0x200]).unwrap();
+    let mut processor = vcpu::Processor::new();
+    let manifest = loader::load(&executable, &mut memory, &mut processor, 0x200).unwrap();
+    assert_eq!(memory.read_word(0x10), Ok(0xFF03_0201));
+    assert_eq!(memory.read_byte(0x20), Ok(4));
+    assert_eq!(memory.read_word(0x104), Ok(0));
+    assert_eq!(memory.read_byte(0x108), Ok(0xFF));
+    assert_eq!(processor.program_counter(), 4);
+    assert_eq!(processor.register(RegisterId::SP).u(), 0x200);
+    assert!(manifest.sections[1].flags.read_only);
+    assert_eq!(manifest.section_at(0x107), Some(&manifest.sections[2]));
+    assert_eq!(manifest.section_at(0x108), None);
+    assert_eq!(
+        manifest.to_string(),
+        "entry point 0x00000004
+stack pointer 0x00000200
+0x00000010-0x00000013          3 bytes
+0x00000020-0x00000021          1 bytes read-only
+0x00000100-0x00000108          8 bytes zero-fill
+"
+    );
+
+    let mut small = vcpu::CompositeMemory::new();
+    small.mount(0, "ram", vec![0u8; 0x104]).unwrap();
+    assert!(loader::load(&executable, &mut small, &mut processor, 0x104).is_err());
+
+    let machine = Machine::new(&executable, 0x400, &[], Box::new(std::io::sink())).unwrap();
+    assert_eq!(machine.manifest().sections, manifest.sections);
+    assert_eq!(machine.manifest().stack_pointer, 0x400);
+}
+
+/// A device which counts the writes to its only register.
+struct Counter(u32);
+
+impl Storage for Counter {
+    fn length(&self) -> u32 {
+        4
+    }
+
+    fn check_range(&self, address: u32, length: u32) -> bool {
+        address == 0 && length == 4
+    }
+
+    fn read(&self, address: u32, size: u32) -> Result<u32, ()> {
+        if self.check_range(address, size) {
+            Ok(self.0)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl vcpu::StorageMut for Counter {
+    fn write(&mut self, address: u32, size: u32, _: u32) -> Result<(), ()> {
+        if !self.check_range(address, size) {
+            return Err(());
+        }
+        self.0 += 1;
+        Ok(())
+    }
+}
+
+struct CounterPlugin;
+
+impl plugin::DevicePlugin for CounterPlugin {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
+
+    fn size(&self) -> u32 {
+        4
+    }
+
+    fn create(&self, _: Device) -> Box<dyn vcpu::StorageMut> {
+        Box::new(Counter(0))
+    }
+}
+
+#[test]
+fn plugins() {
+    assert!("counter".parse::<DeviceKind>().is_err());
+    plugin::register(&CounterPlugin).unwrap();
+    assert!(plugin::register(&CounterPlugin).is_err());
+    assert_eq!(
+        "counter@0x80000000".parse(),
+        Ok(Device {
+            kind: DeviceKind::Plugin("counter"),
+            address: 0x8000_0000,
+        })
+    );
+    assert_eq!(DeviceKind::Plugin("counter").size(), 4);
+
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0x80000000
+        SW $ZERO, 0($T0)
+        SW $ZERO, 0($T0)
+        LW $V0, 0($T0)
+        SH $ZERO, 0($T0)
+        HALT",
+    );
+    let config =
+        MachineConfig::parse("[[device]]\nkind = \"counter\"\naddress = 0x80000000").unwrap();
+    let session = debugger::Session::new(executable, config, Vec::new(), Vec::new());
+    let mut machine = session.start(Box::new(std::io::sink())).unwrap();
+    let log = machine.device_log();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::BadMemoryAccess)
+    );
+    assert_eq!(machine.processor().register(RegisterId::V0).u(), 2);
+    assert_eq!(log.take().len(), 2);
+    assert_eq!(
+        describe_stop(&machine, Stop::Exit(ExitCode::BadMemoryAccess)).unwrap(),
+        "Program stopped at 0x00000014: BadMemoryAccess (address 0x80000000)"
+    );
+}
+
+#[test]
+fn mmio_contract() {
+    // the stores to DIRECTION and the transmit buffer and the load of it are fine, the one in the loop happens twice
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0x80000000
+        LWI $T1, 0x80001000
+        LI $T2, 2
+        SW $T2, 0($T0)
+        SB $T2, 40($T1)
+loop:   SB $T2, 4($T0)
+        SUBI $T2, $T2, 1
+        BNZ $T2, loop
+        LW $T3, 20($T0)
+        SW $T3, 8($T0)
+        LH $T3, 30($T1)
+        LB $T3, 33($T1)
+        LH $T3, 289($T1)
+        HALT",
+    );
+    let gpio = Device {
+        kind: DeviceKind::Gpio,
+        address: 0x8000_0000,
+    };
+    let network = Device {
+        kind: DeviceKind::Network,
+        address: 0x8000_1000,
+    };
+    let mut machine = Machine::new(
+        &executable,
+        4096,
+        &[DEFAULT_DEVICE, gpio, network],
+        Box::new(std::io::sink()),
+    )
+    .unwrap();
+    let mut checker = contract::ContractChecker::new(machine.devices());
+    let mut bus = events::EventBus::new(machine.processor());
+    bus.subscribe(&mut checker);
+    machine.run_observed(&Limits::default(), |address, word, processor| {
+        bus.record(address, word, processor)
+    });
+    drop(bus);
+
+    let problems: Vec<(u32, contract::Problem, u64)> = checker
+        .violations()
+        .iter()
+        .map(|violation| (violation.pc, violation.problem, violation.count))
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            (28, contract::Problem::Size("OUTPUT"), 2),
+            (40, contract::Problem::ReadOfWriteOnly("CLEAR"), 1),
+            (44, contract::Problem::WriteOfReadOnly("INPUT"), 1),
+            (48, contract::Problem::Size("INTERRUPT"), 1),
+            (56, contract::Problem::Size("RX_BUFFER"), 1),
+        ]
+    );
+    assert_eq!(checker.violations()[0].device, gpio);
+    assert_eq!(checker.violations()[4].offset, 289);
+
+    let mut report = Vec::new();
+    checker.write_report(&mut report, None).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("5 accesses to devices broke their register maps\n"));
+    assert!(report.contains(
+        "  1          0x0000002C  store of 4 bytes at gpio@0x80000000+0x8: write of read-only INPUT\n"
+    ));
+}
+
+#[test]
+fn simulation_pool() {
+    // squares its input and returns the number of arguments
+    let executable = assemble(
+        ".data
+input:  .word 0
+output: .word 0
+.instructions
+        LDA $T0, input
+        LW $T1, 0($T0)
+        MUL $T1, $T1, $T1
+        SW $T1, 4($T0)
+        ADD $V0, $A0, $ZERO
+        HALT",
+    );
+    let output = executable.memory_size() - 4;
+    let session = debugger::Session::new(
+        executable,
+        MachineConfig::default(),
+        vec!["square".to_owned()],
+        Vec::new(),
+    );
+    let mut pool = pool::SimulationPool::new(session, 3);
+    pool.set_outputs(vec![0..4, output..output + 4]);
+    let jobs: Vec<pool::Job> = (0..100u32)
+        .map(|i| pool::Job {
+            args: vec!["square".to_owned(); i as usize % 3],
+            memory: vec![(0, i.to_le_bytes().to_vec())],
+            ..pool::Job::default()
+        })
+        .collect();
+    let outcomes = pool.run(&jobs);
+    assert_eq!(outcomes.len(), 100);
+    for (i, outcome) in outcomes.into_iter().enumerate() {
+        let outcome = outcome.unwrap();
+        assert_eq!(outcome.stop, Stop::Exit(ExitCode::Halted));
+        assert_eq!(outcome.status, i as i32 % 3);
+        assert_eq!(outcome.memory[0], (i as u32).to_le_bytes());
+        assert_eq!(outcome.memory[1], ((i * i) as u32).to_le_bytes());
+    }
+
+    let job = pool::Job {
+        memory: vec![(0xFFFF_FFF0, vec![1])],
+        ..pool::Job::default()
+    };
+    assert!(pool.run(&[job])[0].is_err());
+}
+
+#[test]
+fn sizes() {
+    assert_eq!(parse_size("4096"), Ok(4096));
+    assert_eq!(parse_size("0x100"), Ok(256));
+    assert_eq!(parse_size("64K"), Ok(64 * 1024));
+    assert_eq!(parse_size("2m"), Ok(2 * 1024 * 1024));
+    assert!(parse_size("4096M").is_err());
+    assert!(parse_size("K").is_err());
+}
+
+#[test]
+fn machine_file() {
+    let config = MachineConfig::parse(
+        "# two consoles
+ram = \"64K\"
+max-instructions = 1_000
+timeout = 2.5 # seconds
+semihosting-root = \"fixtures\"
+isa = \"base,+shift\"
+reset-vector = 0xFFF00000
+disk-file = \"boot.img\"
+device-change-interrupt = 7
+
+[[device]]
+kind = \"uart\"
+address = 0xFFFF0000
+
+[[device]]
+address = 0x10000
+kind = \"uart\"",
+    )
+    .unwrap();
+    assert_eq!(config.ram_size, Some(64 * 1024));
+    assert_eq!(config.limits.max_instructions, Some(1000));
+    assert_eq!(config.limits.timeout, Some(Duration::from_millis(2500)));
+    assert_eq!(config.semihosting_root, Some("fixtures".into()));
+    assert_eq!(config.reset_vector, Some(0xFFF0_0000));
+    assert_eq!(config.disk_file, Some("boot.img".into()));
+    assert_eq!(config.device_change_interrupt, Some(7));
+    assert_eq!(
+        config.isa,
+        vcpu::IsaProfile::base().with(vcpu::InstructionGroup::Shift)
+    );
+    assert_eq!(
+        config.devices,
+        vec![
+            DEFAULT_DEVICE,
+            Device {
+                kind: DeviceKind::Uart,
+                address: 0x10000
+            }
+        ]
+    );
+
+    // TOML which goes beyond simple values, like escapes and inline tables
+    let config = MachineConfig::parse(
+        "semihosting-root = \"fix\\ttures\"
+device = [{ kind = \"uart\", address = \"64K\" }]",
+    )
+    .unwrap();
+    assert_eq!(config.semihosting_root, Some("fix\ttures".into()));
+    assert_eq!(
+        config.devices,
+        vec![Device {
+            kind: DeviceKind::Uart,
+            address: 0x10000
+        }]
+    );
+
+    assert!(MachineConfig::parse("ram = 1024\nrom = 5")
+        .unwrap_err()
+        .starts_with("line 2: unknown field `rom`"));
+    assert_eq!(
+        MachineConfig::parse("[[device]]\nkind = \"tape\"").unwrap_err(),
+        "line 2: Unknown device \"tape\""
+    );
+    assert_eq!(
+        MachineConfig::parse("[[device]]\nkind = \"uart\"").unwrap_err(),
+        "line 1: missing field `address`"
+    );
+    assert!(MachineConfig::parse("ram = \"1M").is_err());
+    assert_eq!(
+        MachineConfig::parse("isa = \"-simd\"").unwrap_err(),
+        "line 1: Unknown instruction group \"simd\""
+    );
+    let config = MachineConfig::parse("isa = \"regs:ZERO/V0/ACC=T0/SP/RA\"").unwrap();
+    assert_eq!(config.isa.registers().find("acc"), Some(RegisterId::T0));
+    assert_eq!(config.isa.registers().count(), 5);
+    assert_eq!(
+        MachineConfig::parse("isa = \"regs:V0/SP\"").unwrap_err(),
+        "line 1: The register table does not contain ZERO"
+    );
+    assert_eq!(
+        MachineConfig::parse("timeout = -1").unwrap_err(),
+        "line 1: Expected a number of seconds"
+    );
+    assert_eq!(
+        MachineConfig::parse("ram = 0x100000000").unwrap_err(),
+        "line 1: Size 4294967296 is too large"
+    );
+}
+
+#[test]
+fn devices() {
+    assert_eq!("uart@0xFFFF0000".parse(), Ok(DEFAULT_DEVICE));
+    assert_eq!(DEFAULT_DEVICE.to_string(), "uart@0xFFFF0000");
+    assert!("uart".parse::<Device>().is_err());
+    assert!("uart@nowhere".parse::<Device>().is_err());
+
+    let executable = assemble(
+        ".include <std/uart.vasm>
+.data
+.instructions
+        LI $T0, 0x100
+        LDA $T1, uart_tx
+        SW $T0, 0($T1)
+        LI $A0, 33
+        JL uart_putc
+        HALT",
+    );
+    let devices = [Device {
+        kind: DeviceKind::Uart,
+        address: 0x100,
+    }];
+    let output = SharedOutput::default();
+    let mut machine = Machine::new(&executable, 0x100, &devices, Box::new(output.clone())).unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(&output.0.borrow()[..], b"!");
+}
+
+#[test]
+fn arguments() {
+    let executable = assemble(
+        ".include <std/args.vasm>
+.include <std/uart.vasm>
+.data
+name:   .byte 72, 79, 77, 69, 0
+.instructions
+        JL args_init
+        LI $A0, 1
+        JL args_get
+        COPY $A0, $V0
+        JL uart_puts
+        LDA $A0, name
+        JL getenv
+        COPY $A0, $V0
+        JL uart_puts
+        LI $A0, 2
+        JL args_get
+        COPY $S0, $V0
+        HALT",
+    );
+    let output = SharedOutput::default();
+    let mut machine = Machine::new(
+        &executable,
+        256,
+        &[DEFAULT_DEVICE],
+        Box::new(output.clone()),
+    )
+    .unwrap();
+    machine
+        .pass_arguments(&["prog", "first "], &["HOMEDIR=/x", "HOME=/home"])
+        .unwrap();
+
+    let registers = machine.processor();
+    assert_eq!(registers.register(RegisterId::A0).u(), 2);
+    let argv = registers.register(RegisterId::A1).u();
+    assert_eq!(registers.register(RegisterId::A2).u(), argv + 12);
+    assert_eq!(registers.register(RegisterId::SP).u(), argv);
+    assert_eq!(argv % 4, 0);
+
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(&output.0.borrow()[..], b"first /home");
+    assert_eq!(machine.processor().register(RegisterId::S0).u(), 0);
+
+    let mut machine = Machine::new(&executable, 128, &[DEFAULT_DEVICE], Box::new(output)).unwrap();
+    assert!(machine.pass_arguments(&["x"; 40], &[]).is_err());
+}
+
+#[test]
+fn debugger() {
+    let assembly = vasm::assemble_program(
+        ".data
+value:  .word 7
+.instructions
+start:  LI $T0, 3
+loop:   SUBI $T0, $T0, 1
+        BNZ $T0, loop
+        LDA $T1, value
+        LW $V0, 0($T1)
+        HALT
+.entry start",
+        0,
+    )
+    .unwrap();
+    let machine = Machine::new(
+        &assembly.executable,
+        256,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut debugger = debugger::Debugger::new(machine, Some(assembly.debug_info));
+
+    let loop_address = debugger.eval("loop").unwrap();
+    assert_eq!(debugger.eval("loop + 4 - 0x2"), Ok(loop_address + 2));
+    assert_eq!(debugger.eval("$sp-$SP"), Ok(0));
+    assert_eq!(debugger.eval("pc"), Ok(debugger.eval("start").unwrap()));
+    assert!(debugger.eval("nowhere").is_err());
+    assert!(debugger.eval("$XY").is_err());
+    assert!(debugger.eval("loop+").is_err());
+    assert_eq!(
+        debugger.symbolize(loop_address + 4),
+        Some("loop+0x4".to_owned())
+    );
+
+    assert!(debugger.add_breakpoint(loop_address));
+    assert!(!debugger.add_breakpoint(loop_address));
+    for remaining in (1..=3).rev() {
+        assert_eq!(debugger.resume(&Limits::default()), None);
+        assert_eq!(debugger.eval("pc"), Ok(loop_address));
+        assert_eq!(debugger.eval("$T0"), Ok(remaining));
+    }
+    assert!(debugger.remove_breakpoint(loop_address));
+    assert_eq!(debugger.step(2), None);
+    assert_eq!(debugger.eval("$t0"), Ok(0));
+    assert_eq!(
+        debugger.resume(&Limits::default()),
+        Some(Stop::Exit(ExitCode::Halted))
+    );
+    assert_eq!(
+        debugger.machine().processor().register(RegisterId::V0).u(),
+        7
+    );
+    assert_eq!(
+        debugger.memory(debugger.eval("value").unwrap(), 1),
+        format!("{:08X}: 00000007\n", debugger.eval("value").unwrap())
+    );
+    assert!(debugger
+        .describe_instruction(loop_address)
+        .contains("<loop>  SUBI $T0, $T0, 1"));
+
+    assert_eq!(debugger.registers().lines().count(), 1 + REGISTER_COUNT / 4);
+    debugger
+        .machine_mut()
+        .processor_mut()
+        .set_profile("regs16".parse().unwrap());
+    let registers = debugger.registers();
+    assert_eq!(registers.lines().count(), 1 + 16 / 4);
+    assert!(registers.contains("RA   00000000\n"));
+    assert!(!registers.contains("T4"));
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let assembly = vasm::assemble_program(
+        ".data
+counter: .word 0
+.instructions
+        LI $T0, 10
+        LDA $T1, counter
+loop:   SW $T0, 0($T1)
+        SUBI $T0, $T0, 1
+        BNZ $T0, loop
+        HALT",
+        0,
+    )
+    .unwrap();
+    let machine = Machine::new(
+        &assembly.executable,
+        256,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut debugger = debugger::Debugger::new(machine, Some(assembly.debug_info));
+    let loop_address = debugger.eval("loop").unwrap();
+
+    assert!(debugger.set_condition(loop_address, None).is_err());
+    assert!(debugger.add_breakpoint(loop_address));
+    assert!(debugger
+        .set_condition(loop_address, Some("$T0 =="))
+        .is_err());
+    debugger
+        .set_condition(loop_address, Some("hits == 3"))
+        .unwrap();
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.eval("$T0"), Ok(8));
+    assert_eq!(debugger.breakpoint(loop_address).unwrap().hits, 3);
+
+    // the word the program stored in the previous iteration
+    assert_eq!(debugger.eval("[counter]"), Ok(9));
+    assert_eq!(debugger.eval("[$T1 - 4 + 4] + 1"), Ok(10));
+    assert!(debugger.eval("[counter").is_err());
+    debugger
+        .set_condition(loop_address, Some("[counter] <= 4"))
+        .unwrap();
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.eval("$T0"), Ok(3));
+    assert_eq!(debugger.eval("$T0 > -1 && t0 * 2 == 6"), Ok(1));
+    assert_eq!(debugger.eval("[t1 - 4 + 4] - 4"), Ok(0));
+    assert!(debugger
+        .set_condition(loop_address, Some("nowhere"))
+        .is_err());
+
+    // conditions which cannot be evaluated stop the program
+    debugger
+        .set_condition(loop_address, Some("[0xFFFFFFF0] == 0"))
+        .unwrap();
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.eval("$T0"), Ok(2));
+    debugger
+        .set_condition(loop_address, Some("$T0 == 7"))
+        .unwrap();
+    assert_eq!(
+        debugger.resume(&Limits::default()),
+        Some(Stop::Exit(ExitCode::Halted))
+    );
+    assert_eq!(debugger.breakpoint(loop_address).unwrap().hits, 10);
+}
+
+#[test]
+fn backtrace() {
+    let assembly = vasm::assemble_program(
+        ".data
+.instructions
+main:   CALL work
+        HALT
+work:   PUSH $RA
+        CALL leaf
+after:  CALL leaf
+        POP $RA
+        RET
+leaf:   RET",
+        0,
+    )
+    .unwrap();
+    let machine = Machine::new(
+        &assembly.executable,
+        256,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut debugger = debugger::Debugger::new(machine, Some(assembly.debug_info));
+    let address = |name: &str| debugger.eval(name).unwrap();
+    let (main, after, leaf) = (address("main"), address("after"), address("leaf"));
+
+    assert_eq!(debugger.backtrace(), vec![main]);
+    debugger.add_breakpoint(leaf);
+    debugger.add_breakpoint(after);
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.backtrace(), vec![leaf, after, main + 4]);
+    // $RA still points behind the first call of leaf, which returned already.
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.backtrace(), vec![after, main + 4]);
+    assert_eq!(
+        debugger.format_backtrace(),
+        format!(
+            "#0  0x{:08X} <after>  (:7)\n#1  0x{:08X} <main+0x4>  (:4)\n",
+            after,
+            main + 4
+        )
+    );
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(debugger.backtrace(), vec![leaf, after + 4, main + 4]);
+}
+
+#[test]
+fn step_over_and_out() {
+    let assembly = vasm::assemble_program(
+        ".data
+.instructions
+main:   LI $A0, 2
+        CALL down
+        HALT
+down:   PUSH $RA
+        BEZ $A0, done
+        SUBI $A0, $A0, 1
+        CALL down
+back:   NOP
+done:   POP $RA
+        RET",
+        0,
+    )
+    .unwrap();
+    let start = || {
+        Machine::new(
+            &assembly.executable,
+            256,
+            &[DEFAULT_DEVICE],
+            Box::new(SharedOutput::default()),
+        )
+        .unwrap()
+    };
+    let mut debugger = debugger::Debugger::new(start(), Some(assembly.debug_info.clone()));
+    let address = |name: &str| debugger.eval(name).unwrap();
+    let (main, back, done) = (address("main"), address("back"), address("done"));
+    let pc = |debugger: &debugger::Debugger| debugger.machine().processor().program_counter();
+    let limits = Limits::default();
+
+    assert_eq!(debugger.step_over(&limits), None);
+    assert_eq!(pc(&debugger), main + 4);
+    assert_eq!(debugger.step_over(&limits), None);
+    assert_eq!(pc(&debugger), main + 8);
+    assert_eq!(debugger.eval("$A0"), Ok(0));
+
+    debugger.set_machine(start());
+    assert_eq!(debugger.run_to(done, &limits), None);
+    assert_eq!(debugger.backtrace(), vec![done, back, back, main + 8]);
+    // every step out skips the recursive calls which return to the same address
+    assert_eq!(debugger.step_out(&limits), Ok(None));
+    assert_eq!(debugger.backtrace(), vec![back, back, main + 8]);
+    assert_eq!(debugger.step_out(&limits), Ok(None));
+    assert_eq!(debugger.backtrace(), vec![back, main + 8]);
+    assert_eq!(debugger.step_out(&limits), Ok(None));
+    assert_eq!(pc(&debugger), main + 8);
+
+    debugger.set_machine(start());
+    debugger.add_breakpoint(done);
+    debugger.step(1);
+    assert_eq!(debugger.step_over(&limits), None);
+    assert_eq!(pc(&debugger), done);
+    assert_eq!(debugger.step_out(&limits), Ok(None));
+    assert_eq!(pc(&debugger), back);
+    // the breakpoint is reached again before the caller of the next frame
+    assert_eq!(debugger.step_out(&limits), Ok(None));
+    assert_eq!(pc(&debugger), done);
+    debugger.remove_breakpoint(done);
+    assert_eq!(
+        debugger.run_to(main, &limits),
+        Some(Stop::Exit(ExitCode::Halted))
+    );
+}
+
+#[test]
+fn monitor_screen() {
+    let assembly = vasm::assemble_program(
+        ".include <std/uart.vasm>
+.data
+text:   .byte 104, 105, 10, 0
+.instructions
+start:  LDA $A0, text
+        JL uart_puts
+        HALT
+.entry start",
+        0,
+    )
+    .unwrap();
+    let console = monitor::ConsoleBuffer::default();
+    let machine = Machine::new(
+        &assembly.executable,
+        256,
+        &[DEFAULT_DEVICE],
+        Box::new(console.clone()),
+    )
+    .unwrap();
+    let mut debugger = debugger::Debugger::new(machine, Some(assembly.debug_info));
+    debugger.add_breakpoint(debugger.eval("uart_puts").unwrap());
+    assert_eq!(debugger.resume(&Limits::default()), None);
+    assert_eq!(
+        debugger.resume(&Limits::default()),
+        Some(Stop::Exit(ExitCode::Halted))
+    );
+    assert_eq!(console.last_lines(2), vec!["hi", ""]);
+
+    let view = monitor::View {
+        memory_address: 0,
+        status: "status",
+        footer: "keys",
+    };
+    let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+    terminal
+        .draw(|frame| monitor::render(frame, &debugger, &console, &view))
+        .unwrap();
+    let buffer = terminal.backend().buffer();
+    let lines: Vec<String> = (0..30)
+        .map(|y| (0..100).map(|x| buffer[(x, y)].symbol()).collect())
+        .collect();
+    assert!(lines[0].starts_with("status"));
+    assert!(lines[29].starts_with("keys"));
+    let pc_line = lines
+        .iter()
+        .position(|line| line.contains("=> 0x") && line.contains("HALT"))
+        .unwrap();
+    let x = lines[pc_line].find("=>").unwrap() as u16;
+    assert!(buffer[(x, pc_line as u16)]
+        .modifier
+        .contains(Modifier::REVERSED));
+    assert!(lines
+        .iter()
+        .any(|line| line.contains("* ") && line.contains("<uart_puts>")));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("00000000  68 69 0A 00") && line.contains("|hi..")));
+    assert!(lines.iter().any(|line| line.trim_end() == "hi"));
+}
+
+#[test]
+fn remote() {
+    let path = std::env::temp_dir().join("vcpu-run-remote-test.vasm");
+    std::fs::write(
+        &path,
+        ".include <std/uart.vasm>
+.data
+text:   .byte 111, 107, 0
+.instructions
+start:  LDA $A0, text
+        JL uart_puts
+        LI $V0, 5
+        HALT
+.entry start",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap().replace('\\', "\\\\");
+
+    let mut remote = remote::Remote::new(MachineConfig::default());
+    let mut notifications = Vec::new();
+    let mut call = |request: &str| {
+        remote
+            .handle(request, &mut |n| {
+                notifications.push(serde_json::to_string(&n).unwrap())
+            })
+            .map(|response| serde_json::to_string(&response).unwrap())
+    };
+
+    assert_eq!(
+        call(r#"{"jsonrpc":"2.0","method":"step","id":1}"#).unwrap(),
+        r#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"No program is loaded"},"id":1}"#
+    );
+    assert!(call(&format!(
+        r#"{{"jsonrpc":"2.0","method":"load","params":{{"path":"{}"}},"id":2}}"#,
+        path
+    ))
+    .unwrap()
+    .contains(r#""result":{"entry_point":0"#));
+    assert_eq!(
+        call(r#"{"jsonrpc":"2.0","method":"break","params":{"address":"uart_puts"},"id":3}"#)
+            .unwrap(),
+        r#"{"jsonrpc":"2.0","result":40,"id":3}"#
+    );
+    assert!(call(r#"{"jsonrpc":"2.0","method":"run","id":4}"#)
+        .unwrap()
+        .contains(r#""pc":40,"executed":3,"stopped":null,"reason":"breakpoint""#));
+    assert_eq!(
+        call(r#"{"jsonrpc":"2.0","method":"read_memory","params":{"address":"$A0","length":3},"id":5}"#)
+            .unwrap(),
+        r#"{"jsonrpc":"2.0","result":{"bytes":"6f6b00"},"id":5}"#
+    );
+    assert_eq!(
+        call(r#"{"jsonrpc":"2.0","method":"write_memory","params":{"address":1,"bytes":"21"}}"#),
+        None
+    );
+    assert!(
+        call(r#"{"jsonrpc":"2.0","method":"subscribe","params":{"events":["console","instruction"]},"id":6}"#)
+            .unwrap()
+            .contains(r#""result":["instruction","console"]"#)
+    );
+    assert!(
+        call(r#"{"jsonrpc":"2.0","method":"step","params":{"count":2},"id":7}"#)
+            .unwrap()
+            .contains(r#""reason":"instruction-limit""#)
+    );
+    assert!(
+        call(r#"{"jsonrpc":"2.0","method":"delete","params":{"address":40},"id":8}"#).is_some()
+    );
+    assert!(call(r#"{"jsonrpc":"2.0","method":"run","id":9}"#)
+        .unwrap()
+        .contains(r#""stopped":"Halted","status":5,"reason":"Halted""#));
+    assert!(call(
+        r#"{"jsonrpc":"2.0","method":"set_register","params":{"register":"t0","value":7},"id":10}"#
+    )
+    .unwrap()
+    .contains(r#""result":null"#));
+    assert!(call(r#"{"jsonrpc":"2.0","method":"registers","id":11}"#)
+        .unwrap()
+        .contains(r#""T0":7"#));
+    assert!(call(r#"{"jsonrpc":"2.0","method":"frobnicate","id":12}"#)
+        .unwrap()
+        .contains("-32601"));
+    assert!(call("{").unwrap().contains("-32700"));
+
+    assert!(notifications[0]
+        .starts_with(r#"{"jsonrpc":"2.0","method":"instruction","params":{"pc":40,"word":"#));
+    assert_eq!(
+        notifications.last().unwrap(),
+        r#"{"jsonrpc":"2.0","method":"console","params":{"text":"o!"}}"#
+    );
+}
+
+#[test]
+fn profile() {
+    let assembly = vasm::assemble_program(
+        ".data
+.instructions
+main:   LI $S0, 3
+again:  JL work
+        SUBI $S0, $S0, 1
+        BNZ $S0, again
+        HALT
+work:   COPY $S1, $RA
+        JL leaf
+        JL leaf
+        JR $S1
+leaf:   ADDI $T0, $T0, 1
+        JR $RA
+.entry main",
+        0,
+    )
+    .unwrap();
+    let executable = assembly.executable;
+    let debug_info = assembly.debug_info;
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut profiler = profiler::Profiler::new(executable.entry_point());
+    assert_eq!(
+        machine.run_observed(&Limits::default(), |address, word, processor| {
+            profiler.record(address, word, processor)
+        }),
+        Stop::Exit(ExitCode::Halted)
+    );
+    assert_eq!(profiler.total(), machine.executed());
+    let leaf = debug_info.symbol("leaf").unwrap().address;
+    assert_eq!(profiler.instruction_counts()[&leaf], 6);
+
+    let functions = profiler.functions();
+    let by_name = |name: &str| {
+        let address = debug_info.symbol(name).unwrap().address;
+        *functions.iter().find(|f| f.address == address).unwrap()
+    };
+    assert_eq!(by_name("main").self_count, 1 + 3 * 3 + 1);
+    assert_eq!(by_name("main").total_count, profiler.total());
+    assert_eq!(by_name("work").self_count, 3 * 4);
+    assert_eq!(by_name("work").total_count, 3 * 4 + 6 * 2);
+    assert_eq!(by_name("leaf").self_count, 6 * 2);
+    assert_eq!(functions[0].address, by_name("work").address);
+
+    let mut folded = Vec::new();
+    profiler
+        .write_folded(&mut folded, Some(&debug_info))
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "main 11\nmain;work 12\nmain;work;leaf 12\n"
+    );
+    let mut report = Vec::new();
+    profiler
+        .write_report(&mut report, Some(&debug_info), 5)
+        .unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("Profile of 35 instructions"));
+    assert!(report.contains("leaf\n"));
+}
+
+#[test]
+fn statistics() {
+    let assembly = vasm::assemble_program(
+        ".data
+.instructions
+main:   LI $T0, 3
+loop:   BEZ $ZERO, skip
+        NOP
+skip:   SUBI $T0, $T0, 1
+        ADD $T1, $T1, $T0
+        BNZ $T0, loop
+        HALT",
+        0,
+    )
+    .unwrap();
+    let mut machine = Machine::new(
+        &assembly.executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut statistics = statistics::Statistics::new();
+    let mut bus = events::EventBus::new(machine.processor());
+    bus.subscribe(&mut statistics);
+    machine.run_observed(&Limits::default(), |address, word, processor| {
+        bus.record(address, word, processor)
+    });
+    assert_eq!(statistics.total(), machine.executed());
+    assert_eq!(machine.executed(), 14);
+    assert_eq!(statistics.mnemonics()["ADD"], 3);
+    assert_eq!(statistics.mnemonics()["BEZ"], 3);
+    assert!(!statistics.mnemonics().contains_key("NOP"));
+    let skip = assembly.debug_info.symbol("skip").unwrap().address;
+    assert_eq!(statistics.addresses()[&skip], 3);
+    assert_eq!(statistics.addresses().get(&(skip - 4)), None);
+    assert_eq!(
+        statistics.branch_totals(),
+        statistics::BranchStatistics {
+            executed: 6,
+            taken: 5,
+            // the forward BEZ every time and the BNZ which leaves the loop
+            mispredicted: 4,
+        }
+    );
+
+    let report = statistics.report(Some(&assembly.debug_info));
+    assert_eq!(report.instructions, 14);
+    assert_eq!(report.branches.taken_rate, 5.0 / 6.0);
+    assert_eq!(report.branches.sites.len(), 2);
+    assert_eq!(report.addresses[0].line.as_deref(), Some(":3"));
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(
+        json["mnemonics"][&report.mnemonics[0].0],
+        report.mnemonics[0].1
+    );
+    assert_eq!(
+        json["branches"]["sites"][0]["taken"],
+        report.branches.sites[0].statistics.taken
+    );
+
+    let mut csv = Vec::new();
+    statistics
+        .write(&mut csv, statistics::StatisticsFormat::Csv, None)
+        .unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with("kind,key,count,taken\ntotal,instructions,14,\n"));
+    assert!(csv.contains("\nmnemonic,SUBI,3,\n"));
+    assert!(csv.contains(&format!("\nbranch,0x{:08X},3,2\n", skip + 8)));
+}
+
+#[test]
+fn branch_predictors() {
+    use predictor::{PredictorKind, PredictorSimulation};
+
+    assert_eq!("gshare:12".parse(), Ok(PredictorKind::Gshare(12)));
+    assert_eq!("bimodal".parse(), Ok(PredictorKind::Bimodal(10)));
+    assert_eq!(
+        "always-taken".parse::<PredictorKind>().unwrap().to_string(),
+        "always-taken"
+    );
+    assert!("always-taken:4".parse::<PredictorKind>().is_err());
+    assert!("gshare:0".parse::<PredictorKind>().is_err());
+    assert!("perceptron".parse::<PredictorKind>().is_err());
+
+    let executable = assemble(
+        ".data
+.instructions
+        LI $T0, 40
+loop:   ANDI $T2, $T0, 1
+        BEZ $T2, skip
+        ADDI $T1, $T1, 1
+skip:   SUBI $T0, $T0, 1
+        BNZ $T0, loop
+        HALT",
+    );
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut simulations: Vec<_> = ["always-taken", "bimodal:4", "gshare:4"]
+        .iter()
+        .map(|kind| PredictorSimulation::new(kind.parse().unwrap()))
+        .collect();
+    let mut bus = events::EventBus::new(machine.processor());
+    for simulation in simulations.iter_mut() {
+        bus.subscribe(simulation);
+    }
+    machine.run_observed(&Limits::default(), |address, word, processor| {
+        bus.record(address, word, processor)
+    });
+    assert!(simulations.iter().all(|s| s.branches() == 80));
+    // BEZ is taken every other time and BNZ every time but the last
+    assert_eq!(simulations[0].mispredicted(), 20 + 1);
+    // a 2-bit counter mispredicts an alternating branch every time
+    assert_eq!(simulations[1].mispredicted(), 40 + 2);
+    assert!(simulations[2].mispredicted() < simulations[0].mispredicted());
+    assert_eq!(simulations[0].accuracy(), 0.7375);
+    assert_eq!(simulations[0].penalty_cycles(3), 63);
+
+    let mut report = Vec::new();
+    predictor::write_report(&mut report, &simulations, machine.executed(), 2).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.contains("\n  bimodal:4        80         42             47.50%  "));
+}
+
+#[test]
+fn taint() {
+    use taint::{TaintSource, TaintTracker};
+    use vcpu::RegisterId;
+
+    assert_eq!("$a0".parse(), Ok(TaintSource::Register(RegisterId::A0)));
+    assert_eq!("0x100:8".parse(), Ok(TaintSource::Memory(0x100..0x108)));
+    assert_eq!(
+        "uart@0xFFFF0000".parse(),
+        Ok(TaintSource::Device(0xFFFF_0000..0xFFFF_0004))
+    );
+    assert!("$XY".parse::<TaintSource>().is_err());
+
+    let assembly = vasm::assemble_program(
+        ".data
+input:  .word 7
+copy:   .word 0
+.instructions
+main:   LDA $T0, input
+        LW $T1, 0($T0)
+        ADDI $T2, $T1, 1
+        SB $T2, 4($T0)
+        XOR $T2, $T2, $T2
+        LW $T3, 4($T0)
+        BNZ $T3, done
+        ADD $T4, $A0, $ZERO
+done:   HALT",
+        0,
+    )
+    .unwrap();
+    let mut machine = Machine::new(
+        &assembly.executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let input = assembly.debug_info.symbol("input").unwrap().address;
+    let mut tracker = TaintTracker::new(&machine, &[TaintSource::Memory(input..input + 4)]);
+    machine.run_inspected(&Limits::default(), |address, word, machine| {
+        tracker.record(address, word, machine)
+    });
+
+    let main = assembly.debug_info.symbol("main").unwrap().address;
+    // LDA takes two instructions, the ADD is skipped
+    let consumers: Vec<u32> = tracker
+        .uses()
+        .keys()
+        .map(|address| address - main)
+        .collect();
+    assert_eq!(consumers, [8, 12, 16, 20, 24, 28]);
+    assert_eq!(tracker.uses()[&(main + 8)].count, 1);
+    assert!(tracker.is_register_tainted(RegisterId::T1));
+    assert!(!tracker.is_register_tainted(RegisterId::T2));
+    assert!(tracker.is_register_tainted(RegisterId::T3));
+    // only the byte written by SB is tainted in the copy
+    assert!(tracker.is_byte_tainted(input + 4));
+    assert!(!tracker.is_byte_tainted(input + 5));
+    assert_eq!(tracker.tainted_bytes(), 5);
+
+    let mut report = Vec::new();
+    tracker
+        .write_report(&mut report, Some(&assembly.debug_info))
+        .unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("Tainted data was consumed by 6 instructions\n"));
+    assert!(report.contains(&format!(
+        "\n  1          0x{:08X}  BNZ $T3, 8               <main+0x1C>\n",
+        main + 28
+    )));
+    assert!(report.ends_with("5 bytes of memory, registers $T1, $T3\n"));
+}
+
+#[test]
+fn pipeline() {
+    use vcpu::RegisterId;
+
+    assert_eq!(pipeline::parse_window("100:20"), Ok(100..120));
+    assert_eq!(
+        pipeline::parse_window("5"),
+        Ok(5..5 + pipeline::DEFAULT_WINDOW)
+    );
+    assert!(pipeline::parse_window("5:x").is_err());
+
+    let executable = assemble(
+        ".data
+value:  .word 5
+.instructions
+        LI $T1, value
+        LW $T2, 0($T1)
+        ADD $T3, $T2, $T1
+        BNZ $T3, done
+        NOP
+done:   HALT",
+    );
+    let run = |forwarding| {
+        let mut machine = Machine::new(
+            &executable,
+            64,
+            &[DEFAULT_DEVICE],
+            Box::new(SharedOutput::default()),
+        )
+        .unwrap();
+        let mut pipeline = pipeline::Pipeline::new(forwarding, 1..4);
+        let mut bus = events::EventBus::new(machine.processor());
+        bus.subscribe(&mut pipeline);
+        machine.run_observed(&Limits::default(), |address, word, processor| {
+            bus.record(address, word, processor)
+        });
+        pipeline
+    };
+
+    let forwarding = run(true);
+    assert_eq!(forwarding.instructions(), 5);
+    assert_eq!(forwarding.entries().len(), 3);
+    let add = &forwarding.entries()[1];
+    // the loaded word is available one cycle after the ADD could have used it
+    assert_eq!((add.fetch, add.decode, add.execute), (2, 3, 5));
+    assert_eq!(add.stalled_on, vec![RegisterId::T2]);
+    assert_eq!(add.forwarded, vec![RegisterId::T2]);
+    assert_eq!(forwarding.entries()[2].flushed, 2);
+    assert_eq!(
+        (
+            forwarding.cycles(),
+            forwarding.stalls(),
+            forwarding.flushed()
+        ),
+        // HALT is fetched after the flushed instructions
+        (5 + 4 + 1 + 2, 1, 2)
+    );
+
+    let mut diagram = Vec::new();
+    forwarding.write_diagram(&mut diagram).unwrap();
+    let diagram = String::from_utf8(diagram).unwrap();
+    let lines: Vec<&str> = diagram.lines().collect();
+    assert_eq!(
+        lines[0],
+        "Pipeline with forwarding: 5 instructions in 12 cycles, CPI 2.400"
+    );
+    assert_eq!(
+        lines[3],
+        "Cycle                               1  2  3  4  5  6  7  8"
+    );
+    assert_eq!(
+        lines[5],
+        "0x00000008  ADD $T3, $T2, $T1          IF ID -- EX ME WB  stall 1 ($T2), forward $T2"
+    );
+
+    let stalling = run(false);
+    let add = &stalling.entries()[1];
+    assert_eq!(add.stalls(), 2);
+    assert!(add.forwarded.is_empty());
+    // LW waits for LI, ADD for LW and BNZ for ADD
+    assert_eq!(stalling.stalls(), 6);
+}
+
+#[test]
+fn events() {
+    use events::{Event, EventBus, EventListener};
+    use vcpu::Opcode;
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl EventListener for Recorder {
+        fn notify(&mut self, event: &Event) {
+            self.0.push(*event);
+        }
+    }
+
+    let executable = assemble(
+        ".data
+.instructions
+        LI $T0, 0x20
+        LB $T0, 3($T0)
+        SW $T0, -4($SP)
+        BNZ $T0, done
+        JL done
+done:   HALT",
+    );
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let word = |address| machine.instruction_at(address).unwrap();
+    let words: Vec<_> = (0..6).map(|i| word(i * 4)).collect();
+    let (mut first, mut second) = (Recorder::default(), Recorder::default());
+    let mut bus = EventBus::new(machine.processor());
+    bus.subscribe(&mut first);
+    bus.subscribe(&mut second);
+    machine.run_observed(&Limits::default(), |address, word, processor| {
+        bus.record(address, word, processor)
+    });
+
+    let fetch = |i: usize| Event::Fetch {
+        address: i as u32 * 4,
+        word: words[i],
+    };
+    let retire = |i: usize| Event::Retire {
+        address: i as u32 * 4,
+        word: words[i],
+    };
+    // the byte at 0x23 is 0, so the branch is not taken
+    assert_eq!(
+        first.0,
+        vec![
+            fetch(0),
+            retire(0),
+            fetch(1),
+            Event::Load {
+                pc: 4,
+                address: 0x23,
+                size: 1
+            },
+            retire(1),
+            fetch(2),
+            Event::Store {
+                pc: 8,
+                address: 60,
+                size: 4
+            },
+            retire(2),
+            fetch(3),
+            Event::Branch {
+                address: 12,
+                opcode: Opcode::BNZ,
+                target: 20,
+                taken: false
+            },
+            retire(3),
+            fetch(4),
+            Event::Branch {
+                address: 16,
+                opcode: Opcode::JL,
+                target: 20,
+                taken: true
+            },
+            retire(4),
+            fetch(5),
+            retire(5),
+        ]
+    );
+    assert_eq!(first.0, second.0);
+}
+
+#[test]
+fn trace() {
+    use trace::{run_traced, TraceFilter, TraceFormat, Tracer};
+
+    assert_eq!("csv".parse(), Ok(TraceFormat::Csv));
+    assert!("json".parse::<TraceFormat>().is_err());
+
+    let executable = assemble(
+        ".include <std/uart.vasm>
+.data
+.instructions
+        LDA $T0, uart_tx
+        LW $T0, 0($T0)
+        LI $T1, 0x68
+        SB $T1, 0($T0)
+        HALT",
+    );
+    let trace = |format| {
+        let mut machine = Machine::new(
+            &executable,
+            64,
+            &[DEFAULT_DEVICE],
+            Box::new(SharedOutput::default()),
+        )
+        .unwrap();
+        let mut tracer = Tracer::new(Vec::new(), format).unwrap();
+        let (stop, result) =
+            run_traced(&mut machine, &Limits::default(), &mut tracer, |_, _, _| {});
+        assert_eq!(stop, Stop::Exit(ExitCode::Halted));
+        result.unwrap();
+        (
+            machine.executed(),
+            String::from_utf8(tracer.finish().unwrap()).unwrap(),
+        )
+    };
+
+    let (executed, chrome) = trace(TraceFormat::Chrome);
+    let events: Vec<serde_json::Value> = serde_json::from_str(&chrome).unwrap();
+    let category = |name: &str| {
+        events
+            .iter()
+            .filter(|event| event["cat"] == name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(category("instruction").len() as u64, executed);
+    let writes = category("mmio");
+    assert_eq!(writes.len(), 1);
+    let args = &writes[0]["args"];
+    assert_eq!(args["address"], "0xFFFF0000");
+    assert_eq!(args["value"], "0x00000068");
+    assert_eq!(args["size"], 1);
+
+    let (executed, csv) = trace(TraceFormat::Csv);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "cycle,pc,event,word,text,device,offset,value");
+    assert_eq!(
+        lines
+            .iter()
+            .filter(|line| line.contains(",instruction,"))
+            .count() as u64,
+        executed
+    );
+    assert!(lines
+        .iter()
+        .any(|line| line.ends_with(",mmio,,,uart@0xFFFF0000,0,0x00000068")));
+
+    assert!(TraceFilter::new("nowhere == 0", None).is_err());
+    assert!(TraceFilter::new("$T1 ==", None).is_err());
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    let mut tracer = Tracer::new(Vec::new(), TraceFormat::Csv).unwrap();
+    tracer.set_filter(Some(
+        TraceFilter::new("$T1 == 0x68 && pc > 0", None).unwrap(),
+    ));
+    let (_, result) = run_traced(&mut machine, &Limits::default(), &mut tracer, |_, _, _| {});
+    result.unwrap();
+    let csv = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    let lines: Vec<&str> = csv.lines().skip(1).collect();
+    let last = machine.executed() - 1;
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].contains(",instruction,") && lines[0].contains("LI $T1, 104"));
+    assert!(lines[1].starts_with(&format!("{},", last - 1)));
+    assert!(lines[2].contains(",mmio,"));
+    assert!(lines[3].starts_with(&format!("{},", last)) && lines[3].contains(",HALT,"));
+}
+
+#[test]
+fn expectations() {
+    use expect::{Condition, Expectation};
+
+    assert_eq!(
+        expect::parse("    LI $T2, 106 # EXPECT: T2 == 100 + 6\n# EXPECT: [data+4] == 1 0xFF\n"),
+        Ok(vec![
+            Expectation {
+                line: 1,
+                condition: Condition::Register(RegisterId::T2, "100 + 6".to_owned()),
+            },
+            Expectation {
+                line: 2,
+                condition: Condition::Memory("data+4".to_owned(), vec![1, 0xFF]),
+            },
+        ])
+    );
+    assert_eq!(
+        expect::parse("# EXPECT: output == \"a\\n\"\n# EXPECT: status == 129")
+            .unwrap()
+            .into_iter()
+            .map(|expectation| expectation.condition)
+            .collect::<Vec<_>>(),
+        vec![Condition::Output(b"a\n".to_vec()), Condition::Status(129)]
+    );
+    assert!(expect::parse("# EXPECT: X9 == 1").is_err());
+    assert!(expect::parse("# EXPECT: [data] == 256").is_err());
+    assert!(expect::parse("# EXPECT: status").is_err());
+
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/asm-tests");
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            expect::run_file(path, &MachineConfig::default()),
+            Ok(Some(Vec::new())),
+            "{}",
+            path
+        );
+    }
+
+    let path = std::env::temp_dir().join(format!("vasm-test-{}.vasm", std::process::id()));
+    std::fs::write(
+        &path,
+        "# EXPECT: T0 == 3
+# EXPECT: [$SP-4] == \"x\"
+.data
+.instructions
+loop:   ADDI $T0, $T0, 1
+        JMP loop",
+    )
+    .unwrap();
+    let config = MachineConfig {
+        limits: Limits {
+            max_instructions: Some(4),
+            timeout: None,
+        },
+        ..MachineConfig::default()
+    };
+    let failures = expect::run_file(path.to_str().unwrap(), &config)
+        .unwrap()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        failures,
+        [
+            "Program stopped at 0x00000000: executed 4 instructions without halting",
+            "line 1: $T0 is 0x00000002 (2) instead of 0x00000003 (3)",
+            "line 2: [$SP-4] is \"\\x00\" instead of \"x\"",
+        ]
+    );
+}
+
+#[test]
+fn load_warnings() {
+    let path = std::env::temp_dir().join(format!("vcpu-load-{}.vasm", std::process::id()));
+    std::fs::write(&path, ".data\n.instructions\nunused: HALT\n").unwrap();
+    let loaded = load(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.executable.instructions().len(), 4);
+    assert_eq!(loaded.warnings.len(), 1);
+    assert_eq!(loaded.warnings[0].kind, vasm::WarningKind::UnusedLabel);
+}
+
+#[test]
+fn golden() {
+    let executable = assemble(
+        ".data
+text:   .byte 0x48, 0x69
+.instructions
+        LI $T0, -1
+        LI $V0, 3
+        HALT",
+    );
+    let mut machine = Machine::new(
+        &executable,
+        64,
+        &[DEFAULT_DEVICE],
+        Box::new(SharedOutput::default()),
+    )
+    .unwrap();
+    machine.run(&Limits::default());
+    let dump = golden::dump(&machine, &[0..4, 60..66]);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines[..3], ["state Halted", "pc 0x00000008", "executed 3"]);
+    assert!(lines.contains(&"$T0 0xFFFFFFFF"));
+    assert!(lines.contains(&"$V0 0x00000003"));
+    assert_eq!(
+        lines[lines.len() - 4..],
+        [
+            "memory 0x00000000..0x00000004",
+            "0x00000000 48 69 00 00",
+            "memory 0x0000003C..0x00000042",
+            "0x0000003C 00 00 00 00 ?? ??",
+        ]
+    );
+
+    let path = std::env::temp_dir().join(format!("vcpu-golden-{}.txt", std::process::id()));
+    std::fs::write(&path, &dump).unwrap();
+    assert_eq!(golden::compare(&dump, &path), Ok(()));
+    let changed = dump.replace("$V0 0x00000003", "$V0 0x00000004");
+    let error = golden::compare(&changed, &path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(error.contains(" - $V0 0x00000003\n"));
+    assert!(error.contains(" + $V0 0x00000004\n"));
+    assert!(golden::compare(&dump, &path).is_err());
+}
+
+#[test]
+fn screenshot() {
+    // draws one red pixel at (1, 0) of a 2x1 framebuffer
+    let executable = assemble(
+        ".data
+.instructions
+        LWI $T0, 0x100000
+        LWI $T1, 0xFF0000FF
+        SW $T1, 4($T0)
+        HALT",
+    );
+    let config = MachineConfig {
+        framebuffer: Some("0x100000:2x1".parse().unwrap()),
+        ..MachineConfig::default()
+    };
+    let session = debugger::Session::new(executable.clone(), config, Vec::new(), Vec::new());
+    let mut machine = session.start(Box::new(std::io::sink())).unwrap();
+    machine.run(&Limits::default());
+    let framebuffer = session.config.framebuffer.unwrap();
+    assert_eq!(
+        framebuffer.capture(&machine),
+        Ok(vec![0, 0, 0, 0, 0xFF, 0, 0, 0xFF])
+    );
+
+    let image = png::encode(2, 1, &framebuffer.capture(&machine).unwrap()).unwrap();
+    assert!(image.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x01"));
+    assert!(image.ends_with(b"\0\0\0\0IEND\xAE\x42\x60\x82"));
+    let mut reader = ::png::Decoder::new(&image[..]).read_info().unwrap();
+    let mut decoded = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut decoded).unwrap();
+    assert_eq!(decoded, framebuffer.capture(&machine).unwrap());
+    assert!(png::encode(2, 2, &[0; 8]).is_err());
+
+    let path = std::env::temp_dir().join(format!("vcpu-screenshot-{}.png", std::process::id()));
+    let path_text = path.to_str().unwrap();
+    session.screenshot(&machine, path_text).unwrap();
+    assert_eq!(golden::compare_image(&image, &path), Ok(()));
+    assert!(golden::compare_image(&png::encode(2, 1, &[0; 8]).unwrap(), &path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    let plain =
+        debugger::Session::new(executable, MachineConfig::default(), Vec::new(), Vec::new());
+    assert_eq!(
+        plain.screenshot(&machine, path_text),
+        Err("The machine has no framebuffer".to_owned())
+    );
+}
+
+#[test]
+fn script() {
+    let assembly = vasm::assemble_program(
+        ".include <std/uart.vasm>
+.data
+.instructions
+start:  LI $T0, 3
+loop:   SUBI $T0, $T0, 1
+        BNZ $T0, loop
+        LI $A0, 33
+        JL uart_putc
+        ADDI $V0, $A1, 0
+        HALT
+.entry start",
+        0,
+    )
+    .unwrap();
+    let debug_info = assembly.debug_info;
+    let session = debugger::Session::new(
+        assembly.executable,
+        MachineConfig::default(),
+        Vec::new(),
+        Vec::new(),
+    );
+    let session = Rc::new(session);
+    let console = SharedOutput::default();
+    let new_console = {
+        let console = console.clone();
+        move || -> Box<dyn Write> { Box::new(console.clone()) }
+    };
+    let machine = session.start(new_console()).unwrap();
+    let debugger = Rc::new(RefCell::new(debugger::Debugger::new(
+        machine,
+        Some(debug_info),
+    )));
+    let run = |source: &str| {
+        let output = SharedOutput::default();
+        let script = script::Script::parse(source)?;
+        let flow = script::ScriptRunner::new(
+            debugger.clone(),
+            session.clone(),
+            Box::new(output.clone()),
+            Box::new(new_console.clone()),
+        )
+        .run(&script)?;
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        Ok::<_, String>((flow, output))
+    };
+
+    let (flow, output) = run(r#"// counts the iterations
+on_break("loop", || print(`T0 is ${signed(reg("T0"))}`));
+on_write(0xFFFF0000, |write| print(`wrote ${hex(write.value)} (${write.size} bytes)`));
+on_exit(|| print("done"));
+set_reg("$A1", 42);
+run();
+assert_eq(reg("V0"), 42);
+assert_ne(reg("T0"), 1);
+"#)
+    .unwrap();
+    assert_eq!(flow, script::Flow::Continue);
+    assert_eq!(
+        output,
+        "T0 is 3\nT0 is 2\nT0 is 1\nwrote 0x00000021 (1 bytes)\nProgram halted with status 42\ndone\n"
+    );
+    assert_eq!(console.0.borrow().as_slice(), b"!");
+
+    let (flow, output) = run(r#"restart();
+on_break("loop", || stop());
+run();
+print(hex(reg("T0")));
+step(2);
+poke(0x100, 0x1234, 2);
+print(hex(peek(0x100)));
+print(memory("0x100", 1));
+quit();
+print(1);"#)
+    .unwrap();
+    assert_eq!(flow, script::Flow::Quit);
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("Stopped at ") && lines[0].contains("<loop>"));
+    assert_eq!(lines[1], "0x00000003");
+    assert!(lines[2].contains("<loop>"));
+    assert_eq!(lines[3], "0x00001234");
+    assert_eq!(lines[4], "00000100: 00001234");
+    assert_eq!(lines.len(), 5);
+
+    assert_eq!(
+        run("restart();\nset_reg(\"V0\", -1);\nassert_eq(reg(\"V0\"), 1);"),
+        Err("line 3: Assertion failed: 4294967295 != 1".to_owned())
+    );
+    assert_eq!(
+        run("on_break(\"loop\", || {\n  run();\n});\nrestart();\nrun();"),
+        Err("line 2: Handlers cannot run the program".to_owned())
+    );
+    assert_eq!(
+        run("poke(\"nowhere\", 0)"),
+        Err("line 1: Unknown symbol nowhere".to_owned())
+    );
+    assert!(script::Script::parse("on_break(\"loop\", || {")
+        .err()
+        .unwrap()
+        .starts_with("line 1: "));
+    assert_eq!(
+        run("stop()"),
+        Err("line 1: stop can only be used in handlers".to_owned())
+    );
+}
+
+#[test]
+fn semihosting() {
+    let root = std::env::temp_dir().join(format!("vcpu-semihosting-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("in.txt"), "hello\n").unwrap();
+
+    // echoes in.txt to stdout, seeks 2 bytes before its end, fails to open ../in.txt and prints to stderr
+    let executable = assemble(
+        ".include <std/semihosting.vasm>
+.data
+path:   .byte 105, 110, 46, 116, 120, 116, 0
+bad:    .byte 46, 46, 47, 105, 110, 46, 116, 120, 116, 0
+buffer: .block 16
+.instructions
+start:  LDA A0, path
+        LI A1, 0
+        JL sh_open
+        COPY S0, V0
+        COPY A0, S0
+        LDA A1, buffer
+        LI A2, 16
+        JL sh_read
+        COPY A2, V0
+        LI A0, 1
+        LDA A1, buffer
+        JL sh_write
+        COPY A0, S0
+        LI A1, -2
+        LI A2, 2
+        JL sh_seek
+        COPY S1, V0
+        COPY A0, S0
+        JL sh_close
+        LDA A0, bad
+        LI A1, 0
+        JL sh_open
+        COPY S2, V0
+        LI A0, 2
+        LDA A1, buffer
+        JL sh_puts
+        HALT
+.entry start",
+    );
+    let device: Device = "semihosting@0xFFFF1000".parse().unwrap();
+    let output = SharedOutput::default();
+    let errors = SharedOutput::default();
+    let mut machine = Machine::new(&executable, 1024, &[device], Box::new(output.clone())).unwrap();
+    machine.set_semihosting_root(Some(root.clone()));
+    machine.set_semihosting_errors(Box::new(errors.clone()));
+
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    let processor = machine.processor();
+    assert_eq!(processor.register(RegisterId::S0).i(), 3);
+    assert_eq!(processor.register(RegisterId::S1).i(), 4);
+    assert_eq!(processor.register(RegisterId::S2).i(), -1);
+    assert_eq!(&output.0.borrow()[..], b"hello\n");
+    assert_eq!(&errors.0.borrow()[..], b"hello\n");
+
+    // without a root, files cannot be opened at all
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    machine.set_semihosting_errors(Box::new(std::io::sink()));
+    machine.run(&Limits::default());
+    assert_eq!(machine.processor().register(RegisterId::S0).i(), -1);
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn semihosting_heap() {
+    // the heap starts at 0x101, which is aligned to 0x104, and ends at 0x140
+    let executable = assemble(
+        ".include <std/semihosting.vasm>
+.data
+.instructions
+start:  LI A0, 0x101
+        LI A1, 0x3F
+        JL sh_heap_init
+        LI A0, 5
+        JL sh_malloc
+        COPY S0, V0
+        LI A0, 8
+        JL sh_malloc
+        COPY S1, V0
+        COPY A0, S0
+        JL sh_free
+        LI A0, 4
+        JL sh_malloc
+        COPY S2, V0
+        LI T0, 0x1234
+        SW T0, 0(S2)
+        COPY A0, S2
+        LI A1, 12
+        JL sh_realloc
+        COPY S3, V0
+        LI A0, 64
+        JL sh_malloc
+        COPY S4, V0
+        LI A0, 0x200
+        JL sh_free
+        COPY S5, V0
+        HALT
+.entry start",
+    );
+    let device: Device = "semihosting@0xFFFF1000".parse().unwrap();
+    let mut machine =
+        Machine::new(&executable, 1024, &[device], Box::new(std::io::sink())).unwrap();
+    assert_eq!(
+        machine.run(&Limits::default()),
+        Stop::Exit(ExitCode::Halted)
+    );
+    let register = |id| machine.processor().register(id).u();
+    assert_eq!(register(RegisterId::S0), 0x104);
+    assert_eq!(register(RegisterId::S1), 0x10C);
+    // the freed block is reused, and moved with its contents when it cannot grow
+    assert_eq!(register(RegisterId::S2), 0x104);
+    assert_eq!(register(RegisterId::S3), 0x114);
+    assert_eq!(machine.memory().read_word(0x114), Ok(0x1234));
+    assert_eq!(register(RegisterId::S4), 0);
+    assert_eq!(register(RegisterId::S5) as i32, -1);
+}
@@ -0,0 +1,301 @@
+//! Writes a trace of every executed instruction and every write to a device.
+//!
+//! The Chrome format is the JSON array of the `trace_event` format, which `chrome://tracing`, Perfetto and
+//! Speedscope can show on a timeline. Every instruction takes one cycle, which is written as one microsecond.
+//! Instructions are complete events on the first thread and device writes are instant events on the second.
+//! The CSV format has one row per event with the columns `cycle,pc,event,word,text,device,offset,value`.
+//!
+//! A [`TraceFilter`](struct.TraceFilter.html) restricts the trace to the instructions for which an expression
+//! like in the [`debugger`](../debugger/index.html) is not zero, e.g. `pc >= work && pc < work_end` or
+//! `[counter] > 100`. It is evaluated after the instruction was executed, with `pc` being its address. Device
+//! writes are traced together with their instruction, and skipped instructions still take their cycle.
+
+use crate::debugger::MachineContext;
+use crate::machine::{DeviceWrite, Limits, Machine, Stop};
+use serde::{Serialize, Serializer};
+use std::io::prelude::*;
+use std::str::FromStr;
+use vasm::{EvalContext, ToolExpression};
+use vcpu::Word;
+use vex::debug::DebugInfo;
+
+const INSTRUCTION_THREAD: u32 = 1;
+const DEVICE_THREAD: u32 = 2;
+
+/// File format of a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Chrome,
+    Csv,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chrome" => Ok(TraceFormat::Chrome),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err(format!("Unknown trace format \"{}\"", s)),
+        }
+    }
+}
+
+/// Selects the instructions which are traced.
+pub struct TraceFilter {
+    expression: ToolExpression,
+    debug_info: Option<DebugInfo>,
+}
+
+/// The symbols which are known before the program runs.
+struct Symbols<'a>(Option<&'a DebugInfo>);
+
+impl EvalContext for Symbols<'_> {
+    fn symbol(&self, name: &str) -> Option<u32> {
+        match name {
+            "pc" => Some(0),
+            _ => self.0.and_then(|info| info.symbol(name)).map(|s| s.address),
+        }
+    }
+}
+
+impl TraceFilter {
+    /// Parses the expression of a filter, whose symbols must be defined in the debug information.
+    pub fn new(expression: &str, debug_info: Option<DebugInfo>) -> Result<TraceFilter, String> {
+        let expression: ToolExpression = expression.parse()?;
+        expression.check_symbols(&Symbols(debug_info.as_ref()))?;
+        Ok(TraceFilter {
+            expression,
+            debug_info,
+        })
+    }
+
+    /// Returns whether the instruction at `pc`, which `machine` just executed, is traced. Expressions which
+    /// cannot be evaluated, e.g. because of an unreadable address, are false.
+    pub fn matches(&self, pc: u32, machine: &Machine) -> bool {
+        let mut context = MachineContext::new(machine, self.debug_info.as_ref());
+        context.pc = pc;
+        self.expression
+            .evaluate(&context)
+            .is_ok_and(|value| value != 0)
+    }
+}
+
+/// Writes trace events while the program runs.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: Option<TraceFilter>,
+    cycle: u64,
+    /// Whether an event was written, which the next event of a Chrome trace must be separated from.
+    written: bool,
+}
+
+/// An event of the Chrome trace format.
+#[derive(Serialize)]
+struct TraceEvent<A> {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+    ph: &'static str,
+    /// Scope of an instant event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u32,
+    tid: u32,
+    args: A,
+}
+
+#[derive(Serialize)]
+struct ThreadName {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+struct InstructionArgs {
+    #[serde(serialize_with = "hex")]
+    pc: u32,
+    #[serde(serialize_with = "hex")]
+    word: u32,
+}
+
+#[derive(Serialize)]
+struct WriteArgs {
+    #[serde(serialize_with = "hex")]
+    pc: u32,
+    #[serde(serialize_with = "hex")]
+    address: u32,
+    size: u32,
+    #[serde(serialize_with = "hex")]
+    value: u32,
+}
+
+fn hex<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{:08X}", value))
+}
+
+/// Quotes a CSV field if it contains a separator.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    /// Writes the start of a trace in the given `format`.
+    pub fn new(mut writer: W, format: TraceFormat) -> std::io::Result<Tracer<W>> {
+        match format {
+            TraceFormat::Chrome => writeln!(writer, "[")?,
+            TraceFormat::Csv => writeln!(writer, "cycle,pc,event,word,text,device,offset,value")?,
+        }
+        let mut tracer = Tracer {
+            writer,
+            format,
+            filter: None,
+            cycle: 0,
+            written: false,
+        };
+        if format == TraceFormat::Chrome {
+            for (thread, name) in [
+                (INSTRUCTION_THREAD, "instructions"),
+                (DEVICE_THREAD, "devices"),
+            ] {
+                tracer.write_event(TraceEvent {
+                    name: "thread_name".to_owned(),
+                    cat: None,
+                    ph: "M",
+                    s: None,
+                    ts: None,
+                    dur: None,
+                    pid: 1,
+                    tid: thread,
+                    args: ThreadName { name },
+                })?;
+            }
+        }
+        Ok(tracer)
+    }
+
+    /// Only traces the instructions selected by `filter` from now on.
+    pub fn set_filter(&mut self, filter: Option<TraceFilter>) {
+        self.filter = filter;
+    }
+
+    fn write_event<A: Serialize>(&mut self, event: TraceEvent<A>) -> std::io::Result<()> {
+        if self.written {
+            writeln!(self.writer, ",")?;
+        }
+        self.written = true;
+        serde_json::to_writer(&mut self.writer, &event)?;
+        Ok(())
+    }
+
+    /// Records the instruction `word` at `pc`, which was just executed, and the device writes it caused.
+    pub fn record(
+        &mut self,
+        pc: u32,
+        word: Word,
+        device_writes: &[DeviceWrite],
+    ) -> std::io::Result<()> {
+        let text = vasm::disassemble(word).unwrap_or_else(|| "???".to_owned());
+        let cycle = self.cycle;
+        self.cycle += 1;
+        match self.format {
+            TraceFormat::Chrome => {
+                self.write_event(TraceEvent {
+                    name: text,
+                    cat: Some("instruction"),
+                    ph: "X",
+                    s: None,
+                    ts: Some(cycle),
+                    dur: Some(1),
+                    pid: 1,
+                    tid: INSTRUCTION_THREAD,
+                    args: InstructionArgs { pc, word },
+                })?;
+                for write in device_writes {
+                    self.write_event(TraceEvent {
+                        name: format!("write {}", write.device),
+                        cat: Some("mmio"),
+                        ph: "i",
+                        s: Some("t"),
+                        ts: Some(cycle),
+                        dur: None,
+                        pid: 1,
+                        tid: DEVICE_THREAD,
+                        args: WriteArgs {
+                            pc,
+                            address: write.device.address.wrapping_add(write.offset),
+                            size: write.size,
+                            value: write.value,
+                        },
+                    })?;
+                }
+            }
+            TraceFormat::Csv => {
+                writeln!(
+                    self.writer,
+                    "{},0x{:08X},instruction,0x{:08X},{},,,",
+                    cycle,
+                    pc,
+                    word,
+                    csv_field(&text)
+                )?;
+                for write in device_writes {
+                    writeln!(
+                        self.writer,
+                        "{},0x{:08X},mmio,,,{},{},0x{:08X}",
+                        cycle, pc, write.device, write.offset, write.value
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the end of the trace and returns the writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.format == TraceFormat::Chrome {
+            writeln!(self.writer, "\n]")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Runs `machine` like [`Machine::run_inspected`](../struct.Machine.html#method.run_inspected) and traces it.
+/// `observe` is called for every instruction as well, e.g. for profiling, even if the filter of the tracer
+/// skips it. Writing stops at the first error, which is returned after the program stopped.
+pub fn run_traced<W, F>(
+    machine: &mut Machine,
+    limits: &Limits,
+    tracer: &mut Tracer<W>,
+    mut observe: F,
+) -> (Stop, std::io::Result<()>)
+where
+    W: Write,
+    F: FnMut(u32, Word, &Machine),
+{
+    let device_log = machine.device_log();
+    let mut result = Ok(());
+    let stop = machine.run_inspected(limits, |pc, word, machine| {
+        observe(pc, word, machine);
+        let writes = device_log.take();
+        let traced = tracer
+            .filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(pc, machine));
+        if !traced {
+            tracer.cycle += 1;
+        } else if result.is_ok() {
+            result = tracer.record(pc, word, &writes);
+        }
+    });
+    (stop, result)
+}
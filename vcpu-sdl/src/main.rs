@@ -0,0 +1,292 @@
+//! Shows a VCPU program in an SDL2 window, as the desktop counterpart of the browser playground.
+//!
+//! The machine is read from a machine file like for the other runners, and the program gets a framebuffer
+//! whose RGBA pixels are shown in the window, scaled up. Every frame of the window runs one frame of the
+//! [`FrameDriver`](../vcpu_run/frame/struct.FrameDriver.html) at the clock of the processor and presents the
+//! framebuffer afterwards, synchronized to the display. Output to the console goes to stdout.
+//!
+//! With `--keyboard`, the character of every key typed into the window is stored as a word at that address
+//! before the next frame, `Return` as 10 and `Backspace` as 8. The program reads and clears it, so it only sees
+//! the last character if several are typed during one frame.
+//!
+//! The samples of the first `audio` device of the machine are played with the sample rate the program sets,
+//! paced by the clock of the processor.
+//!
+//! The window stays open after the program stopped, and the exit status is the one of `vcpu-run`.
+
+#[macro_use]
+extern crate clap;
+
+use clap::{AppSettings, Arg};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use std::io::prelude::*;
+use vcpu_run::config::{parse_frequency, parse_size, MachineConfig};
+use vcpu_run::debugger::Session;
+use vcpu_run::frame::{FrameDriver, Framebuffer, DEFAULT_FRAME_RATE, PIXEL_BYTES};
+use vcpu_run::monitor::ConsoleBuffer;
+use vcpu_run::*;
+
+/// Instructions per second if no clock is given.
+const DEFAULT_CLOCK: u64 = 6_000_000;
+/// The framebuffer if none is given, which starts at 1 MiB and has 320x200 pixels.
+const DEFAULT_FRAMEBUFFER: Framebuffer = Framebuffer {
+    address: 0x0010_0000,
+    width: 320,
+    height: 200,
+};
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(ERROR_STATUS);
+}
+
+/// Returns the character which is stored in the keyboard register for an event, if any.
+fn typed_character(event: &Event) -> Option<u32> {
+    match event {
+        Event::TextInput { text, .. } => text.chars().last().map(u32::from),
+        Event::KeyDown {
+            keycode: Some(Keycode::Return),
+            ..
+        } => Some(10),
+        Event::KeyDown {
+            keycode: Some(Keycode::Backspace),
+            ..
+        } => Some(8),
+        _ => None,
+    }
+}
+
+fn main() {
+    let matches = app_from_crate!()
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the vexfile, ELF file or assembly source to run")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("ARGS")
+                .help("Sets the arguments passed to the program")
+                .multiple(true)
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .value_name("MACHINE")
+                .help("Reads the RAM size and devices from a machine file"),
+        )
+        .arg(
+            Arg::with_name("ram")
+                .long("ram")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("Sets the size of the RAM in bytes, optionally with a K or M suffix"),
+        )
+        .arg(
+            Arg::with_name("framebuffer")
+                .long("framebuffer")
+                .takes_value(true)
+                .value_name("ADDRESS:WIDTHxHEIGHT")
+                .validator(|value| value.parse::<Framebuffer>().map(|_| ()))
+                .help("Places the framebuffer (default: 0x100000:320x200)"),
+        )
+        .arg(
+            Arg::with_name("keyboard")
+                .long("keyboard")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("Stores the typed characters as words at this address"),
+        )
+        .arg(
+            Arg::with_name("clock")
+                .long("clock")
+                .takes_value(true)
+                .value_name("HZ")
+                .validator(|value| parse_frequency(&value).map(|_| ()))
+                .help("Sets the instructions executed per second (default: 6M)"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .validator(|value| match value.parse::<u32>() {
+                    Ok(1..=16) => Ok(()),
+                    _ => Err("expected a factor from 1 to 16".to_owned()),
+                })
+                .help("Scales the pixels of the window (default: 2)"),
+        )
+        .get_matches();
+
+    let input = matches.value_of("INPUT").unwrap();
+    let Loaded {
+        executable,
+        warnings,
+    } = load(input).unwrap_or_else(|err| fail(&err));
+    for warning in warnings.iter() {
+        eprintln!("Warning:\n{}", warning);
+    }
+    let mut config = match matches.value_of("machine") {
+        Some(path) => MachineConfig::read_file(path).unwrap_or_else(|err| fail(&err)),
+        None => MachineConfig::default(),
+    };
+    if let Some(value) = matches.value_of("ram") {
+        config.ram_size = Some(parse_size(value).unwrap());
+    }
+    let framebuffer = matches
+        .value_of("framebuffer")
+        .map_or(DEFAULT_FRAMEBUFFER, |value| value.parse().unwrap());
+    let keyboard = matches
+        .value_of("keyboard")
+        .map(|value| parse_size(value).unwrap());
+    let clock = matches
+        .value_of("clock")
+        .map_or(DEFAULT_CLOCK, |value| parse_frequency(value).unwrap());
+    let scale: u32 = matches
+        .value_of("scale")
+        .map_or(2, |value| value.parse().unwrap());
+
+    let args = std::iter::once(input)
+        .chain(matches.values_of("ARGS").into_iter().flatten())
+        .map(String::from)
+        .collect();
+    let session = Session::new(executable, config, args, Vec::new());
+    let console = ConsoleBuffer::default();
+    let mut machine = session
+        .start(Box::new(console.clone()))
+        .unwrap_or_else(|err| fail(&err));
+    let pixels = framebuffer
+        .mount(&mut machine)
+        .unwrap_or_else(|err| fail(&err));
+    if let Some(output) = machine.audio_outputs().first() {
+        output.set_clock(Some(clock));
+    }
+    let mut driver = FrameDriver::for_clock(clock, DEFAULT_FRAME_RATE);
+    driver.set_console(Some(console));
+
+    let status = show(
+        &mut machine,
+        &mut driver,
+        framebuffer,
+        &pixels,
+        keyboard,
+        scale,
+    )
+    .unwrap_or_else(|err| fail(&format!("SDL failed: {}", err)));
+    std::process::exit(status);
+}
+
+/// Runs the machine in a window until the window is closed, and returns the exit status.
+fn show(
+    machine: &mut Machine,
+    driver: &mut FrameDriver,
+    framebuffer: Framebuffer,
+    pixels: &frame::SharedBuffer,
+    keyboard: Option<u32>,
+    scale: u32,
+) -> Result<i32, String> {
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let window = video
+        .window(
+            "vcpu",
+            framebuffer.width * scale,
+            framebuffer.height * scale,
+        )
+        .position_centered()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            framebuffer.width,
+            framebuffer.height,
+        )
+        .map_err(|err| err.to_string())?;
+    video.text_input().start();
+    let mut events = sdl.event_pump()?;
+    let audio = sdl.audio()?;
+    let output = machine.audio_outputs().first().cloned();
+    // opened once samples arrive, and again whenever the program changes the sample rate
+    let mut queue: Option<(u32, AudioQueue<i16>)> = None;
+
+    let mut stop = None;
+    loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    let stop = stop.unwrap_or(Stop::Exit(vcpu::ExitCode::Terminated));
+                    return Ok(exit_status(machine, stop));
+                }
+                _ => {}
+            }
+            if let (Some(address), Some(character)) = (keyboard, typed_character(&event)) {
+                driver.queue_input(address, 4, character);
+            }
+        }
+
+        if stop.is_none() {
+            let frame = driver.run_frame(machine)?;
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(&frame.console)
+                .and_then(|_| stdout.flush())
+                .map_err(|err| err.to_string())?;
+            if let Some(exit_code) = frame.exit_code {
+                stop = Some(Stop::Exit(exit_code));
+                if let Some(message) = describe_stop(machine, Stop::Exit(exit_code)) {
+                    eprintln!("{}", message);
+                }
+            }
+        }
+
+        if let Some(output) = &output {
+            let samples = output.take(output.available());
+            let rate = output.sample_rate();
+            if !samples.is_empty() && rate > 0 {
+                if queue.as_ref().map(|(queue_rate, _)| *queue_rate) != Some(rate) {
+                    let spec = AudioSpecDesired {
+                        freq: Some(rate as i32),
+                        channels: Some(1),
+                        samples: None,
+                    };
+                    let new_queue = audio.open_queue(None, &spec)?;
+                    new_queue.resume();
+                    queue = Some((rate, new_queue));
+                }
+                if let Some((_, queue)) = &queue {
+                    queue.queue_audio(&samples)?;
+                }
+            }
+        }
+
+        texture
+            .update(
+                None,
+                &pixels.to_vec(),
+                (framebuffer.width * PIXEL_BYTES) as usize,
+            )
+            .map_err(|err| err.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+    }
+}
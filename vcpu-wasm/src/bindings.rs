@@ -0,0 +1,185 @@
+//! The playground as a JavaScript class, generated with [wasm-bindgen](https://rustwasm.github.io/wasm-bindgen/).
+//!
+//! Build the package with `wasm-pack build vcpu-wasm --target web`, which generates the bindings together with
+//! TypeScript declarations:
+//!
+//! ```js
+//! import init, { Vcpu } from "./pkg/vcpu_wasm.js";
+//!
+//! await init();
+//! const vcpu = new Vcpu();
+//! vcpu.assemble(source);
+//! requestAnimationFrame(function frame() {
+//!     const state = vcpu.runFrame();
+//!     console.append(vcpu.takeConsole());
+//!     context.putImageData(new ImageData(vcpu.framebuffer(), width, height), 0, 0);
+//!     if (state === undefined) requestAnimationFrame(frame);
+//! });
+//! ```
+//!
+//! States are the number of the exit code, or `undefined` while the program is running. Methods which need a
+//! loaded program, and all other failures, throw an `Error` with the message.
+
+use crate::{Framebuffer, Playground, DEFAULT_RAM_SIZE};
+use num::FromPrimitive;
+use vcpu::{enum_to_u32, ExitCode, RegisterId, REGISTER_COUNT};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+/// A [`Playground`](../struct.Playground.html) which JavaScript owns.
+#[wasm_bindgen]
+pub struct Vcpu {
+    playground: Playground,
+}
+
+fn error(message: String) -> JsError {
+    JsError::new(&message)
+}
+
+fn state(exit_code: Option<ExitCode>) -> Option<u32> {
+    exit_code.map(enum_to_u32)
+}
+
+#[wasm_bindgen]
+impl Vcpu {
+    /// Creates a playground with `ram_size` bytes of RAM, or its default size.
+    #[wasm_bindgen(constructor)]
+    pub fn new(ram_size: Option<u32>) -> Vcpu {
+        Vcpu {
+            playground: Playground::new(ram_size.unwrap_or(DEFAULT_RAM_SIZE)),
+        }
+    }
+
+    /// Sets the framebuffer of the next program which is loaded or reset, or removes it if `width` or `height`
+    /// is 0.
+    #[wasm_bindgen(js_name = setFramebuffer)]
+    pub fn set_framebuffer(&mut self, address: u32, width: u32, height: u32) {
+        let framebuffer = if width == 0 || height == 0 {
+            None
+        } else {
+            Some(Framebuffer {
+                address,
+                width,
+                height,
+            })
+        };
+        self.playground.set_framebuffer(framebuffer);
+    }
+
+    /// Assembles and loads the program, or throws an error with the diagnostics.
+    pub fn assemble(&mut self, source: &str) -> Result<(), JsError> {
+        self.playground.assemble(source).map_err(error)
+    }
+
+    #[wasm_bindgen(js_name = loadVex)]
+    pub fn load_vex(&mut self, bytes: &[u8]) -> Result<(), JsError> {
+        self.playground.load_vex(bytes).map_err(error)
+    }
+
+    /// Restarts the program with a new machine.
+    pub fn reset(&mut self) -> Result<(), JsError> {
+        self.playground.reset().map_err(error)
+    }
+
+    pub fn step(&mut self) -> Result<Option<u32>, JsError> {
+        self.playground.step().map(state).map_err(error)
+    }
+
+    /// Executes at most `max_instructions` instructions.
+    pub fn run(&mut self, max_instructions: u32) -> Result<Option<u32>, JsError> {
+        self.playground
+            .run(u64::from(max_instructions))
+            .map(state)
+            .map_err(error)
+    }
+
+    /// Sets how many instructions `runFrame` executes at most.
+    #[wasm_bindgen(js_name = setInstructionsPerFrame)]
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.playground
+            .set_instructions_per_frame(u64::from(instructions));
+    }
+
+    /// Stores `value` with `size` bytes at `address` before the next frame, e.g. from a key event.
+    #[wasm_bindgen(js_name = queueInput)]
+    pub fn queue_input(&mut self, address: u32, size: u32, value: u32) {
+        self.playground.queue_input(address, size, value);
+    }
+
+    /// Applies the queued input, runs one frame and returns the state like `run`.
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) -> Result<Option<u32>, JsError> {
+        self.playground
+            .run_frame()
+            .map(|frame| state(frame.exit_code))
+            .map_err(error)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn loaded(&self) -> bool {
+        self.playground.machine().is_some()
+    }
+
+    /// The state of the loaded program.
+    #[wasm_bindgen(getter)]
+    pub fn state(&self) -> Option<u32> {
+        self.playground
+            .machine()
+            .and_then(|machine| state(machine.processor().state()))
+    }
+
+    /// Number of instructions executed since the program was loaded or reset.
+    #[wasm_bindgen(getter)]
+    pub fn executed(&self) -> f64 {
+        self.playground
+            .machine()
+            .map_or(0.0, |machine| machine.executed() as f64)
+    }
+
+    #[wasm_bindgen(getter, js_name = programCounter)]
+    pub fn program_counter(&self) -> u32 {
+        self.playground
+            .machine()
+            .map_or(0, |machine| machine.processor().program_counter())
+    }
+
+    /// Names of the registers, in the order of their indices.
+    #[wasm_bindgen(js_name = registerNames)]
+    pub fn register_names(&self) -> Vec<String> {
+        (0..REGISTER_COUNT as u32)
+            .filter_map(RegisterId::from_u32)
+            .map(|id| format!("{:?}", id))
+            .collect()
+    }
+
+    /// Returns the register with the given index, or `undefined` if there is none or no program is loaded.
+    pub fn register(&self, index: u32) -> Option<u32> {
+        self.playground.register(index)
+    }
+
+    #[wasm_bindgen(js_name = setRegister)]
+    pub fn set_register(&mut self, index: u32, value: u32) -> Result<(), JsError> {
+        self.playground.set_register(index, value).map_err(error)
+    }
+
+    #[wasm_bindgen(js_name = readMemory)]
+    pub fn read_memory(&self, address: u32, length: u32) -> Result<Vec<u8>, JsError> {
+        self.playground.read_memory(address, length).map_err(error)
+    }
+
+    #[wasm_bindgen(js_name = writeMemory)]
+    pub fn write_memory(&mut self, address: u32, bytes: &[u8]) -> Result<(), JsError> {
+        self.playground.write_memory(address, bytes).map_err(error)
+    }
+
+    /// Returns everything the program wrote to the UART since the last call.
+    #[wasm_bindgen(js_name = takeConsole)]
+    pub fn take_console(&self) -> String {
+        String::from_utf8_lossy(&self.playground.take_console()).into_owned()
+    }
+
+    /// Copy of the RGBA pixels of the framebuffer, which is empty if the machine has none.
+    pub fn framebuffer(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.playground.pixels().to_vec())
+    }
+}
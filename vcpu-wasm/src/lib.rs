@@ -0,0 +1,217 @@
+//! Runs VCPU programs in the browser, e.g. for an interactive playground.
+//!
+//! A [`Playground`](struct.Playground.html) assembles or loads a program onto the machine of the runner, with
+//! the UART at `0xFFFF0000` writing to a console buffer and an optional framebuffer, whose RGBA pixels the page
+//! can copy into a canvas after every run. Programs are run in slices of a limited number of instructions, so
+//! that the page stays responsive, usually one [`frame`](../vcpu_run/frame/index.html) per animation frame of
+//! the page.
+//!
+//! Compiled for `wasm32-unknown-unknown`, the [`bindings`](bindings/index.html) provide playgrounds to JavaScript
+//! as instances of the `Vcpu` class.
+
+pub mod bindings;
+#[cfg(test)]
+mod test;
+
+use num::FromPrimitive;
+use vcpu::{ExitCode, RegisterId, Storage, StorageMut};
+use vcpu_run::frame::{Frame, FrameDriver};
+pub use vcpu_run::frame::{Framebuffer, SharedBuffer, PIXEL_BYTES};
+use vcpu_run::monitor::ConsoleBuffer;
+use vcpu_run::{Limits, Machine, Stop, DEFAULT_DEVICE};
+use vex::Executable;
+
+/// RAM size of a new playground.
+pub const DEFAULT_RAM_SIZE: u32 = 1 << 16;
+
+/// Instructions a frame of a new playground executes.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u64 = 100_000;
+
+/// A machine for programs which are edited and run in the browser.
+pub struct Playground {
+    ram_size: u32,
+    framebuffer: Option<Framebuffer>,
+    executable: Option<Executable>,
+    machine: Option<Machine>,
+    console: ConsoleBuffer,
+    pixels: SharedBuffer,
+    frames: FrameDriver,
+}
+
+impl Default for Playground {
+    fn default() -> Self {
+        Playground::new(DEFAULT_RAM_SIZE)
+    }
+}
+
+impl Playground {
+    pub fn new(ram_size: u32) -> Playground {
+        Playground {
+            ram_size,
+            framebuffer: None,
+            executable: None,
+            machine: None,
+            console: ConsoleBuffer::default(),
+            pixels: SharedBuffer::default(),
+            frames: FrameDriver::new(DEFAULT_INSTRUCTIONS_PER_FRAME),
+        }
+    }
+
+    /// Sets the framebuffer of the machine, which is used from the next program that is loaded or reset.
+    pub fn set_framebuffer(&mut self, framebuffer: Option<Framebuffer>) {
+        self.framebuffer = framebuffer;
+    }
+
+    /// Assembles `source` and loads the program. The error contains all diagnostics, one per line.
+    pub fn assemble(&mut self, source: &str) -> Result<(), String> {
+        let assembly = vasm::assemble(source, &vasm::Options::default()).map_err(|errors| {
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })?;
+        let mut executable = assembly.executable;
+        executable.set_debug_info(Some(assembly.debug_info));
+        self.load(executable)
+    }
+
+    /// Loads the program from the bytes of a vexfile.
+    pub fn load_vex(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let executable = Executable::from_bytes(bytes)
+            .map_err(|err| format!("Reading the vexfile failed: {}", err))?;
+        self.load(executable)
+    }
+
+    pub fn load(&mut self, executable: Executable) -> Result<(), String> {
+        self.executable = Some(executable);
+        self.reset()
+    }
+
+    /// Restarts the loaded program with a new machine and clears the console and the queued input.
+    pub fn reset(&mut self) -> Result<(), String> {
+        let executable = self
+            .executable
+            .as_ref()
+            .ok_or_else(|| "No program is loaded".to_owned())?;
+        self.machine = None;
+        self.console.clear();
+        self.frames = FrameDriver::new(self.frames.instructions_per_frame());
+        let mut machine = Machine::new(
+            executable,
+            self.ram_size,
+            &[DEFAULT_DEVICE],
+            Box::new(self.console.clone()),
+        )?;
+        self.pixels = match self.framebuffer {
+            Some(framebuffer) => framebuffer.mount(&mut machine)?,
+            None => SharedBuffer::default(),
+        };
+        self.machine = Some(machine);
+        Ok(())
+    }
+
+    pub fn machine(&self) -> Option<&Machine> {
+        self.machine.as_ref()
+    }
+
+    pub fn machine_mut(&mut self) -> Option<&mut Machine> {
+        self.machine.as_mut()
+    }
+
+    fn loaded(&mut self) -> Result<&mut Machine, String> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| "No program is loaded".to_owned())
+    }
+
+    /// Executes a single instruction and returns the state of the processor afterwards.
+    pub fn step(&mut self) -> Result<Option<ExitCode>, String> {
+        let machine = self.loaded()?;
+        machine.step();
+        Ok(machine.processor().state())
+    }
+
+    /// Executes at most `max_instructions` instructions and returns the state of the processor afterwards,
+    /// which is `None` if the program is still running.
+    pub fn run(&mut self, max_instructions: u64) -> Result<Option<ExitCode>, String> {
+        let machine = self.loaded()?;
+        let limits = Limits {
+            max_instructions: Some(machine.executed().saturating_add(max_instructions)),
+            ..Limits::default()
+        };
+        Ok(match machine.run(&limits) {
+            Stop::Exit(exit_code) => Some(exit_code),
+            Stop::InstructionLimit | Stop::Timeout => None,
+        })
+    }
+
+    /// Sets how many instructions [`run_frame`](#method.run_frame) executes at most.
+    pub fn set_instructions_per_frame(&mut self, instructions: u64) {
+        self.frames = FrameDriver::new(instructions);
+    }
+
+    /// Stores `value` with `size` bytes at `address` before the next frame, e.g. the key which was pressed.
+    pub fn queue_input(&mut self, address: u32, size: u32, value: u32) {
+        self.frames.queue_input(address, size, value);
+    }
+
+    /// Applies the queued input and runs the next frame, after which the console and the pixels can be taken.
+    pub fn run_frame(&mut self) -> Result<Frame, String> {
+        let machine = self
+            .machine
+            .as_mut()
+            .ok_or_else(|| "No program is loaded".to_owned())?;
+        self.frames.run_frame(machine)
+    }
+
+    pub fn register(&self, index: u32) -> Option<u32> {
+        let machine = self.machine.as_ref()?;
+        RegisterId::from_u32(index).map(|id| machine.processor().register(id).u())
+    }
+
+    pub fn set_register(&mut self, index: u32, value: u32) -> Result<(), String> {
+        let id = RegisterId::from_u32(index)
+            .ok_or_else(|| format!("Register index {} is out of range", index))?;
+        self.loaded()?.processor_mut().register_mut(id).set_u(value);
+        Ok(())
+    }
+
+    /// Reads `length` bytes of the memory, including devices, starting at `address`.
+    pub fn read_memory(&self, address: u32, length: u32) -> Result<Vec<u8>, String> {
+        let machine = self
+            .machine
+            .as_ref()
+            .ok_or_else(|| "No program is loaded".to_owned())?;
+        (0..length)
+            .map(|i| {
+                let byte_address = address.wrapping_add(i);
+                machine
+                    .memory()
+                    .read_byte(byte_address)
+                    .map_err(|_| format!("Address 0x{:08X} cannot be read", byte_address))
+            })
+            .collect()
+    }
+
+    pub fn write_memory(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        let memory = self.loaded()?.memory_mut();
+        for (i, byte) in bytes.iter().enumerate() {
+            let byte_address = address.wrapping_add(i as u32);
+            memory
+                .write_byte(byte_address, *byte)
+                .map_err(|_| format!("Address 0x{:08X} cannot be written", byte_address))?;
+        }
+        Ok(())
+    }
+
+    /// Removes and returns everything the program wrote to the console so far.
+    pub fn take_console(&self) -> Vec<u8> {
+        self.console.take()
+    }
+
+    /// The pixels of the framebuffer, which is empty if the machine has none.
+    pub fn pixels(&self) -> &SharedBuffer {
+        &self.pixels
+    }
+}
@@ -0,0 +1,144 @@
+use crate::bindings::*;
+use crate::*;
+use vcpu::REGISTER_COUNT;
+
+const HELLO: &str = ".include <std/uart.vasm>
+.data
+.instructions
+        LI $A0, 72
+        JL uart_putc
+        LI $A0, 105
+        JL uart_putc
+        LI $V0, 7
+        HALT";
+
+#[test]
+fn assemble_and_run() {
+    let mut playground = Playground::default();
+    assert!(playground.step().is_err());
+    playground.assemble(HELLO).unwrap();
+
+    assert_eq!(playground.step(), Ok(None));
+    assert_eq!(playground.register(RegisterId::A0 as u32), Some(72));
+    assert_eq!(playground.run(3), Ok(None));
+    assert_eq!(playground.run(1000), Ok(Some(ExitCode::Halted)));
+    assert_eq!(playground.register(RegisterId::V0 as u32), Some(7));
+    assert_eq!(playground.take_console(), b"Hi");
+    assert!(playground.take_console().is_empty());
+
+    playground.reset().unwrap();
+    assert_eq!(playground.machine().unwrap().executed(), 0);
+    playground.set_register(RegisterId::V0 as u32, 3).unwrap();
+    assert_eq!(playground.register(RegisterId::V0 as u32), Some(3));
+    assert!(playground.set_register(99, 3).is_err());
+}
+
+#[test]
+fn assembler_errors() {
+    let mut playground = Playground::default();
+    let err = playground
+        .assemble(".data\n.instructions\nSTUFF\nHALT")
+        .unwrap_err();
+    assert!(err.starts_with("3:1: error:"), "{}", err);
+    assert!(playground.machine().is_none());
+}
+
+#[test]
+fn memory_and_framebuffer() {
+    let mut playground = Playground::new(1024);
+    playground.set_framebuffer(Some(Framebuffer {
+        address: 0x1000,
+        width: 2,
+        height: 2,
+    }));
+    playground
+        .assemble(
+            ".data
+.instructions
+        LWI $T0, 0x1000
+        LWI $T1, 0xFF0000FF
+        SW $T1, 4($T0)
+        HALT",
+        )
+        .unwrap();
+    assert_eq!(playground.pixels().length(), 16);
+    assert_eq!(playground.run(100), Ok(Some(ExitCode::Halted)));
+    assert_eq!(
+        playground.pixels().to_vec(),
+        [0, 0, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    playground.write_memory(0x10, b"abc").unwrap();
+    assert_eq!(playground.read_memory(0x10, 3).unwrap(), b"abc");
+    assert_eq!(
+        playground.read_memory(1022, 4),
+        Err("Address 0x00000400 cannot be read".to_owned())
+    );
+
+    playground.set_framebuffer(Some(Framebuffer {
+        address: 0,
+        width: 1,
+        height: 1,
+    }));
+    assert!(playground.reset().is_err());
+}
+
+#[test]
+fn frames() {
+    let mut playground = Playground::new(1024);
+    assert!(playground.run_frame().is_err());
+    playground
+        .assemble(
+            ".include <std/uart.vasm>
+.data
+.instructions
+wait:   LW $A0, 0x100($ZERO)
+        BEZ $A0, wait
+        JL uart_putc
+        HALT",
+        )
+        .unwrap();
+    playground.set_instructions_per_frame(50);
+    playground.reset().unwrap();
+
+    let frame = playground.run_frame().unwrap();
+    assert_eq!(
+        (frame.number, frame.executed, frame.exit_code),
+        (0, 50, None)
+    );
+    playground.queue_input(0x100, 4, u32::from(b'!'));
+    let frame = playground.run_frame().unwrap();
+    assert_eq!(frame.exit_code, Some(ExitCode::Halted));
+    assert_eq!(playground.take_console(), b"!");
+
+    // reset starts counting the frames again
+    playground.reset().unwrap();
+    assert_eq!(playground.run_frame().unwrap().number, 0);
+}
+
+#[test]
+fn bindings() {
+    let mut vcpu = Vcpu::new(Some(1 << 12));
+    assert!(!vcpu.loaded());
+    vcpu.assemble(HELLO).unwrap();
+    assert!(vcpu.loaded());
+
+    assert_eq!(vcpu.run(2).unwrap(), None);
+    assert_eq!(vcpu.executed(), 2.0);
+    assert_eq!(vcpu.run(1000).unwrap(), Some(ExitCode::Halted as u32));
+    assert_eq!(vcpu.state(), Some(ExitCode::Halted as u32));
+    assert_eq!(vcpu.register(RegisterId::V0 as u32), Some(7));
+    assert_eq!(vcpu.register(99), None);
+    assert_eq!(vcpu.take_console(), "Hi");
+    assert_eq!(vcpu.read_memory(0, 4).unwrap().len(), 4);
+    assert!(vcpu.framebuffer().0.is_empty());
+
+    let names = vcpu.register_names();
+    assert_eq!(names.len(), REGISTER_COUNT);
+    assert_eq!(names[RegisterId::SP as usize], "SP");
+
+    vcpu.set_framebuffer(0x10000, 2, 2);
+    vcpu.reset().unwrap();
+    assert_eq!(vcpu.state(), None);
+    assert_eq!(vcpu.framebuffer().0.len(), 2 * 2 * PIXEL_BYTES as usize);
+}
@@ -0,0 +1,52 @@
+//! Compression of the payload of executables with deflate, in a zlib stream whose checksum detects corrupt
+//! files.
+//!
+//! The codec is only compiled in with the `compression` feature. Without it, executables cannot be compressed
+//! and reading a compressed one fails.
+
+use std::io::{Error, ErrorKind};
+
+#[cfg(feature = "compression")]
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    // writing to a Vec cannot fail
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompresses exactly `len` bytes, which must be all of the compressed data.
+#[cfg(feature = "compression")]
+pub fn decompress(bytes: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    let corrupt = || Error::new(ErrorKind::InvalidData, "Compressed executable is corrupt");
+    let mut decoder = ZlibDecoder::new(bytes);
+    let mut payload = Vec::new();
+    // one more byte than expected is read to detect longer payloads
+    (&mut decoder)
+        .take(len as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|_| corrupt())?;
+    if payload.len() != len || decoder.total_in() != bytes.len() as u64 {
+        return Err(corrupt());
+    }
+    Ok(payload)
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn compress(_bytes: &[u8]) -> Vec<u8> {
+    unreachable!("executables can only be compressed with the compression feature")
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decompress(_bytes: &[u8], _len: usize) -> Result<Vec<u8>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "Compressed executables need the compression feature of vex",
+    ))
+}
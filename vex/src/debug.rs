@@ -0,0 +1,166 @@
+//! Debug information which maps a program back to its source.
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::Path;
+use util::Endian;
+
+const MAGIC: &[u8; 4] = b"VDBG";
+const VERSION: u32 = 1;
+
+/// Kind of address a [`DebugSymbol`](struct.DebugSymbol.html) refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Address in main memory.
+    Data,
+    /// Address in instruction memory.
+    Instruction,
+}
+
+/// A named address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub address: u32,
+}
+
+/// Associates the instruction at `address` with a line in one of the source files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineEntry {
+    pub address: u32,
+    /// Index into [`DebugInfo::files`](struct.DebugInfo.html#structfield.files).
+    pub file: u32,
+    pub line: u32,
+}
+
+/// Source-level debug information of a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<LineEntry>,
+    pub symbols: Vec<DebugSymbol>,
+}
+
+impl DebugInfo {
+    /// Returns the line entry for the instruction at `address`, if there is one.
+    pub fn line_at(&self, address: u32) -> Option<&LineEntry> {
+        self.lines.iter().find(|entry| entry.address == address)
+    }
+
+    /// Returns the symbol with the given `name`, if there is one.
+    pub fn symbol(&self, name: &str) -> Option<&DebugSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let len = reader.read_u32::<Endian>()?;
+    // the length is not allocated up front, since it may come from a broken file
+    let mut bytes = Vec::new();
+    reader.take(len.into()).read_to_end(&mut bytes)?;
+    if bytes.len() < len as usize {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data("String is not valid UTF-8"))
+}
+
+pub(crate) fn write_string<W: Write>(writer: &mut W, value: &str) -> std::io::Result<()> {
+    writer.write_u32::<Endian>(value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+pub fn read<R: Read>(reader: &mut R) -> std::io::Result<DebugInfo> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a debug information file"));
+    }
+    if reader.read_u32::<Endian>()? != VERSION {
+        return Err(invalid_data("Unsupported debug information version"));
+    }
+
+    let file_count = reader.read_u32::<Endian>()?;
+    let mut files = Vec::new();
+    for _ in 0..file_count {
+        files.push(read_string(reader)?);
+    }
+
+    let line_count = reader.read_u32::<Endian>()?;
+    let mut lines = Vec::new();
+    for _ in 0..line_count {
+        lines.push(LineEntry {
+            address: reader.read_u32::<Endian>()?,
+            file: reader.read_u32::<Endian>()?,
+            line: reader.read_u32::<Endian>()?,
+        });
+    }
+
+    let symbol_count = reader.read_u32::<Endian>()?;
+    let mut symbols = Vec::new();
+    for _ in 0..symbol_count {
+        let kind = match reader.read_u8()? {
+            0 => SymbolKind::Data,
+            1 => SymbolKind::Instruction,
+            _ => return Err(invalid_data("Unknown symbol kind")),
+        };
+        let address = reader.read_u32::<Endian>()?;
+        let name = read_string(reader)?;
+        symbols.push(DebugSymbol {
+            name,
+            kind,
+            address,
+        });
+    }
+
+    Ok(DebugInfo {
+        files,
+        lines,
+        symbols,
+    })
+}
+
+pub fn write<W: Write>(writer: &mut W, debug_info: &DebugInfo) -> std::io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<Endian>(VERSION)?;
+
+    writer.write_u32::<Endian>(debug_info.files.len() as u32)?;
+    for file in debug_info.files.iter() {
+        write_string(writer, file)?;
+    }
+
+    writer.write_u32::<Endian>(debug_info.lines.len() as u32)?;
+    for entry in debug_info.lines.iter() {
+        writer.write_u32::<Endian>(entry.address)?;
+        writer.write_u32::<Endian>(entry.file)?;
+        writer.write_u32::<Endian>(entry.line)?;
+    }
+
+    writer.write_u32::<Endian>(debug_info.symbols.len() as u32)?;
+    for symbol in debug_info.symbols.iter() {
+        writer.write_u8(match symbol.kind {
+            SymbolKind::Data => 0,
+            SymbolKind::Instruction => 1,
+        })?;
+        writer.write_u32::<Endian>(symbol.address)?;
+        write_string(writer, &symbol.name)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> std::io::Result<DebugInfo> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, debug_info: &DebugInfo) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer, debug_info)?;
+    writer.flush()
+}
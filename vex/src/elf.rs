@@ -0,0 +1,458 @@
+//! Conversion between executables and 32-bit little endian ELF files, so that tools like `objdump`,
+//! `readelf` and `size` can be used on programs, and programs built by other toolchains can be run.
+//!
+//! Instruction memory and main memory are separate address spaces, which ELF has no notion of.
+//! Executable segments therefore go to instruction memory and all other segments to main memory,
+//! even if their addresses overlap. Written files use the machine type `EM_NONE`, read files may have any.
+//! Symbols are exported from and imported into the [`DebugInfo`](../debug/struct.DebugInfo.html),
+//! other debug information is not converted.
+
+use crate::debug::{DebugInfo, DebugSymbol, SymbolKind};
+use crate::{check_entry_point, invalid_data, Executable, Section};
+use byteorder::{ByteOrder, WriteBytesExt};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::Path;
+use util::Endian;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_NONE: u16 = 0;
+
+const HEADER_SIZE: u32 = 52;
+const PROGRAM_HEADER_SIZE: u32 = 32;
+const SECTION_HEADER_SIZE: u32 = 40;
+const SYMBOL_SIZE: u32 = 16;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+const SHN_ABS: u16 = 0xFFF1;
+
+const STB_GLOBAL: u8 = 1;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Instruction segments must not start further from address 0, so that broken files can't make the
+/// instruction memory huge.
+const MAX_INSTRUCTION_ADDRESS: u32 = 1 << 26;
+
+/// Collects the names of sections or symbols in the string table format.
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable { bytes: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u32,
+    address: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entry_size: u32,
+}
+
+fn align(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// Writes `executable` as an ELF file with one segment and section for the instructions and for each section.
+pub fn write<W: Write>(writer: &mut W, executable: &Executable) -> std::io::Result<()> {
+    let sections = executable.sections();
+    let segment_count = 1 + sections.len() as u32;
+    let mut names = StringTable::new();
+
+    // segment contents, which start after the header and the program headers
+    let mut contents = Vec::new();
+    let mut segments = Vec::new();
+    let mut section_headers = Vec::new();
+    let contents_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE * segment_count;
+
+    let instructions = executable.instructions();
+    segments.push((contents_offset, instructions.len() as u32, PF_R | PF_X, 0));
+    section_headers.push(SectionHeader {
+        name: names.add(".text"),
+        kind: SHT_PROGBITS,
+        flags: SHF_ALLOC | SHF_EXECINSTR,
+        address: 0,
+        offset: contents_offset,
+        size: instructions.len() as u32,
+        link: 0,
+        info: 0,
+        align: 4,
+        entry_size: 0,
+    });
+    contents.extend_from_slice(instructions);
+
+    for section in sections {
+        contents.resize(align(contents.len()), 0);
+        let offset = contents_offset + contents.len() as u32;
+        let flags = section.flags();
+        let (name, file_size) = if flags.zero_fill {
+            (".bss", 0)
+        } else if flags.read_only {
+            (".rodata", section.size())
+        } else {
+            (".data", section.size())
+        };
+        let segment_flags = if flags.read_only { PF_R } else { PF_R | PF_W };
+        segments.push((offset, file_size, segment_flags, section.address()));
+        section_headers.push(SectionHeader {
+            name: names.add(name),
+            kind: if flags.zero_fill {
+                SHT_NOBITS
+            } else {
+                SHT_PROGBITS
+            },
+            flags: if flags.read_only {
+                SHF_ALLOC
+            } else {
+                SHF_ALLOC | SHF_WRITE
+            },
+            address: section.address(),
+            offset,
+            size: section.size(),
+            link: 0,
+            info: 0,
+            align: 4,
+            entry_size: 0,
+        });
+        contents.extend_from_slice(section.bytes());
+    }
+
+    if let Some(debug_info) = executable.debug_info() {
+        let mut symbol_names = StringTable::new();
+        let mut symbols = vec![0; SYMBOL_SIZE as usize];
+        for symbol in debug_info.symbols.iter() {
+            let (kind, index) = match symbol.kind {
+                SymbolKind::Instruction => (STT_FUNC, 1),
+                SymbolKind::Data => {
+                    let index = sections.iter().position(|section| {
+                        section.address() <= symbol.address
+                            && (symbol.address as u64)
+                                < section.address() as u64 + section.size() as u64
+                    });
+                    (STT_OBJECT, index.map_or(SHN_ABS, |index| index as u16 + 2))
+                }
+            };
+            symbols.write_u32::<Endian>(symbol_names.add(&symbol.name))?;
+            symbols.write_u32::<Endian>(symbol.address)?;
+            symbols.write_u32::<Endian>(0)?;
+            symbols.write_u8(STB_GLOBAL << 4 | kind)?;
+            symbols.write_u8(0)?;
+            symbols.write_u16::<Endian>(index)?;
+        }
+
+        contents.resize(align(contents.len()), 0);
+        let symbol_table_index = section_headers.len() as u32 + 1;
+        section_headers.push(SectionHeader {
+            name: names.add(".symtab"),
+            kind: SHT_SYMTAB,
+            flags: 0,
+            address: 0,
+            offset: contents_offset + contents.len() as u32,
+            size: symbols.len() as u32,
+            link: symbol_table_index + 1,
+            info: 1,
+            align: 4,
+            entry_size: SYMBOL_SIZE,
+        });
+        contents.extend_from_slice(&symbols);
+        section_headers.push(SectionHeader {
+            name: names.add(".strtab"),
+            kind: SHT_STRTAB,
+            flags: 0,
+            address: 0,
+            offset: contents_offset + contents.len() as u32,
+            size: symbol_names.bytes.len() as u32,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+        contents.extend_from_slice(&symbol_names.bytes);
+    }
+
+    let name = names.add(".shstrtab");
+    section_headers.push(SectionHeader {
+        name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: contents_offset + contents.len() as u32,
+        size: names.bytes.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    contents.extend_from_slice(&names.bytes);
+    contents.resize(align(contents.len()), 0);
+
+    let section_headers_offset = contents_offset + contents.len() as u32;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(ELF_MAGIC);
+    bytes.extend_from_slice(&[ELFCLASS32, ELFDATA2LSB, EV_CURRENT]);
+    bytes.resize(16, 0);
+    bytes.write_u16::<Endian>(ET_EXEC)?;
+    bytes.write_u16::<Endian>(EM_NONE)?;
+    bytes.write_u32::<Endian>(EV_CURRENT.into())?;
+    bytes.write_u32::<Endian>(executable.entry_point())?;
+    bytes.write_u32::<Endian>(HEADER_SIZE)?;
+    bytes.write_u32::<Endian>(section_headers_offset)?;
+    bytes.write_u32::<Endian>(0)?;
+    bytes.write_u16::<Endian>(HEADER_SIZE as u16)?;
+    bytes.write_u16::<Endian>(PROGRAM_HEADER_SIZE as u16)?;
+    bytes.write_u16::<Endian>(segment_count as u16)?;
+    bytes.write_u16::<Endian>(SECTION_HEADER_SIZE as u16)?;
+    bytes.write_u16::<Endian>(section_headers.len() as u16 + 1)?;
+    bytes.write_u16::<Endian>(section_headers.len() as u16)?;
+
+    for ((offset, file_size, flags, address), header) in segments.iter().zip(section_headers.iter())
+    {
+        bytes.write_u32::<Endian>(PT_LOAD)?;
+        bytes.write_u32::<Endian>(*offset)?;
+        bytes.write_u32::<Endian>(*address)?;
+        bytes.write_u32::<Endian>(*address)?;
+        bytes.write_u32::<Endian>(*file_size)?;
+        bytes.write_u32::<Endian>(header.size)?;
+        bytes.write_u32::<Endian>(*flags)?;
+        bytes.write_u32::<Endian>(4)?;
+    }
+    bytes.extend_from_slice(&contents);
+
+    // the first section header is always empty
+    bytes.resize(bytes.len() + SECTION_HEADER_SIZE as usize, 0);
+    for header in section_headers.iter() {
+        for value in [
+            header.name,
+            header.kind,
+            header.flags,
+            header.address,
+            header.offset,
+            header.size,
+            header.link,
+            header.info,
+            header.align,
+            header.entry_size,
+        ] {
+            bytes.write_u32::<Endian>(value)?;
+        }
+    }
+
+    writer.write_all(&bytes)
+}
+
+/// The bytes of an ELF file, with bounds checked accessors.
+struct ElfFile<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ElfFile<'a> {
+    fn slice(&self, offset: u32, len: u32) -> std::io::Result<&'a [u8]> {
+        let start = offset as usize;
+        let end = start + len as usize;
+        self.bytes
+            .get(start..end)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "ELF file is truncated"))
+    }
+
+    fn u16(&self, offset: u32) -> std::io::Result<u16> {
+        Ok(Endian::read_u16(self.slice(offset, 2)?))
+    }
+
+    fn u32(&self, offset: u32) -> std::io::Result<u32> {
+        Ok(Endian::read_u32(self.slice(offset, 4)?))
+    }
+
+    /// Returns the zero-terminated string at `offset` in the string table at `table`.
+    fn string(&self, table: u32, offset: u32) -> std::io::Result<String> {
+        let start = table as usize + offset as usize;
+        let bytes = self.bytes.get(start..).unwrap_or(&[]);
+        let len = bytes
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| invalid_data("ELF string is not terminated".to_owned()))?;
+        String::from_utf8(bytes[..len].to_vec())
+            .map_err(|_| invalid_data("ELF string is not valid UTF-8".to_owned()))
+    }
+}
+
+/// Reads an executable from the loadable segments of an ELF file.
+///
+/// Executable segments are placed at their address in instruction memory, gaps between them are filled
+/// with zeros. Every other segment becomes a section, followed by a zero-filled section if it is larger in
+/// memory than in the file. Segments without write permission become read-only sections.
+pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Executable> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let file = ElfFile { bytes: &bytes };
+
+    if file.slice(0, 4)? != ELF_MAGIC {
+        return Err(invalid_data("Not an ELF file".to_owned()));
+    }
+    if file.slice(4, 2)? != [ELFCLASS32, ELFDATA2LSB] {
+        return Err(invalid_data(
+            "Only 32-bit little endian ELF files are supported".to_owned(),
+        ));
+    }
+    if file.u16(16)? != ET_EXEC {
+        return Err(invalid_data("ELF file is not an executable".to_owned()));
+    }
+    let entry_point = file.u32(24)?;
+    let program_headers = file.u32(28)?;
+    let section_headers = file.u32(32)?;
+    if file.u16(42)? as u32 != PROGRAM_HEADER_SIZE {
+        return Err(invalid_data(
+            "Unexpected ELF program header size".to_owned(),
+        ));
+    }
+    let segment_count = file.u16(44)? as u32;
+
+    let mut instructions = Vec::new();
+    let mut sections = Vec::new();
+    for i in 0..segment_count {
+        let header = program_headers + i * PROGRAM_HEADER_SIZE;
+        if file.u32(header)? != PT_LOAD {
+            continue;
+        }
+        let offset = file.u32(header + 4)?;
+        let address = file.u32(header + 8)?;
+        let file_size = file.u32(header + 16)?;
+        let memory_size = file.u32(header + 20)?;
+        let flags = file.u32(header + 24)?;
+        if memory_size < file_size {
+            return Err(invalid_data(format!(
+                "ELF segment at 0x{:08X} is smaller in memory than in the file",
+                address
+            )));
+        }
+        let contents = file.slice(offset, file_size)?;
+
+        if flags & PF_X != 0 {
+            if address > MAX_INSTRUCTION_ADDRESS || memory_size > MAX_INSTRUCTION_ADDRESS {
+                return Err(invalid_data(format!(
+                    "Executable ELF segment at 0x{:08X} is too large or too far from address 0",
+                    address
+                )));
+            }
+            let start = address as usize;
+            let end = start + memory_size as usize;
+            if instructions.len() < end {
+                instructions.resize(end, 0);
+            }
+            instructions[start..start + contents.len()].copy_from_slice(contents);
+        } else {
+            if file_size > 0 {
+                sections.push(if flags & PF_W == 0 {
+                    Section::read_only(address, contents.to_vec())
+                } else {
+                    Section::new(address, contents.to_vec())
+                });
+            }
+            if memory_size > file_size {
+                sections.push(Section::zero_fill(
+                    address.wrapping_add(file_size),
+                    memory_size - file_size,
+                ));
+            }
+        }
+    }
+
+    let mut executable = Executable::new(instructions);
+    for section in sections {
+        executable.add_section(section).map_err(invalid_data)?;
+    }
+    check_entry_point(&executable, entry_point)?;
+    executable.set_entry_point(entry_point);
+
+    if section_headers != 0 {
+        let symbols = read_symbols(&file, section_headers)?;
+        if !symbols.is_empty() {
+            executable.set_debug_info(Some(DebugInfo {
+                symbols,
+                ..DebugInfo::default()
+            }));
+        }
+    }
+    Ok(executable)
+}
+
+/// Reads the named global and local symbols of the first symbol table, if there is one.
+fn read_symbols(file: &ElfFile, section_headers: u32) -> std::io::Result<Vec<DebugSymbol>> {
+    let section_count = file.u16(48)? as u32;
+    let section_header = |index: u32| section_headers + index * SECTION_HEADER_SIZE;
+
+    let mut symbols = Vec::new();
+    let symbol_table = (0..section_count)
+        .map(section_header)
+        .find(|header| file.u32(header + 4).ok() == Some(SHT_SYMTAB));
+    let symbol_table = match symbol_table {
+        Some(header) => header,
+        None => return Ok(symbols),
+    };
+    let offset = file.u32(symbol_table + 16)?;
+    let size = file.u32(symbol_table + 20)?;
+    let names = file.u32(section_header(file.u32(symbol_table + 24)?) + 16)?;
+
+    for i in 1..size / SYMBOL_SIZE {
+        let symbol = offset + i * SYMBOL_SIZE;
+        let kind = file.slice(symbol + 12, 1)?[0] & 0xF;
+        let index = file.u16(symbol + 14)?;
+        let name = file.string(names, file.u32(symbol)?)?;
+        if name.is_empty() || !(kind == STT_FUNC || kind == STT_OBJECT || kind == 0) || index == 0 {
+            continue;
+        }
+        let executable =
+            index < SHN_ABS && file.u32(section_header(index.into()) + 8)? & SHF_EXECINSTR != 0;
+        symbols.push(DebugSymbol {
+            name,
+            kind: if executable {
+                SymbolKind::Instruction
+            } else {
+                SymbolKind::Data
+            },
+            address: file.u32(symbol + 4)?,
+        });
+    }
+    Ok(symbols)
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> std::io::Result<Executable> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, executable: &Executable) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer, executable)?;
+    writer.flush()
+}
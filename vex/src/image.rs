@@ -0,0 +1,115 @@
+//! Writers for memory images in formats understood by other tools, such as EPROM programmers.
+//!
+//! Instruction and data memory are separate address spaces, so each of them is written as its own image.
+
+use std::io::prelude::*;
+
+const RECORD_BYTES: usize = 16;
+
+/// Writes `bytes` as a raw flat binary.
+pub fn write_binary<W: Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_all(bytes)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn write_hex_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    for b in bytes {
+        write!(writer, "{:02X}", b)?;
+    }
+    Ok(())
+}
+
+fn write_intel_hex_record<W: Write>(
+    writer: &mut W,
+    record_type: u8,
+    address: u16,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut record = vec![
+        data.len() as u8,
+        (address >> 8) as u8,
+        address as u8,
+        record_type,
+    ];
+    record.extend_from_slice(data);
+    let sum = checksum(&record);
+    record.push(sum.wrapping_neg());
+
+    write!(writer, ":")?;
+    write_hex_bytes(writer, &record)?;
+    writeln!(writer)
+}
+
+/// Writes `bytes` in the Intel HEX format, starting at `base_address`.
+///
+/// Extended linear address records are emitted whenever the upper 16 bits of the address change.
+pub fn write_intel_hex<W: Write>(
+    writer: &mut W,
+    bytes: &[u8],
+    base_address: u32,
+) -> std::io::Result<()> {
+    let mut upper = None;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = base_address.wrapping_add(offset as u32);
+        if upper != Some(address >> 16) {
+            upper = Some(address >> 16);
+            let segment = address >> 16;
+            write_intel_hex_record(writer, 0x04, 0, &[(segment >> 8) as u8, segment as u8])?;
+        }
+
+        // records must not cross a 64 KiB boundary
+        let until_boundary = 0x1_0000 - (address & 0xFFFF) as usize;
+        let len = RECORD_BYTES.min(bytes.len() - offset).min(until_boundary);
+        write_intel_hex_record(writer, 0x00, address as u16, &bytes[offset..offset + len])?;
+        offset += len;
+    }
+
+    write_intel_hex_record(writer, 0x01, 0, &[])
+}
+
+fn write_srecord<W: Write>(
+    writer: &mut W,
+    record_type: char,
+    address: &[u8],
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut record = vec![(address.len() + data.len() + 1) as u8];
+    record.extend_from_slice(address);
+    record.extend_from_slice(data);
+    let sum = checksum(&record);
+    record.push(!sum);
+
+    write!(writer, "S{}", record_type)?;
+    write_hex_bytes(writer, &record)?;
+    writeln!(writer)
+}
+
+/// Writes `bytes` as Motorola S-records with 32-bit addresses, starting at `base_address`.
+///
+/// The output consists of an `S0` header containing `header`, `S3` data records,
+/// an `S5` record count (if there are at most 65535 data records) and an `S7` termination record.
+pub fn write_srec<W: Write>(
+    writer: &mut W,
+    bytes: &[u8],
+    base_address: u32,
+    header: &str,
+) -> std::io::Result<()> {
+    write_srecord(writer, '0', &[0, 0], header.as_bytes())?;
+
+    let mut count = 0;
+    for (i, chunk) in bytes.chunks(RECORD_BYTES).enumerate() {
+        let address = base_address.wrapping_add((i * RECORD_BYTES) as u32);
+        write_srecord(writer, '3', &address.to_be_bytes(), chunk)?;
+        count += 1;
+    }
+
+    if count <= 0xFFFF {
+        write_srecord(writer, '5', &(count as u16).to_be_bytes(), &[])?;
+    }
+    write_srecord(writer, '7', &base_address.to_be_bytes(), &[])
+}
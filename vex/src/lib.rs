@@ -1,75 +1,632 @@
 use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
 use std::mem;
 use std::path::Path;
 use util::Endian;
 
+use debug::DebugInfo;
+
+mod compression;
+pub mod debug;
+pub mod elf;
+pub mod image;
+
 // TODO: use proper binary serialization using serde/bincode
 
-#[derive(Debug, PartialEq)]
+/// Signature at the start of every executable file.
+pub const MAGIC: &[u8; 4] = b"VEX\0";
+/// Version of the executable format which is written by [`write`](fn.write.html).
+/// Files with a newer version are rejected when reading.
+///
+/// Version 1 lacks the entry point and memory size, which are read as their defaults.
+/// Versions 1 and 2 contain a single data section, which is read as the first section.
+/// Version 4 added chunks after the sections, which carry optional information like debug information.
+/// Version 5 added flags after the version, whose lowest byte is the codec the rest of the file is
+/// compressed with.
+pub const VERSION: u32 = 5;
+
+const CODEC_MASK: u32 = 0xFF;
+const CODEC_NONE: u32 = 0;
+/// Deflate in a zlib stream.
+const CODEC_DEFLATE: u32 = 1;
+
+/// Tag of the chunk which contains the [`DebugInfo`](debug/struct.DebugInfo.html).
+const DEBUG_INFO_TAG: &[u8; 4] = b"DBUG";
+/// Tag of the chunk which contains the metadata.
+const METADATA_TAG: &[u8; 4] = b"META";
+
+/// Metadata key for the name of the program.
+pub const METADATA_NAME: &str = "name";
+/// Metadata key for the version of the program.
+pub const METADATA_VERSION: &str = "version";
+/// Metadata key for the time the program was built at, in seconds since the Unix epoch.
+pub const METADATA_BUILD_TIMESTAMP: &str = "build-timestamp";
+/// Metadata key for the name and version of the tool which built the program.
+pub const METADATA_TOOLCHAIN: &str = "toolchain";
+
+const FLAG_READ_ONLY: u32 = 1;
+const FLAG_ZERO_FILL: u32 = 2;
+
+/// Properties of a [`Section`](struct.Section.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionFlags {
+    /// The program does not write to the section, so it may be loaded into ROM.
+    pub read_only: bool,
+    /// The section has no contents in the file and is filled with zeros when it is loaded.
+    pub zero_fill: bool,
+}
+
+impl SectionFlags {
+    fn bits(self) -> u32 {
+        let mut bits = 0;
+        if self.read_only {
+            bits |= FLAG_READ_ONLY;
+        }
+        if self.zero_fill {
+            bits |= FLAG_ZERO_FILL;
+        }
+        bits
+    }
+
+    fn from_bits(bits: u32) -> Option<SectionFlags> {
+        if bits & !(FLAG_READ_ONLY | FLAG_ZERO_FILL) != 0 {
+            return None;
+        }
+        Some(SectionFlags {
+            read_only: bits & FLAG_READ_ONLY != 0,
+            zero_fill: bits & FLAG_ZERO_FILL != 0,
+        })
+    }
+}
+
+/// A block of main memory which is loaded at a fixed address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    address: u32,
+    size: u32,
+    flags: SectionFlags,
+    bytes: Vec<u8>,
+}
+
+impl Section {
+    /// Creates a writable section containing `bytes`.
+    pub fn new(address: u32, bytes: Vec<u8>) -> Section {
+        Section {
+            address,
+            size: bytes.len() as u32,
+            flags: SectionFlags::default(),
+            bytes,
+        }
+    }
+
+    /// Creates a read-only section containing `bytes`.
+    pub fn read_only(address: u32, bytes: Vec<u8>) -> Section {
+        Section {
+            flags: SectionFlags {
+                read_only: true,
+                zero_fill: false,
+            },
+            ..Section::new(address, bytes)
+        }
+    }
+
+    /// Creates a writable section of `size` bytes which are all zero, e.g. for uninitialized variables (bss).
+    pub fn zero_fill(address: u32, size: u32) -> Section {
+        Section {
+            address,
+            size,
+            flags: SectionFlags {
+                read_only: false,
+                zero_fill: true,
+            },
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Address in main memory where the section is loaded.
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Size of the section in memory, in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn flags(&self) -> SectionFlags {
+        self.flags
+    }
+
+    /// Contents of the section, which are empty for zero-filled sections.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..]
+    }
+
+    /// Address after the last byte of the section.
+    fn end(&self) -> u64 {
+        self.address as u64 + self.size as u64
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Executable {
-    data_offset: u32,
+    entry_point: u32,
+    memory_size: u32,
     instructions: Vec<u8>,
-    data: Vec<u8>,
+    sections: Vec<Section>,
+    debug_info: Option<DebugInfo>,
+    metadata: BTreeMap<String, String>,
+    compressed: bool,
 }
 
 impl Executable {
+    /// Creates an executable with a single data section, which starts at address 0 and needs no more memory
+    /// than its data.
     pub fn from(data_offset: u32, instructions: Vec<u8>, data: Vec<u8>) -> Executable {
+        let data = Section::new(data_offset, data);
         Executable {
-            data_offset,
+            entry_point: 0,
+            memory_size: data.end().min(u32::MAX as u64) as u32,
             instructions,
-            data,
+            sections: vec![data],
+            debug_info: None,
+            metadata: BTreeMap::new(),
+            compressed: false,
         }
     }
 
-    pub fn copy_from(data_offset: u32, instructions: &[u8], data: &[u8]) -> Executable {
+    /// Creates an executable without any sections, which starts at address 0.
+    pub fn new(instructions: Vec<u8>) -> Executable {
         Executable {
-            data_offset,
-            instructions: Vec::from(instructions),
-            data: Vec::from(data),
+            entry_point: 0,
+            memory_size: 0,
+            instructions,
+            sections: Vec::new(),
+            debug_info: None,
+            metadata: BTreeMap::new(),
+            compressed: false,
         }
     }
 
+    pub fn copy_from(data_offset: u32, instructions: &[u8], data: &[u8]) -> Executable {
+        Executable::from(data_offset, Vec::from(instructions), Vec::from(data))
+    }
+
+    /// Address of the first section, i.e. the data section of an assembled program.
     pub fn data_offset(&self) -> u32 {
-        self.data_offset
+        self.sections.first().map_or(0, Section::address)
     }
 
     pub fn instructions(&self) -> &[u8] {
         &self.instructions[..]
     }
 
+    /// Contents of the first section, i.e. the data section of an assembled program.
     pub fn data(&self) -> &[u8] {
-        &self.data[..]
+        self.sections.first().map_or(&[], Section::bytes)
+    }
+
+    /// All sections of main memory, in the order they were added.
+    pub fn sections(&self) -> &[Section] {
+        &self.sections[..]
+    }
+
+    /// Adds a section, which must neither overlap another section nor exceed the address space.
+    /// The memory size is raised to the end of the section if necessary.
+    pub fn add_section(&mut self, section: Section) -> std::result::Result<(), String> {
+        if section.end() > 1 << 32 {
+            return Err(format!(
+                "Section at 0x{:08X} exceeds the address space",
+                section.address
+            ));
+        }
+        if section.size > 0 {
+            let overlapping = self.sections.iter().find(|other| {
+                other.size > 0
+                    && (other.address as u64) < section.end()
+                    && (section.address as u64) < other.end()
+            });
+            if let Some(other) = overlapping {
+                return Err(format!(
+                    "Sections at 0x{:08X} and 0x{:08X} overlap",
+                    other.address, section.address
+                ));
+            }
+        }
+        self.memory_size = self
+            .memory_size
+            .max(section.end().min(u32::MAX as u64) as u32);
+        self.sections.push(section);
+        Ok(())
+    }
+
+    /// Address in instruction memory of the first instruction to execute.
+    pub fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    pub fn set_entry_point(&mut self, entry_point: u32) {
+        self.entry_point = entry_point;
+    }
+
+    /// Minimum size of main memory in bytes which the program needs, counted from address 0.
+    /// It is never smaller than the end of the last section.
+    pub fn memory_size(&self) -> u32 {
+        self.memory_size
+    }
+
+    /// Sets the minimum size of main memory, which is raised to the end of the last section if it is smaller.
+    pub fn set_memory_size(&mut self, memory_size: u32) {
+        self.memory_size = memory_size.max(self.sections_end());
+    }
+
+    fn sections_end(&self) -> u32 {
+        self.sections
+            .iter()
+            .map(|section| section.end().min(u32::MAX as u64) as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Debug information embedded in the executable, so that debuggers don't need a separate file.
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    /// Free-form information about the program, like its name or the tool which built it.
+    /// Keys for common entries are defined as constants, e.g. [`METADATA_NAME`](constant.METADATA_NAME.html).
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Sets the metadata entry `key`, replacing its previous value.
+    pub fn set_metadata<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.metadata.insert(key.into(), value.into());
+    }
+
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.metadata.remove(key)
+    }
+
+    /// Whether everything after the header is compressed when the executable is written.
+    /// Compressed executables are decompressed transparently when they are read, which needs the
+    /// `compression` feature.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    #[cfg(feature = "compression")]
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    /// Optional parts of the executable, as tag and contents.
+    fn chunks(&self) -> Vec<(&'static [u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+        if let Some(debug_info) = &self.debug_info {
+            let mut bytes = Vec::new();
+            debug::write(&mut bytes, debug_info).unwrap();
+            chunks.push((DEBUG_INFO_TAG, bytes));
+        }
+        if !self.metadata.is_empty() {
+            let mut bytes = Vec::new();
+            bytes
+                .write_u32::<Endian>(self.metadata.len() as u32)
+                .unwrap();
+            for (key, value) in self.metadata.iter() {
+                debug::write_string(&mut bytes, key).unwrap();
+                debug::write_string(&mut bytes, value).unwrap();
+            }
+            chunks.push((METADATA_TAG, bytes));
+        }
+        chunks
     }
 
     pub fn required_size(&self) -> usize {
-        mem::size_of::<u32>() * 3 + self.instructions.len() + self.data.len()
+        let payload = if self.compressed {
+            mem::size_of::<u32>() * 2 + compression::compress(&self.payload()).len()
+        } else {
+            self.payload().len()
+        };
+        MAGIC.len() + mem::size_of::<u32>() * 2 + payload
+    }
+
+    /// Everything after the header and flags: the sections, the instructions and the chunks.
+    fn payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // writing to a Vec cannot fail
+        self.write_payload(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn write_payload<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_u32::<Endian>(self.instructions.len() as u32)?;
+        writer.write_u32::<Endian>(self.entry_point)?;
+        writer.write_u32::<Endian>(self.memory_size)?;
+        writer.write_u32::<Endian>(self.sections.len() as u32)?;
+        for section in self.sections.iter() {
+            writer.write_u32::<Endian>(section.address)?;
+            writer.write_u32::<Endian>(section.size)?;
+            writer.write_u32::<Endian>(section.flags.bits())?;
+        }
+        writer.write_all(&self.instructions[..])?;
+        for section in self.sections.iter() {
+            writer.write_all(&section.bytes[..])?;
+        }
+
+        let chunks = self.chunks();
+        writer.write_u32::<Endian>(chunks.len() as u32)?;
+        for (tag, bytes) in chunks {
+            writer.write_all(tag)?;
+            writer.write_u32::<Endian>(bytes.len() as u32)?;
+            writer.write_all(&bytes[..])?;
+        }
+        Ok(())
+    }
+
+    /// Reads an executable from a stream, see [`read`](fn.read.html).
+    ///
+    /// Nothing after the end of the executable is consumed, so the stream can continue with other data.
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Executable> {
+        read(reader)
     }
+
+    /// Writes the executable to a stream, which is not flushed.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write(writer, self)
+    }
+
+    /// Reads an executable which takes up all of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Executable> {
+        let mut reader = bytes;
+        let executable = read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(invalid_data(format!(
+                "Executable is followed by {} unexpected bytes",
+                reader.len()
+            )));
+        }
+        Ok(executable)
+    }
+
+    /// Returns the executable in the format written by [`write`](fn.write.html).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // writing to a Vec cannot fail
+        write(&mut bytes, self).unwrap();
+        bytes
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
 }
 
+/// Replaces the generic message of a premature end of the input.
+fn truncated(err: Error) -> Error {
+    if err.kind() == ErrorKind::UnexpectedEof {
+        Error::new(ErrorKind::UnexpectedEof, "Executable file is truncated")
+    } else {
+        err
+    }
+}
+
+/// Reads `len` bytes without allocating them up front, since the length comes from a possibly broken file.
+fn read_bytes<R: Read>(reader: &mut R, len: u32) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len.into()).read_to_end(&mut bytes)?;
+    if bytes.len() < len as usize {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Executable file is truncated",
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Reads an executable, checking its signature and version first.
+///
+/// Fails with [`ErrorKind::InvalidData`](https://doc.rust-lang.org/std/io/enum.ErrorKind.html) if the input is
+/// not an executable, has an unsupported version or contains invalid sections,
+/// and with `ErrorKind::UnexpectedEof` if the input ends before the executable does.
 pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Executable> {
+    read_executable(reader).map_err(truncated)
+}
+
+fn read_executable<R: Read>(reader: &mut R) -> std::io::Result<Executable> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not an executable file".to_owned()));
+    }
+    let version = reader.read_u32::<Endian>()?;
+    if version == 0 || version > VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported executable version {} (expected at most {})",
+            version, VERSION
+        )));
+    }
+
+    let (mut executable, entry_point, memory_size) = if version >= 5 {
+        read_payload(reader, version)?
+    } else if version >= 3 {
+        read_sections(reader, version)?
+    } else {
+        read_legacy(reader, version)?
+    };
+
+    if let Some(entry_point) = entry_point {
+        check_entry_point(&executable, entry_point)?;
+        executable.entry_point = entry_point;
+    }
+    if let Some(memory_size) = memory_size {
+        if memory_size < executable.sections_end() {
+            return Err(invalid_data(format!(
+                "Memory size 0x{:08X} is smaller than the end of the last section",
+                memory_size
+            )));
+        }
+        executable.memory_size = memory_size;
+    }
+    Ok(executable)
+}
+
+fn check_entry_point(executable: &Executable, entry_point: u32) -> std::io::Result<()> {
+    if entry_point != 0 && entry_point as usize >= executable.instructions.len() {
+        return Err(invalid_data(format!(
+            "Entry point 0x{:08X} is outside of the instructions",
+            entry_point
+        )));
+    }
+    Ok(())
+}
+
+type ReadResult = (Executable, Option<u32>, Option<u32>);
+
+/// Reads the rest of a version 1 or 2 file, which has exactly one data section.
+fn read_legacy<R: Read>(reader: &mut R, version: u32) -> std::io::Result<ReadResult> {
     let instr_len = reader.read_u32::<Endian>()?;
     let data_length = reader.read_u32::<Endian>()?;
     let data_offset = reader.read_u32::<Endian>()?;
+    let (entry_point, memory_size) = if version >= 2 {
+        (
+            Some(reader.read_u32::<Endian>()?),
+            Some(reader.read_u32::<Endian>()?),
+        )
+    } else {
+        (None, None)
+    };
+
+    let instructions = read_bytes(reader, instr_len)?;
+    let data = read_bytes(reader, data_length)?;
+    Ok((
+        Executable::from(data_offset, instructions, data),
+        entry_point,
+        memory_size,
+    ))
+}
+
+/// Reads the flags of a version 5 file and the sections, which may have to be decompressed first.
+fn read_payload<R: Read>(reader: &mut R, version: u32) -> std::io::Result<ReadResult> {
+    let flags = reader.read_u32::<Endian>()?;
+    if flags & !CODEC_MASK != 0 {
+        return Err(invalid_data(format!(
+            "Unknown executable flags 0x{:08X}",
+            flags
+        )));
+    }
+    match flags & CODEC_MASK {
+        CODEC_NONE => return read_sections(reader, version),
+        CODEC_DEFLATE => {}
+        codec => return Err(invalid_data(format!("Unknown compression codec {}", codec))),
+    }
 
-    let mut instructions = vec![0; instr_len as usize];
-    let mut data = vec![0; data_length as usize];
+    let len = reader.read_u32::<Endian>()?;
+    let compressed_len = reader.read_u32::<Endian>()?;
+    let compressed = read_bytes(reader, compressed_len)?;
+    let payload = compression::decompress(&compressed, len as usize)?;
 
-    reader.read_exact(&mut instructions)?;
-    reader.read_exact(&mut data)?;
+    let corrupt = || invalid_data("Compressed executable is corrupt".to_owned());
+    let mut payload_reader = &payload[..];
+    let (mut executable, entry_point, memory_size) = read_sections(&mut payload_reader, version)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => corrupt(),
+            _ => err,
+        })?;
+    if !payload_reader.is_empty() {
+        return Err(corrupt());
+    }
+    executable.compressed = true;
+    Ok((executable, entry_point, memory_size))
+}
 
-    Ok(Executable::from(data_offset, instructions, data))
+fn read_sections<R: Read>(reader: &mut R, version: u32) -> std::io::Result<ReadResult> {
+    let instr_len = reader.read_u32::<Endian>()?;
+    let entry_point = reader.read_u32::<Endian>()?;
+    let memory_size = reader.read_u32::<Endian>()?;
+    let section_count = reader.read_u32::<Endian>()?;
+
+    let mut headers = Vec::new();
+    for _ in 0..section_count {
+        let address = reader.read_u32::<Endian>()?;
+        let size = reader.read_u32::<Endian>()?;
+        let bits = reader.read_u32::<Endian>()?;
+        let flags = SectionFlags::from_bits(bits).ok_or_else(|| {
+            invalid_data(format!(
+                "Section at 0x{:08X} has unknown flags 0x{:08X}",
+                address, bits
+            ))
+        })?;
+        headers.push((address, size, flags));
+    }
+
+    let mut executable = Executable::new(read_bytes(reader, instr_len)?);
+    for (address, size, flags) in headers {
+        let bytes = if flags.zero_fill {
+            Vec::new()
+        } else {
+            read_bytes(reader, size)?
+        };
+        executable
+            .add_section(Section {
+                address,
+                size,
+                flags,
+                bytes,
+            })
+            .map_err(invalid_data)?;
+    }
+
+    if version >= 4 {
+        let chunk_count = reader.read_u32::<Endian>()?;
+        for _ in 0..chunk_count {
+            let mut tag = [0u8; 4];
+            reader.read_exact(&mut tag)?;
+            let len = reader.read_u32::<Endian>()?;
+            let bytes = read_bytes(reader, len)?;
+            // unknown chunks are skipped, so that newer optional information doesn't break older readers
+            if &tag == DEBUG_INFO_TAG {
+                executable.debug_info = Some(debug::read(&mut &bytes[..])?);
+            } else if &tag == METADATA_TAG {
+                executable.metadata = read_metadata(&mut &bytes[..])?;
+            }
+        }
+    }
+    Ok((executable, Some(entry_point), Some(memory_size)))
+}
+
+fn read_metadata<R: Read>(reader: &mut R) -> std::io::Result<BTreeMap<String, String>> {
+    let count = reader.read_u32::<Endian>()?;
+    let mut metadata = BTreeMap::new();
+    for _ in 0..count {
+        let key = debug::read_string(reader)?;
+        let value = debug::read_string(reader)?;
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
 }
 
 pub fn write<W: Write>(writer: &mut W, executable: &Executable) -> std::io::Result<()> {
-    writer.write_u32::<Endian>(executable.instructions.len() as u32)?;
-    writer.write_u32::<Endian>(executable.data.len() as u32)?;
-    writer.write_u32::<Endian>(executable.data_offset)?;
-    writer.write_all(&executable.instructions[..])?;
-    writer.write_all(&executable.data[..])?;
-    Ok(())
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<Endian>(VERSION)?;
+    let payload = executable.payload();
+    if executable.compressed {
+        writer.write_u32::<Endian>(CODEC_DEFLATE)?;
+        let compressed = compression::compress(&payload);
+        writer.write_u32::<Endian>(payload.len() as u32)?;
+        writer.write_u32::<Endian>(compressed.len() as u32)?;
+        writer.write_all(&compressed[..])
+    } else {
+        writer.write_u32::<Endian>(CODEC_NONE)?;
+        writer.write_all(&payload[..])
+    }
 }
 
 pub trait ReadVexExt: Read + Sized {
@@ -93,7 +650,9 @@ pub fn read_file<P: AsRef<Path>>(path: P) -> std::io::Result<Executable> {
 }
 
 pub fn write_file<P: AsRef<Path>>(path: P, executable: &Executable) -> std::io::Result<()> {
-    BufWriter::new(File::create(path)?).write_vex(executable)
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_vex(executable)?;
+    writer.flush()
 }
 
 #[cfg(test)]
@@ -19,3 +19,422 @@ fn write_read() {
 
     std::fs::remove_file(path).unwrap();
 }
+
+#[test]
+fn header() {
+    let executable = Executable::from(0x100, vec![1, 2, 3, 4], vec![5]);
+    let mut buffer = Vec::new();
+    write(&mut buffer, &executable).unwrap();
+    assert_eq!(&buffer[..8], b"VEX\0\x05\0\0\0");
+    assert_eq!(buffer.len(), executable.required_size());
+    assert_eq!(read(&mut &buffer[..]).unwrap(), executable);
+
+    let error = |bytes: &[u8]| {
+        let err = read(&mut &bytes[..]).unwrap_err();
+        (err.kind(), err.to_string())
+    };
+
+    let mut bad_magic = buffer.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(
+        error(&bad_magic),
+        (
+            std::io::ErrorKind::InvalidData,
+            "Not an executable file".to_owned()
+        )
+    );
+
+    let mut newer = buffer.clone();
+    newer[4] = 6;
+    assert_eq!(
+        error(&newer),
+        (
+            std::io::ErrorKind::InvalidData,
+            "Unsupported executable version 6 (expected at most 5)".to_owned()
+        )
+    );
+
+    let truncated = (
+        std::io::ErrorKind::UnexpectedEof,
+        "Executable file is truncated".to_owned(),
+    );
+    assert_eq!(error(&buffer[..6]), truncated);
+    assert_eq!(error(&buffer[..buffer.len() - 1]), truncated);
+    assert_eq!(error(&[]), truncated);
+}
+
+#[test]
+fn entry_point_and_memory_size() {
+    let mut executable = Executable::from(0x100, vec![0; 8], vec![1, 2]);
+    assert_eq!(executable.entry_point(), 0);
+    assert_eq!(executable.memory_size(), 0x102);
+
+    executable.set_entry_point(4);
+    executable.set_memory_size(0x10);
+    assert_eq!(executable.memory_size(), 0x102);
+    executable.set_memory_size(0x1000);
+
+    let mut buffer = Vec::new();
+    write(&mut buffer, &executable).unwrap();
+    let executable_read = read(&mut &buffer[..]).unwrap();
+    assert_eq!(executable_read.entry_point(), 4);
+    assert_eq!(executable_read.memory_size(), 0x1000);
+
+    // entry point and memory size follow the flags and the length of the instructions
+    let mut bad = buffer.clone();
+    bad[16] = 8;
+    assert_eq!(
+        read(&mut &bad[..]).unwrap_err().to_string(),
+        "Entry point 0x00000008 is outside of the instructions"
+    );
+    let mut bad = buffer.clone();
+    bad[20..24].copy_from_slice(&[0; 4]);
+    assert_eq!(
+        read(&mut &bad[..]).unwrap_err().to_string(),
+        "Memory size 0x00000000 is smaller than the end of the last section"
+    );
+
+    // version 1 has neither of them
+    let mut old = b"VEX\0\x01\0\0\0".to_vec();
+    old.extend_from_slice(&[4, 0, 0, 0, 1, 0, 0, 0, 0x10, 0, 0, 0]);
+    old.extend_from_slice(&[1, 2, 3, 4, 5]);
+    let executable_read = read(&mut &old[..]).unwrap();
+    assert_eq!(
+        executable_read,
+        Executable::from(0x10, vec![1, 2, 3, 4], vec![5])
+    );
+    assert_eq!(executable_read.memory_size(), 0x11);
+}
+
+#[test]
+fn sections() {
+    let mut executable = Executable::from(0x100, vec![0; 4], vec![1, 2, 3, 4]);
+    executable
+        .add_section(Section::read_only(0x1000, vec![5, 6]))
+        .unwrap();
+    executable
+        .add_section(Section::zero_fill(0x2000, 0x800))
+        .unwrap();
+    assert_eq!(executable.memory_size(), 0x2800);
+    assert_eq!(
+        executable.add_section(Section::new(0x1001, vec![0])),
+        Err("Sections at 0x00001000 and 0x00001001 overlap".to_owned())
+    );
+    assert_eq!(
+        executable.add_section(Section::zero_fill(0xFFFF_FFFF, 2)),
+        Err("Section at 0xFFFFFFFF exceeds the address space".to_owned())
+    );
+    assert!(executable.add_section(Section::new(0x1002, vec![])).is_ok());
+
+    let mut buffer = Vec::new();
+    write(&mut buffer, &executable).unwrap();
+    assert_eq!(buffer.len(), executable.required_size());
+    let executable_read = read(&mut &buffer[..]).unwrap();
+    assert_eq!(executable_read, executable);
+
+    let sections = executable_read.sections();
+    assert_eq!(sections.len(), 4);
+    assert_eq!(executable_read.data_offset(), 0x100);
+    assert_eq!(executable_read.data(), &[1, 2, 3, 4]);
+    assert!(sections[1].flags().read_only);
+    assert!(sections[2].flags().zero_fill);
+    assert_eq!(sections[2].size(), 0x800);
+    assert!(sections[2].bytes().is_empty());
+
+    // flags of the first section
+    let mut bad = buffer.clone();
+    bad[36] = 0x80;
+    assert_eq!(
+        read(&mut &bad[..]).unwrap_err().to_string(),
+        "Section at 0x00000100 has unknown flags 0x00000080"
+    );
+    // address of the second section
+    let mut bad = buffer.clone();
+    bad[40..44].copy_from_slice(&[0x02, 0x01, 0, 0]);
+    assert_eq!(
+        read(&mut &bad[..]).unwrap_err().to_string(),
+        "Sections at 0x00000100 and 0x00000102 overlap"
+    );
+}
+
+#[test]
+fn streams_and_bytes() {
+    let executable = Executable::from(0x40, vec![1, 2, 3, 4], vec![5, 6]);
+    let bytes = executable.to_bytes();
+    assert_eq!(bytes.len(), executable.required_size());
+    assert_eq!(Executable::from_bytes(&bytes).unwrap(), executable);
+
+    // an executable embedded in a stream is read without consuming what follows it
+    let mut stream = b"header".to_vec();
+    executable.write_to(&mut stream).unwrap();
+    stream.extend_from_slice(b"trailer");
+    let mut reader = &stream[6..];
+    assert_eq!(Executable::read_from(&mut reader).unwrap(), executable);
+    assert_eq!(reader, b"trailer");
+
+    assert_eq!(
+        Executable::from_bytes(&stream[6..])
+            .unwrap_err()
+            .to_string(),
+        "Executable is followed by 7 unexpected bytes"
+    );
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn compression() {
+    use crate::compression::{compress, decompress};
+
+    let mut input = vec![0u8; 10_000];
+    input.extend((0..2000u32).map(|i| (i * 7 % 251) as u8));
+    for bytes in [&input[..], &[], &[1]] {
+        let compressed = compress(bytes);
+        assert_eq!(decompress(&compressed, bytes.len()).unwrap(), bytes);
+    }
+    assert!(compress(&input).len() < input.len() / 2);
+
+    let compressed = compress(&input);
+    assert!(decompress(&compressed, input.len() - 1).is_err());
+    assert!(decompress(&compressed, input.len() + 1).is_err());
+    assert!(decompress(&compressed[..compressed.len() - 1], input.len()).is_err());
+    let mut trailing = compressed.clone();
+    trailing.push(0);
+    assert!(decompress(&trailing, input.len()).is_err());
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn compressed_executable() {
+    let mut executable = Executable::from(0, vec![0x3D; 64], vec![0; 4096]);
+    executable.set_compressed(true);
+    let bytes = executable.to_bytes();
+    assert_eq!(bytes.len(), executable.required_size());
+    assert!(bytes.len() < 512);
+    // the codec is deflate
+    assert_eq!(&bytes[8..12], &[1, 0, 0, 0]);
+
+    let executable_read = Executable::from_bytes(&bytes).unwrap();
+    assert!(executable_read.is_compressed());
+    assert_eq!(executable_read, executable);
+
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xFF;
+    assert_eq!(
+        Executable::from_bytes(&corrupt).unwrap_err().to_string(),
+        "Compressed executable is corrupt"
+    );
+    let mut unknown = bytes.clone();
+    unknown[8] = 3;
+    assert_eq!(
+        Executable::from_bytes(&unknown).unwrap_err().to_string(),
+        "Unknown compression codec 3"
+    );
+    unknown[8] = 1;
+    unknown[9] = 1;
+    assert_eq!(
+        Executable::from_bytes(&unknown).unwrap_err().to_string(),
+        "Unknown executable flags 0x00000101"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "compression"))]
+fn compressed_executable_without_feature() {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    for value in [VERSION, 1, 16, 4, 0] {
+        bytes.write_u32::<Endian>(value).unwrap();
+    }
+    let err = Executable::from_bytes(&bytes).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(
+        err.to_string(),
+        "Compressed executables need the compression feature of vex"
+    );
+}
+
+#[test]
+fn metadata() {
+    let mut executable = Executable::from(0, vec![0; 4], vec![]);
+    assert!(executable.metadata().is_empty());
+    executable.set_metadata(METADATA_NAME, "demo");
+    executable.set_metadata(METADATA_TOOLCHAIN, "vasm 0.1.0");
+    executable.set_metadata("custom", "");
+    executable.set_metadata(METADATA_NAME, "renamed");
+    assert_eq!(executable.remove_metadata("custom"), Some(String::new()));
+    #[cfg(feature = "compression")]
+    executable.set_compressed(true);
+
+    let bytes = executable.to_bytes();
+    assert_eq!(bytes.len(), executable.required_size());
+    let executable_read = Executable::from_bytes(&bytes).unwrap();
+    assert_eq!(executable_read, executable);
+    let entries: Vec<_> = executable_read
+        .metadata()
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![("name", "renamed"), ("toolchain", "vasm 0.1.0")]
+    );
+}
+
+#[test]
+fn debug_info_write_read() {
+    use crate::debug::*;
+
+    let debug_info = DebugInfo {
+        files: vec!["main.vasm".to_owned(), "lib.vasm".to_owned()],
+        lines: vec![
+            LineEntry {
+                address: 0,
+                file: 0,
+                line: 3,
+            },
+            LineEntry {
+                address: 4,
+                file: 1,
+                line: 12,
+            },
+        ],
+        symbols: vec![
+            DebugSymbol {
+                name: "table".to_owned(),
+                kind: SymbolKind::Data,
+                address: 64,
+            },
+            DebugSymbol {
+                name: "main".to_owned(),
+                kind: SymbolKind::Instruction,
+                address: 0,
+            },
+        ],
+    };
+
+    let mut buffer = Vec::new();
+    write(&mut buffer, &debug_info).unwrap();
+    let debug_info_read = read(&mut &buffer[..]).unwrap();
+
+    assert_eq!(debug_info, debug_info_read);
+    assert_eq!(debug_info_read.line_at(4).map(|e| e.line), Some(12));
+    assert_eq!(debug_info_read.symbol("table").map(|s| s.address), Some(64));
+}
+
+#[test]
+fn embedded_debug_info() {
+    use crate::debug::{DebugInfo, LineEntry};
+
+    let mut executable = Executable::from(0, vec![0; 8], vec![]);
+    let mut buffer = Vec::new();
+    write(&mut buffer, &executable).unwrap();
+    assert_eq!(read(&mut &buffer[..]).unwrap().debug_info(), None);
+
+    let debug_info = DebugInfo {
+        files: vec!["main.vasm".to_owned()],
+        lines: vec![LineEntry {
+            address: 4,
+            file: 0,
+            line: 2,
+        }],
+        symbols: vec![],
+    };
+    executable.set_debug_info(Some(debug_info.clone()));
+    let mut buffer = Vec::new();
+    write(&mut buffer, &executable).unwrap();
+    assert_eq!(buffer.len(), executable.required_size());
+    let executable_read = read(&mut &buffer[..]).unwrap();
+    assert_eq!(executable_read.debug_info(), Some(&debug_info));
+
+    // unknown chunks are skipped; the chunk count follows the header, one section and the instructions
+    buffer[28 + 12 + 8] = 2;
+    buffer.extend_from_slice(b"NEW\0\x02\0\0\0ab");
+    assert_eq!(read(&mut &buffer[..]).unwrap(), executable);
+}
+
+#[test]
+fn debug_info_bad_magic() {
+    let buffer = b"VEX\0\x01\0\0\0";
+    assert!(debug::read(&mut &buffer[..]).is_err());
+}
+
+#[test]
+fn intel_hex() {
+    let mut output = Vec::new();
+    image::write_intel_hex(&mut output, &[1, 2, 3], 0).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        ":020000040000FA\n:03000000010203F7\n:00000001FF\n"
+    );
+}
+
+#[test]
+fn intel_hex_segment_boundary() {
+    let mut output = Vec::new();
+    image::write_intel_hex(&mut output, &[0xAA, 0xBB], 0xFFFF).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        ":020000040000FA\n:01FFFF00AA57\n:020000040001F9\n:01000000BB44\n:00000001FF\n"
+    );
+}
+
+#[test]
+fn srec() {
+    let mut output = Vec::new();
+    image::write_srec(&mut output, &[1, 2, 3], 0, "HDR").unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "S00600004844521B\nS30800000000010203F1\nS5030001FB\nS70500000000FA\n"
+    );
+}
+
+#[test]
+fn elf_write_read() {
+    use crate::debug::{DebugInfo, DebugSymbol, SymbolKind};
+
+    let mut executable = Executable::from(0x100, vec![1, 2, 3, 4, 5, 6, 7, 8], vec![9, 10]);
+    executable
+        .add_section(Section::read_only(0x200, vec![11, 12, 13, 14]))
+        .unwrap();
+    executable
+        .add_section(Section::zero_fill(0x300, 0x40))
+        .unwrap();
+    executable.set_entry_point(4);
+    executable.set_debug_info(Some(DebugInfo {
+        symbols: vec![
+            DebugSymbol {
+                name: "main".to_owned(),
+                kind: SymbolKind::Instruction,
+                address: 4,
+            },
+            DebugSymbol {
+                name: "table".to_owned(),
+                kind: SymbolKind::Data,
+                address: 0x200,
+            },
+        ],
+        ..DebugInfo::default()
+    }));
+
+    let mut buffer = Vec::new();
+    elf::write(&mut buffer, &executable).unwrap();
+    assert_eq!(&buffer[..6], b"\x7FELF\x01\x01");
+
+    let executable_read = elf::read(&mut &buffer[..]).unwrap();
+    assert_eq!(executable_read.instructions(), executable.instructions());
+    assert_eq!(executable_read.sections(), executable.sections());
+    assert_eq!(executable_read.entry_point(), 4);
+    assert_eq!(executable_read.memory_size(), 0x340);
+    assert_eq!(executable_read.debug_info(), executable.debug_info());
+
+    let error = |bytes: &[u8]| elf::read(&mut &bytes[..]).unwrap_err().to_string();
+    assert_eq!(error(b"VEX\0\x05\0\0\0"), "Not an ELF file");
+    let mut elf64 = buffer.clone();
+    elf64[4] = 2;
+    assert_eq!(
+        error(&elf64),
+        "Only 32-bit little endian ELF files are supported"
+    );
+    assert_eq!(error(&buffer[..40]), "ELF file is truncated");
+}